@@ -0,0 +1,93 @@
+#[path = "src/civil_date.rs"]
+mod civil_date;
+
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use civil_date::civil_from_days;
+
+/// Dependencies whose versions are recorded in the build information
+const REPORTED_DEPENDENCIES: [(&str, &str); 2] = [("opus", "ZOOG_OPUS_VERSION"), ("ogg", "ZOOG_OGG_VERSION")];
+
+/// The build date, respecting `SOURCE_DATE_EPOCH` for reproducible builds
+fn build_date() -> Option<String> {
+    let seconds = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(value) => value.trim().parse::<i64>().ok()?,
+        Err(_) => i64::try_from(SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs()).ok()?,
+    };
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    Some(format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+/// The abbreviated hash of the current git commit, if building from a git
+/// checkout
+fn git_commit(manifest_dir: &Path) -> Option<String> {
+    let git_dir = manifest_dir.join(".git");
+    if !git_dir.exists() {
+        return None;
+    }
+    println!("cargo:rerun-if-changed={}", git_dir.join("HEAD").display());
+    if let Ok(head) = std::fs::read_to_string(git_dir.join("HEAD")) {
+        if let Some(reference) = head.trim().strip_prefix("ref: ") {
+            println!("cargo:rerun-if-changed={}", git_dir.join(reference).display());
+        }
+    }
+    let output = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .current_dir(manifest_dir)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let commit = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!commit.is_empty()).then_some(commit)
+}
+
+/// Extracts the version of the named package from the contents of a
+/// `Cargo.lock` file
+fn locked_version(lock_file: &str, package: &str) -> Option<String> {
+    let name_line = format!("name = \"{}\"", package);
+    let mut lines = lock_file.lines();
+    while let Some(line) = lines.next() {
+        if line.trim() == name_line {
+            let version = lines.next()?.trim().strip_prefix("version = \"")?.strip_suffix('"')?;
+            return Some(version.to_string());
+        }
+    }
+    None
+}
+
+/// Names of the enabled Cargo features
+fn enabled_features() -> Vec<String> {
+    let mut features: Vec<_> = std::env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(|f| f.to_lowercase().replace('_', "-")))
+        .filter(|feature| feature != "default")
+        .collect();
+    features.sort();
+    features
+}
+
+fn main() {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set");
+    let manifest_dir = Path::new(&manifest_dir);
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    if let Some(commit) = git_commit(manifest_dir) {
+        println!("cargo:rustc-env=ZOOG_GIT_COMMIT={}", commit);
+    }
+    if let Some(date) = build_date() {
+        println!("cargo:rustc-env=ZOOG_BUILD_DATE={}", date);
+    }
+    println!("cargo:rustc-env=ZOOG_FEATURES={}", enabled_features().join(","));
+
+    let lock_path = manifest_dir.join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock_path.display());
+    if let Ok(lock_file) = std::fs::read_to_string(&lock_path) {
+        for (package, variable) in REPORTED_DEPENDENCIES {
+            if let Some(version) = locked_version(&lock_file, package) {
+                println!("cargo:rustc-env={}={}", variable, version);
+            }
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! Edits the comments of an Ogg Opus stream held in memory, as `zoogcomment`
+//! does for files.
+//!
+//! Run with `cargo run --example edit_comments --features testing`.
+
+use std::io::Cursor;
+
+use zoog::comment_rewrite::{CommentHeaderRewrite, CommentHeaderSummary, CommentRewriterAction, CommentRewriterConfig};
+use zoog::header::{CommentList as _, DiscreteCommentList};
+use zoog::header_rewriter::{read_raw_headers, rewrite_stream, CodecHeaders, SubmitResult};
+use zoog::testing::{opus_file, StreamSpec};
+use zoog::Error;
+
+/// Rewrites the comments of `input` according to `action`, returning the new
+/// stream and its comments
+fn rewrite(input: &[u8], action: CommentRewriterAction) -> Result<(Vec<u8>, DiscreteCommentList), Error> {
+    let rewrite = CommentHeaderRewrite::new(CommentRewriterConfig { action });
+    let mut output = Vec::new();
+    match rewrite_stream(rewrite, CommentHeaderSummary::default(), Cursor::new(input), &mut output, false)? {
+        SubmitResult::HeadersChanged { to, .. } | SubmitResult::HeadersUnchanged(to) => Ok((output, to)),
+        SubmitResult::Good => unreachable!("Rewrite returned without a result"),
+    }
+}
+
+fn main() -> Result<(), Error> {
+    let input = opus_file(&[StreamSpec::default()]);
+
+    let mut tags = DiscreteCommentList::default();
+    tags.extend([("ARTIST", "Someone"), ("TITLE", "Draft"), ("COMMENT", "Temporary")])?;
+    let (tagged, comments) = rewrite(&input, CommentRewriterAction::Replace(tags))?;
+    assert_eq!(comments.len(), 3);
+
+    // Drop the comment and replace the title, leaving the artist alone
+    let mut append = DiscreteCommentList::default();
+    append.push("TITLE", "Final")?;
+    let retain =
+        Box::new(|key: &str, _: &str| !key.eq_ignore_ascii_case("COMMENT") && !key.eq_ignore_ascii_case("TITLE"));
+    let (edited, comments) = rewrite(&tagged, CommentRewriterAction::Modify { retain, append })?;
+    comments.write_as_text(std::io::stdout(), true).map_err(Error::WriteError)?;
+
+    // The rewritten stream contains the new comments
+    let CodecHeaders::Opus(_, header) = read_raw_headers(Cursor::new(&edited))?.parse()? else {
+        unreachable!("Generated stream is not Opus")
+    };
+    assert_eq!(header.len(), 2);
+    assert_eq!(header.get_first("ARTIST"), Some("Someone"));
+    assert_eq!(header.get_first("TITLE"), Some("Final"));
+    assert_eq!(header.get_first("COMMENT"), None);
+    Ok(())
+}
@@ -0,0 +1,65 @@
+//! Normalizes the output gain of an Ogg Opus file to the EBU R 128 reference
+//! level, as `opusgain` does by default.
+//!
+//! Run with `cargo run --example normalize --features testing [FILE]`. If no
+//! file is given, a generated one is normalized instead.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write as _};
+use std::path::{Path, PathBuf};
+
+use zoog::header_rewriter::{rewrite_stream, SubmitResult};
+use zoog::opus::VolumeAnalyzer;
+use zoog::testing::{opus_file, StreamSpec};
+use zoog::volume_rewrite::{GainsSummary, VolumeHeaderRewrite, VolumeRewriterConfig, VolumeTarget, DEFAULT_TOLERANCE};
+use zoog::R128_LUFS;
+
+fn open(path: &Path) -> Result<BufReader<File>, zoog::Error> {
+    File::open(path).map(BufReader::new).map_err(|e| zoog::Error::FileOpenError(path.to_path_buf(), e))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let generated = tempfile::tempdir()?;
+    let path = match std::env::args_os().nth(1) {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let path = generated.path().join("generated.opus");
+            std::fs::write(&path, opus_file(&[StreamSpec::default()]))?;
+            path
+        }
+    };
+
+    let mut analyzer = VolumeAnalyzer::default();
+    analyzer.analyze_stream(open(&path)?)?;
+    let config = VolumeRewriterConfig::builder()
+        .target(VolumeTarget::LUFS(R128_LUFS))
+        .track_volume(analyzer.last_track_lufs())
+        .build()?;
+
+    // The rewritten file replaces the original only once it is complete
+    let directory = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut output = tempfile::NamedTempFile::new_in(directory)?;
+    let result = {
+        let mut writer = BufWriter::new(output.as_file_mut());
+        let result =
+            rewrite_stream(VolumeHeaderRewrite::new(config), GainsSummary::default(), open(&path)?, &mut writer, true)?;
+        writer.flush()?;
+        result
+    };
+    match result {
+        SubmitResult::HeadersChanged { from, to } => {
+            assert!(to.is_normalized_to(VolumeTarget::LUFS(R128_LUFS), DEFAULT_TOLERANCE));
+            output.persist(&path)?;
+            println!("{}: {} => {}", path.display(), from, to);
+        }
+        SubmitResult::HeadersUnchanged(gains) => println!("{}: already normalized ({})", path.display(), gains),
+        SubmitResult::Good => unreachable!("Rewrite returned without a result"),
+    }
+
+    // Normalizing again finds nothing to change
+    let result =
+        rewrite_stream(VolumeHeaderRewrite::new(config), GainsSummary::default(), open(&path)?, std::io::sink(), true)?;
+    assert!(matches!(result, SubmitResult::HeadersUnchanged(_)));
+    Ok(())
+}
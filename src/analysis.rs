@@ -0,0 +1,109 @@
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+use crate::header::IdHeader;
+use crate::Error;
+
+/// The default difference between the duration implied by granule positions
+/// and the decoded duration above which a file is reported as damaged
+pub const DEFAULT_MAX_DURATION_MISMATCH: Duration = Duration::from_secs(1);
+
+/// The parameters of decoded audio which must remain fixed for the duration of
+/// a loudness analysis
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AudioParameters {
+    /// The number of decoded channels
+    pub channel_count: usize,
+
+    /// The sample rate audio is decoded at
+    pub sample_rate: usize,
+}
+
+impl AudioParameters {
+    /// Extracts the decoding parameters from a codec identification header
+    #[must_use]
+    pub fn from_id_header<I: IdHeader>(header: &I) -> AudioParameters {
+        AudioParameters { channel_count: header.num_output_channels(), sample_rate: header.output_sample_rate() }
+    }
+
+    /// Returns `UnexpectedAudioParametersChange` if `other` does not match
+    /// these parameters. This is used to reject chained streams which cannot be
+    /// measured as a single piece of audio.
+    pub fn check_unchanged(&self, other: &AudioParameters) -> Result<(), Error> {
+        if self == other {
+            Ok(())
+        } else {
+            Err(Error::UnexpectedAudioParametersChange)
+        }
+    }
+}
+
+/// A non-fatal problem found in the audio of a file while analyzing it
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AnalysisIssue {
+    /// The duration implied by the final granule positions of the file differs
+    /// from the duration of the audio decoded from it. This usually means pages
+    /// are missing from the middle of the file, such as after an incomplete
+    /// download.
+    DurationMismatch { granule: Duration, decoded: Duration },
+}
+
+impl AnalysisIssue {
+    /// Returns `DurationMismatch` if the duration implied by granule positions
+    /// differs from the decoded duration by more than `threshold`
+    #[must_use]
+    pub fn check_duration(granule: Duration, decoded: Duration, threshold: Duration) -> Option<AnalysisIssue> {
+        let difference = granule.max(decoded) - granule.min(decoded);
+        (difference > threshold).then_some(AnalysisIssue::DurationMismatch { granule, decoded })
+    }
+}
+
+impl Display for AnalysisIssue {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AnalysisIssue::DurationMismatch { granule, decoded } => write!(
+                formatter,
+                "Granule positions imply a duration of {:.3}s but {:.3}s of audio was decoded, so the file may be \
+                 damaged",
+                granule.as_secs_f64(),
+                decoded.as_secs_f64()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_parameters_accepted() {
+        let params = AudioParameters { channel_count: 2, sample_rate: 48000 };
+        assert!(params.check_unchanged(&params).is_ok());
+    }
+
+    #[test]
+    fn changed_parameters_rejected() {
+        let params = AudioParameters { channel_count: 2, sample_rate: 48000 };
+        for other in [
+            AudioParameters { channel_count: 1, sample_rate: 48000 },
+            AudioParameters { channel_count: 2, sample_rate: 44100 },
+            AudioParameters { channel_count: 1, sample_rate: 44100 },
+        ] {
+            assert!(matches!(params.check_unchanged(&other), Err(Error::UnexpectedAudioParametersChange)));
+        }
+    }
+
+    #[test]
+    fn duration_mismatch_exceeds_threshold() {
+        let threshold = DEFAULT_MAX_DURATION_MISMATCH;
+        let (short, long) = (Duration::from_millis(9500), Duration::from_millis(11_000));
+        assert_eq!(AnalysisIssue::check_duration(long, long, threshold), None);
+        assert_eq!(AnalysisIssue::check_duration(long, Duration::from_secs(10), threshold), None);
+        for (granule, decoded) in [(long, short), (short, long)] {
+            let issue = AnalysisIssue::check_duration(granule, decoded, threshold);
+            assert_eq!(issue, Some(AnalysisIssue::DurationMismatch { granule, decoded }));
+        }
+    }
+}
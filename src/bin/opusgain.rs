@@ -1,37 +1,79 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::uninlined_format_args)]
 
+#[path = "../civil_date.rs"]
+mod civil_date;
+
+#[path = "../cli_docs.rs"]
+mod cli_docs;
+
 #[path = "../console_output.rs"]
 mod console_output;
 
 #[path = "../ctrlc_handling.rs"]
 mod ctrlc_handling;
 
+#[path = "../exec_after.rs"]
+mod exec_after;
+
+#[path = "../exit_code.rs"]
+mod exit_code;
+
+#[path = "../loudness_report.rs"]
+mod loudness_report;
+
 #[path = "../output_file.rs"]
 mod output_file;
 
-use std::collections::{BTreeMap, HashMap};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Write};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
 
-use clap::{Parser, ValueEnum};
-use console_output::{ConsoleOutput, Delayed as DelayedConsoleOutput, Standard};
+use clap::builder::PossibleValuesParser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, ValueEnum, ValueHint};
+use clap_complete::Shell;
+use console_output::{ConsoleOutput, Delayed as DelayedConsoleOutput, Filtered, Standard};
 use ctrlc_handling::CtrlCChecker;
-use ogg::reading::PacketReader;
-use output_file::OutputFile;
+use exec_after::ExecAfter;
+use loudness_report::{write_analysis, write_gain_reports_csv, write_vendor_stats, AlbumReport, ReportFormat};
+use output_file::{OutputFile, OutputFileOptions, TempNaming};
 use parking_lot::Mutex;
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use rayon::ThreadPoolBuilder;
 use thiserror::Error;
-use zoog::file_timestamp::set_mtime_with_minimal_increment;
-use zoog::header_rewriter::{rewrite_stream_with_interrupt, SubmitResult};
+use zoog::case_sensitivity::CaseSensitivityCache;
+use zoog::file_timestamp::{read_mtime, set_mtime, set_mtime_with_minimal_increment, FileSnapshot};
+use zoog::hashing::{DigestAlgorithm, HashingReader, HashingWriter};
+use zoog::header::replaygain::foreign_gain_tags;
+use zoog::header::CommentList as _;
+use zoog::header_issue::{HeaderIssue, IssueSummary, WithIssues};
+use zoog::header_rewriter::{
+    rewrite_stream_with_interrupt, rewrite_stream_with_stats, CodecHeaders, DuplicateHeaderAction, HeaderSummarize,
+    NoRewrite, RewriteStats, SubmitResult,
+};
+use zoog::input_paths::expand_inputs;
+use zoog::interrupt::{DeadlineInterrupt, Interrupt};
+use zoog::io::{input_source, is_archive_path, is_url};
+use zoog::lint::{self, LintSeverity, LintSummary};
+use zoog::loudness::{amplitude_to_dbfs, combine_measurements, combine_sample_peaks, TrackMeasurement};
+use zoog::loudness_class::LoudnessClassifier;
 use zoog::opus::{VolumeAnalyzer, TAG_ALBUM_GAIN, TAG_TRACK_GAIN};
+use zoog::output::AppendFile;
+use zoog::report::{write_file_results_json, AnalysisResult, FileDigests, FileResult, FileStatus, TrackResult};
+use zoog::track_order::{sort_by_track, TrackPosition};
+use zoog::vendor_stats::{EncoderSummary, VendorStats};
 use zoog::volume_rewrite::{
-    GainsSummary, OpusGains, OutputGainMode, VolumeHeaderRewrite, VolumeRewriterConfig, VolumeTarget,
+    check_tags, limit_output_gain_to_peak, max_headroom_target, output_gain_spread, plan, plan_with_explanation,
+    plan_with_issues, with_volumes_from_tags, ForeignTagPolicy, GainsSummary, OpusGains, OutputGainMode, PeakCheck,
+    VolumeHeaderRewrite, VolumeRewriterConfig, VolumeTarget, DEFAULT_TOLERANCE,
 };
-use zoog::{Decibels, Error, R128_LUFS, REPLAY_GAIN_LUFS};
+use zoog::{build_info, parse_number, Codec, Decibels, Error, Severity, R128_LUFS, REPLAY_GAIN_LUFS};
 
 #[derive(Debug, Error)]
 enum AppError {
@@ -40,67 +82,261 @@ enum AppError {
 
     #[error("Unable to register Ctrl-C handler: `{0}`")]
     CtrlCRegistration(#[from] ctrlc_handling::CtrlCRegistrationError),
+
+    #[error("{0} file(s) could not be processed")]
+    FilesFailed(usize),
+
+    #[error("{0} file(s) have gains inconsistent with the target")]
+    TagsInconsistent(usize),
+
+    #[error("{0} file(s) have lint warnings")]
+    LintWarnings(usize),
+
+    #[error("{0} album(s) would have output gains jumping between tracks")]
+    GainJumps(usize),
+
+    #[error("Unable to find an unused path for `{0}`")]
+    NoUnusedPath(PathBuf),
+}
+
+impl AppError {
+    fn in_file(self, path: &Path) -> AppError {
+        match self {
+            AppError::Library(e) => AppError::Library(e.in_file(path)),
+            e @ (AppError::CtrlCRegistration(_)
+            | AppError::FilesFailed(_)
+            | AppError::TagsInconsistent(_)
+            | AppError::LintWarnings(_)
+            | AppError::GainJumps(_)
+            | AppError::NoUnusedPath(_)) => e,
+        }
+    }
+
+    /// The underlying library error, ignoring the file it occurred in
+    fn library_error(&self) -> Option<&Error> {
+        match self {
+            AppError::Library(Error::InFile { source, .. }) => Some(source),
+            AppError::Library(e) => Some(e),
+            AppError::CtrlCRegistration(_)
+            | AppError::FilesFailed(_)
+            | AppError::TagsInconsistent(_)
+            | AppError::LintWarnings(_)
+            | AppError::GainJumps(_)
+            | AppError::NoUnusedPath(_) => None,
+        }
+    }
+
+    fn is_interrupt(&self) -> bool { matches!(self.library_error(), Some(Error::Interrupted)) }
+
+    fn is_timeout(&self) -> bool { matches!(self.library_error(), Some(Error::TimedOut)) }
+
+    fn is_internal(&self) -> bool { matches!(self.library_error(), Some(Error::Internal(_))) }
+
+    fn is_modified_during_processing(&self) -> bool {
+        matches!(self.library_error(), Some(Error::ModifiedDuringProcessing(_)))
+    }
+
+    /// Whether the error, including a panic, only affects the file it occurred
+    /// in, so that other files can still be processed unless failing fast
+    fn is_confined_to_file(&self) -> bool { self.severity() == Severity::File || self.is_internal() }
+
+    fn severity(&self) -> Severity {
+        match self {
+            AppError::Library(e) => e.severity(),
+            AppError::CtrlCRegistration(_) | AppError::NoUnusedPath(_) => Severity::Environment,
+            AppError::FilesFailed(_)
+            | AppError::TagsInconsistent(_)
+            | AppError::LintWarnings(_)
+            | AppError::GainJumps(_) => Severity::File,
+        }
+    }
 }
 
 fn main() {
     match main_impl() {
         Ok(()) => {}
         Err(e) => {
-            eprintln!("Aborted due to error: {}", e);
-            std::process::exit(1);
+            match e {
+                // Failures have already been reported individually
+                AppError::FilesFailed(_) | AppError::TagsInconsistent(_) | AppError::LintWarnings(_) => {}
+                ref e => eprintln!("Aborted due to error: {}", e),
+            }
+            std::process::exit(exit_code::for_severity(e.severity()));
         }
     }
 }
 
-fn check_running(checker: &CtrlCChecker) -> Result<(), Error> {
-    if checker.is_running() {
-        Ok(())
-    } else {
-        Err(Error::Interrupted)
+/// Runs `body`, converting a panic into `Error::Internal` so it can be
+/// reported against the file being processed rather than aborting the batch
+fn catch_panic<T, E, F>(body: F) -> Result<T, E>
+where
+    E: From<Error>,
+    F: FnOnce() -> Result<T, E>,
+{
+    // State shared between files is either atomic or protected by `parking_lot`
+    // mutexes, which do not poison. Each thread's analyzer is reset before it is
+    // used again. Any temporary file is deleted as the `OutputFile` owning it is
+    // dropped during unwinding.
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| (*message).to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(Error::Internal(message).into())
+    })
+}
+
+/// Interrupt for an operation on a single file, which is set when `outer` is
+/// or once the optional per-file timeout has elapsed
+struct FileInterrupt<'a, I> {
+    outer: &'a I,
+    deadline: Option<DeadlineInterrupt>,
+}
+
+impl<'a, I: Interrupt> FileInterrupt<'a, I> {
+    /// Constructs an interrupt whose deadline, if any, starts from now
+    fn new(outer: &'a I, timeout: Option<Duration>) -> FileInterrupt<'a, I> {
+        FileInterrupt { outer, deadline: timeout.map(DeadlineInterrupt::after) }
+    }
+
+    fn timed_out(&self) -> bool { !self.outer.is_set() && self.deadline.is_some_and(|deadline| deadline.is_set()) }
+
+    /// Converts an interruption caused by the deadline passing into
+    /// `Error::TimedOut`
+    fn classify(&self, error: Error) -> Error {
+        match error {
+            Error::Interrupted if self.timed_out() => Error::TimedOut,
+            error => error,
+        }
     }
+
+    fn check(&self) -> Result<(), Error> {
+        if self.is_set() {
+            Err(self.classify(Error::Interrupted))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<I: Interrupt> Interrupt for FileInterrupt<'_, I> {
+    fn is_set(&self) -> bool { self.outer.is_set() || self.deadline.is_some_and(|deadline| deadline.is_set()) }
+}
+
+thread_local! {
+    /// An analyzer for each worker thread, reused between files so that its
+    /// decoder and buffers are not reallocated for every file
+    static ANALYZER: RefCell<VolumeAnalyzer> = RefCell::new(VolumeAnalyzer::default());
 }
 
-fn apply_volume_analysis<P, C>(
-    analyzer: &mut VolumeAnalyzer, path: P, console_output: &C, report_error: bool, interrupt_checker: &CtrlCChecker,
-) -> Result<(), Error>
+/// Runs `body` with the analyzer of the current thread, reset so that it holds
+/// no results from previous files
+fn with_analyzer<T, F: FnOnce(&mut VolumeAnalyzer) -> T>(body: F) -> T {
+    ANALYZER.with(|analyzer| {
+        let mut analyzer = analyzer.borrow_mut();
+        analyzer.reset_for_new_file();
+        body(&mut analyzer)
+    })
+}
+
+/// Analyzes the loudness of the file at `path`. If `quick` is set only a window
+/// of that length from the middle of the file is analyzed. `None` is returned
+/// if the file contains no audio. A warning is printed if the decoded duration
+/// differs from that implied by granule positions by more than
+/// `max_duration_mismatch`.
+fn apply_volume_analysis<P, C, I>(
+    analyzer: &mut VolumeAnalyzer, path: P, console_output: &C, report_error: bool, interrupt: &FileInterrupt<I>,
+    quick: Option<Duration>, max_duration_mismatch: Duration,
+) -> Result<Option<TrackMeasurement>, Error>
 where
     P: AsRef<Path>,
     C: ConsoleOutput,
+    I: Interrupt,
 {
-    let mut body = || -> Result<(), Error> {
+    let mut body = || -> Result<Option<TrackMeasurement>, Error> {
         let input_path = path.as_ref();
-        let input_file = File::open(input_path).map_err(|e| Error::FileOpenError(input_path.to_path_buf(), e))?;
-        let input_file = BufReader::new(input_file);
-        let mut ogg_reader = PacketReader::new(input_file);
-        loop {
-            check_running(interrupt_checker)?;
-            match ogg_reader.read_packet() {
-                Err(e) => break Err(Error::OggDecode(e)),
-                Ok(None) => {
-                    analyzer.file_complete();
-                    writeln!(
-                        console_output.out(),
-                        "Computed loudness of {} as {:.2} LUFS (ignoring output gain)",
-                        input_path.display(),
-                        analyzer.last_track_lufs().expect("Last track volume unexpectedly missing").as_f64()
-                    )
-                    .map_err(Error::ConsoleIoError)?;
-                    break Ok(());
-                }
-                Ok(Some(packet)) => analyzer.submit(packet)?,
-            }
+        let input_file = BufReader::new(input_source(input_path)?.open()?);
+        let measurement = match quick {
+            None => analyzer.analyze_stream_with_interrupt(input_file, interrupt)?,
+            Some(length) => analyzer.analyze_centered_window_with_interrupt(input_file, length, interrupt)?,
+        };
+        if let Some(ref measurement) = measurement {
+            writeln!(
+                console_output.out(),
+                "Computed {}loudness of {} as {:.2} LUFS (ignoring output gain)",
+                if quick.is_some() { "approximate " } else { "" },
+                input_path.display(),
+                measurement.lufs.as_f64()
+            )
+        } else {
+            writeln!(console_output.out(), "No audio found in {}", input_path.display())
         }
-    };
-    let result = body();
-    if report_error {
-        if let Err(ref e) = result {
-            writeln!(console_output.err(), "Failed to analyze volume of {}: {}", path.as_ref().display(), e)
+        .map_err(Error::ConsoleIoError)?;
+        if let Some(issue) = measurement.as_ref().and_then(|track| track.duration_issue(max_duration_mismatch)) {
+            writeln!(console_output.err(), "Warning: {}: {}", input_path.display(), issue)
                 .map_err(Error::ConsoleIoError)?;
         }
+        Ok(measurement)
+    };
+    let result = body().map_err(|e| interrupt.classify(e).in_file(path.as_ref()));
+    if let Err(ref e) = result {
+        if report_error {
+            writeln!(console_output.err(), "Failed to analyze volume: {}", e).map_err(Error::ConsoleIoError)?;
+        }
     }
     result
 }
 
+fn print_issues<C: ConsoleOutput>(issues: &[HeaderIssue], console: &C) -> Result<(), Error> {
+    for issue in issues {
+        writeln!(console.err(), "Warning: {}", issue).map_err(Error::ConsoleIoError)?;
+    }
+    Ok(())
+}
+
+/// Warns about R128 gain tags which were left unchanged since the gains computed
+/// for them cannot be represented
+fn print_unrepresentable_gains<C: ConsoleOutput>(
+    existing: &OpusGains, config: &VolumeRewriterConfig, from_tags: bool, console: &C,
+) -> Result<(), Error> {
+    let config = if from_tags { with_volumes_from_tags(existing, config) } else { *config };
+    let (_, issues) = plan_with_issues(existing, &config)?;
+    print_issues(&issues, console)
+}
+
+/// Warns that ReplayGain tags written by other tools were left unchanged by a
+/// change to the output gain, so no longer describe the decoded audio
+fn print_foreign_tag_warning<C: ConsoleOutput>(foreign_tags: &[String], console: &C) -> Result<(), Error> {
+    if foreign_tags.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        console.err(),
+        "Warning: The output gain changed so {} written by other tools {} now stale. Use --fix-foreign-tags to \
+         adjust them or --drop-foreign-tags to remove them.",
+        foreign_tags.join(", "),
+        if foreign_tags.len() == 1 { "is" } else { "are" }
+    )
+    .map_err(Error::ConsoleIoError)
+}
+
+/// Reports any duplicate comment headers found by a rewrite
+fn print_duplicate_headers<C: ConsoleOutput>(
+    stats: &RewriteStats, action: DuplicateHeaderAction, console: &C,
+) -> Result<(), Error> {
+    if stats.duplicate_headers == 0 {
+        return Ok(());
+    }
+    let message = match action {
+        DuplicateHeaderAction::Preserve => {
+            "Warning: The comment header is duplicated. Use --drop-duplicate-headers to remove the duplicate."
+        }
+        DuplicateHeaderAction::Drop => "Removing duplicate comment header.",
+    };
+    writeln!(console.err(), "{}", message).map_err(Error::ConsoleIoError)
+}
+
 fn print_gains<C: ConsoleOutput>(gains: &OpusGains, console: &C) -> Result<(), Error> {
     let do_io = || {
         writeln!(console.out(), "\tOutput Gain: {}", gains.output)?;
@@ -115,58 +351,278 @@ fn print_gains<C: ConsoleOutput>(gains: &OpusGains, console: &C) -> Result<(), E
     do_io().map_err(Error::ConsoleIoError)
 }
 
+/// Prints the class `classifier` assigns to a file with the gains `existing`
+fn print_classification<C: ConsoleOutput>(
+    classifier: &LoudnessClassifier, existing: &OpusGains, config: &VolumeRewriterConfig, from_tags: bool, console: &C,
+) -> Result<(), Error> {
+    let config = if from_tags { with_volumes_from_tags(existing, config) } else { *config };
+    if let Some(track_volume) = config.track_volume {
+        writeln!(console.out(), "Loudness class: {}={}", classifier.tag(), classifier.classify(track_volume))
+            .map_err(Error::ConsoleIoError)?;
+    }
+    Ok(())
+}
+
+/// Prints each step of the computation of the gains planned for a file with the
+/// gains `existing`, exactly as the rewrite computes them, followed by the
+/// sample peak `peak` after the output gain if it was measured
+fn print_explanation<C: ConsoleOutput>(
+    existing: &OpusGains, config: &VolumeRewriterConfig, from_tags: bool, peak: Option<Decibels>, console: &C,
+) -> Result<(), Error> {
+    let config = if from_tags { with_volumes_from_tags(existing, config) } else { *config };
+    let (gains, explanation) = plan_with_explanation(existing, &config)?;
+    let lufs =
+        |volume: Option<Decibels>| volume.map_or_else(|| String::from("none"), |v| format!("{:.3} LUFS", v.as_f64()));
+    let output_gain = explanation.output_gain.as_decibels();
+    let do_io = || {
+        writeln!(console.out(), "Gain calculation:")?;
+        writeln!(console.out(), "\tTrack loudness: {}", lufs(explanation.track_volume))?;
+        writeln!(console.out(), "\tAlbum loudness: {}", lufs(explanation.album_volume))?;
+        writeln!(console.out(), "\tTarget: {}", explanation.target.to_friendly_string())?;
+        match (explanation.target, explanation.source_volume, explanation.raw_output_gain) {
+            (VolumeTarget::LUFS(target), Some(source), Some(raw)) => {
+                let mode = match explanation.output_gain_mode {
+                    OutputGainMode::Album => "album",
+                    OutputGainMode::Track => "track",
+                };
+                writeln!(console.out(), "\tSource loudness: {} ({} loudness)", lufs(Some(source)), mode)?;
+                writeln!(
+                    console.out(),
+                    "\tUnrounded output gain = target - source = {:.3} - {:.3} = {:.3} dB",
+                    target.as_f64(),
+                    source.as_f64(),
+                    raw.as_f64()
+                )?;
+            }
+            _ => writeln!(console.out(), "\tThe output gain is not computed from a loudness")?,
+        }
+        writeln!(
+            console.out(),
+            "\tOutput gain (in steps of 1/256 dB) = {}/256 dB = {}",
+            explanation.output_gain.as_fixed_point(),
+            output_gain
+        )?;
+        for (tag, name, volume, raw, gain) in [
+            (TAG_TRACK_GAIN, "track", explanation.track_volume, explanation.raw_track_r128, gains.track_r128),
+            (TAG_ALBUM_GAIN, "album", explanation.album_volume, explanation.raw_album_r128, gains.album_r128),
+        ] {
+            if let (Some(volume), Some(raw), Some(gain)) = (volume, raw, gain) {
+                writeln!(
+                    console.out(),
+                    "\t{} = reference - {} loudness - output gain = {:.3} - {:.3} - {} = {:.3} dB, rounded to {}",
+                    tag,
+                    name,
+                    explanation.r128_reference.as_f64(),
+                    volume.as_f64(),
+                    output_gain,
+                    raw.as_f64(),
+                    gain
+                )?;
+            } else {
+                writeln!(console.out(), "\t{}: none", tag)?;
+            }
+        }
+        if let Some(peak) = peak {
+            let check = PeakCheck { peak, output_gain };
+            writeln!(
+                console.out(),
+                "\tSample peak after output gain = sample peak + output gain = {:.3} + {} = {:.3} dBFS (headroom \
+                 {:.3} dB)",
+                peak.as_f64(),
+                output_gain,
+                check.peak_after_output_gain().as_f64(),
+                check.headroom().as_f64()
+            )?;
+        }
+        Ok(())
+    };
+    do_io().map_err(Error::ConsoleIoError)
+}
+
+/// Warns if the output gain of `gains` raises a sample peak of `peak` above
+/// full scale
+fn print_peak_warning<C: ConsoleOutput>(peak: Option<Decibels>, gains: &OpusGains, console: &C) -> Result<(), Error> {
+    let Some(peak) = peak else {
+        return Ok(());
+    };
+    let check = PeakCheck { peak, output_gain: gains.output };
+    if check.exceeds_full_scale() {
+        writeln!(
+            console.err(),
+            "Warning: The output gain of {} raises the sample peak to {:.2} dBFS, exceeding the headroom of {:.2} dB, \
+             so playback may clip before any R128 gain is applied. Use --limit-output-gain-to-peak to prevent this.",
+            gains.output,
+            check.peak_after_output_gain().as_f64(),
+            check.headroom().as_f64()
+        )
+        .map_err(Error::ConsoleIoError)?;
+    }
+    Ok(())
+}
+
+/// Collects one line per modified file so they can be printed in the order the
+/// files were supplied
+#[derive(Debug)]
+struct ChangeLog {
+    dry_run: bool,
+    lines: Mutex<BTreeMap<usize, String>>,
+}
+
+impl ChangeLog {
+    fn new(dry_run: bool) -> ChangeLog { ChangeLog { dry_run, lines: Mutex::new(BTreeMap::new()) } }
+
+    fn record(&self, idx: usize, path: &Path, from: &OpusGains, to: &OpusGains) {
+        let prefix = if self.dry_run { "would-change\t" } else { "" };
+        let line = format!("{}{}\t{}\t{}", prefix, path.display(), from.output, to.output);
+        self.lines.lock().insert(idx, line);
+    }
+
+    fn into_lines(self) -> impl Iterator<Item = String> { self.lines.into_inner().into_values() }
+}
+
 #[derive(Debug)]
 struct AlbumVolume {
     mean: Decibels,
-    tracks: HashMap<PathBuf, Decibels>,
+    peak: Decibels,
+    tracks: HashMap<PathBuf, TrackMeasurement>,
+    failed: HashSet<PathBuf>,
 }
 
 impl AlbumVolume {
+    /// Combines the measurements of tracks, which are supplied in album order.
+    /// The files in `failed` could not be measured so are left out.
+    fn new(tracks: Vec<(PathBuf, TrackMeasurement)>, failed: HashSet<PathBuf>) -> AlbumVolume {
+        let mean = combine_measurements(tracks.iter().map(|(_, track)| track));
+        let peak = amplitude_to_dbfs(combine_sample_peaks(tracks.iter().map(|(_, track)| track)));
+        AlbumVolume { mean, peak, tracks: tracks.into_iter().collect(), failed }
+    }
+
+    /// Whether measuring the file at `path` failed. The failure has already
+    /// been reported.
+    pub fn has_failed(&self, path: &Path) -> bool { self.failed.contains(path) }
+
+    pub fn num_failed(&self) -> usize { self.failed.len() }
+
     pub fn get_album_mean(&self) -> Decibels { self.mean }
 
-    pub fn get_track_mean(&self, path: &Path) -> Option<Decibels> { self.tracks.get(path).copied() }
+    pub fn get_album_peak(&self) -> Decibels { self.peak }
+
+    pub fn get_track(&self, path: &Path) -> Option<&TrackMeasurement> { self.tracks.get(path) }
+
+    pub fn get_track_mean(&self, path: &Path) -> Option<Decibels> { self.tracks.get(path).map(|track| track.lufs) }
+
+    /// The volume and sample peak of each track
+    pub fn get_track_volumes_and_peaks(&self) -> impl Iterator<Item = (Decibels, Option<Decibels>)> + '_ {
+        self.tracks.values().map(|track| (track.lufs, Some(track.sample_peak_dbfs())))
+    }
 }
 
-fn compute_album_volume<I, P, C>(
-    paths: I, console_output: &C, interrupt_checker: &CtrlCChecker,
-) -> Result<AlbumVolume, Error>
+/// An album whose tracks are still being measured by `measure_albums`
+#[derive(Debug)]
+struct PendingAlbum {
+    remaining: usize,
+    tracks: BTreeMap<usize, (PathBuf, TrackMeasurement)>,
+    failed: HashSet<PathBuf>,
+}
+
+/// Measures the tracks of several albums as a single list of tasks, so that
+/// the albums are measured in parallel with each other as well as within
+/// themselves and a slow album does not hold up the rest. The volume of each
+/// album is computed as soon as all of its tracks have been measured and
+/// passed to `on_complete` together with the index of the album, which allows
+/// work on that album to begin while others are still being measured. Albums
+/// without any files are never passed to `on_complete`.
+///
+/// `measure` returns `None` for files without audio, which are left out of
+/// their album. A file which cannot be measured is also left out and recorded
+/// as failed, unless `fail_fast` is set or the error affects more than that
+/// file, in which case measurement is abandoned. The volumes are returned in
+/// the order the albums were supplied, with the tracks of each combined in the
+/// supplied order.
+fn measure_albums<P, M, F>(
+    albums: &[Vec<P>], measure: M, fail_fast: bool, on_complete: F,
+) -> Result<Vec<AlbumVolume>, AppError>
 where
-    I: IntoIterator<Item = P>,
     P: AsRef<Path> + Sync,
-    C: ConsoleOutput + Sync,
+    M: Fn(&Path) -> Result<Option<TrackMeasurement>, Error> + Sync,
+    F: Fn(usize, &AlbumVolume) + Sync,
 {
-    let paths: Vec<_> = paths.into_iter().enumerate().collect();
-    let tracks = Mutex::new(HashMap::new());
-
-    // This is a BTreeMap so we process the analyzers in the supplied order
-    let analyzers = Mutex::new(BTreeMap::new());
-
-    paths.into_par_iter().panic_fuse().try_for_each(|(idx, input_path)| -> Result<(), Error> {
-        let mut analyzer = VolumeAnalyzer::default();
-        apply_volume_analysis(
-            &mut analyzer,
-            input_path.as_ref(),
-            &DelayedConsoleOutput::new(console_output),
-            true,
-            interrupt_checker,
-        )?;
-        tracks.lock().insert(
-            input_path.as_ref().to_path_buf(),
-            analyzer.last_track_lufs().expect("Track volume unexpectedly missing"),
-        );
-        analyzers.lock().insert(idx, analyzer);
+    let pending: Vec<_> = albums
+        .iter()
+        .map(|album| {
+            Mutex::new(PendingAlbum { remaining: album.len(), tracks: BTreeMap::new(), failed: HashSet::new() })
+        })
+        .collect();
+    let volumes: Vec<_> = albums.iter().map(|_| Mutex::new(None)).collect();
+    let tasks: Vec<_> = albums
+        .iter()
+        .enumerate()
+        .flat_map(|(album_idx, album)| album.iter().enumerate().map(move |(idx, path)| (album_idx, idx, path)))
+        .collect();
+
+    tasks.into_par_iter().panic_fuse().try_for_each(|(album_idx, idx, input_path)| -> Result<(), AppError> {
+        let path = input_path.as_ref();
+        let track = match measure(path).map_err(AppError::from) {
+            Ok(track) => Ok(track),
+            Err(e) if !fail_fast && e.is_confined_to_file() => Err(()),
+            Err(e) => return Err(e),
+        };
+        let completed = {
+            let mut album = pending[album_idx].lock();
+            match track {
+                Ok(Some(track)) => {
+                    album.tracks.insert(idx, (path.to_path_buf(), track));
+                }
+                // Files without audio are left out of the album
+                Ok(None) => {}
+                Err(()) => {
+                    album.failed.insert(path.to_path_buf());
+                }
+            }
+            album.remaining -= 1;
+            (album.remaining == 0).then(|| (std::mem::take(&mut album.tracks), std::mem::take(&mut album.failed)))
+        };
+        if let Some((tracks, failed)) = completed {
+            let volume = AlbumVolume::new(tracks.into_values().collect(), failed);
+            on_complete(album_idx, &volume);
+            *volumes[album_idx].lock() = Some(volume);
+        }
         Ok(())
     })?;
 
-    let analyzers = analyzers.into_inner();
-    let analyzers: Vec<_> = analyzers.into_values().collect();
-    let tracks = tracks.into_inner();
-    let mean = VolumeAnalyzer::mean_lufs_across_multiple(analyzers.iter());
-    let album_volume = AlbumVolume { mean, tracks };
-    Ok(album_volume)
+    let volumes = volumes
+        .into_iter()
+        .map(|volume| volume.into_inner().unwrap_or_else(|| AlbumVolume::new(Vec::new(), HashSet::new())));
+    Ok(volumes.collect())
+}
+
+/// Measures the files in `paths` as a single album. Files which cannot be
+/// measured are left out as described by `measure_albums`.
+fn compute_album_volume<I, P, C>(
+    paths: I, console_output: &C, interrupt_checker: &CtrlCChecker, timeout: Option<Duration>, quick: Option<Duration>,
+    max_duration_mismatch: Duration, fail_fast: bool,
+) -> Result<AlbumVolume, AppError>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path> + Sync,
+    C: ConsoleOutput + Sync,
+{
+    let measure = |input_path: &Path| {
+        with_analyzer(|analyzer| {
+            let console = DelayedConsoleOutput::new(console_output);
+            let interrupt = FileInterrupt::new(interrupt_checker, timeout);
+            catch_panic(|| {
+                apply_volume_analysis(analyzer, input_path, &console, true, &interrupt, quick, max_duration_mismatch)
+            })
+            .map_err(|e| e.in_file(input_path))
+        })
+    };
+    let mut volumes = measure_albums(&[paths.into_iter().collect()], measure, fail_fast, |_, _| {})?;
+    volumes.pop().ok_or_else(|| Error::Internal(String::from("Album volume unexpectedly missing")).into())
 }
 
 #[derive(Copy, Clone, Debug, ValueEnum)]
+#[allow(clippy::doc_markdown)]
 enum Preset {
     /// ReplayGain (normalize to -18 LUFS)
     #[clap(name = "rg")]
@@ -183,6 +639,11 @@ enum Preset {
     /// leave the output gain unchanged
     #[clap(name = "no-change")]
     NoChange,
+
+    /// loudest volume reachable without any output gain exceeding the peak
+    /// headroom
+    #[clap(name = "max-headroom")]
+    MaxHeadroom,
 }
 
 #[derive(Copy, Clone, Debug, ValueEnum)]
@@ -194,9 +655,23 @@ enum OutputGainSetting {
     Track,
 }
 
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum HashSetting {
+    /// SHA-256 (requires the sha2 feature)
+    Sha256,
+}
+
+impl From<HashSetting> for DigestAlgorithm {
+    fn from(setting: HashSetting) -> DigestAlgorithm {
+        match setting {
+            HashSetting::Sha256 => DigestAlgorithm::Sha256,
+        }
+    }
+}
+
 #[derive(Debug, Parser)]
-#[clap(author, version, about = "Modifies Ogg Opus output gain values and R128 tags")]
-#[allow(clippy::struct_excessive_bools)]
+#[clap(name = "opusgain", author, version, about = "Modifies Ogg Opus output gain values and R128 tags")]
+#[allow(clippy::struct_excessive_bools, clippy::doc_markdown)]
 struct Cli {
     #[clap(short, long, action)]
     /// Enable album mode
@@ -204,21 +679,66 @@ struct Cli {
 
     #[clap(value_enum, short, long, default_value_t = Preset::ReplayGain)]
     /// Choices for modifying the output gain value
+    ///
+    /// In all cases the R128_TRACK_GAIN and R128_ALBUM_GAIN tags are set so
+    /// that players which support them normalize playback to -23 LUFS relative
+    /// to the output gain. The preset only determines the volume heard in
+    /// players which ignore these tags. It is recommended to specify this value
+    /// explicitly, as the default may change.
     preset: Preset,
 
     #[clap(value_enum, short, long, default_value_t = OutputGainSetting::Auto)]
     /// When modifying the output gain to target a particular LUFS, what volume
     /// should be used
+    ///
+    /// Using track volume in album mode means that players which ignore R128
+    /// tags will play each track at the target loudness, while album
+    /// normalization remains available via the R128_ALBUM_GAIN tag.
     output_gain_mode: OutputGainSetting,
 
-    #[clap(required(true))]
-    /// The Opus files to process
+    #[clap(required(true), value_hint = ValueHint::AnyPath)]
+    /// The Opus files to process. A directory is replaced by the Ogg files
+    /// immediately inside it, in order of name. If built with the `http`
+    /// feature, modes which only read files also accept URLs, and if built
+    /// with the `archive` feature, files inside zip archives named as
+    /// `archive.zip!inner/path.opus`.
     input_files: Vec<PathBuf>,
 
+    #[clap(long, action)]
+    /// Do not replace directories given as inputs with the files they contain,
+    /// so that each is reported as an error instead.
+    no_expand_dirs: bool,
+
     #[clap(short = 'n', long = "dry-run", action)]
     /// Display output without performing any file modification.
     dry_run: bool,
 
+    #[clap(
+        long,
+        value_name = "PATH",
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = ["analyze_only", "check_tags", "lint", "vendor_stats", "changed_only"]
+    )]
+    /// Process a single input file as in a dry run, but write the file that
+    /// would replace it to PATH for inspection. The input is left untouched.
+    /// If the input would not change, PATH is a copy of it.
+    dry_run_output: Option<PathBuf>,
+
+    #[clap(long, action, requires = "dry_run_output")]
+    /// Replace any existing file at the path given by --dry-run-output.
+    overwrite: bool,
+
+    #[clap(
+        long,
+        value_enum,
+        value_name = "ALGORITHM",
+        conflicts_with_all = ["analyze_only", "check_tags", "lint", "vendor_stats"]
+    )]
+    /// Compute a digest of each input file and of the file that replaces it
+    /// while they are read and written, and include them in the reports. If a
+    /// file is unchanged, both digests are of the input.
+    hash: Option<HashSetting>,
+
     #[clap(short='j', long, default_value_t = num_cpus::get())]
     /// Number of threads to use for processing. Default is the number of cores
     /// on the system.
@@ -232,100 +752,1248 @@ struct Cli {
     #[clap(short = 'M', long, action)]
     /// Minimize modification timestamp increment when rewriting files.
     minimize_mtime_change: bool,
+
+    #[clap(long, value_name = "FILE", conflicts_with = "minimize_mtime_change", value_hint = ValueHint::FilePath)]
+    /// Set the modification time of each rewritten file to that of FILE
+    mtime_from: Option<PathBuf>,
+
+    #[clap(
+        long,
+        value_name = "COMMAND",
+        conflicts_with_all = ["analyze_only", "check_tags", "lint", "vendor_stats", "dry_run", "dry_run_output"]
+    )]
+    /// Run COMMAND after each file is modified, with {} replaced by its path
+    ///
+    /// COMMAND is split into words at whitespace, which may be quoted with
+    /// single or double quotes, and run directly rather than by a shell, so the
+    /// path is always part of a single argument. If COMMAND contains no {}, the
+    /// path is passed as a final argument. Commands are run one at a time and
+    /// are not run for files which are unchanged. A command which fails is
+    /// reported as a warning.
+    exec_after: Option<String>,
+
+    #[clap(long, action, requires = "exec_after")]
+    /// Run the command given by --exec-after with the system shell. On Unix,
+    /// {} is replaced by a reference to a positional parameter holding the
+    /// path, so the path is never interpreted by the shell.
+    exec_shell: bool,
+
+    #[clap(long, action, conflicts_with_all = ["analyze_only", "check_tags", "lint"])]
+    /// Replace read-only files owned by the current user, restoring their
+    /// permissions afterwards. Otherwise read-only files, and files in
+    /// directories which are not writable, are skipped before any analysis.
+    make_writable: bool,
+
+    #[clap(
+        long,
+        value_name = "MODE",
+        value_parser = output_file::parse_file_mode,
+        conflicts_with_all = ["analyze_only", "check_tags", "lint"]
+    )]
+    /// Octal Unix permissions for rewritten files, such as 664, applied
+    /// regardless of the umask. By default rewritten files keep their
+    /// permissions. Ignored on other platforms.
+    file_mode: Option<u32>,
+
+    #[clap(
+        long,
+        value_name = "DIR",
+        env = "ZOOG_TMPDIR",
+        value_hint = ValueHint::DirPath
+    )]
+    /// Directory in which to write rewritten files before they replace the
+    /// originals, rather than next to them. If it is on a different
+    /// filesystem, rewritten files are copied next to the originals before
+    /// replacing them, or copied over them if there is no room, which unlike
+    /// renaming is not atomic. Ignored when no files are rewritten, so that
+    /// it can be set in the environment.
+    temp_dir: Option<PathBuf>,
+
+    #[clap(long, value_enum, value_name = "NAMING", env = "ZOOG_TEMP_NAMING", default_value_t = TempNaming::Random)]
+    /// How temporary files are named. `deterministic` names the temporary
+    /// for a file `<stem><ext>` `<stem>-zoogtmp<ext>`, and fails if it already
+    /// exists, for example after an earlier run was killed.
+    temp_naming: TempNaming,
+
+    #[clap(long, action, conflicts_with_all = ["clear", "changed_only"])]
+    /// Only analyze loudness, printing the loudness and duration of each file
+    /// (and the album loudness in album mode) without modifying any files.
+    analyze_only: bool,
+
+    #[clap(long, value_enum, default_value_t = ReportFormat::Table, conflicts_with = "changed_only")]
+    /// Output format. Other than for `table`, per-file progress is suppressed
+    /// and a single record is printed for each file once processing is
+    /// complete.
+    format: ReportFormat,
+
+    #[clap(long, action)]
+    /// Only print a single tab-separated line for each file modified,
+    /// containing the path, old output gain and new output gain.
+    changed_only: bool,
+
+    #[clap(long, action, conflicts_with_all = ["analyze_only", "check_tags", "lint", "vendor_stats", "changed_only", "format"])]
+    /// Print each step of the computation of the gains of each file: the
+    /// measured loudness, the loudness the output gain is computed from, the
+    /// target, the unrounded and rounded output gain and the resulting R128
+    /// gains.
+    explain: bool,
+
+    #[clap(
+        long,
+        value_name = "SPEC",
+        value_parser = parse_classifier,
+        conflicts_with_all = ["clear", "analyze_only", "check_tags", "lint", "vendor_stats"]
+    )]
+    /// Set a comment according to the loudness of each track, e.g.
+    /// `LOUDNESS_CLASS:quiet<-30,normal<-18,loud`. Classes are listed from
+    /// quietest to loudest. A track takes the first class whose threshold in
+    /// LUFS its loudness is below, or otherwise the last class. Any existing
+    /// values of the comment are replaced.
+    classify: Option<LoudnessClassifier>,
+
+    #[clap(long, action, conflicts_with_all = ["clear", "from_tags", "analyze_only", "check_tags", "lint", "vendor_stats"])]
+    /// Lower the output gain of any file where it would raise the sample peak
+    /// above full scale to the available headroom, adjusting the R128 gains to
+    /// compensate. When the output gain is computed from the album loudness,
+    /// the peak of the whole album is used so that its tracks keep the same
+    /// output gain. Without this option a warning is printed instead.
+    limit_output_gain_to_peak: bool,
+
+    #[clap(
+        long,
+        action,
+        conflicts_with_all = [
+            "analyze_only", "clear", "changed_only", "dry_run", "minimize_mtime_change", "mtime_from", "format"
+        ]
+    )]
+    /// Check whether the existing output gain and R128 tags of each file are
+    /// consistent with the selected preset, without decoding any audio. Missing
+    /// or contradictory tags are also reported. No files are modified.
+    check_tags: bool,
+
+    #[clap(long, action, conflicts_with_all = ["analyze_only", "clear", "check_tags", "lint"])]
+    /// Compute the output gain and R128 tags from the loudness implied by the
+    /// existing R128 tags rather than by decoding the audio. Files without the
+    /// required tag are reported as failed.
+    from_tags: bool,
+
+    #[clap(long, action, conflicts_with_all = ["analyze_only", "check_tags", "lint"])]
+    /// Also write a REPLAYGAIN_REFERENCE_LOUDNESS tag recording the loudness
+    /// the output gain was normalized to, e.g. `-18.00 LUFS`. The tag is
+    /// removed for the original and no-change presets. It is always removed by
+    /// `--clear`.
+    write_reference_tag: bool,
+
+    #[clap(long, action, conflicts_with_all = ["album", "clear", "analyze_only", "check_tags", "lint"])]
+    /// Remove any existing R128_ALBUM_GAIN tag. Outside album mode it is
+    /// otherwise kept and adjusted for any change to the output gain, so that
+    /// normalizing one track of an album does not affect album playback.
+    drop_album_gain: bool,
+
+    #[clap(long, action, conflicts_with_all = ["analyze_only", "check_tags", "lint"])]
+    /// Remove a second comment header immediately following the first, as
+    /// written by some buggy tools. By default it is kept with a warning. It is
+    /// never analyzed as audio.
+    drop_duplicate_headers: bool,
+
+    #[clap(long, action, conflicts_with_all = ["drop_foreign_tags", "analyze_only", "check_tags", "lint"])]
+    /// Adjust REPLAYGAIN_* gain and peak tags written by other tools, such as
+    /// loudgain, by any change to the output gain so that they remain correct.
+    /// By default they are left unchanged with a warning.
+    fix_foreign_tags: bool,
+
+    #[clap(long, action, conflicts_with_all = ["analyze_only", "check_tags", "lint"])]
+    /// Remove REPLAYGAIN_* gain and peak tags written by other tools when the
+    /// output gain changes, since their values would no longer be correct.
+    drop_foreign_tags: bool,
+
+    #[clap(
+        long,
+        value_name = "DB",
+        default_value_t = DEFAULT_TOLERANCE.as_f64(),
+        value_parser = parse_finite,
+        requires = "check_tags"
+    )]
+    /// Maximum difference between the existing and expected output gain
+    /// permitted by `--check-tags`
+    tolerance: f64,
+
+    #[clap(
+        long,
+        action,
+        conflicts_with_all = [
+            "analyze_only", "clear", "changed_only", "dry_run", "minimize_mtime_change", "mtime_from", "format",
+            "check_tags"
+        ]
+    )]
+    /// Report suspicious combinations of header fields in each file, such as a
+    /// channel mapping table which does not match the channel count, without
+    /// decoding any audio. Each finding is labelled with a stable identifier.
+    /// No files are modified.
+    lint: bool,
+
+    #[clap(
+        long,
+        action,
+        conflicts_with_all = [
+            "album", "analyze_only", "clear", "changed_only", "dry_run", "minimize_mtime_change", "mtime_from",
+            "check_tags", "lint",
+            "make_writable", "file_mode", "from_tags", "write_reference_tag", "drop_album_gain",
+            "drop_duplicate_headers", "fix_foreign_tags", "drop_foreign_tags", "quick", "gapless_safe",
+            "allow_track_gain_jumps"
+        ]
+    )]
+    /// Count the files produced by each encoder, as identified by the vendor
+    /// string and any ENCODER tag, without decoding any audio. The encoders are
+    /// printed most common first with an example file for each. Files whose
+    /// headers cannot be read are counted separately. No files are modified.
+    vendor_stats: bool,
+
+    #[clap(long, value_name = "ID", value_parser = PossibleValuesParser::new(lint::rule_ids()), requires = "lint")]
+    /// Do not report findings with the specified identifier. May be specified
+    /// multiple times.
+    lint_allow: Vec<String>,
+
+    #[clap(long, value_name = "FILE", value_hint = ValueHint::FilePath, requires = "album")]
+    /// Path of the album report written in album mode. Use `-` to print the
+    /// report to standard output instead. Defaults to `album-loudness.txt` in
+    /// the directory of the first input file.
+    report: Option<PathBuf>,
+
+    #[clap(long, action, requires = "album")]
+    /// Overwrite an existing album report rather than adding a numbered suffix
+    /// to the name of the new one.
+    report_overwrite: bool,
+
+    #[clap(long, action, requires = "album", conflicts_with = "report_overwrite")]
+    /// Append the album report to an existing report, as for a rolling log,
+    /// rather than writing a new file. The file is locked while the report is
+    /// written so that the reports of concurrent runs are not interleaved.
+    report_append: bool,
+
+    #[clap(long, action, requires = "album")]
+    /// Process and report the files of the album in the order of their
+    /// DISCNUMBER and TRACKNUMBER tags rather than the order they were given.
+    /// Files without a track number follow in path order.
+    sort_by_track: bool,
+
+    #[clap(long, action)]
+    /// Treat any issue found in the headers of a file as an error for that
+    /// file.
+    strict_headers: bool,
+
+    #[clap(long, action)]
+    /// Stop processing further files after the first file that fails.
+    fail_fast: bool,
+
+    #[clap(long, value_name = "SECS", value_parser = parse_timeout)]
+    /// Maximum time to spend analyzing and rewriting each file. A file taking
+    /// longer is reported as timed out and skipped.
+    timeout_per_file: Option<Duration>,
+
+    #[clap(long, value_name = "SECS", conflicts_with_all = ["analyze_only", "check_tags", "lint", "vendor_stats"])]
+    /// Skip files modified less than SECS seconds ago, which may still be
+    /// being written. Files which change while being processed are always
+    /// skipped rather than replaced.
+    stable_seconds: Option<u64>,
+
+    #[clap(long, value_name = "SECS", default_value = "1", value_parser = parse_timeout)]
+    /// Warn about files whose decoded duration differs by more than SECS
+    /// seconds from the duration implied by their final granule positions,
+    /// which usually means pages are missing from the middle of the file.
+    max_duration_mismatch: Duration,
+
+    #[clap(
+        long,
+        value_name = "SECS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "600",
+        value_parser = parse_timeout,
+        conflicts_with_all = ["clear", "from_tags", "check_tags", "lint"]
+    )]
+    /// Analyze only a window of the specified length (default 600 seconds)
+    /// from the middle of each file. This is much faster for very long files
+    /// but the loudness found is approximate. Gains are only written based on
+    /// it if `--quick-write` is also specified.
+    quick: Option<Duration>,
+
+    #[clap(long, action, requires = "quick", conflicts_with = "analyze_only")]
+    /// Permit writing gains computed from the approximate loudness found by
+    /// `--quick`.
+    quick_write: bool,
+
+    #[clap(long, action, conflicts_with_all = ["clear", "analyze_only", "check_tags", "lint"])]
+    /// Give every track of an album likely to be played gaplessly a consistent
+    /// output gain if the planned output gains would otherwise differ. In album
+    /// mode the output gain is based on album volume, and otherwise the
+    /// existing output gains are kept.
+    gapless_safe: bool,
+
+    #[clap(long, action, conflicts_with_all = ["gapless_safe", "clear", "analyze_only", "check_tags", "lint"])]
+    /// Write differing output gains to the tracks of an album in album mode
+    /// even though this causes jumps in level between tracks played
+    /// gaplessly.
+    allow_track_gain_jumps: bool,
+
+    #[clap(
+        long,
+        value_name = "LUFS",
+        default_value_t = R128_LUFS.as_f64(),
+        allow_negative_numbers = true,
+        value_parser = parse_finite,
+        hide_short_help = true
+    )]
+    /// Reference loudness the R128 tags normalize to (advanced)
+    ///
+    /// WARNING: RFC 7845 requires R128_TRACK_GAIN and R128_ALBUM_GAIN to be
+    /// relative to -23 LUFS. Any other value produces files which conforming
+    /// players will play at the wrong volume. Only use this if your workflow
+    /// post-processes the tags.
+    r128_reference: f64,
+
+    #[clap(long, action, exclusive = true)]
+    /// Print version together with build information for bug reports
+    version_verbose: bool,
+
+    #[clap(long, value_name = "SHELL", hide = true, exclusive = true)]
+    /// Print shell completions for the specified shell
+    generate_completions: Option<Shell>,
+
+    #[clap(long, action, hide = true, exclusive = true)]
+    /// Print a man page in roff format
+    generate_manpage: bool,
 }
 
-#[allow(clippy::too_many_lines)]
-fn main_impl() -> Result<(), AppError> {
-    let interrupt_checker = CtrlCChecker::new()?;
-    let cli = Cli::parse_from(wild::args_os());
-    let album_mode = cli.album;
-    let minimize_mtime_change = cli.minimize_mtime_change;
-    let num_threads = if cli.num_threads == 0 {
-        eprintln!("The number of thread specified must be greater than 0.");
-        Err(Error::InvalidThreadCount)
-    } else {
-        let num_cores = num_cpus::get();
-        let rounded = std::cmp::min(cli.num_threads, num_cores);
-        if rounded != cli.num_threads {
-            eprintln!("Rounding down number of threads from {} to {}.", cli.num_threads, num_cores);
-        }
-        Ok(rounded)
-    }?;
-    ThreadPoolBuilder::new().num_threads(num_threads).build_global().expect("Failed to initialize thread pool");
+fn parse_finite(value: &str) -> Result<f64, String> { parse_number(value).map_err(|e| e.to_string()) }
 
-    let output_gain_mode = match cli.output_gain_mode {
-        OutputGainSetting::Auto => {
-            if album_mode {
-                OutputGainMode::Album
-            } else {
-                OutputGainMode::Track
+fn parse_classifier(value: &str) -> Result<LoudnessClassifier, String> {
+    value.parse().map_err(|e: Error| e.to_string())
+}
+
+/// Name of the album report written alongside the input files by default
+const DEFAULT_REPORT_NAME: &str = "album-loudness.txt";
+
+/// Returns `path` if nothing exists there, otherwise the first path formed by
+/// adding a numbered suffix to its file stem at which nothing exists
+fn unused_path(path: &Path) -> Result<PathBuf, AppError> {
+    if !path.exists() {
+        return Ok(path.to_path_buf());
+    }
+    let stem = path.file_stem().unwrap_or_default();
+    (1..=u64::MAX)
+        .map(|n| {
+            let mut name = stem.to_os_string();
+            name.push(format!("-{}", n));
+            if let Some(extension) = path.extension() {
+                name.push(".");
+                name.push(extension);
             }
+            path.with_file_name(name)
+        })
+        .find(|candidate| !candidate.exists())
+        .ok_or_else(|| AppError::NoUnusedPath(path.to_path_buf()))
+}
+
+/// How the album report is written if a file already exists at its path
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReportMode {
+    /// A numbered suffix is added to the name of the new report
+    Numbered,
+
+    /// The existing file is replaced
+    Overwrite,
+
+    /// The report is appended to the existing file
+    Append,
+}
+
+/// Writes the album report to `destination`, or alongside `first_input` if
+/// unspecified. The report is written atomically, or appended while the file
+/// is locked, and is discarded if `dry_run` is set. Returns the path of the
+/// report, or `None` if it was written to standard output.
+fn write_album_report(
+    report: &AlbumReport, destination: Option<&Path>, first_input: &Path, mode: ReportMode, dry_run: bool,
+    temp_naming: TempNaming,
+) -> Result<Option<PathBuf>, AppError> {
+    let path = match destination {
+        Some(path) if path == Path::new("-") => {
+            report.write(&mut std::io::stdout().lock()).map_err(Error::ConsoleIoError)?;
+            return Ok(None);
         }
-        OutputGainSetting::Track => OutputGainMode::Track,
-    };
-    let volume_target = match cli.preset {
-        Preset::ReplayGain => VolumeTarget::LUFS(REPLAY_GAIN_LUFS),
-        Preset::R128 => VolumeTarget::LUFS(R128_LUFS),
-        Preset::ZeroGain => VolumeTarget::ZeroGain,
-        Preset::NoChange => VolumeTarget::NoChange,
+        Some(path) => path.to_path_buf(),
+        None => first_input.with_file_name(DEFAULT_REPORT_NAME),
     };
+    let path = if mode == ReportMode::Numbered { unused_path(&path)? } else { path };
+    if mode == ReportMode::Append {
+        if !dry_run {
+            let mut text = Vec::new();
+            report.write(&mut text).map_err(Error::WriteError)?;
+            AppendFile::open(&path)?.append(&String::from_utf8_lossy(&text))?;
+        }
+        return Ok(Some(path));
+    }
+    let options = OutputFileOptions { temp_dir: None, temp_naming };
+    let mut output_file = OutputFile::new_target_or_discard(&path, options, dry_run)?;
+    {
+        let mut output_file = BufWriter::new(&mut output_file);
+        report.write(&mut output_file).and_then(|()| output_file.flush()).map_err(Error::WriteError)?;
+    }
+    output_file.commit()?;
+    Ok(Some(path))
+}
 
-    let dry_run = cli.dry_run;
-    let clear = cli.clear;
-    let (album_mode, volume_target) = if clear {
-        // We do not compute album loudness or change output gain when clearing tags
-        (false, VolumeTarget::NoChange)
+/// The volume target of a preset, or `None` if it depends on the volumes of
+/// the input files
+fn volume_target_for_preset(preset: Preset) -> Option<VolumeTarget> {
+    match preset {
+        Preset::ReplayGain => Some(VolumeTarget::LUFS(REPLAY_GAIN_LUFS)),
+        Preset::R128 => Some(VolumeTarget::LUFS(R128_LUFS)),
+        Preset::ZeroGain => Some(VolumeTarget::ZeroGain),
+        Preset::NoChange => Some(VolumeTarget::NoChange),
+        Preset::MaxHeadroom => None,
+    }
+}
+
+/// Summarizes the headers of the specified file without decoding any audio
+fn read_summary<S, I>(path: &Path, summarize: S, interrupt: &I) -> Result<S::Summary, Error>
+where
+    S: HeaderSummarize<Error = Error>,
+    I: Interrupt,
+{
+    let input_file = input_source(path)?.open()?;
+    let abort_on_unchanged = true;
+    let result = rewrite_stream_with_interrupt(
+        NoRewrite::default(),
+        summarize,
+        BufReader::new(input_file),
+        std::io::sink(),
+        abort_on_unchanged,
+        interrupt,
+    )?;
+    match result {
+        SubmitResult::HeadersUnchanged(summary) => Ok(summary),
+        SubmitResult::Good => Err(Error::MissingStream(Codec::Opus)),
+        SubmitResult::HeadersChanged { .. } => Err(Error::Internal(String::from("Headers unexpectedly changed"))),
+    }
+}
+
+/// Reads the gains from the headers of the specified file without decoding
+/// any audio
+fn read_gains<I: Interrupt>(path: &Path, strict_headers: bool, interrupt: &I) -> Result<WithIssues<OpusGains>, Error> {
+    let summarize = if strict_headers {
+        IssueSummary::strict(GainsSummary::default())
     } else {
-        (album_mode, volume_target)
+        IssueSummary::new(GainsSummary::default())
     };
+    read_summary(path, summarize, interrupt)
+}
 
-    let num_processed = AtomicUsize::new(0);
-    let num_already_normalized = AtomicUsize::new(0);
+/// Output gains planned for the tracks of one album which differ by more than
+/// this many decibels are reported as jumps in level
+const MAX_GAPLESS_GAIN_SPREAD_DB: f64 = 1.0;
 
-    if dry_run {
-        println!("Display-only mode is enabled so no files will actually be modified.\n");
-    }
+/// The ALBUM tag used to group files likely to be played gaplessly
+const TAG_ALBUM: &str = "ALBUM";
 
-    let console_output = Standard::default();
-    let input_files = cli.input_files;
-    let album_volume =
-        if album_mode { Some(compute_album_volume(&input_files, &console_output, &interrupt_checker)?) } else { None };
+/// Returns the gains from the codec headers together with the names of any
+/// ReplayGain tags written by other tools
+#[derive(Debug, Default)]
+struct ForeignTagsSummary {}
 
-    // Prevent us from rewriting more than one file at once. This is to stop us
-    // consuming too much disk space or leaving lots of temporary files around
-    // if we encounter an error.
-    let rewrite_mutex = Mutex::new(());
+impl HeaderSummarize for ForeignTagsSummary {
+    type Error = Error;
+    type Summary = (OpusGains, Vec<String>);
 
-    input_files.into_par_iter().panic_fuse().try_for_each(|input_path| -> Result<(), AppError> {
-        let console = &DelayedConsoleOutput::new(&console_output);
-        let body = || -> Result<(), AppError> {
-            writeln!(
-                console.out(),
+    fn summarize(&self, headers: &CodecHeaders) -> Result<(OpusGains, Vec<String>), Error> {
+        let gains = GainsSummary::default().summarize(headers)?;
+        let foreign_tags = match headers {
+            CodecHeaders::Opus(_, comments) => foreign_gain_tags(comments),
+            CodecHeaders::Vorbis(_, comments) => foreign_gain_tags(comments),
+        };
+        Ok((gains, foreign_tags))
+    }
+}
+
+/// Returns the gains from the codec headers together with the ALBUM tag
+#[derive(Debug, Default)]
+struct AlbumGainsSummary {}
+
+impl HeaderSummarize for AlbumGainsSummary {
+    type Error = Error;
+    type Summary = (OpusGains, Option<String>);
+
+    fn summarize(&self, headers: &CodecHeaders) -> Result<(OpusGains, Option<String>), Error> {
+        let gains = GainsSummary::default().summarize(headers)?;
+        let album = match headers {
+            CodecHeaders::Opus(_, comments) => comments.get_first(TAG_ALBUM).map(String::from),
+            CodecHeaders::Vorbis(_, comments) => comments.get_first(TAG_ALBUM).map(String::from),
+        };
+        Ok((gains, album))
+    }
+}
+
+/// Reads the existing gains and ALBUM tag of each file without decoding any
+/// audio. Files whose headers cannot be read are omitted since they are
+/// reported when they are processed.
+fn read_album_gains(
+    input_files: &[PathBuf], interrupt_checker: &CtrlCChecker,
+) -> Result<HashMap<PathBuf, (OpusGains, Option<String>)>, Error> {
+    let results: Vec<_> = input_files
+        .par_iter()
+        .map(|path| (path, read_summary(path, AlbumGainsSummary::default(), interrupt_checker)))
+        .collect();
+    let mut summaries = HashMap::new();
+    for (path, result) in results {
+        match result {
+            Ok(summary) => {
+                summaries.insert(path.clone(), summary);
+            }
+            Err(Error::Interrupted) => return Err(Error::Interrupted),
+            Err(_) => {}
+        }
+    }
+    Ok(summaries)
+}
+
+/// Returns the track position given by the DISCNUMBER and TRACKNUMBER tags
+#[derive(Debug, Default)]
+struct TrackPositionSummary {}
+
+impl HeaderSummarize for TrackPositionSummary {
+    type Error = Error;
+    type Summary = Option<TrackPosition>;
+
+    fn summarize(&self, headers: &CodecHeaders) -> Result<Option<TrackPosition>, Error> {
+        Ok(match headers {
+            CodecHeaders::Opus(_, comments) => TrackPosition::from_comments(comments),
+            CodecHeaders::Vorbis(_, comments) => TrackPosition::from_comments(comments),
+        })
+    }
+}
+
+/// Orders the files of an album by their disc and track numbers. Files whose
+/// headers cannot be read are treated as having no position since they are
+/// reported when they are processed.
+fn sort_files_by_track(input_files: Vec<PathBuf>, interrupt_checker: &CtrlCChecker) -> Result<Vec<PathBuf>, Error> {
+    let results: Vec<_> = input_files
+        .par_iter()
+        .map(|path| read_summary(path, TrackPositionSummary::default(), interrupt_checker))
+        .collect();
+    let mut positions = HashMap::new();
+    for (path, result) in input_files.iter().zip(results) {
+        match result {
+            Ok(position) => {
+                positions.insert(path.clone(), position);
+            }
+            Err(Error::Interrupted) => return Err(Error::Interrupted),
+            Err(_) => {}
+        }
+    }
+    Ok(sort_by_track(input_files, |path| positions.get(path).copied().flatten()))
+}
+
+/// Files which are likely to be played gaplessly, so that differing output
+/// gains would be heard as jumps in level at the boundaries between them
+#[derive(Debug, PartialEq, Eq)]
+struct GaplessGroup {
+    /// The ALBUM tag shared by the files, or `None` for all the files of an
+    /// album specified in album mode
+    album: Option<String>,
+
+    /// The files of the group, in the order they were specified
+    files: Vec<PathBuf>,
+}
+
+impl GaplessGroup {
+    fn describe(&self) -> String {
+        self.album.as_ref().map_or_else(|| String::from("the album"), |album| format!("album \"{}\"", album))
+    }
+}
+
+/// Groups the files which are likely to be played gaplessly. In album mode all
+/// files whose headers could be read form one group. Otherwise files sharing
+/// an ALBUM tag are grouped, and files with no such tag or which are alone in
+/// their album are left out.
+fn gapless_groups(
+    input_files: &[PathBuf], summaries: &HashMap<PathBuf, (OpusGains, Option<String>)>, album_mode: bool,
+) -> Vec<GaplessGroup> {
+    if album_mode {
+        let files = input_files.iter().filter(|path| summaries.contains_key(*path)).cloned().collect();
+        return vec![GaplessGroup { album: None, files }];
+    }
+    let mut by_album: BTreeMap<&str, Vec<PathBuf>> = BTreeMap::new();
+    for path in input_files {
+        if let Some((_, Some(album))) = summaries.get(path) {
+            if !album.is_empty() {
+                by_album.entry(album.as_str()).or_default().push(path.clone());
+            }
+        }
+    }
+    by_album
+        .into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .map(|(album, files)| GaplessGroup { album: Some(album.to_string()), files })
+        .collect()
+}
+
+/// Checks whether the output gains planned by `plan_gains` for the files of
+/// each group differ by more than `MAX_GAPLESS_GAIN_SPREAD_DB`, printing the
+/// spread for each group where they do. With `gapless_safe` set, the files of
+/// those groups are returned so that they can be given a consistent output
+/// gain. Otherwise `AppError::GainJumps` is returned if this happens for an
+/// album specified in album mode, unless `allow_jumps` is set. Files which
+/// merely share an ALBUM tag are not necessarily played gaplessly, so are only
+/// warned about. Files for which no gains can be planned are ignored.
+fn check_gain_jumps<F>(
+    groups: &[GaplessGroup], plan_gains: F, gapless_safe: bool, allow_jumps: bool,
+) -> Result<HashSet<PathBuf>, AppError>
+where
+    F: Fn(&Path) -> Option<OpusGains>,
+{
+    let mut consistent = HashSet::new();
+    let mut num_jumping = 0;
+    let mut num_warned = 0;
+    for group in groups {
+        let planned: Vec<_> = group.files.iter().filter_map(|path| plan_gains(path)).collect();
+        let Some(spread) = output_gain_spread(&planned).filter(|spread| spread.as_f64() > MAX_GAPLESS_GAIN_SPREAD_DB)
+        else {
+            continue;
+        };
+        eprintln!(
+            "Warning: output gains of the {} tracks of {} would differ by up to {:.2} dB, which would be heard as jumps \
+             in level between tracks played gaplessly.",
+            group.files.len(),
+            group.describe(),
+            spread.as_f64()
+        );
+        if gapless_safe {
+            consistent.extend(group.files.iter().cloned());
+        } else if group.album.is_none() {
+            num_jumping += 1;
+        } else {
+            num_warned += 1;
+        }
+    }
+    if num_jumping != 0 && !allow_jumps {
+        eprintln!("Use --gapless-safe to keep output gains consistent or --allow-track-gain-jumps to proceed anyway.");
+        return Err(AppError::GainJumps(num_jumping));
+    }
+    if num_warned != 0 && !allow_jumps {
+        eprintln!("Use --gapless-safe to keep output gains consistent.");
+    }
+    Ok(consistent)
+}
+
+/// Adjusts `config` for a file of an album whose tracks must share an output
+/// gain. In album mode the output gain targets album volume, otherwise the
+/// existing output gain is kept. R128 tags are still written as usual.
+fn gapless_safe_config(config: VolumeRewriterConfig, album_mode: bool) -> VolumeRewriterConfig {
+    if album_mode {
+        VolumeRewriterConfig { output_gain_mode: OutputGainMode::Album, ..config }
+    } else {
+        VolumeRewriterConfig { output_gain: VolumeTarget::NoChange, ..config }
+    }
+}
+
+/// Implements `--check-tags`, printing whether the gains of each file are
+/// consistent with `config`
+fn check_all_tags(
+    input_files: &[PathBuf], config: &VolumeRewriterConfig, tolerance: Decibels, strict_headers: bool,
+    interrupt_checker: &CtrlCChecker,
+) -> Result<(), AppError> {
+    println!("Checking gains against target loudness of {}.\n", config.output_gain.to_friendly_string());
+    let results: Vec<_> = input_files
+        .par_iter()
+        .map(|path| {
+            catch_panic(|| {
+                let gains = read_gains(path, strict_headers, interrupt_checker)?;
+                let check = check_tags(&gains.summary, config, tolerance)?;
+                Ok((gains, check))
+            })
+            .map_err(|e: AppError| e.in_file(path))
+        })
+        .collect();
+    let console = Standard::default();
+    let mut num_consistent = 0;
+    let mut num_inconsistent = 0;
+    let mut num_failed = 0;
+    for (path, result) in input_files.iter().zip(results) {
+        match result {
+            Err(e) if e.is_interrupt() => {
+                println!("Processing interrupted.");
+                return Err(e);
+            }
+            Err(e) => {
+                eprintln!("Failed to check: {}", e);
+                num_failed += 1;
+            }
+            Ok((gains, check)) => {
+                print_issues(&gains.issues, &console)?;
+                let implied = check
+                    .implied_lufs
+                    .map_or_else(|| String::from("unknown"), |lufs| format!("{:.2} LUFS", lufs.as_f64()));
+                if check.is_consistent() {
+                    println!("{}: OK (implied loudness {})", path.display(), implied);
+                    num_consistent += 1;
+                } else {
+                    let problems: Vec<_> = check.problems.iter().map(ToString::to_string).collect();
+                    println!("{}: {} (implied loudness {})", path.display(), problems.join("; "), implied);
+                    num_inconsistent += 1;
+                }
+            }
+        }
+    }
+    println!("\nFiles consistent with target: {}", num_consistent);
+    println!("Files with inconsistent gains: {}", num_inconsistent);
+    if num_failed != 0 {
+        println!("Files that could not be processed: {}", num_failed);
+        Err(AppError::FilesFailed(num_failed))
+    } else if num_inconsistent != 0 {
+        Err(AppError::TagsInconsistent(num_inconsistent))
+    } else {
+        Ok(())
+    }
+}
+
+/// Implements `--lint`, printing the findings for each file which are not in
+/// `allowed`
+fn lint_all(input_files: &[PathBuf], allowed: &[String], interrupt_checker: &CtrlCChecker) -> Result<(), AppError> {
+    let results: Vec<_> = input_files
+        .par_iter()
+        .map(|path| {
+            catch_panic(|| Ok(read_summary(path, LintSummary::default(), interrupt_checker)?))
+                .map_err(|e: AppError| e.in_file(path))
+        })
+        .collect();
+    let mut num_with_warnings = 0;
+    let mut num_failed = 0;
+    for (path, result) in input_files.iter().zip(results) {
+        match result {
+            Err(e) if e.is_interrupt() => {
+                println!("Processing interrupted.");
+                return Err(e);
+            }
+            Err(e) => {
+                eprintln!("Failed to lint: {}", e);
+                num_failed += 1;
+            }
+            Ok(findings) => {
+                let findings: Vec<_> = findings.into_iter().filter(|finding| !allowed.contains(&finding.id)).collect();
+                for finding in &findings {
+                    println!("{}: {}", path.display(), finding);
+                }
+                if findings.iter().any(|finding| finding.severity == LintSeverity::Warning) {
+                    num_with_warnings += 1;
+                }
+            }
+        }
+    }
+    println!("\nFiles with lint warnings: {}", num_with_warnings);
+    if num_failed != 0 {
+        println!("Files that could not be processed: {}", num_failed);
+        Err(AppError::FilesFailed(num_failed))
+    } else if num_with_warnings != 0 {
+        Err(AppError::LintWarnings(num_with_warnings))
+    } else {
+        Ok(())
+    }
+}
+
+/// Implements `--vendor-stats`, printing the number of files produced by each
+/// encoder
+fn vendor_stats_all(
+    input_files: &[PathBuf], format: ReportFormat, interrupt_checker: &CtrlCChecker,
+) -> Result<(), AppError> {
+    // Statistics are folded as files are read so that memory use depends on the
+    // number of distinct encoders rather than the number of files
+    let result = input_files
+        .par_iter()
+        .enumerate()
+        .map(|(index, path)| {
+            let result = catch_panic(|| Ok(read_summary(path, EncoderSummary::default(), interrupt_checker)?));
+            (index, path, result.map_err(|e: AppError| e.in_file(path)))
+        })
+        .try_fold(VendorStats::default, |mut stats, (index, path, result)| {
+            match result {
+                Err(e) if e.is_interrupt() => return Err(e),
+                Err(e) => {
+                    eprintln!("Failed to read headers: {}", e);
+                    stats.record_unreadable();
+                }
+                Ok(encoder) => stats.record(encoder, index, path),
+            }
+            Ok(stats)
+        })
+        .try_reduce(VendorStats::default, |lhs, rhs| Ok(lhs.merge(rhs)));
+    let stats = match result {
+        Ok(stats) => stats,
+        Err(e) => {
+            println!("Processing interrupted.");
+            return Err(e);
+        }
+    };
+    write_vendor_stats(&stats, format, &mut std::io::stdout()).map_err(Error::ConsoleIoError)?;
+    match stats.unreadable() {
+        0 => Ok(()),
+        num_failed => Err(AppError::FilesFailed(num_failed)),
+    }
+}
+
+fn parse_timeout(value: &str) -> Result<Duration, String> {
+    match parse_finite(value)? {
+        seconds if seconds > 0.0 => Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string()),
+        _ => Err(String::from("value must be positive")),
+    }
+}
+
+/// Replaces the file at `path` with `output_file` unless the file changed
+/// since `snapshot` was taken, in which case `output_file` is aborted
+fn commit_unless_modified(output_file: OutputFile, path: &Path, snapshot: &FileSnapshot) -> Result<(), Error> {
+    match snapshot.verify(path) {
+        Ok(()) => output_file.commit(),
+        Err(e) => {
+            output_file.abort()?;
+            Err(e)
+        }
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+fn main_impl() -> Result<(), AppError> {
+    let interrupt_checker = CtrlCChecker::new()?;
+    let cli = Cli::parse_from(wild::args_os());
+    if cli.version_verbose {
+        println!("{}", build_info::verbose_version(env!("CARGO_BIN_NAME")));
+        return Ok(());
+    }
+    if let Some(shell) = cli.generate_completions {
+        cli_docs::write_completions::<Cli, _>(shell, &mut std::io::stdout())?;
+        return Ok(());
+    }
+    if cli.generate_manpage {
+        cli_docs::write_manpage::<Cli, _>(&mut std::io::stdout())?;
+        return Ok(());
+    }
+    let album_mode = cli.album;
+    let r128_reference = Decibels::from(cli.r128_reference);
+    if r128_reference != R128_LUFS {
+        eprintln!(
+            "Warning: using a non-standard R128 reference of {:.2} LUFS. Written R128 tags will not conform to RFC \
+             7845.",
+            r128_reference.as_f64()
+        );
+    }
+    let minimize_mtime_change = cli.minimize_mtime_change;
+    // Read up front so that an unreadable reference is reported before any file
+    // is rewritten
+    let reference_mtime = cli.mtime_from.as_deref().map(read_mtime).transpose()?;
+    let exec_after = cli
+        .exec_after
+        .as_deref()
+        .map(|command| ExecAfter::new(command, cli.exec_shell))
+        .transpose()
+        .unwrap_or_else(|message| Cli::command().error(ErrorKind::InvalidValue, message).exit());
+    let make_writable = cli.make_writable;
+    let file_mode = cli.file_mode;
+    let temp_dir = cli.temp_dir.clone();
+    let temp_naming = cli.temp_naming;
+    let strict_headers = cli.strict_headers;
+    let fail_fast = cli.fail_fast;
+    let timeout = cli.timeout_per_file;
+    let stable_period = cli.stable_seconds.map(Duration::from_secs);
+    let max_duration_mismatch = cli.max_duration_mismatch;
+    let num_threads = if cli.num_threads == 0 {
+        eprintln!("The number of thread specified must be greater than 0.");
+        Err(Error::InvalidThreadCount)
+    } else {
+        let num_cores = num_cpus::get();
+        let rounded = std::cmp::min(cli.num_threads, num_cores);
+        if rounded != cli.num_threads {
+            eprintln!("Rounding down number of threads from {} to {}.", cli.num_threads, num_cores);
+        }
+        Ok(rounded)
+    }?;
+    ThreadPoolBuilder::new().num_threads(num_threads).build_global().expect("Failed to initialize thread pool");
+
+    let output_gain_mode = match cli.output_gain_mode {
+        OutputGainSetting::Auto => {
+            if album_mode {
+                OutputGainMode::Album
+            } else {
+                OutputGainMode::Track
+            }
+        }
+        OutputGainSetting::Track => OutputGainMode::Track,
+    };
+    let dry_run_output = cli.dry_run_output.clone();
+    let overwrite = cli.overwrite;
+    let hash = cli.hash.map(DigestAlgorithm::from);
+    // Writing the output elsewhere leaves the input untouched, as in a dry run
+    let dry_run = cli.dry_run || dry_run_output.is_some();
+    let clear = cli.clear;
+    // Clearing tags always removes the reference loudness tag too
+    let write_reference_tag = clear || cli.write_reference_tag;
+    // Album gains written by an earlier run in album mode are kept unless tags are
+    // being cleared
+    let preserve_album_gain = !clear && !cli.drop_album_gain;
+    let duplicate_header_action =
+        if cli.drop_duplicate_headers { DuplicateHeaderAction::Drop } else { DuplicateHeaderAction::Preserve };
+    let foreign_tag_policy = match (cli.fix_foreign_tags, cli.drop_foreign_tags) {
+        (true, _) => ForeignTagPolicy::Fix,
+        (false, true) => ForeignTagPolicy::Drop,
+        (false, false) => ForeignTagPolicy::Keep,
+    };
+    let changed_only = cli.changed_only;
+    let format = cli.format;
+    // Whether the normal per-file output is replaced by a summary printed at the
+    // end
+    let quiet = changed_only || format != ReportFormat::Table;
+    let (album_mode, preset) = if clear {
+        // We do not compute album loudness or change output gain when clearing tags
+        (false, Preset::NoChange)
+    } else {
+        (album_mode, cli.preset)
+    };
+    // The maximum headroom target depends on the volumes of all tracks so these
+    // must be computed up front
+    let from_tags = cli.from_tags;
+    let explain = cli.explain;
+    let limit_to_peak = cli.limit_output_gain_to_peak;
+    let classifier = cli.classify.clone();
+    if from_tags && matches!(preset, Preset::MaxHeadroom) {
+        Cli::command()
+            .error(ErrorKind::ArgumentConflict, "the max-headroom preset cannot be computed from existing tags")
+            .exit();
+    }
+    let analyze_all_first = !from_tags && (album_mode || matches!(preset, Preset::MaxHeadroom));
+    let quick = cli.quick;
+    if quick.is_some() && !cli.analyze_only && !cli.quick_write {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--quick only approximates loudness so gains are not written unless --quick-write is also specified",
+            )
+            .exit();
+    }
+    if let Some(length) = quick {
+        eprintln!(
+            "Warning: only {:.1} seconds from the middle of each file will be analyzed so loudness is approximate.",
+            length.as_secs_f64()
+        );
+    }
+
+    // The same file may be named more than once, for example by overlapping globs
+    // on a case-insensitive filesystem
+    let input_files = if cli.no_expand_dirs {
+        cli.input_files
+    } else {
+        let expanded = expand_inputs(cli.input_files)?;
+        for dir in expanded.empty_directories {
+            eprintln!("Warning: {} contains no Ogg files.", dir.display());
+        }
+        expanded.files
+    };
+    let (input_files, duplicates) = CaseSensitivityCache::default().dedup(input_files);
+    for duplicate in duplicates {
+        eprintln!("Ignoring {} since it refers to a file already specified.", duplicate.display());
+    }
+    if dry_run_output.is_some() && input_files.len() != 1 {
+        Cli::command()
+            .error(ErrorKind::TooManyValues, "--dry-run-output can only be used with a single input file")
+            .exit();
+    }
+    if hash.is_some_and(|algorithm| !algorithm.is_available()) {
+        Cli::command().error(ErrorKind::InvalidValue, "opusgain was built without support for --hash").exit();
+    }
+    let read_only = cli.analyze_only || cli.check_tags || cli.lint || cli.vendor_stats;
+    if let Some(url) = input_files.iter().find(|path| is_url(path)).filter(|_| !read_only) {
+        let message = format!(
+            "{} is a URL, which can only be read by --analyze-only, --check-tags, --lint or --vendor-stats",
+            url.display()
+        );
+        Cli::command().error(ErrorKind::InvalidValue, message).exit();
+    }
+    if let Some(member) = input_files.iter().find(|path| is_archive_path(path)).filter(|_| !read_only) {
+        let message = format!(
+            "{} is inside an archive, so can only be read by --analyze-only, --check-tags, --lint or --vendor-stats",
+            member.display()
+        );
+        Cli::command().error(ErrorKind::InvalidValue, message).exit();
+    }
+    let input_files =
+        if cli.sort_by_track { sort_files_by_track(input_files, &interrupt_checker)? } else { input_files };
+
+    if cli.check_tags {
+        let Some(target) = volume_target_for_preset(preset) else {
+            Cli::command()
+                .error(ErrorKind::ArgumentConflict, "the max-headroom preset cannot be checked without decoding")
+                .exit();
+        };
+        let config = VolumeRewriterConfig::builder()
+            .target(target)
+            .output_gain_mode(output_gain_mode)
+            .r128_reference(r128_reference)
+            .preserve_album_gain(preserve_album_gain)
+            .volumes_from_tags()
+            .build()
+            .map_err(Error::from)?;
+        let tolerance = Decibels::from(cli.tolerance.abs());
+        return check_all_tags(&input_files, &config, tolerance, strict_headers, &interrupt_checker);
+    }
+
+    if cli.lint {
+        return lint_all(&input_files, &cli.lint_allow, &interrupt_checker);
+    }
+
+    if cli.vendor_stats {
+        return vendor_stats_all(&input_files, cli.format, &interrupt_checker);
+    }
+
+    if cli.analyze_only {
+        // Progress messages are suppressed so that only the report is written to
+        // standard output
+        let console_output = Filtered::new(Standard::default(), false);
+        let volumes = compute_album_volume(
+            &input_files,
+            &console_output,
+            &interrupt_checker,
+            timeout,
+            quick,
+            max_duration_mismatch,
+            fail_fast,
+        )?;
+        // Files without audio are omitted from the report
+        let tracks = input_files
+            .iter()
+            .filter_map(|path| {
+                let track = volumes.get_track(path)?;
+                Some(TrackResult {
+                    path: path.clone(),
+                    lufs: track.lufs,
+                    duration: track.duration,
+                    duration_issue: track.duration_issue(max_duration_mismatch),
+                })
+            })
+            .collect();
+        let report = AnalysisResult {
+            tracks,
+            album_lufs: album_mode.then(|| volumes.get_album_mean()),
+            approximate: quick.is_some(),
+        };
+        write_analysis(&report, format, &mut std::io::stdout()).map_err(Error::ConsoleIoError)?;
+        return match volumes.num_failed() {
+            0 => Ok(()),
+            num_failed => Err(AppError::FilesFailed(num_failed)),
+        };
+    }
+
+    // Output gains only differ between the tracks of an album when each is
+    // normalized to its own volume
+    let check_gapless = matches!(output_gain_mode, OutputGainMode::Track)
+        && matches!(preset, Preset::ReplayGain | Preset::R128 | Preset::MaxHeadroom);
+    let (gain_summaries, gapless_groups) = if check_gapless {
+        let summaries = read_album_gains(&input_files, &interrupt_checker)?;
+        let groups = gapless_groups(&input_files, &summaries, album_mode);
+        (summaries, groups)
+    } else {
+        (HashMap::new(), Vec::new())
+    };
+
+    let num_processed = AtomicUsize::new(0);
+    let num_already_normalized = AtomicUsize::new(0);
+    let num_failed = AtomicUsize::new(0);
+    let num_interrupted = AtomicUsize::new(0);
+    let num_timed_out = AtomicUsize::new(0);
+    let num_skipped = AtomicUsize::new(0);
+    let num_modified = AtomicUsize::new(0);
+
+    if dry_run && !quiet {
+        match &dry_run_output {
+            Some(path) => println!(
+                "Display-only mode is enabled so the input will not be modified. Its output is written to {}.\n",
+                path.display()
+            ),
+            None => println!("Display-only mode is enabled so no files will actually be modified.\n"),
+        }
+    }
+
+    let console_output = Filtered::new(Standard::default(), !quiet);
+    let change_log = ChangeLog::new(dry_run);
+    let gain_reports = Mutex::new(BTreeMap::new());
+    let album_volume = if analyze_all_first {
+        Some(compute_album_volume(
+            &input_files,
+            &console_output,
+            &interrupt_checker,
+            timeout,
+            quick,
+            max_duration_mismatch,
+            fail_fast,
+        )?)
+    } else if !from_tags && !gapless_groups.is_empty() {
+        // The gains planned for the tracks of each album are compared before any
+        // file is rewritten
+        let grouped = gapless_groups.iter().flat_map(|group| &group.files);
+        Some(compute_album_volume(
+            grouped,
+            &console_output,
+            &interrupt_checker,
+            timeout,
+            quick,
+            max_duration_mismatch,
+            fail_fast,
+        )?)
+    } else {
+        None
+    };
+    let volume_target = match volume_target_for_preset(preset) {
+        Some(volume_target) => volume_target,
+        None => {
+            let album_volume = album_volume.as_ref().expect("Track volumes unexpectedly missing");
+            let target = match output_gain_mode {
+                OutputGainMode::Album => {
+                    max_headroom_target([(album_volume.get_album_mean(), Some(album_volume.get_album_peak()))])
+                }
+                OutputGainMode::Track => max_headroom_target(album_volume.get_track_volumes_and_peaks()),
+            };
+            if let Some(target) = target {
+                writeln!(console_output.out(), "Maximum headroom target loudness is {:.2} LUFS.\n", target.as_f64())
+                    .map_err(Error::ConsoleIoError)?;
+                VolumeTarget::LUFS(target)
+            } else {
+                writeln!(
+                    console_output.out(),
+                    "Unable to determine maximum headroom target so using original input volume.\n"
+                )
+                .map_err(Error::ConsoleIoError)?;
+                VolumeTarget::ZeroGain
+            }
+        }
+    };
+    let config_for = |track_volume: Option<Decibels>| {
+        let builder = VolumeRewriterConfig::builder()
+            .target(volume_target)
+            .output_gain_mode(output_gain_mode)
+            .r128_reference(r128_reference)
+            .preserve_album_gain(preserve_album_gain);
+        let builder = if from_tags {
+            builder.volumes_from_tags()
+        } else {
+            builder
+                .track_volume(track_volume)
+                .album_volume(album_volume.as_ref().filter(|_| album_mode).map(AlbumVolume::get_album_mean))
+        };
+        builder.build()
+    };
+    let plan_gains = |path: &Path| {
+        let (existing, _) = gain_summaries.get(path)?;
+        let config = config_for(album_volume.as_ref().and_then(|volumes| volumes.get_track_mean(path))).ok()?;
+        let config = if from_tags { with_volumes_from_tags(existing, &config) } else { config };
+        config.volume_for_output_gain_calculation()?;
+        plan(existing, &config).ok()
+    };
+    let consistent_gain_files =
+        check_gain_jumps(&gapless_groups, plan_gains, cli.gapless_safe, cli.allow_track_gain_jumps)?;
+
+    // Prevent us from rewriting more than one file at once. This is to stop us
+    // consuming too much disk space or leaving lots of temporary files around
+    // if we encounter an error.
+    let rewrite_mutex = Mutex::new(());
+
+    let input_files: Vec<_> = input_files.into_iter().enumerate().collect();
+    let result = input_files.into_par_iter().panic_fuse().try_for_each(|(idx, input_path)| -> Result<(), AppError> {
+        let console = &DelayedConsoleOutput::new(&console_output);
+        let mut report = FileResult::new(input_path.clone());
+        let body = |report: &mut FileResult| -> Result<(), AppError> {
+            // The failure was reported when the album was measured
+            if album_volume.as_ref().is_some_and(|volumes| volumes.has_failed(&input_path)) {
+                num_failed.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+            if !dry_run {
+                // Files which cannot be replaced are skipped before any work is done on them
+                output_file::check_replaceable(&input_path, make_writable, temp_naming)?;
+            }
+            // Recorded before any work is done so that a file which is still being
+            // written is never replaced
+            let snapshot = FileSnapshot::read(&input_path)?;
+            if let Some(period) = stable_period.filter(|&period| snapshot.is_recent(period, SystemTime::now())) {
+                writeln!(
+                    console.out(),
+                    "Skipping {} since it was modified less than {} second(s) ago.",
+                    input_path.display(),
+                    period.as_secs()
+                )
+                .map_err(Error::ConsoleIoError)?;
+                report.status = FileStatus::Skipped;
+                num_skipped.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+            writeln!(
+                console.out(),
                 "Processing file {} with target loudness of {}...",
                 &input_path.display(),
                 volume_target.to_friendly_string()
             )
             .map_err(Error::ConsoleIoError)?;
-            let track_volume = if clear {
-                None
+            let (track_volume, track_peak) = if clear || from_tags {
+                (None, None)
             } else {
-                Some(match &album_volume {
-                    None => {
-                        let mut analyzer = VolumeAnalyzer::default();
-                        apply_volume_analysis(&mut analyzer, &input_path, console, false, &interrupt_checker)?;
-                        analyzer.last_track_lufs().expect("Last track volume unexpectedly missing")
-                    }
-                    Some(album_volume) => album_volume
-                        .get_track_mean(&input_path)
-                        .expect("Could not find previously computed track volume"),
-                })
+                // Volumes are computed in advance for all files or only those of albums
+                let precomputed = album_volume.as_ref().filter(|volumes| volumes.get_track_mean(&input_path).is_some());
+                let summary = |track: &TrackMeasurement| {
+                    let duration_issue = track.duration_issue(max_duration_mismatch);
+                    (track.lufs, track.duration, track.sample_peak_dbfs(), duration_issue)
+                };
+                let track_volume = match precomputed {
+                    None => with_analyzer(|analyzer| {
+                        let interrupt = FileInterrupt::new(&interrupt_checker, timeout);
+                        apply_volume_analysis(
+                            analyzer,
+                            &input_path,
+                            console,
+                            false,
+                            &interrupt,
+                            quick,
+                            max_duration_mismatch,
+                        )
+                        .map_err(|e| snapshot.explain_error(&input_path, e))
+                    })?
+                    .as_ref()
+                    .map(summary),
+                    Some(album_volume) => album_volume.get_track(&input_path).map(summary),
+                };
+                let Some((lufs, duration, peak, duration_issue)) = track_volume else {
+                    writeln!(console.out(), "Skipping {} since it contains no audio.", input_path.display())
+                        .map_err(Error::ConsoleIoError)?;
+                    report.status = FileStatus::Unchanged;
+                    num_processed.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                };
+                report.duration = Some(duration);
+                report.duration_issue = duration_issue;
+                (Some(lufs), Some(peak))
+            };
+            let rewriter_config = config_for(track_volume).map_err(Error::from)?;
+            let rewriter_config = if consistent_gain_files.contains(&input_path) {
+                gapless_safe_config(rewriter_config, album_mode)
+            } else {
+                rewriter_config
             };
-            let rewriter_config = VolumeRewriterConfig {
-                output_gain: volume_target,
-                output_gain_mode,
-                track_volume,
-                album_volume: album_volume.as_ref().map(AlbumVolume::get_album_mean),
+            let rewriter_config = if limit_to_peak {
+                let peak = match rewriter_config.output_gain_mode {
+                    OutputGainMode::Album => {
+                        album_volume.as_ref().filter(|_| album_mode).map(AlbumVolume::get_album_peak)
+                    }
+                    OutputGainMode::Track => track_peak,
+                };
+                peak.map_or(rewriter_config, |peak| limit_output_gain_to_peak(&rewriter_config, peak))
+            } else {
+                rewriter_config
             };
+            report.track_lufs = rewriter_config.track_volume;
+            report.album_lufs = rewriter_config.album_volume;
 
             let input_file = File::open(&input_path).map_err(|e| Error::FileOpenError(input_path.clone(), e))?;
             let input_file_modified = if minimize_mtime_change {
@@ -338,34 +2006,79 @@ fn main_impl() -> Result<(), AppError> {
             } else {
                 None
             };
-            let mut input_file = BufReader::new(input_file);
+            let new_digest = || hash.and_then(DigestAlgorithm::new_digest);
+            let mut input_file = BufReader::new(HashingReader::new(input_file, new_digest()));
 
+            let mut modified = false;
             {
                 let rewrite_guard = rewrite_mutex.lock();
-                check_running(&interrupt_checker)?;
-                let mut output_file = OutputFile::new_target_or_discard(&input_path, dry_run)?;
-                let rewrite_result = {
-                    let mut output_file = BufWriter::new(&mut output_file);
-                    let rewrite = VolumeHeaderRewrite::new(rewriter_config);
-                    let summarize = GainsSummary::default();
+                // The deadline starts once we hold the lock so that waiting for other
+                // rewrites does not count towards it
+                let interrupt = FileInterrupt::new(&interrupt_checker, timeout);
+                interrupt.check()?;
+                let options = OutputFileOptions { temp_dir: temp_dir.as_deref(), temp_naming };
+                let output_file = match &dry_run_output {
+                    Some(path) => OutputFile::new_copy(path, options, overwrite)?,
+                    None => OutputFile::new_target_or_discard(&input_path, options, dry_run)?,
+                };
+                let mut output_file = output_file.with_make_writable(make_writable).with_file_mode(file_mode);
+                let mut stats = RewriteStats::default();
+                let (rewrite_result, output_digest) = {
+                    let mut output_file = BufWriter::new(HashingWriter::new(&mut output_file, new_digest()));
+                    let rewrite = if from_tags {
+                        VolumeHeaderRewrite::from_tags(rewriter_config)
+                    } else {
+                        VolumeHeaderRewrite::new(rewriter_config)
+                    };
+                    let rewrite = if write_reference_tag { rewrite.with_reference_tag() } else { rewrite };
+                    let rewrite = if strict_headers { rewrite.with_strict_headers() } else { rewrite };
+                    let rewrite = rewrite
+                        .with_duplicate_header_action(duplicate_header_action)
+                        .with_foreign_tag_policy(foreign_tag_policy);
+                    let rewrite = match &classifier {
+                        Some(classifier) => rewrite.with_classifier(classifier.clone()),
+                        None => rewrite,
+                    };
+                    let summarize = if strict_headers {
+                        IssueSummary::strict(ForeignTagsSummary::default())
+                    } else {
+                        IssueSummary::new(ForeignTagsSummary::default())
+                    };
                     let abort_on_unchanged = true;
-                    rewrite_stream_with_interrupt(
+                    let rewrite_result = rewrite_stream_with_stats(
                         rewrite,
                         summarize,
                         &mut input_file,
                         &mut output_file,
                         abort_on_unchanged,
-                        &interrupt_checker,
+                        &interrupt,
+                        &mut stats,
                     )
+                    .map_err(|e| interrupt.classify(e));
+                    // A successful rewrite has already flushed the output
+                    (rewrite_result, output_file.into_inner().ok().and_then(HashingWriter::finish))
                 };
-                drop(input_file); // Important for Windows
-                num_processed.fetch_add(1, Ordering::Relaxed);
+                // The rewrite may stop early so the remainder of the input is read to
+                // complete its digest
+                let mut input_file = input_file.into_inner();
+                let rewrite_result = rewrite_result
+                    .and_then(|result| input_file.read_remaining().map(|()| result).map_err(Error::ReadError));
+                let input_digest = input_file.finish(); // Closing is important for Windows
 
+                if rewrite_result.is_ok() {
+                    print_duplicate_headers(&stats, duplicate_header_action, console)?;
+                }
                 match rewrite_result {
-                    Err(e) => {
-                        writeln!(console.err(), "Failure during processing of {}.", input_path.display())
+                    Err(Error::Interrupted) => {
+                        output_file.abort()?;
+                        num_interrupted.fetch_add(1, Ordering::Relaxed);
+                        writeln!(console.err(), "Interrupted rewrite of {} {}.", input_path.display(), stats)
                             .map_err(Error::ConsoleIoError)?;
-                        return Err(e.into());
+                        return Err(Error::Interrupted.into());
+                    }
+                    Err(e) => {
+                        output_file.abort()?;
+                        return Err(snapshot.explain_error(&input_path, e).into());
                     }
                     Ok(SubmitResult::Good) => {
                         // Either we should already be normalized or get back a result which
@@ -377,9 +2090,15 @@ fn main_impl() -> Result<(), AppError> {
                             input_path.display(),
                         )
                         .map_err(Error::ConsoleIoError)?;
+                        report.status = FileStatus::Unchanged;
+                        report.digests = input_digest.map(|input| FileDigests { output: input.clone(), input });
                     }
-                    Ok(SubmitResult::HeadersChanged { from: old_gains, to: new_gains }) => {
-                        output_file.commit()?;
+                    Ok(SubmitResult::HeadersChanged { from, to }) => {
+                        let WithIssues { summary: (old_gains, _), issues } = from;
+                        let (new_gains, foreign_tags) = to.summary;
+                        print_issues(&issues, console)?;
+                        print_unrepresentable_gains(&old_gains, &rewriter_config, from_tags, console)?;
+                        commit_unless_modified(output_file, &input_path, &snapshot)?;
                         // Update timestamp if necessary
                         if !dry_run {
                             if let Some(modification_time) = input_file_modified {
@@ -387,36 +2106,590 @@ fn main_impl() -> Result<(), AppError> {
                                     .and_then(|file| set_mtime_with_minimal_increment(&file, modification_time))
                                     .map_err(|e| Error::FileMetadataWriteError(input_path.clone(), e))?;
                             }
+                            if let Some(reference_mtime) = reference_mtime {
+                                set_mtime(&input_path, reference_mtime)?;
+                            }
                         }
+                        modified = !dry_run;
+                        change_log.record(idx, &input_path, &old_gains, &new_gains);
+                        report.status = if dry_run { FileStatus::WouldChange } else { FileStatus::Changed };
+                        report.old_gains = Some(old_gains);
+                        report.new_gains = Some(new_gains);
+                        report.digests =
+                            input_digest.zip(output_digest).map(|(input, output)| FileDigests { input, output });
                         writeln!(console.out(), "Old gain values:").map_err(Error::ConsoleIoError)?;
                         print_gains(&old_gains, console)?;
                         writeln!(console.out(), "New gain values:").map_err(Error::ConsoleIoError)?;
                         print_gains(&new_gains, console)?;
+                        if let Some(classifier) = &classifier {
+                            print_classification(classifier, &old_gains, &rewriter_config, from_tags, console)?;
+                        }
+                        print_peak_warning(track_peak, &new_gains, console)?;
+                        if foreign_tag_policy == ForeignTagPolicy::Keep && old_gains.output != new_gains.output {
+                            print_foreign_tag_warning(&foreign_tags, console)?;
+                        }
+                        if explain {
+                            print_explanation(&old_gains, &rewriter_config, from_tags, track_peak, console)?;
+                        }
                     }
-                    Ok(SubmitResult::HeadersUnchanged(gains)) => {
+                    Ok(SubmitResult::HeadersUnchanged(WithIssues { summary: (gains, _), issues })) => {
+                        print_issues(&issues, console)?;
+                        print_unrepresentable_gains(&gains, &rewriter_config, from_tags, console)?;
                         writeln!(console.out(), "All gains are already correct so doing nothing. Existing gains were:")
                             .map_err(Error::ConsoleIoError)?;
                         print_gains(&gains, console)?;
+                        if let Some(classifier) = &classifier {
+                            print_classification(classifier, &gains, &rewriter_config, from_tags, console)?;
+                        }
+                        print_peak_warning(track_peak, &gains, console)?;
+                        if explain {
+                            print_explanation(&gains, &rewriter_config, from_tags, track_peak, console)?;
+                        }
+                        if dry_run_output.is_some() {
+                            // The output is still created, as a copy of the input
+                            output_file.copy_from(&input_path)?;
+                            commit_unless_modified(output_file, &input_path, &snapshot)?;
+                        }
                         num_already_normalized.fetch_add(1, Ordering::Relaxed);
+                        report.status = FileStatus::Unchanged;
+                        report.old_gains = Some(gains);
+                        report.new_gains = Some(gains);
+                        // The input is left in place, or copied by --dry-run-output
+                        report.digests = input_digest.map(|input| FileDigests { output: input.clone(), input });
                     }
                 }
                 drop(rewrite_guard);
             }
+            // Run once the rewrite lock is released so that a slow command does not
+            // hold up other rewrites
+            if let Some(exec_after) = exec_after.as_ref().filter(|_| modified) {
+                if let Err(e) = exec_after.run(&input_path) {
+                    writeln!(console.err(), "Warning: {}.", e).map_err(Error::ConsoleIoError)?;
+                }
+            }
+            num_processed.fetch_add(1, Ordering::Relaxed);
             Ok(())
         };
-        let result = body();
+        let result = catch_panic(|| body(&mut report)).map_err(|e| e.in_file(&input_path));
+        if result.as_ref().is_err_and(AppError::is_interrupt) {
+            report.status = FileStatus::Interrupted;
+        } else if result.as_ref().is_err_and(AppError::is_timeout) {
+            report.status = FileStatus::TimedOut;
+        } else if result.as_ref().is_err_and(AppError::is_modified_during_processing) {
+            report.status = FileStatus::Skipped;
+        }
+        gain_reports.lock().insert(idx, report);
         if let Err(ref e) = result {
-            writeln!(console.err(), "Failed to rewrite {}: {}", input_path.display(), e)
-                .map_err(Error::ConsoleIoError)?;
+            if e.is_modified_during_processing() {
+                writeln!(console.err(), "{}: modified during processing; skipped.", input_path.display())
+                    .map_err(Error::ConsoleIoError)?;
+            } else if !e.is_interrupt() {
+                // Interrupted rewrites have already been reported with their progress
+                writeln!(console.err(), "Failed to rewrite: {}", e).map_err(Error::ConsoleIoError)?;
+            }
         }
         writeln!(console.out()).map_err(Error::ConsoleIoError)?;
-        result
-    })?;
+        match result {
+            // The file was left in place to be processed once it is complete
+            Err(e) if e.is_modified_during_processing() => {
+                num_modified.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            // Problems with an individual file, including panics while processing it,
+            // should not prevent processing of the others
+            Err(e) if !fail_fast && e.is_confined_to_file() => {
+                num_failed.fetch_add(1, Ordering::Relaxed);
+                if e.is_timeout() {
+                    num_timed_out.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(())
+            }
+            result => result,
+        }
+    });
 
     let num_processed = num_processed.into_inner();
     let num_already_normalized = num_already_normalized.into_inner();
-    println!("Processing complete.");
+    let num_failed = num_failed.into_inner();
+    let num_interrupted = num_interrupted.into_inner();
+    let num_timed_out = num_timed_out.into_inner();
+    let num_skipped = num_skipped.into_inner();
+    let num_modified = num_modified.into_inner();
+    let reports: Vec<_> = gain_reports.into_inner().into_values().collect();
+    if let (true, Ok(()), Some(album_volume)) = (album_mode, &result, &album_volume) {
+        let volume_description = match output_gain_mode {
+            OutputGainMode::Album => "album",
+            OutputGainMode::Track => "track",
+        };
+        let report = AlbumReport {
+            generator: format!("{} {}", env!("CARGO_BIN_NAME"), build_info::VERSION),
+            generated: SystemTime::now(),
+            album_lufs: album_volume.get_album_mean(),
+            target: format!(
+                "{} (output gain based on {} volume)",
+                volume_target.to_friendly_string(),
+                volume_description
+            ),
+            files: reports.clone(),
+        };
+        let first_input = reports.first().map_or_else(|| Path::new(""), |report| report.path.as_path());
+        let report_mode = if cli.report_append {
+            ReportMode::Append
+        } else if cli.report_overwrite {
+            ReportMode::Overwrite
+        } else {
+            ReportMode::Numbered
+        };
+        let written =
+            write_album_report(&report, cli.report.as_deref(), first_input, report_mode, dry_run, cli.temp_naming)?;
+        if let Some(path) = written {
+            let verb = if dry_run { "would be" } else { "was" };
+            writeln!(console_output.out(), "Album report {} written to {}.\n", verb, path.display())
+                .map_err(Error::ConsoleIoError)?;
+        }
+    }
+    if quiet {
+        for line in change_log.into_lines().filter(|_| changed_only) {
+            println!("{}", line);
+        }
+        let mut stdout = std::io::stdout();
+        match format {
+            ReportFormat::Table => Ok(()),
+            ReportFormat::Json => write_file_results_json(&reports, &mut stdout),
+            ReportFormat::Csv => write_gain_reports_csv(&reports, &mut stdout),
+        }
+        .map_err(Error::ConsoleIoError)?;
+        return match result {
+            Ok(()) if num_failed != 0 => Err(AppError::FilesFailed(num_failed)),
+            result => result,
+        };
+    }
+    match result {
+        Ok(()) => println!("Processing complete."),
+        Err(ref e) if e.is_interrupt() => println!("Processing interrupted."),
+        Err(e) => return Err(e),
+    }
     println!("Total files processed: {}", num_processed);
     println!("Files processed but already normalized: {}", num_already_normalized);
-    Ok(())
+    if num_interrupted != 0 {
+        println!("Files interrupted during rewrite: {}", num_interrupted);
+    }
+    if num_timed_out != 0 {
+        println!("Files that timed out: {}", num_timed_out);
+    }
+    if num_skipped != 0 {
+        println!("Files skipped since they were not yet stable: {}", num_skipped);
+    }
+    if num_modified != 0 {
+        println!("Files skipped since they changed during processing: {}", num_modified);
+    }
+    if num_failed != 0 {
+        println!("Files that could not be processed: {}", num_failed);
+    }
+    if let Some(failures) = exec_after.as_ref().map(ExecAfter::failures).filter(|&failures| failures != 0) {
+        println!("Commands run after modifying files that failed: {}", failures);
+    }
+    if result.is_err() {
+        result
+    } else if num_failed == 0 {
+        Ok(())
+    } else {
+        Err(AppError::FilesFailed(num_failed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+
+    use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+    use zoog::header_rewriter::{rewrite_stream, CodecHeaders, HeaderRewrite};
+    use zoog::interrupt::{FlagInterrupt, Never};
+
+    use super::*;
+
+    #[test]
+    fn timeouts_must_be_positive() {
+        assert_eq!(parse_timeout("2.5"), Ok(Duration::from_millis(2500)));
+        assert_eq!(parse_timeout("0,5"), Ok(Duration::from_millis(500)));
+        assert!(parse_timeout("0").is_err());
+        assert!(parse_timeout("-1").is_err());
+        assert!(parse_timeout("NaN").is_err());
+    }
+
+    #[test]
+    fn change_log_lines_follow_input_order() {
+        let gains = |output: f64| OpusGains { output: Decibels::from(output), track_r128: None, album_r128: None };
+        let fixtures = [(2, "c.opus", -1.5, 2.0), (0, "a.opus", 0.0, 8.5), (1, "b.opus", 3.0, 0.0)];
+        for dry_run in [false, true] {
+            let change_log = ChangeLog::new(dry_run);
+            for (idx, path, from, to) in fixtures {
+                change_log.record(idx, Path::new(path), &gains(from), &gains(to));
+            }
+            let prefix = if dry_run { "would-change\t" } else { "" };
+            let expected: Vec<_> = ["a.opus\t0 dB\t8.5 dB", "b.opus\t3 dB\t0 dB", "c.opus\t-1.5 dB\t2 dB"]
+                .iter()
+                .map(|line| format!("{}{}", prefix, line))
+                .collect();
+            assert_eq!(change_log.into_lines().collect::<Vec<_>>(), expected);
+        }
+    }
+
+    /// A rewrite which panics, standing in for a bug in header processing
+    struct PanickingRewrite;
+
+    impl HeaderRewrite for PanickingRewrite {
+        type Error = Error;
+
+        fn rewrite(&self, _headers: &mut CodecHeaders) -> Result<(), Error> { panic!("injected panic") }
+    }
+
+    /// An Ogg Opus file containing only the identification and comment headers
+    fn headers_only_opus_file() -> Vec<u8> {
+        let id_header = b"OpusHead\x01\x02\x38\x01\x80\xbb\x00\x00\x00\x00\x00".to_vec();
+        let comment_header = b"OpusTags\x04\x00\x00\x00test\x00\x00\x00\x00".to_vec();
+        let mut data = Vec::new();
+        let mut writer = PacketWriter::new(&mut data);
+        writer.write_packet(id_header, 1, PacketWriteEndInfo::EndPage, 0).unwrap();
+        writer.write_packet(comment_header, 1, PacketWriteEndInfo::EndStream, 0).unwrap();
+        drop(writer);
+        data
+    }
+
+    #[test]
+    fn panic_during_rewrite_is_contained() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input.opus");
+        std::fs::write(&path, headers_only_opus_file()).unwrap();
+        let result = catch_panic(|| -> Result<(), AppError> {
+            let input = BufReader::new(File::open(&path).map_err(|e| Error::FileOpenError(path.clone(), e))?);
+            let mut output = OutputFile::new_target(&path, OutputFileOptions::default())?;
+            rewrite_stream(PanickingRewrite, GainsSummary::default(), input, &mut output, true)?;
+            output.commit()?;
+            Ok(())
+        })
+        .map_err(|e| e.in_file(&path));
+
+        let error = result.unwrap_err();
+        assert!(error.is_internal());
+        assert_eq!(error.severity(), Severity::Internal);
+        let message = error.to_string();
+        assert!(message.contains("injected panic") && message.contains("input.opus"), "{}", message);
+        // The temporary file was removed and the input left untouched
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+        assert_eq!(std::fs::read(&path).unwrap(), headers_only_opus_file());
+    }
+
+    /// A rewrite which leaves the headers unchanged
+    struct IdentityRewrite;
+
+    impl HeaderRewrite for IdentityRewrite {
+        type Error = Error;
+
+        fn rewrite(&self, _headers: &mut CodecHeaders) -> Result<(), Error> { Ok(()) }
+    }
+
+    /// Rewrites the file at `path` as the main loop does, calling `between`
+    /// once the rewritten file is ready to replace it
+    fn rewrite_then<F: FnOnce(&Path)>(path: &Path, between: F) -> Result<(), Error> {
+        let snapshot = FileSnapshot::read(path)?;
+        let input = BufReader::new(File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?);
+        let mut output = OutputFile::new_target(path, OutputFileOptions::default())?;
+        rewrite_stream(IdentityRewrite, GainsSummary::default(), input, &mut output, false)?;
+        between(path);
+        commit_unless_modified(output, path, &snapshot)
+    }
+
+    #[test]
+    fn files_modified_during_processing_are_not_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.opus");
+        std::fs::write(&path, headers_only_opus_file()).unwrap();
+        rewrite_then(&path, |_| {}).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), headers_only_opus_file());
+
+        // A recorder appends to the file after it was read
+        let append = |path: &Path| {
+            let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+            file.write_all(b"more audio").unwrap();
+        };
+        let error = rewrite_then(&path, append).unwrap_err();
+        assert!(matches!(&error, Error::ModifiedDuringProcessing(p) if p == &path));
+        assert!(AppError::from(error).is_modified_during_processing());
+
+        // The temporary file was removed and the recording left as written
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+        let mut expected = headers_only_opus_file();
+        expected.extend_from_slice(b"more audio");
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+    }
+
+    #[test]
+    fn read_gains_from_headers_only_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input.opus");
+        std::fs::write(&path, headers_only_opus_file()).unwrap();
+        let gains = read_gains(&path, false, &Never {}).unwrap();
+        assert!(gains.summary.track_r128.is_none());
+        assert!(gains.summary.album_r128.is_none());
+    }
+
+    #[test]
+    fn album_report_does_not_overwrite_by_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let first_input = dir.path().join("track.opus");
+        let report = AlbumReport {
+            generator: String::from("opusgain"),
+            generated: SystemTime::now(),
+            album_lufs: Decibels::from(-20.0),
+            target: String::from("-18.00 LUFS"),
+            files: vec![FileResult::new(first_input.clone())],
+        };
+        let write =
+            |mode, dry_run| write_album_report(&report, None, &first_input, mode, dry_run, TempNaming::Random).unwrap();
+        let default_path = dir.path().join(DEFAULT_REPORT_NAME);
+        assert_eq!(write(ReportMode::Numbered, false), Some(default_path.clone()));
+        assert_eq!(write(ReportMode::Numbered, false), Some(dir.path().join("album-loudness-1.txt")));
+        assert_eq!(write(ReportMode::Overwrite, false), Some(default_path.clone()));
+        assert_eq!(write(ReportMode::Numbered, true), Some(dir.path().join("album-loudness-2.txt")));
+        assert!(!dir.path().join("album-loudness-2.txt").exists());
+        assert!(std::fs::read_to_string(default_path).unwrap().contains("Album loudness: -20.00 LUFS"));
+    }
+
+    #[test]
+    fn album_reports_can_be_appended() {
+        let dir = tempfile::tempdir().unwrap();
+        let first_input = dir.path().join("track.opus");
+        let report = AlbumReport {
+            generator: String::from("opusgain"),
+            generated: SystemTime::now(),
+            album_lufs: Decibels::from(-20.0),
+            target: String::from("-18.00 LUFS"),
+            files: vec![FileResult::new(first_input.clone())],
+        };
+        let default_path = dir.path().join(DEFAULT_REPORT_NAME);
+        for dry_run in [false, false, true] {
+            let written =
+                write_album_report(&report, None, &first_input, ReportMode::Append, dry_run, TempNaming::Random);
+            assert_eq!(written.unwrap(), Some(default_path.clone()));
+        }
+        let contents = std::fs::read_to_string(&default_path).unwrap();
+        assert_eq!(contents.matches("Album loudness report").count(), 2);
+        assert!(contents.ends_with('\n'));
+    }
+
+    /// Simulates a file on a slow or hung device
+    struct SlowReader<R> {
+        inner: R,
+        delay: Duration,
+    }
+
+    impl<R: Read> Read for SlowReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            std::thread::sleep(self.delay);
+            self.inner.read(buf)
+        }
+    }
+
+    impl<R: Seek> Seek for SlowReader<R> {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> { self.inner.seek(pos) }
+    }
+
+    #[test]
+    fn timeout_does_not_affect_other_files() {
+        let data = headers_only_opus_file();
+        let slow = || SlowReader { inner: Cursor::new(data.clone()), delay: Duration::from_millis(20) };
+        let never = Never {};
+        let rewrite = |timeout| {
+            let interrupt = FileInterrupt::new(&never, timeout);
+            let summarize = GainsSummary::default();
+            rewrite_stream_with_interrupt(NoRewrite::default(), summarize, slow(), std::io::sink(), false, &interrupt)
+                .map_err(|e| interrupt.classify(e))
+        };
+        std::thread::scope(|scope| {
+            let timed = scope.spawn(|| rewrite(Some(Duration::from_millis(1))));
+            let untimed = scope.spawn(|| rewrite(None));
+            assert!(matches!(timed.join().unwrap(), Err(Error::TimedOut)));
+            assert!(matches!(untimed.join().unwrap(), Ok(SubmitResult::HeadersUnchanged(_))));
+        });
+
+        let interrupt = FileInterrupt::new(&never, Some(Duration::from_millis(1)));
+        let result = VolumeAnalyzer::default().analyze_stream_with_interrupt(slow(), &interrupt);
+        assert!(matches!(result.map_err(|e| interrupt.classify(e)), Err(Error::TimedOut)));
+    }
+
+    #[test]
+    fn interrupt_is_not_reported_as_timeout() {
+        let outer = FlagInterrupt::new();
+        let interrupt = FileInterrupt::new(&outer, Some(Duration::ZERO));
+        assert!(matches!(interrupt.check(), Err(Error::TimedOut)));
+        outer.handle().set();
+        assert!(matches!(interrupt.check(), Err(Error::Interrupted)));
+        assert!(FileInterrupt::new(&Never {}, None).check().is_ok());
+    }
+
+    fn album_summaries(
+        files: &[(&str, Option<&str>)],
+    ) -> (Vec<PathBuf>, HashMap<PathBuf, (OpusGains, Option<String>)>) {
+        let gains = OpusGains { output: Decibels::default(), track_r128: None, album_r128: None };
+        let paths = files.iter().map(|(path, _)| PathBuf::from(path)).collect();
+        let summaries =
+            files.iter().map(|(path, album)| (PathBuf::from(path), (gains, album.map(String::from)))).collect();
+        (paths, summaries)
+    }
+
+    #[test]
+    fn gapless_groups_share_album_tag() {
+        let files = [
+            ("a.opus", Some("First")),
+            ("b.opus", Some("Second")),
+            ("c.opus", Some("First")),
+            ("d.opus", None),
+            ("e.opus", Some("")),
+            ("f.opus", Some("")),
+        ];
+        let (paths, summaries) = album_summaries(&files);
+        let groups = gapless_groups(&paths, &summaries, false);
+        let expected =
+            GaplessGroup { album: Some(String::from("First")), files: vec!["a.opus".into(), "c.opus".into()] };
+        assert_eq!(groups, [expected]);
+
+        // In album mode every file which could be read is part of the album
+        let mut unreadable = paths.clone();
+        unreadable.push(PathBuf::from("g.opus"));
+        let groups = gapless_groups(&unreadable, &summaries, true);
+        assert_eq!(groups, [GaplessGroup { album: None, files: paths }]);
+    }
+
+    #[test]
+    fn gain_jumps_are_detected() {
+        let group = |album: Option<&str>, files: &[&str]| GaplessGroup {
+            album: album.map(String::from),
+            files: files.iter().map(PathBuf::from).collect(),
+        };
+        let outputs = HashMap::from([("a.opus", -4.0), ("b.opus", 1.5), ("c.opus", 2.0), ("d.opus", 2.5)]);
+        let plan_gains = |path: &Path| {
+            let output = outputs.get(path.to_str().unwrap())?;
+            Some(OpusGains { output: Decibels::from(*output), track_r128: None, album_r128: None })
+        };
+
+        // An album specified in album mode stops processing
+        let groups = [group(None, &["a.opus", "b.opus"])];
+        assert!(matches!(check_gain_jumps(&groups, plan_gains, false, false), Err(AppError::GainJumps(1))));
+        assert!(check_gain_jumps(&groups, plan_gains, false, true).unwrap().is_empty());
+        let consistent = check_gain_jumps(&groups, plan_gains, true, false).unwrap();
+        assert_eq!(consistent, HashSet::from([PathBuf::from("a.opus"), PathBuf::from("b.opus")]));
+
+        // Files which only share an ALBUM tag are warned about
+        let groups =
+            [group(Some("Jumping"), &["a.opus", "b.opus"]), group(Some("Level"), &["c.opus", "d.opus", "e.opus"])];
+        assert!(check_gain_jumps(&groups, plan_gains, false, false).unwrap().is_empty());
+        let consistent = check_gain_jumps(&groups, plan_gains, true, false).unwrap();
+        assert_eq!(consistent, HashSet::from([PathBuf::from("a.opus"), PathBuf::from("b.opus")]));
+    }
+
+    #[test]
+    fn gapless_safe_config_keeps_output_gains_consistent() {
+        let config = VolumeRewriterConfig::builder()
+            .target(VolumeTarget::LUFS(REPLAY_GAIN_LUFS))
+            .track_volume(Some(Decibels::from(-12.0)))
+            .album_volume(Some(Decibels::from(-14.0)))
+            .build()
+            .unwrap();
+        let album = gapless_safe_config(config, true);
+        assert!(matches!(album.output_gain_mode, OutputGainMode::Album));
+        assert!(matches!(album.output_gain, VolumeTarget::LUFS(_)));
+        let track = gapless_safe_config(config, false);
+        assert!(matches!(track.output_gain, VolumeTarget::NoChange));
+        assert_eq!(track.track_volume, config.track_volume);
+    }
+
+    #[test]
+    fn albums_are_measured_independently() {
+        let albums = [vec!["a1.opus", "a2.opus", "silent.opus", "a3.opus"], vec!["b1.opus"], vec![]];
+        // Each track is identified by its duration
+        let measure = |path: &Path| -> Result<Option<TrackMeasurement>, Error> {
+            let name = path.to_str().unwrap();
+            let seconds = name[1..2].parse().ok();
+            Ok(seconds.map(|seconds| TrackMeasurement {
+                windows: zoog::loudness::Windows::default(),
+                lufs: Decibels::from(-20.0),
+                duration: Duration::from_secs(if name.starts_with('a') { seconds } else { seconds + 10 }),
+                sample_peak: 0.5,
+                granule_duration: None,
+            }))
+        };
+        let completed = Mutex::new(Vec::new());
+        let volumes = measure_albums(&albums, measure, false, |idx, volume| {
+            completed.lock().push((idx, volume.tracks.len()));
+        })
+        .unwrap();
+
+        let mut completed = completed.into_inner();
+        completed.sort_unstable();
+        assert_eq!(completed, [(0, 3), (1, 1)]);
+        assert_eq!(volumes.len(), 3);
+        let duration =
+            |album: &AlbumVolume, path: &str| album.get_track(Path::new(path)).map(|track| track.duration.as_secs());
+        assert_eq!(
+            ["a1.opus", "a2.opus", "a3.opus"].map(|path| duration(&volumes[0], path)),
+            [Some(1), Some(2), Some(3)]
+        );
+        assert_eq!(duration(&volumes[0], "silent.opus"), None);
+        assert_eq!(duration(&volumes[0], "b1.opus"), None);
+        assert_eq!(duration(&volumes[1], "b1.opus"), Some(11));
+        assert!(volumes[2].tracks.is_empty());
+    }
+
+    #[test]
+    fn album_measurement_continues_past_failed_files() {
+        let albums = [vec!["a1.opus", "damaged.opus", "a2.opus"]];
+        let measure = |error: fn() -> Error| {
+            move |path: &Path| -> Result<Option<TrackMeasurement>, Error> {
+                if path == Path::new("damaged.opus") {
+                    return Err(error().in_file(path));
+                }
+                Ok(Some(TrackMeasurement {
+                    windows: zoog::loudness::Windows::default(),
+                    lufs: Decibels::from(-20.0),
+                    duration: Duration::from_secs(1),
+                    sample_peak: 0.5,
+                    granule_duration: None,
+                }))
+            }
+        };
+        let volumes = measure_albums(&albums, measure(|| Error::MalformedCommentHeader), false, |_, _| {}).unwrap();
+        assert_eq!(volumes[0].tracks.len(), 2);
+        assert!(volumes[0].has_failed(Path::new("damaged.opus")));
+        assert!(!volumes[0].has_failed(Path::new("a1.opus")));
+        assert_eq!(volumes[0].num_failed(), 1);
+
+        // Failing fast, or an error which affects more than the file, stops measurement
+        assert!(measure_albums(&albums, measure(|| Error::MalformedCommentHeader), true, |_, _| {}).is_err());
+        let result = measure_albums(&albums, measure(|| Error::Interrupted), false, |_, _| {});
+        assert!(result.is_err_and(|e| e.is_interrupt()));
+    }
+
+    #[test]
+    fn completions_contain_options() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let mut output = Vec::new();
+            cli_docs::write_completions::<Cli, _>(shell, &mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("opusgain"));
+            assert!(output.contains("preset"));
+            assert!(output.contains("minimize-mtime-change"));
+        }
+    }
+
+    #[test]
+    fn manpage_contains_options() {
+        let mut output = Vec::new();
+        cli_docs::write_manpage::<Cli, _>(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(".ie"));
+        assert!(output.contains(".TH opusgain"));
+        assert!(output.contains("preset"));
+        assert!(output.contains("R128_ALBUM_GAIN"));
+    }
 }
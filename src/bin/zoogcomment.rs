@@ -1,9 +1,21 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::uninlined_format_args)]
 
+#[path = "../cli_args.rs"]
+mod cli_args;
+
+#[path = "../cli_docs.rs"]
+mod cli_docs;
+
 #[path = "../ctrlc_handling.rs"]
 mod ctrlc_handling;
 
+#[path = "../exec_after.rs"]
+mod exec_after;
+
+#[path = "../exit_code.rs"]
+mod exit_code;
+
 #[path = "../output_file.rs"]
 mod output_file;
 
@@ -11,22 +23,34 @@ use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::convert::Into;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek as _, Write as _};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write as _};
 use std::ops::BitOrAssign;
 use std::path::{Path, PathBuf};
 
-use clap::Parser;
+use clap::{ArgAction, Parser, ValueEnum, ValueHint};
+use clap_complete::Shell;
 use ctrlc_handling::CtrlCChecker;
-use output_file::OutputFile;
+use exec_after::ExecAfter;
+use output_file::{OutputFile, OutputFileOptions, TempNaming};
 use thiserror::Error;
 use zoog::comment_rewrite::{CommentHeaderRewrite, CommentHeaderSummary, CommentRewriterAction, CommentRewriterConfig};
-use zoog::file_timestamp::set_mtime_with_minimal_increment;
-use zoog::header::{parse_comment, validate_comment_field_name, CommentList, DiscreteCommentList};
-use zoog::header_rewriter::{rewrite_stream_with_interrupt, SubmitResult};
-use zoog::{escaping, Error};
+use zoog::file_timestamp::{read_mtime, set_mtime, set_mtime_with_minimal_increment};
+use zoog::header::replaygain::{translate_comments, GainContext};
+use zoog::header::{
+    key_has_prefix, parse_comment, validate_comment_field_name, validate_portable_comment_field_name, CommentDiff,
+    CommentHeader as _, CommentHeaderSizes, CommentList, DiscreteCommentList,
+};
+use zoog::header_issue::{HeaderIssue, IssueSummary, WithIssues};
+use zoog::header_rewriter::{
+    read_selected_raw_headers, rewrite_stream_with_stats, CodecHeaders, DuplicateHeaderAction, RewriteStats,
+    StreamSelection, SubmitResult,
+};
+use zoog::io::{input_source, is_archive_path, ReadSeek};
+use zoog::report::{write_comment_diff_json, write_comment_sizes_json};
+use zoog::{build_info, escaping, list_streams, ogg_seek, Error, Severity, StreamInfo, PRIVATE_TAG_PREFIX};
 
-const OGG_OPUS_EXTENSIONS: [&str; 7] = ["ogg", "ogv", "oga", "ogx", "ogm", "spx", "opus"];
 const STANDARD_STREAM_NAME: &str = "-";
+const PICTURE_TAG: &str = "METADATA_BLOCK_PICTURE";
 
 #[derive(Debug, Error)]
 enum AppError {
@@ -41,22 +65,38 @@ enum AppError {
 
     #[error("Failed to read from standard input: `{0}`")]
     StandardInputReadError(io::Error),
+
+    #[error("Cover art in {} is {} bytes, which exceeds the limit of {} bytes", .0.display(), .1, .2)]
+    OversizedArt(PathBuf, usize, usize),
+
+    #[error("The value of tag {0} contains a NUL character so cannot be written in the null tags format")]
+    NulInTagValue(String),
+}
+
+impl AppError {
+    fn severity(&self) -> Severity {
+        match self {
+            AppError::LibraryError(e) => e.severity(),
+            AppError::SilentExit | AppError::OversizedArt(..) | AppError::NulInTagValue(_) => Severity::File,
+            AppError::CtrlCRegistration(_) | AppError::StandardInputReadError(_) => Severity::Environment,
+        }
+    }
 }
 
 fn main() {
     if let Err(e) = main_impl() {
         match e {
-            AppError::LibraryError(e) => eprintln!("Aborted due to error: {}", e),
+            AppError::LibraryError(ref e) => eprintln!("Aborted due to error: {}", e),
             AppError::SilentExit => {}
-            e => eprintln!("{}", e),
+            ref e => eprintln!("{}", e),
         }
-        std::process::exit(1);
+        std::process::exit(exit_code::for_severity(e.severity()));
     }
 }
 
 #[derive(Debug, Parser)]
 #[allow(clippy::struct_excessive_bools)]
-#[clap(author, version, about = "List or edit comments in Ogg Opus and Ogg Vorbis files.")]
+#[clap(name = "zoogcomment", author, version, about = "List or edit comments in Ogg Opus and Ogg Vorbis files.")]
 struct Cli {
     #[clap(short, long, action, conflicts_with = "replace", conflicts_with = "modify")]
     /// List comments in the Ogg Opus file
@@ -70,6 +110,11 @@ struct Cli {
     /// Replace comments in the Ogg Opus file
     replace: bool,
 
+    #[clap(long, action, requires = "replace")]
+    /// Allow --replace to remove every comment when no replacement tags are
+    /// supplied with -t, -I or --copy-from
+    allow_empty: bool,
+
     #[clap(short = 't', long = "tag", value_name = "NAME=VALUE", conflicts_with = "list")]
     /// Specify a tag
     tags: Vec<String>,
@@ -78,6 +123,10 @@ struct Cli {
     /// Specify a tag name or name-value mapping to be deleted
     delete: Vec<String>,
 
+    #[clap(long, action, requires = "modify")]
+    /// Delete all private tags written by zoog, whose names begin with ZOOG_
+    strip_private: bool,
+
     #[clap(short, long, action)]
     /// Use escapes \n, \r, \0 and \\ for tag-value input and output
     escapes: bool,
@@ -86,24 +135,303 @@ struct Cli {
     /// Display output without performing any file modification.
     dry_run: bool,
 
-    #[clap(short = 'I', long = "tags-in", conflicts_with = "list")]
+    #[clap(long, value_enum, value_name = "FORMAT", requires = "dry_run")]
+    /// Output format for the comments --dry-run would add and remove [default:
+    /// text]
+    diff_format: Option<DiffFormat>,
+
+    #[clap(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["list", "output_file", "dump_raw", "sizes"],
+        value_hint = ValueHint::FilePath
+    )]
+    /// Write the file that would be produced to PATH for inspection
+    ///
+    /// The input file is left untouched, as with --dry-run. If the comments
+    /// would not change, PATH is a copy of the input. Requires --modify or
+    /// --replace.
+    dry_run_output: Option<PathBuf>,
+
+    #[clap(long, action, requires = "dry_run_output")]
+    /// Replace any existing file at the path given by --dry-run-output
+    overwrite: bool,
+
+    #[clap(short = 'I', long = "tags-in", conflicts_with = "list", value_hint = ValueHint::FilePath)]
     /// File for reading tags from
+    ///
+    /// Tags are read in NAME=VALUE format, one per line. If - is specified,
+    /// tags are read from standard input.
     tags_in: Option<PathBuf>,
 
-    #[clap(short = 'O', long = "tags-out", conflicts_with = "modify", conflicts_with = "replace")]
+    #[clap(long, value_name = "PATH", conflicts_with = "list", value_hint = ValueHint::FilePath)]
+    /// Ogg Opus or Ogg Vorbis file to copy tags from
+    ///
+    /// The tags of its first stream are added as if read with -I. Gain and peak
+    /// tags only apply to the codec and output gain of the file they were
+    /// computed for, so are skipped with a warning when copying between Opus
+    /// and Vorbis files.
+    copy_from: Option<PathBuf>,
+
+    #[clap(long, action, requires = "copy_from")]
+    /// Convert the gain tags copied by --copy-from between the R128 tags of
+    /// Opus and the ReplayGain tags of Vorbis rather than skipping them
+    ///
+    /// ReplayGain peaks can only be written when copying from an Opus file which
+    /// has them, since R128 has no peak tags.
+    translate_gain_tags: bool,
+
+    #[clap(
+        short = 'O',
+        long = "tags-out",
+        conflicts_with = "modify",
+        conflicts_with = "replace",
+        value_hint = ValueHint::FilePath
+    )]
     /// File for writing tags to
+    ///
+    /// Tags are written in NAME=VALUE format, one per line. If - is specified,
+    /// tags are written to standard output.
     tags_out: Option<PathBuf>,
 
-    /// Input file
-    input_file: PathBuf,
+    #[clap(long, value_enum, value_name = "FORMAT", default_value_t = TagsFormat::Text)]
+    /// Format of tags read with --tags-in and of tags listed or written with
+    /// --tags-out
+    ///
+    /// In the null format each tag is NAME=VALUE followed by a NUL byte. Values
+    /// are never escaped, so may contain newlines, but values containing NUL
+    /// cannot be written.
+    tags_format: TagsFormat,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["modify", "replace", "tags_in", "tags_out", "output_file"],
+        value_hint = ValueHint::FilePath
+    )]
+    /// File for writing the raw comment header to
+    ///
+    /// The comment header packet is written exactly as it appears in the input
+    /// file, as a hex dump with offsets. If - is specified, it is written to
+    /// standard output. No comments are listed.
+    dump_raw: Option<PathBuf>,
+
+    #[clap(long, action, requires = "dump_raw")]
+    /// Write the raw comment header as binary rather than as a hex dump
+    dump_raw_binary: bool,
+
+    #[clap(long, action)]
+    /// Use the files given by --tags-in, --tags-out and --dump-raw even if they
+    /// begin like an Ogg file
+    ///
+    /// Without this, such a file is refused since it is likely to be media
+    /// mistakenly passed in place of a tags file.
+    force_tags_file: bool,
+
+    #[clap(long, action, conflicts_with_all = ["modify", "replace", "tags_out", "dump_raw"])]
+    /// Print the size in bytes of each comment and of the other parts of the
+    /// comment header rather than the comments themselves
+    ///
+    /// Comments are listed in order of decreasing size. The size of a comment
+    /// is that of its name, the separator and its value.
+    sizes: bool,
+
+    #[clap(long, value_enum, value_name = "FORMAT", requires = "sizes")]
+    /// Output format for --sizes [default: table]
+    format: Option<SizesFormat>,
+
+    #[clap(long, value_name = "HEX", value_parser = parse_stream_serial, conflicts_with = "stream_index")]
+    /// Operate on the logical stream with this serial in a file containing
+    /// several, as shown by --list-streams
+    ///
+    /// All other logical streams are copied unchanged.
+    stream_serial: Option<u32>,
+
+    #[clap(long, value_name = "N")]
+    /// Operate on the Nth logical stream in a file containing several,
+    /// counting from zero in the order shown by --list-streams
+    ///
+    /// All other logical streams are copied unchanged.
+    stream_index: Option<usize>,
+
+    #[clap(long, action, conflicts_with_all = ["modify", "replace", "tags_out", "dump_raw", "sizes", "output_file"])]
+    /// Print the index, serial and codec of each logical stream rather than
+    /// any comments
+    list_streams: bool,
+
+    #[clap(required(true), value_hint = ValueHint::FilePath)]
+    /// Input file. If built with the `archive` feature, a file inside a zip
+    /// archive can be listed by naming it as `archive.zip!inner/path.opus`.
+    input_file: Option<PathBuf>,
 
     /// Output file (cannot be specified in list mode)
-    #[clap(conflicts_with = "list")]
+    #[clap(conflicts_with = "list", value_hint = ValueHint::FilePath)]
     output_file: Option<PathBuf>,
 
     #[clap(short = 'M', long, action)]
     /// Minimize modification timestamp increment when rewriting files.
     minimize_mtime_change: bool,
+
+    #[clap(long, value_name = "FILE", conflicts_with = "minimize_mtime_change", value_hint = ValueHint::FilePath)]
+    /// Set the modification time of each rewritten file to that of FILE
+    mtime_from: Option<PathBuf>,
+
+    #[clap(long, value_name = "COMMAND", conflicts_with_all = ["list", "dry_run", "dry_run_output"])]
+    /// Run COMMAND after the file is written, with {} replaced by its path
+    ///
+    /// COMMAND is split into words at whitespace, which may be quoted with
+    /// single or double quotes, and run directly rather than by a shell, so the
+    /// path is always part of a single argument. If COMMAND contains no {}, the
+    /// path is passed as a final argument. A command which fails is reported as
+    /// a warning.
+    exec_after: Option<String>,
+
+    #[clap(long, action, requires = "exec_after")]
+    /// Run the command given by --exec-after with the system shell. On Unix,
+    /// {} is replaced by a reference to a positional parameter holding the
+    /// path, so the path is never interpreted by the shell.
+    exec_shell: bool,
+
+    #[clap(long, action, conflicts_with = "list")]
+    /// Replace the output file even if it is read-only, provided it is owned by
+    /// the current user. Its permissions are restored afterwards.
+    make_writable: bool,
+
+    #[clap(long, value_name = "MODE", value_parser = output_file::parse_file_mode, conflicts_with = "list")]
+    /// Octal Unix permissions for the output file, such as 664, applied
+    /// regardless of the umask. By default a file being replaced keeps its
+    /// permissions and a new output file takes those of the input file. Ignored
+    /// on other platforms.
+    file_mode: Option<u32>,
+
+    #[clap(long, value_name = "BOOL", action = ArgAction::Set, default_value_t = true, conflicts_with = "list")]
+    /// Whether an output file which is a symbolic link is written by replacing
+    /// the file it refers to, leaving the link in place. If false, the link
+    /// itself is replaced by a regular file.
+    dereference_output: bool,
+
+    #[clap(long, value_name = "DIR", env = "ZOOG_TMPDIR", value_hint = ValueHint::DirPath)]
+    /// Directory in which to write temporary files, rather than next to the
+    /// files they replace. If it is on a different filesystem, files are copied
+    /// next to the files they replace first, or copied over them if there is
+    /// no room, which unlike renaming is not atomic.
+    temp_dir: Option<PathBuf>,
+
+    #[clap(long, value_enum, value_name = "NAMING", env = "ZOOG_TEMP_NAMING", default_value_t = TempNaming::Random)]
+    /// How temporary files are named. `deterministic` names the temporary
+    /// for a file `<stem><ext>` `<stem>-zoogtmp<ext>`, and fails if it already
+    /// exists, for example after an earlier run was killed.
+    temp_naming: TempNaming,
+
+    #[clap(long, action, conflicts_with = "list")]
+    /// Remove a second comment header immediately following the first, as
+    /// written by some buggy tools. By default it is kept with a warning.
+    drop_duplicate_headers: bool,
+
+    #[clap(long, action)]
+    /// Treat any issue found in the headers of a file as an error for that
+    /// file.
+    strict_headers: bool,
+
+    #[clap(
+        long,
+        value_name = "HZ|unset",
+        value_parser = parse_input_rate,
+        conflicts_with_all = ["list", "sizes", "list_streams", "dump_raw"]
+    )]
+    /// Set the sample rate of the original input recorded in the Opus header,
+    /// or mark it as unknown with `unset`. The rate is informational, but an
+    /// implausible value written by a broken encoder may be displayed by
+    /// other tools. Implies --modify unless --replace is given.
+    fix_input_rate: Option<InputRate>,
+
+    #[clap(long, action, conflicts_with = "list")]
+    /// Warn about new tag names containing characters other than A-Z, a-z, 0-9
+    /// and _.
+    ///
+    /// Such names are permitted by the specification but rejected by some
+    /// other tools. Tags already present in the file are not checked.
+    portable_names: bool,
+
+    #[clap(long, action, requires = "portable_names")]
+    /// Treat non-portable tag names as errors rather than warnings.
+    strict: bool,
+
+    #[clap(long, value_name = "BYTES", conflicts_with = "list")]
+    /// Refuse to write a file containing cover art larger than this
+    ///
+    /// The size of cover art is that of its METADATA_BLOCK_PICTURE comment, as
+    /// reported by --sizes.
+    max_art_size: Option<usize>,
+
+    #[clap(long, action, exclusive = true)]
+    /// Print version together with build information for bug reports
+    version_verbose: bool,
+
+    #[clap(long, value_name = "SHELL", hide = true, exclusive = true)]
+    /// Print shell completions for the specified shell
+    generate_completions: Option<Shell>,
+
+    #[clap(long, action, hide = true, exclusive = true)]
+    /// Print a man page in roff format
+    generate_manpage: bool,
+}
+
+/// Formats in which tags can be read and written
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum)]
+enum TagsFormat {
+    /// one NAME=VALUE per line
+    #[default]
+    Text,
+
+    /// NAME=VALUE records terminated by NUL bytes, without escaping
+    Null,
+}
+
+/// Formats in which comment header sizes can be written
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum)]
+enum SizesFormat {
+    /// human-readable output
+    #[default]
+    Table,
+
+    /// JSON object
+    Json,
+}
+
+/// Formats in which the changes made by a dry run can be written
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum)]
+enum DiffFormat {
+    /// removed comments prefixed by `-` then added comments prefixed by `+`
+    #[default]
+    Text,
+
+    /// JSON object
+    Json,
+}
+
+/// How the names of new tags are validated beyond the checks required by the
+/// specification
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NameValidation {
+    Standard,
+    WarnNonPortable,
+    RejectNonPortable,
+}
+
+impl NameValidation {
+    fn check(self, key: &str) -> Result<(), Error> {
+        if self == NameValidation::Standard {
+            return Ok(());
+        }
+        match validate_portable_comment_field_name(key) {
+            Err(e @ Error::NonPortableCommentFieldName(_)) if self == NameValidation::WarnNonPortable => {
+                eprintln!("Warning: {}", e);
+                Ok(())
+            }
+            result => result,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -174,7 +502,7 @@ impl KeyValueMatch {
     }
 }
 
-fn parse_new_comment_args<S, I>(comments: I, escaped: bool) -> Result<DiscreteCommentList, Error>
+fn parse_new_comment_args<S, I>(comments: I, escaped: bool, names: NameValidation) -> Result<DiscreteCommentList, Error>
 where
     S: AsRef<str>,
     I: IntoIterator<Item = S>,
@@ -184,24 +512,55 @@ where
     for comment in comments {
         let comment = comment.as_ref();
         let (key, value) = parse_comment(comment)?;
+        names.check(key)?;
         let value = if escaped { escaping::unescape_str(value)? } else { Cow::from(value) };
         result.push(key, &value)?;
     }
     Ok(result)
 }
 
-/// Try to protect user against passing a media file as a tags file
+/// Parses a logical stream serial written in hexadecimal, with or without a
+/// leading `0x`
+fn parse_stream_serial(serial: &str) -> Result<u32, String> {
+    let digits = serial.strip_prefix("0x").or_else(|| serial.strip_prefix("0X")).unwrap_or(serial);
+    u32::from_str_radix(digits, 16).map_err(|e| format!("{} is not a hexadecimal serial: {}", serial, e))
+}
+
+/// An input sample rate given on the command line, where `None` marks the rate
+/// as unknown
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct InputRate(Option<usize>);
+
+/// Parses a sample rate in Hz, or `unset`
+fn parse_input_rate(value: &str) -> Result<InputRate, String> {
+    if value.eq_ignore_ascii_case("unset") {
+        return Ok(InputRate(None));
+    }
+    match value.parse::<u32>() {
+        Ok(0) | Err(_) => Err(format!("{} is neither a positive sample rate in Hz nor `unset`", value)),
+        Ok(rate) => Ok(InputRate(Some(rate as usize))),
+    }
+}
+
+/// Try to protect user against passing a media file as a tags file. Files are
+/// judged by their content rather than their extension, and files that do not
+/// yet exist are always accepted.
 fn validate_comment_filename(path: &Path) -> Result<(), AppError> {
-    if let Some(ext) = path.extension() {
-        let mut ext = ext.to_string_lossy().to_string();
-        ext.make_ascii_lowercase();
-        if OGG_OPUS_EXTENSIONS.iter().any(|e| ext == *e) {
-            eprintln!(
-                "Based on the file extension {:?} looks like it might be a media file. Refusing to use it for tags.",
-                path
-            );
-            return Err(AppError::SilentExit);
-        }
+    if path == Path::new(STANDARD_STREAM_NAME) {
+        return Ok(());
+    }
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(Error::FileOpenError(path.to_path_buf(), e).into()),
+    };
+    if ogg_seek::starts_with_capture_pattern(&mut file).map_err(|e| e.in_file(path))? {
+        eprintln!(
+            "{} begins like an Ogg file so might be a media file. Refusing to use it for tags (use \
+             --force-tags-file to override).",
+            path.display()
+        );
+        return Err(AppError::SilentExit);
     }
     Ok(())
 }
@@ -234,7 +593,15 @@ where
     Ok(result)
 }
 
-fn read_comments_from_read<R, M, E>(read: R, escaped: bool, error_map: M) -> Result<DiscreteCommentList, E>
+/// The byte order mark which tools on Windows often write at the start of
+/// UTF-8 text
+const BYTE_ORDER_MARK: char = '\u{feff}';
+
+/// Reads comments as `NAME=VALUE` lines. A leading byte order mark is ignored
+/// and lines may end with either LF or CRLF.
+fn read_comments_from_read<R, M, E>(
+    read: R, escaped: bool, names: NameValidation, error_map: M,
+) -> Result<DiscreteCommentList, E>
 where
     R: Read,
     M: Fn(io::Error) -> E,
@@ -242,35 +609,348 @@ where
 {
     let read = BufReader::new(read);
     let mut result = DiscreteCommentList::default();
-    for line in read.lines() {
+    for (idx, line) in read.lines().enumerate() {
         let line = line.map_err(&error_map)?;
+        // `lines` only removes a carriage return followed by a line feed, so one
+        // ending the input is removed here
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        let line = if idx == 0 { line.strip_prefix(BYTE_ORDER_MARK).unwrap_or(line) } else { line };
         if line.trim().is_empty() {
             continue;
         }
-        let (key, value) = parse_comment(&line)?;
+        let (key, value) = parse_comment(line)?;
+        names.check(key)?;
+        if value.ends_with(char::is_whitespace) {
+            eprintln!("Warning: The value of {} on line {} ends with whitespace.", key, idx + 1);
+        }
         let value = if escaped { escaping::unescape_str(value).map_err(Into::into)? } else { Cow::from(value) };
         result.push(key, &value)?;
     }
     Ok(result)
 }
 
-fn read_comments_from_file<P: AsRef<Path>>(path: P, escaped: bool) -> Result<DiscreteCommentList, Error> {
+/// Reads comments as `NAME=VALUE` records each terminated by a NUL byte.
+/// Values are not unescaped. The terminator of the final record may be
+/// omitted.
+fn read_null_terminated_comments_from_read<R, M, E>(
+    mut read: R, names: NameValidation, error_map: M,
+) -> Result<DiscreteCommentList, E>
+where
+    R: Read,
+    M: Fn(io::Error) -> E,
+    E: From<Error>,
+{
+    let mut data = Vec::new();
+    read.read_to_end(&mut data).map_err(&error_map)?;
+    let mut result = DiscreteCommentList::default();
+    for record in data.split(|&byte| byte == 0).filter(|record| !record.is_empty()) {
+        let record = String::from_utf8(record.to_vec()).map_err(Error::from)?;
+        let (key, value) = parse_comment(&record)?;
+        names.check(key)?;
+        result.push(key, value)?;
+    }
+    Ok(result)
+}
+
+/// Reads comments from `read` in the specified format. `escaped` only applies
+/// to the text format.
+fn read_comments_in_format<R, M, E>(
+    read: R, format: TagsFormat, escaped: bool, names: NameValidation, error_map: M,
+) -> Result<DiscreteCommentList, E>
+where
+    R: Read,
+    M: Fn(io::Error) -> E,
+    E: From<Error>,
+{
+    match format {
+        TagsFormat::Text => read_comments_from_read(read, escaped, names, error_map),
+        TagsFormat::Null => read_null_terminated_comments_from_read(read, names, error_map),
+    }
+}
+
+fn read_comments_from_file<P: AsRef<Path>>(
+    path: P, format: TagsFormat, escaped: bool, names: NameValidation,
+) -> Result<DiscreteCommentList, Error> {
     let path = path.as_ref();
     let file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
     let error_map = |e| Error::FileReadError(path.to_path_buf(), e);
-    read_comments_from_read(file, escaped, error_map)
+    read_comments_in_format(file, format, escaped, names, error_map).map_err(|e: Error| e.in_file(path))
 }
 
-fn read_comments_from_stdin(escaped: bool) -> Result<DiscreteCommentList, AppError> {
+fn read_comments_from_stdin(
+    format: TagsFormat, escaped: bool, names: NameValidation,
+) -> Result<DiscreteCommentList, AppError> {
     let stdin = io::stdin();
     let error_map = AppError::StandardInputReadError;
-    read_comments_from_read(stdin, escaped, error_map)
+    read_comments_in_format(stdin, format, escaped, names, error_map)
+}
+
+/// Writes `comments` in the specified format. `escaped` only applies to the
+/// text format.
+fn write_comments<L: CommentList, W: io::Write>(
+    comments: &L, output: W, format: TagsFormat, escaped: bool,
+) -> io::Result<()> {
+    match format {
+        TagsFormat::Text => comments.write_as_text(output, escaped),
+        TagsFormat::Null => comments.write_null_terminated(output),
+    }
+}
+
+/// Writes the comments removed and added by a rewrite
+fn write_diff<W: io::Write>(diff: &CommentDiff, output: &mut W, format: DiffFormat, escaped: bool) -> io::Result<()> {
+    match format {
+        DiffFormat::Text => {
+            for (prefix, comments) in [('-', &diff.removed), ('+', &diff.added)] {
+                for (key, value) in comments {
+                    let value = if escaped { escaping::escape_str(value) } else { Cow::from(value.as_str()) };
+                    writeln!(output, "{} {}={}", prefix, key, value)?;
+                }
+            }
+            writeln!(output, "{} comment(s) unchanged", diff.unchanged)
+        }
+        DiffFormat::Json => write_comment_diff_json(diff, output),
+    }
+}
+
+/// Checks that `comments` can be written in the specified format
+fn check_writable<L: CommentList>(comments: &L, format: TagsFormat) -> Result<(), AppError> {
+    match comments.iter().find(|(_, value)| format == TagsFormat::Null && value.contains('\0')) {
+        Some((key, _)) => Err(AppError::NulInTagValue(key.to_string())),
+        None => Ok(()),
+    }
 }
 
+/// Writes `data` as a hex dump with offsets, 16 bytes per line followed by
+/// their printable ASCII characters
+fn write_hex_dump<W: io::Write + ?Sized>(data: &[u8], output: &mut W) -> io::Result<()> {
+    for (line_idx, chunk) in data.chunks(16).enumerate() {
+        write!(output, "{:08x} ", line_idx * 16)?;
+        for idx in 0..16 {
+            if idx == 8 {
+                write!(output, " ")?;
+            }
+            match chunk.get(idx) {
+                Some(byte) => write!(output, " {:02x}", byte)?,
+                None => write!(output, "   ")?,
+            }
+        }
+        let printable: String = chunk
+            .iter()
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { char::from(byte) } else { '.' })
+            .collect();
+        writeln!(output, "  |{}|", printable)?;
+    }
+    writeln!(output, "{:08x}", data.len())
+}
+
+/// Writes the unparsed comment header of `input_path` to `dump_path`, or
+/// standard output if it is `-`
+fn dump_raw_comment_header(
+    input_path: &Path, selection: StreamSelection, dump_path: &Path, binary: bool, options: OutputFileOptions,
+) -> Result<(), Error> {
+    let input_file = open_input(input_path)?;
+    let headers =
+        read_selected_raw_headers(BufReader::new(input_file), selection).map_err(|e| e.in_file(input_path))?;
+    let write = |output: &mut dyn io::Write| {
+        if binary {
+            output.write_all(&headers.comment)
+        } else {
+            write_hex_dump(&headers.comment, output)
+        }
+    };
+    if dump_path == std::ffi::OsStr::new(STANDARD_STREAM_NAME) {
+        let mut stdout = io::stdout().lock();
+        write(&mut stdout).and_then(|()| stdout.flush()).map_err(Error::ConsoleIoError)
+    } else {
+        let mut dump_file = OutputFile::new_target(dump_path, options)?;
+        {
+            let mut dump_file = BufWriter::new(&mut dump_file);
+            write(&mut dump_file)
+                .and_then(|()| dump_file.flush())
+                .map_err(|e| Error::FileWriteError(dump_path.to_path_buf(), e))?;
+        }
+        dump_file.commit()
+    }
+}
+
+/// Checks that no cover art in `comments` is larger than `limit` bytes,
+/// returning the size of the first comment which is
+fn check_art_size<L: CommentList>(comments: &L, limit: usize) -> Result<(), usize> {
+    let oversized = comments
+        .iter()
+        .filter(|(key, _)| key.eq_ignore_ascii_case(PICTURE_TAG))
+        .map(|(key, value)| key.len() + 1 + value.len())
+        .find(|&size| size > limit);
+    match oversized {
+        Some(size) => Err(size),
+        None => Ok(()),
+    }
+}
+
+/// Opens the file to be read, which may be inside an archive if zoog was built
+/// with the `archive` feature
+fn open_input(path: &Path) -> Result<Box<dyn ReadSeek>, Error> {
+    if is_archive_path(path) {
+        return input_source(path)?.open();
+    }
+    let file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+    Ok(Box::new(file))
+}
+
+/// Reads and parses the headers of the selected stream of `path`
+fn read_headers(path: &Path, selection: StreamSelection) -> Result<CodecHeaders, Error> {
+    read_selected_raw_headers(BufReader::new(open_input(path)?), selection)
+        .and_then(|headers| headers.parse())
+        .map_err(|e| e.in_file(path))
+}
+
+/// The codec and output gain that the gain tags of a stream relate to
+fn gain_context(headers: &CodecHeaders) -> GainContext {
+    match headers {
+        CodecHeaders::Opus(id_header, _) => GainContext::opus(id_header.get_output_gain()),
+        CodecHeaders::Vorbis(_, _) => GainContext::vorbis(),
+    }
+}
+
+/// Reads the comments of `source` for copying to the selected stream of
+/// `input_path`. Gain and peak tags are translated if `translate` is set and the
+/// codecs differ, and a warning is printed for each that is skipped.
+fn read_comments_to_copy(
+    source: &Path, input_path: &Path, selection: StreamSelection, translate: bool,
+) -> Result<DiscreteCommentList, Error> {
+    let from = read_headers(source, StreamSelection::First)?;
+    let to = read_headers(input_path, selection)?;
+    let comments = match &from {
+        CodecHeaders::Opus(_, comment_header) => comment_header.to_discrete_comment_list(),
+        CodecHeaders::Vorbis(_, comment_header) => comment_header.to_discrete_comment_list(),
+    };
+    let translated = translate_comments(&comments, gain_context(&from), gain_context(&to), translate)
+        .map_err(|e| e.in_file(source))?;
+    for tag in &translated.skipped {
+        if translate {
+            eprintln!(
+                "Warning: not copying {} from {} since it has no equivalent in {} files.",
+                tag,
+                source.display(),
+                to.codec()
+            );
+        } else {
+            eprintln!(
+                "Warning: not copying {} from {} since gain tags differ between {} and {} files (use \
+                 --translate-gain-tags to convert them).",
+                tag,
+                source.display(),
+                from.codec(),
+                to.codec()
+            );
+        }
+    }
+    Ok(translated.comments)
+}
+
+/// Reads the sizes of the parts of the comment header of `input_path`
+fn read_comment_header_sizes(input_path: &Path, selection: StreamSelection) -> Result<CommentHeaderSizes, Error> {
+    let input_file = open_input(input_path)?;
+    read_selected_raw_headers(BufReader::new(input_file), selection)
+        .and_then(|headers| headers.comment_header_sizes())
+        .map_err(|e| e.in_file(input_path))
+}
+
+/// Writes the logical streams of a file one per line, as index, serial and
+/// codec
+fn write_streams<W: io::Write>(streams: &[StreamInfo], output: &mut W) -> io::Result<()> {
+    for (idx, stream) in streams.iter().enumerate() {
+        let codec = stream.codec.map_or_else(|| String::from("unknown"), |codec| codec.to_string());
+        writeln!(output, "{}\t{:#010x}\t{}", idx, stream.serial, codec)?;
+    }
+    Ok(())
+}
+
+/// Writes the sizes as a table with each part's percentage of the whole
+/// header packet
+fn write_sizes_table<W: io::Write>(sizes: &CommentHeaderSizes, output: &mut W) -> io::Result<()> {
+    #[allow(clippy::cast_precision_loss)]
+    let percent = |size: usize| if sizes.total == 0 { 0.0 } else { size as f64 * 100.0 / sizes.total as f64 };
+    writeln!(output, "{:>10}  {:>6}  Part", "Bytes", "%")?;
+    for (key, size) in sizes.comments_by_size() {
+        writeln!(output, "{:>10}  {:>5.1}%  {}", size, percent(size), key)?;
+    }
+    for (name, size) in [
+        ("(all comments)", sizes.comments_total()),
+        ("(vendor)", sizes.vendor),
+        ("(padding and suffix)", sizes.suffix),
+        ("(whole header)", sizes.total),
+    ] {
+        writeln!(output, "{:>10}  {:>5.1}%  {}", size, percent(size), name)?;
+    }
+    Ok(())
+}
+
+fn print_issues(issues: &[HeaderIssue]) {
+    for issue in issues {
+        eprintln!("Warning: {}", issue);
+    }
+}
+
+/// Explains why replacing the comments of `input_path` with none was refused
+fn print_empty_replacement(input_path: &Path, selection: StreamSelection) {
+    let removed = match read_comment_header_sizes(input_path, selection) {
+        Ok(sizes) => format!("all {} comment(s)", sizes.comments.len()),
+        Err(_) => String::from("every comment"),
+    };
+    eprintln!(
+        "No tags were supplied with -t, -I or --copy-from, so --replace would remove {} from {}. Use --modify to \
+         add or delete individual tags, or --allow-empty to remove every comment.",
+        removed,
+        input_path.display()
+    );
+}
+
+fn print_diff(diff: &CommentDiff, format: DiffFormat, escaped: bool) -> Result<(), Error> {
+    let mut stdout = io::stdout().lock();
+    write_diff(diff, &mut stdout, format, escaped).and_then(|()| stdout.flush()).map_err(Error::ConsoleIoError)
+}
+
+/// Reports the number of private tags removed from the original comments
+fn print_private_tags_removed(original: &DiscreteCommentList) {
+    let count = original.iter().filter(|(k, _)| key_has_prefix(k, PRIVATE_TAG_PREFIX)).count();
+    eprintln!("Removed {} private tag(s).", count);
+}
+
+/// Reports any duplicate comment headers found by a rewrite
+fn print_duplicate_headers(stats: &RewriteStats, action: DuplicateHeaderAction) {
+    if stats.duplicate_headers == 0 {
+        return;
+    }
+    match action {
+        DuplicateHeaderAction::Preserve => {
+            eprintln!(
+                "Warning: The comment header is duplicated. Use --drop-duplicate-headers to remove the duplicate."
+            );
+        }
+        DuplicateHeaderAction::Drop => eprintln!("Removing duplicate comment header."),
+    }
+}
+
+#[allow(clippy::too_many_lines)]
 fn main_impl() -> Result<(), AppError> {
     let interrupt_checker = CtrlCChecker::new()?;
-    let cli = Cli::parse_from(wild::args_os());
-    let operation_mode = match (cli.list, cli.modify, cli.replace) {
+    let arguments = cli_args::Arguments::from_env();
+    let cli = Cli::parse_from(arguments.expanded());
+    if cli.version_verbose {
+        println!("{}", build_info::verbose_version(env!("CARGO_BIN_NAME")));
+        return Ok(());
+    }
+    if let Some(shell) = cli.generate_completions {
+        cli_docs::write_completions::<Cli, _>(shell, &mut io::stdout())?;
+        return Ok(());
+    }
+    if cli.generate_manpage {
+        cli_docs::write_manpage::<Cli, _>(&mut io::stdout())?;
+        return Ok(());
+    }
+    let modify = cli.modify || (cli.fix_input_rate.is_some() && !cli.replace);
+    let operation_mode = match (cli.list, modify, cli.replace) {
         (_, false, false) => OperationMode::List,
         (false, true, false) => OperationMode::Modify,
         (false, false, true) => OperationMode::Replace,
@@ -279,25 +959,122 @@ fn main_impl() -> Result<(), AppError> {
             return Err(AppError::SilentExit);
         }
     };
+    if cli.dry_run_output.is_some() && matches!(operation_mode, OperationMode::List) {
+        eprintln!("--dry-run-output requires --modify or --replace");
+        return Err(AppError::SilentExit);
+    }
 
-    for comment_file in [&cli.tags_in, &cli.tags_out].iter().copied().flatten() {
-        validate_comment_filename(comment_file)?;
+    if !cli.force_tags_file {
+        for comment_file in [&cli.tags_in, &cli.tags_out, &cli.dump_raw].iter().copied().flatten() {
+            validate_comment_filename(comment_file)?;
+        }
     }
 
-    let dry_run = cli.dry_run;
+    // Files inside archives cannot be written back
+    if let Some(input_path) = cli.input_file.as_ref().filter(|path| is_archive_path(path)) {
+        if !matches!(operation_mode, OperationMode::List) {
+            eprintln!("{} is inside an archive, so its comments can only be listed.", input_path.display());
+            return Err(AppError::SilentExit);
+        }
+    }
+
+    // Checked before any other work since, unlike opusgain, only a single file can
+    // be operated on
+    if let Some(input_path) = cli.input_file.as_ref().filter(|path| path.is_dir()) {
+        eprintln!("{} is a directory but zoogcomment operates on a single file.", input_path.display());
+        return Err(AppError::SilentExit);
+    }
+    // A wildcard matching two files would otherwise have the second rewritten
+    // with the contents of the first
+    if let Some(output_path) = cli.output_file.as_ref().filter(|path| arguments.is_from_wildcard(path)) {
+        eprintln!(
+            "The output file {} was produced by expanding a wildcard but zoogcomment operates on a single file. \
+             Specify the input and output files explicitly.",
+            output_path.display()
+        );
+        return Err(AppError::SilentExit);
+    }
+
+    let stream_selection = match (cli.stream_serial, cli.stream_index) {
+        (Some(serial), _) => StreamSelection::Serial(serial),
+        (None, Some(index)) => StreamSelection::Index(index),
+        (None, None) => StreamSelection::First,
+    };
+
+    if cli.list_streams {
+        let input_path = cli.input_file.as_ref().expect("Input file should be a required argument");
+        let input_file = open_input(input_path)?;
+        let streams = list_streams(BufReader::new(input_file)).map_err(|e| e.in_file(input_path))?;
+        let mut stdout = io::stdout().lock();
+        write_streams(&streams, &mut stdout).and_then(|()| stdout.flush()).map_err(Error::ConsoleIoError)?;
+        return Ok(());
+    }
+
+    if let Some(ref dump_path) = cli.dump_raw {
+        let input_path = cli.input_file.as_ref().expect("Input file should be a required argument");
+        let options = OutputFileOptions { temp_dir: cli.temp_dir.as_deref(), temp_naming: cli.temp_naming };
+        dump_raw_comment_header(input_path, stream_selection, dump_path, cli.dump_raw_binary, options)?;
+        return Ok(());
+    }
+
+    if cli.sizes {
+        let input_path = cli.input_file.as_ref().expect("Input file should be a required argument");
+        let sizes = read_comment_header_sizes(input_path, stream_selection)?;
+        let mut stdout = io::stdout().lock();
+        match cli.format.unwrap_or_default() {
+            SizesFormat::Table => write_sizes_table(&sizes, &mut stdout),
+            SizesFormat::Json => write_comment_sizes_json(&sizes, &mut stdout),
+        }
+        .and_then(|()| stdout.flush())
+        .map_err(Error::ConsoleIoError)?;
+        return Ok(());
+    }
+
+    let dry_run_output = cli.dry_run_output;
+    let overwrite = cli.overwrite;
+    // Writing the output elsewhere leaves the input untouched, as in a dry run
+    let dry_run = cli.dry_run || dry_run_output.is_some();
     let escape = cli.escapes;
+    let diff_format = cli.diff_format.unwrap_or_default();
+    let tags_format = cli.tags_format;
     let minimize_mtime_change = cli.minimize_mtime_change;
+    // Read up front so that an unreadable reference is reported before the file
+    // is rewritten
+    let reference_mtime = cli.mtime_from.as_deref().map(read_mtime).transpose()?;
+    let exec_after = cli.exec_after.as_deref().map(|command| ExecAfter::new(command, cli.exec_shell)).transpose();
+    let exec_after = match exec_after {
+        Ok(exec_after) => exec_after,
+        Err(message) => {
+            eprintln!("Invalid --exec-after command: {}.", message);
+            return Err(AppError::SilentExit);
+        }
+    };
+    let strict_headers = cli.strict_headers;
+    let fix_input_rate = cli.fix_input_rate;
+    // Cover art limits only apply to files being written
+    let max_art_size = cli.max_art_size.filter(|_| !matches!(operation_mode, OperationMode::List));
+    let names = match (cli.portable_names, cli.strict) {
+        (false, _) => NameValidation::Standard,
+        (true, false) => NameValidation::WarnNonPortable,
+        (true, true) => NameValidation::RejectNonPortable,
+    };
+    let strip_private = cli.strip_private;
     let delete_tags = parse_delete_comment_args(cli.delete, escape)?;
     let append = {
-        let mut append = parse_new_comment_args(cli.tags, escape)?;
+        let mut append = parse_new_comment_args(cli.tags, escape, names)?;
         if let Some(ref file) = cli.tags_in {
             let mut tags = if file == std::ffi::OsStr::new(STANDARD_STREAM_NAME) {
-                read_comments_from_stdin(escape)?
+                read_comments_from_stdin(tags_format, escape, names)?
             } else {
-                read_comments_from_file(file, escape)?
+                read_comments_from_file(file, tags_format, escape, names)?
             };
             append.append(&mut tags);
         }
+        if let Some(ref source) = cli.copy_from {
+            let input_path = cli.input_file.as_ref().expect("Input file should be a required argument");
+            let mut copied = read_comments_to_copy(source, input_path, stream_selection, cli.translate_gain_tags)?;
+            append.append(&mut copied);
+        }
         append
     };
 
@@ -305,20 +1082,62 @@ fn main_impl() -> Result<(), AppError> {
         OperationMode::List => CommentRewriterAction::NoChange,
         OperationMode::Modify => {
             #[allow(clippy::type_complexity)]
-            let retain: Box<dyn Fn(&str, &str) -> bool> = Box::new(|k, v| !delete_tags.matches(k, v));
+            let retain: Box<dyn Fn(&str, &str) -> bool> = Box::new(|k, v| {
+                !delete_tags.matches(k, v) && !(strip_private && key_has_prefix(k, PRIVATE_TAG_PREFIX))
+            });
             CommentRewriterAction::Modify { retain, append }
         }
-        OperationMode::Replace => CommentRewriterAction::Replace(append),
+        OperationMode::Replace => {
+            if append.is_empty() && !cli.allow_empty {
+                let input_path = cli.input_file.as_ref().expect("Input file should be a required argument");
+                print_empty_replacement(input_path, stream_selection);
+                return Err(AppError::SilentExit);
+            }
+            CommentRewriterAction::Replace(append)
+        }
     };
 
     let rewriter_config = CommentRewriterConfig { action };
-    let input_path = cli.input_file;
-    let output_path = cli.output_file.unwrap_or_else(|| input_path.clone());
-    let input_file = File::open(&input_path).map_err(|e| Error::FileOpenError(input_path.clone(), e))?;
-    let input_file_modified = if minimize_mtime_change {
+    let input_path = cli.input_file.expect("Input file should be a required argument");
+    let output_path = match &dry_run_output {
+        Some(path) => path.clone(),
+        None => {
+            let output_path = cli.output_file.unwrap_or_else(|| input_path.clone());
+            output_file::resolve_output_path(&output_path, cli.dereference_output)?
+        }
+    };
+    // The output may be the input under another name, such as a link to it or a
+    // name differing only by case on a case-insensitive filesystem. The input
+    // must then never be copied onto it.
+    let in_place = input_path == output_path || output_file::same_file(&input_path, &output_path);
+    if in_place && dry_run_output.is_some() {
+        eprintln!("The path given by --dry-run-output must not be the input file");
+        return Err(AppError::SilentExit);
+    }
+    let make_writable = cli.make_writable;
+    let file_mode = cli.file_mode;
+    let temp_options = OutputFileOptions { temp_dir: cli.temp_dir.as_deref(), temp_naming: cli.temp_naming };
+    let duplicate_header_action =
+        if cli.drop_duplicate_headers { DuplicateHeaderAction::Drop } else { DuplicateHeaderAction::Preserve };
+    // A new output file is a modified copy of the input so takes its permissions
+    let new_output_file = |output_path: &Path| -> Result<OutputFile, Error> {
+        let output_file = if dry_run_output.is_some() {
+            OutputFile::new_copy(output_path, temp_options, overwrite)?
+        } else {
+            OutputFile::new_target_or_discard(output_path, temp_options, dry_run)?
+        };
+        let output_file =
+            if output_path.exists() { output_file } else { output_file.with_permissions_from(&input_path)? };
+        Ok(output_file.with_make_writable(make_writable).with_file_mode(file_mode))
+    };
+    if !dry_run && !matches!(operation_mode, OperationMode::List) {
+        output_file::check_replaceable(&output_path, make_writable, cli.temp_naming)?;
+    }
+    let input_file = open_input(&input_path)?;
+    // Only files which are written have their modification time preserved
+    let input_file_modified = if minimize_mtime_change && !matches!(operation_mode, OperationMode::List) {
         Some(
-            input_file
-                .metadata()
+            std::fs::metadata(&input_path)
                 .and_then(|metadata| metadata.modified())
                 .map_err(|e| Error::FileMetadataReadError(input_path.clone(), e))?,
         )
@@ -330,73 +1149,117 @@ fn main_impl() -> Result<(), AppError> {
 
     let mut output_file = match operation_mode {
         OperationMode::List => OutputFile::new_sink(),
-        OperationMode::Modify | OperationMode::Replace => OutputFile::new_target_or_discard(&output_path, dry_run)?,
+        OperationMode::Modify | OperationMode::Replace => new_output_file(&output_path)?,
     };
 
+    let mut stats = RewriteStats::default();
     let rewrite_result = {
         let mut output_file = BufWriter::new(&mut output_file);
-        let rewrite = CommentHeaderRewrite::new(rewriter_config);
-        let summarize = CommentHeaderSummary::default();
+        let rewrite = CommentHeaderRewrite::new(rewriter_config)
+            .with_duplicate_header_action(duplicate_header_action)
+            .with_stream_selection(stream_selection);
+        let rewrite = match fix_input_rate {
+            Some(InputRate(rate)) => rewrite.with_input_sample_rate(rate),
+            None => rewrite,
+        };
+        let summarize = if strict_headers {
+            IssueSummary::strict(CommentHeaderSummary::default())
+        } else {
+            IssueSummary::new(CommentHeaderSummary::default())
+        };
         let abort_on_unchanged = true;
-        rewrite_stream_with_interrupt(
+        rewrite_stream_with_stats(
             rewrite,
             summarize,
             &mut input_file,
             &mut output_file,
             abort_on_unchanged,
             &interrupt_checker,
+            &mut stats,
         )
     };
+    if rewrite_result.is_ok() {
+        print_duplicate_headers(&stats, duplicate_header_action);
+    }
     let mut commit = false;
     match rewrite_result {
-        Err(e) => {
-            eprintln!("Failure during processing of {}.", input_path.display());
-            return Err(e.into());
+        Err(Error::Interrupted) => {
+            output_file.abort()?;
+            eprintln!("Interrupted rewrite of {} {}.", input_path.display(), stats);
+            return Err(Error::Interrupted.in_file(input_path).into());
         }
+        Err(e) => return Err(e.in_file(input_path).into()),
         Ok(SubmitResult::Good) => {
             // We finished processing the file but never got the headers
             eprintln!("File {} appeared to be oddly truncated. Doing nothing.", input_path.display());
         }
-        Ok(SubmitResult::HeadersUnchanged(comments)) => match operation_mode {
-            OperationMode::List => {
-                if let Some(ref path) = cli.tags_out.filter(|p| p != std::ffi::OsStr::new(STANDARD_STREAM_NAME)) {
-                    let mut comment_file = OutputFile::new_target_or_discard(path, dry_run)?;
-                    {
-                        let mut comment_file = BufWriter::new(&mut comment_file);
-                        comments
-                            .write_as_text(&mut comment_file, escape)
-                            .map_err(|e| Error::FileWriteError(path.into(), e))?;
-                        comment_file.flush().map_err(|e| Error::FileWriteError(path.into(), e))?;
+        Ok(SubmitResult::HeadersUnchanged(WithIssues { summary: comments, issues })) => {
+            print_issues(&issues);
+            if strip_private {
+                print_private_tags_removed(&comments);
+            }
+            match operation_mode {
+                OperationMode::List => {
+                    check_writable(&comments, tags_format)?;
+                    if let Some(ref path) = cli.tags_out.filter(|p| p != std::ffi::OsStr::new(STANDARD_STREAM_NAME)) {
+                        let mut comment_file = OutputFile::new_target_or_discard(path, temp_options, dry_run)?;
+                        {
+                            let mut comment_file = BufWriter::new(&mut comment_file);
+                            write_comments(&comments, &mut comment_file, tags_format, escape)
+                                .map_err(|e| Error::FileWriteError(path.into(), e))?;
+                            comment_file.flush().map_err(|e| Error::FileWriteError(path.into(), e))?;
+                        }
+                        comment_file.commit()?;
+                    } else {
+                        write_comments(&comments, io::stdout(), tags_format, escape).map_err(Error::ConsoleIoError)?;
                     }
-                    comment_file.commit()?;
-                } else {
-                    comments.write_as_text(io::stdout(), escape).map_err(Error::ConsoleIoError)?;
                 }
+                OperationMode::Modify | OperationMode::Replace => {
+                    if cli.dry_run && diff_format == DiffFormat::Json {
+                        print_diff(&comments.diff(&comments), diff_format, escape)?;
+                    }
+                    if in_place {
+                        eprintln!("No changes to {}, file untouched.", input_path.display());
+                    } else {
+                        // Only art which is about to be written to the output is checked
+                        if let Some(limit) = max_art_size {
+                            if let Err(size) = check_art_size(&comments, limit) {
+                                output_file.abort()?;
+                                return Err(AppError::OversizedArt(input_path, size, limit));
+                            }
+                        }
+                        // The output must still be created as a copy of the input. This
+                        // replaces the partial output via its temporary file so that an
+                        // existing output is only replaced once the copy is complete.
+                        output_file.copy_from(&input_path)?;
+                        commit = true;
+                    }
+                }
+            }
+        }
+        Ok(SubmitResult::HeadersChanged { from, to }) => {
+            print_issues(&to.issues);
+            if strip_private {
+                print_private_tags_removed(&from.summary);
             }
-            OperationMode::Modify | OperationMode::Replace => {
-                // If these match we are definitely in-place. If they don't we're probably not,
-                // but can't be 100% certain. Hence we still do the copy via a
-                // temporary file rather than just invoking a filesystem copy.
-                if input_path != output_path {
-                    // Drop the existing output file and create a new one
-                    let mut old_output_file = OutputFile::new_target_or_discard(&output_path, dry_run)?;
-                    std::mem::swap(&mut output_file, &mut old_output_file);
-                    old_output_file.abort()?;
-                    // Copy the input file to the output file
-                    input_file.rewind().map_err(Error::ReadError)?;
-                    std::io::copy(&mut input_file, &mut output_file)
-                        .map_err(|e| Error::FileCopy(input_path, output_path.clone(), e))?;
-                    commit = true;
+            if cli.dry_run {
+                print_diff(&from.summary.diff(&to.summary), diff_format, escape)?;
+            }
+            if let Some(limit) = max_art_size {
+                if let Err(size) = check_art_size(&to.summary, limit) {
+                    output_file.abort()?;
+                    return Err(AppError::OversizedArt(input_path, size, limit));
                 }
             }
-        },
-        Ok(SubmitResult::HeadersChanged { .. }) => {
             commit = true;
         }
-    };
+    }
     drop(input_file); // Important for Windows so we can overwrite
     if commit {
         output_file.commit()?;
+        if let Some(path) = &dry_run_output {
+            eprintln!("Dry run output written to {}.", path.display());
+        }
         // Update timestamp if necessary
         if !dry_run {
             if let Some(modification_time) = input_file_modified {
@@ -404,6 +1267,14 @@ fn main_impl() -> Result<(), AppError> {
                     .and_then(|file| set_mtime_with_minimal_increment(&file, modification_time))
                     .map_err(|e| Error::FileMetadataWriteError(output_path.clone(), e))?;
             }
+            if let Some(reference_mtime) = reference_mtime {
+                set_mtime(&output_path, reference_mtime)?;
+            }
+            if let Some(exec_after) = &exec_after {
+                if let Err(e) = exec_after.run(&output_path) {
+                    eprintln!("Warning: {}.", e);
+                }
+            }
         }
     } else {
         output_file.abort()?;
@@ -429,6 +1300,66 @@ mod tests {
         assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
     }
 
+    #[test]
+    fn cli_fix_input_rate() {
+        let cli = Cli::try_parse_from(["zoogcomment", "--fix-input-rate", "44100", "input.opus"]).unwrap();
+        assert_eq!(cli.fix_input_rate, Some(InputRate(Some(44100))));
+        let cli = Cli::try_parse_from(["zoogcomment", "-m", "--fix-input-rate", "unset", "input.opus"]).unwrap();
+        assert_eq!(cli.fix_input_rate, Some(InputRate(None)));
+        for invalid in ["0", "44.1", "4294967296"] {
+            let result = Cli::try_parse_from(["zoogcomment", "--fix-input-rate", invalid, "input.opus"]);
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::ValueValidation, "{}", invalid);
+        }
+        let result = Cli::try_parse_from(["zoogcomment", "-l", "--fix-input-rate", "48000", "input.opus"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn cli_dry_run_output() {
+        let cli = Cli::try_parse_from(["zoogcomment", "-m", "--dry-run-output", "out.ogg", "input.ogg"]).unwrap();
+        assert_eq!(cli.dry_run_output.as_deref(), Some(Path::new("out.ogg")));
+        assert!(!cli.overwrite);
+        let result = Cli::try_parse_from(["zoogcomment", "-m", "--overwrite", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+        let result =
+            Cli::try_parse_from(["zoogcomment", "-m", "--dry-run-output", "out.ogg", "input.ogg", "output.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+        let result = Cli::try_parse_from(["zoogcomment", "-l", "--dry-run-output", "out.ogg", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn cli_version_verbose() {
+        let result = Cli::try_parse_from(["zoogcomment", "--version-verbose"]);
+        assert!(result.is_ok_and(|cli| cli.version_verbose));
+        let result = Cli::try_parse_from(["zoogcomment", "--version-verbose", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+        let result = Cli::try_parse_from(["zoogcomment"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn completions_contain_options() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let mut output = Vec::new();
+            cli_docs::write_completions::<Cli, _>(shell, &mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("zoogcomment"));
+            assert!(output.contains("tags-in"));
+            assert!(output.contains("escapes"));
+        }
+    }
+
+    #[test]
+    fn manpage_contains_options() {
+        let mut output = Vec::new();
+        cli_docs::write_manpage::<Cli, _>(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(".TH zoogcomment"));
+        assert!(output.contains("tags\\-out"));
+        assert!(output.contains("replace"));
+    }
+
     #[test]
     fn cli_list_mode() {
         let result = Cli::try_parse_from(["zoogcomment", "--list", "input.ogg"]);
@@ -492,5 +1423,316 @@ mod tests {
 
         let result = Cli::try_parse_from(["zoogcomment", "--replace", "-d", "TAG=VALUE", "input.ogg"]);
         assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+
+        let result = Cli::try_parse_from(["zoogcomment", "--replace", "--allow-empty", "input.ogg"]);
+        assert!(result.is_ok_and(|cli| cli.allow_empty));
+
+        let result = Cli::try_parse_from(["zoogcomment", "--modify", "--allow-empty", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn cli_dump_raw() {
+        let result = Cli::try_parse_from(["zoogcomment", "--dump-raw", "-", "--dump-raw-binary", "input.ogg"]);
+        assert!(result.is_ok_and(|cli| cli.dump_raw_binary && cli.dump_raw == Some(PathBuf::from("-"))));
+
+        let result = Cli::try_parse_from(["zoogcomment", "--dump-raw-binary", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+
+        let result = Cli::try_parse_from(["zoogcomment", "--modify", "--dump-raw", "-", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn cli_portable_names() {
+        let result = Cli::try_parse_from(["zoogcomment", "--modify", "--portable-names", "--strict", "input.ogg"]);
+        assert!(result.is_ok_and(|cli| cli.portable_names && cli.strict));
+
+        let result = Cli::try_parse_from(["zoogcomment", "--modify", "--strict", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+
+        let result = Cli::try_parse_from(["zoogcomment", "--list", "--portable-names", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn non_portable_new_names() {
+        for names in [NameValidation::Standard, NameValidation::WarnNonPortable] {
+            let tags = parse_new_comment_args(["MY TAG=1", "TAG_2=2"], false, names).unwrap();
+            assert_eq!(tags.len(), 2);
+        }
+        let result = parse_new_comment_args(["TAG_2=2", "MY TAG=1"], false, NameValidation::RejectNonPortable);
+        assert!(matches!(result, Err(Error::NonPortableCommentFieldName(key)) if key == "MY TAG"));
+        let result = parse_new_comment_args(["MY~TAG=1"], false, NameValidation::Standard);
+        assert!(matches!(result, Err(Error::InvalidOpusCommentFieldName(_))));
+
+        let input: &[u8] = b"TITLE=Song\nMY-TAG=1\n";
+        let result = read_comments_from_read(input, false, NameValidation::RejectNonPortable, Error::ReadError);
+        assert!(matches!(result, Err(Error::NonPortableCommentFieldName(key)) if key == "MY-TAG"));
+    }
+
+    #[test]
+    fn text_tags_from_windows() {
+        // A byte order mark, CRLF line endings and a final carriage return
+        let input: &[u8] = b"\xef\xbb\xbfTITLE=Song\r\nARTIST=Someone\nALBUM=Album\r\n\r\nCOMMENT=Padded \r";
+        for escaped in [false, true] {
+            let comments = read_comments_from_read(input, escaped, NameValidation::Standard, Error::ReadError).unwrap();
+            let expected = [("TITLE", "Song"), ("ARTIST", "Someone"), ("ALBUM", "Album"), ("COMMENT", "Padded ")];
+            assert_eq!(comments.iter().collect::<Vec<_>>(), expected);
+        }
+
+        // Only a leading byte order mark is ignored
+        let input = "TITLE=Song\n\u{feff}ARTIST=Someone\n".as_bytes();
+        let result = read_comments_from_read(input, false, NameValidation::Standard, Error::ReadError);
+        assert!(matches!(result, Err(Error::InvalidOpusCommentFieldName(_))));
+    }
+
+    #[test]
+    fn null_terminated_tags_round_trip() {
+        let values = ["Two\nlines\r\n", "C:\\music\\new", "\\n is not an escape", "a=b", ""];
+        let mut comments = DiscreteCommentList::default();
+        for value in values {
+            comments.push("VALUE", value).unwrap();
+        }
+        // Escaping does not apply to the null format
+        for escaped in [false, true] {
+            let mut output = Vec::new();
+            write_comments(&comments, &mut output, TagsFormat::Null, escaped).unwrap();
+            let read = read_comments_in_format(
+                output.as_slice(),
+                TagsFormat::Null,
+                escaped,
+                NameValidation::Standard,
+                Error::ReadError,
+            )
+            .unwrap();
+            assert_eq!(read.iter().collect::<Vec<_>>(), comments.iter().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn empty_values_round_trip() {
+        let mut comments = DiscreteCommentList::default();
+        comments.extend([("TITLE", ""), ("K", ""), ("ARTIST", "Someone")]).unwrap();
+        for format in [TagsFormat::Text, TagsFormat::Null] {
+            for escaped in [false, true] {
+                let mut output = Vec::new();
+                write_comments(&comments, &mut output, format, escaped).unwrap();
+                let read = read_comments_in_format(
+                    output.as_slice(),
+                    format,
+                    escaped,
+                    NameValidation::Standard,
+                    Error::ReadError,
+                )
+                .unwrap();
+                assert_eq!(read.iter().collect::<Vec<_>>(), comments.iter().collect::<Vec<_>>());
+            }
+        }
+
+        // A name without a separator is not an empty value
+        for escaped in [false, true] {
+            let result = read_comments_from_read(&b"TITLE\n"[..], escaped, NameValidation::Standard, Error::ReadError);
+            assert!(matches!(result, Err(Error::MissingCommentSeparator)));
+        }
+    }
+
+    #[test]
+    fn null_terminated_tags_input() {
+        // The final terminator is optional and empty records are ignored
+        let input: &[u8] = b"TITLE=Song\n\0\0ARTIST=Someone";
+        let read = read_null_terminated_comments_from_read(input, NameValidation::Standard, Error::ReadError).unwrap();
+        assert_eq!(read.iter().collect::<Vec<_>>(), [("TITLE", "Song\n"), ("ARTIST", "Someone")]);
+
+        let input: &[u8] = b"TITLE\0";
+        let result = read_null_terminated_comments_from_read(input, NameValidation::Standard, Error::ReadError);
+        assert!(matches!(result, Err(Error::MissingCommentSeparator)));
+        let input: &[u8] = b"TITLE=\xff\0";
+        let result = read_null_terminated_comments_from_read(input, NameValidation::Standard, Error::ReadError);
+        assert!(matches!(result, Err(Error::UTF8Error(_))));
+    }
+
+    #[test]
+    fn nul_values_cannot_be_written_null_terminated() {
+        let mut comments = DiscreteCommentList::default();
+        comments.push("TITLE", "Song").unwrap();
+        comments.push("BAD", "a\0b").unwrap();
+        assert!(check_writable(&comments, TagsFormat::Text).is_ok());
+        let result = check_writable(&comments, TagsFormat::Null);
+        assert!(matches!(result, Err(AppError::NulInTagValue(key)) if key == "BAD"));
+    }
+
+    #[test]
+    fn cli_tags_format() {
+        let cli = Cli::try_parse_from(["zoogcomment", "--list", "input.ogg"]).unwrap();
+        assert_eq!(cli.tags_format, TagsFormat::Text);
+        let cli = Cli::try_parse_from(["zoogcomment", "-r", "-I", "-", "--tags-format", "null", "input.ogg"]).unwrap();
+        assert_eq!(cli.tags_format, TagsFormat::Null);
+        assert!(Cli::try_parse_from(["zoogcomment", "--tags-format", "nul", "input.ogg"]).is_err());
+    }
+
+    #[test]
+    fn non_portable_names_can_be_deleted() {
+        // Deletion only matches tags already present in a file, so is never
+        // subject to the portability check
+        let delete = parse_delete_comment_args(["MY TAG", "OTHER-TAG=1"], false).unwrap();
+        assert!(delete.matches("my tag", "anything"));
+        assert!(delete.matches("OTHER-TAG", "1"));
+
+        let mut existing = DiscreteCommentList::default();
+        existing.push("MY TAG", "value").unwrap();
+        existing.push("TITLE", "Song").unwrap();
+        let append = parse_new_comment_args(["ARTIST=Someone"], false, NameValidation::RejectNonPortable).unwrap();
+        existing.retain(|k, v| !delete.matches(k, v));
+        existing.extend(append.iter()).unwrap();
+        assert_eq!(existing.iter().collect::<Vec<_>>(), vec![("TITLE", "Song"), ("ARTIST", "Someone")]);
+    }
+
+    #[test]
+    fn cli_strip_private() {
+        let cli = Cli::try_parse_from(["zoogcomment", "-m", "--strip-private", "-d", "TITLE", "input.ogg"]).unwrap();
+        assert!(cli.strip_private);
+        let result = Cli::try_parse_from(["zoogcomment", "--strip-private", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn cli_sizes() {
+        let result = Cli::try_parse_from(["zoogcomment", "--sizes", "--format", "json", "input.ogg"]);
+        assert!(result.is_ok_and(|cli| cli.sizes && cli.format == Some(SizesFormat::Json)));
+
+        let result = Cli::try_parse_from(["zoogcomment", "--format", "json", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+
+        let result = Cli::try_parse_from(["zoogcomment", "--sizes", "--modify", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn cli_stream_selection() {
+        let cli = Cli::try_parse_from(["zoogcomment", "--stream-serial", "0x1A2b", "input.ogg"]).unwrap();
+        assert_eq!(cli.stream_serial, Some(0x1a2b));
+        let cli = Cli::try_parse_from(["zoogcomment", "--stream-serial", "ff", "input.ogg"]).unwrap();
+        assert_eq!(cli.stream_serial, Some(0xff));
+
+        let result = Cli::try_parse_from(["zoogcomment", "--stream-serial", "0xfg", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ValueValidation);
+
+        let result = Cli::try_parse_from(["zoogcomment", "--stream-serial", "1", "--stream-index", "1", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+
+        let result = Cli::try_parse_from(["zoogcomment", "--list-streams", "--modify", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn streams_listing() {
+        let streams = [
+            StreamInfo { serial: 0x1234, codec: Some(zoog::Codec::Opus) },
+            StreamInfo { serial: 0xdead_beef, codec: None },
+        ];
+        let mut output = Vec::new();
+        write_streams(&streams, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "0\t0x00001234\tOpus\n1\t0xdeadbeef\tunknown\n");
+    }
+
+    fn picture_sizes() -> CommentHeaderSizes {
+        CommentHeaderSizes {
+            vendor: 13,
+            comments: vec![("TITLE".into(), 10), ("METADATA_BLOCK_PICTURE".into(), 900), ("ARTIST".into(), 10)],
+            suffix: 3,
+            total: 1000,
+        }
+    }
+
+    #[test]
+    fn sizes_table_sorted_by_size() {
+        let mut output = Vec::new();
+        write_sizes_table(&picture_sizes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[1], "       900   90.0%  METADATA_BLOCK_PICTURE");
+        assert_eq!(lines[2], "        10    1.0%  TITLE");
+        assert_eq!(lines[3], "        10    1.0%  ARTIST");
+        assert_eq!(lines[4], "       920   92.0%  (all comments)");
+        assert_eq!(lines.last(), Some(&"      1000  100.0%  (whole header)"));
+    }
+
+    #[test]
+    fn sizes_json() {
+        let mut output = Vec::new();
+        write_comment_sizes_json(&picture_sizes(), &mut output).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(value["schema_version"], zoog::report::SCHEMA_VERSION);
+        assert_eq!(value["total"], 1000);
+        assert_eq!(value["suffix"], 3);
+        assert_eq!(value["comments_total"], 920);
+        assert_eq!(value["comments"][0]["name"], "METADATA_BLOCK_PICTURE");
+        assert_eq!(value["comments"][0]["bytes"], 900);
+        assert_eq!(value["comments"].as_array().map(Vec::len), Some(3));
+    }
+
+    fn example_diff() -> CommentDiff {
+        let mut old = DiscreteCommentList::default();
+        old.extend([("TITLE", "Old"), ("ARTIST", "Someone")]).unwrap();
+        let mut new = DiscreteCommentList::default();
+        new.extend([("ARTIST", "Someone"), ("TITLE", "Two\nlines"), ("GENRE", "Folk")]).unwrap();
+        old.diff(&new)
+    }
+
+    #[test]
+    fn diff_text() {
+        let mut output = Vec::new();
+        write_diff(&example_diff(), &mut output, DiffFormat::Text, true).unwrap();
+        let expected = "- TITLE=Old\n+ TITLE=Two\\nlines\n+ GENRE=Folk\n1 comment(s) unchanged\n";
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn diff_json() {
+        let mut output = Vec::new();
+        write_diff(&example_diff(), &mut output, DiffFormat::Json, false).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(value["added"][0]["name"], "TITLE");
+        assert_eq!(value["added"][0]["value"], "Two\nlines");
+        assert_eq!(value["added"][1]["name"], "GENRE");
+        assert_eq!(value["removed"].as_array().map(Vec::len), Some(1));
+        assert_eq!(value["removed"][0]["value"], "Old");
+        assert_eq!(value["unchanged"], 1);
+
+        let result = Cli::try_parse_from(["zoogcomment", "-m", "--diff-format", "json", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn art_size_limit() {
+        let mut comments = DiscreteCommentList::default();
+        comments.push("TITLE", &"x".repeat(100)).unwrap();
+        // Files without art are never flagged
+        assert_eq!(check_art_size(&comments, 0), Ok(()));
+
+        comments.push("metadata_block_picture", &"A".repeat(77)).unwrap();
+        assert_eq!(check_art_size(&comments, 100), Ok(()));
+        assert_eq!(check_art_size(&comments, 99), Err(100));
+
+        let result = Cli::try_parse_from(["zoogcomment", "--list", "--max-art-size", "1000", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn hex_dump_format() {
+        let mut output = Vec::new();
+        write_hex_dump(b"OpusTags\x04\x00\x00\x00zoog\x00\x00\x00\x00 \x7f", &mut output).unwrap();
+        // Missing bytes are padded so the printable characters stay aligned
+        let expected = format!(
+            "00000000  4f 70 75 73 54 61 67 73  04 00 00 00 7a 6f 6f 67  |OpusTags....zoog|\n00000010  00 00 00 00 20 \
+             7f{}  |.... .|\n00000016\n",
+            " ".repeat(31)
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+
+        let mut output = Vec::new();
+        write_hex_dump(&[], &mut output).unwrap();
+        assert_eq!(output, b"00000000\n");
     }
 }
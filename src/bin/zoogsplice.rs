@@ -0,0 +1,217 @@
+#![warn(clippy::pedantic)]
+#![allow(clippy::uninlined_format_args)]
+
+#[path = "../cli_docs.rs"]
+mod cli_docs;
+
+#[path = "../exit_code.rs"]
+mod exit_code;
+
+#[path = "../output_file.rs"]
+mod output_file;
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand, ValueHint};
+use clap_complete::Shell;
+use output_file::{OutputFile, OutputFileOptions, TempNaming};
+use zoog::ogg_tools::{concatenate, copy_pages, PageRange};
+use zoog::{build_info, Error};
+
+fn main() {
+    if let Err(e) = main_impl() {
+        eprintln!("Aborted due to error: {}", e);
+        std::process::exit(exit_code::for_severity(e.severity()));
+    }
+}
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "zoogsplice",
+    author,
+    version,
+    about = "Copy ranges of pages from Ogg files or join Ogg Opus files.",
+    arg_required_else_help = true
+)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(short = 'n', long, action, global = true)]
+    /// Display output without writing any files.
+    dry_run: bool,
+
+    #[clap(long, value_name = "DIR", env = "ZOOG_TMPDIR", value_hint = ValueHint::DirPath, global = true)]
+    /// Directory in which to write temporary files, rather than next to the
+    /// output file. If it is on a different filesystem, the output is copied
+    /// next to the output file first, or copied over it if there is no room,
+    /// which unlike renaming is not atomic.
+    temp_dir: Option<PathBuf>,
+
+    #[clap(
+        long,
+        value_enum,
+        value_name = "NAMING",
+        env = "ZOOG_TEMP_NAMING",
+        default_value_t = TempNaming::Random,
+        global = true
+    )]
+    /// How temporary files are named. `deterministic` names the temporary
+    /// for a file `<stem><ext>` `<stem>-zoogtmp<ext>`, and fails if it already
+    /// exists, for example after an earlier run was killed.
+    temp_naming: TempNaming,
+
+    #[clap(long, action, exclusive = true)]
+    /// Print version together with build information for bug reports
+    version_verbose: bool,
+
+    #[clap(long, value_name = "SHELL", hide = true, exclusive = true)]
+    /// Print shell completions for the specified shell
+    generate_completions: Option<Shell>,
+
+    #[clap(long, action, hide = true, exclusive = true)]
+    /// Print a man page in roff format
+    generate_manpage: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Copy a range of pages from an Ogg file
+    ///
+    /// Pages are numbered from zero. The last page copied from each logical
+    /// stream is marked as ending that stream, so this can be used to remove
+    /// corrupt pages from the end of a file.
+    Pages {
+        #[clap(long, value_name = "INDEX", default_value_t = 0)]
+        /// Index of the first page to copy
+        start: usize,
+
+        #[clap(long, value_name = "INDEX")]
+        /// Index of the first page not to copy. Defaults to copying all pages
+        /// from the start index onwards.
+        end: Option<usize>,
+
+        #[clap(value_hint = ValueHint::FilePath)]
+        /// Input file
+        input_file: PathBuf,
+
+        #[clap(value_hint = ValueHint::FilePath)]
+        /// Output file
+        output_file: PathBuf,
+    },
+
+    /// Join Ogg Opus files into a single logical stream
+    ///
+    /// All input files must have been encoded with identical parameters and
+    /// contain a single logical stream. The headers of the first file are used
+    /// for the output.
+    Concat {
+        #[clap(short, long, value_hint = ValueHint::FilePath)]
+        /// Output file
+        output_file: PathBuf,
+
+        #[clap(required = true, value_hint = ValueHint::FilePath)]
+        /// Input files
+        input_files: Vec<PathBuf>,
+    },
+}
+
+fn open_input(path: &Path) -> Result<BufReader<File>, Error> {
+    let file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+    Ok(BufReader::new(file))
+}
+
+/// Writes to a temporary file which replaces `path` only if `body` succeeds.
+/// The output is discarded if `dry_run` is set.
+fn write_output<F, T>(path: &Path, options: OutputFileOptions, dry_run: bool, body: F) -> Result<T, Error>
+where
+    F: FnOnce(&mut BufWriter<&mut OutputFile>) -> Result<T, Error>,
+{
+    let mut output_file = OutputFile::new_target_or_discard(path, options, dry_run)?;
+    let result = {
+        let mut output = BufWriter::new(&mut output_file);
+        body(&mut output)
+    };
+    match result {
+        Ok(value) => {
+            output_file.commit()?;
+            Ok(value)
+        }
+        Err(e) => {
+            output_file.abort()?;
+            Err(e)
+        }
+    }
+}
+
+fn main_impl() -> Result<(), Error> {
+    let cli = Cli::parse_from(wild::args_os());
+    if cli.version_verbose {
+        println!("{}", build_info::verbose_version(env!("CARGO_BIN_NAME")));
+        return Ok(());
+    }
+    if let Some(shell) = cli.generate_completions {
+        return cli_docs::write_completions::<Cli, _>(shell, &mut io::stdout());
+    }
+    if cli.generate_manpage {
+        return cli_docs::write_manpage::<Cli, _>(&mut io::stdout());
+    }
+    let dry_run = cli.dry_run;
+    let options = OutputFileOptions { temp_dir: cli.temp_dir.as_deref(), temp_naming: cli.temp_naming };
+    match cli.command {
+        Some(Command::Pages { start, end, input_file, output_file }) => {
+            let input = open_input(&input_file)?;
+            let range = PageRange { start, end };
+            let num_copied = write_output(&output_file, options, dry_run, |output| copy_pages(input, output, range))
+                .map_err(|e| e.in_file(&input_file))?;
+            println!("Copied {} page(s) to {}", num_copied, output_file.display());
+        }
+        Some(Command::Concat { output_file, input_files }) => {
+            let inputs = input_files.iter().map(|path| open_input(path)).collect::<Result<Vec<_>, _>>()?;
+            write_output(&output_file, options, dry_run, |output| concatenate(inputs, output))?;
+            println!("Joined {} file(s) into {}", input_files.len(), output_file.display());
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::error::ErrorKind;
+
+    use super::*;
+
+    #[test]
+    fn cli_pages() {
+        let cli = Cli::try_parse_from(["zoogsplice", "pages", "--end", "10", "input.opus", "output.opus"]).unwrap();
+        match cli.command {
+            Some(Command::Pages { start, end, .. }) => assert_eq!((start, end), (0, Some(10))),
+            other => panic!("Unexpected command: {:?}", other),
+        }
+        let result = Cli::try_parse_from(["zoogsplice", "pages", "input.opus"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn cli_concat() {
+        let cli = Cli::try_parse_from(["zoogsplice", "concat", "-o", "out.opus", "a.opus", "b.opus"]).unwrap();
+        match cli.command {
+            Some(Command::Concat { input_files, .. }) => assert_eq!(input_files.len(), 2),
+            other => panic!("Unexpected command: {:?}", other),
+        }
+        let result = Cli::try_parse_from(["zoogsplice", "concat", "a.opus", "b.opus"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn completions_contain_options() {
+        let mut output = Vec::new();
+        cli_docs::write_completions::<Cli, _>(Shell::Bash, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("zoogsplice"));
+        assert!(output.contains("concat"));
+    }
+}
@@ -0,0 +1,58 @@
+use std::fmt::Write as _;
+
+/// The version of the zoog crate
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The abbreviated git commit zoog was built from, if known
+pub const GIT_COMMIT: Option<&str> = option_env!("ZOOG_GIT_COMMIT");
+
+/// The date zoog was built, in `YYYY-MM-DD` format, if known
+pub const BUILD_DATE: Option<&str> = option_env!("ZOOG_BUILD_DATE");
+
+/// The version of the `opus` crate zoog was built against, if known
+pub const OPUS_CRATE_VERSION: Option<&str> = option_env!("ZOOG_OPUS_VERSION");
+
+/// The version of the `ogg` crate zoog was built against, if known
+pub const OGG_CRATE_VERSION: Option<&str> = option_env!("ZOOG_OGG_VERSION");
+
+const FEATURES: &str = env!("ZOOG_FEATURES");
+
+/// The Cargo features zoog was built with
+pub fn features() -> impl Iterator<Item = &'static str> { FEATURES.split(',').filter(|f| !f.is_empty()) }
+
+/// Multi-line description of the build, intended for inclusion in bug reports
+#[must_use]
+#[allow(clippy::missing_panics_doc)]
+pub fn verbose_version(binary_name: &str) -> String {
+    let unknown = "unknown";
+    let features: Vec<_> = features().collect();
+    let features = if features.is_empty() { "none".to_string() } else { features.join(", ") };
+    let mut result = String::new();
+    let mut append = || -> std::fmt::Result {
+        writeln!(result, "{} {}", binary_name, VERSION)?;
+        writeln!(result, "git commit: {}", GIT_COMMIT.unwrap_or(unknown))?;
+        writeln!(result, "build date: {}", BUILD_DATE.unwrap_or(unknown))?;
+        writeln!(result, "features: {}", features)?;
+        writeln!(result, "opus crate: {}", OPUS_CRATE_VERSION.unwrap_or(unknown))?;
+        write!(result, "ogg crate: {}", OGG_CRATE_VERSION.unwrap_or(unknown))
+    };
+    append().expect("Writing to a String should not fail");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbose_version_contains_crate_version() {
+        let version = verbose_version("opusgain");
+        assert!(version.starts_with(&format!("opusgain {}\n", env!("CARGO_PKG_VERSION"))));
+        assert!(version.lines().any(|line| line.starts_with("features: ")));
+    }
+
+    #[test]
+    fn features_match_build_configuration() {
+        assert_eq!(features().any(|f| f == "serde"), cfg!(feature = "serde"));
+    }
+}
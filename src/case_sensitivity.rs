@@ -0,0 +1,279 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use parking_lot::Mutex;
+
+/// Whether file names differing only by case refer to the same file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaseSensitivity {
+    /// Names differing only by case refer to different files
+    Sensitive,
+
+    /// Names differing only by case refer to the same file, as on most macOS
+    /// and Windows filesystems
+    Insensitive,
+}
+
+/// Determines the case sensitivity of the filesystem containing a directory
+pub trait CaseProbe {
+    /// Probes the case sensitivity of the filesystem containing `dir`
+    fn probe(&self, dir: &Path) -> io::Result<CaseSensitivity>;
+}
+
+/// Probes case sensitivity by creating a temporary file and checking whether
+/// it can also be found under a name with the case of each letter swapped.
+/// The temporary file is removed once the probe is complete.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FilesystemProbe;
+
+impl CaseProbe for FilesystemProbe {
+    fn probe(&self, dir: &Path) -> io::Result<CaseSensitivity> {
+        let temp = tempfile::Builder::new().prefix("zoog-case-probe-").tempfile_in(dir)?;
+        let name = temp.path().file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let swapped: String = name
+            .chars()
+            .map(|c| if c.is_ascii_lowercase() { c.to_ascii_uppercase() } else { c.to_ascii_lowercase() })
+            .collect();
+        let found = dir.join(swapped).symlink_metadata().is_ok();
+        Ok(if found { CaseSensitivity::Insensitive } else { CaseSensitivity::Sensitive })
+    }
+}
+
+/// Identifies the filesystem a directory belongs to. On Unix this is the
+/// device, so that a single probe covers every directory on it. Elsewhere each
+/// directory is probed separately.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum CacheKey {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    Device(u64),
+    Directory(PathBuf),
+}
+
+#[cfg(unix)]
+fn cache_key(dir: &Path) -> CacheKey {
+    use std::os::unix::fs::MetadataExt as _;
+    match dir.metadata() {
+        Ok(metadata) => CacheKey::Device(metadata.dev()),
+        Err(_) => CacheKey::Directory(dir.to_path_buf()),
+    }
+}
+
+#[cfg(not(unix))]
+fn cache_key(dir: &Path) -> CacheKey { CacheKey::Directory(dir.to_path_buf()) }
+
+/// Identifies an existing file however it is reached. On Unix this is the
+/// device and inode, so that hard links to a file are identified with it.
+/// Elsewhere, or if the file cannot be examined, the key from `file_key` is
+/// used.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum FileIdentity {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    Inode(u64, u64),
+    Key(PathBuf),
+}
+
+#[cfg(unix)]
+fn inode(path: &Path) -> Option<FileIdentity> {
+    use std::os::unix::fs::MetadataExt as _;
+    path.metadata().ok().map(|metadata| FileIdentity::Inode(metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn inode(_path: &Path) -> Option<FileIdentity> { None }
+
+/// Caches the case sensitivity of filesystems so that each is only probed
+/// once. A directory which cannot be probed, for example because it is not
+/// writable, is treated as case-sensitive so that distinct files are never
+/// mistaken for one another.
+#[derive(Debug)]
+pub struct CaseSensitivityCache<P> {
+    probe: P,
+    results: Mutex<HashMap<CacheKey, CaseSensitivity>>,
+}
+
+impl<P: CaseProbe> CaseSensitivityCache<P> {
+    /// Constructs an empty cache which uses `probe` to determine case
+    /// sensitivity
+    #[must_use]
+    pub fn new(probe: P) -> CaseSensitivityCache<P> { CaseSensitivityCache { probe, results: Mutex::default() } }
+
+    /// The case sensitivity of the filesystem containing `dir`
+    #[must_use]
+    pub fn get(&self, dir: &Path) -> CaseSensitivity {
+        let key = cache_key(dir);
+        if let Some(result) = self.results.lock().get(&key) {
+            return *result;
+        }
+        let result = self.probe.probe(dir).unwrap_or(CaseSensitivity::Sensitive);
+        *self.results.lock().entry(key).or_insert(result)
+    }
+
+    /// A key identifying the file at `path`, such that paths to the same file
+    /// have equal keys. Paths are canonicalized where possible, and compared
+    /// ignoring case on case-insensitive filesystems.
+    #[must_use]
+    pub fn file_key(&self, path: &Path) -> PathBuf {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        match (self.get(dir), path.to_str()) {
+            (CaseSensitivity::Insensitive, Some(name)) => PathBuf::from(name.to_lowercase()),
+            _ => path,
+        }
+    }
+
+    /// Splits `paths` into those referring to distinct files and those
+    /// referring to the same file as an earlier path, including through a
+    /// hard link on Unix. The order of the paths is preserved, so each file is
+    /// kept at its first occurrence.
+    #[must_use]
+    pub fn dedup<I: IntoIterator<Item = PathBuf>>(&self, paths: I) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let mut seen = HashSet::new();
+        paths.into_iter().partition(|path| {
+            let identity = inode(path).unwrap_or_else(|| FileIdentity::Key(self.file_key(path)));
+            seen.insert(identity)
+        })
+    }
+
+    /// Finds pairs of `paths` which would refer to the same file, for example
+    /// because they differ only by case on a case-insensitive filesystem. Each
+    /// pair consists of the earlier and later path.
+    #[must_use]
+    pub fn collisions<'a, I: IntoIterator<Item = &'a Path>>(&self, paths: I) -> Vec<(&'a Path, &'a Path)> {
+        let mut first_by_key: HashMap<PathBuf, &Path> = HashMap::new();
+        let mut result = Vec::new();
+        for path in paths {
+            match first_by_key.entry(self.file_key(path)) {
+                Entry::Occupied(entry) => result.push((*entry.get(), path)),
+                Entry::Vacant(entry) => {
+                    entry.insert(path);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Default for CaseSensitivityCache<FilesystemProbe> {
+    fn default() -> CaseSensitivityCache<FilesystemProbe> { CaseSensitivityCache::new(FilesystemProbe) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    /// A probe returning a fixed result and counting how often it is used
+    struct MockProbe {
+        result: io::Result<CaseSensitivity>,
+        calls: Cell<usize>,
+    }
+
+    impl MockProbe {
+        fn new(result: io::Result<CaseSensitivity>) -> MockProbe { MockProbe { result, calls: Cell::new(0) } }
+    }
+
+    impl CaseProbe for &MockProbe {
+        fn probe(&self, _: &Path) -> io::Result<CaseSensitivity> {
+            self.calls.set(self.calls.get() + 1);
+            match &self.result {
+                Ok(result) => Ok(*result),
+                Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+            }
+        }
+    }
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> { names.iter().map(PathBuf::from).collect() }
+
+    #[test]
+    fn dedup_ignores_case_on_insensitive_filesystem() {
+        let probe = MockProbe::new(Ok(CaseSensitivity::Insensitive));
+        let cache = CaseSensitivityCache::new(&probe);
+        let (unique, duplicates) = cache.dedup(paths(&["music/Track.opus", "music/other.opus", "music/track.opus"]));
+        assert_eq!(unique, paths(&["music/Track.opus", "music/other.opus"]));
+        assert_eq!(duplicates, paths(&["music/track.opus"]));
+    }
+
+    #[test]
+    fn dedup_respects_case_on_sensitive_filesystem() {
+        let probe = MockProbe::new(Ok(CaseSensitivity::Sensitive));
+        let cache = CaseSensitivityCache::new(&probe);
+        let (unique, duplicates) = cache.dedup(paths(&["music/Track.opus", "music/track.opus", "music/Track.opus"]));
+        assert_eq!(unique, paths(&["music/Track.opus", "music/track.opus"]));
+        assert_eq!(duplicates, paths(&["music/Track.opus"]));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn dedup_identifies_links_to_the_same_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (track, other) = (dir.path().join("track.opus"), dir.path().join("other.opus"));
+        for path in [&track, &other] {
+            std::fs::write(path, b"data").unwrap();
+        }
+        let (hard_link, symlink) = (dir.path().join("hard link.opus"), dir.path().join("symlink.opus"));
+        std::fs::hard_link(&track, &hard_link).unwrap();
+        std::os::unix::fs::symlink(&track, &symlink).unwrap();
+        let dotted = dir.path().join(".").join("track.opus");
+
+        let probe = MockProbe::new(Ok(CaseSensitivity::Sensitive));
+        let cache = CaseSensitivityCache::new(&probe);
+        let inputs = [hard_link.clone(), other.clone(), track.clone(), symlink.clone(), dotted.clone()];
+        let (unique, duplicates) = cache.dedup(inputs);
+        assert_eq!(unique, [hard_link, other]);
+        assert_eq!(duplicates, [track, symlink, dotted]);
+    }
+
+    #[test]
+    fn failed_probe_is_case_sensitive() {
+        let probe = MockProbe::new(Err(io::Error::from(io::ErrorKind::PermissionDenied)));
+        let cache = CaseSensitivityCache::new(&probe);
+        assert_eq!(cache.get(Path::new("music")), CaseSensitivity::Sensitive);
+        let (_, duplicates) = cache.dedup(paths(&["music/Track.opus", "music/track.opus"]));
+        assert!(duplicates.is_empty());
+    }
+
+    #[test]
+    fn collisions_differing_by_case() {
+        let probe = MockProbe::new(Ok(CaseSensitivity::Insensitive));
+        let cache = CaseSensitivityCache::new(&probe);
+        let names = paths(&["out/A.opus", "out/b.opus", "out/a.opus"]);
+        let collisions = cache.collisions(names.iter().map(PathBuf::as_path));
+        assert_eq!(collisions, [(Path::new("out/A.opus"), Path::new("out/a.opus"))]);
+
+        let probe = MockProbe::new(Ok(CaseSensitivity::Sensitive));
+        let cache = CaseSensitivityCache::new(&probe);
+        assert!(cache.collisions(names.iter().map(PathBuf::as_path)).is_empty());
+    }
+
+    #[test]
+    fn probe_result_is_cached() {
+        let probe = MockProbe::new(Ok(CaseSensitivity::Insensitive));
+        let cache = CaseSensitivityCache::new(&probe);
+        for _ in 0..3 {
+            assert_eq!(cache.get(Path::new("music")), CaseSensitivity::Insensitive);
+        }
+        assert_eq!(probe.calls.get(), 1);
+    }
+
+    #[test]
+    fn filesystem_probe_cleans_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = FilesystemProbe.probe(dir.path()).unwrap();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        // Check the result against the filesystem directly
+        std::fs::write(dir.path().join("probe"), b"").unwrap();
+        let expected = if dir.path().join("PROBE").exists() {
+            CaseSensitivity::Insensitive
+        } else {
+            CaseSensitivity::Sensitive
+        };
+        assert_eq!(result, expected);
+    }
+}
@@ -0,0 +1,14 @@
+/// Converts a count of days since the Unix epoch to a (year, month, day)
+/// triple. See <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = u32::try_from(doy - (153 * mp + 2) / 5 + 1).expect("Day out of range");
+    let month = u32::try_from(if mp < 10 { mp + 3 } else { mp - 9 }).expect("Month out of range");
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,79 @@
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+
+/// The command-line arguments of a binary. On platforms where the shell does
+/// not expand wildcards, such as Windows, they are expanded here. The
+/// arguments as originally given are retained so that paths produced by
+/// expansion can be told apart from those specified literally.
+#[derive(Clone, Debug)]
+pub struct Arguments {
+    given: Vec<OsString>,
+    expanded: Vec<OsString>,
+}
+
+impl Arguments {
+    /// Reads the arguments of the current process
+    #[must_use]
+    pub fn from_env() -> Arguments { Arguments::new(std::env::args_os().collect(), wild::args_os().collect()) }
+
+    fn new(given: Vec<OsString>, expanded: Vec<OsString>) -> Arguments { Arguments { given, expanded } }
+
+    /// The arguments after wildcard expansion, to be passed to the parser
+    #[must_use]
+    pub fn expanded(&self) -> Vec<OsString> { self.expanded.clone() }
+
+    /// Whether `path` was produced by expanding a wildcard rather than being
+    /// given literally. A binary operating on a fixed number of paths treats
+    /// each by position, so a wildcard matching more files than expected
+    /// would otherwise have an input taken for the output.
+    #[must_use]
+    pub fn is_from_wildcard(&self, path: &Path) -> bool {
+        let path = normalize(path);
+        self.given != self.expanded && !self.given.iter().any(|arg| normalize(Path::new(arg)) == path)
+    }
+}
+
+/// Removes `.` components so that `./a.opus` and `a.opus` compare equal
+fn normalize(path: &Path) -> PathBuf { path.components().filter(|component| *component != Component::CurDir).collect() }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> { args.iter().map(OsString::from).collect() }
+
+    #[test]
+    fn expanded_paths_are_identified() {
+        let arguments =
+            Arguments::new(args(&["zoogcomment", "-m", "*.opus"]), args(&["zoogcomment", "-m", "a.opus", "b.opus"]));
+        assert_eq!(arguments.expanded(), args(&["zoogcomment", "-m", "a.opus", "b.opus"]));
+        assert!(arguments.is_from_wildcard(Path::new("a.opus")));
+        assert!(arguments.is_from_wildcard(Path::new("b.opus")));
+    }
+
+    #[test]
+    fn literal_paths_are_not_from_wildcard() {
+        let given = args(&["zoogcomment", "-m", "a.opus", "b.opus"]);
+        let arguments = Arguments::new(given.clone(), given);
+        assert!(!arguments.is_from_wildcard(Path::new("b.opus")));
+
+        // A literal path is recognised alongside an expanded one
+        let arguments = Arguments::new(
+            args(&["zoogcomment", "-m", "a*.opus", "b.opus"]),
+            args(&["zoogcomment", "-m", "a.opus", "b.opus"]),
+        );
+        assert!(arguments.is_from_wildcard(Path::new("a.opus")));
+        assert!(!arguments.is_from_wildcard(Path::new("b.opus")));
+    }
+
+    #[test]
+    fn literal_paths_are_compared_after_normalization() {
+        let arguments = Arguments::new(
+            args(&["zoogcomment", "-m", "a*.opus", "./b.opus"]),
+            args(&["zoogcomment", "-m", "a.opus", "./b.opus"]),
+        );
+        assert!(!arguments.is_from_wildcard(Path::new("b.opus")));
+        assert!(!arguments.is_from_wildcard(Path::new("./b.opus")));
+        assert!(arguments.is_from_wildcard(Path::new("./a.opus")));
+    }
+}
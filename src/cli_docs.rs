@@ -0,0 +1,20 @@
+use std::io::Write;
+
+use clap::CommandFactory;
+use clap_complete::Shell;
+use zoog::Error;
+
+/// Writes shell completions for the command-line interface `C`
+pub fn write_completions<C: CommandFactory, W: Write>(shell: Shell, output: &mut W) -> Result<(), Error> {
+    let mut command = C::command();
+    let name = command.get_name().to_string();
+    // Completions are buffered since `clap_complete` panics on write failure
+    let mut buffer = Vec::new();
+    clap_complete::generate(shell, &mut command, name, &mut buffer);
+    output.write_all(&buffer).map_err(Error::ConsoleIoError)
+}
+
+/// Writes a man page in roff format for the command-line interface `C`
+pub fn write_manpage<C: CommandFactory, W: Write>(output: &mut W) -> Result<(), Error> {
+    clap_mangen::Man::new(C::command()).render(output).map_err(Error::ConsoleIoError)
+}
@@ -1,7 +1,15 @@
 use std::fmt::{self, Display, Formatter};
+use std::io::{Read, Seek, SeekFrom};
+
+use ogg::reading::OggReadError;
+use ogg::PacketReader;
+
+use crate::header::IdHeader as _;
+use crate::{ogg_seek, opus, vorbis, Error};
 
 /// Known audio codecs
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Codec {
     /// Opus
     Opus,
@@ -19,3 +27,158 @@ impl Display for Codec {
         write!(formatter, "{}", name)
     }
 }
+
+/// Identifies the codec of the first logical stream of an Ogg file from its
+/// identification header, returning `None` for a codec that is not known. Only
+/// the page holding the first packet is read, and the reader is returned to
+/// its original position whether or not detection succeeds.
+pub fn detect_codec<R: Read + Seek>(mut input: R) -> Result<Option<Codec>, Error> {
+    let start = input.stream_position().map_err(Error::ReadError)?;
+    let result = detect_codec_from(&mut input, start);
+    input.seek(SeekFrom::Start(start)).map_err(Error::ReadError)?;
+    result
+}
+
+fn detect_codec_from<R: Read + Seek>(input: &mut R, start: u64) -> Result<Option<Codec>, Error> {
+    // Checked first since the Ogg reader would otherwise search the whole
+    // input for a page
+    if !ogg_seek::starts_with_capture_pattern(input)? {
+        return Err(Error::OggDecode(OggReadError::NoCapturePatternFound));
+    }
+    input.seek(SeekFrom::Start(start)).map_err(Error::ReadError)?;
+    let mut ogg_reader = PacketReader::new(input);
+    let identification = ogg_reader.read_packet().map_err(Error::OggDecode)?.ok_or(Error::MissingHeaders)?;
+    codec_of_id_header(&identification.data)
+}
+
+/// Identifies the codec of an identification header
+fn codec_of_id_header(data: &[u8]) -> Result<Option<Codec>, Error> {
+    if opus::IdHeader::try_parse(data)?.is_some() {
+        Ok(Some(Codec::Opus))
+    } else if vorbis::IdHeader::try_parse(data)?.is_some() {
+        Ok(Some(Codec::Vorbis))
+    } else {
+        Ok(None)
+    }
+}
+
+/// A logical stream of an Ogg file, as described by its beginning-of-stream
+/// packet
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StreamInfo {
+    /// The serial of the logical stream
+    pub serial: u32,
+
+    /// The codec of the logical stream, or `None` if it is not known
+    pub codec: Option<Codec>,
+}
+
+/// Lists the logical streams of an Ogg file in the order their
+/// beginning-of-stream packets occur. Since chained streams may begin anywhere,
+/// the whole of `input` is read.
+pub fn list_streams<R: Read + Seek>(input: R) -> Result<Vec<StreamInfo>, Error> {
+    let mut ogg_reader = PacketReader::new(input);
+    let mut streams = Vec::new();
+    while let Some(packet) = ogg_reader.read_packet().map_err(Error::OggDecode)? {
+        if packet.first_in_stream() {
+            let codec = codec_of_id_header(&packet.data)?;
+            streams.push(StreamInfo { serial: packet.stream_serial(), codec });
+        }
+    }
+    Ok(streams)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+
+    use super::*;
+    use crate::testing::{opus_file, StreamSpec};
+
+    /// Constructs an Ogg file containing a single stream with the specified
+    /// identification header
+    fn ogg_file(id_header: Vec<u8>) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut writer = PacketWriter::new(&mut data);
+        writer.write_packet(id_header, 3, PacketWriteEndInfo::EndPage, 0).unwrap();
+        writer.write_packet(b"comments".to_vec(), 3, PacketWriteEndInfo::EndStream, 0).unwrap();
+        drop(writer);
+        data
+    }
+
+    fn vorbis_file() -> Vec<u8> {
+        let mut id_header = b"\x01vorbis".to_vec();
+        id_header.extend([0, 0, 0, 0, 2, 0x44, 0xac, 0, 0]);
+        id_header.extend([0; 12]);
+        id_header.extend([0xb8, 1]);
+        ogg_file(id_header)
+    }
+
+    fn theora_file() -> Vec<u8> {
+        let mut id_header = b"\x80theora".to_vec();
+        id_header.extend([3, 2, 1]);
+        id_header.extend([0; 35]);
+        ogg_file(id_header)
+    }
+
+    #[test]
+    fn known_codecs_are_detected() -> Result<(), Error> {
+        let opus = opus_file(&[StreamSpec::default()]);
+        assert!(matches!(detect_codec(Cursor::new(&opus))?, Some(Codec::Opus)));
+        assert!(matches!(detect_codec(Cursor::new(vorbis_file()))?, Some(Codec::Vorbis)));
+        assert!(detect_codec(Cursor::new(theora_file()))?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn reader_position_is_restored() -> Result<(), Error> {
+        let prefix = b"not part of the stream";
+        let mut data = prefix.to_vec();
+        data.extend(opus_file(&[StreamSpec::default()]));
+        let mut input = Cursor::new(&data);
+        input.set_position(prefix.len() as u64);
+        assert!(matches!(detect_codec(&mut input)?, Some(Codec::Opus)));
+        assert_eq!(input.position(), prefix.len() as u64);
+
+        // Including on failure
+        input.set_position(1);
+        assert!(detect_codec(&mut input).is_err());
+        assert_eq!(input.position(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_input_is_an_error() {
+        let opus = opus_file(&[StreamSpec::default()]);
+        for truncated_len in [0, 3, 20, 40] {
+            assert!(detect_codec(Cursor::new(&opus[..truncated_len])).is_err(), "Length {}", truncated_len);
+        }
+        let text = b"ARTIST=Someone\n".repeat(100);
+        let result = detect_codec(Cursor::new(text));
+        assert!(matches!(result, Err(Error::OggDecode(OggReadError::NoCapturePatternFound))));
+    }
+
+    #[test]
+    fn streams_are_listed_in_order() -> Result<(), Error> {
+        let mut data = opus_file(&[StreamSpec { serial: 7, ..StreamSpec::default() }]);
+        data.extend(theora_file());
+        let streams = list_streams(Cursor::new(data))?;
+        let listed: Vec<_> = streams.iter().map(|s| (s.serial, s.codec.map(|c| c.to_string()))).collect();
+        assert_eq!(listed, vec![(7, Some(String::from("Opus"))), (3, None)]);
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() -> Result<(), serde_json::Error> {
+        for codec in [Codec::Opus, Codec::Vorbis] {
+            let json = serde_json::to_string(&codec)?;
+            let decoded: Codec = serde_json::from_str(&json)?;
+            assert_eq!(codec.to_string(), decoded.to_string());
+        }
+        Ok(())
+    }
+}
@@ -1,8 +1,10 @@
 use derivative::Derivative;
 
 use crate::header::{self, CommentList, DiscreteCommentList};
-use crate::header_rewriter::{HeaderRewriteGeneric, HeaderSummarizeGeneric};
-use crate::Error;
+use crate::header_rewriter::{
+    CodecHeaders, DuplicateHeaderAction, HeaderRewrite, HeaderSummarize, HeaderSummarizeGeneric, StreamSelection,
+};
+use crate::{Codec, Error};
 
 /// Mode type for `CommentRewriter`
 #[derive(Derivative)]
@@ -30,11 +32,59 @@ pub struct CommentRewriterConfig<'a> {
 #[derive(Debug)]
 pub struct CommentHeaderRewrite<'a> {
     config: CommentRewriterConfig<'a>,
+    duplicate_header_action: DuplicateHeaderAction,
+    stream_selection: StreamSelection,
+    input_sample_rate: Option<Option<usize>>,
 }
 
-impl CommentHeaderRewrite<'_> {
+impl<'a> CommentHeaderRewrite<'a> {
     #[must_use]
-    pub fn new(config: CommentRewriterConfig) -> CommentHeaderRewrite { CommentHeaderRewrite { config } }
+    pub fn new(config: CommentRewriterConfig) -> CommentHeaderRewrite {
+        CommentHeaderRewrite {
+            config,
+            duplicate_header_action: DuplicateHeaderAction::default(),
+            stream_selection: StreamSelection::default(),
+            input_sample_rate: None,
+        }
+    }
+
+    /// Sets what is done with a duplicate comment header following the
+    /// original
+    #[must_use]
+    pub fn with_duplicate_header_action(
+        self, duplicate_header_action: DuplicateHeaderAction,
+    ) -> CommentHeaderRewrite<'a> {
+        CommentHeaderRewrite { duplicate_header_action, ..self }
+    }
+
+    /// Sets which logical stream has its comments rewritten
+    #[must_use]
+    pub fn with_stream_selection(self, stream_selection: StreamSelection) -> CommentHeaderRewrite<'a> {
+        CommentHeaderRewrite { stream_selection, ..self }
+    }
+
+    /// Additionally sets the input sample rate of an Opus stream, with `None`
+    /// marking it as unknown. Rewriting a Vorbis stream then fails with
+    /// `Error::UnsupportedCodec` since its sample rate is not informational.
+    #[must_use]
+    pub fn with_input_sample_rate(self, rate: Option<usize>) -> CommentHeaderRewrite<'a> {
+        CommentHeaderRewrite { input_sample_rate: Some(rate), ..self }
+    }
+
+    fn rewrite_comments<C: header::CommentHeader>(&self, comment_header: &mut C) -> Result<(), Error> {
+        match &self.config.action {
+            CommentRewriterAction::NoChange => {}
+            CommentRewriterAction::Replace(tags) => {
+                comment_header.clear();
+                comment_header.extend(tags.iter())?;
+            }
+            CommentRewriterAction::Modify { retain, append } => {
+                comment_header.retain(retain);
+                comment_header.extend(append.iter())?;
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Summarizes codec headers by returning the comment list
@@ -54,25 +104,36 @@ impl HeaderSummarizeGeneric for CommentHeaderSummary {
     }
 }
 
-impl HeaderRewriteGeneric for CommentHeaderRewrite<'_> {
+impl HeaderSummarize for CommentHeaderSummary {
     type Error = Error;
+    type Summary = DiscreteCommentList;
 
-    fn rewrite<I, C>(&self, _idheader: &mut I, comment_header: &mut C) -> Result<(), Error>
-    where
-        I: header::IdHeader,
-        C: header::CommentHeader,
-    {
-        match &self.config.action {
-            CommentRewriterAction::NoChange => {}
-            CommentRewriterAction::Replace(tags) => {
-                comment_header.clear();
-                comment_header.extend(tags.iter())?;
+    fn summarize(&self, headers: &CodecHeaders) -> Result<DiscreteCommentList, Error> {
+        self.summarize_headers(headers)
+    }
+}
+
+impl HeaderRewrite for CommentHeaderRewrite<'_> {
+    type Error = Error;
+
+    fn rewrite(&self, headers: &mut CodecHeaders) -> Result<(), Error> {
+        match headers {
+            CodecHeaders::Opus(id_header, comment_header) => {
+                if let Some(rate) = self.input_sample_rate {
+                    id_header.set_input_sample_rate(rate)?;
+                }
+                self.rewrite_comments(comment_header)
             }
-            CommentRewriterAction::Modify { retain, append } => {
-                comment_header.retain(retain);
-                comment_header.extend(append.iter())?;
+            CodecHeaders::Vorbis(_, comment_header) => {
+                if self.input_sample_rate.is_some() {
+                    return Err(Error::UnsupportedCodec(Codec::Vorbis));
+                }
+                self.rewrite_comments(comment_header)
             }
         }
-        Ok(())
     }
+
+    fn duplicate_header_action(&self) -> DuplicateHeaderAction { self.duplicate_header_action }
+
+    fn stream_selection(&self) -> StreamSelection { self.stream_selection }
 }
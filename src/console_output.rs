@@ -56,6 +56,55 @@ impl ConsoleOutput for Standard {
     fn err(&self) -> Self::ErrStream<'_> { &self.err }
 }
 
+/// A writer which discards all output if it has no inner writer
+#[derive(Debug)]
+pub struct Optional<W> {
+    inner: Option<W>,
+}
+
+impl<W: Write> Write for Optional<W> {
+    fn write(&mut self, data: &[u8]) -> Result<usize, io::Error> {
+        match self.inner {
+            Some(ref mut inner) => inner.write(data),
+            None => Ok(data.len()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        match self.inner {
+            Some(ref mut inner) => inner.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: LockableWriter> LockableWriter for Optional<W> {
+    type Locked<'a> = Optional<W::Locked<'a>> where Self: 'a;
+
+    fn lock(&self) -> Self::Locked<'_> { Optional { inner: self.inner.as_ref().map(LockableWriter::lock) } }
+}
+
+/// Console output which optionally discards everything written to the output
+/// stream. The error stream is always passed through.
+#[derive(Debug)]
+pub struct Filtered<W: ConsoleOutput> {
+    inner: W,
+    show_out: bool,
+}
+
+impl<W: ConsoleOutput> Filtered<W> {
+    pub fn new(inner: W, show_out: bool) -> Filtered<W> { Filtered { inner, show_out } }
+}
+
+impl<W: ConsoleOutput> ConsoleOutput for Filtered<W> {
+    type ErrStream<'a> = W::ErrStream<'a> where Self: 'a;
+    type OutStream<'a> = Optional<W::OutStream<'a>> where Self: 'a;
+
+    fn out(&self) -> Self::OutStream<'_> { Optional { inner: self.show_out.then(|| self.inner.out()) } }
+
+    fn err(&self) -> Self::ErrStream<'_> { self.inner.err() }
+}
+
 #[derive(Copy, Clone, Debug)]
 enum StreamOperation {
     Write(usize),
@@ -76,6 +125,8 @@ impl StreamWrites {
         Ok(data.len())
     }
 
+    fn next_id(&self) -> Option<usize> { self.operations.front().map(|(id, _)| *id) }
+
     #[allow(clippy::unnecessary_wraps)]
     fn flush(&mut self, id: usize) -> Result<(), io::Error> {
         self.operations.push_back((id, StreamOperation::Flush));
@@ -162,35 +213,25 @@ where
         Delayed { inner, id_generator: IdGenerator::default(), out: Mutex::default(), err: Mutex::default() }
     }
 
+    /// Writes the buffered operations to the underlying console. Flushes are
+    /// serialized by `FLUSH_LOCK` so the output of each `Delayed` remains
+    /// contiguous, but only one underlying stream is locked at a time. Holding
+    /// both risks deadlock with code that locks them in a different order.
     #[allow(clippy::similar_names)]
     fn flush_delayed_operations(&mut self) -> Result<(), io::Error> {
+        let _flush_guard = FLUSH_LOCK.lock();
         let (out, err) = (self.inner.out(), self.inner.err());
-        let (mut out, mut err) = (out.lock(), err.lock());
         let (mut out_writes, mut err_writes) = (self.out.lock(), self.err.lock());
         let (mut out_offset, mut err_offset) = (0, 0);
 
         loop {
-            let next_is_stdout = match (out_writes.operations.front(), err_writes.operations.front()) {
-                (Some((out_id, _)), Some((err_id, _))) => out_id < err_id,
-                (Some(_), None) => true,
-                (None, Some(_)) => false,
-                (None, None) => break,
-            };
-            let (writer, offset, writes): (&mut dyn Write, _, _) = if next_is_stdout {
-                (&mut out, &mut out_offset, &mut out_writes)
-            } else {
-                (&mut err, &mut err_offset, &mut err_writes)
-            };
-            let (_id, op) = writes.operations.pop_front().expect("Unexpectedly failed to pop operation");
-            let data = &writes.data;
-            match op {
-                StreamOperation::Write(length) => {
-                    writer.write_all(&data[*offset..(*offset + length)])?;
-                    *offset += length;
-                }
-                StreamOperation::Flush => {
-                    writer.flush()?;
+            let (out_next, err_next) = (out_writes.next_id(), err_writes.next_id());
+            match (out_next, err_next) {
+                (Some(out_id), _) if err_next.map_or(true, |err_id| out_id < err_id) => {
+                    write_run(&out, &mut out_writes, &mut out_offset, err_next)?;
                 }
+                (_, Some(_)) => write_run(&err, &mut err_writes, &mut err_offset, out_next)?,
+                (_, None) => break,
             }
         }
 
@@ -199,9 +240,162 @@ where
     }
 }
 
+/// Serializes flushes of `Delayed` output
+static FLUSH_LOCK: Mutex<()> = parking_lot::const_mutex(());
+
+/// Locks `stream` and performs the buffered operations from `writes` with IDs
+/// preceding `until`
+fn write_run<L: LockableWriter>(
+    stream: &L, writes: &mut StreamWrites, offset: &mut usize, until: Option<usize>,
+) -> Result<(), io::Error> {
+    let mut stream = stream.lock();
+    while let Some(&(id, op)) = writes.operations.front() {
+        if until.is_some_and(|until| until < id) {
+            break;
+        }
+        writes.operations.pop_front();
+        match op {
+            StreamOperation::Write(length) => {
+                stream.write_all(&writes.data[*offset..(*offset + length)])?;
+                *offset += length;
+            }
+            StreamOperation::Flush => {
+                stream.flush()?;
+            }
+        }
+    }
+    Ok(())
+}
+
 impl<W> Drop for Delayed<'_, W>
 where
     W: ConsoleOutput,
 {
-    fn drop(&mut self) { drop(self.flush_delayed_operations()); }
+    fn drop(&mut self) {
+        // Panicking during drop could abort the process, so failures are only
+        // logged. Writing to standard error may itself fail if it is the cause.
+        if let Err(e) = self.flush_delayed_operations() {
+            drop(writeln!(io::stderr(), "Failed to write buffered console output: {}", e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc};
+    use std::time::Duration;
+
+    use super::*;
+
+    /// A stream which can be shared between threads and locked like the
+    /// standard streams
+    #[derive(Clone, Debug, Default)]
+    struct SharedStream {
+        data: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl SharedStream {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.data.lock().clone()).unwrap().lines().map(String::from).collect()
+        }
+    }
+
+    impl Write for &SharedStream {
+        fn write(&mut self, data: &[u8]) -> Result<usize, io::Error> { self.data.lock().write(data) }
+
+        fn flush(&mut self) -> Result<(), io::Error> { Ok(()) }
+    }
+
+    #[derive(Debug)]
+    struct LockedSharedStream<'a> {
+        data: MutexGuard<'a, Vec<u8>>,
+    }
+
+    impl Write for LockedSharedStream<'_> {
+        fn write(&mut self, data: &[u8]) -> Result<usize, io::Error> { self.data.write(data) }
+
+        fn flush(&mut self) -> Result<(), io::Error> { Ok(()) }
+    }
+
+    impl LockableWriter for &SharedStream {
+        type Locked<'a> = LockedSharedStream<'a> where Self: 'a;
+
+        fn lock(&self) -> Self::Locked<'_> { LockedSharedStream { data: self.data.lock() } }
+    }
+
+    #[derive(Debug, Default)]
+    struct Captured {
+        out: SharedStream,
+        err: SharedStream,
+    }
+
+    impl ConsoleOutput for Captured {
+        type ErrStream<'a> = &'a SharedStream where Self: 'a;
+        type OutStream<'a> = &'a SharedStream where Self: 'a;
+
+        fn out(&self) -> Self::OutStream<'_> { &self.out }
+
+        fn err(&self) -> Self::ErrStream<'_> { &self.err }
+    }
+
+    const NUM_DELAYED_THREADS: usize = 16;
+    const NUM_DIRECT_THREADS: usize = 4;
+    const LINES_PER_THREAD: usize = 200;
+
+    fn stress(console: &Captured) {
+        std::thread::scope(|scope| {
+            for thread in 0..NUM_DELAYED_THREADS {
+                scope.spawn(move || {
+                    for _ in 0..LINES_PER_THREAD {
+                        let delayed = Delayed::new(console);
+                        for line in 0..4 {
+                            writeln!(delayed.out(), "delayed {} {}", thread, line).unwrap();
+                            writeln!(delayed.err(), "delayed {} {}", thread, line).unwrap();
+                        }
+                    }
+                });
+            }
+            for thread in 0..NUM_DIRECT_THREADS {
+                scope.spawn(move || {
+                    for _ in 0..LINES_PER_THREAD {
+                        // Lock the streams in the opposite order to a flush which holds both
+                        let err = console.err();
+                        let mut err = err.lock();
+                        std::thread::yield_now();
+                        let out = console.out();
+                        let mut out = out.lock();
+                        writeln!(err, "direct {}", thread).unwrap();
+                        writeln!(out, "direct {}", thread).unwrap();
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn concurrent_flushes_do_not_deadlock() {
+        let console = Arc::new(Captured::default());
+        let (sender, receiver) = mpsc::channel();
+        {
+            let console = Arc::clone(&console);
+            std::thread::spawn(move || {
+                stress(&console);
+                sender.send(()).unwrap();
+            });
+        }
+        receiver.recv_timeout(Duration::from_secs(30)).expect("Console output deadlocked");
+
+        for stream in [&console.out, &console.err] {
+            let lines = stream.lines();
+            assert_eq!(lines.len(), (NUM_DELAYED_THREADS * 4 + NUM_DIRECT_THREADS) * LINES_PER_THREAD);
+            // The output of each flush is contiguous with respect to other flushes
+            let delayed: Vec<_> = lines.iter().filter(|line| line.starts_with("delayed")).collect();
+            for block in delayed.chunks(4) {
+                let thread = block[0].split(' ').nth(1).unwrap();
+                for (line, text) in block.iter().enumerate() {
+                    assert_eq!(**text, format!("delayed {} {}", thread, line));
+                }
+            }
+        }
+    }
 }
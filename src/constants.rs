@@ -11,6 +11,10 @@ pub mod global {
 
     /// Separator between field-names and values in comments
     pub const FIELD_NAME_TERMINATOR: u8 = b'=';
+
+    /// The prefix of the names of private tags written by zoog for its own
+    /// bookkeeping, which may be removed with `CommentList::remove_by_prefix`
+    pub const PRIVATE_TAG_PREFIX: &str = "ZOOG_";
 }
 
 pub mod opus {
@@ -21,4 +25,8 @@ pub mod opus {
     /// The name of the tag used to identify the album gain in Opus comment
     /// headers
     pub const TAG_ALBUM_GAIN: &str = "R128_ALBUM_GAIN";
+
+    /// The name of the informational tag recording the loudness that the
+    /// output gain of an Opus stream was normalized to
+    pub const TAG_REFERENCE_LOUDNESS: &str = "REPLAYGAIN_REFERENCE_LOUDNESS";
 }
@@ -2,7 +2,13 @@ use std::fmt::{Display, Formatter};
 use std::ops::{Add, Sub};
 
 /// Represents a Decibel-valued sound level
-#[derive(Copy, Clone, Debug)]
+///
+/// Equality follows that of `f64`, so a NaN value (which can result from
+/// analyzing near-silence) compares unequal to every value including itself.
+///
+/// When the `serde` feature is enabled, this serializes as a bare `f64`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Decibels {
     inner: f64,
 }
@@ -43,3 +49,30 @@ impl Add for Decibels {
 
     fn add(self, other: Decibels) -> Decibels { Decibels { inner: self.inner + other.inner } }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equality() {
+        assert_eq!(Decibels::from(-23.0), Decibels::from(-23.0));
+        assert_ne!(Decibels::from(-23.0), Decibels::from(-18.0));
+    }
+
+    #[test]
+    fn nan_is_not_equal_to_itself() {
+        let nan = Decibels::from(f64::NAN);
+        assert_ne!(nan, nan);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() -> Result<(), serde_json::Error> {
+        let value = Decibels::from(-4.25);
+        let json = serde_json::to_string(&value)?;
+        assert_eq!(json, "-4.25");
+        assert_eq!(serde_json::from_str::<Decibels>(&json)?, value);
+        Ok(())
+    }
+}
@@ -1,10 +1,52 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use ogg::reading::OggReadError;
 use tempfile::PersistError;
 use thiserror::Error;
 
-use crate::{escaping, Codec};
+use crate::header_issue::HeaderIssue;
+use crate::{escaping, volume_rewrite, Codec};
+
+/// Classification of errors by what they imply about continuing a batch
+/// operation
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    /// The file being operated on is at fault. Other files may still be
+    /// processed.
+    File,
+
+    /// The environment (filesystem, console, invocation) is at fault, so
+    /// processing further files is unlikely to succeed.
+    Environment,
+
+    /// An internal invariant was violated.
+    Internal,
+}
+
+#[cfg(unix)]
+const OUT_OF_SPACE_OS_ERRORS: &[i32] = &[28]; // ENOSPC
+
+#[cfg(windows)]
+const OUT_OF_SPACE_OS_ERRORS: &[i32] = &[39, 112]; // ERROR_HANDLE_DISK_FULL, ERROR_DISK_FULL
+
+#[cfg(not(any(unix, windows)))]
+const OUT_OF_SPACE_OS_ERRORS: &[i32] = &[];
+
+/// Classifies an IO error, returning `default` unless the error indicates
+/// resource exhaustion
+fn io_severity(error: &std::io::Error, default: Severity) -> Severity {
+    let out_of_space = error.raw_os_error().is_some_and(|code| OUT_OF_SPACE_OS_ERRORS.contains(&code));
+    if out_of_space || error.kind() == std::io::ErrorKind::OutOfMemory {
+        Severity::Environment
+    } else {
+        default
+    }
+}
+
+/// Formats a list of header issues for inclusion in an error message
+fn join_issues(issues: &[HeaderIssue]) -> String {
+    issues.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+}
 
 /// The Zoog error type
 #[derive(Debug, Error)]
@@ -18,10 +60,32 @@ pub enum Error {
     #[error("Unable to read from file `{0}` due to `{1}`")]
     FileReadError(PathBuf, std::io::Error),
 
+    /// The entries of a directory could not be read
+    #[error("Unable to read directory `{0}` due to `{1}`")]
+    DirectoryReadError(PathBuf, std::io::Error),
+
     /// An error occurred writing to the file
     #[error("Unable to write to file `{0}` due to `{1}`")]
     FileWriteError(PathBuf, std::io::Error),
 
+    /// An input was a URL but zoog was built without support for reading them
+    #[error("`{0}` is a URL but zoog was built without the `http` feature")]
+    UrlUnsupported(PathBuf),
+
+    /// An input named a file inside an archive but zoog was built without
+    /// support for reading them
+    #[error("`{0}` names a file inside an archive but zoog was built without the `archive` feature")]
+    ArchiveUnsupported(PathBuf),
+
+    /// A file inside an archive could not be read
+    #[error("Unable to read `{0}` from its archive: {1}")]
+    ArchiveError(PathBuf, String),
+
+    /// A compressed file inside an archive was too large to decompress into
+    /// memory
+    #[error("`{0}` is compressed and larger than the {1} byte limit for reading from archives")]
+    ArchiveMemberTooLarge(PathBuf, u64),
+
     /// A specified file could not be copied due to an IO error
     #[error("Unable to copy `{0}` to `{1}` due to `{2}`")]
     FileCopy(PathBuf, PathBuf, std::io::Error),
@@ -30,6 +94,28 @@ pub enum Error {
     #[error("Unable to open temporary file in `{0}` due to `{1}`")]
     TempFileOpenError(PathBuf, std::io::Error),
 
+    /// A temporary file with a deterministic name already exists, which may
+    /// have been left behind by an earlier run that did not finish
+    #[error("Temporary file `{0}` already exists, possibly left behind by an earlier run")]
+    TempFileExists(PathBuf),
+
+    /// Files could not be created in the directory of a file to be replaced
+    #[error("Unable to create files in directory `{0}` due to `{1}`")]
+    DirectoryNotWritable(PathBuf, std::io::Error),
+
+    /// A file to be replaced is read-only
+    #[error("File `{0}` is read-only")]
+    ReadOnlyFile(PathBuf),
+
+    /// A read-only file to be replaced cannot be made writable since it is not
+    /// owned by the current user
+    #[error("File `{0}` is read-only and not owned by the current user")]
+    ReadOnlyFileNotOwned(PathBuf),
+
+    /// A file to be created already exists and may not be overwritten
+    #[error("File `{0}` already exists")]
+    OutputFileExists(PathBuf),
+
     /// An Ogg stream failed to decode correctly
     #[error("Ogg decoding error: `{0}`")]
     OggDecode(OggReadError),
@@ -58,6 +144,10 @@ pub enum Error {
     #[error("Malformed comment header")]
     MalformedCommentHeader,
 
+    /// The comment header declares more comments than the parser accepts
+    #[error("Comment header declares {0} comments, exceeding the limit of {1}")]
+    TooManyComments(u32, usize),
+
     /// Missing comment separator
     #[error("Missing separator in comment")]
     MissingCommentSeparator,
@@ -70,6 +160,22 @@ pub enum Error {
     #[error("R128 tag has invalid value: `{0}`")]
     InvalidR128Tag(String),
 
+    /// A value could not be parsed as a number by `parse_number`
+    #[error("Invalid number `{0}`: expected a decimal number such as -16.5 or -16,5 without thousands separators")]
+    InvalidNumber(String),
+
+    /// A ReplayGain gain value could not be parsed
+    #[error("Invalid ReplayGain value `{0}`: expected a gain such as -6.54 dB")]
+    InvalidReplayGainValue(String),
+
+    /// A ReplayGain peak value could not be parsed
+    #[error("Invalid ReplayGain peak `{0}`: expected an amplitude such as 0.988553")]
+    InvalidReplayGainPeak(String),
+
+    /// An R128 tag required to compute gains was missing
+    #[error("Required tag {0} is missing")]
+    MissingR128Tag(String),
+
     /// A gain value was out of bounds for being representable
     #[error("A computed gain value was not representable")]
     GainOutOfBounds,
@@ -86,6 +192,10 @@ pub enum Error {
     #[error("Unsupported channel count: `{0}`")]
     InvalidChannelCount(usize),
 
+    /// An unsupported sample rate was found
+    #[error("Unsupported sample rate: `{0}`")]
+    InvalidSampleRate(usize),
+
     /// An error was returned from the Opus library
     #[error("Opus error: `{0}`")]
     OpusError(opus::Error),
@@ -94,6 +204,10 @@ pub enum Error {
     #[error("Console IO error: `{0}`")]
     ConsoleIoError(std::io::Error),
 
+    /// A loudness classification could not be parsed
+    #[error("Invalid loudness classification `{0}`: {1}")]
+    InvalidLoudnessClassification(String, String),
+
     /// An invalid thread count was specified
     #[error("An invalid number of threads was specified")]
     InvalidThreadCount,
@@ -110,10 +224,19 @@ pub enum Error {
     #[error("Invalid Opus comment field name: `{0}`")]
     InvalidOpusCommentFieldName(String),
 
+    /// A comment field name was valid but contained characters which other
+    /// tools commonly reject
+    #[error("Comment field name `{0}` is not portable (only A-Z, a-z, 0-9 and _ are widely supported)")]
+    NonPortableCommentFieldName(String),
+
     /// An escaped string was invalid
     #[error("{0}")]
     EscapeDecodeError(#[from] escaping::EscapeDecodeError),
 
+    /// A `VolumeRewriterConfig` with inconsistent settings was used
+    #[error("Invalid volume rewriter configuration: {0}")]
+    InvalidVolumeRewriterConfig(#[from] volume_rewrite::ConfigError),
+
     /// An interrupt was detected
     #[error("The operation was interrupted")]
     Interrupted,
@@ -134,8 +257,17 @@ pub enum Error {
     #[error("Unexpected logical stream in Ogg file, serial {0:#x}")]
     UnexpectedLogicalStream(u32),
 
-    /// Audio parameters changed
-    #[error("Channel count and/or sample rate changed between concatenated audio streams")]
+    /// No page of the logical stream with the specified serial was found
+    #[error("No logical stream with serial {0:#x} in Ogg file")]
+    MissingLogicalStream(u32),
+
+    /// The file has fewer logical streams than the specified index requires
+    #[error("No logical stream with index {0} in Ogg file")]
+    MissingStreamIndex(usize),
+
+    /// Audio parameters changed between chained streams, so the loudness of
+    /// the file cannot be analyzed as a single piece of audio
+    #[error("Unable to analyze loudness since the channel count and/or sample rate changed between chained streams")]
     UnexpectedAudioParametersChange,
 
     /// An error occurred reading the file metadata
@@ -145,4 +277,240 @@ pub enum Error {
     /// An error occurred writing the file metadata
     #[error("Unable to write metadata to file `{0}` due to `{1}`")]
     FileMetadataWriteError(PathBuf, std::io::Error),
+
+    /// An internal error such as a panic occurred
+    #[error("Internal error: {0}")]
+    Internal(String),
+
+    /// Processing of a file did not complete before its deadline
+    #[error("Processing timed out")]
+    TimedOut,
+
+    /// The file changed while it was being processed, for example because it
+    /// is still being written, so it was left in place
+    #[error("File `{0}` was modified during processing")]
+    ModifiedDuringProcessing(PathBuf),
+
+    /// The stream ended before both header packets were read
+    #[error("The stream ended before the identification and comment headers were read")]
+    MissingHeaders,
+
+    /// Streams to be concatenated have differing identification headers
+    #[error("The identification headers of the streams to be concatenated differ")]
+    IncompatibleStreams,
+
+    /// Issues were found in the headers while checking them strictly
+    #[error("Header issues found: {}", join_issues(.0))]
+    HeaderIssues(Vec<HeaderIssue>),
+
+    /// An error occurred while operating on the specified file
+    #[error("`{path}`: {source}")]
+    InFile {
+        /// The file being operated on
+        path: PathBuf,
+
+        /// The underlying error
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Classifies the error by whether it is specific to the file being
+    /// operated on, or whether it implies that further operations are likely
+    /// to fail.
+    #[must_use]
+    pub fn severity(&self) -> Severity {
+        // This match is deliberately exhaustive so that new variants must be classified
+        match self {
+            Error::FileOpenError(_, e)
+            | Error::FileReadError(_, e)
+            | Error::DirectoryReadError(_, e)
+            | Error::ReadError(e)
+            | Error::FileMetadataReadError(_, e)
+            | Error::DirectoryNotWritable(_, e)
+            | Error::OggDecode(OggReadError::ReadError(e)) => io_severity(e, Severity::File),
+            Error::FileWriteError(_, e)
+            | Error::FileCopy(_, _, e)
+            | Error::TempFileOpenError(_, e)
+            | Error::WriteError(e)
+            | Error::FileDelete(_, e)
+            | Error::ConsoleIoError(e)
+            | Error::FileMetadataWriteError(_, e) => io_severity(e, Severity::Environment),
+            Error::PersistError(e) => io_severity(&e.error, Severity::Environment),
+            Error::OpusError(e) => match e.code() {
+                opus::ErrorCode::InvalidPacket => Severity::File,
+                opus::ErrorCode::AllocFail => Severity::Environment,
+                _ => Severity::Internal,
+            },
+            Error::OggDecode(_)
+            | Error::MissingStream(_)
+            | Error::UnknownCodec
+            | Error::MalformedIdentificationHeader
+            | Error::MalformedCommentHeader
+            | Error::TooManyComments(_, _)
+            | Error::MissingCommentSeparator
+            | Error::UTF8Error(_)
+            | Error::InvalidR128Tag(_)
+            | Error::InvalidNumber(_)
+            | Error::InvalidReplayGainValue(_)
+            | Error::InvalidReplayGainPeak(_)
+            | Error::MissingR128Tag(_)
+            | Error::GainOutOfBounds
+            | Error::InvalidChannelCount(_)
+            | Error::InvalidSampleRate(_)
+            | Error::NoParentError(_)
+            | Error::NotAFilePath(_)
+            | Error::ReadOnlyFile(_)
+            | Error::ReadOnlyFileNotOwned(_)
+            | Error::InvalidOpusCommentFieldName(_)
+            | Error::NonPortableCommentFieldName(_)
+            | Error::EscapeDecodeError(_)
+            | Error::UnsupportedCodecVersion(_, _)
+            | Error::UnsupportedCodec(_)
+            | Error::UnrepresentableValueInCommentHeader
+            | Error::UnexpectedLogicalStream(_)
+            | Error::MissingLogicalStream(_)
+            | Error::MissingStreamIndex(_)
+            | Error::UnexpectedAudioParametersChange
+            | Error::MissingHeaders
+            | Error::TimedOut
+            | Error::ModifiedDuringProcessing(_)
+            | Error::IncompatibleStreams
+            | Error::HeaderIssues(_)
+            | Error::ArchiveError(_, _)
+            | Error::ArchiveMemberTooLarge(_, _) => Severity::File,
+            Error::InvalidThreadCount
+            | Error::InvalidLoudnessClassification(_, _)
+            | Error::Interrupted
+            | Error::OutputFileExists(_)
+            | Error::TempFileExists(_)
+            | Error::UrlUnsupported(_)
+            | Error::ArchiveUnsupported(_) => Severity::Environment,
+            Error::Internal(_) | Error::InvalidVolumeRewriterConfig(_) => Severity::Internal,
+            Error::InFile { source, .. } => source.severity(),
+        }
+    }
+
+    /// Returns the path of the file this error names, if any
+    #[must_use]
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::FileOpenError(path, _)
+            | Error::FileReadError(path, _)
+            | Error::DirectoryReadError(path, _)
+            | Error::FileWriteError(path, _)
+            | Error::FileCopy(path, _, _)
+            | Error::NoParentError(path)
+            | Error::NotAFilePath(path)
+            | Error::ReadOnlyFile(path)
+            | Error::ReadOnlyFileNotOwned(path)
+            | Error::OutputFileExists(path)
+            | Error::TempFileExists(path)
+            | Error::UrlUnsupported(path)
+            | Error::ArchiveUnsupported(path)
+            | Error::ArchiveError(path, _)
+            | Error::ArchiveMemberTooLarge(path, _)
+            | Error::FileMetadataReadError(path, _)
+            | Error::FileMetadataWriteError(path, _)
+            | Error::ModifiedDuringProcessing(path)
+            | Error::InFile { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Attaches the path of the file being operated on to this error. If the
+    /// error already names a file it is returned unchanged, so that each error
+    /// names its file exactly once.
+    #[must_use]
+    pub fn in_file<P: Into<PathBuf>>(self, path: P) -> Error {
+        if self.path().is_some() {
+            self
+        } else {
+            Error::InFile { path: path.into(), source: Box::new(self) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use byteorder::{LittleEndian, WriteBytesExt};
+
+    use super::*;
+    use crate::comment_rewrite::{
+        CommentHeaderRewrite, CommentHeaderSummary, CommentRewriterAction, CommentRewriterConfig,
+    };
+    use crate::header_rewriter::rewrite_stream;
+    use crate::testing::opus_id_header;
+
+    fn file_with_comment_header(comment_header: Vec<u8>) -> Vec<u8> {
+        use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+
+        let mut data = Vec::new();
+        let mut writer = PacketWriter::new(&mut data);
+        writer.write_packet(opus_id_header(2, 48000, 0, 0), 1, PacketWriteEndInfo::EndPage, 0).unwrap();
+        writer.write_packet(comment_header, 1, PacketWriteEndInfo::EndStream, 0).unwrap();
+        drop(writer);
+        data
+    }
+
+    #[test]
+    fn comment_parse_failure_names_file() {
+        let mut comment_header = b"OpusTags".to_vec();
+        let comment = b"NO_SEPARATOR";
+        comment_header.write_u32::<LittleEndian>(0).unwrap();
+        comment_header.write_u32::<LittleEndian>(1).unwrap();
+        comment_header.write_u32::<LittleEndian>(comment.len().try_into().unwrap()).unwrap();
+        comment_header.extend(comment);
+        let input = Cursor::new(file_with_comment_header(comment_header));
+
+        let rewrite = CommentHeaderRewrite::new(CommentRewriterConfig { action: CommentRewriterAction::NoChange });
+        let summarize = CommentHeaderSummary::default();
+        let error = rewrite_stream(rewrite, summarize, input, std::io::sink(), true)
+            .map_err(|e: Error| e.in_file("music/track.opus"))
+            .unwrap_err();
+        assert_eq!(error.to_string(), "`music/track.opus`: Missing separator in comment");
+    }
+
+    #[test]
+    fn path_is_attached_once() {
+        let error = Error::MalformedCommentHeader.in_file("a.opus").in_file("b.opus");
+        assert_eq!(error.to_string(), "`a.opus`: Malformed comment header");
+        assert_eq!(error.path(), Some(Path::new("a.opus")));
+    }
+
+    #[test]
+    fn severity_classification() {
+        use std::io::{Error as IoError, ErrorKind};
+
+        assert_eq!(Error::MalformedCommentHeader.severity(), Severity::File);
+        assert_eq!(Error::UnexpectedAudioParametersChange.severity(), Severity::File);
+        assert_eq!(Error::Interrupted.severity(), Severity::Environment);
+        assert_eq!(Error::Internal("panic".into()).in_file("a.opus").severity(), Severity::Internal);
+        let bad_arg = opus::Decoder::new(1234, opus::Channels::Mono).unwrap_err();
+        assert_eq!(Error::OpusError(bad_arg).severity(), Severity::Internal);
+        assert_eq!(
+            Error::FileOpenError(PathBuf::from("a.opus"), IoError::from(ErrorKind::NotFound)).severity(),
+            Severity::File
+        );
+        assert_eq!(Error::WriteError(IoError::from(ErrorKind::PermissionDenied)).severity(), Severity::Environment);
+        assert_eq!(Error::MalformedCommentHeader.in_file("a.opus").severity(), Severity::File);
+        assert_eq!(Error::Interrupted.in_file("a.opus").severity(), Severity::Environment);
+    }
+
+    #[test]
+    fn out_of_space_is_environmental() {
+        for &code in OUT_OF_SPACE_OS_ERRORS {
+            let error = Error::FileReadError(PathBuf::from("a.opus"), std::io::Error::from_raw_os_error(code));
+            assert_eq!(error.severity(), Severity::Environment);
+        }
+    }
+
+    #[test]
+    fn errors_naming_file_are_not_wrapped() {
+        let io_error = std::io::Error::new(std::io::ErrorKind::NotFound, "not found");
+        let error = Error::FileOpenError(PathBuf::from("a.opus"), io_error).in_file("a.opus");
+        assert!(matches!(error, Error::FileOpenError(_, _)));
+    }
 }
@@ -0,0 +1,241 @@
+use std::ffi::OsString;
+use std::io;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use parking_lot::Mutex;
+use thiserror::Error;
+
+/// The text in a command which is replaced by the path of the modified file
+pub const PATH_PLACEHOLDER: &str = "{}";
+
+/// Why a command run after modifying a file failed. Messages are intended to
+/// follow `Warning: `.
+#[derive(Debug, Error)]
+pub enum ExecError {
+    #[error("unable to run the command for {0}: {1}")]
+    Spawn(String, io::Error),
+
+    #[error("the command for {0} exited unsuccessfully ({1})")]
+    Status(String, ExitStatus),
+}
+
+#[derive(Debug)]
+enum Template {
+    /// Words passed directly as the program and its arguments
+    Direct(Vec<String>),
+
+    /// A command line interpreted by the system shell
+    Shell(String),
+}
+
+/// A command run after each file is modified, as given to `--exec-after`.
+/// Commands are run one at a time, so that processing many files in parallel
+/// does not start many processes at once.
+#[derive(Debug)]
+pub struct ExecAfter {
+    template: Template,
+    lock: Mutex<()>,
+    failures: AtomicUsize,
+}
+
+impl ExecAfter {
+    /// Parses `command`, which is run by the system shell if `shell` is set.
+    /// Otherwise it is split into words at whitespace, which may be quoted
+    /// with single or double quotes.
+    pub fn new(command: &str, shell: bool) -> Result<ExecAfter, String> {
+        let template = if shell {
+            if command.trim().is_empty() {
+                return Err(String::from("the command to run after modifying a file is empty"));
+            }
+            Template::Shell(command.to_string())
+        } else {
+            Template::Direct(split_words(command)?)
+        };
+        Ok(ExecAfter { template, lock: Mutex::new(()), failures: AtomicUsize::new(0) })
+    }
+
+    /// Runs the command for the file at `path` and waits for it to exit. The
+    /// standard output of the command is sent to standard error so that it
+    /// cannot be mistaken for a report. Failures are counted as well as
+    /// returned.
+    pub fn run(&self, path: &Path) -> Result<(), ExecError> {
+        let _guard = self.lock.lock();
+        let status = self
+            .command(path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::from(io::stderr()))
+            .status()
+            .map_err(|e| ExecError::Spawn(path.display().to_string(), e));
+        let result = match status {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(ExecError::Status(path.display().to_string(), status)),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// The number of commands which could not be run or exited unsuccessfully
+    #[allow(dead_code)]
+    pub fn failures(&self) -> usize { self.failures.load(Ordering::Relaxed) }
+
+    fn command(&self, path: &Path) -> Command {
+        match &self.template {
+            Template::Direct(words) => {
+                let mut arguments: Vec<OsString> = words.iter().map(|word| substitute_path(word, path)).collect();
+                if !words.iter().any(|word| word.contains(PATH_PLACEHOLDER)) {
+                    arguments.push(path.into());
+                }
+                let mut command = Command::new(&arguments[0]);
+                command.args(&arguments[1..]);
+                command
+            }
+            Template::Shell(line) => shell_command(line, path),
+        }
+    }
+}
+
+/// Replaces each placeholder in `word` with `path`
+fn substitute_path(word: &str, path: &Path) -> OsString {
+    let mut result = OsString::new();
+    for (idx, part) in word.split(PATH_PLACEHOLDER).enumerate() {
+        if idx != 0 {
+            result.push(path);
+        }
+        result.push(part);
+    }
+    result
+}
+
+/// The path is passed to the shell as a positional parameter rather than
+/// inserted into the command line, so it is never interpreted by the shell
+#[cfg(unix)]
+fn shell_command(line: &str, path: &Path) -> Command {
+    let line = if line.contains(PATH_PLACEHOLDER) {
+        line.replace(PATH_PLACEHOLDER, "\"$1\"")
+    } else {
+        format!("{} \"$1\"", line)
+    };
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(line).arg("sh").arg(path);
+    command
+}
+
+/// `cmd` has no positional parameters, so the path is quoted instead.
+/// Characters which cannot appear in Windows paths cannot escape the quotes.
+#[cfg(not(unix))]
+fn shell_command(line: &str, path: &Path) -> Command {
+    let quoted = format!("\"{}\"", path.display());
+    let line = if line.contains(PATH_PLACEHOLDER) {
+        line.replace(PATH_PLACEHOLDER, &quoted)
+    } else {
+        format!("{} {}", line, quoted)
+    };
+    let mut command = Command::new("cmd");
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt as _;
+        command.arg("/C").raw_arg(line);
+    }
+    #[cfg(not(windows))]
+    command.arg("/C").arg(line);
+    command
+}
+
+/// Splits a command into words at whitespace. Single or double quotes group
+/// text containing whitespace into a single word and are removed. There are
+/// no escapes, so that Windows paths need no special treatment.
+fn split_words(command: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in command.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => word.get_or_insert_with(String::new).push(c),
+            None if c == '\'' || c == '"' => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            None if c.is_whitespace() => words.extend(word.take()),
+            None => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(q) = quote {
+        return Err(format!("the command `{}` has an unterminated {} quote", command, q));
+    }
+    words.extend(word);
+    if words.is_empty() {
+        return Err(String::from("the command to run after modifying a file is empty"));
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_split_into_words() {
+        let split = |command: &str| split_words(command).unwrap();
+        assert_eq!(split("rescan {}"), ["rescan", "{}"]);
+        assert_eq!(split("  rescan\t--path={}  now "), ["rescan", "--path={}", "now"]);
+        assert_eq!(split("'my tool' \"a b\"c ''"), ["my tool", "a bc", ""]);
+        assert_eq!(split(r"C:\tools\rescan.exe {}"), [r"C:\tools\rescan.exe", "{}"]);
+        assert_eq!(split("say \"it's\""), ["say", "it's"]);
+    }
+
+    #[test]
+    fn invalid_commands_are_rejected() {
+        for command in ["", "   ", "rescan 'unterminated", "\"open"] {
+            assert!(split_words(command).is_err(), "{:?} should be rejected", command);
+        }
+        assert!(ExecAfter::new(" ", true).is_err());
+    }
+
+    fn arguments(command: &Command) -> Vec<String> {
+        command.get_args().map(|argument| argument.to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn path_is_a_single_argument() {
+        let path = Path::new("dir with spaces/it's \"quoted\"; rm -rf.opus");
+        let hook = ExecAfter::new("rescan --file={} {}", false).unwrap();
+        let command = hook.command(path);
+        assert_eq!(command.get_program().to_str(), Some("rescan"));
+        let path_text = path.display().to_string();
+        assert_eq!(arguments(&command), [format!("--file={}", path_text), path_text.clone()]);
+
+        // A command without a placeholder receives the path last
+        let hook = ExecAfter::new("rescan", false).unwrap();
+        assert_eq!(arguments(&hook.command(path)), [path_text]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn shell_receives_path_as_parameter() {
+        let path = Path::new("$(touch pwned).opus");
+        let hook = ExecAfter::new("echo {} >> log.txt", true).unwrap();
+        let command = hook.command(path);
+        assert_eq!(command.get_program().to_str(), Some("sh"));
+        assert_eq!(arguments(&command), ["-c", "echo \"$1\" >> log.txt", "sh", "$(touch pwned).opus"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn failures_are_counted() {
+        let hook = ExecAfter::new("true", false).unwrap();
+        assert!(hook.run(Path::new("a.opus")).is_ok());
+        assert_eq!(hook.failures(), 0);
+        let hook = ExecAfter::new("false", false).unwrap();
+        assert!(matches!(hook.run(Path::new("a.opus")), Err(ExecError::Status(_, _))));
+        let hook = ExecAfter::new("/nonexistent/zoog-hook", false).unwrap();
+        assert!(matches!(hook.run(Path::new("a.opus")), Err(ExecError::Spawn(_, _))));
+        assert!(hook.run(Path::new("b.opus")).is_err());
+        assert_eq!(hook.failures(), 2);
+    }
+}
@@ -0,0 +1,10 @@
+use zoog::Severity;
+
+/// Maps the severity of an error to the process exit code
+pub fn for_severity(severity: Severity) -> i32 {
+    match severity {
+        Severity::File => 1,
+        Severity::Environment => 2,
+        Severity::Internal => 3,
+    }
+}
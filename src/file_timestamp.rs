@@ -1,5 +1,8 @@
+use std::path::Path;
 use std::time::{Duration, SystemTime};
 
+use crate::Error;
+
 /// Modification timestamp granularities from various filesystems.
 const SORTED_MODIFICATION_GRANULARITIES: &[Duration] = &[
     Duration::from_nanos(1),    // btrfs, ZFS, APFS, ext4 (256-bit inodes)
@@ -42,3 +45,157 @@ pub fn set_mtime_with_minimal_increment(file: &std::fs::File, base_mtime: System
     }
     Ok(false)
 }
+
+/// Reads the modification time of the file at `path`
+pub fn read_mtime(path: &Path) -> Result<SystemTime, Error> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|e| Error::FileMetadataReadError(path.to_path_buf(), e))
+}
+
+/// Sets the modification time of the file at `path`, for example to that of a
+/// reference file read with `read_mtime`. The time stored may be rounded to
+/// the granularity of the filesystem.
+pub fn set_mtime(path: &Path, mtime: SystemTime) -> Result<(), Error> {
+    std::fs::File::open(path)
+        .and_then(|file| file.set_modified(mtime))
+        .map_err(|e| Error::FileMetadataWriteError(path.to_path_buf(), e))
+}
+
+/// The size and modification time of a file, recorded before processing it so
+/// that changes made while it was processed, such as by a recorder still
+/// writing it, can be detected before it is replaced
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FileSnapshot {
+    len: u64,
+    modified: SystemTime,
+}
+
+impl FileSnapshot {
+    /// Records the size and modification time of the file at `path`
+    pub fn read(path: &Path) -> Result<FileSnapshot, Error> {
+        std::fs::metadata(path)
+            .and_then(|metadata| Ok(FileSnapshot { len: metadata.len(), modified: metadata.modified()? }))
+            .map_err(|e| Error::FileMetadataReadError(path.to_path_buf(), e))
+    }
+
+    /// The modification time of the file when the snapshot was taken
+    #[must_use]
+    pub fn modified(&self) -> SystemTime { self.modified }
+
+    /// Whether the file had been modified less than `period` before `now`
+    /// when the snapshot was taken. Modification times in the future count as
+    /// recent.
+    #[must_use]
+    pub fn is_recent(&self, period: Duration, now: SystemTime) -> bool {
+        now.duration_since(self.modified).map_or(true, |age| age < period)
+    }
+
+    /// Whether the file at `path` still has the recorded size and modification
+    /// time. A file whose metadata can no longer be read counts as changed.
+    #[must_use]
+    pub fn is_unchanged(&self, path: &Path) -> bool { FileSnapshot::read(path).is_ok_and(|current| current == *self) }
+
+    /// Checks that the file at `path` is unchanged since the snapshot was
+    /// taken, returning `Error::ModifiedDuringProcessing` if not
+    pub fn verify(&self, path: &Path) -> Result<(), Error> {
+        if self.is_unchanged(path) {
+            Ok(())
+        } else {
+            Err(Error::ModifiedDuringProcessing(path.to_path_buf()))
+        }
+    }
+
+    /// Replaces an error from processing the file at `path` with
+    /// `Error::ModifiedDuringProcessing` if the file changed meanwhile, since
+    /// reading a file while it is written commonly fails as if it were
+    /// truncated
+    #[must_use]
+    pub fn explain_error(&self, path: &Path, error: Error) -> Error {
+        match error {
+            Error::Interrupted | Error::TimedOut | Error::Internal(_) => error,
+            error => self.verify(path).err().unwrap_or(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+
+    use super::*;
+
+    #[test]
+    fn mtime_is_copied_between_files() -> Result<(), Error> {
+        let dir = tempfile::tempdir().unwrap();
+        let (reference, target) = (dir.path().join("reference"), dir.path().join("target"));
+        std::fs::write(&reference, b"reference").unwrap();
+        std::fs::write(&target, b"target").unwrap();
+        // A whole number of seconds divisible by the coarsest granularity
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        set_mtime(&reference, mtime)?;
+        set_mtime(&target, read_mtime(&reference)?)?;
+        assert_eq!(read_mtime(&target)?, mtime);
+
+        let missing = dir.path().join("missing");
+        assert!(matches!(read_mtime(&missing), Err(Error::FileMetadataReadError(path, _)) if path == missing));
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_detects_changes() -> Result<(), Error> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.opus");
+        std::fs::write(&path, b"first page").unwrap();
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        set_mtime(&path, mtime)?;
+        let snapshot = FileSnapshot::read(&path)?;
+        assert!(snapshot.is_unchanged(&path));
+        assert!(snapshot.verify(&path).is_ok());
+
+        // A file which is appended to grows
+        std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"second page").unwrap();
+        set_mtime(&path, mtime)?;
+        assert!(!snapshot.is_unchanged(&path));
+        assert!(matches!(snapshot.verify(&path), Err(Error::ModifiedDuringProcessing(p)) if p == path));
+
+        // A file rewritten with the same size has a new modification time
+        let snapshot = FileSnapshot::read(&path)?;
+        std::fs::write(&path, b"first pagesecond page").unwrap();
+        set_mtime(&path, mtime + Duration::from_secs(2))?;
+        assert!(!snapshot.is_unchanged(&path));
+
+        // A file which has been removed cannot be replaced
+        let snapshot = FileSnapshot::read(&path)?;
+        std::fs::remove_file(&path).unwrap();
+        assert!(!snapshot.is_unchanged(&path));
+        Ok(())
+    }
+
+    #[test]
+    fn errors_are_explained_by_changes() -> Result<(), Error> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.opus");
+        std::fs::write(&path, b"first page").unwrap();
+        let snapshot = FileSnapshot::read(&path)?;
+        let explained = snapshot.explain_error(&path, Error::MissingHeaders);
+        assert!(matches!(explained, Error::MissingHeaders));
+
+        std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"second page").unwrap();
+        let explained = snapshot.explain_error(&path, Error::MissingHeaders);
+        assert!(matches!(explained, Error::ModifiedDuringProcessing(_)));
+        assert!(matches!(snapshot.explain_error(&path, Error::Interrupted), Error::Interrupted));
+        Ok(())
+    }
+
+    #[test]
+    fn recent_modifications_are_detected() {
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let snapshot = FileSnapshot { len: 0, modified: mtime };
+        let period = Duration::from_secs(30);
+        assert!(snapshot.is_recent(period, mtime + Duration::from_secs(29)));
+        assert!(!snapshot.is_recent(period, mtime + Duration::from_secs(30)));
+        assert!(snapshot.is_recent(period, mtime - Duration::from_secs(1)));
+        assert!(!snapshot.is_recent(Duration::ZERO, mtime));
+    }
+}
@@ -0,0 +1,200 @@
+use std::fmt::Write as _;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// An incremental digest of a stream of bytes
+pub trait StreamDigest: Send {
+    /// Adds `data` to the digest
+    fn update(&mut self, data: &[u8]);
+
+    /// Returns the digest of all data added, as lowercase hexadecimal
+    fn finalize_hex(self: Box<Self>) -> String;
+}
+
+#[cfg(feature = "sha2")]
+impl StreamDigest for sha2::Sha256 {
+    fn update(&mut self, data: &[u8]) { sha2::Digest::update(self, data) }
+
+    fn finalize_hex(self: Box<Self>) -> String { to_hex(&sha2::Digest::finalize(*self)) }
+}
+
+/// Formats bytes as lowercase hexadecimal
+#[cfg_attr(not(feature = "sha2"), allow(dead_code))]
+fn to_hex(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(result, "{:02x}", byte).expect("Writing to a String should not fail");
+    }
+    result
+}
+
+/// Algorithms with which file contents can be hashed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    /// SHA-256, which requires the `sha2` feature
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    /// Whether support for the algorithm was enabled when zoog was built
+    #[must_use]
+    pub fn is_available(self) -> bool { self.new_digest().is_some() }
+
+    /// Creates an empty digest, or returns `None` if support for the algorithm
+    /// was not enabled when zoog was built
+    #[must_use]
+    pub fn new_digest(self) -> Option<Box<dyn StreamDigest>> {
+        match self {
+            #[cfg(feature = "sha2")]
+            DigestAlgorithm::Sha256 => Some(Box::<sha2::Sha256>::default()),
+            #[cfg(not(feature = "sha2"))]
+            DigestAlgorithm::Sha256 => None,
+        }
+    }
+}
+
+/// Wraps a reader, computing a digest of all data read through it. Without a
+/// digest it simply forwards reads.
+///
+/// The reader may also be seeked. The digest then covers the data from the
+/// start of the input up to the furthest point read without skipping any
+/// bytes, and data which is read again is not hashed twice.
+pub struct HashingReader<R> {
+    inner: R,
+    digest: Option<Box<dyn StreamDigest>>,
+    position: u64,
+    hashed: u64,
+}
+
+impl<R> HashingReader<R> {
+    /// Wraps `inner`, which must be positioned at its start, hashing data read
+    /// with `digest` if supplied
+    #[must_use]
+    pub fn new(inner: R, digest: Option<Box<dyn StreamDigest>>) -> HashingReader<R> {
+        HashingReader { inner, digest, position: 0, hashed: 0 }
+    }
+
+    /// Returns the digest of all data read, if hashing
+    #[must_use]
+    pub fn finish(self) -> Option<String> { self.digest.map(StreamDigest::finalize_hex) }
+}
+
+impl<R: Read + Seek> HashingReader<R> {
+    /// Reads any data not yet hashed through to the end of the input so that
+    /// the digest covers all of it. Does nothing when not hashing.
+    pub fn read_remaining(&mut self) -> io::Result<()> {
+        if self.digest.is_some() {
+            self.seek(SeekFrom::Start(self.hashed))?;
+            io::copy(self, &mut io::sink())?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        let end = self.position + len as u64;
+        if let Some(digest) = &mut self.digest {
+            if (self.position..end).contains(&self.hashed) {
+                let skip = usize::try_from(self.hashed - self.position).expect("Offset should be within the buffer");
+                digest.update(&buf[skip..len]);
+                self.hashed = end;
+            }
+        }
+        self.position = end;
+        Ok(len)
+    }
+}
+
+impl<R: Seek> Seek for HashingReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = self.inner.seek(pos)?;
+        Ok(self.position)
+    }
+}
+
+/// Wraps a writer, computing a digest of all data written through it. Without
+/// a digest it simply forwards writes.
+pub struct HashingWriter<W> {
+    inner: W,
+    digest: Option<Box<dyn StreamDigest>>,
+}
+
+impl<W> HashingWriter<W> {
+    /// Wraps `inner`, hashing data written with `digest` if supplied
+    #[must_use]
+    pub fn new(inner: W, digest: Option<Box<dyn StreamDigest>>) -> HashingWriter<W> { HashingWriter { inner, digest } }
+
+    /// Returns the digest of all data written, if hashing
+    #[must_use]
+    pub fn finish(self) -> Option<String> { self.digest.map(StreamDigest::finalize_hex) }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        if let Some(digest) = &mut self.digest {
+            digest.update(&buf[..len]);
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_is_lowercase_and_padded() {
+        assert_eq!(to_hex(&[0x00, 0x0f, 0xab, 0xff]), "000fabff");
+    }
+
+    #[test]
+    fn adapters_forward_data_without_digest() {
+        let mut reader = HashingReader::new(&b"data"[..], None);
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(reader.finish(), None);
+
+        let mut writer = HashingWriter::new(Vec::new(), None);
+        writer.write_all(&data).unwrap();
+        assert_eq!(writer.inner, b"data");
+        assert_eq!(writer.finish(), None);
+    }
+
+    #[cfg(feature = "sha2")]
+    #[test]
+    fn adapters_compute_sha256() {
+        const EMPTY: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let digest = || DigestAlgorithm::Sha256.new_digest();
+        assert_eq!(HashingReader::new(io::empty(), digest()).finish().as_deref(), Some(EMPTY));
+
+        // Data is hashed however it is split between calls
+        let mut reader = HashingReader::new(&b"abc"[..], digest());
+        let mut byte = [0u8; 1];
+        while reader.read(&mut byte).unwrap() != 0 {}
+        assert_eq!(reader.finish().as_deref(), Some(ABC));
+
+        let mut writer = HashingWriter::new(io::sink(), digest());
+        writer.write_all(b"ab").unwrap();
+        writer.write_all(b"c").unwrap();
+        assert_eq!(writer.finish().as_deref(), Some(ABC));
+    }
+
+    #[cfg(feature = "sha2")]
+    #[test]
+    fn seeking_hashes_each_byte_once() {
+        const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let mut reader = HashingReader::new(io::Cursor::new(b"abc"), DigestAlgorithm::Sha256.new_digest());
+        let mut bytes = [0u8; 2];
+        reader.read_exact(&mut bytes).unwrap();
+        reader.seek(SeekFrom::Start(1)).unwrap();
+        reader.read_exact(&mut bytes[..1]).unwrap();
+        reader.seek(SeekFrom::End(0)).unwrap();
+        reader.read_remaining().unwrap();
+        assert_eq!(reader.finish().as_deref(), Some(ABC));
+    }
+}
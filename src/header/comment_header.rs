@@ -7,7 +7,8 @@ use crate::Error;
 pub trait CommentHeader: CommentList {
     /// Attempts to parse the supplied slice as a comment header. An error
     /// is returned if the header is believed to be corrupt, otherwise the
-    /// parsed header is returned.
+    /// parsed header is returned. Headers declaring more comments than
+    /// `DEFAULT_MAX_COMMENTS` are rejected, as described by `ParseOptions`.
     fn try_parse(data: &[u8]) -> Result<Self, Error>
     where
         Self: Sized;
@@ -23,4 +24,9 @@ pub trait CommentHeader: CommentList {
 
     /// Writes the serialized header
     fn serialize_into<W: Write>(&self, writer: &mut W) -> Result<(), Error>;
+
+    /// Has the header been modified since it was parsed? This may report
+    /// modifications which left the content unchanged, such as replacing a
+    /// comment with an identical value.
+    fn is_dirty(&self) -> bool;
 }
@@ -1,4 +1,5 @@
 use std::borrow::Cow;
+use std::cmp::Reverse;
 use std::io::{Cursor, Read, Write};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
@@ -7,6 +8,53 @@ use derivative::Derivative;
 use crate::header::{parse_comment, CommentList, DiscreteCommentList};
 use crate::{header, Error, FIELD_NAME_TERMINATOR};
 
+/// Size of each of the little-endian 32-bit length fields in the header
+const LENGTH_FIELD_SIZE: usize = 4;
+
+/// Converts a length to its 32-bit representation in the header, failing if it
+/// is too large to be represented
+fn encoded_length(len: usize) -> Result<u32, Error> {
+    len.try_into().map_err(|_| Error::UnrepresentableValueInCommentHeader)
+}
+
+/// The maximum number of comments accepted when parsing a comment header with
+/// the default `ParseOptions`
+pub const DEFAULT_MAX_COMMENTS: usize = 65536;
+
+/// Limits applied when parsing comment headers.
+///
+/// Each parsed comment occupies far more memory than the few bytes it may take
+/// in the header, so a hostile header declaring millions of empty comments
+/// could otherwise exhaust memory. Headers declaring more comments than
+/// `max_comments` are rejected with `Error::TooManyComments` before any are
+/// read. The default limit of `DEFAULT_MAX_COMMENTS` is far beyond what real
+/// files contain.
+///
+/// ```
+/// use zoog::header::ParseOptions;
+/// use zoog::opus::CommentHeader;
+///
+/// let mut header = b"OpusTags".to_vec();
+/// header.extend(0u32.to_le_bytes()); // Vendor string length
+/// header.extend(3u32.to_le_bytes()); // Comment count
+/// for _ in 0..3 {
+///     header.extend(1u32.to_le_bytes());
+///     header.push(b'=');
+/// }
+/// let options = ParseOptions { max_comments: 2 };
+/// assert!(CommentHeader::try_parse_with_options(&header, &options).is_err());
+/// assert!(CommentHeader::try_parse_with_options(&header, &ParseOptions::default()).is_ok());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// The maximum number of comments accepted
+    pub max_comments: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions { ParseOptions { max_comments: DEFAULT_MAX_COMMENTS } }
+}
+
 /// Implementation-specific details of comment headers (Opus versus Vorbis)
 pub trait CommentHeaderSpecifics {
     /// Return the magic signature which should be present at the start of the
@@ -23,56 +71,54 @@ pub trait CommentHeaderSpecifics {
 /// Allows querying and modification of an Opus/Vorbis comment header. This type
 /// is parameterized by a type implementing `CommentHeaderSpecifics` which
 /// encodes format-specific logic.
+///
+/// A header can be parsed, edited and serialized again:
+///
+/// ```
+/// use zoog::header::{CommentHeader as _, CommentList as _};
+/// use zoog::opus::CommentHeader;
+///
+/// let mut header = CommentHeader::default();
+/// header.set_vendor("example");
+/// header.push("ARTIST", "Someone")?;
+/// header.push("TITLE", "Old title")?;
+/// let mut data = Vec::new();
+/// header.serialize_into(&mut data)?;
+///
+/// let mut parsed = CommentHeader::try_parse(&data)?;
+/// assert_eq!(parsed.get_vendor(), "example");
+/// parsed.replace("title", "New title")?;
+/// parsed.remove_all("ARTIST");
+/// assert!(parsed.is_dirty());
+/// assert_eq!(parsed.len(), 1);
+/// assert_eq!(parsed.get_first("TITLE"), Some("New title"));
+/// # Ok::<(), zoog::Error>(())
+/// ```
 #[derive(Derivative)]
 #[derivative(Clone, Debug, Default, PartialEq)]
 pub struct CommentHeaderGeneric<S> {
     vendor: String,
     user_comments: DiscreteCommentList,
     specifics: S,
+
+    #[derivative(PartialEq = "ignore")]
+    vendor_dirty: bool,
 }
 
 impl<S: CommentHeaderSpecifics + Default> header::CommentHeader for CommentHeaderGeneric<S> {
     fn try_parse(data: &[u8]) -> Result<CommentHeaderGeneric<S>, Error> {
-        let magic = S::get_magic();
-        let identical = data.iter().take(magic.len()).eq(magic.iter());
-        if !identical {
-            return Err(Error::MalformedCommentHeader);
-        }
-        let mut reader = Cursor::new(&data[magic.len()..]);
-        let vendor_len = Self::read_length(&mut reader)?;
-        let mut vendor = vec![0u8; vendor_len as usize];
-        Self::read_exact(&mut reader, &mut vendor)?;
-        let vendor = String::from_utf8(vendor)?;
-        let num_comments = Self::read_length(&mut reader)?;
-        let mut user_comments = DiscreteCommentList::with_capacity(num_comments as usize);
-        for _ in 0..num_comments {
-            let comment_len = Self::read_length(&mut reader)?;
-            let mut comment = vec![0u8; comment_len as usize];
-            Self::read_exact(&mut reader, &mut comment)?;
-            let comment = String::from_utf8(comment)?;
-            let (key, value) = parse_comment(&comment)?;
-            user_comments.push(key, value)?;
-        }
-        let mut specifics = S::default();
-        specifics.read_suffix(&mut reader)?;
-        let result = CommentHeaderGeneric { vendor, user_comments, specifics };
-        Ok(result)
+        Self::try_parse_with_options(data, &ParseOptions::default())
     }
 
     fn serialize_into<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
         writer.write_all(&S::get_magic()).map_err(Error::WriteError)?;
         let vendor = self.vendor.as_bytes();
-        let vendor_len = vendor.len().try_into().map_err(|_| Error::UnrepresentableValueInCommentHeader)?;
-        writer.write_u32::<LittleEndian>(vendor_len).map_err(Error::WriteError)?;
+        writer.write_u32::<LittleEndian>(encoded_length(vendor.len())?).map_err(Error::WriteError)?;
         writer.write_all(vendor).map_err(Error::WriteError)?;
-        let user_comments_len =
-            self.user_comments.len().try_into().map_err(|_| Error::UnrepresentableValueInCommentHeader)?;
-        writer.write_u32::<LittleEndian>(user_comments_len).map_err(Error::WriteError)?;
+        writer.write_u32::<LittleEndian>(encoded_length(self.user_comments.len())?).map_err(Error::WriteError)?;
         let field_name_terminator = [FIELD_NAME_TERMINATOR];
         for (k, v) in self.user_comments.iter().map(|(k, v)| (k.as_bytes(), v.as_bytes())) {
-            let comment_len = k.len() + v.len() + 1;
-            let comment_len = comment_len.try_into().map_err(|_| Error::UnrepresentableValueInCommentHeader)?;
-            writer.write_u32::<LittleEndian>(comment_len).map_err(Error::WriteError)?;
+            writer.write_u32::<LittleEndian>(encoded_length(k.len() + v.len() + 1)?).map_err(Error::WriteError)?;
             writer.write_all(k).map_err(Error::WriteError)?;
             writer.write_all(&field_name_terminator).map_err(Error::WriteError)?;
             writer.write_all(v).map_err(Error::WriteError)?;
@@ -81,14 +127,98 @@ impl<S: CommentHeaderSpecifics + Default> header::CommentHeader for CommentHeade
         Ok(())
     }
 
-    fn set_vendor(&mut self, vendor: &str) { self.vendor = vendor.into(); }
+    fn set_vendor(&mut self, vendor: &str) {
+        self.vendor = vendor.into();
+        self.vendor_dirty = true;
+    }
 
     fn get_vendor(&self) -> &str { self.vendor.as_str() }
 
     fn to_discrete_comment_list(&self) -> DiscreteCommentList { self.user_comments.clone() }
+
+    fn is_dirty(&self) -> bool { self.vendor_dirty || self.user_comments.is_dirty() }
+}
+
+impl<S: CommentHeaderSpecifics + Default> CommentHeaderGeneric<S> {
+    /// Parses the supplied slice as a comment header as `try_parse` does, but
+    /// with the limits in `options` rather than the defaults
+    pub fn try_parse_with_options(data: &[u8], options: &ParseOptions) -> Result<CommentHeaderGeneric<S>, Error> {
+        let magic = S::get_magic();
+        let identical = data.iter().take(magic.len()).eq(magic.iter());
+        if !identical {
+            return Err(Error::MalformedCommentHeader);
+        }
+        let mut reader = Cursor::new(&data[magic.len()..]);
+        let vendor = String::from_utf8(Self::read_field(&mut reader)?)?;
+        let num_comments = Self::read_length(&mut reader)?;
+        if usize::try_from(num_comments).map_or(true, |num_comments| num_comments > options.max_comments) {
+            return Err(Error::TooManyComments(num_comments, options.max_comments));
+        }
+        let mut user_comments = DiscreteCommentList::with_capacity(num_comments as usize);
+        for _ in 0..num_comments {
+            let comment = String::from_utf8(Self::read_field(&mut reader)?)?;
+            let (key, value) = parse_comment(&comment)?;
+            user_comments.push(key, value)?;
+        }
+        user_comments.mark_clean();
+        let mut specifics = S::default();
+        specifics.read_suffix(&mut reader)?;
+        let result = CommentHeaderGeneric { vendor, user_comments, specifics, vendor_dirty: false };
+        Ok(result)
+    }
+}
+
+/// The sizes in bytes of the parts of a serialized comment header
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommentHeaderSizes {
+    /// The length of the vendor string
+    pub vendor: usize,
+
+    /// The key and serialized length (key, separator and value) of each
+    /// comment, in header order
+    pub comments: Vec<(String, usize)>,
+
+    /// The length of the data following the comments, including any padding
+    pub suffix: usize,
+
+    /// The length of the whole header packet
+    pub total: usize,
+}
+
+impl CommentHeaderSizes {
+    /// The combined serialized length of all comments
+    #[must_use]
+    pub fn comments_total(&self) -> usize { self.comments.iter().map(|(_, size)| size).sum() }
+
+    /// The key and size of each comment in order of decreasing size. Comments
+    /// of equal size remain in header order.
+    #[must_use]
+    pub fn comments_by_size(&self) -> Vec<(&str, usize)> {
+        let mut comments: Vec<_> = self.comments.iter().map(|(key, size)| (key.as_str(), *size)).collect();
+        comments.sort_by_key(|(_, size)| Reverse(*size));
+        comments
+    }
+}
+
+impl<S: CommentHeaderSpecifics> CommentHeaderGeneric<S> {
+    /// Computes the sizes of the parts of this header, given the length of the
+    /// packet it was parsed from. Since padding is discarded on parsing, the
+    /// suffix size is whatever remains of the packet after the comments.
+    #[must_use]
+    pub fn sizes(&self, packet_len: usize) -> CommentHeaderSizes {
+        let comments: Vec<_> = self.user_comments.iter().map(|(k, v)| (k.to_string(), k.len() + 1 + v.len())).collect();
+        let length_fields = LENGTH_FIELD_SIZE * (comments.len() + 2);
+        let mut result = CommentHeaderSizes { vendor: self.vendor.len(), comments, suffix: 0, total: packet_len };
+        let accounted = S::get_magic().len() + length_fields + result.vendor + result.comments_total();
+        result.suffix = packet_len.saturating_sub(accounted);
+        result
+    }
 }
 
 impl<S> CommentHeaderGeneric<S> {
+    /// The format-specific state of the header
+    pub(crate) fn specifics(&self) -> &S { &self.specifics }
+
     fn read_length<R: Read>(mut reader: R) -> Result<u32, Error> {
         reader.read_u32::<LittleEndian>().map_err(|_| Error::MalformedCommentHeader)
     }
@@ -96,10 +226,26 @@ impl<S> CommentHeaderGeneric<S> {
     fn read_exact<R: Read>(mut reader: R, data: &mut [u8]) -> Result<(), Error> {
         reader.read_exact(data).map_err(|_| Error::MalformedCommentHeader)
     }
+
+    /// Reads a length-prefixed field. A declared length exceeding the data
+    /// remaining is rejected before any space is allocated for the field.
+    fn read_field(reader: &mut Cursor<&[u8]>) -> Result<Vec<u8>, Error> {
+        let len = Self::read_length(&mut *reader)?;
+        let remaining = (reader.get_ref().len() as u64).saturating_sub(reader.position());
+        if u64::from(len) > remaining {
+            return Err(Error::MalformedCommentHeader);
+        }
+        let mut data = vec![0u8; len as usize];
+        Self::read_exact(reader, &mut data)?;
+        Ok(data)
+    }
 }
 
 impl<S: CommentHeaderSpecifics> CommentList for CommentHeaderGeneric<S> {
-    type Iter<'b> = <DiscreteCommentList as CommentList>::Iter<'b> where Self: 'b;
+    type Iter<'b>
+        = <DiscreteCommentList as CommentList>::Iter<'b>
+    where
+        Self: 'b;
 
     fn len(&self) -> usize { self.user_comments.len() }
 
@@ -189,4 +335,124 @@ mod tests {
             _ => panic!("Wrong error for malformed header"),
         };
     }
+
+    /// A header declaring `declared` empty comments but containing only
+    /// `present` of them
+    fn empty_comments_header(declared: u32, present: usize) -> Vec<u8> {
+        let mut header = TEST_MAGIC.to_vec();
+        header.extend(0u32.to_le_bytes());
+        header.extend(declared.to_le_bytes());
+        for _ in 0..present {
+            header.extend(1u32.to_le_bytes());
+            header.push(FIELD_NAME_TERMINATOR);
+        }
+        header.extend(TEST_SUFFIX);
+        header
+    }
+
+    #[test]
+    fn comment_count_is_limited() -> Result<(), Error> {
+        // Rejected from the declared count alone, before the comments are read
+        // or any space is reserved for them
+        for declared in [u32::try_from(DEFAULT_MAX_COMMENTS).unwrap() + 1, u32::MAX - 1, u32::MAX] {
+            match CommentHeaderTest::try_parse(&empty_comments_header(declared, 0)) {
+                Err(Error::TooManyComments(count, DEFAULT_MAX_COMMENTS)) => assert_eq!(count, declared),
+                other => panic!("Unexpected result: {:?}", other),
+            }
+        }
+        let header = CommentHeaderTest::try_parse(&empty_comments_header(1000, 1000))?;
+        assert_eq!(header.len(), 1000);
+
+        let options = ParseOptions { max_comments: 999 };
+        let result = CommentHeaderTest::try_parse_with_options(&empty_comments_header(1000, 1000), &options);
+        assert!(matches!(result, Err(Error::TooManyComments(1000, 999))));
+        let options = ParseOptions { max_comments: 1000 };
+        assert!(CommentHeaderTest::try_parse_with_options(&empty_comments_header(1000, 1000), &options).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn minimal_fields_round_trip() -> Result<(), Error> {
+        let mut header = CommentHeaderTest::default();
+        header.push("K", "")?;
+        header.push("TITLE", "")?;
+        header.push("A", "b")?;
+        let data = comment_header_as_vec(&header)?;
+
+        let mut expected = TEST_MAGIC.to_vec();
+        expected.extend(0u32.to_le_bytes()); // Empty vendor string
+        expected.extend(3u32.to_le_bytes());
+        for comment in [&b"K="[..], b"TITLE=", b"A=b"] {
+            expected.extend(u32::try_from(comment.len()).unwrap().to_le_bytes());
+            expected.extend(comment);
+        }
+        expected.extend(TEST_SUFFIX);
+        assert_eq!(data, expected);
+
+        let parsed = CommentHeaderTest::try_parse(&data)?;
+        assert_eq!(parsed.get_vendor(), "");
+        assert_eq!(parsed.iter().collect::<Vec<_>>(), [("K", ""), ("TITLE", ""), ("A", "b")]);
+        assert_eq!(comment_header_as_vec(&parsed)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn overlong_declared_lengths_are_rejected() {
+        // Rejected from the declared length alone, before space is allocated
+        // for the field
+        for declared in [3, 1 << 20, u32::MAX] {
+            let mut header = TEST_MAGIC.to_vec();
+            header.extend(0u32.to_le_bytes());
+            header.extend(1u32.to_le_bytes());
+            header.extend(declared.to_le_bytes());
+            header.extend(b"K=");
+            let result = CommentHeaderTest::try_parse(&header);
+            assert!(matches!(result, Err(Error::MalformedCommentHeader)), "Declared length {}", declared);
+
+            let mut header = TEST_MAGIC.to_vec();
+            header.extend(declared.to_le_bytes());
+            header.extend(b"zo");
+            let result = CommentHeaderTest::try_parse(&header);
+            assert!(matches!(result, Err(Error::MalformedCommentHeader)), "Declared vendor length {}", declared);
+        }
+    }
+
+    #[test]
+    fn parsed_header_is_clean() -> Result<(), Error> {
+        let mut rng = SmallRng::seed_from_u64(7621);
+        let header: CommentHeaderTest = create_random_header(&mut rng);
+        let mut header = CommentHeaderTest::try_parse(&comment_header_as_vec(&header)?)?;
+        assert!(!header.is_dirty());
+        header.retain(|_, _| true);
+        assert!(!header.is_dirty());
+        header.set_vendor("zoog");
+        assert!(header.is_dirty());
+        Ok(())
+    }
+
+    #[test]
+    fn sizes_account_for_whole_packet() -> Result<(), Error> {
+        let mut header = CommentHeaderTest::default();
+        header.set_vendor("zoog");
+        header.push("TITLE", "Song")?;
+        header.push("ARTIST", "Ünïcödé")?;
+        let data = comment_header_as_vec(&header)?;
+        let sizes = CommentHeaderTest::try_parse(&data)?.sizes(data.len());
+        assert_eq!(sizes.vendor, 4);
+        assert_eq!(sizes.comments, vec![("TITLE".to_string(), 10), ("ARTIST".to_string(), 7 + "Ünïcödé".len())]);
+        assert_eq!(sizes.suffix, TEST_SUFFIX.len());
+        assert_eq!(sizes.total, data.len());
+        Ok(())
+    }
+
+    #[test]
+    fn overlong_lengths_are_errors() {
+        assert_eq!(encoded_length(0).unwrap(), 0);
+        assert_eq!(encoded_length(usize::try_from(u32::MAX).unwrap()).unwrap(), u32::MAX);
+        // A header this large cannot be constructed in a test, so the length
+        // conversion is checked directly
+        if let Ok(len) = usize::try_from(u64::from(u32::MAX) + 1) {
+            assert!(matches!(encoded_length(len), Err(Error::UnrepresentableValueInCommentHeader)));
+        }
+    }
 }
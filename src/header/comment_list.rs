@@ -39,7 +39,22 @@ pub trait CommentList {
     /// Retain only the key value mappings for which the predicate returns true
     fn retain<F: FnMut(&str, &str) -> bool>(&mut self, f: F);
 
-    /// Write each comment in the user-friendly textual representation
+    /// Removes all mappings whose key begins with `prefix`, ignoring case,
+    /// returning the number removed
+    fn remove_by_prefix(&mut self, prefix: &str) -> usize {
+        let mut removed = 0;
+        self.retain(|k, _| {
+            let matches = key_has_prefix(k, prefix);
+            removed += usize::from(matches);
+            !matches
+        });
+        removed
+    }
+
+    /// Write each comment in the user-friendly textual representation. A
+    /// comment with an empty value is written as `NAME=`, whether or not
+    /// values are escaped, so it is distinct from a line missing its
+    /// separator.
     fn write_as_text<W: Write>(&self, mut writer: W, escape: bool) -> Result<(), io::Error> {
         for (k, v) in self.iter() {
             let v = if escape { escaping::escape_str(v) } else { Cow::from(v) };
@@ -48,6 +63,21 @@ pub trait CommentList {
         Ok(())
     }
 
+    /// Write each comment as `NAME=VALUE` terminated by a NUL byte, without
+    /// escaping. An error of kind `InvalidData` is returned before anything is
+    /// written if a value contains a NUL character, since the comments could
+    /// not then be read back.
+    fn write_null_terminated<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        if let Some((k, _)) = self.iter().find(|(_, v)| v.contains('\0')) {
+            let message = format!("Value of comment {} contains a NUL character", k);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        for (k, v) in self.iter() {
+            write!(writer, "{}{}{}\0", k, FIELD_NAME_TERMINATOR as char, v)?;
+        }
+        Ok(())
+    }
+
     /// Extend with mappings from supplied iterator
     fn extend<K, V, I>(&mut self, comments: I) -> Result<(), Error>
     where
@@ -65,9 +95,11 @@ pub trait CommentList {
 
     /// Attempts to parse the first mapping for the specified key as the
     /// fixed-point Decibel representation used in Opus comment headers.
+    /// Non-canonical representations accepted by
+    /// `FixedPointGain::parse_lenient` are tolerated.
     fn get_gain_from_tag(&self, tag: &str) -> Result<Option<FixedPointGain>, Error> {
         let parsed =
-            self.get_first(tag).map(|v| v.parse::<FixedPointGain>().map_err(|_| Error::InvalidR128Tag(v.into())));
+            self.get_first(tag).map(|v| FixedPointGain::parse_lenient(v).map_err(|_| Error::InvalidR128Tag(v.into())));
         match parsed {
             Some(Ok(v)) => Ok(Some(v)),
             Some(Err(e)) => Err(e),
@@ -77,12 +109,27 @@ pub trait CommentList {
 
     /// Sets the specified tag to the supplied gain using the fixed-point
     /// representation used in Ogg Opus comment headers. All other mappings
-    /// for the same tag will be removed.
+    /// for the same tag will be removed. The tag is always written with the
+    /// spelling of `tag`, even if existing mappings differ from it in case.
     fn set_tag_to_gain(&mut self, tag: &str, gain: FixedPointGain) -> Result<(), Error> {
-        self.replace(tag, &format!("{}", gain.as_fixed_point()))
+        let value = format!("{}", gain.as_fixed_point());
+        // `replace` keeps the name of the mapping it updates, so any spelled
+        // differently are removed and the tag appended instead
+        if self.iter().any(|(k, _)| k != tag && k.eq_ignore_ascii_case(tag)) {
+            self.remove_all(tag);
+            self.push(tag, &value)
+        } else {
+            self.replace(tag, &value)
+        }
     }
 }
 
+/// Does the comment key begin with `prefix`, ignoring case?
+#[must_use]
+pub fn key_has_prefix(key: &str, prefix: &str) -> bool {
+    key.get(..prefix.len()).is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
 /// Parses the textual representation of an Opus comment
 pub fn parse_comment(comment: &str) -> Result<(&str, &str), Error> {
     let offset = comment.find(char::from(FIELD_NAME_TERMINATOR)).ok_or(Error::MissingCommentSeparator)?;
@@ -101,3 +148,109 @@ pub fn validate_comment_field_name(field_name: &str) -> Result<(), Error> {
     }
     Ok(())
 }
+
+/// Validates that a comment field name is portable, meaning it consists only of
+/// ASCII letters, digits and underscores. This is stricter than the
+/// specification, but names outside this set are rejected by some other tools.
+pub fn validate_portable_comment_field_name(field_name: &str) -> Result<(), Error> {
+    validate_comment_field_name(field_name)?;
+    let portable = !field_name.is_empty() && field_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if portable {
+        Ok(())
+    } else {
+        Err(Error::NonPortableCommentFieldName(field_name.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::DiscreteCommentList;
+    use crate::PRIVATE_TAG_PREFIX;
+
+    #[test]
+    fn portable_field_name_boundaries() {
+        for name in ["A", "Z", "a", "z", "0", "9", "_", "REPLAYGAIN_TRACK_GAIN"] {
+            assert!(validate_portable_comment_field_name(name).is_ok(), "{} should be portable", name);
+        }
+        // Each is accepted by the standard validator
+        for name in ["", " ", "MY TAG", "}", "/", "@", "[", "`", "{", "<", ">", "-"] {
+            assert!(validate_comment_field_name(name).is_ok());
+            assert!(
+                matches!(validate_portable_comment_field_name(name), Err(Error::NonPortableCommentFieldName(_))),
+                "{:?} should not be portable",
+                name
+            );
+        }
+        assert!(matches!(validate_portable_comment_field_name("A=B"), Err(Error::InvalidOpusCommentFieldName(_))));
+    }
+
+    #[test]
+    fn null_terminated_output() {
+        let mut comments = DiscreteCommentList::default();
+        comments.push("TITLE", "Two\nlines").unwrap();
+        comments.push("PATH", "C:\\music\\a=b").unwrap();
+        let mut output = Vec::new();
+        comments.write_null_terminated(&mut output).unwrap();
+        assert_eq!(output, b"TITLE=Two\nlines\0PATH=C:\\music\\a=b\0");
+
+        comments.push("BAD", "a\0b").unwrap();
+        let mut output = Vec::new();
+        let result = comments.write_null_terminated(&mut output);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn empty_values_as_text() -> Result<(), Error> {
+        let mut comments = DiscreteCommentList::default();
+        comments.extend([("TITLE", ""), ("A", "b"), ("K", "")])?;
+        for escape in [false, true] {
+            let mut output = Vec::new();
+            comments.write_as_text(&mut output, escape).unwrap();
+            assert_eq!(output, b"TITLE=\nA=b\nK=\n");
+        }
+        assert_eq!(parse_comment("TITLE=")?, ("TITLE", ""));
+        assert_eq!(parse_comment("K=")?, ("K", ""));
+        assert!(matches!(parse_comment("TITLE"), Err(Error::MissingCommentSeparator)));
+        Ok(())
+    }
+
+    #[test]
+    fn removal_by_prefix_ignores_case() -> Result<(), Error> {
+        let mut comments = DiscreteCommentList::default();
+        comments.extend([
+            ("ZOOG_LOUDNESS", "-18"),
+            ("TITLE", "Title"),
+            ("zoog_undo", "data"),
+            ("NOT_ZOOG_TAG", "kept"),
+            ("ZOOGLE", "kept"),
+            ("ZOOG_", "empty suffix"),
+        ])?;
+        assert_eq!(comments.remove_by_prefix(PRIVATE_TAG_PREFIX), 3);
+        let expected = [("TITLE", "Title"), ("NOT_ZOOG_TAG", "kept"), ("ZOOGLE", "kept")];
+        assert_eq!(comments.iter().collect::<Vec<_>>(), expected);
+
+        // Removal is idempotent
+        assert_eq!(comments.remove_by_prefix(PRIVATE_TAG_PREFIX), 0);
+        assert_eq!(comments.iter().collect::<Vec<_>>(), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn gain_tags_are_written_with_canonical_spelling() -> Result<(), Error> {
+        let gain = FixedPointGain::try_from(crate::Decibels::from(-2.0))?;
+        for existing in [&["R128_TRACK_GAIN"][..], &["r128_track_gain"], &["R128_Track_Gain", "R128_TRACK_GAIN"]] {
+            let mut comments = DiscreteCommentList::default();
+            comments.push("TITLE", "Title")?;
+            for name in existing {
+                comments.push(name, "0")?;
+            }
+            comments.set_tag_to_gain("R128_TRACK_GAIN", gain)?;
+            let mut expected = DiscreteCommentList::default();
+            expected.extend([("TITLE", "Title"), ("R128_TRACK_GAIN", "-512")])?;
+            assert_eq!(comments.iter().collect::<Vec<_>>(), expected.iter().collect::<Vec<_>>(), "{:?}", existing);
+        }
+        Ok(())
+    }
+}
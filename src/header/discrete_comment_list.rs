@@ -1,12 +1,19 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
+use derivative::Derivative;
+
 use crate::header::{validate_comment_field_name, CommentList};
 use crate::Error;
 
 /// Stand-alone representation of an Ogg Opus comment list
-#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(Derivative)]
+#[derivative(Clone, Debug, Default, PartialEq)]
 pub struct DiscreteCommentList {
     comments: Vec<(Arc<String>, Arc<String>)>,
+
+    #[derivative(PartialEq = "ignore")]
+    dirty: bool,
 }
 
 impl DiscreteCommentList {
@@ -15,11 +22,117 @@ impl DiscreteCommentList {
     /// Allocates a list with the specified capacity
     #[must_use]
     pub fn with_capacity(cap: usize) -> DiscreteCommentList {
-        DiscreteCommentList { comments: Vec::with_capacity(cap) }
+        DiscreteCommentList { comments: Vec::with_capacity(cap), dirty: false }
     }
 
     /// Appends all comments from the other list, leaving it empty
-    pub fn append(&mut self, other: &mut DiscreteCommentList) { self.comments.append(&mut other.comments); }
+    pub fn append(&mut self, other: &mut DiscreteCommentList) {
+        if !other.comments.is_empty() {
+            self.dirty = true;
+            other.dirty = true;
+        }
+        self.comments.append(&mut other.comments);
+    }
+
+    /// Has the list been modified since it was created or last marked clean?
+    #[must_use]
+    pub fn is_dirty(&self) -> bool { self.dirty }
+
+    /// Marks the list as unmodified
+    pub(crate) fn mark_clean(&mut self) { self.dirty = false; }
+
+    /// Returns the comments added and removed in going from this list to
+    /// `other`.
+    ///
+    /// Comments are compared as a multiset, so reordering comments is not a
+    /// change, and a comment present twice in one list but once in the other
+    /// has one occurrence added or removed. As elsewhere, keys are compared
+    /// case-insensitively, so a key which only changes case is unchanged. A
+    /// changed value is reported as the removal of the old comment and the
+    /// addition of the new one.
+    ///
+    /// ```
+    /// use zoog::header::{CommentList, DiscreteCommentList};
+    ///
+    /// let mut old = DiscreteCommentList::default();
+    /// old.extend([("ARTIST", "Someone"), ("TITLE", "Old")])?;
+    /// let mut new = DiscreteCommentList::default();
+    /// new.extend([("TITLE", "New"), ("artist", "Someone")])?;
+    /// let diff = old.diff(&new);
+    /// assert_eq!(diff.removed, [("TITLE".to_string(), "Old".to_string())]);
+    /// assert_eq!(diff.added, [("TITLE".to_string(), "New".to_string())]);
+    /// assert_eq!(diff.unchanged, 1);
+    /// # Ok::<(), zoog::Error>(())
+    /// ```
+    #[must_use]
+    pub fn diff(&self, other: &DiscreteCommentList) -> CommentDiff {
+        fn normalize((k, v): &(Arc<String>, Arc<String>)) -> (String, &str) { (k.to_ascii_uppercase(), v.as_str()) }
+
+        let mut remaining: HashMap<_, usize> = HashMap::new();
+        for comment in &self.comments {
+            *remaining.entry(normalize(comment)).or_default() += 1;
+        }
+        let mut diff = CommentDiff::default();
+        let mut matched: HashMap<_, usize> = HashMap::new();
+        for comment in &other.comments {
+            match remaining.get_mut(&normalize(comment)).filter(|count| **count > 0) {
+                Some(count) => {
+                    *count -= 1;
+                    *matched.entry(normalize(comment)).or_default() += 1;
+                    diff.unchanged += 1;
+                }
+                None => diff.added.push((comment.0.to_string(), comment.1.to_string())),
+            }
+        }
+        // The earliest occurrences of each comment are those treated as kept
+        for comment in &self.comments {
+            match matched.get_mut(&normalize(comment)).filter(|count| **count > 0) {
+                Some(count) => *count -= 1,
+                None => diff.removed.push((comment.0.to_string(), comment.1.to_string())),
+            }
+        }
+        diff
+    }
+}
+
+/// The differences between two comment lists, as computed by
+/// `DiscreteCommentList::diff`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommentDiff {
+    /// Comments present only in the new list, in its order
+    pub added: Vec<(String, String)>,
+
+    /// Comments present only in the old list, in its order
+    pub removed: Vec<(String, String)>,
+
+    /// The number of comments present in both lists
+    pub unchanged: usize,
+}
+
+impl CommentDiff {
+    /// Were any comments added or removed?
+    #[must_use]
+    pub fn is_empty(&self) -> bool { self.added.is_empty() && self.removed.is_empty() }
+}
+
+/// Serializes as an ordered sequence of key-value pairs so that duplicate keys
+/// are preserved.
+#[cfg(feature = "serde")]
+impl serde::Serialize for DiscreteCommentList {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for DiscreteCommentList {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pairs: Vec<(String, String)> = serde::Deserialize::deserialize(deserializer)?;
+        let mut result = DiscreteCommentList::with_capacity(pairs.len());
+        result.extend(pairs).map_err(serde::de::Error::custom)?;
+        Ok(result)
+    }
 }
 
 mod internal {
@@ -44,15 +157,19 @@ impl CommentList for DiscreteCommentList {
 
     fn is_empty(&self) -> bool { self.comments.is_empty() }
 
-    fn clear(&mut self) { self.comments.clear() }
+    fn clear(&mut self) {
+        self.dirty |= !self.comments.is_empty();
+        self.comments.clear();
+    }
 
     fn get_first(&self, key: &str) -> Option<&str> {
         self.comments.iter().find(|(k, _)| Self::keys_equal(k, key)).map(|(_, v)| v.as_str())
     }
 
-    fn remove_all(&mut self, key: &str) { self.comments.retain(|(k, _)| !Self::keys_equal(key, k)); }
+    fn remove_all(&mut self, key: &str) { self.retain(|k, _| !Self::keys_equal(key, k)); }
 
     fn replace(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        self.dirty = true;
         let mut found = false;
         self.comments.retain_mut(|(k, ref mut v)| {
             if Self::keys_equal(k, key) {
@@ -79,17 +196,34 @@ impl CommentList for DiscreteCommentList {
     fn push(&mut self, key: &str, value: &str) -> Result<(), Error> {
         validate_comment_field_name(key)?;
         self.comments.push((Arc::new(key.into()), Arc::new(value.into())));
+        self.dirty = true;
         Ok(())
     }
 
     fn iter(&self) -> Self::Iter<'_> { Self::Iter { inner: self.comments.iter() } }
 
-    fn retain<F: FnMut(&str, &str) -> bool>(&mut self, mut f: F) { self.comments.retain(|(k, v)| f(k, v)); }
+    fn retain<F: FnMut(&str, &str) -> bool>(&mut self, mut f: F) {
+        let len = self.comments.len();
+        self.comments.retain(|(k, v)| f(k, v));
+        self.dirty |= self.comments.len() != len;
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::header::FixedPointGain;
+
+    #[test]
+    fn gain_from_tag_is_lenient() -> Result<(), Error> {
+        let mut list = DiscreteCommentList::default();
+        list.push("R128_TRACK_GAIN", " +1536 dB ")?;
+        list.push("R128_ALBUM_GAIN", "-6.0 dB")?;
+        assert_eq!(list.get_gain_from_tag("R128_TRACK_GAIN")?, Some(FixedPointGain::from_fixed_point(1536)));
+        assert!(matches!(list.get_gain_from_tag("R128_ALBUM_GAIN"), Err(Error::InvalidR128Tag(_))));
+        assert_eq!(list.get_gain_from_tag("R128_MISSING_GAIN")?, None);
+        Ok(())
+    }
 
     #[test]
     fn replace_pushes_on_missing() -> Result<(), Error> {
@@ -162,6 +296,27 @@ mod tests {
         Ok(())
     }
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip_with_duplicates() -> Result<(), Error> {
+        let mut list = DiscreteCommentList::default();
+        list.push("ARTIST", "a")?;
+        list.push("TITLE", "t")?;
+        list.push("ARTIST", "b")?;
+        let json = serde_json::to_string(&list).unwrap();
+        assert_eq!(json, r#"[["ARTIST","a"],["TITLE","t"],["ARTIST","b"]]"#);
+        let decoded: DiscreteCommentList = serde_json::from_str(&json).unwrap();
+        assert_eq!(list, decoded);
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_rejects_invalid_field_name() {
+        let json = r#"[["IN=VALID","a"]]"#;
+        assert!(serde_json::from_str::<DiscreteCommentList>(json).is_err());
+    }
+
     #[test]
     fn remove_all_case_insensitive() -> Result<(), Error> {
         let mut list_1 = DiscreteCommentList::default();
@@ -179,4 +334,62 @@ mod tests {
         assert_eq!(list_1, list_2);
         Ok(())
     }
+
+    fn list(comments: &[(&str, &str)]) -> DiscreteCommentList {
+        let mut list = DiscreteCommentList::default();
+        list.extend(comments.iter().copied()).unwrap();
+        list
+    }
+
+    fn pairs(comments: &[(&str, &str)]) -> Vec<(String, String)> {
+        comments.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn diff_ignores_order() {
+        let old = list(&[("A", "1"), ("B", "2"), ("C", "3")]);
+        let new = list(&[("C", "3"), ("A", "1"), ("B", "2")]);
+        assert_eq!(old.diff(&new), CommentDiff { unchanged: 3, ..CommentDiff::default() });
+        assert!(old.diff(&new).is_empty());
+    }
+
+    #[test]
+    fn diff_counts_duplicate_values() {
+        let old = list(&[("ARTIST", "A"), ("ARTIST", "A"), ("ARTIST", "B"), ("GENRE", "Rock")]);
+        let new = list(&[("ARTIST", "A"), ("ARTIST", "B"), ("ARTIST", "B"), ("ARTIST", "C")]);
+        let diff = old.diff(&new);
+        assert_eq!(diff.added, pairs(&[("ARTIST", "B"), ("ARTIST", "C")]));
+        assert_eq!(diff.removed, pairs(&[("ARTIST", "A"), ("GENRE", "Rock")]));
+        assert_eq!(diff.unchanged, 2);
+
+        // The reverse diff swaps the additions and removals
+        let reverse = new.diff(&old);
+        assert_eq!(reverse.added, pairs(&[("ARTIST", "A"), ("GENRE", "Rock")]));
+        assert_eq!(reverse.removed, pairs(&[("ARTIST", "B"), ("ARTIST", "C")]));
+        assert_eq!(reverse.unchanged, 2);
+    }
+
+    #[test]
+    fn diff_matches_keys_case_insensitively() {
+        let old = list(&[("title", "Song"), ("Artist", "Someone"), ("ALBUM", "Record")]);
+        let new = list(&[("TITLE", "Song"), ("artist", "Someone Else"), ("album", "record")]);
+        let diff = old.diff(&new);
+        assert_eq!(diff.added, pairs(&[("artist", "Someone Else"), ("album", "record")]));
+        assert_eq!(diff.removed, pairs(&[("Artist", "Someone"), ("ALBUM", "Record")]));
+        assert_eq!(diff.unchanged, 1);
+    }
+
+    #[test]
+    fn retain_only_dirties_on_removal() -> Result<(), Error> {
+        let mut list = DiscreteCommentList::default();
+        list.push("v0", "k0")?;
+        list.mark_clean();
+        list.retain(|_, _| true);
+        list.remove_all("missing");
+        assert!(!list.is_dirty());
+        list.retain(|k, _| k != "v0");
+        assert!(list.is_dirty());
+        assert!(list.is_empty());
+        Ok(())
+    }
 }
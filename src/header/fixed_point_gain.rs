@@ -1,24 +1,39 @@
 use std::convert::TryFrom;
 use std::fmt::{Display, Formatter};
+use std::num::ParseIntError;
 use std::str::FromStr;
 
 use crate::{Decibels, Error};
 
 /// Represents the fixed-point Decibel representation used
 /// within Opus comment headers
+///
+/// When the `serde` feature is enabled, this serializes as its underlying
+/// `i16` value, which is in units of 1/256 dB.
 #[derive(Default, Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct FixedPointGain {
     value: i16,
 }
 
 impl FixedPointGain {
+    /// The difference between adjacent fixed-point gains, which is the
+    /// resolution of the output gain and R128 tags (1/256 dB)
+    ///
+    /// ```
+    /// use zoog::header::FixedPointGain;
+    ///
+    /// assert_eq!(FixedPointGain::from_fixed_point(3).as_decibels().as_f64(), 3.0 * FixedPointGain::STEP.as_f64());
+    /// ```
+    pub const STEP: Decibels = Decibels::new(1.0 / 256.0);
+
     /// The underlying signed 16-bit integer representation
     #[must_use]
     pub fn as_fixed_point(self) -> i16 { self.value }
 
     /// This value as Decibels
     #[must_use]
-    pub fn as_decibels(self) -> Decibels { Decibels::from(f64::from(self.value) / 256.0) }
+    pub fn as_decibels(self) -> Decibels { Decibels::from(f64::from(self.value) * Self::STEP.as_f64()) }
 
     /// Construct from a fixed-point integer encoding
     #[must_use]
@@ -28,6 +43,31 @@ impl FixedPointGain {
     #[must_use]
     pub fn is_zero(self) -> bool { self.value == 0 }
 
+    /// Parses this value from a textual representation which may not be
+    /// canonical. Surrounding whitespace, a leading `+` and a trailing `dB` or
+    /// `LU` suffix are accepted, but the value must still be an integer in
+    /// range.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value is not an integer in range after removing
+    /// the permitted decorations.
+    pub fn parse_lenient(s: &str) -> Result<FixedPointGain, ParseIntError> {
+        let s = s.trim();
+        let split = s.len().saturating_sub(2);
+        let s = match (s.get(..split), s.get(split..)) {
+            (Some(number), Some(suffix)) if ["dB", "LU"].iter().any(|u| suffix.eq_ignore_ascii_case(u)) => {
+                number.trim_end()
+            }
+            _ => s,
+        };
+        s.parse()
+    }
+
+    /// Is `s` the canonical textual representation of this value?
+    #[must_use]
+    pub fn is_canonical_text(self, s: &str) -> bool { s == self.value.to_string() }
+
     /// Checked addition returning `None` on overflow or underflow.
     #[must_use]
     pub fn checked_add(self, rhs: FixedPointGain) -> Option<FixedPointGain> {
@@ -45,10 +85,10 @@ impl TryFrom<Decibels> for FixedPointGain {
     type Error = Error;
 
     fn try_from(value: Decibels) -> Result<FixedPointGain, Error> {
-        let fixed = (value.as_f64() * 256.0).round();
+        let fixed = (value.as_f64() / FixedPointGain::STEP.as_f64()).round();
         #[allow(clippy::cast_possible_truncation)]
         let value = fixed as i16;
-        if (f64::from(value) - fixed).abs() < std::f64::EPSILON {
+        if (f64::from(value) - fixed).abs() < f64::EPSILON {
             Ok(FixedPointGain { value })
         } else {
             Err(Error::GainOutOfBounds)
@@ -78,6 +118,16 @@ impl Display for FixedPointGain {
 mod tests {
     use super::*;
 
+    #[test]
+    fn step_is_resolution() {
+        let step = FixedPointGain::STEP.as_f64();
+        assert_eq!(FixedPointGain::from_fixed_point(1).as_decibels(), FixedPointGain::STEP);
+        for (multiple, expected) in [(0.49, 0), (0.51, 1), (-0.51, -1), (2.0, 2), (-1280.0, -1280)] {
+            let gain = FixedPointGain::try_from(Decibels::from(multiple * step)).unwrap();
+            assert_eq!(gain.as_fixed_point(), expected, "{} steps", multiple);
+        }
+    }
+
     #[test]
     fn zero_db_is_none() {
         assert!(FixedPointGain::try_from(Decibels::default()).unwrap().is_zero());
@@ -124,6 +174,51 @@ mod tests {
         assert_eq!("32767".parse::<FixedPointGain>(), Ok(FixedPointGain { value: 32767 }));
     }
 
+    #[test]
+    fn parse_lenient_whitespace() {
+        assert_eq!(FixedPointGain::parse_lenient(" -1536\t"), Ok(FixedPointGain { value: -1536 }));
+        assert!(!FixedPointGain { value: -1536 }.is_canonical_text(" -1536\t"));
+    }
+
+    #[test]
+    fn parse_lenient_plus_sign() {
+        assert_eq!(FixedPointGain::parse_lenient("+256"), Ok(FixedPointGain { value: 256 }));
+        assert!(!FixedPointGain { value: 256 }.is_canonical_text("+256"));
+        assert!(FixedPointGain { value: 256 }.is_canonical_text("256"));
+    }
+
+    #[test]
+    fn parse_lenient_suffix() {
+        for text in ["-1536 dB", "-1536dB", "-1536 db", "-1536 LU", "-1536lu"] {
+            assert_eq!(FixedPointGain::parse_lenient(text), Ok(FixedPointGain { value: -1536 }), "{}", text);
+        }
+        assert!(FixedPointGain::parse_lenient("dB").is_err());
+        assert!(FixedPointGain::parse_lenient("-1536 dBFS").is_err());
+    }
+
+    #[test]
+    fn parse_lenient_float() {
+        assert!(FixedPointGain::parse_lenient("-6.0").is_err());
+        assert!(FixedPointGain::parse_lenient("-6.0 dB").is_err());
+    }
+
+    #[test]
+    fn parse_lenient_overflow() {
+        assert!(FixedPointGain::parse_lenient("32768 dB").is_err());
+        assert!(FixedPointGain::parse_lenient("-32769").is_err());
+        assert_eq!(FixedPointGain::parse_lenient("+32767 LU"), Ok(FixedPointGain { value: i16::MAX }));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() -> Result<(), serde_json::Error> {
+        let value = FixedPointGain { value: -1280 };
+        let json = serde_json::to_string(&value)?;
+        assert_eq!(json, "-1280");
+        assert_eq!(serde_json::from_str::<FixedPointGain>(&json)?, value);
+        Ok(())
+    }
+
     #[test]
     fn parse_invalid() {
         assert!("-32769".parse::<FixedPointGain>().is_err());
@@ -26,4 +26,7 @@ pub trait IdHeader {
 
     /// The number of samples to be skipped when decoding this stream
     fn preskip_samples(&self) -> usize;
+
+    /// Has the header been modified since it was parsed?
+    fn is_dirty(&self) -> bool;
 }
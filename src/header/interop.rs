@@ -0,0 +1,184 @@
+//! Checks that comment headers written by zoog are framed the way other tools
+//! expect, using golden fixtures and a minimal parser written independently of
+//! zoog's own.
+
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+
+use crate::header::test_utils::{comment_header_as_vec, create_random_header};
+use crate::header::{CommentHeader, CommentList};
+use crate::{opus, vorbis, Error};
+
+const OPUS_MAGIC: &[u8] = b"OpusTags";
+const VORBIS_MAGIC: &[u8] = b"\x03vorbis";
+
+const OPUS_TAGS: &[u8] = include_bytes!("testdata/opus_tags.bin");
+const OPUS_EMPTY: &[u8] = include_bytes!("testdata/opus_empty.bin");
+const OPUS_PADDED: &[u8] = include_bytes!("testdata/opus_padded.bin");
+const OPUS_EMPTY_PADDED: &[u8] = include_bytes!("testdata/opus_empty_padded.bin");
+const VORBIS_TAGS: &[u8] = include_bytes!("testdata/vorbis_tags.bin");
+const VORBIS_EMPTY: &[u8] = include_bytes!("testdata/vorbis_empty.bin");
+
+const GOLDEN_VENDOR: &str = "zoog";
+const GOLDEN_COMMENTS: [(&str, &str); 2] = [("TITLE", "Golden"), ("ARTIST", "Zoog")];
+
+/// The fields of a comment header as read by `parse_independently`
+#[derive(Debug, PartialEq)]
+struct Fields {
+    vendor: Vec<u8>,
+    comments: Vec<Vec<u8>>,
+    trailing: Vec<u8>,
+}
+
+/// Parses a comment header following the framing in the Vorbis I
+/// specification, panicking on any truncated field
+fn parse_independently(magic: &[u8], data: &[u8]) -> Fields {
+    fn take<'a>(data: &mut &'a [u8], len: usize) -> &'a [u8] {
+        assert!(data.len() >= len, "Field of length {} overruns the {} bytes remaining", len, data.len());
+        let (field, rest) = data.split_at(len);
+        *data = rest;
+        field
+    }
+    fn take_length(data: &mut &[u8]) -> usize {
+        let bytes = take(data, 4);
+        let len = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        usize::try_from(len).expect("Length does not fit in usize")
+    }
+
+    let mut data = data;
+    assert_eq!(take(&mut data, magic.len()), magic);
+    let vendor_len = take_length(&mut data);
+    let vendor = take(&mut data, vendor_len).to_vec();
+    let num_comments = take_length(&mut data);
+    let comments = (0..num_comments)
+        .map(|_| {
+            let len = take_length(&mut data);
+            take(&mut data, len).to_vec()
+        })
+        .collect();
+    Fields { vendor, comments, trailing: data.to_vec() }
+}
+
+fn golden_header<H: CommentHeader + Default>(comments: &[(&str, &str)]) -> Result<H, Error> {
+    let mut header = H::default();
+    header.set_vendor(GOLDEN_VENDOR);
+    for (key, value) in comments {
+        header.push(key, value)?;
+    }
+    Ok(header)
+}
+
+fn golden_fields(comments: &[(&str, &str)], trailing: &[u8]) -> Fields {
+    Fields {
+        vendor: GOLDEN_VENDOR.as_bytes().to_vec(),
+        comments: comments.iter().map(|(key, value)| format!("{}={}", key, value).into_bytes()).collect(),
+        trailing: trailing.to_vec(),
+    }
+}
+
+#[test]
+fn fixtures_parse_independently() {
+    let cases: [(&[u8], &[u8], &[(&str, &str)], &[u8]); 6] = [
+        (OPUS_MAGIC, OPUS_TAGS, &GOLDEN_COMMENTS, &[]),
+        (OPUS_MAGIC, OPUS_EMPTY, &[], &[]),
+        (OPUS_MAGIC, OPUS_PADDED, &GOLDEN_COMMENTS, &[0; 256]),
+        (OPUS_MAGIC, OPUS_EMPTY_PADDED, &[], &[0; 256]),
+        (VORBIS_MAGIC, VORBIS_TAGS, &GOLDEN_COMMENTS, &[1]),
+        (VORBIS_MAGIC, VORBIS_EMPTY, &[], &[1]),
+    ];
+    for (magic, data, comments, trailing) in cases {
+        assert_eq!(parse_independently(magic, data), golden_fields(comments, trailing));
+    }
+}
+
+#[test]
+fn opus_output_matches_golden() -> Result<(), Error> {
+    let tagged: opus::CommentHeader = golden_header(&GOLDEN_COMMENTS)?;
+    assert_eq!(comment_header_as_vec(&tagged)?, OPUS_TAGS);
+    let empty: opus::CommentHeader = golden_header(&[])?;
+    assert_eq!(comment_header_as_vec(&empty)?, OPUS_EMPTY);
+    Ok(())
+}
+
+#[test]
+fn vorbis_output_matches_golden() -> Result<(), Error> {
+    let tagged: vorbis::CommentHeader = golden_header(&GOLDEN_COMMENTS)?;
+    assert_eq!(comment_header_as_vec(&tagged)?, VORBIS_TAGS);
+    let empty: vorbis::CommentHeader = golden_header(&[])?;
+    assert_eq!(comment_header_as_vec(&empty)?, VORBIS_EMPTY);
+    Ok(())
+}
+
+#[test]
+fn padding_is_not_left_behind() -> Result<(), Error> {
+    // Removing every comment from a padded header must not leave the padding
+    // after a comment count of zero
+    let mut cleared = opus::CommentHeader::try_parse(OPUS_PADDED)?;
+    cleared.clear();
+    assert_eq!(comment_header_as_vec(&cleared)?, OPUS_EMPTY);
+
+    let mut retagged = opus::CommentHeader::try_parse(OPUS_EMPTY_PADDED)?;
+    assert_eq!(comment_header_as_vec(&retagged)?, OPUS_EMPTY);
+    for (key, value) in GOLDEN_COMMENTS {
+        retagged.push(key, value)?;
+    }
+    assert_eq!(comment_header_as_vec(&retagged)?, OPUS_TAGS);
+    Ok(())
+}
+
+#[test]
+fn vorbis_rewrite_keeps_single_framing_bit() -> Result<(), Error> {
+    let mut cleared = vorbis::CommentHeader::try_parse(VORBIS_TAGS)?;
+    cleared.clear();
+    assert_eq!(comment_header_as_vec(&cleared)?, VORBIS_EMPTY);
+    Ok(())
+}
+
+fn check_random_headers<H: CommentHeader + Default>(magic: &[u8], trailing: &[u8], seed: u64) -> Result<(), Error> {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    for _ in 0..16 {
+        let header: H = create_random_header(&mut rng);
+        let fields = parse_independently(magic, &comment_header_as_vec(&header)?);
+        let expected = Fields {
+            vendor: header.get_vendor().as_bytes().to_vec(),
+            comments: header.iter().map(|(key, value)| format!("{}={}", key, value).into_bytes()).collect(),
+            trailing: trailing.to_vec(),
+        };
+        assert_eq!(fields, expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn random_opus_headers_frame_correctly() -> Result<(), Error> {
+    check_random_headers::<opus::CommentHeader>(OPUS_MAGIC, &[], 61207)
+}
+
+#[test]
+fn random_vorbis_headers_frame_correctly() -> Result<(), Error> {
+    check_random_headers::<vorbis::CommentHeader>(VORBIS_MAGIC, &[1], 30418)
+}
+
+/// Runs `opustags` on a file containing the golden comment header. This only
+/// runs if `ZOOG_INTEROP_TOOLS` is set, since it requires `opustags` to be
+/// installed.
+#[test]
+fn opustags_reads_golden_header() -> Result<(), Error> {
+    use std::io::Write as _;
+    use std::process::Command;
+
+    use crate::testing::{opus_file, read_packets, write_packets, StreamSpec};
+
+    if std::env::var_os("ZOOG_INTEROP_TOOLS").is_none() {
+        return Ok(());
+    }
+    let mut packets = read_packets(&opus_file(&[StreamSpec::default()]));
+    packets[1].data = OPUS_TAGS.to_vec();
+    let data = write_packets(&packets);
+    let mut file = tempfile::NamedTempFile::new().expect("Unable to create temporary file");
+    file.write_all(&data).map_err(Error::WriteError)?;
+    let output = Command::new("opustags").arg(file.path()).output().expect("Unable to run opustags");
+    assert!(output.status.success(), "opustags failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "TITLE=Golden\nARTIST=Zoog\n");
+    Ok(())
+}
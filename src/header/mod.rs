@@ -5,6 +5,11 @@ mod discrete_comment_list;
 mod fixed_point_gain;
 mod id_header;
 
+/// Conversion between the textual gains of ReplayGain tags and R128 gains
+pub mod replaygain;
+
+#[cfg(test)]
+mod interop;
 #[cfg(test)]
 pub(crate) mod test_utils;
 
@@ -0,0 +1,561 @@
+use std::convert::TryFrom;
+
+use crate::header::{CommentList, DiscreteCommentList, FixedPointGain};
+use crate::opus::{TAG_ALBUM_GAIN, TAG_REFERENCE_LOUDNESS, TAG_TRACK_GAIN};
+use crate::{Codec, Decibels, Error, R128_LUFS, REPLAY_GAIN_LUFS};
+
+/// The ReplayGain track gain tag written by scanners such as loudgain
+pub const TAG_RG_TRACK_GAIN: &str = "REPLAYGAIN_TRACK_GAIN";
+
+/// The ReplayGain album gain tag written by scanners such as loudgain
+pub const TAG_RG_ALBUM_GAIN: &str = "REPLAYGAIN_ALBUM_GAIN";
+
+/// The ReplayGain track peak tag written by scanners such as loudgain
+pub const TAG_RG_TRACK_PEAK: &str = "REPLAYGAIN_TRACK_PEAK";
+
+/// The ReplayGain album peak tag written by scanners such as loudgain
+pub const TAG_RG_ALBUM_PEAK: &str = "REPLAYGAIN_ALBUM_PEAK";
+
+/// The ReplayGain tags written by other tools whose values are computed from
+/// the decoded audio, and so depend on the output gain of an Opus stream
+pub const FOREIGN_GAIN_TAGS: [&str; 4] = [TAG_RG_TRACK_GAIN, TAG_RG_ALBUM_GAIN, TAG_RG_TRACK_PEAK, TAG_RG_ALBUM_PEAK];
+
+/// Parses a gain in the textual format used by ReplayGain tags such as
+/// `REPLAYGAIN_TRACK_GAIN`, e.g. `-6.54 dB`. Surrounding whitespace, a leading
+/// `+` and a missing or differently-cased `dB` suffix are accepted.
+///
+/// Returns `Error::InvalidReplayGainValue` if the value is not a finite number.
+pub fn parse_rg_value(value: &str) -> Result<Decibels, Error> {
+    let invalid = || Error::InvalidReplayGainValue(value.to_string());
+    let trimmed = value.trim();
+    let split = trimmed.len().saturating_sub(2);
+    let number = match (trimmed.get(..split), trimmed.get(split..)) {
+        (Some(number), Some(suffix)) if suffix.eq_ignore_ascii_case("dB") => number.trim_end(),
+        _ => trimmed,
+    };
+    // Reject anything other than digits, signs, a decimal point and exponents so
+    // that spellings such as `inf` and `NaN` are not accepted
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c)) {
+        return Err(invalid());
+    }
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    if number.is_finite() {
+        Ok(Decibels::from(number))
+    } else {
+        Err(invalid())
+    }
+}
+
+/// Formats a gain in the textual format used by ReplayGain tags, which is
+/// always signed with two decimal places and a ` dB` suffix, e.g. `+2.31 dB`.
+#[must_use]
+pub fn format_rg_value(gain: Decibels) -> String {
+    // Adding zero turns a negative zero into a positive one so that small
+    // negative gains are written as `+0.00 dB` rather than `-0.00 dB`
+    let rounded = (gain.as_f64() * 100.0).round() / 100.0 + 0.0;
+    format!("{:+.2} dB", rounded)
+}
+
+/// Parses a peak in the textual format used by ReplayGain tags such as
+/// `REPLAYGAIN_TRACK_PEAK`, which is a linear amplitude relative to full scale,
+/// e.g. `0.988553`. Surrounding whitespace is accepted.
+///
+/// Returns `Error::InvalidReplayGainPeak` if the value is not a finite,
+/// non-negative number.
+pub fn parse_rg_peak(value: &str) -> Result<f64, Error> {
+    let invalid = || Error::InvalidReplayGainPeak(value.to_string());
+    let number = value.trim();
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c)) {
+        return Err(invalid());
+    }
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    if number.is_finite() && number >= 0.0 {
+        Ok(number)
+    } else {
+        Err(invalid())
+    }
+}
+
+/// Formats a peak in the textual format used by ReplayGain tags, which has six
+/// decimal places, e.g. `0.988553`.
+#[must_use]
+pub fn format_rg_peak(peak: f64) -> String { format!("{:.6}", peak) }
+
+/// Returns the names of any foreign ReplayGain tags in `comments`, as listed
+/// in `FOREIGN_GAIN_TAGS`, in the order they first occur. Names are matched
+/// ignoring case and returned as spelled in `comments`.
+#[must_use]
+pub fn foreign_gain_tags<L: CommentList>(comments: &L) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for (key, _) in comments.iter() {
+        let foreign = FOREIGN_GAIN_TAGS.iter().any(|tag| key.eq_ignore_ascii_case(tag));
+        if foreign && !names.iter().any(|name| name == key) {
+            names.push(key.to_string());
+        }
+    }
+    names
+}
+
+/// Adjusts the foreign ReplayGain tags in `comments` for a change of
+/// `output_gain_change` to the output gain. Raising the output gain makes the
+/// decoded audio louder, so the gains are lowered by the same amount and the
+/// peaks are scaled up to match. Other comments, and their order, are
+/// unchanged.
+///
+/// Returns `Error::InvalidReplayGainValue` or `Error::InvalidReplayGainPeak`
+/// if a tag cannot be parsed, in which case `comments` is unmodified.
+///
+/// ```
+/// use zoog::header::replaygain::adjust_foreign_tags;
+/// use zoog::header::{CommentList, DiscreteCommentList};
+/// use zoog::Decibels;
+///
+/// let mut comments = DiscreteCommentList::default();
+/// comments.push("REPLAYGAIN_TRACK_GAIN", "-6.54 dB")?;
+/// comments.push("REPLAYGAIN_TRACK_PEAK", "0.500000")?;
+/// adjust_foreign_tags(&mut comments, Decibels::from(-3.0))?;
+/// assert_eq!(comments.get_first("REPLAYGAIN_TRACK_GAIN"), Some("-3.54 dB"));
+/// assert_eq!(comments.get_first("REPLAYGAIN_TRACK_PEAK"), Some("0.353973"));
+/// # Ok::<(), zoog::Error>(())
+/// ```
+pub fn adjust_foreign_tags<L: CommentList>(comments: &mut L, output_gain_change: Decibels) -> Result<(), Error> {
+    let is_one_of = |key: &str, tags: [&str; 2]| tags.iter().any(|tag| key.eq_ignore_ascii_case(tag));
+    let peak_scale = 10.0_f64.powf(output_gain_change.as_f64() / 20.0);
+    let adjusted = comments
+        .iter()
+        .map(|(key, value)| {
+            let value = if is_one_of(key, [TAG_RG_TRACK_GAIN, TAG_RG_ALBUM_GAIN]) {
+                format_rg_value(parse_rg_value(value)? - output_gain_change)
+            } else if is_one_of(key, [TAG_RG_TRACK_PEAK, TAG_RG_ALBUM_PEAK]) {
+                format_rg_peak(parse_rg_peak(value)? * peak_scale)
+            } else {
+                value.to_string()
+            };
+            Ok((key.to_string(), value))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    comments.clear();
+    comments.extend(adjusted)
+}
+
+/// Converts a ReplayGain gain, which is relative to a reference of -18 LUFS,
+/// to an R128 gain, which is relative to -23 LUFS.
+///
+/// Returns `Error::GainOutOfBounds` if the result cannot be represented.
+pub fn rg_to_r128(gain: Decibels) -> Result<FixedPointGain, Error> {
+    FixedPointGain::try_from(gain + R128_LUFS - REPLAY_GAIN_LUFS)
+}
+
+/// Converts an R128 gain, which is relative to a reference of -23 LUFS, to a
+/// ReplayGain gain, which is relative to -18 LUFS.
+#[must_use]
+pub fn r128_to_rg(gain: FixedPointGain) -> Decibels { gain.as_decibels() + REPLAY_GAIN_LUFS - R128_LUFS }
+
+/// The gain and peak tags of either codec, whose values are only meaningful
+/// for the codec, and output gain, of the file they were computed for
+pub const CODEC_GAIN_TAGS: [&str; 7] = [
+    TAG_TRACK_GAIN,
+    TAG_ALBUM_GAIN,
+    TAG_RG_TRACK_GAIN,
+    TAG_RG_ALBUM_GAIN,
+    TAG_RG_TRACK_PEAK,
+    TAG_RG_ALBUM_PEAK,
+    TAG_REFERENCE_LOUDNESS,
+];
+
+/// The codec of a file that tags are copied from or to, together with the
+/// output gain that the gains and peaks of an Opus stream are relative to
+#[derive(Clone, Copy, Debug)]
+pub struct GainContext {
+    /// The codec of the file
+    pub codec: Codec,
+
+    /// The output gain of the stream, which is zero for Vorbis
+    pub output_gain: Decibels,
+}
+
+impl GainContext {
+    /// The context of an Opus stream with the specified output gain
+    #[must_use]
+    pub fn opus(output_gain: FixedPointGain) -> GainContext {
+        GainContext { codec: Codec::Opus, output_gain: output_gain.as_decibels() }
+    }
+
+    /// The context of a Vorbis stream
+    #[must_use]
+    pub fn vorbis() -> GainContext { GainContext { codec: Codec::Vorbis, output_gain: Decibels::default() } }
+}
+
+/// Comments translated for a file of another codec by `translate_comments`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TranslatedComments {
+    /// The comments to write to the destination file
+    pub comments: DiscreteCommentList,
+
+    /// The names of the gain and peak tags which were not copied, as spelled
+    /// in the source
+    pub skipped: Vec<String>,
+}
+
+/// Translates `comments` from a file described by `from` for writing to one
+/// described by `to`. Comments other than those in `CODEC_GAIN_TAGS` are
+/// copied verbatim, as are all comments if the codecs are the same.
+///
+/// Otherwise the gain and peak tags are skipped unless `translate` is set. If
+/// it is, R128 gains of an Opus source become ReplayGain gains for Vorbis and
+/// ReplayGain gains of a Vorbis source become R128 gains for Opus, allowing for
+/// the difference in reference loudness and for the output gain of each Opus
+/// stream. R128 has no peak tags, so ReplayGain peaks are only written for a
+/// Vorbis destination if the Opus source has them. All other gain tags are
+/// skipped.
+///
+/// Returns `Error::InvalidR128Tag`, `Error::InvalidReplayGainValue` or
+/// `Error::InvalidReplayGainPeak` if a tag to be translated cannot be parsed,
+/// and `Error::GainOutOfBounds` if a translated R128 gain cannot be
+/// represented.
+///
+/// ```
+/// use zoog::header::replaygain::{translate_comments, GainContext};
+/// use zoog::header::{CommentList, DiscreteCommentList, FixedPointGain};
+///
+/// let mut vorbis = DiscreteCommentList::default();
+/// vorbis.push("TITLE", "Song")?;
+/// vorbis.push("REPLAYGAIN_TRACK_GAIN", "-6.54 dB")?;
+/// vorbis.push("REPLAYGAIN_TRACK_PEAK", "0.988553")?;
+/// let to = GainContext::opus(FixedPointGain::default());
+///
+/// let copied = translate_comments(&vorbis, GainContext::vorbis(), to, false)?;
+/// assert_eq!(copied.comments.iter().collect::<Vec<_>>(), [("TITLE", "Song")]);
+/// assert_eq!(copied.skipped, ["REPLAYGAIN_TRACK_GAIN", "REPLAYGAIN_TRACK_PEAK"]);
+///
+/// let translated = translate_comments(&vorbis, GainContext::vorbis(), to, true)?;
+/// assert_eq!(translated.comments.get_first("R128_TRACK_GAIN"), Some("-2954"));
+/// assert_eq!(translated.skipped, ["REPLAYGAIN_TRACK_PEAK"]);
+/// # Ok::<(), zoog::Error>(())
+/// ```
+pub fn translate_comments<L: CommentList>(
+    comments: &L, from: GainContext, to: GainContext, translate: bool,
+) -> Result<TranslatedComments, Error> {
+    let mut result = TranslatedComments::default();
+    let same_codec = matches!((from.codec, to.codec), (Codec::Opus, Codec::Opus) | (Codec::Vorbis, Codec::Vorbis));
+    for (key, value) in comments.iter() {
+        if same_codec || !CODEC_GAIN_TAGS.iter().any(|tag| key.eq_ignore_ascii_case(tag)) {
+            result.comments.push(key, value)?;
+            continue;
+        }
+        let translated = if translate { translate_gain_tag(key, value, from, to)? } else { None };
+        match translated {
+            Some((key, value)) => result.comments.push(key, &value)?,
+            None => result.skipped.push(key.to_string()),
+        }
+    }
+    Ok(result)
+}
+
+/// Translates a single gain or peak tag between codecs, returning the name and
+/// value to write, or `None` if the tag has no equivalent
+fn translate_gain_tag<'a>(
+    key: &'a str, value: &str, from: GainContext, to: GainContext,
+) -> Result<Option<(&'a str, String)>, Error> {
+    let is_tag = |tag: &str| key.eq_ignore_ascii_case(tag);
+    let gain_tags = [(TAG_TRACK_GAIN, TAG_RG_TRACK_GAIN), (TAG_ALBUM_GAIN, TAG_RG_ALBUM_GAIN)];
+    let translated = match (from.codec, to.codec) {
+        (Codec::Opus, Codec::Vorbis) => {
+            if let Some((_, rg_tag)) = gain_tags.into_iter().find(|(r128_tag, _)| is_tag(r128_tag)) {
+                let r128 = FixedPointGain::parse_lenient(value).map_err(|_| Error::InvalidR128Tag(value.into()))?;
+                // The R128 gain applies on top of the output gain, so the
+                // unscaled audio needs both
+                Some((rg_tag, format_rg_value(r128_to_rg(r128) + from.output_gain)))
+            } else if is_tag(TAG_RG_TRACK_PEAK) || is_tag(TAG_RG_ALBUM_PEAK) {
+                // Peaks measured after the output gain are scaled back to those
+                // of the unscaled audio
+                let peak_scale = 10.0_f64.powf(-from.output_gain.as_f64() / 20.0);
+                Some((key, format_rg_peak(parse_rg_peak(value)? * peak_scale)))
+            } else {
+                None
+            }
+        }
+        (Codec::Vorbis, Codec::Opus) => match gain_tags.into_iter().find(|(_, rg_tag)| is_tag(rg_tag)) {
+            Some((r128_tag, _)) => {
+                let r128 = rg_to_r128(parse_rg_value(value)? - to.output_gain)?;
+                Some((r128_tag, r128.as_fixed_point().to_string()))
+            }
+            None => None,
+        },
+        (Codec::Opus, Codec::Opus) | (Codec::Vorbis, Codec::Vorbis) => Some((key, value.to_string())),
+    };
+    Ok(translated)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_valid() {
+        // Values in the formats written by vorbisgain and loudgain, followed by
+        // more lenient spellings
+        let cases = [
+            ("-6.54 dB", -6.54),
+            ("+2.31 dB", 2.31),
+            ("+0.00 dB", 0.0),
+            ("-0.00 dB", 0.0),
+            ("-12.08 dB", -12.08),
+            ("+10.50 dB", 10.5),
+            ("-6.54", -6.54),
+            ("2.31", 2.31),
+            (" -6.54dB ", -6.54),
+            ("-6.54 DB", -6.54),
+            ("-6.54 db", -6.54),
+            ("-6 dB", -6.0),
+            ("-6.5e0 dB", -6.5),
+        ];
+        for (text, expected) in cases {
+            let parsed = parse_rg_value(text).unwrap_or_else(|e| panic!("Failed to parse {:?}: {}", text, e));
+            assert_eq!(parsed, Decibels::from(expected), "Unexpected value for {:?}", text);
+        }
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let cases = [
+            "",
+            "dB",
+            " dB",
+            "NaN",
+            "nan dB",
+            "inf",
+            "+inf dB",
+            "-infinity",
+            "1e999 dB",
+            "-6.54 dBFS",
+            "-6.54 LU",
+            "six dB",
+            "-6,54 dB",
+            "--6.54",
+        ];
+        for text in cases {
+            assert!(
+                matches!(parse_rg_value(text), Err(Error::InvalidReplayGainValue(v)) if v == text),
+                "Unexpectedly parsed {:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn format_values() {
+        let cases = [
+            (-6.54, "-6.54 dB"),
+            (2.31, "+2.31 dB"),
+            (0.0, "+0.00 dB"),
+            (-0.0, "+0.00 dB"),
+            (-0.001, "+0.00 dB"),
+            (-12.075_001, "-12.08 dB"),
+            (10.5, "+10.50 dB"),
+            (-6.0, "-6.00 dB"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(format_rg_value(Decibels::from(value)), expected, "Unexpected text for {}", value);
+        }
+    }
+
+    #[test]
+    fn format_round_trips() {
+        for text in ["-6.54 dB", "+2.31 dB", "+0.00 dB", "-12.08 dB", "+10.50 dB"] {
+            assert_eq!(format_rg_value(parse_rg_value(text).unwrap()), text);
+        }
+    }
+
+    #[test]
+    fn reference_offset() {
+        // (ReplayGain gain, R128 gain in units of 1/256 dB)
+        let cases = [(0.0, -1280), (5.0, 0), (-6.54, -2954), (2.31, -689), (-1.0, -1536)];
+        for (rg, r128) in cases {
+            let rg = Decibels::from(rg);
+            let r128 = FixedPointGain::from_fixed_point(r128);
+            assert_eq!(rg_to_r128(rg).unwrap(), r128, "Unexpected R128 gain for {}", rg);
+            assert_eq!(format_rg_value(r128_to_rg(r128)), format_rg_value(rg));
+        }
+    }
+
+    #[test]
+    fn parse_peaks() {
+        for (text, expected) in [("0.988553", 0.988_553), (" 1.000000 ", 1.0), ("0", 0.0), ("1.5e0", 1.5)] {
+            assert_eq!(parse_rg_peak(text).unwrap(), expected, "Unexpected value for {:?}", text);
+        }
+        for text in ["", "-0.5", "NaN", "inf", "1e999", "0.5 dB"] {
+            assert!(
+                matches!(parse_rg_peak(text), Err(Error::InvalidReplayGainPeak(v)) if v == text),
+                "Unexpectedly parsed {:?}",
+                text
+            );
+        }
+        assert_eq!(format_rg_peak(parse_rg_peak("0.988553").unwrap()), "0.988553");
+    }
+
+    fn rg_comments() -> DiscreteCommentList {
+        let mut comments = DiscreteCommentList::default();
+        for (key, value) in [
+            ("TITLE", "Song"),
+            ("replaygain_track_gain", "-6.54 dB"),
+            ("REPLAYGAIN_TRACK_PEAK", "0.500000"),
+            ("REPLAYGAIN_ALBUM_GAIN", "+1.20 dB"),
+            ("REPLAYGAIN_ALBUM_PEAK", "0.900000"),
+            ("REPLAYGAIN_REFERENCE_LOUDNESS", "89.0 dB"),
+        ] {
+            comments.push(key, value).unwrap();
+        }
+        comments
+    }
+
+    #[test]
+    fn foreign_tags_are_found() {
+        let expected =
+            ["replaygain_track_gain", "REPLAYGAIN_TRACK_PEAK", "REPLAYGAIN_ALBUM_GAIN", "REPLAYGAIN_ALBUM_PEAK"];
+        assert_eq!(foreign_gain_tags(&rg_comments()), expected);
+        assert!(foreign_gain_tags(&DiscreteCommentList::default()).is_empty());
+    }
+
+    #[test]
+    fn foreign_tags_follow_output_gain() -> Result<(), Error> {
+        // Raising the output gain by 6.02 dB doubles the decoded amplitude
+        let mut comments = rg_comments();
+        adjust_foreign_tags(&mut comments, Decibels::from(20.0 * 2.0_f64.log10()))?;
+        let adjusted: Vec<_> = comments.iter().collect();
+        let expected = [
+            ("TITLE", "Song"),
+            ("replaygain_track_gain", "-12.56 dB"),
+            ("REPLAYGAIN_TRACK_PEAK", "1.000000"),
+            ("REPLAYGAIN_ALBUM_GAIN", "-4.82 dB"),
+            ("REPLAYGAIN_ALBUM_PEAK", "1.800000"),
+            ("REPLAYGAIN_REFERENCE_LOUDNESS", "89.0 dB"),
+        ];
+        assert_eq!(adjusted, expected);
+
+        // Undoing the change restores the original values
+        adjust_foreign_tags(&mut comments, Decibels::from(-20.0 * 2.0_f64.log10()))?;
+        assert_eq!(comments, rg_comments());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_foreign_tag_is_not_adjusted() {
+        let mut comments = rg_comments();
+        comments.push("REPLAYGAIN_TRACK_PEAK", "loud").unwrap();
+        let result = adjust_foreign_tags(&mut comments, Decibels::from(1.0));
+        assert!(matches!(result, Err(Error::InvalidReplayGainPeak(v)) if v == "loud"));
+        assert_eq!(comments.len(), rg_comments().len() + 1);
+        assert_eq!(comments.get_first("REPLAYGAIN_TRACK_PEAK"), Some("0.500000"));
+    }
+
+    #[test]
+    fn rg_to_r128_out_of_bounds() {
+        assert!(matches!(rg_to_r128(Decibels::from(200.0)), Err(Error::GainOutOfBounds)));
+    }
+
+    fn comments(pairs: &[(&str, &str)]) -> DiscreteCommentList {
+        let mut comments = DiscreteCommentList::default();
+        for (key, value) in pairs {
+            comments.push(key, value).unwrap();
+        }
+        comments
+    }
+
+    #[test]
+    fn gain_tags_are_skipped_between_codecs() -> Result<(), Error> {
+        let opus = comments(&[("TITLE", "Song"), ("R128_TRACK_GAIN", "-1280"), ("ARTIST", "Band")]);
+        let opus_context = GainContext::opus(FixedPointGain::default());
+        let translated = translate_comments(&opus, opus_context, GainContext::vorbis(), false)?;
+        assert_eq!(translated.comments, comments(&[("TITLE", "Song"), ("ARTIST", "Band")]));
+        assert_eq!(translated.skipped, ["R128_TRACK_GAIN"]);
+
+        let translated = translate_comments(&rg_comments(), GainContext::vorbis(), opus_context, false)?;
+        assert_eq!(translated.comments, comments(&[("TITLE", "Song")]));
+        assert_eq!(translated.skipped.len(), rg_comments().len() - 1);
+        Ok(())
+    }
+
+    #[test]
+    fn same_codec_copies_verbatim() -> Result<(), Error> {
+        let opus = comments(&[("TITLE", "Song"), ("R128_TRACK_GAIN", "-1280"), ("REPLAYGAIN_TRACK_PEAK", "0.5")]);
+        let from = GainContext::opus(FixedPointGain::from_fixed_point(256));
+        let to = GainContext::opus(FixedPointGain::default());
+        for translate in [false, true] {
+            let translated = translate_comments(&opus, from, to, translate)?;
+            assert_eq!(translated, TranslatedComments { comments: opus.clone(), skipped: Vec::new() });
+            let translated =
+                translate_comments(&rg_comments(), GainContext::vorbis(), GainContext::vorbis(), translate)?;
+            assert_eq!(translated.comments, rg_comments());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn vorbis_gains_are_translated_to_r128() -> Result<(), Error> {
+        let opus_context = GainContext::opus(FixedPointGain::default());
+        let translated = translate_comments(&rg_comments(), GainContext::vorbis(), opus_context, true)?;
+        // ReplayGain is relative to -18 LUFS and R128 to -23 LUFS
+        let expected = comments(&[("TITLE", "Song"), ("R128_TRACK_GAIN", "-2954"), ("R128_ALBUM_GAIN", "-973")]);
+        assert_eq!(translated.comments, expected);
+        // R128 has no peak tags and the reference loudness is specific to the codec
+        assert_eq!(
+            translated.skipped,
+            ["REPLAYGAIN_TRACK_PEAK", "REPLAYGAIN_ALBUM_PEAK", "REPLAYGAIN_REFERENCE_LOUDNESS"]
+        );
+
+        // An output gain of +1 dB leaves 1 dB less for the R128 gain to apply
+        let opus_context = GainContext::opus(FixedPointGain::from_fixed_point(256));
+        let translated = translate_comments(&rg_comments(), GainContext::vorbis(), opus_context, true)?;
+        assert_eq!(translated.comments.get_first("R128_TRACK_GAIN"), Some("-3210"));
+        Ok(())
+    }
+
+    #[test]
+    fn r128_gains_are_translated_to_replaygain() -> Result<(), Error> {
+        let opus = comments(&[
+            ("R128_TRACK_GAIN", "-2954"),
+            ("TITLE", "Song"),
+            ("R128_ALBUM_GAIN", "-973"),
+            ("REPLAYGAIN_TRACK_GAIN", "-6.54 dB"),
+        ]);
+        let translated =
+            translate_comments(&opus, GainContext::opus(FixedPointGain::default()), GainContext::vorbis(), true)?;
+        let expected = comments(&[
+            ("REPLAYGAIN_TRACK_GAIN", "-6.54 dB"),
+            ("TITLE", "Song"),
+            ("REPLAYGAIN_ALBUM_GAIN", "+1.20 dB"),
+        ]);
+        assert_eq!(translated.comments, expected);
+        // ReplayGain gains in an Opus file duplicate the R128 gains, and there
+        // was no peak data to translate
+        assert_eq!(translated.skipped, ["REPLAYGAIN_TRACK_GAIN"]);
+        assert!(foreign_gain_tags(&translated.comments).iter().all(|tag| !tag.ends_with("_PEAK")));
+        Ok(())
+    }
+
+    #[test]
+    fn opus_output_gain_is_included_in_replaygain() -> Result<(), Error> {
+        // The output gain lowers the decoded audio by 6 dB, so the unscaled audio
+        // is 6 dB louder with a peak almost twice as high
+        let output_gain = FixedPointGain::try_from(Decibels::from(-6.0))?;
+        let opus = comments(&[("R128_TRACK_GAIN", "0"), ("REPLAYGAIN_TRACK_PEAK", "0.500000")]);
+        let translated = translate_comments(&opus, GainContext::opus(output_gain), GainContext::vorbis(), true)?;
+        let expected = comments(&[("REPLAYGAIN_TRACK_GAIN", "-1.00 dB"), ("REPLAYGAIN_TRACK_PEAK", "0.997631")]);
+        assert_eq!(translated.comments, expected);
+        assert!(translated.skipped.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_gain_tags_are_not_translated() {
+        let opus_context = GainContext::opus(FixedPointGain::default());
+        let vorbis = comments(&[("REPLAYGAIN_TRACK_GAIN", "loud")]);
+        let result = translate_comments(&vorbis, GainContext::vorbis(), opus_context, true);
+        assert!(matches!(result, Err(Error::InvalidReplayGainValue(_))));
+        let opus = comments(&[("R128_TRACK_GAIN", "loud")]);
+        let result = translate_comments(&opus, opus_context, GainContext::vorbis(), true);
+        assert!(matches!(result, Err(Error::InvalidR128Tag(_))));
+        // Untranslated tags are skipped without being parsed
+        assert!(translate_comments(&opus, opus_context, GainContext::vorbis(), false).is_ok());
+    }
+}
@@ -0,0 +1,384 @@
+use std::fmt::{self, Display, Formatter};
+use std::ops::RangeInclusive;
+
+use crate::header::{CommentList, FixedPointGain, IdHeader as _};
+use crate::header_rewriter::{CodecHeaders, HeaderSummarize};
+use crate::opus::{TAG_ALBUM_GAIN, TAG_TRACK_GAIN};
+use crate::{parse_number, Error};
+
+/// The Opus gain tags checked for issues
+const GAIN_TAGS: [&str; 2] = [TAG_TRACK_GAIN, TAG_ALBUM_GAIN];
+
+/// Input sample rates in Hz which an Opus encoder could plausibly have been
+/// given. Zero is excluded since it marks the rate as unknown.
+const PLAUSIBLE_INPUT_SAMPLE_RATES: RangeInclusive<usize> = 8000..=768_000;
+
+/// A non-fatal problem found in the headers of a stream
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HeaderIssue {
+    /// An R128 gain tag whose value is not a fixed-point gain
+    InvalidR128Value { tag: String, value: String },
+
+    /// An R128 gain tag whose value is a gain followed by units, e.g. `-1280
+    /// dB`
+    R128ValueWithUnits { tag: String, value: String },
+
+    /// An R128 gain tag whose value is an accepted but non-canonical
+    /// representation of a fixed-point gain, e.g. `+256` or `-1280 dB`
+    NonCanonicalGainValue { tag: String, value: String },
+
+    /// An R128 gain tag which occurs more than once
+    DuplicateR128Tag { tag: String, count: usize },
+
+    /// An R128 gain tag whose name differs in case from the canonical
+    /// uppercase spelling, e.g. `r128_track_gain`. Names are case-insensitive,
+    /// but such tags are written by tools which may not recognise them.
+    MixedCaseGainTag { tag: String, name: String },
+
+    /// An R128 gain tag in an Ogg Vorbis stream, where it has no defined
+    /// meaning
+    R128TagOnVorbis { tag: String },
+
+    /// Data following the framing bit of an Ogg Vorbis comment header, which
+    /// is preserved but has no defined meaning
+    VorbisTrailingData { length: usize },
+
+    /// An Opus input sample rate which is known but outside the range of
+    /// plausible rates, such as the 1 Hz written by some broken encoders. The
+    /// rate is informational but may be displayed by other tools.
+    ImplausibleInputSampleRate { rate: usize },
+
+    /// An R128 gain tag which could not be updated because the gain computed
+    /// for it cannot be represented, so its existing value, if any, was kept
+    UnrepresentableR128Gain { tag: String, gain: String, existing: Option<String> },
+}
+
+impl Display for HeaderIssue {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderIssue::InvalidR128Value { tag, value } => write!(formatter, "Invalid value `{}` for {}", value, tag),
+            HeaderIssue::R128ValueWithUnits { tag, value } => {
+                write!(formatter, "Value `{}` for {} should not include units", value, tag)
+            }
+            HeaderIssue::NonCanonicalGainValue { tag, value } => {
+                write!(formatter, "Value `{}` for {} is not in canonical form", value, tag)
+            }
+            HeaderIssue::DuplicateR128Tag { tag, count } => write!(formatter, "{} occurs {} times", tag, count),
+            HeaderIssue::MixedCaseGainTag { tag, name } => write!(formatter, "{} should be written as {}", name, tag),
+            HeaderIssue::R128TagOnVorbis { tag } => write!(formatter, "{} has no meaning in an Ogg Vorbis stream", tag),
+            HeaderIssue::VorbisTrailingData { length } => {
+                write!(formatter, "Comment header has {} unexpected byte(s) after the framing bit", length)
+            }
+            HeaderIssue::ImplausibleInputSampleRate { rate } => {
+                write!(formatter, "Input sample rate of {} Hz is implausible", rate)
+            }
+            HeaderIssue::UnrepresentableR128Gain { tag, gain, existing } => {
+                write!(formatter, "Computed {} of {} cannot be represented so ", tag, gain)?;
+                match existing {
+                    Some(existing) => write!(formatter, "it was left at {}", existing),
+                    None => write!(formatter, "it was not written"),
+                }
+            }
+        }
+    }
+}
+
+/// Does the value look like a gain with units appended?
+fn has_units(value: &str) -> bool {
+    let value = value.trim();
+    let split = value.len().saturating_sub(2);
+    match (value.get(..split), value.get(split..)) {
+        (Some(number), Some(units)) if units.eq_ignore_ascii_case("db") => parse_number(number).is_ok(),
+        _ => false,
+    }
+}
+
+fn find_gain_tag_issues<L: CommentList>(comments: &L, is_vorbis: bool, issues: &mut Vec<HeaderIssue>) {
+    for tag in GAIN_TAGS {
+        let mappings: Vec<_> = comments.iter().filter(|(k, _)| k.eq_ignore_ascii_case(tag)).collect();
+        if mappings.is_empty() {
+            continue;
+        }
+        if is_vorbis {
+            issues.push(HeaderIssue::R128TagOnVorbis { tag: tag.into() });
+        }
+        if mappings.len() > 1 {
+            issues.push(HeaderIssue::DuplicateR128Tag { tag: tag.into(), count: mappings.len() });
+        }
+        let mut names: Vec<_> = mappings.iter().map(|(k, _)| *k).filter(|k| *k != tag).collect();
+        names.sort_unstable();
+        names.dedup();
+        for name in names {
+            issues.push(HeaderIssue::MixedCaseGainTag { tag: tag.into(), name: name.into() });
+        }
+        let values = mappings.into_iter().map(|(_, v)| v);
+        for value in values {
+            let (tag, value) = (tag.to_string(), value.to_string());
+            match FixedPointGain::parse_lenient(&value) {
+                Ok(gain) if gain.is_canonical_text(&value) => {}
+                Ok(_) => issues.push(HeaderIssue::NonCanonicalGainValue { tag, value }),
+                Err(_) if has_units(&value) => issues.push(HeaderIssue::R128ValueWithUnits { tag, value }),
+                Err(_) => issues.push(HeaderIssue::InvalidR128Value { tag, value }),
+            }
+        }
+    }
+}
+
+/// Finds non-fatal problems with the supplied headers
+#[must_use]
+pub fn find_issues(headers: &CodecHeaders) -> Vec<HeaderIssue> {
+    let mut issues = Vec::new();
+    match headers {
+        CodecHeaders::Opus(id_header, comments) => {
+            if let Some(rate) = id_header.input_sample_rate().filter(|r| !PLAUSIBLE_INPUT_SAMPLE_RATES.contains(r)) {
+                issues.push(HeaderIssue::ImplausibleInputSampleRate { rate });
+            }
+            find_gain_tag_issues(comments, false, &mut issues);
+        }
+        CodecHeaders::Vorbis(_, comments) => {
+            find_gain_tag_issues(comments, true, &mut issues);
+            let length = comments.trailing_data().len();
+            if length != 0 {
+                issues.push(HeaderIssue::VorbisTrailingData { length });
+            }
+        }
+    }
+    issues
+}
+
+/// A header summary together with any issues found in the headers
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WithIssues<S> {
+    /// The summary produced by the wrapped summarizer
+    pub summary: S,
+
+    /// Issues found in the headers
+    pub issues: Vec<HeaderIssue>,
+}
+
+/// Wraps a `HeaderSummarize`, additionally reporting issues found in the
+/// headers
+#[derive(Debug, Default)]
+pub struct IssueSummary<HS> {
+    inner: HS,
+    strict: bool,
+}
+
+impl<HS> IssueSummary<HS> {
+    /// Reports issues alongside the summary produced by `inner`
+    #[must_use]
+    pub fn new(inner: HS) -> IssueSummary<HS> { IssueSummary { inner, strict: false } }
+
+    /// Fails summarization with `Error::HeaderIssues` if any issues are found
+    #[must_use]
+    pub fn strict(inner: HS) -> IssueSummary<HS> { IssueSummary { inner, strict: true } }
+}
+
+impl<HS> HeaderSummarize for IssueSummary<HS>
+where
+    HS: HeaderSummarize,
+    HS::Error: From<Error>,
+{
+    type Error = HS::Error;
+    type Summary = WithIssues<HS::Summary>;
+
+    fn summarize(&self, headers: &CodecHeaders) -> Result<Self::Summary, Self::Error> {
+        let summary = self.inner.summarize(headers)?;
+        let issues = find_issues(headers);
+        if self.strict && !issues.is_empty() {
+            return Err(Error::HeaderIssues(issues).into());
+        }
+        Ok(WithIssues { summary, issues })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::{CommentHeader as _, IdHeader as _};
+    use crate::testing::opus_id_header;
+    use crate::{opus, vorbis};
+
+    fn opus_headers(comments: &[(&str, &str)]) -> CodecHeaders {
+        let id_header = opus::IdHeader::try_parse(&opus_id_header(2, 48000, 0, 0)).unwrap().unwrap();
+        let mut comment_header = opus::CommentHeader::default();
+        comment_header.extend(comments.iter().copied()).unwrap();
+        CodecHeaders::Opus(id_header, comment_header)
+    }
+
+    fn vorbis_headers(comments: &[(&str, &str)]) -> CodecHeaders {
+        let mut id_header = b"\x01vorbis".to_vec();
+        id_header.extend_from_slice(&[0, 0, 0, 0, 2, 0x44, 0xac, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xb8, 1]);
+        let id_header = vorbis::IdHeader::try_parse(&id_header).unwrap().unwrap();
+        let mut comment_header = vorbis::CommentHeader::default();
+        comment_header.extend(comments.iter().copied()).unwrap();
+        CodecHeaders::Vorbis(id_header, comment_header)
+    }
+
+    #[test]
+    fn valid_opus_headers_have_no_issues() {
+        let headers = opus_headers(&[("TITLE", "dB"), (TAG_TRACK_GAIN, "-1280"), (TAG_ALBUM_GAIN, "256")]);
+        assert_eq!(find_issues(&headers), []);
+    }
+
+    #[test]
+    fn invalid_r128_value() {
+        let headers = opus_headers(&[(TAG_TRACK_GAIN, "+120 decibels"), (TAG_ALBUM_GAIN, "99999")]);
+        assert_eq!(
+            find_issues(&headers),
+            [
+                HeaderIssue::InvalidR128Value { tag: TAG_TRACK_GAIN.into(), value: "+120 decibels".into() },
+                HeaderIssue::InvalidR128Value { tag: TAG_ALBUM_GAIN.into(), value: "99999".into() },
+            ]
+        );
+    }
+
+    #[test]
+    fn r128_value_with_units() {
+        for value in ["-5.0dB", "0.5 DB", "-5,0 dB"] {
+            let headers = opus_headers(&[(TAG_TRACK_GAIN, value)]);
+            assert_eq!(
+                find_issues(&headers),
+                [HeaderIssue::R128ValueWithUnits { tag: TAG_TRACK_GAIN.into(), value: value.into() }]
+            );
+        }
+    }
+
+    #[test]
+    fn non_canonical_gain_value() {
+        for value in ["-1280 dB", " 3", "+256", "12 LU"] {
+            let headers = opus_headers(&[(TAG_TRACK_GAIN, value)]);
+            assert_eq!(
+                find_issues(&headers),
+                [HeaderIssue::NonCanonicalGainValue { tag: TAG_TRACK_GAIN.into(), value: value.into() }]
+            );
+        }
+    }
+
+    #[test]
+    fn duplicate_r128_tag() {
+        let headers = opus_headers(&[(TAG_ALBUM_GAIN, "0"), (TAG_ALBUM_GAIN, "12")]);
+        assert_eq!(find_issues(&headers), [HeaderIssue::DuplicateR128Tag { tag: TAG_ALBUM_GAIN.into(), count: 2 }]);
+    }
+
+    #[test]
+    fn mixed_case_gain_tag() {
+        let headers = opus_headers(&[("r128_track_gain", "0")]);
+        assert_eq!(
+            find_issues(&headers),
+            [HeaderIssue::MixedCaseGainTag { tag: TAG_TRACK_GAIN.into(), name: "r128_track_gain".into() }]
+        );
+
+        let headers = opus_headers(&[(TAG_ALBUM_GAIN, "0"), ("r128_album_gain", "12")]);
+        assert_eq!(
+            find_issues(&headers),
+            [
+                HeaderIssue::DuplicateR128Tag { tag: TAG_ALBUM_GAIN.into(), count: 2 },
+                HeaderIssue::MixedCaseGainTag { tag: TAG_ALBUM_GAIN.into(), name: "r128_album_gain".into() },
+            ]
+        );
+    }
+
+    #[test]
+    fn r128_tag_on_vorbis() {
+        let headers = vorbis_headers(&[(TAG_TRACK_GAIN, "0"), ("ARTIST", "Someone")]);
+        assert_eq!(find_issues(&headers), [HeaderIssue::R128TagOnVorbis { tag: TAG_TRACK_GAIN.into() }]);
+        assert_eq!(find_issues(&vorbis_headers(&[("ARTIST", "Someone")])), []);
+    }
+
+    #[test]
+    fn vorbis_trailing_data() -> Result<(), Error> {
+        let mut comment = Vec::new();
+        vorbis::CommentHeader::default().serialize_into(&mut comment)?;
+        comment.extend([0, 0xAB]);
+        let comment_header = vorbis::CommentHeader::try_parse(&comment)?;
+        let CodecHeaders::Vorbis(id_header, _) = vorbis_headers(&[]) else { unreachable!() };
+        let headers = CodecHeaders::Vorbis(id_header, comment_header);
+        assert_eq!(find_issues(&headers), [HeaderIssue::VorbisTrailingData { length: 2 }]);
+        Ok(())
+    }
+
+    #[test]
+    fn implausible_input_sample_rate() {
+        let with_rate = |rate| {
+            let id_header = opus::IdHeader::try_parse(&opus_id_header(2, rate, 0, 0)).unwrap().unwrap();
+            find_issues(&CodecHeaders::Opus(id_header, opus::CommentHeader::default()))
+        };
+        for rate in [0, 8000, 44100, 768_000] {
+            assert_eq!(with_rate(rate), [], "{} Hz", rate);
+        }
+        for rate in [1, 7999, 768_001, u32::MAX] {
+            assert_eq!(with_rate(rate), [HeaderIssue::ImplausibleInputSampleRate { rate: rate as usize }]);
+        }
+    }
+
+    #[test]
+    fn issue_summary_wraps_inner_summary() {
+        use crate::volume_rewrite::GainsSummary;
+
+        let headers = opus_headers(&[(TAG_TRACK_GAIN, "-512"), (TAG_TRACK_GAIN, "-512")]);
+        let summary = IssueSummary::new(GainsSummary::default()).summarize(&headers).unwrap();
+        assert_eq!(summary.summary.track_r128, Some(crate::Decibels::from(-2.0)));
+        assert_eq!(summary.issues, [HeaderIssue::DuplicateR128Tag { tag: TAG_TRACK_GAIN.into(), count: 2 }]);
+    }
+
+    #[test]
+    fn strict_summary_rejects_issues() {
+        use crate::volume_rewrite::GainsSummary;
+
+        let valid = opus_headers(&[(TAG_TRACK_GAIN, "-512")]);
+        let summary = IssueSummary::strict(GainsSummary::default()).summarize(&valid).unwrap();
+        assert_eq!(summary.issues, []);
+
+        let invalid = opus_headers(&[(TAG_TRACK_GAIN, "-512 dB")]);
+        assert!(IssueSummary::new(GainsSummary::default()).summarize(&invalid).is_ok());
+        match IssueSummary::strict(GainsSummary::default()).summarize(&invalid) {
+            Err(Error::HeaderIssues(issues)) => {
+                assert_eq!(
+                    issues,
+                    [HeaderIssue::NonCanonicalGainValue { tag: TAG_TRACK_GAIN.into(), value: "-512 dB".into() }]
+                );
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn strict_rewrite_fails_on_file_with_issues() {
+        use std::io::Cursor;
+
+        use crate::comment_rewrite::{
+            CommentHeaderRewrite, CommentHeaderSummary, CommentRewriterAction, CommentRewriterConfig,
+        };
+        use crate::header::DiscreteCommentList;
+        use crate::header_rewriter::{rewrite_stream, SubmitResult};
+        use crate::testing::{opus_file, StreamSpec};
+
+        let no_change = || CommentHeaderRewrite::new(CommentRewriterConfig { action: CommentRewriterAction::NoChange });
+        let mut comments = DiscreteCommentList::default();
+        comments.push(TAG_TRACK_GAIN, "-512 dB").unwrap();
+        let rewrite =
+            CommentHeaderRewrite::new(CommentRewriterConfig { action: CommentRewriterAction::Replace(comments) });
+        let mut data = Vec::new();
+        let input = Cursor::new(opus_file(&[StreamSpec::default()]));
+        rewrite_stream(rewrite, CommentHeaderSummary::default(), input, &mut data, false).unwrap();
+
+        let lenient = IssueSummary::new(CommentHeaderSummary::default());
+        match rewrite_stream(no_change(), lenient, Cursor::new(&data), std::io::sink(), true).unwrap() {
+            SubmitResult::HeadersUnchanged(WithIssues { issues, .. }) => assert_eq!(issues.len(), 1),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let strict = IssueSummary::strict(CommentHeaderSummary::default());
+        let result = rewrite_stream(no_change(), strict, Cursor::new(&data), std::io::sink(), true);
+        assert!(matches!(result, Err(Error::HeaderIssues(issues)) if issues.len() == 1));
+    }
+
+    #[test]
+    fn display() {
+        let issue = HeaderIssue::R128ValueWithUnits { tag: TAG_TRACK_GAIN.into(), value: "-1280 dB".into() };
+        assert_eq!(issue.to_string(), "Value `-1280 dB` for R128_TRACK_GAIN should not include units");
+    }
+}
@@ -1,17 +1,20 @@
+use std::cell::Cell;
 use std::collections::VecDeque;
-use std::io::{Read, Seek, Write};
+use std::fmt::{self, Display, Formatter};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::marker::PhantomData;
 
 use derivative::Derivative;
 use ogg::writing::{PacketWriteEndInfo, PacketWriter};
 use ogg::{Packet, PacketReader};
 
-use crate::header::{CommentHeader as _, IdHeader as _};
+use crate::header::{CommentHeader as _, CommentHeaderSizes, IdHeader as _};
 use crate::interrupt::{Interrupt, Never};
 use crate::{header, opus, vorbis, Codec, Error};
 
 /// The result of submitting a packet to a `HeaderRewriter`
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SubmitResult<S> {
     /// Packet was accepted
     Good,
@@ -25,13 +28,176 @@ pub enum SubmitResult<S> {
     HeadersChanged { from: S, to: S },
 }
 
+/// Statistics describing how far a rewrite progressed through its input
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RewriteStats {
+    /// Number of packets read from the input stream
+    pub packets_read: u64,
+
+    /// Number of bytes consumed from the input stream
+    pub bytes_read: u64,
+
+    /// Length of the input stream in bytes
+    pub input_length: u64,
+
+    /// Number of packets found immediately after a comment header which were
+    /// themselves comment headers
+    pub duplicate_headers: u64,
+}
+
+impl RewriteStats {
+    /// The percentage of the input stream consumed, or `None` if the input
+    /// was empty
+    #[must_use]
+    pub fn percent_complete(&self) -> Option<f64> {
+        #[allow(clippy::cast_precision_loss)]
+        (self.input_length != 0).then(|| 100.0 * self.bytes_read as f64 / self.input_length as f64)
+    }
+}
+
+impl Display for RewriteStats {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        match self.percent_complete() {
+            Some(percent) => write!(formatter, "at {:.1}% (after {} bytes)", percent, self.bytes_read),
+            None => write!(formatter, "after {} bytes", self.bytes_read),
+        }
+    }
+}
+
+/// Wraps a reader, recording the current stream position in a shared cell
+struct PositionTracker<'a, R> {
+    inner: R,
+    position: &'a Cell<u64>,
+}
+
+impl<R: Read> Read for PositionTracker<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let num_read = self.inner.read(buf)?;
+        self.position.set(self.position.get() + num_read as u64);
+        Ok(num_read)
+    }
+}
+
+impl<R: Seek> Seek for PositionTracker<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = self.inner.seek(pos)?;
+        self.position.set(position);
+        Ok(position)
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 enum State {
     AwaitingHeader,
     AwaitingComments { serial: u32 },
+    AwaitingFirstPacket { serial: u32, codec: Codec },
     Forwarding,
 }
 
+/// What a `HeaderRewriter` does with a packet which immediately follows the
+/// comment header and also parses as a comment header, as written by some
+/// buggy tools. Such a packet would otherwise be treated as audio.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateHeaderAction {
+    /// The packet is written unchanged
+    #[default]
+    Preserve,
+
+    /// The packet is removed from the stream, which counts as a change to the
+    /// headers. A duplicate which is the last packet of its stream is
+    /// preserved, since removing it would leave the stream without an end.
+    Drop,
+}
+
+/// Which logical stream of a file containing several has its headers parsed
+/// and rewritten by a `HeaderRewriter`. Packets of all other logical streams
+/// are passed through untouched.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StreamSelection {
+    /// The logical stream of the first packet
+    #[default]
+    First,
+
+    /// The logical stream with the specified serial
+    Serial(u32),
+
+    /// The logical stream whose beginning-of-stream packet is at the specified
+    /// index among those in the file, counting from zero
+    Index(usize),
+}
+
+impl StreamSelection {
+    /// Does the selection pick the logical stream begun by `packet`, which is
+    /// the `index`th beginning-of-stream packet if it is one?
+    fn selects(self, packet: &Packet, index: usize) -> bool {
+        match self {
+            StreamSelection::First => true,
+            StreamSelection::Serial(serial) => packet.first_in_stream() && packet.stream_serial() == serial,
+            StreamSelection::Index(selected) => packet.first_in_stream() && index == selected,
+        }
+    }
+
+    /// The error returned when no logical stream was selected
+    fn not_found(self) -> Error {
+        match self {
+            StreamSelection::First => Error::MissingHeaders,
+            StreamSelection::Serial(serial) => Error::MissingLogicalStream(serial),
+            StreamSelection::Index(index) => Error::MissingStreamIndex(index),
+        }
+    }
+}
+
+/// Is `data` a comment header for `codec`?
+fn is_comment_header(codec: Codec, data: &[u8]) -> bool {
+    match codec {
+        Codec::Opus => opus::CommentHeader::try_parse(data).is_ok(),
+        Codec::Vorbis => vorbis::CommentHeader::try_parse(data).is_ok(),
+    }
+}
+
+/// The outcome of rewriting the headers of a stream, which is held back until
+/// the packet following the comment header has been checked for duplicates
+struct PendingResult<S> {
+    from: S,
+    to: S,
+    changed: bool,
+}
+
+impl<S> PendingResult<S> {
+    fn into_submit_result(self) -> SubmitResult<S> {
+        if self.changed {
+            SubmitResult::HeadersChanged { from: self.from, to: self.to }
+        } else {
+            SubmitResult::HeadersUnchanged(self.from)
+        }
+    }
+}
+
+/// Replaces the content of the header packets with the serialized `headers`,
+/// returning whether they differ from the re-serialized original packets
+fn reserialize_headers(
+    headers: &CodecHeaders, id_header_packet: &mut Packet, comment_header_packet: &mut Packet,
+) -> Result<bool, Error> {
+    // We compare serialized headers rather than the values of the `OpusGains`
+    // structs because using the latter glosses over issues such as duplicate
+    // or invalid gain tags which we will fix if present. Comparing against
+    // the re-serialized original rather than the input bytes means
+    // differences such as discarded padding do not count as changes.
+    let original = CodecHeaders::parse(&id_header_packet.data, &comment_header_packet.data)?;
+    let mut original_id_header = Vec::new();
+    original.serialize_id_header(&mut original_id_header)?;
+    let mut original_comment_header = Vec::new();
+    original.serialize_comment_header(&mut original_comment_header)?;
+    // Update ID header
+    id_header_packet.data.clear();
+    headers.serialize_id_header(&mut id_header_packet.data)?;
+    // Update comment header
+    comment_header_packet.data.clear();
+    headers.serialize_comment_header(&mut comment_header_packet.data)?;
+    Ok(id_header_packet.data != original_id_header || comment_header_packet.data != original_comment_header)
+}
+
 /// Enumeration of ID and comment headers for all supported codecs
 #[derive(Clone, Debug, PartialEq)]
 pub enum CodecHeaders {
@@ -43,6 +209,20 @@ pub enum CodecHeaders {
 }
 
 impl CodecHeaders {
+    /// Parses the identification and comment header packets of a stream,
+    /// determining the codec from the identification header
+    pub fn parse(identification: &[u8], comment: &[u8]) -> Result<CodecHeaders, Error> {
+        if let Some(opus_header) = opus::IdHeader::try_parse(identification)? {
+            let comment_header = opus::CommentHeader::try_parse(comment)?;
+            return Ok(CodecHeaders::Opus(opus_header, comment_header));
+        }
+        if let Some(vorbis_header) = vorbis::IdHeader::try_parse(identification)? {
+            let comment_header = vorbis::CommentHeader::try_parse(comment)?;
+            return Ok(CodecHeaders::Vorbis(vorbis_header, comment_header));
+        }
+        Err(Error::UnknownCodec)
+    }
+
     /// Which codec are the headers for
     #[must_use]
     pub fn codec(&self) -> Codec {
@@ -52,6 +232,15 @@ impl CodecHeaders {
         }
     }
 
+    /// Have either of the headers been modified since they were parsed?
+    #[must_use]
+    pub fn is_dirty(&self) -> bool {
+        match self {
+            CodecHeaders::Opus(i, c) => i.is_dirty() || c.is_dirty(),
+            CodecHeaders::Vorbis(i, c) => i.is_dirty() || c.is_dirty(),
+        }
+    }
+
     /// Serializes the identification header into a `Write`
     pub fn serialize_id_header<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
         match self {
@@ -84,7 +273,9 @@ pub trait HeaderSummarize {
 }
 
 /// Trait for implementing `HeaderSummarize` when headers of different
-/// codecs can be treated equivalently.
+/// codecs can be treated equivalently. A `HeaderSummarizeGeneric` can be used
+/// as a `HeaderSummarize` by wrapping it in `Generic`, or by implementing
+/// `HeaderSummarize` with `summarize_headers()`.
 pub trait HeaderSummarizeGeneric {
     /// Type for summarizing header content which is reported back via
     /// `SubmitResult`
@@ -98,9 +289,21 @@ pub trait HeaderSummarizeGeneric {
     fn summarize<I: header::IdHeader, C: header::CommentHeader>(
         &self, id_header: &I, comment_header: &C,
     ) -> Result<Self::Summary, Self::Error>;
+
+    /// Summarizes the headers of any supported codec
+    fn summarize_headers(&self, headers: &CodecHeaders) -> Result<Self::Summary, Self::Error> {
+        match headers {
+            CodecHeaders::Opus(id, comment) => HeaderSummarizeGeneric::summarize(self, id, comment),
+            CodecHeaders::Vorbis(id, comment) => HeaderSummarizeGeneric::summarize(self, id, comment),
+        }
+    }
 }
 
-impl<T> HeaderSummarize for T
+/// Adapts a `HeaderSummarizeGeneric` to a `HeaderSummarize`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Generic<T>(pub T);
+
+impl<T> HeaderSummarize for Generic<T>
 where
     T: HeaderSummarizeGeneric,
 {
@@ -108,13 +311,94 @@ where
     type Summary = T::Summary;
 
     fn summarize(&self, headers: &CodecHeaders) -> Result<Self::Summary, Self::Error> {
-        match headers {
-            CodecHeaders::Opus(id, comment) => HeaderSummarizeGeneric::summarize(self, id, comment),
-            CodecHeaders::Vorbis(id, comment) => HeaderSummarizeGeneric::summarize(self, id, comment),
-        }
+        self.0.summarize_headers(headers)
     }
 }
 
+/// Conversion of errors into the error type of a rewrite. The errors of a
+/// `HeaderSummarize`, and zoog's own `Error`, must be convertible into the
+/// error type of the `HeaderRewrite` used alongside it.
+///
+/// Every type converts into itself, so nothing further is needed when all
+/// three error types are the same. Otherwise, a summarizer with its own error
+/// type can be used without the rewrite's error type wrapping zoog's:
+///
+/// ```
+/// use std::io::Cursor;
+///
+/// use zoog::header::{CommentHeader, IdHeader};
+/// use zoog::header_rewriter::{
+///     rewrite_stream, CodecHeaders, Generic, HeaderRewrite, HeaderSummarize, HeaderSummarizeGeneric, IntoRewriteError,
+///     SubmitResult,
+/// };
+///
+/// #[derive(Debug)]
+/// struct MissingTitle;
+///
+/// #[derive(Debug)]
+/// enum AppError {
+///     Stream(String),
+///     MissingTitle,
+/// }
+///
+/// impl IntoRewriteError<AppError> for zoog::Error {
+///     fn into_rewrite_error(self) -> AppError { AppError::Stream(self.to_string()) }
+/// }
+///
+/// impl IntoRewriteError<AppError> for MissingTitle {
+///     fn into_rewrite_error(self) -> AppError { AppError::MissingTitle }
+/// }
+///
+/// /// Summarizes a stream as its title
+/// struct Title;
+///
+/// impl HeaderSummarizeGeneric for Title {
+///     type Error = MissingTitle;
+///     type Summary = String;
+///
+///     fn summarize<I: IdHeader, C: CommentHeader>(&self, _: &I, comments: &C) -> Result<String, MissingTitle> {
+///         comments.get_first("TITLE").map(String::from).ok_or(MissingTitle)
+///     }
+/// }
+///
+/// // Vorbis streams are not required to have a title
+/// impl HeaderSummarize for Title {
+///     type Error = MissingTitle;
+///     type Summary = String;
+///
+///     fn summarize(&self, headers: &CodecHeaders) -> Result<String, MissingTitle> {
+///         match headers {
+///             CodecHeaders::Opus(_, _) => self.summarize_headers(headers),
+///             CodecHeaders::Vorbis(_, _) => Ok(String::new()),
+///         }
+///     }
+/// }
+///
+/// struct NoChange;
+///
+/// impl HeaderRewrite for NoChange {
+///     type Error = AppError;
+///
+///     fn rewrite(&self, _: &mut CodecHeaders) -> Result<(), AppError> { Ok(()) }
+/// }
+///
+/// fn titles(data: &[u8]) -> Result<(SubmitResult<String>, SubmitResult<String>), AppError> {
+///     let title = rewrite_stream(NoChange, Title, Cursor::new(data), std::io::sink(), true)?;
+///     let generic_title = rewrite_stream(NoChange, Generic(Title), Cursor::new(data), std::io::sink(), true)?;
+///     Ok((title, generic_title))
+/// }
+///
+/// assert!(matches!(titles(&[]), Ok((SubmitResult::Good, SubmitResult::Good))));
+/// ```
+pub trait IntoRewriteError<E> {
+    /// Performs the conversion
+    fn into_rewrite_error(self) -> E;
+}
+
+impl<E> IntoRewriteError<E> for E {
+    fn into_rewrite_error(self) -> E { self }
+}
+
 /// Trait for codec header rewriting
 pub trait HeaderRewrite {
     /// Type for errors thrown during header update
@@ -122,6 +406,12 @@ pub trait HeaderRewrite {
 
     /// Rewrites the Opus and Opus comment headers
     fn rewrite(&self, headers: &mut CodecHeaders) -> Result<(), Self::Error>;
+
+    /// What to do with a duplicate comment header following the original
+    fn duplicate_header_action(&self) -> DuplicateHeaderAction { DuplicateHeaderAction::default() }
+
+    /// Which logical stream has its headers rewritten
+    fn stream_selection(&self) -> StreamSelection { StreamSelection::default() }
 }
 
 /// Trait for implementing `HeaderRewrite` when different codecs can be treated
@@ -134,6 +424,12 @@ pub trait HeaderRewriteGeneric {
     fn rewrite<I: header::IdHeader, C: header::CommentHeader>(
         &self, id_header: &mut I, comment_header: &mut C,
     ) -> Result<(), Self::Error>;
+
+    /// What to do with a duplicate comment header following the original
+    fn duplicate_header_action(&self) -> DuplicateHeaderAction { DuplicateHeaderAction::default() }
+
+    /// Which logical stream has its headers rewritten
+    fn stream_selection(&self) -> StreamSelection { StreamSelection::default() }
 }
 
 impl<T> HeaderRewrite for T
@@ -148,6 +444,77 @@ where
             CodecHeaders::Vorbis(id, comment) => HeaderRewriteGeneric::rewrite(self, id, comment),
         }
     }
+
+    fn duplicate_header_action(&self) -> DuplicateHeaderAction { HeaderRewriteGeneric::duplicate_header_action(self) }
+
+    fn stream_selection(&self) -> StreamSelection { HeaderRewriteGeneric::stream_selection(self) }
+}
+
+/// A `HeaderRewrite` which leaves the headers unmodified. Combined with
+/// `abort_on_unchanged`, this allows a stream's headers to be summarized
+/// without reading the rest of the stream.
+#[derive(Debug, Default)]
+pub struct NoRewrite {}
+
+impl HeaderRewriteGeneric for NoRewrite {
+    type Error = Error;
+
+    fn rewrite<I: header::IdHeader, C: header::CommentHeader>(&self, _: &mut I, _: &mut C) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// The unparsed identification and comment header packets of a stream
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawHeaders {
+    pub identification: Vec<u8>,
+    pub comment: Vec<u8>,
+}
+
+impl RawHeaders {
+    /// Parses the header packets
+    pub fn parse(&self) -> Result<CodecHeaders, Error> { CodecHeaders::parse(&self.identification, &self.comment) }
+
+    /// Computes the sizes of the parts of the comment header packet
+    pub fn comment_header_sizes(&self) -> Result<CommentHeaderSizes, Error> {
+        let packet_len = self.comment.len();
+        Ok(match self.parse()? {
+            CodecHeaders::Opus(_, comment) => comment.sizes(packet_len),
+            CodecHeaders::Vorbis(_, comment) => comment.sizes(packet_len),
+        })
+    }
+}
+
+/// Reads the identification and comment header packets of the first logical
+/// stream in `input` without parsing them. Reading stops once the comment
+/// header has been read. Packets of other logical streams are ignored.
+pub fn read_raw_headers<R: Read + Seek>(input: R) -> Result<RawHeaders, Error> {
+    read_selected_raw_headers(input, StreamSelection::First)
+}
+
+/// Identical to `read_raw_headers` except that the headers are read from the
+/// logical stream chosen by `selection`. If the file contains no such stream,
+/// the error describes the selection.
+pub fn read_selected_raw_headers<R: Read + Seek>(input: R, selection: StreamSelection) -> Result<RawHeaders, Error> {
+    let mut ogg_reader = PacketReader::new(input);
+    let mut streams_seen = 0;
+    let identification = loop {
+        let packet = ogg_reader.read_packet().map_err(Error::OggDecode)?.ok_or_else(|| selection.not_found())?;
+        let index = streams_seen;
+        if packet.first_in_stream() {
+            streams_seen += 1;
+        }
+        if selection.selects(&packet, index) {
+            break packet;
+        }
+    };
+    let serial = identification.stream_serial();
+    while let Some(packet) = ogg_reader.read_packet().map_err(Error::OggDecode)? {
+        if packet.stream_serial() == serial {
+            return Ok(RawHeaders { identification: identification.data, comment: packet.data });
+        }
+    }
+    Err(Error::MissingHeaders)
 }
 
 /// Re-writes an Ogg Opus stream with modified headers
@@ -163,13 +530,19 @@ pub struct HeaderRewriter<'a, HR: HeaderRewrite, HS: HeaderSummarize, W: Write,
     packet_queue: VecDeque<Packet>,
     header_rewrite: HR,
     header_summarize: HS,
+    #[derivative(Debug = "ignore")]
+    pending_result: Option<PendingResult<HS::Summary>>,
+    duplicate_headers: u64,
+    stream_selection: StreamSelection,
+    streams_seen: usize,
     _error: PhantomData<E>,
 }
 
 impl<HR, HS, W, E> HeaderRewriter<'_, HR, HS, W, E>
 where
     HR: HeaderRewrite<Error = E>,
-    HS: HeaderSummarize<Error = E>,
+    HS: HeaderSummarize,
+    HS::Error: IntoRewriteError<E>,
     W: Write,
 {
     /// Constructs a new rewriter
@@ -177,6 +550,7 @@ where
     /// - `packet_writer` - the Ogg stream writer that the rewritten packets
     ///   will be sent to.
     pub fn new(rewrite: HR, summarize: HS, packet_writer: PacketWriter<W>) -> HeaderRewriter<HR, HS, W, E> {
+        let stream_selection = rewrite.stream_selection();
         HeaderRewriter {
             packet_writer,
             header_packet: None,
@@ -184,82 +558,122 @@ where
             packet_queue: VecDeque::new(),
             header_rewrite: rewrite,
             header_summarize: summarize,
+            pending_result: None,
+            duplicate_headers: 0,
+            stream_selection,
+            streams_seen: 0,
             _error: PhantomData,
         }
     }
 
-    fn parse_codec_headers(identification: &[u8], comment: &[u8]) -> Result<CodecHeaders, Error> {
-        if let Some(opus_header) = opus::IdHeader::try_parse(identification)? {
-            let comment_header = opus::CommentHeader::try_parse(comment)?;
-            return Ok(CodecHeaders::Opus(opus_header, comment_header));
-        }
-        if let Some(vorbis_header) = vorbis::IdHeader::try_parse(identification)? {
-            let comment_header = vorbis::CommentHeader::try_parse(comment)?;
-            return Ok(CodecHeaders::Vorbis(vorbis_header, comment_header));
-        }
-        Err(Error::UnknownCodec)
-    }
+    /// The number of packets found immediately after a comment header which
+    /// were themselves comment headers
+    #[must_use]
+    pub fn duplicate_headers(&self) -> u64 { self.duplicate_headers }
 
     /// Submits a new packet to the rewriter. If `Ready` is returned, another
     /// packet from the same stream should continue to be submitted. If
     /// `HeadersUnchanged` is returned, the supplied stream did not need
     /// any alterations. In this case, the partial output should be discarded
     /// and no further packets submitted.
+    ///
+    /// The result of rewriting the headers is returned on submitting the
+    /// packet after the comment header, once it is known whether that packet
+    /// is a duplicate of it. For a stream containing only headers, it is
+    /// returned by `finish()` instead.
     #[allow(clippy::missing_panics_doc)]
     pub fn submit(&mut self, mut packet: Packet) -> Result<SubmitResult<HS::Summary>, E>
     where
-        HR::Error: From<Error>,
+        Error: IntoRewriteError<E>,
     {
         let packet_serial = packet.stream_serial();
+        let stream_index = self.streams_seen;
+        if packet.first_in_stream() {
+            self.streams_seen += 1;
+        }
         match self.state {
-            State::AwaitingHeader => {
+            State::AwaitingHeader if self.stream_selection.selects(&packet, stream_index) => {
                 self.header_packet = Some(packet);
                 self.state = State::AwaitingComments { serial: packet_serial };
             }
             State::AwaitingComments { serial } if serial == packet_serial => {
                 // Parse Opus header
                 let mut id_header_packet = self.header_packet.take().expect("Missing header packet");
-                let (summary_before, summary_after, changed) = {
+                let (summary_before, summary_after, changed, codec) = {
                     // Parse headers
-                    let original_headers = Self::parse_codec_headers(&id_header_packet.data, &packet.data)?;
-                    let mut headers = original_headers.clone();
-                    let summary_before = self.header_summarize.summarize(&headers)?;
+                    let mut headers = CodecHeaders::parse(&id_header_packet.data, &packet.data)
+                        .map_err(IntoRewriteError::into_rewrite_error)?;
+                    let codec = headers.codec();
+                    let summarize = |headers: &CodecHeaders| {
+                        self.header_summarize.summarize(headers).map_err(IntoRewriteError::into_rewrite_error)
+                    };
+                    let summary_before = summarize(&headers)?;
                     self.header_rewrite.rewrite(&mut headers)?;
-                    let summary_after = self.header_summarize.summarize(&headers)?;
-
-                    // We compare headers rather than the values of the `OpusGains` structs because
-                    // using the latter glosses over issues such as duplicate or invalid gain tags
-                    // which we will fix if present.
-                    let changed = headers != original_headers;
-                    // Update ID header
-                    id_header_packet.data.clear();
-                    headers.serialize_id_header(&mut id_header_packet.data)?;
-                    // Update comment header
-                    packet.data.clear();
-                    headers.serialize_comment_header(&mut packet.data)?;
-                    (summary_before, summary_after, changed)
+                    let summary_after = summarize(&headers)?;
+                    // If the rewrite did not touch the headers, the original packets are
+                    // forwarded untouched without being re-serialized.
+                    let changed = headers.is_dirty()
+                        && reserialize_headers(&headers, &mut id_header_packet, &mut packet)
+                            .map_err(IntoRewriteError::into_rewrite_error)?;
+                    (summary_before, summary_after, changed, codec)
                 };
                 self.packet_queue.push_back(id_header_packet);
                 self.packet_queue.push_back(packet);
+                self.state = State::AwaitingFirstPacket { serial, codec };
+                self.pending_result = Some(PendingResult { from: summary_before, to: summary_after, changed });
+                return Ok(SubmitResult::Good);
+            }
+            State::AwaitingFirstPacket { serial, codec } if serial == packet_serial => {
+                // Only this packet is checked so that audio packets are not scanned
                 self.state = State::Forwarding;
-
-                return Ok(if changed {
-                    SubmitResult::HeadersChanged { from: summary_before, to: summary_after }
-                } else {
-                    SubmitResult::HeadersUnchanged(summary_before)
-                });
+                let mut dropped = false;
+                if is_comment_header(codec, &packet.data) {
+                    self.duplicate_headers += 1;
+                    dropped = self.header_rewrite.duplicate_header_action() == DuplicateHeaderAction::Drop
+                        && !packet.last_in_stream();
+                }
+                if !dropped {
+                    self.packet_queue.push_back(packet);
+                }
+                let mut pending = self.pending_result.take().expect("Missing header rewrite result");
+                pending.changed |= dropped;
+                return Ok(pending.into_submit_result());
             }
-            State::AwaitingComments { .. } | State::Forwarding => {
+            State::AwaitingHeader
+            | State::AwaitingComments { .. }
+            | State::AwaitingFirstPacket { .. }
+            | State::Forwarding => {
                 self.packet_queue.push_back(packet);
             }
         }
 
         while let Some(packet) = self.packet_queue.pop_front() {
-            self.write_packet(packet)?;
+            self.write_packet(packet).map_err(IntoRewriteError::into_rewrite_error)?;
         }
         Ok(SubmitResult::Good)
     }
 
+    /// Writes any packets not yet written once all packets have been
+    /// submitted. If the result of rewriting the headers has not yet been
+    /// returned by `submit()`, because no packet followed the comment header,
+    /// it is returned here. Otherwise `Good` is returned.
+    ///
+    /// If a logical stream other than the first was selected and no packet of
+    /// it was submitted, an error is returned.
+    pub fn finish(&mut self) -> Result<SubmitResult<HS::Summary>, E>
+    where
+        Error: IntoRewriteError<E>,
+    {
+        if matches!(self.state, State::AwaitingHeader) && self.stream_selection != StreamSelection::First {
+            return Err(self.stream_selection.not_found().into_rewrite_error());
+        }
+        let result = self.pending_result.take().map_or(SubmitResult::Good, PendingResult::into_submit_result);
+        while let Some(packet) = self.packet_queue.pop_front() {
+            self.write_packet(packet).map_err(IntoRewriteError::into_rewrite_error)?;
+        }
+        Ok(result)
+    }
+
     fn write_packet(&mut self, packet: Packet) -> Result<(), Error> {
         // This is an attempt to help polymorphization by moving the writer dependent
         // code into a separate function
@@ -283,40 +697,58 @@ where
     }
 }
 
-/// Convenience function for performing a rewrite.
+/// Identical to `rewrite_stream_with_interrupt` except that the progress of
+/// the rewrite is recorded in `stats`.
 ///
-/// Rewrites the headers of an Ogg Opus stream using the supplied
-/// `HeaderRewrite`. If `abort_on_unchanged` is set, the function will terminate
-/// immediately if it is detected that no headers were modified, otherwise it
-/// will continue to rewrite the stream until the input stream is exhausted, an
-/// error occurs or the interrupt condition is set.
-pub fn rewrite_stream_with_interrupt<HR, HS, R, W, I, E>(
-    rewrite: HR, summarize: HS, input: R, mut output: W, abort_on_unchanged: bool, interrupt: &I,
+/// On return, `stats` describes how far through the input the rewrite got.
+/// This is useful for reporting the point at which a rewrite was interrupted.
+pub fn rewrite_stream_with_stats<HR, HS, R, W, I, E>(
+    rewrite: HR, summarize: HS, mut input: R, mut output: W, abort_on_unchanged: bool, interrupt: &I,
+    stats: &mut RewriteStats,
 ) -> Result<SubmitResult<HS::Summary>, E>
 where
     HR: HeaderRewrite<Error = E>,
-    HS: HeaderSummarize<Error = E>,
+    HS: HeaderSummarize,
+    HS::Error: IntoRewriteError<E>,
     R: Read + Seek,
     W: Write,
     I: Interrupt,
-    E: From<Error>,
+    Error: IntoRewriteError<E>,
 {
-    let mut ogg_reader = PacketReader::new(input);
+    let read_error = |e| Error::ReadError(e).into_rewrite_error();
+    let start = input.stream_position().map_err(read_error)?;
+    let end = input.seek(SeekFrom::End(0)).map_err(read_error)?;
+    input.seek(SeekFrom::Start(start)).map_err(read_error)?;
+    *stats = RewriteStats { input_length: end.saturating_sub(start), ..RewriteStats::default() };
+
+    let position = Cell::new(start);
+    let mut ogg_reader = PacketReader::new(PositionTracker { inner: input, position: &position });
     let ogg_writer = PacketWriter::new(&mut output);
     let mut rewriter = HeaderRewriter::new(rewrite, summarize, ogg_writer);
     let mut result = SubmitResult::Good;
     loop {
         if interrupt.is_set() {
-            return Err(Error::Interrupted.into());
+            return Err(Error::Interrupted.into_rewrite_error());
         }
-        match ogg_reader.read_packet() {
-            Err(e) => break Err(Error::OggDecode(e).into()),
+        let packet = ogg_reader.read_packet();
+        stats.bytes_read = position.get().saturating_sub(start);
+        match packet {
+            Err(e) => break Err(Error::OggDecode(e).into_rewrite_error()),
             Ok(None) => {
+                let finished = rewriter.finish();
+                stats.duplicate_headers = rewriter.duplicate_headers();
+                match finished {
+                    Ok(SubmitResult::Good) => {}
+                    Ok(r) => result = r,
+                    Err(e) => break Err(e),
+                }
                 // Make sure to flush any buffered data
-                break output.flush().map(|()| result).map_err(|e| Error::WriteError(e).into());
+                break output.flush().map(|()| result).map_err(|e| Error::WriteError(e).into_rewrite_error());
             }
             Ok(Some(packet)) => {
+                stats.packets_read += 1;
                 let submit_result = rewriter.submit(packet);
+                stats.duplicate_headers = rewriter.duplicate_headers();
                 match submit_result {
                     Ok(SubmitResult::Good) => {
                         // We can continue submitting packets
@@ -339,17 +771,390 @@ where
     }
 }
 
+/// Convenience function for performing a rewrite.
+///
+/// Rewrites the headers of an Ogg Opus stream using the supplied
+/// `HeaderRewrite`. If `abort_on_unchanged` is set, the function will terminate
+/// immediately if it is detected that no headers were modified, otherwise it
+/// will continue to rewrite the stream until the input stream is exhausted, an
+/// error occurs or the interrupt condition is set.
+pub fn rewrite_stream_with_interrupt<HR, HS, R, W, I, E>(
+    rewrite: HR, summarize: HS, input: R, output: W, abort_on_unchanged: bool, interrupt: &I,
+) -> Result<SubmitResult<HS::Summary>, E>
+where
+    HR: HeaderRewrite<Error = E>,
+    HS: HeaderSummarize,
+    HS::Error: IntoRewriteError<E>,
+    R: Read + Seek,
+    W: Write,
+    I: Interrupt,
+    Error: IntoRewriteError<E>,
+{
+    let mut stats = RewriteStats::default();
+    rewrite_stream_with_stats(rewrite, summarize, input, output, abort_on_unchanged, interrupt, &mut stats)
+}
+
 /// Identical to `rewrite_stream_with_interrupt` except the rewrite loop cannot
 /// be interrupted.
+///
+/// To normalize the output gain of a stream held in memory to the R128
+/// reference level:
+///
+/// ```
+/// # #[cfg(feature = "testing")] {
+/// use std::io::Cursor;
+///
+/// use zoog::header_rewriter::{rewrite_stream, SubmitResult};
+/// use zoog::opus::VolumeAnalyzer;
+/// use zoog::testing::{opus_file, StreamSpec};
+/// use zoog::volume_rewrite::{
+///     GainsSummary, VolumeHeaderRewrite, VolumeRewriterConfig, VolumeTarget, DEFAULT_TOLERANCE,
+/// };
+/// use zoog::{Decibels, R128_LUFS};
+///
+/// let input = opus_file(&[StreamSpec::default()]);
+/// let mut analyzer = VolumeAnalyzer::default();
+/// analyzer.analyze_stream(Cursor::new(&input))?;
+/// let config = VolumeRewriterConfig::builder()
+///     .target(VolumeTarget::LUFS(R128_LUFS))
+///     .track_volume(analyzer.last_track_lufs())
+///     .build()?;
+/// let rewrite = VolumeHeaderRewrite::new(config);
+/// let mut output = Vec::new();
+/// let result = rewrite_stream(rewrite, GainsSummary::default(), Cursor::new(&input), &mut output, false)?;
+/// let SubmitResult::HeadersChanged { from, to } = result else { panic!("Gains were not changed") };
+/// assert_eq!(from.output, Decibels::default());
+/// assert!(to.is_normalized_to(VolumeTarget::LUFS(R128_LUFS), R128_LUFS, DEFAULT_TOLERANCE));
+///
+/// // Rewriting the output again changes nothing
+/// let rewrite = VolumeHeaderRewrite::new(config);
+/// let result = rewrite_stream(rewrite, GainsSummary::default(), Cursor::new(&output), std::io::sink(), true)?;
+/// assert!(matches!(result, SubmitResult::HeadersUnchanged(_)));
+/// # }
+/// # Ok::<(), zoog::Error>(())
+/// ```
 pub fn rewrite_stream<HR, HS, R, W, E>(
     rewrite: HR, summarize: HS, input: R, output: W, abort_on_unchanged: bool,
 ) -> Result<SubmitResult<HS::Summary>, E>
 where
     HR: HeaderRewrite<Error = E>,
-    HS: HeaderSummarize<Error = E>,
+    HS: HeaderSummarize,
+    HS::Error: IntoRewriteError<E>,
     R: Read + Seek,
     W: Write,
-    E: From<Error>,
+    Error: IntoRewriteError<E>,
 {
     rewrite_stream_with_interrupt(rewrite, summarize, input, output, abort_on_unchanged, &Never::default())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::comment_rewrite::{
+        CommentHeaderRewrite, CommentHeaderSummary, CommentRewriterAction, CommentRewriterConfig,
+    };
+    use crate::header::CommentList as _;
+    use crate::testing::{grouped_opus_file, opus_file, read_packets, write_packets, StreamSpec, FRAME_SIZE};
+
+    /// An interrupt which triggers after it has been polled a fixed number of
+    /// times
+    struct AfterPolls {
+        remaining: Cell<usize>,
+    }
+
+    impl Interrupt for AfterPolls {
+        fn is_set(&self) -> bool {
+            let remaining = self.remaining.get();
+            self.remaining.set(remaining.saturating_sub(1));
+            remaining == 0
+        }
+    }
+
+    fn rewrite_with_stats(
+        data: &[u8], interrupt: &impl Interrupt, stats: &mut RewriteStats,
+    ) -> Result<SubmitResult<crate::header::DiscreteCommentList>, Error> {
+        let rewrite = CommentHeaderRewrite::new(CommentRewriterConfig { action: CommentRewriterAction::NoChange });
+        let summarize = CommentHeaderSummary::default();
+        rewrite_stream_with_stats(rewrite, summarize, Cursor::new(data), std::io::sink(), false, interrupt, stats)
+    }
+
+    #[test]
+    fn stats_for_complete_rewrite() {
+        let data = opus_file(&[StreamSpec::default()]);
+        let mut stats = RewriteStats::default();
+        rewrite_with_stats(&data, &Never::default(), &mut stats).unwrap();
+        let num_packets = read_packets(&data).len() as u64;
+        let length = data.len() as u64;
+        let expected =
+            RewriteStats { packets_read: num_packets, bytes_read: length, input_length: length, duplicate_headers: 0 };
+        assert_eq!(stats, expected);
+        assert_eq!(stats.percent_complete(), Some(100.0));
+    }
+
+    #[test]
+    fn stats_for_interrupted_rewrite() {
+        let data = opus_file(&[StreamSpec { num_samples: 500 * FRAME_SIZE, ..StreamSpec::default() }]);
+        let mut stats = RewriteStats::default();
+        let interrupt = AfterPolls { remaining: Cell::new(10) };
+        let result = rewrite_with_stats(&data, &interrupt, &mut stats);
+        assert!(matches!(result, Err(Error::Interrupted)));
+        assert_eq!(stats.packets_read, 10);
+        assert!(stats.bytes_read > 0 && stats.bytes_read < stats.input_length);
+        let percent = stats.percent_complete().unwrap();
+        assert_eq!(stats.to_string(), format!("at {:.1}% (after {} bytes)", percent, stats.bytes_read));
+    }
+
+    #[test]
+    fn stats_display_for_empty_input() {
+        let stats = RewriteStats::default();
+        assert_eq!(stats.percent_complete(), None);
+        assert_eq!(stats.to_string(), "after 0 bytes");
+    }
+
+    /// Reassembles the packets of a logical stream directly from the Ogg page
+    /// structure, independently of the `ogg` crate
+    fn packets_from_pages(data: &[u8], serial: u32) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+        let mut current = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            assert_eq!(&data[offset..offset + 4], b"OggS");
+            let page_serial = u32::from_le_bytes(data[offset + 14..offset + 18].try_into().unwrap());
+            let num_segments = usize::from(data[offset + 26]);
+            let lacing = &data[offset + 27..offset + 27 + num_segments];
+            let mut body_offset = offset + 27 + num_segments;
+            for &length in lacing {
+                let length = usize::from(length);
+                if page_serial == serial {
+                    current.extend_from_slice(&data[body_offset..body_offset + length]);
+                    if length < 255 {
+                        packets.push(std::mem::take(&mut current));
+                    }
+                }
+                body_offset += length;
+            }
+            offset = body_offset;
+        }
+        packets
+    }
+
+    #[test]
+    fn raw_headers_match_on_disk_packets() {
+        // A comment header large enough to span several pages
+        let mut comment = crate::testing::comment_header("zoog-test", &[], &[]);
+        comment.extend((0..200_000_u32).map(|i| (i % 251) as u8));
+        let id_header = crate::testing::opus_id_header(2, 48000, 312, -256);
+        let mut data = Vec::new();
+        let mut writer = PacketWriter::new(&mut data);
+        let serial = 0x1234;
+        writer.write_packet(id_header.clone(), serial, PacketWriteEndInfo::EndPage, 0).unwrap();
+        writer.write_packet(b"interleaved".to_vec(), 7, PacketWriteEndInfo::EndStream, 0).unwrap();
+        writer.write_packet(comment.clone(), serial, PacketWriteEndInfo::EndStream, 0).unwrap();
+        drop(writer);
+
+        let on_disk = packets_from_pages(&data, serial);
+        assert_eq!(on_disk, vec![id_header, comment]);
+        let raw = read_raw_headers(Cursor::new(data)).unwrap();
+        assert_eq!(raw, RawHeaders { identification: on_disk[0].clone(), comment: on_disk[1].clone() });
+    }
+
+    #[test]
+    fn raw_headers_of_truncated_stream() {
+        let packets = read_packets(&opus_file(&[StreamSpec::default()]));
+        let mut data = Vec::new();
+        let mut writer = PacketWriter::new(&mut data);
+        writer.write_packet(packets[0].data.clone(), 1, PacketWriteEndInfo::EndStream, 0).unwrap();
+        drop(writer);
+        assert!(matches!(read_raw_headers(Cursor::new(data)), Err(Error::MissingHeaders)));
+    }
+
+    /// Writes `packets` as an Ogg file, replacing the data of the second
+    /// (comment header) packet with `comment`
+    fn with_comment_header(mut packets: Vec<Packet>, comment: &[u8]) -> Vec<u8> {
+        packets[1].data = comment.to_vec();
+        write_packets(&packets)
+    }
+
+    /// Rewrites `data`, checking the headers changed, and returns the raw
+    /// headers of the output
+    fn rewrite_to_raw_headers<HR: HeaderRewrite<Error = Error>>(data: &[u8], rewrite: HR) -> Result<RawHeaders, Error> {
+        let mut output = Vec::new();
+        let summarize = CommentHeaderSummary::default();
+        let result = rewrite_stream(rewrite, summarize, Cursor::new(data), &mut output, false)?;
+        assert!(matches!(result, SubmitResult::HeadersChanged { .. }));
+        assert_eq!(read_packets(&output).len(), read_packets(data).len());
+        read_raw_headers(Cursor::new(output))
+    }
+
+    fn append_tag_rewrite() -> CommentHeaderRewrite<'static> {
+        let mut append = crate::header::DiscreteCommentList::default();
+        append.push("TITLE", "Rewritten").unwrap();
+        let retain = Box::new(|_: &str, _: &str| true);
+        CommentHeaderRewrite::new(CommentRewriterConfig { action: CommentRewriterAction::Modify { retain, append } })
+    }
+
+    #[test]
+    fn opus_experimental_suffix_survives_rewrites() -> Result<(), Error> {
+        use crate::volume_rewrite::{VolumeHeaderRewrite, VolumeRewriterConfig, VolumeTarget};
+        use crate::R128_LUFS;
+
+        // The set LSB of the first byte marks the suffix as data to preserve
+        let suffix = b"\x01experimental\x00data".to_vec();
+        let mut comment = crate::testing::comment_header("zoog-test", &[], &[]);
+        comment.extend(&suffix);
+        let data = with_comment_header(read_packets(&opus_file(&[StreamSpec::default()])), &comment);
+        assert!(read_raw_headers(Cursor::new(&data[..]))?.comment.ends_with(&suffix));
+
+        let config = VolumeRewriterConfig::builder()
+            .target(VolumeTarget::LUFS(R128_LUFS))
+            .track_volume(Some(crate::Decibels::from(-20.0)))
+            .build()?;
+        let gain_change = VolumeHeaderRewrite::new(config);
+        for raw in [rewrite_to_raw_headers(&data, gain_change)?, rewrite_to_raw_headers(&data, append_tag_rewrite())?] {
+            assert!(raw.comment.ends_with(&suffix));
+            assert_eq!(opus::CommentHeader::try_parse(&raw.comment)?.suffix_data(), suffix);
+        }
+        Ok(())
+    }
+
+    /// Constructs a minimal Ogg Vorbis stream with the supplied comment header
+    fn vorbis_file(comment: Vec<u8>) -> Vec<u8> {
+        let mut id_header = b"\x01vorbis".to_vec();
+        id_header.extend([0, 0, 0, 0, 2, 0x44, 0xac, 0, 0]);
+        id_header.extend([0; 12]);
+        id_header.extend([0xb8, 1]);
+        let mut data = Vec::new();
+        let mut writer = PacketWriter::new(&mut data);
+        writer.write_packet(id_header, 5, PacketWriteEndInfo::EndPage, 0).unwrap();
+        writer.write_packet(comment, 5, PacketWriteEndInfo::NormalPacket, 0).unwrap();
+        writer.write_packet(b"\x05vorbis setup".to_vec(), 5, PacketWriteEndInfo::EndStream, 0).unwrap();
+        drop(writer);
+        data
+    }
+
+    #[test]
+    fn vorbis_framing_bit_survives_rewrite() -> Result<(), Error> {
+        let mut comment = Vec::new();
+        vorbis::CommentHeader::default().serialize_into(&mut comment)?;
+        assert_eq!(comment.last(), Some(&1));
+        let data = vorbis_file(comment);
+
+        let raw = rewrite_to_raw_headers(&data, append_tag_rewrite())?;
+        assert_eq!(raw.comment.last(), Some(&1));
+        let header = vorbis::CommentHeader::try_parse(&raw.comment)?;
+        assert_eq!(header.get_first("TITLE"), Some("Rewritten"));
+        Ok(())
+    }
+
+    #[test]
+    fn vorbis_trailing_data_survives_rewrites() -> Result<(), Error> {
+        let mut header = vorbis::CommentHeader::default();
+        header.push("ARTIST", "Someone")?;
+        let mut comment = Vec::new();
+        header.serialize_into(&mut comment)?;
+        let trailing = b"\x00\xabtrailing";
+        comment.extend(trailing);
+        let data = vorbis_file(comment.clone());
+
+        // A modification which changes nothing leaves the file identical
+        let empty_modify = CommentRewriterAction::Modify {
+            retain: Box::new(|_, _| true),
+            append: crate::header::DiscreteCommentList::default(),
+        };
+        let rewrite = CommentHeaderRewrite::new(CommentRewriterConfig { action: empty_modify });
+        let mut output = Vec::new();
+        let result =
+            rewrite_stream(rewrite, CommentHeaderSummary::default(), Cursor::new(&data[..]), &mut output, false)?;
+        assert!(matches!(result, SubmitResult::HeadersUnchanged(_)));
+        assert_eq!(output, data);
+
+        let raw = rewrite_to_raw_headers(&data, append_tag_rewrite())?;
+        assert!(raw.comment.ends_with(trailing));
+        assert_eq!(vorbis::CommentHeader::try_parse(&raw.comment)?.trailing_data(), trailing);
+        Ok(())
+    }
+
+    #[test]
+    fn padding_only_difference_is_unchanged() -> Result<(), Error> {
+        // Padding has a clear LSB so is discarded when the header is parsed
+        let mut comment = crate::testing::comment_header("zoog-test", &[], &[]);
+        comment.extend([0xfe; 64]);
+        let data = with_comment_header(read_packets(&opus_file(&[StreamSpec::default()])), &comment);
+        let empty_modify = CommentRewriterAction::Modify {
+            retain: Box::new(|_, _| true),
+            append: crate::header::DiscreteCommentList::default(),
+        };
+        for action in [CommentRewriterAction::NoChange, empty_modify] {
+            let rewrite = CommentHeaderRewrite::new(CommentRewriterConfig { action });
+            let summarize = CommentHeaderSummary::default();
+            let result = rewrite_stream(rewrite, summarize, Cursor::new(&data[..]), std::io::sink(), true)?;
+            assert!(matches!(result, SubmitResult::HeadersUnchanged(_)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn untouched_headers_are_forwarded_verbatim() -> Result<(), Error> {
+        let mut comment = crate::testing::comment_header("zoog-test", &[], &[]);
+        comment.extend([0xfe; 64]);
+        let data = with_comment_header(read_packets(&opus_file(&[StreamSpec::default()])), &comment);
+        let rewrite = CommentHeaderRewrite::new(CommentRewriterConfig { action: CommentRewriterAction::NoChange });
+        let summarize = CommentHeaderSummary::default();
+        let mut output = Vec::new();
+        let result = rewrite_stream(rewrite, summarize, Cursor::new(&data[..]), &mut output, false)?;
+        assert!(matches!(result, SubmitResult::HeadersUnchanged(_)));
+        // The padding would have been discarded had the header been re-serialized
+        assert_eq!(read_raw_headers(Cursor::new(output))?, read_raw_headers(Cursor::new(data))?);
+        Ok(())
+    }
+
+    #[test]
+    fn only_selected_stream_is_rewritten() -> Result<(), Error> {
+        let specs =
+            [StreamSpec { serial: 1, ..StreamSpec::default() }, StreamSpec { serial: 2, ..StreamSpec::default() }];
+        let data = grouped_opus_file(&specs);
+        let original_first = read_raw_headers(Cursor::new(&data[..]))?;
+        for selection in [StreamSelection::Serial(2), StreamSelection::Index(1)] {
+            let rewrite = append_tag_rewrite().with_stream_selection(selection);
+            let mut output = Vec::new();
+            let result =
+                rewrite_stream(rewrite, CommentHeaderSummary::default(), Cursor::new(&data[..]), &mut output, false)?;
+            assert!(matches!(result, SubmitResult::HeadersChanged { .. }));
+            assert_eq!(read_packets(&output).len(), read_packets(&data).len());
+            assert_eq!(read_raw_headers(Cursor::new(&output[..]))?, original_first);
+            let second = read_selected_raw_headers(Cursor::new(&output[..]), StreamSelection::Serial(2))?;
+            assert_eq!(opus::CommentHeader::try_parse(&second.comment)?.get_first("TITLE"), Some("Rewritten"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn missing_selected_stream_is_an_error() {
+        let data = opus_file(&[StreamSpec { serial: 1, ..StreamSpec::default() }]);
+        let selections = [(StreamSelection::Serial(9), "serial 0x9"), (StreamSelection::Index(1), "index 1")];
+        for (selection, expected) in selections {
+            let rewrite = append_tag_rewrite().with_stream_selection(selection);
+            let summarize = CommentHeaderSummary::default();
+            let result = rewrite_stream(rewrite, summarize, Cursor::new(&data[..]), std::io::sink(), true);
+            let error = result.unwrap_err().to_string();
+            assert!(error.contains(expected), "{}", error);
+            let error = read_selected_raw_headers(Cursor::new(&data[..]), selection).unwrap_err().to_string();
+            assert!(error.contains(expected), "{}", error);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() -> Result<(), serde_json::Error> {
+        let result = SubmitResult::HeadersChanged { from: 1, to: 2 };
+        let json = serde_json::to_string(&result)?;
+        assert!(matches!(serde_json::from_str(&json)?, SubmitResult::HeadersChanged { from: 1, to: 2 }));
+
+        let result = SubmitResult::HeadersUnchanged(3);
+        let json = serde_json::to_string(&result)?;
+        assert!(matches!(serde_json::from_str(&json)?, SubmitResult::HeadersUnchanged(3)));
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use crate::{ogg_seek, Error};
+
+/// Extensions, compared case-insensitively, of files taken to be Ogg files
+/// without examining their contents
+pub const OGG_EXTENSIONS: &[&str] = &["opus", "ogg", "oga"];
+
+/// Whether the file at `path` should be treated as an Ogg file when expanding
+/// a directory. Files with one of `OGG_EXTENSIONS` are accepted, as are files
+/// which begin with the Ogg capture pattern whatever their name. Files which
+/// cannot be read are not accepted.
+#[must_use]
+pub fn is_ogg_candidate(path: &Path) -> bool {
+    let known_extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| OGG_EXTENSIONS.iter().any(|known| known.eq_ignore_ascii_case(extension)));
+    known_extension
+        || File::open(path).is_ok_and(|mut file| ogg_seek::starts_with_capture_pattern(&mut file).unwrap_or(false))
+}
+
+/// Returns the Ogg files immediately inside the directory `dir`, as judged by
+/// `is_ogg_candidate`, sorted by path. Subdirectories are not searched.
+pub fn expand_directory(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let read_error = |e| Error::DirectoryReadError(dir.to_path_buf(), e);
+    let mut result = Vec::new();
+    for entry in fs::read_dir(dir).map_err(read_error)? {
+        let path = entry.map_err(read_error)?.path();
+        if path.is_file() && is_ogg_candidate(&path) {
+            result.push(path);
+        }
+    }
+    result.sort();
+    Ok(result)
+}
+
+/// The result of `expand_inputs`
+#[derive(Clone, Debug, Default)]
+pub struct ExpandedInputs {
+    /// The input files, with each directory replaced by the files it contains
+    pub files: Vec<PathBuf>,
+
+    /// Directories which contained no Ogg files
+    pub empty_directories: Vec<PathBuf>,
+}
+
+/// Replaces each directory in `inputs` with the Ogg files immediately inside
+/// it, as returned by `expand_directory`. Other paths, including those which
+/// do not exist, are kept as they are so that any problem with them is
+/// reported when they are processed.
+pub fn expand_inputs<I: IntoIterator<Item = PathBuf>>(inputs: I) -> Result<ExpandedInputs, Error> {
+    let mut result = ExpandedInputs::default();
+    for input in inputs {
+        if input.is_dir() {
+            let files = expand_directory(&input)?;
+            if files.is_empty() {
+                result.empty_directories.push(input);
+            }
+            result.files.extend(files);
+        } else {
+            result.files.push(input);
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directories_are_expanded_one_level() -> Result<(), Error> {
+        let dir = tempfile::tempdir().unwrap();
+        let album = dir.path().join("album");
+        let nested = album.join("disc 2");
+        let empty = dir.path().join("empty");
+        for path in [&nested, &empty] {
+            fs::create_dir_all(path).unwrap();
+        }
+        for name in ["b.opus", "a.OGG", "c.oga", "cover.jpg", "notes.txt"] {
+            fs::write(album.join(name), b"data").unwrap();
+        }
+        fs::write(album.join("misnamed.bin"), b"OggS\0").unwrap();
+        fs::write(nested.join("d.opus"), b"data").unwrap();
+        let single = dir.path().join("single.opus");
+        let missing = dir.path().join("missing.opus");
+
+        let expanded = expand_inputs([single.clone(), album.clone(), empty.clone(), missing.clone()])?;
+        let expected = [
+            single,
+            album.join("a.OGG"),
+            album.join("b.opus"),
+            album.join("c.oga"),
+            album.join("misnamed.bin"),
+            missing,
+        ];
+        assert_eq!(expanded.files, expected);
+        assert_eq!(expanded.empty_directories, [empty]);
+        Ok(())
+    }
+}
@@ -1,9 +1,22 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
 /// Allows reading the status of a potential interrupt
 pub trait Interrupt {
-    /// Has the interrupt been triggered?
+    /// Has the interrupt been triggered? This is checked frequently (e.g. once
+    /// per Ogg packet) so implementations should be cheap.
     fn is_set(&self) -> bool;
 }
 
+impl<I: Interrupt + ?Sized> Interrupt for &I {
+    fn is_set(&self) -> bool { (**self).is_set() }
+}
+
+impl<I: Interrupt + ?Sized> Interrupt for Box<I> {
+    fn is_set(&self) -> bool { (**self).is_set() }
+}
+
 /// An interrupt that is never triggered
 #[derive(Debug, Default)]
 pub struct Never {}
@@ -11,3 +24,168 @@ pub struct Never {}
 impl Interrupt for Never {
     fn is_set(&self) -> bool { false }
 }
+
+/// An interrupt triggered from elsewhere via an `InterruptHandle`
+#[derive(Clone, Debug, Default)]
+pub struct FlagInterrupt(Arc<AtomicBool>);
+
+impl FlagInterrupt {
+    /// Constructs an interrupt which is initially not set
+    #[must_use]
+    pub fn new() -> FlagInterrupt { FlagInterrupt::default() }
+
+    /// Returns a handle which can be used to trigger this interrupt
+    #[must_use]
+    pub fn handle(&self) -> InterruptHandle { InterruptHandle(Arc::clone(&self.0)) }
+}
+
+impl Interrupt for FlagInterrupt {
+    fn is_set(&self) -> bool { self.0.load(Ordering::Relaxed) }
+}
+
+/// Used to trigger a `FlagInterrupt`, possibly from another thread
+#[derive(Clone, Debug)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    /// Triggers the associated interrupt
+    pub fn set(&self) { self.0.store(true, Ordering::Relaxed); }
+}
+
+/// An interrupt triggered once the specified instant has passed
+#[derive(Clone, Copy, Debug)]
+pub struct DeadlineInterrupt(pub Instant);
+
+impl DeadlineInterrupt {
+    /// Constructs an interrupt which triggers once `duration` has elapsed from
+    /// now
+    #[must_use]
+    pub fn after(duration: Duration) -> DeadlineInterrupt { DeadlineInterrupt(Instant::now() + duration) }
+}
+
+impl Interrupt for DeadlineInterrupt {
+    fn is_set(&self) -> bool { Instant::now() >= self.0 }
+}
+
+/// An interrupt which is set when any of its children are
+#[derive(Default)]
+pub struct CombinedInterrupt {
+    children: Vec<Box<dyn Interrupt + Send + Sync>>,
+}
+
+impl CombinedInterrupt {
+    /// Constructs an interrupt with no children, which is never set
+    #[must_use]
+    pub fn new() -> CombinedInterrupt { CombinedInterrupt::default() }
+
+    /// Adds a child interrupt
+    pub fn push<I: Interrupt + Send + Sync + 'static>(&mut self, interrupt: I) {
+        self.children.push(Box::new(interrupt));
+    }
+
+    /// Adds a child interrupt, returning the combined interrupt
+    #[must_use]
+    pub fn with<I: Interrupt + Send + Sync + 'static>(mut self, interrupt: I) -> CombinedInterrupt {
+        self.push(interrupt);
+        self
+    }
+}
+
+impl std::fmt::Debug for CombinedInterrupt {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.debug_struct("CombinedInterrupt").field("num_children", &self.children.len()).finish()
+    }
+}
+
+impl Interrupt for CombinedInterrupt {
+    fn is_set(&self) -> bool { self.children.iter().any(Interrupt::is_set) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+
+    use super::*;
+    use crate::opus::VolumeAnalyzer;
+    use crate::testing::{opus_file, StreamSpec, FRAME_SIZE};
+    use crate::Error;
+
+    /// A reader which waits for a deadline to pass after its first read
+    struct StallingReader<R> {
+        inner: R,
+        deadline: Instant,
+        num_reads: usize,
+    }
+
+    impl<R: Read> Read for StallingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.num_reads += 1;
+            if self.num_reads > 1 {
+                while Instant::now() < self.deadline {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+            self.inner.read(buf)
+        }
+    }
+
+    impl<R: Seek> Seek for StallingReader<R> {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> { self.inner.seek(pos) }
+    }
+
+    #[test]
+    fn flag_interrupt_set_via_handle() {
+        let interrupt = FlagInterrupt::new();
+        let handle = interrupt.handle();
+        assert!(!interrupt.is_set());
+        std::thread::spawn(move || handle.set()).join().unwrap();
+        assert!(interrupt.is_set());
+    }
+
+    #[test]
+    fn deadline_interrupt() {
+        assert!(DeadlineInterrupt(Instant::now()).is_set());
+        assert!(!DeadlineInterrupt::after(Duration::from_secs(3600)).is_set());
+    }
+
+    #[test]
+    fn combined_interrupt_set_by_any_child() {
+        let flag = FlagInterrupt::new();
+        let combined = CombinedInterrupt::new()
+            .with(Never::default())
+            .with(flag.clone())
+            .with(DeadlineInterrupt::after(Duration::from_secs(3600)));
+        assert!(!combined.is_set());
+        flag.handle().set();
+        assert!(combined.is_set());
+        assert!(!CombinedInterrupt::new().is_set());
+    }
+
+    #[test]
+    fn deadline_mid_stream_interrupts_rewrite() {
+        use crate::comment_rewrite::{
+            CommentHeaderRewrite, CommentHeaderSummary, CommentRewriterAction, CommentRewriterConfig,
+        };
+        use crate::header_rewriter::rewrite_stream_with_interrupt;
+
+        let data = opus_file(&[StreamSpec { num_samples: 500 * FRAME_SIZE, ..StreamSpec::default() }]);
+        let interrupt = DeadlineInterrupt::after(Duration::from_millis(50));
+        let mut reader = StallingReader { inner: Cursor::new(data), deadline: interrupt.0, num_reads: 0 };
+        let rewrite = CommentHeaderRewrite::new(CommentRewriterConfig { action: CommentRewriterAction::NoChange });
+        let summarize = CommentHeaderSummary::default();
+        let result = rewrite_stream_with_interrupt(rewrite, summarize, &mut reader, std::io::sink(), false, &interrupt);
+        assert!(matches!(result, Err(Error::Interrupted)));
+        assert!(reader.num_reads > 1);
+    }
+
+    #[test]
+    fn deadline_mid_stream_interrupts_analysis() {
+        let data = opus_file(&[StreamSpec { num_samples: 500 * FRAME_SIZE, ..StreamSpec::default() }]);
+        let interrupt = DeadlineInterrupt::after(Duration::from_millis(50));
+        let mut reader = StallingReader { inner: Cursor::new(data), deadline: interrupt.0, num_reads: 0 };
+        let mut analyzer = VolumeAnalyzer::default();
+        let result = analyzer.analyze_stream_with_interrupt(&mut reader, &interrupt);
+        assert!(matches!(result, Err(Error::Interrupted)));
+        assert!(reader.num_reads > 1);
+    }
+}
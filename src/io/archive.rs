@@ -0,0 +1,207 @@
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use zip::result::ZipError;
+use zip::{CompressionMethod, ZipArchive};
+
+use super::{InputSource, ReadSeek};
+use crate::Error;
+
+/// The default limit on the size of a compressed file inside an archive, which
+/// must be decompressed into memory to be read
+pub const DEFAULT_MAX_DECOMPRESSED_SIZE: u64 = 256 * 1024 * 1024;
+
+/// A file inside a zip archive, which can only be read. Files stored without
+/// compression are read in place. Compressed files cannot be seeked within so
+/// are decompressed into memory, up to a size limit.
+#[derive(Clone, Debug)]
+pub struct ArchiveSource {
+    name: PathBuf,
+    archive: PathBuf,
+    member: String,
+    max_decompressed_size: u64,
+}
+
+impl ArchiveSource {
+    /// Constructs a source for the file `member` of the archive at `archive`.
+    /// `name` is the name of the input as given by the user.
+    #[must_use]
+    pub fn new<N: Into<PathBuf>, A: Into<PathBuf>>(name: N, archive: A, member: String) -> ArchiveSource {
+        ArchiveSource {
+            name: name.into(),
+            archive: archive.into(),
+            member,
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+        }
+    }
+
+    /// Sets the largest compressed file that will be decompressed into memory
+    #[must_use]
+    pub fn with_max_decompressed_size(self, max_decompressed_size: u64) -> ArchiveSource {
+        ArchiveSource { max_decompressed_size, ..self }
+    }
+
+    fn open_archive(&self) -> Result<File, Error> {
+        File::open(&self.archive).map_err(|e| Error::FileOpenError(self.archive.clone(), e))
+    }
+
+    fn archive_error(&self, error: &ZipError) -> Error { Error::ArchiveError(self.name.clone(), error.to_string()) }
+}
+
+impl InputSource for ArchiveSource {
+    fn name(&self) -> &Path { &self.name }
+
+    fn open(&self) -> Result<Box<dyn ReadSeek>, Error> {
+        let mut archive = ZipArchive::new(BufReader::new(self.open_archive()?)).map_err(|e| self.archive_error(&e))?;
+        let member = archive.by_name(&self.member).map_err(|e| self.archive_error(&e))?;
+        if member.compression() == CompressionMethod::Stored {
+            let reader = StoredReader {
+                file: self.open_archive()?,
+                start: member.data_start(),
+                len: member.size(),
+                position: 0,
+            };
+            return Ok(Box::new(reader));
+        }
+        if member.size() > self.max_decompressed_size {
+            return Err(Error::ArchiveMemberTooLarge(self.name.clone(), self.max_decompressed_size));
+        }
+        // The declared size is not trusted when decompressing
+        let mut data = Vec::new();
+        member
+            .take(self.max_decompressed_size + 1)
+            .read_to_end(&mut data)
+            .map_err(|e| Error::FileReadError(self.name.clone(), e))?;
+        if data.len() as u64 > self.max_decompressed_size {
+            return Err(Error::ArchiveMemberTooLarge(self.name.clone(), self.max_decompressed_size));
+        }
+        Ok(Box::new(Cursor::new(data)))
+    }
+}
+
+/// Reads a file stored without compression directly from its archive
+struct StoredReader {
+    file: File,
+    start: u64,
+    len: u64,
+    position: u64,
+}
+
+impl Read for StoredReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.position);
+        let count = usize::try_from(remaining).map_or(buf.len(), |remaining| remaining.min(buf.len()));
+        if count == 0 {
+            return Ok(0);
+        }
+        self.file.seek(SeekFrom::Start(self.start + self.position))?;
+        let count = self.file.read(&mut buf[..count])?;
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for StoredReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+        }
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")
+        })?;
+        self.position = position;
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    use super::*;
+    use crate::header_rewriter::read_raw_headers;
+    use crate::opus::VolumeAnalyzer;
+    use crate::testing::{opus_file, StreamSpec};
+
+    /// Writes an archive containing `data` stored both with and without
+    /// compression
+    fn write_archive(path: &Path, data: &[u8]) {
+        let mut writer = ZipWriter::new(File::create(path).unwrap());
+        for (name, method) in
+            [("stored.opus", CompressionMethod::Stored), ("deflated.opus", CompressionMethod::Deflated)]
+        {
+            writer.start_file(format!("inner/{}", name), FileOptions::default().compression_method(method)).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn source(archive: &Path, member: &str) -> ArchiveSource {
+        ArchiveSource::new(format!("{}!{}", archive.display(), member), archive, member.to_string())
+    }
+
+    #[test]
+    fn members_are_read() -> Result<(), Error> {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("album.zip");
+        let data = opus_file(&[StreamSpec::default()]);
+        write_archive(&archive, &data);
+        for member in ["inner/stored.opus", "inner/deflated.opus"] {
+            let mut read = Vec::new();
+            source(&archive, member).open()?.read_to_end(&mut read).unwrap();
+            assert_eq!(read, data, "Contents of {} differ", member);
+
+            read_raw_headers(source(&archive, member).open()?)?.parse()?;
+            let mut analyzer = VolumeAnalyzer::default();
+            analyzer.analyze_stream(source(&archive, member).open()?)?;
+            assert_eq!(analyzer.track_lufs().len(), 1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn stored_members_can_be_seeked() -> Result<(), Error> {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("album.zip");
+        let data: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+        write_archive(&archive, &data);
+        let mut reader = source(&archive, "inner/stored.opus").open()?;
+        assert_eq!(reader.seek(SeekFrom::End(-100)).unwrap(), 9_900);
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, data[9_900..]);
+        reader.seek(SeekFrom::Start(5)).unwrap();
+        let mut byte = [0u8];
+        reader.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], data[5]);
+        Ok(())
+    }
+
+    #[test]
+    fn large_compressed_members_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("album.zip");
+        write_archive(&archive, &[0; 1000]);
+        let result = source(&archive, "inner/deflated.opus").with_max_decompressed_size(999).open();
+        assert!(matches!(result, Err(Error::ArchiveMemberTooLarge(_, 999))));
+        assert!(source(&archive, "inner/deflated.opus").with_max_decompressed_size(1000).open().is_ok());
+        // Stored members are not held in memory so have no limit
+        assert!(source(&archive, "inner/stored.opus").with_max_decompressed_size(999).open().is_ok());
+    }
+
+    #[test]
+    fn missing_members_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("album.zip");
+        write_archive(&archive, &[0; 10]);
+        assert!(matches!(source(&archive, "missing.opus").open(), Err(Error::ArchiveError(_, _))));
+        let missing = dir.path().join("missing.zip");
+        assert!(matches!(source(&missing, "inner/stored.opus").open(), Err(Error::FileOpenError(_, _))));
+    }
+}
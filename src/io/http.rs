@@ -0,0 +1,175 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use super::{InputSource, ReadSeek};
+use crate::Error;
+
+/// HTTP status for a response containing only the requested range
+const PARTIAL_CONTENT: u16 = 206;
+
+/// A file served over HTTP or HTTPS. Seeking issues a new range request, so
+/// reading only part of the file requires a server that supports them.
+#[derive(Clone, Debug)]
+pub struct HttpSource {
+    name: PathBuf,
+    agent: ureq::Agent,
+}
+
+impl HttpSource {
+    /// Constructs a source for the specified URL
+    #[must_use]
+    pub fn new<P: Into<PathBuf>>(url: P) -> HttpSource { HttpSource { name: url.into(), agent: ureq::Agent::new() } }
+}
+
+impl InputSource for HttpSource {
+    fn name(&self) -> &Path { &self.name }
+
+    fn open(&self) -> Result<Box<dyn ReadSeek>, Error> {
+        let url = self.name.to_string_lossy().into_owned();
+        let reader =
+            HttpReader::open(self.agent.clone(), url).map_err(|e| Error::FileOpenError(self.name.clone(), e))?;
+        Ok(Box::new(reader))
+    }
+}
+
+/// Reads the body of a URL, starting a new request from the current position
+/// after each seek
+struct HttpReader {
+    agent: ureq::Agent,
+    url: String,
+    position: u64,
+    length: Option<u64>,
+    body: Option<Box<dyn Read + Send + Sync>>,
+}
+
+impl HttpReader {
+    fn open(agent: ureq::Agent, url: String) -> io::Result<HttpReader> {
+        let mut result = HttpReader { agent, url, position: 0, length: None, body: None };
+        let response = result.agent.get(&result.url).call().map_err(|e| io::Error::other(e.to_string()))?;
+        result.length = response.header("Content-Length").and_then(|length| length.parse().ok());
+        result.body = Some(response.into_reader());
+        Ok(result)
+    }
+
+    fn request_from_position(&self) -> io::Result<Box<dyn Read + Send + Sync>> {
+        let range = format!("bytes={}-", self.position);
+        let response =
+            self.agent.get(&self.url).set("Range", &range).call().map_err(|e| io::Error::other(e.to_string()))?;
+        if self.position > 0 && response.status() != PARTIAL_CONTENT {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "server does not support range requests"));
+        }
+        Ok(response.into_reader())
+    }
+}
+
+impl Read for HttpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.length.is_some_and(|length| self.position >= length) {
+            return Ok(0);
+        }
+        let body = match self.body.take() {
+            Some(body) => body,
+            None => self.request_from_position()?,
+        };
+        let body = self.body.insert(body);
+        let count = body.read(buf)?;
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for HttpReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let invalid =
+            || io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position");
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => {
+                let length = self.length.ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::Unsupported, "server did not report the length of the file")
+                })?;
+                length.checked_add_signed(offset)
+            }
+        }
+        .ok_or_else(invalid)?;
+        if position != self.position {
+            self.position = position;
+            self.body = None;
+        }
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+
+    /// Serves `data` to each connection, honouring a `Range` header of the form
+    /// `bytes=N-` if `ranges` is set
+    fn serve(data: Vec<u8>, ranges: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut start = 0;
+                for line in BufReader::new(&stream).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(range) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                        start = range.trim_end_matches('-').parse().unwrap();
+                    }
+                }
+                let (status, body) =
+                    if ranges && start > 0 { ("206 Partial Content", &data[start..]) } else { ("200 OK", &data[..]) };
+                // The client may hang up early after seeking elsewhere
+                let header =
+                    format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len());
+                let _ = stream.write_all(header.as_bytes()).and_then(|()| stream.write_all(body));
+            }
+        });
+        format!("http://{}/file.opus", address)
+    }
+
+    #[test]
+    fn reads_whole_file() -> Result<(), Error> {
+        let data: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+        let source = HttpSource::new(serve(data.clone(), true));
+        let mut read = Vec::new();
+        source.open()?.read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+        Ok(())
+    }
+
+    #[test]
+    fn seeks_with_range_requests() -> Result<(), Error> {
+        let data: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+        let source = HttpSource::new(serve(data.clone(), true));
+        let mut reader = source.open()?;
+        assert_eq!(reader.seek(SeekFrom::End(-100)).unwrap(), 9_900);
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, data[9_900..]);
+        reader.seek(SeekFrom::Start(5)).unwrap();
+        let mut byte = [0u8];
+        reader.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], data[5]);
+        Ok(())
+    }
+
+    #[test]
+    fn seeking_requires_range_support() -> Result<(), Error> {
+        let source = HttpSource::new(serve(vec![0; 1000], false));
+        let mut reader = source.open()?;
+        reader.seek(SeekFrom::Start(500)).unwrap();
+        let error = reader.read(&mut [0u8; 16]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+        Ok(())
+    }
+}
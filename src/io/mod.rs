@@ -0,0 +1,207 @@
+#[cfg(feature = "archive")]
+mod archive;
+#[cfg(feature = "http")]
+mod http;
+
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "archive")]
+pub use archive::*;
+#[cfg(feature = "http")]
+pub use http::*;
+
+use crate::Error;
+
+/// A readable and seekable stream. Analysis of part of a file requires
+/// seeking, so inputs must support both.
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// Somewhere an Ogg file can be read from
+pub trait InputSource: Send + Sync {
+    /// The name of the input, as used in messages and reports
+    fn name(&self) -> &Path;
+
+    /// Opens the input for reading from its start
+    fn open(&self) -> Result<Box<dyn ReadSeek>, Error>;
+}
+
+/// A file on the local filesystem
+#[derive(Clone, Debug)]
+pub struct FileSource {
+    path: PathBuf,
+}
+
+impl FileSource {
+    /// Constructs a source for the file at `path`
+    #[must_use]
+    pub fn new<P: Into<PathBuf>>(path: P) -> FileSource { FileSource { path: path.into() } }
+}
+
+impl InputSource for FileSource {
+    fn name(&self) -> &Path { &self.path }
+
+    fn open(&self) -> Result<Box<dyn ReadSeek>, Error> {
+        let file = File::open(&self.path).map_err(|e| Error::FileOpenError(self.path.clone(), e))?;
+        Ok(Box::new(file))
+    }
+}
+
+/// Whether an input named on the command line is an HTTP or HTTPS URL rather
+/// than a path
+#[must_use]
+pub fn is_url(input: &Path) -> bool {
+    input.to_str().is_some_and(|input| {
+        let lower = input.to_ascii_lowercase();
+        lower.starts_with("http://") || lower.starts_with("https://")
+    })
+}
+
+/// The extension of archives whose members can be named as inputs
+const ARCHIVE_EXTENSION: &str = ".zip";
+
+/// Separates the path of an archive from the path of a file inside it
+const ARCHIVE_MEMBER_SEPARATOR: char = '!';
+
+/// Splits an input of the form `archive.zip!inner/path.opus` into the path of
+/// the archive and the path of the file inside it. An existing file whose name
+/// happens to have this form is not split.
+#[must_use]
+pub fn split_archive_path(input: &Path) -> Option<(PathBuf, String)> {
+    let text = input.to_str()?;
+    let (archive, member) = text.match_indices(ARCHIVE_MEMBER_SEPARATOR).find_map(|(idx, _)| {
+        let (archive, member) = (&text[..idx], &text[idx + 1..]);
+        archive.to_ascii_lowercase().ends_with(ARCHIVE_EXTENSION).then_some((archive, member))
+    })?;
+    if member.is_empty() || input.exists() {
+        return None;
+    }
+    Some((PathBuf::from(archive), member.to_string()))
+}
+
+/// Whether an input named on the command line is a file inside an archive,
+/// which can only be read
+#[must_use]
+pub fn is_archive_path(input: &Path) -> bool { split_archive_path(input).is_some() }
+
+/// Returns the source for an input named on the command line. URLs are only
+/// supported when zoog is built with the `http` feature and files inside
+/// archives with the `archive` feature.
+pub fn input_source(input: &Path) -> Result<Box<dyn InputSource>, Error> {
+    if is_url(input) {
+        #[cfg(feature = "http")]
+        return Ok(Box::new(HttpSource::new(input)));
+        #[cfg(not(feature = "http"))]
+        return Err(Error::UrlUnsupported(input.to_path_buf()));
+    }
+    #[cfg(feature = "archive")]
+    if let Some((archive, member)) = split_archive_path(input) {
+        return Ok(Box::new(ArchiveSource::new(input, archive, member)));
+    }
+    #[cfg(not(feature = "archive"))]
+    if is_archive_path(input) {
+        return Err(Error::ArchiveUnsupported(input.to_path_buf()));
+    }
+    Ok(Box::new(FileSource::new(input)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, SeekFrom};
+
+    use super::*;
+
+    /// An input held in memory, standing in for a remote one
+    struct MemorySource {
+        name: PathBuf,
+        data: Vec<u8>,
+    }
+
+    impl InputSource for MemorySource {
+        fn name(&self) -> &Path { &self.name }
+
+        fn open(&self) -> Result<Box<dyn ReadSeek>, Error> { Ok(Box::new(Cursor::new(self.data.clone()))) }
+    }
+
+    #[test]
+    fn urls_are_recognized() {
+        assert!(is_url(Path::new("http://example.com/a.opus")));
+        assert!(is_url(Path::new("HTTPS://example.com/a.opus")));
+        assert!(!is_url(Path::new("http/a.opus")));
+        assert!(!is_url(Path::new("ftp://example.com/a.opus")));
+        assert!(!is_url(Path::new("a.opus")));
+    }
+
+    #[test]
+    fn paths_are_opened_as_files() -> Result<(), Error> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.opus");
+        std::fs::write(&path, b"OggS").unwrap();
+        let source = input_source(&path)?;
+        assert_eq!(source.name(), path);
+        let mut data = Vec::new();
+        source.open()?.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"OggS");
+        Ok(())
+    }
+
+    #[test]
+    fn missing_file_is_an_open_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = FileSource::new(dir.path().join("missing.opus"));
+        assert!(matches!(source.open(), Err(Error::FileOpenError(_, _))));
+    }
+
+    #[test]
+    #[cfg(not(feature = "http"))]
+    fn urls_require_http_feature() {
+        let result = input_source(Path::new("https://example.com/a.opus"));
+        assert!(matches!(result, Err(Error::UrlUnsupported(_))));
+    }
+
+    #[test]
+    fn archive_paths_are_split() {
+        let split = |input: &str| split_archive_path(Path::new(input));
+        assert_eq!(split("a.zip!b.opus"), Some((PathBuf::from("a.zip"), String::from("b.opus"))));
+        assert_eq!(split("dir/A.ZIP!inner/b.opus"), Some((PathBuf::from("dir/A.ZIP"), String::from("inner/b.opus"))));
+        assert_eq!(split("odd!name.zip!b!c.opus"), Some((PathBuf::from("odd!name.zip"), String::from("b!c.opus"))));
+        assert_eq!(split("a.zip!"), None);
+        assert_eq!(split("a.zip"), None);
+        assert_eq!(split("a.tar!b.opus"), None);
+        assert_eq!(split("wow!.opus"), None);
+        assert!(!is_archive_path(Path::new("a.opus")));
+    }
+
+    #[test]
+    fn existing_files_are_not_archive_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("odd.zip!name.opus");
+        assert!(is_archive_path(&path));
+        std::fs::write(&path, b"OggS").unwrap();
+        assert!(!is_archive_path(&path));
+    }
+
+    #[test]
+    #[cfg(not(feature = "archive"))]
+    fn archive_paths_require_archive_feature() {
+        let result = input_source(Path::new("album.zip!track.opus"));
+        assert!(matches!(result, Err(Error::ArchiveUnsupported(_))));
+    }
+
+    #[test]
+    fn sources_can_be_seeked() -> Result<(), Error> {
+        let source = MemorySource { name: PathBuf::from("memory"), data: (0..=255).collect() };
+        let sources: Vec<Box<dyn InputSource>> = vec![Box::new(source)];
+        for source in &sources {
+            let mut reader = source.open()?;
+            reader.seek(SeekFrom::End(-16)).unwrap();
+            let mut tail = Vec::new();
+            reader.read_to_end(&mut tail).unwrap();
+            assert_eq!(tail, (240..=255).collect::<Vec<u8>>());
+        }
+        Ok(())
+    }
+}
@@ -5,6 +5,13 @@ mod codec;
 mod constants;
 mod decibels;
 mod error;
+mod number;
+
+/// Codec-independent functionality shared by loudness analyzers
+pub mod analysis;
+
+/// Information about how zoog was built
+pub mod build_info;
 
 /// Functionality for escaping and unescaping values for command-line tools
 pub mod escaping;
@@ -12,12 +19,37 @@ pub mod escaping;
 /// Functionality for rewriting Ogg Opus streams with new headers
 pub mod header_rewriter;
 
+/// Detection of non-fatal problems in codec headers
+pub mod header_issue;
+
+/// Computing digests of data as it is read and written
+pub mod hashing;
+
+/// Detection of suspicious combinations of header fields
+pub mod lint;
+
 /// Functionality for rewriting Ogg Opus streams with new comments
 pub mod comment_rewrite;
 
+/// Detection of case-insensitive filesystems for comparing file paths
+pub mod case_sensitivity;
+
+/// BS.1770 loudness measurement of decoded audio
+pub mod loudness;
+
+/// Classification of tracks by their measured loudness
+pub mod loudness_class;
+
 /// Functionality for manipulating file timestamps
 pub mod file_timestamp;
 
+/// Expansion of directories given as inputs into the files they contain
+pub mod input_paths;
+
+/// Sources that input files can be read from, such as the local filesystem
+/// or, with the `http` feature, URLs
+pub mod io;
+
 /// Support for detecting an operation should be interrupted
 pub mod interrupt;
 
@@ -28,13 +60,37 @@ pub mod volume_rewrite;
 /// Functionality for manipulating headers
 pub mod header;
 
+/// Appending records to files shared between concurrent writers
+pub mod output;
+
+/// Seeking within Ogg streams by granule position
+pub mod ogg_seek;
+
+/// Page-level copying and concatenation of Ogg streams
+pub mod ogg_tools;
+
 /// Types for manipulating headers of Ogg Opus streams
 pub mod opus;
 
+/// Versioned types and JSON documents describing the results of the
+/// command-line tools
+pub mod report;
+
+/// Generation of Ogg Opus streams for tests and examples
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+/// Ordering of files by their disc and track number tags
+pub mod track_order;
+
 /// Types for manipulating headers of Ogg Vorbis streams
 pub mod vorbis;
 
+/// Aggregation of the software which encoded a collection of files
+pub mod vendor_stats;
+
 pub use codec::*;
 pub use constants::global::*;
 pub use decibels::*;
 pub use error::*;
+pub use number::*;
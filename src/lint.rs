@@ -0,0 +1,215 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::header::{CommentList, IdHeader as _};
+use crate::header_rewriter::{CodecHeaders, HeaderSummarize};
+use crate::opus::{self, TAG_ALBUM_GAIN, TAG_TRACK_GAIN};
+use crate::Error;
+
+/// The sample rate R128 gains are measured relative to
+const R128_MEASUREMENT_RATE: usize = 48000;
+
+/// How concerning a lint finding is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LintSeverity {
+    /// Worth knowing about, but not necessarily a problem
+    Info,
+
+    /// Likely indicates a bug in the software that produced the file
+    Warning,
+}
+
+impl Display for LintSeverity {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LintSeverity::Info => "info",
+            LintSeverity::Warning => "warning",
+        };
+        formatter.write_str(name)
+    }
+}
+
+/// A suspicious combination of header fields
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LintFinding {
+    /// Identifier of the rule which produced the finding. This is stable
+    /// between releases so may be used to filter findings.
+    pub id: String,
+
+    /// How concerning the finding is
+    pub severity: LintSeverity,
+
+    /// Description of the finding
+    pub message: String,
+}
+
+impl Display for LintFinding {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        write!(formatter, "{} [{}]: {}", self.severity, self.id, self.message)
+    }
+}
+
+/// A check applied to Ogg Opus headers
+struct Rule {
+    id: &'static str,
+    severity: LintSeverity,
+    check: fn(&opus::IdHeader, &opus::CommentHeader) -> Option<String>,
+}
+
+/// The rules applied to Ogg Opus headers, in the order findings are reported
+const OPUS_RULES: [Rule; 4] = [
+    Rule { id: "channel-mapping-mismatch", severity: LintSeverity::Warning, check: check_channel_mapping },
+    Rule { id: "r128-input-sample-rate", severity: LintSeverity::Info, check: check_r128_input_sample_rate },
+    Rule { id: "zero-preskip", severity: LintSeverity::Warning, check: check_preskip },
+    Rule { id: "output-gain-at-limit", severity: LintSeverity::Warning, check: check_output_gain_limit },
+];
+
+fn check_channel_mapping(id_header: &opus::IdHeader, _: &opus::CommentHeader) -> Option<String> {
+    let channels = id_header.num_output_channels();
+    let family = id_header.channel_mapping_family();
+    let table_len = id_header.channel_mapping_table().len();
+    if family == 0 && channels > 2 {
+        Some(format!("Channel mapping family 0 does not permit {} channels", channels))
+    } else if family != 0 && table_len != channels {
+        Some(format!("Channel mapping table has {} entries for {} channels", table_len, channels))
+    } else {
+        None
+    }
+}
+
+fn check_r128_input_sample_rate(id_header: &opus::IdHeader, comments: &opus::CommentHeader) -> Option<String> {
+    let rate = id_header.input_sample_rate().filter(|rate| *rate != R128_MEASUREMENT_RATE)?;
+    let has_r128 =
+        comments.iter().any(|(k, _)| k.eq_ignore_ascii_case(TAG_TRACK_GAIN) || k.eq_ignore_ascii_case(TAG_ALBUM_GAIN));
+    has_r128.then(|| {
+        format!(
+            "Input sample rate is {} Hz but R128 gains are relative to decoding at {} Hz",
+            rate, R128_MEASUREMENT_RATE
+        )
+    })
+}
+
+fn check_preskip(id_header: &opus::IdHeader, _: &opus::CommentHeader) -> Option<String> {
+    (id_header.preskip_samples() == 0).then(|| String::from("Pre-skip is zero, which suggests an encoder bug"))
+}
+
+fn check_output_gain_limit(id_header: &opus::IdHeader, _: &opus::CommentHeader) -> Option<String> {
+    let gain = id_header.get_output_gain();
+    matches!(gain.as_fixed_point(), i16::MIN | i16::MAX)
+        .then(|| format!("Output gain of {} is at the limit of its range and may have been clamped", gain))
+}
+
+/// Returns the identifiers of all lint rules
+pub fn rule_ids() -> impl Iterator<Item = &'static str> { OPUS_RULES.iter().map(|rule| rule.id) }
+
+/// Finds suspicious combinations of fields in the supplied headers
+#[must_use]
+pub fn lint(headers: &CodecHeaders) -> Vec<LintFinding> {
+    match headers {
+        CodecHeaders::Opus(id_header, comments) => OPUS_RULES
+            .iter()
+            .filter_map(|rule| {
+                (rule.check)(id_header, comments).map(|message| LintFinding {
+                    id: rule.id.into(),
+                    severity: rule.severity,
+                    message,
+                })
+            })
+            .collect(),
+        CodecHeaders::Vorbis(_, _) => Vec::new(),
+    }
+}
+
+/// Summarizes headers as the lint findings for them
+#[derive(Debug, Default)]
+pub struct LintSummary {}
+
+impl HeaderSummarize for LintSummary {
+    type Error = Error;
+    type Summary = Vec<LintFinding>;
+
+    fn summarize(&self, headers: &CodecHeaders) -> Result<Self::Summary, Self::Error> { Ok(lint(headers)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::IdHeader as _;
+    use crate::testing::opus_id_header;
+
+    fn opus_headers(id_header: Vec<u8>, comments: &[(&str, &str)]) -> CodecHeaders {
+        let id_header = opus::IdHeader::try_parse(&id_header).unwrap().unwrap();
+        let mut comment_header = opus::CommentHeader::default();
+        comment_header.extend(comments.iter().copied()).unwrap();
+        CodecHeaders::Opus(id_header, comment_header)
+    }
+
+    fn ids(headers: &CodecHeaders) -> Vec<String> { lint(headers).into_iter().map(|finding| finding.id).collect() }
+
+    /// Builds an identification header using channel mapping family 1 with a
+    /// table of the specified length
+    fn mapped_id_header(channels: u8, table_len: u8) -> Vec<u8> {
+        let mut header = opus_id_header(channels, 48000, 312, 0);
+        *header.last_mut().unwrap() = 1;
+        header.extend_from_slice(&[table_len, 0]);
+        header.extend(0..table_len);
+        header
+    }
+
+    #[test]
+    fn clean_headers_have_no_findings() {
+        let headers = opus_headers(opus_id_header(2, 48000, 312, -256), &[(TAG_TRACK_GAIN, "0")]);
+        assert_eq!(lint(&headers), []);
+        assert_eq!(ids(&opus_headers(mapped_id_header(6, 6), &[])), Vec::<String>::new());
+    }
+
+    #[test]
+    fn channel_mapping_mismatch() {
+        assert_eq!(ids(&opus_headers(mapped_id_header(2, 6), &[])), ["channel-mapping-mismatch"]);
+        assert_eq!(ids(&opus_headers(opus_id_header(6, 48000, 312, 0), &[])), ["channel-mapping-mismatch"]);
+    }
+
+    #[test]
+    fn r128_input_sample_rate() {
+        let id_header = || opus_id_header(2, 44100, 312, 0);
+        let findings = lint(&opus_headers(id_header(), &[(TAG_ALBUM_GAIN, "0")]));
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].id, "r128-input-sample-rate");
+        assert_eq!(findings[0].severity, LintSeverity::Info);
+        assert_eq!(lint(&opus_headers(id_header(), &[("TITLE", "Something")])), []);
+        assert_eq!(lint(&opus_headers(opus_id_header(2, 0, 312, 0), &[(TAG_TRACK_GAIN, "0")])), []);
+    }
+
+    #[test]
+    fn zero_preskip() {
+        assert_eq!(ids(&opus_headers(opus_id_header(2, 48000, 0, 0), &[])), ["zero-preskip"]);
+    }
+
+    #[test]
+    fn output_gain_at_limit() {
+        for gain in [i16::MIN, i16::MAX] {
+            assert_eq!(ids(&opus_headers(opus_id_header(1, 48000, 312, gain), &[])), ["output-gain-at-limit"]);
+        }
+        assert_eq!(lint(&opus_headers(opus_id_header(1, 48000, 312, i16::MAX - 1), &[])), []);
+    }
+
+    #[test]
+    fn rule_ids_are_unique() {
+        let mut ids: Vec<_> = rule_ids().collect();
+        let count = ids.len();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), count);
+    }
+
+    #[test]
+    fn display() {
+        let finding = LintFinding {
+            id: "zero-preskip".into(),
+            severity: LintSeverity::Warning,
+            message: "Pre-skip is zero".into(),
+        };
+        assert_eq!(finding.to_string(), "warning [zero-preskip]: Pre-skip is zero");
+    }
+}
@@ -0,0 +1,396 @@
+use std::time::Duration;
+
+use bs1770::{ChannelLoudnessMeter, Power, Windows100ms};
+use derivative::Derivative;
+
+use crate::analysis::{AnalysisIssue, AudioParameters};
+use crate::{Decibels, Error};
+
+/// The gating windows of measured audio, from which the loudness of one or
+/// more tracks can be computed
+#[derive(Clone, Derivative)]
+#[derivative(Debug)]
+pub struct Windows {
+    #[derivative(Debug = "ignore")]
+    inner: Windows100ms<Vec<Power>>,
+}
+
+impl Default for Windows {
+    fn default() -> Windows { Windows { inner: Windows100ms::new() } }
+}
+
+impl Windows {
+    /// Appends the windows of `other`, so that the loudness of the result is
+    /// that of both pieces of audio measured as one
+    pub fn extend(&mut self, other: &Windows) { self.inner.inner.extend(other.inner.inner.iter()); }
+
+    /// Merges the windows of several measurements, such as the tracks of an
+    /// album
+    #[must_use]
+    pub fn merge<'a, I: IntoIterator<Item = &'a Windows>>(windows: I) -> Windows {
+        let mut result = Windows::default();
+        for other in windows {
+            result.extend(other);
+        }
+        result
+    }
+
+    /// Whether no audio has been measured
+    #[must_use]
+    pub fn is_empty(&self) -> bool { self.inner.inner.is_empty() }
+
+    /// The gated BS.1770 loudness in LUFS of the measured audio
+    #[must_use]
+    pub fn loudness(&self) -> Decibels {
+        let power = bs1770::gated_mean(self.inner.as_ref());
+        let lufs = if power.0.is_nan() {
+            // Near silence can result in a NaN result (https://github.com/ruuda/bs1770/issues/1).
+            // Returning a large negative value might result in the application of a massive
+            // gain and is therefore not a good idea. Instead we return zero,
+            // which indicates the audio is at peak volume.
+            0.0
+        } else {
+            power.loudness_lkfs().into()
+        };
+        Decibels::from(lufs)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Windows {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.inner.inner.iter().map(|power| power.0))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Windows {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Windows, D::Error> {
+        let powers = Vec::<f64>::deserialize(deserializer)?;
+        Ok(Windows { inner: Windows100ms { inner: powers.into_iter().map(Power).collect() } })
+    }
+}
+
+/// The measurement of a single track. Measurements of tracks can be kept and
+/// later combined in any grouping to find the loudness of an album.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrackMeasurement {
+    /// The gating windows of the track
+    pub windows: Windows,
+
+    /// The gated loudness of the track in LUFS
+    pub lufs: Decibels,
+
+    /// The duration of the measured audio
+    pub duration: Duration,
+
+    /// The largest magnitude of any sample, where 1.0 is full scale, or 0.0 if
+    /// not measured
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sample_peak: f64,
+
+    /// The duration implied by the final granule positions of the track, or
+    /// `None` if not determined, such as when only part of it was measured
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub granule_duration: Option<Duration>,
+}
+
+impl TrackMeasurement {
+    /// Constructs a measurement from the gating windows of a track
+    #[must_use]
+    pub fn new(windows: Windows, duration: Duration) -> TrackMeasurement {
+        TrackMeasurement { lufs: windows.loudness(), windows, duration, sample_peak: 0.0, granule_duration: None }
+    }
+
+    /// Sets the sample peak of the measurement
+    #[must_use]
+    pub fn with_sample_peak(self, sample_peak: f64) -> TrackMeasurement { TrackMeasurement { sample_peak, ..self } }
+
+    /// Sets the duration implied by the granule positions of the track
+    #[must_use]
+    pub fn with_granule_duration(self, granule_duration: Option<Duration>) -> TrackMeasurement {
+        TrackMeasurement { granule_duration, ..self }
+    }
+
+    /// Returns `AnalysisIssue::DurationMismatch` if the measured duration
+    /// differs from that implied by granule positions by more than `threshold`
+    #[must_use]
+    pub fn duration_issue(&self, threshold: Duration) -> Option<AnalysisIssue> {
+        let granule = self.granule_duration?;
+        AnalysisIssue::check_duration(granule, self.duration, threshold)
+    }
+
+    /// The sample peak relative to full scale, which is negative infinity for
+    /// silence
+    #[must_use]
+    pub fn sample_peak_dbfs(&self) -> Decibels { amplitude_to_dbfs(self.sample_peak) }
+}
+
+/// Converts a sample magnitude, where 1.0 is full scale, to decibels relative
+/// to full scale
+#[must_use]
+pub fn amplitude_to_dbfs(amplitude: f64) -> Decibels { Decibels::from(20.0 * amplitude.log10()) }
+
+/// The largest sample peak of the specified tracks, such as the tracks of an
+/// album
+pub fn combine_sample_peaks<'a, I: IntoIterator<Item = &'a TrackMeasurement>>(tracks: I) -> f64 {
+    tracks.into_iter().map(|track| track.sample_peak).fold(0.0, f64::max)
+}
+
+/// The gated loudness in LUFS of the specified tracks measured as one, such as
+/// the tracks of an album
+pub fn combine_measurements<'a, I: IntoIterator<Item = &'a TrackMeasurement>>(tracks: I) -> Decibels {
+    Windows::merge(tracks.into_iter().map(|track| &track.windows)).loudness()
+}
+
+/// Measures the BS.1770 loudness of decoded audio, independently of the codec
+/// or container it was decoded from
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct Meter {
+    parameters: AudioParameters,
+    #[derivative(Debug = "ignore")]
+    meters: Vec<ChannelLoudnessMeter>,
+    frames_measured: u64,
+    sample_peak: f32,
+}
+
+impl Meter {
+    /// Constructs a meter for audio with the specified parameters. Mono and
+    /// stereo audio are supported.
+    pub fn new(parameters: AudioParameters) -> Result<Meter, Error> {
+        let AudioParameters { channel_count, sample_rate } = parameters;
+        if !matches!(channel_count, 1 | 2) {
+            return Err(Error::InvalidChannelCount(channel_count));
+        }
+        let sample_rate_u32 = match u32::try_from(sample_rate) {
+            Ok(rate) if rate > 0 => rate,
+            _ => return Err(Error::InvalidSampleRate(sample_rate)),
+        };
+        let meters = (0..channel_count).map(|_| ChannelLoudnessMeter::new(sample_rate_u32)).collect();
+        Ok(Meter { parameters, meters, frames_measured: 0, sample_peak: 0.0 })
+    }
+
+    /// The parameters of the audio being measured
+    #[must_use]
+    pub fn parameters(&self) -> AudioParameters { self.parameters }
+
+    /// Measures interleaved samples, continuing from any audio already pushed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of samples is not a multiple of the channel count.
+    pub fn push_interleaved(&mut self, samples: &[f32]) {
+        let channel_count = self.parameters.channel_count;
+        assert_eq!(samples.len() % channel_count, 0, "Samples did not contain a whole number of frames");
+        for (channel_idx, meter) in self.meters.iter_mut().enumerate() {
+            meter.push(samples.iter().copied().skip(channel_idx).step_by(channel_count));
+        }
+        self.frames_measured += u64::try_from(samples.len() / channel_count).expect("Sample count out of range");
+        self.sample_peak = samples.iter().map(|sample| sample.abs()).fold(self.sample_peak, f32::max);
+    }
+
+    /// The largest magnitude of any sample measured so far, where 1.0 is full
+    /// scale
+    #[must_use]
+    pub fn sample_peak(&self) -> f64 { f64::from(self.sample_peak) }
+
+    /// The number of samples per channel measured so far
+    #[must_use]
+    pub fn frames_measured(&self) -> u64 { self.frames_measured }
+
+    /// Converts a duration to a number of samples per channel at the sample
+    /// rate of the meter
+    #[must_use]
+    pub fn frames_in(&self, duration: Duration) -> u64 {
+        let frames = duration.as_nanos() * self.parameters.sample_rate as u128 / 1_000_000_000;
+        u64::try_from(frames).unwrap_or(u64::MAX)
+    }
+
+    /// The duration of the audio measured so far
+    #[must_use]
+    pub fn duration(&self) -> Duration { self.duration_of(self.frames_measured) }
+
+    /// Converts a number of samples per channel at the sample rate of the
+    /// meter to a duration
+    #[must_use]
+    pub fn duration_of(&self, frames: u64) -> Duration {
+        let sample_rate = u64::try_from(self.parameters.sample_rate).expect("Sample rate out of range");
+        let seconds = frames / sample_rate;
+        let remainder = frames % sample_rate;
+        let nanos = u32::try_from(remainder * 1_000_000_000 / sample_rate).expect("Nanoseconds out of range");
+        Duration::new(seconds, nanos)
+    }
+
+    /// The gating windows of the audio measured so far, combined across
+    /// channels
+    #[must_use]
+    pub fn windows(&self) -> Windows {
+        let windows: Vec<_> = self.meters.iter().map(ChannelLoudnessMeter::as_100ms_windows).collect();
+        // See notes on `reduce_stero` in `bs1770` crate.
+        let power_scale_factor = match self.meters.len() {
+            1 => 2.0, // Since mono is still output to two devices
+            2 => 1.0,
+            n => unreachable!("Meter constructed for unsupported channel count {}", n),
+        };
+        let num_windows = windows[0].len();
+        for channel_windows in &windows {
+            assert_eq!(num_windows, channel_windows.len(), "Channels had different amounts of audio");
+        }
+        let mut result_windows = Vec::with_capacity(num_windows);
+        for i in 0..num_windows {
+            let mut power = 0.0;
+            for channel_windows in &windows {
+                let channel_windows = &channel_windows.inner;
+                // It would be nice if `Power` implemented addition since this is a
+                // semantically-valid operation
+                power += channel_windows[i].0;
+            }
+            power *= power_scale_factor;
+            result_windows.push(Power(power));
+        }
+        Windows { inner: Windows100ms { inner: result_windows } }
+    }
+
+    /// The gated BS.1770 loudness in LUFS of the audio measured so far
+    #[must_use]
+    pub fn loudness(&self) -> Decibels { self.windows().loudness() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The amplitude of a 1 kHz sine in every channel with a loudness of -23
+    /// LUFS. A full-scale sine in both channels of a stereo signal is 0 LUFS.
+    const REFERENCE_AMPLITUDE: f32 = 0.070_794_58;
+
+    const TOLERANCE: f64 = 0.05;
+
+    fn sine(parameters: AudioParameters, amplitude: f32, duration: Duration) -> Vec<f32> {
+        let AudioParameters { channel_count, sample_rate } = parameters;
+        let num_frames = usize::try_from(duration.as_millis()).unwrap() * sample_rate / 1000;
+        (0..num_frames)
+            .flat_map(|idx| {
+                #[allow(clippy::cast_precision_loss)]
+                let t = idx as f64 / sample_rate as f64;
+                #[allow(clippy::cast_possible_truncation)]
+                let value = (f64::from(amplitude) * (2.0 * std::f64::consts::PI * 1000.0 * t).sin()) as f32;
+                std::iter::repeat(value).take(channel_count)
+            })
+            .collect()
+    }
+
+    fn measure(parameters: AudioParameters, amplitude: f32) -> Meter {
+        let mut meter = Meter::new(parameters).unwrap();
+        meter.push_interleaved(&sine(parameters, amplitude, Duration::from_secs(5)));
+        meter
+    }
+
+    #[test]
+    fn reference_signal_is_minus_23_lufs() {
+        for channel_count in [1, 2] {
+            for sample_rate in [44100, 48000] {
+                let meter = measure(AudioParameters { channel_count, sample_rate }, REFERENCE_AMPLITUDE);
+                let lufs = meter.loudness().as_f64();
+                let description = format!("{} channel(s) at {} Hz", channel_count, sample_rate);
+                assert!((lufs + 23.0).abs() < TOLERANCE, "{} measured at {} LUFS", description, lufs);
+                assert_eq!(meter.duration(), Duration::from_secs(5));
+            }
+        }
+    }
+
+    #[test]
+    fn pushes_are_continuous() {
+        let parameters = AudioParameters { channel_count: 2, sample_rate: 48000 };
+        let samples = sine(parameters, REFERENCE_AMPLITUDE, Duration::from_secs(5));
+        let mut meter = Meter::new(parameters).unwrap();
+        for chunk in samples.chunks(2 * 997) {
+            meter.push_interleaved(chunk);
+        }
+        let whole = measure(parameters, REFERENCE_AMPLITUDE);
+        assert!((meter.loudness().as_f64() - whole.loudness().as_f64()).abs() < f64::EPSILON);
+        assert_eq!(meter.frames_measured(), whole.frames_measured());
+    }
+
+    #[test]
+    fn merged_windows_measure_audio_as_one() {
+        let parameters = AudioParameters { channel_count: 2, sample_rate: 48000 };
+        let loud = measure(parameters, REFERENCE_AMPLITUDE).windows();
+        let quiet = measure(parameters, REFERENCE_AMPLITUDE / 2.0).windows();
+        assert!((quiet.loudness().as_f64() + 29.02).abs() < TOLERANCE);
+
+        // Equal durations at -23 and -29 LUFS have the mean power of the two
+        let expected = 10.0 * ((10f64.powf(-2.3) + 10f64.powf(-2.902)) / 2.0).log10();
+        let merged = Windows::merge([&loud, &quiet]);
+        assert!((merged.loudness().as_f64() - expected).abs() < TOLERANCE);
+        let mut extended = loud.clone();
+        extended.extend(&quiet);
+        assert!((extended.loudness().as_f64() - merged.loudness().as_f64()).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn measurements_combine_as_merged_windows() {
+        let parameters = AudioParameters { channel_count: 2, sample_rate: 48000 };
+        let tracks: Vec<_> = [REFERENCE_AMPLITUDE, REFERENCE_AMPLITUDE / 2.0, 0.0]
+            .into_iter()
+            .map(|amplitude| {
+                let meter = measure(parameters, amplitude);
+                TrackMeasurement::new(meter.windows(), meter.duration())
+            })
+            .collect();
+        assert!((tracks[0].lufs.as_f64() + 23.0).abs() < TOLERANCE);
+        for subset in [&tracks[..1], &tracks[..2], &tracks[1..], &tracks[..]] {
+            let merged = Windows::merge(subset.iter().map(|track| &track.windows));
+            assert!((combine_measurements(subset).as_f64() - merged.loudness().as_f64()).abs() < f64::EPSILON);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn measurement_serde_round_trip() -> Result<(), serde_json::Error> {
+        let parameters = AudioParameters { channel_count: 1, sample_rate: 44100 };
+        let meter = measure(parameters, REFERENCE_AMPLITUDE);
+        let measurement = TrackMeasurement::new(meter.windows(), meter.duration());
+        let json = serde_json::to_string(&measurement)?;
+        let decoded: TrackMeasurement = serde_json::from_str(&json)?;
+        assert_eq!(decoded.duration, measurement.duration);
+        assert_eq!(decoded.lufs, measurement.lufs);
+        assert_eq!(decoded.windows.loudness(), measurement.windows.loudness());
+        Ok(())
+    }
+
+    #[test]
+    fn silence_is_full_scale() {
+        let parameters = AudioParameters { channel_count: 1, sample_rate: 48000 };
+        let meter = measure(parameters, 0.0);
+        assert!(meter.loudness().as_f64().abs() < f64::EPSILON);
+        assert!(Windows::default().is_empty());
+        assert!(Windows::default().loudness().as_f64().abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn sample_peak_is_largest_magnitude() {
+        let parameters = AudioParameters { channel_count: 2, sample_rate: 48000 };
+        let mut meter = Meter::new(parameters).unwrap();
+        assert!(meter.sample_peak().abs() < f64::EPSILON);
+        meter.push_interleaved(&[0.25, -0.5, 0.125, 0.0]);
+        meter.push_interleaved(&[0.375, 0.0]);
+        assert!((meter.sample_peak() - 0.5).abs() < f64::EPSILON);
+
+        let measurement = TrackMeasurement::new(meter.windows(), meter.duration()).with_sample_peak(0.5);
+        assert!((measurement.sample_peak_dbfs().as_f64() + 6.0206).abs() < 1e-4);
+        let silent = TrackMeasurement::new(Windows::default(), Duration::ZERO);
+        assert!(silent.sample_peak_dbfs().as_f64().is_infinite() && silent.sample_peak_dbfs().as_f64() < 0.0);
+        assert!((combine_sample_peaks([&silent, &measurement]) - 0.5).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn unsupported_parameters_are_rejected() {
+        let result = Meter::new(AudioParameters { channel_count: 3, sample_rate: 48000 });
+        assert!(matches!(result, Err(Error::InvalidChannelCount(3))));
+        let result = Meter::new(AudioParameters { channel_count: 2, sample_rate: 0 });
+        assert!(matches!(result, Err(Error::InvalidSampleRate(0))));
+    }
+}
@@ -0,0 +1,124 @@
+use std::str::FromStr;
+
+use crate::header::validate_comment_field_name;
+use crate::{parse_number, Decibels, Error};
+
+/// Assigns a value to a comment according to the loudness of a track, as
+/// described by a specification such as
+/// `LOUDNESS_CLASS:quiet<-30,normal<-18,loud`.
+///
+/// The specification names the comment, then lists classes in order of
+/// increasing loudness. Each class except the last has a threshold in LUFS,
+/// and a track is assigned the first class whose threshold its loudness is
+/// below. The last class has no threshold and is assigned to all louder
+/// tracks. Since `,` separates classes, thresholds must use `.` as their
+/// decimal separator.
+///
+/// ```
+/// use zoog::loudness_class::LoudnessClassifier;
+/// use zoog::Decibels;
+///
+/// let classifier: LoudnessClassifier = "LOUDNESS_CLASS:quiet<-30,normal<-18,loud".parse()?;
+/// assert_eq!(classifier.tag(), "LOUDNESS_CLASS");
+/// assert_eq!(classifier.classify(Decibels::from(-31.0)), "quiet");
+/// assert_eq!(classifier.classify(Decibels::from(-18.0)), "loud");
+/// # Ok::<(), zoog::Error>(())
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoudnessClassifier {
+    tag: String,
+    classes: Vec<(String, Decibels)>,
+    loudest: String,
+}
+
+impl LoudnessClassifier {
+    /// The name of the comment the class is written to
+    #[must_use]
+    pub fn tag(&self) -> &str { &self.tag }
+
+    /// Returns the class of a track with the specified loudness
+    #[must_use]
+    pub fn classify(&self, loudness: Decibels) -> &str {
+        self.classes
+            .iter()
+            .find(|(_, threshold)| loudness.as_f64() < threshold.as_f64())
+            .map_or(&self.loudest, |(class, _)| class)
+    }
+}
+
+impl FromStr for LoudnessClassifier {
+    type Err = Error;
+
+    fn from_str(spec: &str) -> Result<LoudnessClassifier, Error> {
+        let invalid = |reason: String| Error::InvalidLoudnessClassification(spec.into(), reason);
+        let (tag, classes) = spec
+            .split_once(':')
+            .filter(|(tag, _)| !tag.is_empty())
+            .ok_or_else(|| invalid(String::from("expected a comment name followed by `:`")))?;
+        validate_comment_field_name(tag)?;
+        let mut classes: Vec<_> = classes.split(',').collect();
+        let loudest = classes.pop().expect("Splitting should return at least one item");
+        if loudest.contains('<') {
+            return Err(invalid(String::from("the last class must not have a threshold")));
+        }
+        let classes = classes
+            .into_iter()
+            .map(|class| {
+                let (value, threshold) =
+                    class.split_once('<').ok_or_else(|| invalid(format!("class `{}` has no threshold", class)))?;
+                Ok((value.to_string(), Decibels::from(parse_number(threshold)?)))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        if classes.iter().map(|(value, _)| value.as_str()).chain([loudest]).any(str::is_empty) {
+            return Err(invalid(String::from("class names must not be empty")));
+        }
+        if classes.windows(2).any(|pair| pair[0].1.as_f64() >= pair[1].1.as_f64()) {
+            return Err(invalid(String::from("thresholds must be strictly increasing")));
+        }
+        Ok(LoudnessClassifier { tag: tag.to_string(), classes, loudest: loudest.to_string() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_are_classified_by_first_threshold_exceeding_loudness() -> Result<(), Error> {
+        let classifier: LoudnessClassifier = "LOUDNESS_CLASS:quiet<-30,normal<-18.5,loud".parse()?;
+        assert_eq!(classifier.tag(), "LOUDNESS_CLASS");
+        for (loudness, expected) in
+            [(-45.0, "quiet"), (-30.0, "normal"), (-18.6, "normal"), (-18.5, "loud"), (-5.0, "loud")]
+        {
+            assert_eq!(classifier.classify(Decibels::from(loudness)), expected, "Loudness {}", loudness);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn single_class_matches_everything() -> Result<(), Error> {
+        let classifier: LoudnessClassifier = "CLASS:any".parse()?;
+        assert_eq!(classifier.classify(Decibels::from(f64::NEG_INFINITY)), "any");
+        assert_eq!(classifier.classify(Decibels::from(0.0)), "any");
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_specifications_are_rejected() {
+        for spec in [
+            "quiet<-30,loud",
+            "CLASS=X:quiet<-30,loud",
+            ":quiet<-30,loud",
+            "CLASS:quiet<-30,loud<-10",
+            "CLASS:quiet,loud",
+            "CLASS:quiet<abc,loud",
+            "CLASS:quiet<-18,normal<-30,loud",
+            "CLASS:quiet<-30,normal<-30,loud",
+            "CLASS:<-30,loud",
+            "CLASS:quiet<-30,",
+            "CLASS:quiet<-30,5,loud",
+        ] {
+            assert!(spec.parse::<LoudnessClassifier>().is_err(), "Specification `{}` was accepted", spec);
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use zoog::report::{write_vendor_stats_json, AnalysisResult, FileResult};
+use zoog::vendor_stats::VendorStats;
+use zoog::Decibels;
+
+use crate::civil_date::civil_from_days;
+
+/// Formats in which analysis and gain results can be written
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum ReportFormat {
+    /// human-readable output
+    #[default]
+    Table,
+
+    /// JSON object
+    Json,
+
+    /// comma-separated values with a header row
+    Csv,
+}
+
+/// Human-readable summary of an album normalization
+#[derive(Clone, Debug)]
+pub struct AlbumReport {
+    /// Name and version of the program producing the report
+    pub generator: String,
+    pub generated: SystemTime,
+    pub album_lufs: Decibels,
+    /// Description of the target loudness and how the output gain was chosen
+    pub target: String,
+    pub files: Vec<FileResult>,
+}
+
+impl AlbumReport {
+    /// Writes the report as text
+    pub fn write<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        writeln!(output, "Album loudness report")?;
+        writeln!(output, "Generated by {} on {}", self.generator, utc_timestamp(self.generated))?;
+        writeln!(output)?;
+        writeln!(output, "Target: {}", self.target)?;
+        writeln!(output, "Album loudness: {:.2} LUFS", self.album_lufs.as_f64())?;
+        writeln!(output)?;
+        writeln!(output, "{:>10}  {:>10}  {:>11}  {:<12}  Path", "LUFS", "Duration", "Output gain", "Status")?;
+        for file in &self.files {
+            writeln!(
+                output,
+                "{:>10}  {:>10}  {:>11}  {:<12}  {}",
+                optional(file.track_lufs.map(|lufs| format!("{:.2}", lufs.as_f64()))),
+                optional(file.duration.map(friendly_duration)),
+                optional(file.new_gains.map(|gains| format!("{:.2} dB", gains.output.as_f64()))),
+                file.status.as_str(),
+                file.path.display()
+            )?;
+        }
+        if self.files.iter().any(|file| file.digests.is_some()) {
+            writeln!(output)?;
+            writeln!(output, "{:<64}  {:<64}  Path", "Input SHA-256", "Output SHA-256")?;
+            for file in &self.files {
+                let [(_, input), (_, output_digest)] = file.digest_fields();
+                writeln!(
+                    output,
+                    "{:<64}  {:<64}  {}",
+                    input.unwrap_or("-"),
+                    output_digest.unwrap_or("-"),
+                    file.path.display()
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes one CSV row per file with a header row. Digest columns are only
+/// included if any file was hashed.
+pub fn write_gain_reports_csv<W: Write>(reports: &[FileResult], output: &mut W) -> io::Result<()> {
+    let hashed = reports.iter().any(|report| report.digests.is_some());
+    let mut header = vec!["path", "status", "duration_s"];
+    header.extend(FileResult::new(PathBuf::new()).decibel_fields().map(|(name, _)| name));
+    if hashed {
+        header.extend(FileResult::new(PathBuf::new()).digest_fields().map(|(name, _)| name));
+    }
+    writeln!(output, "{}", header.join(","))?;
+    for report in reports {
+        let mut fields = vec![
+            csv_field(&report.path.to_string_lossy()).into_owned(),
+            report.status.as_str().to_string(),
+            report.duration.map(|d| format!("{:.3}", d.as_secs_f64())).unwrap_or_default(),
+        ];
+        fields.extend(report.decibel_fields().map(|(_, value)| csv_decibels(value)));
+        if hashed {
+            fields.extend(report.digest_fields().map(|(_, value)| value.unwrap_or_default().to_string()));
+        }
+        writeln!(output, "{}", fields.join(","))?;
+    }
+    Ok(())
+}
+
+/// Writes the encoders which produced a collection of files, most common
+/// first, with the number of files and an example of each
+pub fn write_vendor_stats<W: Write>(stats: &VendorStats, format: ReportFormat, output: &mut W) -> io::Result<()> {
+    let entries = stats.sorted();
+    match format {
+        ReportFormat::Table => {
+            let vendor_width = entries.iter().map(|(encoder, _)| encoder.vendor.chars().count()).max().unwrap_or(0);
+            let vendor_width = vendor_width.max("Vendor".len());
+            let encoder_width = entries
+                .iter()
+                .map(|(encoder, _)| encoder.encoder.as_deref().map_or(1, |encoder| encoder.chars().count()))
+                .max()
+                .unwrap_or(0)
+                .max("Encoder".len());
+            writeln!(output, "{:>8}  {:<vendor_width$}  {:<encoder_width$}  Example", "Files", "Vendor", "Encoder")?;
+            for (encoder, count) in &entries {
+                writeln!(
+                    output,
+                    "{:>8}  {:<vendor_width$}  {:<encoder_width$}  {}",
+                    count.count,
+                    encoder.vendor,
+                    encoder.encoder.as_deref().unwrap_or("-"),
+                    count.example.display()
+                )?;
+            }
+            writeln!(output, "Distinct encoders: {}", entries.len())?;
+            writeln!(output, "Files that could not be read: {}", stats.unreadable())
+        }
+        ReportFormat::Json => write_vendor_stats_json(stats, output),
+        ReportFormat::Csv => {
+            writeln!(output, "vendor,encoder,count,example")?;
+            for (encoder, count) in &entries {
+                writeln!(
+                    output,
+                    "{},{},{},{}",
+                    csv_field(&encoder.vendor),
+                    csv_field(encoder.encoder.as_deref().unwrap_or_default()),
+                    count.count,
+                    csv_field(&count.example.to_string_lossy())
+                )?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Formats a duration as minutes and seconds
+fn friendly_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let centis = duration.subsec_millis() / 10;
+    format!("{}:{:02}.{:02}", seconds / 60, seconds % 60, centis)
+}
+
+/// Formats a time as a UTC date and time to the nearest second
+fn utc_timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |since_epoch| since_epoch.as_secs());
+    let seconds = i64::try_from(seconds).unwrap_or(i64::MAX);
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let time_of_day = seconds.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60
+    )
+}
+
+/// Quotes a CSV field if it contains characters with special meaning
+fn csv_field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// Formats a decibel value for CSV output with fixed precision, or as an empty
+/// field if absent
+fn csv_decibels(value: Option<Decibels>) -> String {
+    value.map(|value| format!("{:.2}", value.as_f64())).unwrap_or_default()
+}
+
+/// Writes a loudness analysis in the specified format
+pub fn write_analysis<W: Write>(analysis: &AnalysisResult, format: ReportFormat, output: &mut W) -> io::Result<()> {
+    match format {
+        ReportFormat::Table => write_analysis_table(analysis, output),
+        ReportFormat::Json => analysis.write_json(output),
+        ReportFormat::Csv => write_analysis_csv(analysis, output),
+    }
+}
+
+fn write_analysis_table<W: Write>(analysis: &AnalysisResult, output: &mut W) -> io::Result<()> {
+    writeln!(output, "{:>10}  {:>10}  Path", "LUFS", "Duration")?;
+    for track in &analysis.tracks {
+        writeln!(
+            output,
+            "{:>10.2}  {:>10}  {}",
+            track.lufs.as_f64(),
+            friendly_duration(track.duration),
+            track.path.display()
+        )?;
+    }
+    if let Some(album_lufs) = analysis.album_lufs {
+        writeln!(output, "Album loudness: {:.2} LUFS", album_lufs.as_f64())?;
+    }
+    if analysis.approximate {
+        writeln!(output, "Loudness is approximate since only part of each file was analyzed.")?;
+    }
+    Ok(())
+}
+
+fn write_analysis_csv<W: Write>(analysis: &AnalysisResult, output: &mut W) -> io::Result<()> {
+    writeln!(output, "path,track_lufs,album_lufs,duration_seconds")?;
+    let album_lufs = analysis.album_lufs.map(|lufs| format!("{:.2}", lufs.as_f64())).unwrap_or_default();
+    for track in &analysis.tracks {
+        writeln!(
+            output,
+            "{},{:.2},{},{:.3}",
+            csv_field(&track.path.to_string_lossy()),
+            track.lufs.as_f64(),
+            album_lufs,
+            track.duration.as_secs_f64()
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use zoog::report::{write_file_results_json, FileDigests, FileStatus, TrackResult};
+    use zoog::volume_rewrite::OpusGains;
+
+    use super::*;
+
+    fn report(album_lufs: Option<f64>) -> AnalysisResult {
+        let track = |path: &str, lufs: f64, millis: u64| TrackResult {
+            path: PathBuf::from(path),
+            lufs: Decibels::from(lufs),
+            duration: Duration::from_millis(millis),
+            duration_issue: None,
+        };
+        AnalysisResult {
+            tracks: vec![track("one.opus", -18.5, 61_250), track("two, \"live\".opus", -20.0, 5_000)],
+            album_lufs: album_lufs.map(Decibels::from),
+            approximate: false,
+        }
+    }
+
+    fn render(report: &AnalysisResult, format: ReportFormat) -> String {
+        let mut output = Vec::new();
+        write_analysis(report, format, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn table_prints_album_once() {
+        let output = render(&report(Some(-19.1)), ReportFormat::Table);
+        assert!(output.contains("    -18.50     1:01.25  one.opus\n"));
+        assert_eq!(output.matches("Album loudness: -19.10 LUFS").count(), 1);
+        assert!(!render(&report(None), ReportFormat::Table).contains("Album"));
+    }
+
+    #[test]
+    fn csv_quotes_paths() {
+        let output = render(&report(Some(-19.1)), ReportFormat::Csv);
+        let expected = "path,track_lufs,album_lufs,duration_seconds\none.opus,-18.50,-19.10,61.250\n\"two, \
+                        \"\"live\"\".opus\",-20.00,-19.10,5.000\n";
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn json_escapes_paths() {
+        let output = render(&report(None), ReportFormat::Json);
+        assert!(output.contains("\"album_lufs\": null"));
+        assert!(output
+            .contains("{\"path\": \"two, \\\"live\\\".opus\", \"track_lufs\": -20.00, \"duration_seconds\": 5.000}"));
+    }
+
+    fn gain_reports() -> Vec<FileResult> {
+        let gains = |output: f64, track: Option<f64>| OpusGains {
+            output: Decibels::from(output),
+            track_r128: track.map(Decibels::from),
+            album_r128: None,
+        };
+        let changed = FileResult {
+            status: FileStatus::Changed,
+            duration: Some(Duration::from_millis(1500)),
+            track_lufs: Some(Decibels::from(-26.714)),
+            old_gains: Some(gains(0.0, None)),
+            new_gains: Some(gains(8.707_031_25, Some(-5.0))),
+            ..FileResult::new(PathBuf::from("a, \"b\".opus"))
+        };
+        vec![changed, FileResult::new(PathBuf::from("missing.opus"))]
+    }
+
+    #[test]
+    fn digests_are_reported_if_present() {
+        let mut reports = gain_reports();
+        let digests = FileDigests { input: "ab".repeat(32), output: "cd".repeat(32) };
+        reports[0].digests = Some(digests.clone());
+
+        let mut output = Vec::new();
+        write_gain_reports_csv(&reports, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert!(lines[0].ends_with(",new_album_r128_db,input_sha256,output_sha256"));
+        assert!(lines[1].ends_with(&format!(",{},{}", digests.input, digests.output)));
+        assert!(lines[2].ends_with(",,"));
+
+        let mut output = Vec::new();
+        write_file_results_json(&reports, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(&format!(
+            ", \"input_sha256\": \"{}\", \"output_sha256\": \"{}\"}}",
+            digests.input, digests.output
+        )));
+        assert_eq!(output.matches("sha256").count(), 2);
+
+        let report = AlbumReport {
+            generator: "opusgain 1.2.3".to_string(),
+            generated: UNIX_EPOCH,
+            album_lufs: Decibels::from(-21.5),
+            target: "-18.00 LUFS".to_string(),
+            files: reports,
+        };
+        let mut output = Vec::new();
+        report.write(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(&format!("{}  {}  a, \"b\".opus\n", digests.input, digests.output)));
+        assert!(output.contains(&format!("{:<64}  {:<64}  missing.opus\n", "-", "-")));
+    }
+
+    #[test]
+    fn gain_report_csv() {
+        let mut output = Vec::new();
+        write_gain_reports_csv(&gain_reports(), &mut output).unwrap();
+        let expected = "path,status,duration_s,track_lufs,album_lufs,old_output_gain_db,new_output_gain_db,\
+                        old_track_r128_db,new_track_r128_db,old_album_r128_db,new_album_r128_db\n\"a, \"\"b\"\".opus\",\
+                        changed,1.500,-26.71,,0.00,8.71,,-5.00,,\nmissing.opus,failed,,,,,,,,,\n";
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn gain_report_json() {
+        let mut output = Vec::new();
+        write_file_results_json(&gain_reports(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(
+            "{\n  \"schema_version\": 1,\n  \"files\": [\n    {\"path\": \"a, \\\"b\\\".opus\", \
+             \"status\": \"changed\", \"duration_s\": 1.500"
+        ));
+        assert!(output.contains("\"new_output_gain_db\": 8.71, \"old_track_r128_db\": null"));
+        assert!(output.ends_with("\"new_album_r128_db\": null}\n  ]\n}\n"));
+        let mut output = Vec::new();
+        write_file_results_json(&[], &mut output).unwrap();
+        assert_eq!(output, b"{\n  \"schema_version\": 1,\n  \"files\": []\n}\n");
+    }
+
+    #[test]
+    fn json_empty_report() {
+        let report = AnalysisResult {
+            tracks: Vec::new(),
+            album_lufs: Some(Decibels::from(f64::NEG_INFINITY)),
+            approximate: false,
+        };
+        assert_eq!(
+            render(&report, ReportFormat::Json),
+            "{\n  \"schema_version\": 1,\n  \"album_lufs\": null,\n  \"tracks\": []\n}\n"
+        );
+    }
+
+    #[test]
+    fn approximate_report_is_labelled() {
+        let approximate = AnalysisResult { approximate: true, ..report(None) };
+        assert!(render(&approximate, ReportFormat::Table).contains("approximate"));
+        assert!(!render(&report(None), ReportFormat::Table).contains("approximate"));
+        assert!(render(&approximate, ReportFormat::Json)
+            .starts_with("{\n  \"schema_version\": 1,\n  \"approximate\": true,\n"));
+    }
+
+    #[test]
+    fn utc_timestamp_formats_date_and_time() {
+        let time = |seconds| UNIX_EPOCH + Duration::from_secs(seconds);
+        assert_eq!(utc_timestamp(time(0)), "1970-01-01 00:00:00 UTC");
+        assert_eq!(utc_timestamp(time(951_827_696)), "2000-02-29 12:34:56 UTC");
+        assert_eq!(utc_timestamp(time(1_798_761_599)), "2026-12-31 23:59:59 UTC");
+    }
+
+    #[test]
+    fn album_report_lists_files() {
+        let report = AlbumReport {
+            generator: "opusgain 1.2.3".to_string(),
+            generated: UNIX_EPOCH + Duration::from_secs(86_400),
+            album_lufs: Decibels::from(-21.5),
+            target: "-18.00 LUFS".to_string(),
+            files: gain_reports(),
+        };
+        let mut output = Vec::new();
+        report.write(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Generated by opusgain 1.2.3 on 1970-01-02 00:00:00 UTC\n"));
+        assert!(output.contains("Target: -18.00 LUFS\nAlbum loudness: -21.50 LUFS\n"));
+        assert!(output.contains("    -26.71     0:01.50      8.71 dB  changed       a, \"b\".opus\n"));
+        assert!(output.contains("         -           -            -  failed        missing.opus\n"));
+    }
+
+    #[test]
+    fn vendor_stats_formats() {
+        use std::path::Path;
+
+        use zoog::vendor_stats::Encoder;
+
+        let mut stats = VendorStats::default();
+        stats.record(Encoder::new("libopus 1.3", Some("opusenc")), 0, Path::new("a.opus"));
+        stats.record(Encoder::new("libopus 1.3", Some("opusenc")), 1, Path::new("b.opus"));
+        stats.record(Encoder::new("Lavf \"x\", y", None), 2, Path::new("c.opus"));
+        stats.record_unreadable();
+        let render = |format| {
+            let mut output = Vec::new();
+            write_vendor_stats(&stats, format, &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        let table = render(ReportFormat::Table);
+        assert!(table.contains("       2  libopus 1.3  opusenc  a.opus\n"), "{}", table);
+        assert!(table.contains("       1  Lavf \"x\", y  -        c.opus\n"), "{}", table);
+        assert!(table.ends_with("Files that could not be read: 1\n"));
+        let json = render(ReportFormat::Json);
+        assert!(json.starts_with("{\n  \"schema_version\": 1,\n  \"unreadable\": 1,\n"));
+        assert!(json
+            .contains("{\"vendor\": \"Lavf \\\"x\\\", y\", \"encoder\": null, \"count\": 1, \"example\": \"c.opus\"}"));
+        let csv = render(ReportFormat::Csv);
+        assert_eq!(
+            csv,
+            "vendor,encoder,count,example\nlibopus 1.3,opusenc,2,a.opus\n\"Lavf \"\"x\"\", y\",,1,c.opus\n"
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use crate::Error;
+
+/// Parses a finite decimal number, accepting either `.` or `,` as the decimal
+/// separator so that values written using either convention are understood.
+///
+/// Surrounding whitespace, a leading sign and an exponent are accepted. Values
+/// containing more than one separator are rejected, as is a comma followed by
+/// exactly three digits after a non-zero integer part of at most three digits
+/// (e.g. `1,234`), since that is likely to be thousands grouping.
+pub fn parse_number(value: &str) -> Result<f64, Error> {
+    let invalid = || Error::InvalidNumber(value.into());
+    let trimmed = value.trim();
+    let (mantissa, exponent) = match trimmed.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (trimmed, None),
+    };
+    let unsigned = mantissa.strip_prefix(['+', '-']).unwrap_or(mantissa);
+    let (integer, fraction) = match unsigned.split_once(['.', ',']) {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (unsigned, None),
+    };
+    let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if !is_digits(integer) || !fraction.map_or(true, is_digits) || (integer.len() + fraction.map_or(0, str::len)) == 0 {
+        return Err(invalid());
+    }
+    let thousands_grouping = unsigned.contains(',')
+        && fraction.map(str::len) == Some(3)
+        && (1..=3).contains(&integer.len())
+        && !integer.starts_with('0');
+    if thousands_grouping {
+        return Err(invalid());
+    }
+    if let Some(exponent) = exponent {
+        let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        if digits.is_empty() || !is_digits(digits) {
+            return Err(invalid());
+        }
+    }
+    match trimmed.replacen(',', ".", 1).parse::<f64>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_either_separator() {
+        for (value, expected) in [
+            ("-16.5", -16.5),
+            ("-16,5", -16.5),
+            ("+3,25", 3.25),
+            (" 0,125 ", 0.125),
+            ("1234,567", 1234.567),
+            ("1.234", 1.234),
+            (".5", 0.5),
+            (",5", 0.5),
+            ("5.", 5.0),
+            ("5,", 5.0),
+            ("-23", -23.0),
+            ("0", 0.0),
+            ("1e2", 100.0),
+            ("2,5E-1", 0.25),
+        ] {
+            assert_eq!(parse_number(value).ok(), Some(expected), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn rejects_thousands_grouping() {
+        for value in ["1,234", "-16,500", "1,234,567", "1.234,5", "1,234.5", "1.234.567"] {
+            assert!(matches!(parse_number(value), Err(Error::InvalidNumber(v)) if v == value), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        for value in [
+            "", " ", "-", "+", ".", ",", "--1", "+-1", "1-", "1 5", "1_000", "0x10", "1e", "1e+", "1e2.5", "e5", "inf",
+            "-inf", "NaN", "1e400", "١٢", "16 dB",
+        ] {
+            assert!(matches!(parse_number(value), Err(Error::InvalidNumber(_))), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn error_mentions_accepted_formats() {
+        let message = parse_number("1,234").unwrap_err().to_string();
+        assert!(message.contains("-16.5") && message.contains("-16,5"));
+        assert!(message.contains("thousands"));
+    }
+}
@@ -0,0 +1,450 @@
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+
+use crate::Error;
+
+/// The capture pattern found at the start of every Ogg page
+pub const CAPTURE_PATTERN: &[u8; 4] = b"OggS";
+
+/// The granule position of a page on which no packet ends
+const NO_GRANULE: u64 = u64::MAX;
+
+/// Length of an Ogg page header excluding its segment table
+pub(crate) const PAGE_HEADER_LENGTH: usize = 27;
+
+/// Number of bytes read at a time when searching for the start of a page
+const PAGE_SEARCH_CHUNK: usize = 4096;
+
+/// A bisection search narrowed to fewer bytes than this is completed by
+/// reading the remaining pages in order
+const BISECTION_THRESHOLD: u64 = 64 * 1024;
+
+/// The location of an Ogg page within a file together with the header fields
+/// needed for seeking
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageLocation {
+    /// Offset of the first byte of the page
+    pub offset: u64,
+
+    /// Length of the page in bytes, including its header
+    pub length: u64,
+
+    /// Serial number of the logical stream the page belongs to
+    pub serial: u32,
+
+    /// Granule position of the page, which is `u64::MAX` if no packet ends on
+    /// it
+    pub granule: u64,
+}
+
+impl PageLocation {
+    /// Offset of the first byte following the page
+    #[must_use]
+    pub fn end(&self) -> u64 { self.offset + self.length }
+
+    fn has_granule_of(&self, serial: u32) -> bool { self.serial == serial && self.granule != NO_GRANULE }
+}
+
+/// Updates an Ogg page checksum (CRC-32 with polynomial 0x04C11DB7, no
+/// reflection) with `data`
+pub(crate) fn update_checksum(checksum: u32, data: &[u8]) -> u32 {
+    data.iter().fold(checksum, |checksum, &byte| {
+        (0..8).fold(checksum ^ (u32::from(byte) << 24), |checksum, _| {
+            if checksum & 0x8000_0000 == 0 {
+                checksum << 1
+            } else {
+                (checksum << 1) ^ 0x04C1_1DB7
+            }
+        })
+    })
+}
+
+/// Fills `buffer` from `input`, returning `false` if the input ended first
+fn read_fully<R: Read>(input: &mut R, buffer: &mut [u8]) -> Result<bool, Error> {
+    match input.read_exact(buffer) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(Error::ReadError(e)),
+    }
+}
+
+/// Reads as much of `buffer` as possible from `input`, returning the number of
+/// bytes read
+fn read_up_to<R: Read>(input: &mut R, buffer: &mut [u8]) -> Result<usize, Error> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match input.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(Error::ReadError(e)),
+        }
+    }
+    Ok(filled)
+}
+
+/// Whether the data read from `input` begins with the Ogg capture pattern, as
+/// any Ogg file does whatever its name. Only the pattern itself is read.
+pub fn starts_with_capture_pattern<R: Read>(input: &mut R) -> Result<bool, Error> {
+    let mut pattern = [0u8; CAPTURE_PATTERN.len()];
+    Ok(read_fully(input, &mut pattern)? && &pattern == CAPTURE_PATTERN)
+}
+
+/// Reads the page starting at `offset`, returning `None` if there is no
+/// complete page with a valid checksum there
+pub fn read_page_at<R: Read + Seek>(input: &mut R, offset: u64) -> Result<Option<PageLocation>, Error> {
+    input.seek(SeekFrom::Start(offset)).map_err(Error::ReadError)?;
+    let mut header = [0u8; PAGE_HEADER_LENGTH];
+    if !read_fully(input, &mut header)? || &header[..4] != CAPTURE_PATTERN || header[4] != 0 {
+        return Ok(None);
+    }
+    let mut segments = vec![0u8; usize::from(header[26])];
+    if !read_fully(input, &mut segments)? {
+        return Ok(None);
+    }
+    let mut body = vec![0u8; segments.iter().copied().map(usize::from).sum()];
+    if !read_fully(input, &mut body)? {
+        return Ok(None);
+    }
+    let expected_checksum = u32::from_le_bytes(header[22..26].try_into().expect("Checksum has wrong length"));
+    header[22..26].fill(0);
+    let checksum = [header.as_slice(), &segments, &body].into_iter().fold(0, update_checksum);
+    if checksum != expected_checksum {
+        return Ok(None);
+    }
+    let length = PAGE_HEADER_LENGTH + segments.len() + body.len();
+    Ok(Some(PageLocation {
+        offset,
+        length: u64::try_from(length).expect("Page length out of range"),
+        serial: u32::from_le_bytes(header[14..18].try_into().expect("Serial has wrong length")),
+        granule: u64::from_le_bytes(header[6..14].try_into().expect("Granule position has wrong length")),
+    }))
+}
+
+/// Finds the first valid page starting at or after `offset` and before
+/// `limit`
+pub fn next_page<R: Read + Seek>(input: &mut R, offset: u64, limit: u64) -> Result<Option<PageLocation>, Error> {
+    // Consecutive chunks overlap so that a capture pattern spanning two chunks
+    // is still found
+    let mut buffer = vec![0u8; PAGE_SEARCH_CHUNK + 3];
+    let mut position = offset;
+    while position < limit {
+        input.seek(SeekFrom::Start(position)).map_err(Error::ReadError)?;
+        let filled = read_up_to(input, &mut buffer)?;
+        let candidates = buffer[..filled].windows(4).enumerate().take(PAGE_SEARCH_CHUNK);
+        let candidates =
+            candidates.filter(|(_, window)| *window == CAPTURE_PATTERN).map(|(idx, _)| position + idx as u64);
+        for candidate in candidates.take_while(|&candidate| candidate < limit) {
+            if let Some(page) = read_page_at(input, candidate)? {
+                return Ok(Some(page));
+            }
+        }
+        if filled < buffer.len() {
+            break;
+        }
+        position += PAGE_SEARCH_CHUNK as u64;
+    }
+    Ok(None)
+}
+
+/// Finds the first page of logical stream `serial` with a granule position
+/// starting at or after `offset` and before `limit`
+fn next_page_with_granule<R: Read + Seek>(
+    input: &mut R, serial: u32, offset: u64, limit: u64,
+) -> Result<Option<PageLocation>, Error> {
+    let mut offset = offset;
+    while let Some(page) = next_page(input, offset, limit)? {
+        if page.has_granule_of(serial) {
+            return Ok(Some(page));
+        }
+        offset = page.end();
+    }
+    Ok(None)
+}
+
+/// Finds the last page of logical stream `serial` whose granule position is
+/// no greater than `target`, searching the pages of `input` from byte offset
+/// `start`, which would normally be the end of the stream headers.
+///
+/// A bisection search is used, so only a small part of a long file is read.
+/// The audio of the packets following the returned page begins at its granule
+/// position, except that a packet continued from the page itself is
+/// incomplete. `None` is returned if no page has a granule position of at most
+/// `target`.
+pub fn find_page_before_granule<R: Read + Seek>(
+    input: &mut R, serial: u32, target: u64, start: u64,
+) -> Result<Option<PageLocation>, Error> {
+    let end = input.seek(SeekFrom::End(0)).map_err(Error::ReadError)?;
+    // Every page with a granule position that begins at or after `high` is past
+    // the target
+    let mut low = start;
+    let mut high = end;
+    let mut best = None;
+    while low < high && high - low > BISECTION_THRESHOLD {
+        let middle = low + (high - low) / 2;
+        match next_page_with_granule(input, serial, middle, high)? {
+            Some(page) if page.granule <= target => {
+                low = page.end();
+                best = Some(page);
+            }
+            _ => high = middle,
+        }
+    }
+    let mut offset = low;
+    while let Some(page) = next_page_with_granule(input, serial, offset, end)? {
+        if page.granule > target {
+            break;
+        }
+        offset = page.end();
+        best = Some(page);
+    }
+    Ok(best)
+}
+
+/// Returns the granule position of the last page of logical stream `serial`,
+/// searching backwards from the end of `input` no further than byte offset
+/// `start`. `None` is returned if the stream has no page with a granule
+/// position.
+pub fn last_granule<R: Read + Seek>(input: &mut R, serial: u32, start: u64) -> Result<Option<u64>, Error> {
+    let end = input.seek(SeekFrom::End(0)).map_err(Error::ReadError)?;
+    let mut distance = BISECTION_THRESHOLD;
+    loop {
+        let from = end.saturating_sub(distance).max(start);
+        let mut offset = from;
+        let mut last = None;
+        while let Some(page) = next_page_with_granule(input, serial, offset, end)? {
+            offset = page.end();
+            last = Some(page.granule);
+        }
+        if last.is_some() || from == start {
+            return Ok(last);
+        }
+        distance = distance.saturating_mul(2);
+    }
+}
+
+/// Positions `reader` at the start of the page of logical stream `serial`
+/// containing the sample with granule position `target_granule`, returning the
+/// granule position the reader landed on.
+///
+/// The returned granule position is that of the preceding page of the stream,
+/// so decoding the packets which begin on the page the reader is positioned at
+/// resumes at that position. Header pages have a granule position of zero, so
+/// seeking to zero positions the reader at the first page of audio. Seeking
+/// beyond the end of the stream positions the reader immediately after its
+/// last page, which may be the end of the input or the start of a chained
+/// stream. Pages on which no packet ends, which have a granule position of
+/// -1, are never landed on. A bisection search is used, so only a small part of
+/// the input is read when it contains a single logical stream.
+///
+/// `Error::MissingLogicalStream` is returned if no page of the stream with a
+/// granule position is found.
+pub fn seek_to_granule<R: Read + Seek>(reader: &mut R, serial: u32, target_granule: u64) -> Result<u64, Error> {
+    let page =
+        find_page_before_granule(reader, serial, target_granule, 0)?.ok_or(Error::MissingLogicalStream(serial))?;
+    reader.seek(SeekFrom::Start(page.end())).map_err(Error::ReadError)?;
+    Ok(page.granule)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+    use ogg::{Packet, PacketReader};
+
+    use super::*;
+    use crate::testing::{one_packet_per_page, opus_file, read_packets, StreamSpec, FRAME_SIZE};
+
+    /// A stream long enough that seeking must bisect
+    fn long_stream() -> StreamSpec { StreamSpec { num_samples: 3000 * FRAME_SIZE, ..StreamSpec::default() } }
+
+    /// The granule position at the end of the last stream of an Ogg file
+    fn end_granule(data: &[u8]) -> u64 { read_packets(data).last().unwrap().absgp_page() }
+
+    /// The offset of the first page following the two header pages
+    fn audio_start(data: &[u8]) -> u64 {
+        let mut input = Cursor::new(data);
+        let id_page = read_page_at(&mut input, 0).unwrap().unwrap();
+        read_page_at(&mut input, id_page.end()).unwrap().unwrap().end()
+    }
+
+    /// The granule positions of the pages of an Ogg file on which a packet
+    /// ends, taken from the `ogg` crate's reader
+    fn page_granules(data: &[u8]) -> Vec<u64> {
+        read_packets(data).iter().skip(2).filter(|packet| packet.last_in_page()).map(Packet::absgp_page).collect()
+    }
+
+    #[test]
+    fn pages_are_read_in_sequence() {
+        let data = opus_file(&[StreamSpec::default()]);
+        let mut input = Cursor::new(&data);
+        let mut offset = 0;
+        let mut pages = Vec::new();
+        while let Some(page) = next_page(&mut input, offset, data.len() as u64).unwrap() {
+            assert_eq!(page.offset, offset);
+            offset = page.end();
+            pages.push(page);
+        }
+        assert_eq!(offset, data.len() as u64);
+        assert!(pages.iter().all(|page| page.serial == 1));
+        let granules: Vec<_> = pages.iter().skip(2).map(|page| page.granule).collect();
+        assert_eq!(granules, page_granules(&data));
+    }
+
+    #[test]
+    fn capture_pattern_is_detected() {
+        let data = opus_file(&[StreamSpec::default()]);
+        assert!(starts_with_capture_pattern(&mut Cursor::new(&data)).unwrap());
+        for other in [b"".as_slice(), b"Ogg", b"NAME=OggS\n", b"fLaC\0\0\0\x22"] {
+            assert!(!starts_with_capture_pattern(&mut Cursor::new(other)).unwrap());
+        }
+    }
+
+    #[test]
+    fn invalid_pages_are_skipped() {
+        let data = opus_file(&[StreamSpec::default()]);
+        let mut corrupted = b"OggS\0 not a page".to_vec();
+        let garbage_len = corrupted.len() as u64;
+        corrupted.extend_from_slice(&data);
+        let mut input = Cursor::new(&corrupted);
+        let page = next_page(&mut input, 0, corrupted.len() as u64).unwrap().unwrap();
+        assert_eq!(page.offset, garbage_len);
+
+        // A page with a bad checksum is passed over
+        let checksum_offset = usize::try_from(page.offset).unwrap() + 22;
+        corrupted[checksum_offset] ^= 0xFF;
+        let mut input = Cursor::new(&corrupted);
+        assert_eq!(read_page_at(&mut input, page.offset).unwrap(), None);
+        assert_eq!(next_page(&mut input, 0, corrupted.len() as u64).unwrap().unwrap().offset, page.end());
+        assert_eq!(next_page(&mut input, 0, page.end()).unwrap(), None);
+    }
+
+    #[test]
+    fn seek_is_accurate_to_a_packet() {
+        let data = one_packet_per_page(&opus_file(&[long_stream()]));
+        let (start, end) = (audio_start(&data), end_granule(&data));
+        let mut input = Cursor::new(&data);
+        assert_eq!(find_page_before_granule(&mut input, 1, 959, start).unwrap(), None);
+        for target in [960, 961, 48000, 1_234_567, 1_440_000, 2_879_999, 2_880_000, u64::MAX - 1] {
+            let page = find_page_before_granule(&mut input, 1, target, start).unwrap().unwrap();
+            assert!(page.granule <= target);
+            let is_last = page.granule == end;
+            assert!(target - page.granule < 960 || is_last, "Seek to {} found {}", target, page.granule);
+            assert_eq!(read_page_at(&mut input, page.offset).unwrap(), Some(page));
+        }
+    }
+
+    #[test]
+    fn seek_matches_linear_search() {
+        let data = opus_file(&[long_stream()]);
+        let granules = page_granules(&data);
+        let start = audio_start(&data);
+        let mut input = Cursor::new(&data);
+        for target in (0..end_granule(&data)).step_by(12_345) {
+            let expected = granules.iter().rev().find(|&&granule| granule <= target).copied();
+            let found = find_page_before_granule(&mut input, 1, target, start).unwrap().map(|page| page.granule);
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn seek_ignores_other_streams() {
+        let data = opus_file(&[long_stream(), StreamSpec { serial: 2, ..long_stream() }]);
+        let (start, end) = (audio_start(&data), end_granule(&data));
+        let mut input = Cursor::new(&data);
+        let page = find_page_before_granule(&mut input, 1, u64::MAX - 1, start).unwrap().unwrap();
+        assert_eq!(page.granule, end);
+        assert_eq!(last_granule(&mut input, 1, start).unwrap(), Some(end));
+        assert_eq!(last_granule(&mut input, 2, start).unwrap(), Some(end));
+        assert_eq!(last_granule(&mut input, 3, start).unwrap(), None);
+    }
+
+    /// Reads the next packet from the current position of `input`
+    fn next_packet(input: &mut Cursor<&Vec<u8>>) -> Option<Packet> { PacketReader::new(input).read_packet().unwrap() }
+
+    #[test]
+    fn seek_to_start() {
+        let data = one_packet_per_page(&opus_file(&[long_stream()]));
+        let mut input = Cursor::new(&data);
+        assert_eq!(seek_to_granule(&mut input, 1, 0).unwrap(), 0);
+        assert_eq!(input.position(), audio_start(&data));
+        assert_eq!(next_packet(&mut input).unwrap().absgp_page(), 960);
+    }
+
+    #[test]
+    fn seek_lands_on_page_containing_target() {
+        let data = one_packet_per_page(&opus_file(&[long_stream()]));
+        let mut input = Cursor::new(&data);
+        for target in [1, 959, 960, 961, 48_000, 1_000_001, 2_879_039, 2_879_040, 2_879_999] {
+            let granule = seek_to_granule(&mut input, 1, target).unwrap();
+            assert!(granule <= target && target - granule < 960, "Seek to {} landed on {}", target, granule);
+            // Every packet is 960 samples so the next one ends a packet later
+            assert_eq!(next_packet(&mut input).unwrap().absgp_page(), granule + 960);
+        }
+    }
+
+    #[test]
+    fn seek_to_and_past_end() {
+        let data = opus_file(&[long_stream()]);
+        let end = end_granule(&data);
+        let mut input = Cursor::new(&data);
+        for target in [end, end + 1, u64::MAX - 1, u64::MAX] {
+            assert_eq!(seek_to_granule(&mut input, 1, target).unwrap(), end);
+            assert_eq!(input.position(), data.len() as u64);
+            assert!(next_packet(&mut input).is_none());
+        }
+    }
+
+    #[test]
+    fn seek_within_chained_streams() {
+        let first = opus_file(&[long_stream()]);
+        let second = opus_file(&[StreamSpec { serial: 2, num_samples: 500 * FRAME_SIZE, ..StreamSpec::default() }]);
+        let data = [first.clone(), second.clone()].concat();
+        let mut input = Cursor::new(&data);
+
+        // The end of the first stream is the start of the second
+        assert_eq!(seek_to_granule(&mut input, 1, u64::MAX).unwrap(), end_granule(&first));
+        assert_eq!(input.position(), first.len() as u64);
+        assert_eq!(next_packet(&mut input).unwrap().stream_serial(), 2);
+
+        assert_eq!(seek_to_granule(&mut input, 2, 0).unwrap(), 0);
+        assert_eq!(input.position(), first.len() as u64 + audio_start(&second));
+        let packet = next_packet(&mut input).unwrap();
+        assert_eq!(packet.stream_serial(), 2);
+        assert!(packet.absgp_page() > 0);
+
+        let expected = page_granules(&second).into_iter().rev().find(|&granule| granule <= 240_000);
+        assert_eq!(Some(seek_to_granule(&mut input, 2, 240_000).unwrap()), expected);
+        assert_eq!(next_packet(&mut input).unwrap().stream_serial(), 2);
+        assert!(matches!(seek_to_granule(&mut input, 3, 0), Err(Error::MissingLogicalStream(3))));
+    }
+
+    #[test]
+    fn seek_skips_pages_without_granule() {
+        // A comment header spanning several pages leaves pages on which no packet ends
+        let mut data = Vec::new();
+        let mut writer = PacketWriter::new(&mut data);
+        for (idx, packet) in read_packets(&opus_file(&[StreamSpec::default()])).into_iter().enumerate() {
+            let mut packet_data = packet.data.clone();
+            if idx == 1 {
+                packet_data.resize(100_000, 0);
+            }
+            let info =
+                if packet.last_in_stream() { PacketWriteEndInfo::EndStream } else { PacketWriteEndInfo::EndPage };
+            writer.write_packet(packet_data, 1, info, packet.absgp_page()).unwrap();
+        }
+        drop(writer);
+
+        let mut input = Cursor::new(&data);
+        let mut offset = 0;
+        let mut granules = Vec::new();
+        while let Some(page) = next_page(&mut input, offset, data.len() as u64).unwrap() {
+            offset = page.end();
+            granules.push(page.granule);
+        }
+        assert_eq!(granules[..4], [0, u64::MAX, 0, 960]);
+
+        assert_eq!(seek_to_granule(&mut input, 1, 0).unwrap(), 0);
+        assert_eq!(next_packet(&mut input).unwrap().absgp_page(), 960);
+    }
+}
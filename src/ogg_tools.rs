@@ -0,0 +1,515 @@
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind, Read, Seek, Write};
+
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use ogg::{Packet, PacketReader};
+
+use crate::header::IdHeader as _;
+use crate::ogg_seek::{update_checksum, CAPTURE_PATTERN, PAGE_HEADER_LENGTH};
+use crate::{opus, Codec, Error};
+
+/// Header type flag marking the first page of a logical stream
+const FLAG_BEGINS_STREAM: u8 = 0x02;
+
+/// Header type flag marking the last page of a logical stream
+const FLAG_ENDS_STREAM: u8 = 0x04;
+
+/// A half-open range of Ogg pages, numbered from zero in the order they occur
+/// in the input.
+///
+/// Pages are counted as packets are read, so a page on which no packet ends
+/// (because it only contains part of a larger packet) is counted together with
+/// the page on which that packet ends.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PageRange {
+    /// Index of the first page to include
+    pub start: usize,
+
+    /// Index of the first page to exclude, or `None` to include all pages
+    /// after `start`
+    pub end: Option<usize>,
+}
+
+impl PageRange {
+    /// Constructs a range containing all pages from `start` onwards
+    #[must_use]
+    pub fn starting_at(start: usize) -> PageRange { PageRange { start, end: None } }
+
+    /// Constructs a range containing the first `count` pages
+    #[must_use]
+    pub fn first(count: usize) -> PageRange { PageRange { start: 0, end: Some(count) } }
+
+    /// Does the range contain the page with the specified index?
+    #[must_use]
+    pub fn contains(&self, index: usize) -> bool { index >= self.start && self.end.map_or(true, |end| index < end) }
+}
+
+fn packet_write_end_info(packet: &Packet) -> PacketWriteEndInfo {
+    if packet.last_in_stream() {
+        PacketWriteEndInfo::EndStream
+    } else if packet.last_in_page() {
+        PacketWriteEndInfo::EndPage
+    } else {
+        PacketWriteEndInfo::NormalPacket
+    }
+}
+
+fn write_packet<W: Write>(
+    writer: &mut PacketWriter<W>, packet: Packet, info: PacketWriteEndInfo, granule: u64,
+) -> Result<(), Error> {
+    let serial = packet.stream_serial();
+    writer.write_packet(packet.data, serial, info, granule).map_err(Error::WriteError)
+}
+
+/// Copies the packets ending on the pages in `range` from `input` to `output`,
+/// returning the number of pages copied.
+///
+/// Page sequence numbers and checksums are regenerated, the first page copied
+/// from each logical stream is marked as beginning that stream and the last is
+/// marked as ending it. Granule positions are copied unchanged. This is
+/// intended for removing corrupt pages from the end of a file. Removing pages
+/// from the start of a logical stream will also remove its headers.
+pub fn copy_pages<R: Read + Seek, W: Write>(input: R, mut output: W, range: PageRange) -> Result<usize, Error> {
+    let mut reader = PacketReader::new(input);
+    let mut writer = PacketWriter::new(&mut output);
+    // The last packet copied from each logical stream is held back so that it can
+    // be marked as ending the stream if the range ends before the stream does
+    let mut pending = BTreeMap::new();
+    let mut page_index = 0;
+    let mut num_copied = 0;
+    while range.end.map_or(true, |end| page_index < end) {
+        let Some(packet) = reader.read_packet().map_err(Error::OggDecode)? else {
+            break;
+        };
+        let ends_page = packet.last_in_page();
+        if range.contains(page_index) {
+            if let Some(previous) = pending.insert(packet.stream_serial(), packet) {
+                let info = packet_write_end_info(&previous);
+                let granule = previous.absgp_page();
+                write_packet(&mut writer, previous, info, granule)?;
+            }
+            num_copied += usize::from(ends_page);
+        }
+        page_index += usize::from(ends_page);
+    }
+    for packet in pending.into_values() {
+        let granule = packet.absgp_page();
+        write_packet(&mut writer, packet, PacketWriteEndInfo::EndStream, granule)?;
+    }
+    drop(writer);
+    output.flush().map_err(Error::WriteError)?;
+    Ok(num_copied)
+}
+
+/// A packet waiting to be written together with the granule positions to use
+/// depending on whether it turns out to be the last packet of the output
+struct HeldPacket {
+    packet: Packet,
+    info: PacketWriteEndInfo,
+    granule: u64,
+    final_granule: u64,
+}
+
+/// Joins the Ogg Opus streams in `inputs` into a single logical stream written
+/// to `output`.
+///
+/// Each input must contain a single logical stream and all inputs must have
+/// identical identification headers, otherwise `Error::IncompatibleStreams` is
+/// returned. The output uses the serial number and headers of the first input.
+/// Granule positions are recomputed from the durations of the audio packets so
+/// that they increase across each joint. Any end trimming of the final input is
+/// preserved, but that of earlier inputs cannot be represented mid-stream so
+/// their padding samples become part of the output, as do the pre-skip
+/// samples of all inputs after the first.
+pub fn concatenate<I, R, W>(inputs: I, mut output: W) -> Result<(), Error>
+where
+    I: IntoIterator<Item = R>,
+    R: Read + Seek,
+    W: Write,
+{
+    let mut writer = PacketWriter::new(&mut output);
+    let mut first: Option<(u32, opus::IdHeader)> = None;
+    let mut held: Option<HeldPacket> = None;
+    let mut num_samples = 0;
+    for input in inputs {
+        let mut reader = PacketReader::new(input);
+        let id_packet = reader.read_packet().map_err(Error::OggDecode)?.ok_or(Error::MissingHeaders)?;
+        let input_serial = id_packet.stream_serial();
+        let id_header = opus::IdHeader::try_parse(&id_packet.data)?.ok_or(Error::MissingStream(Codec::Opus))?;
+        let sample_rate = u32::try_from(id_header.output_sample_rate()).expect("Unable to truncate sample rate");
+        let comment_packet = reader.read_packet().map_err(Error::OggDecode)?.ok_or(Error::MissingHeaders)?;
+        if comment_packet.stream_serial() != input_serial {
+            return Err(Error::UnexpectedLogicalStream(comment_packet.stream_serial()));
+        }
+        let serial = match &first {
+            None => {
+                let serial = input_serial;
+                writer
+                    .write_packet(id_packet.data, serial, PacketWriteEndInfo::EndPage, 0)
+                    .map_err(Error::WriteError)?;
+                held = Some(HeldPacket {
+                    packet: comment_packet,
+                    info: PacketWriteEndInfo::EndPage,
+                    granule: 0,
+                    final_granule: 0,
+                });
+                first = Some((serial, id_header));
+                serial
+            }
+            Some((serial, first_header)) => {
+                if *first_header != id_header {
+                    return Err(Error::IncompatibleStreams);
+                }
+                *serial
+            }
+        };
+        let offset = num_samples;
+        while let Some(packet) = reader.read_packet().map_err(Error::OggDecode)? {
+            if packet.stream_serial() != input_serial {
+                return Err(Error::UnexpectedLogicalStream(packet.stream_serial()));
+            }
+            let packet_samples = ::opus::packet::get_nb_samples(&packet.data, sample_rate).map_err(Error::OpusError)?;
+            num_samples += u64::try_from(packet_samples).expect("Sample count out of range");
+            let info = if packet.last_in_page() || packet.last_in_stream() {
+                PacketWriteEndInfo::EndPage
+            } else {
+                PacketWriteEndInfo::NormalPacket
+            };
+            let final_granule = std::cmp::min(offset + packet.absgp_page(), num_samples);
+            if let Some(previous) = held.replace(HeldPacket { packet, info, granule: num_samples, final_granule }) {
+                writer
+                    .write_packet(previous.packet.data, serial, previous.info, previous.granule)
+                    .map_err(Error::WriteError)?;
+            }
+        }
+    }
+    if let (Some(previous), Some((serial, _))) = (held, first) {
+        writer
+            .write_packet(previous.packet.data, serial, PacketWriteEndInfo::EndStream, previous.final_granule)
+            .map_err(Error::WriteError)?;
+    }
+    drop(writer);
+    output.flush().map_err(Error::WriteError)
+}
+
+/// The serial number of the logical stream an Ogg page belongs to
+fn page_serial(page: &[u8]) -> u32 { u32::from_le_bytes(page[14..18].try_into().expect("Serial has wrong length")) }
+
+/// Corrects the sequence numbers and beginning and end of stream flags of Ogg
+/// pages written to it before forwarding them to an inner writer.
+///
+/// This allows pages copied unchanged from an input to follow pages produced
+/// by writing packets, for example when a rewritten header occupies a different
+/// number of pages to the original:
+/// - The pages of each logical stream are numbered consecutively from zero.
+/// - The first page of each logical stream is marked as beginning it and no
+///   other page is.
+/// - If a page marked as ending its stream is immediately followed by another
+///   page of the same stream, the mark is moved to the later page.
+/// - The last page written is marked as ending its stream.
+///
+/// Checksums of all pages are recomputed. Only whole pages may be written,
+/// although they may be split across writes in any way. The last page is held
+/// back until `finish()` is called.
+#[derive(Debug)]
+pub struct PageFixer<W: Write> {
+    output: W,
+    buffer: Vec<u8>,
+    held: Option<Vec<u8>>,
+    next_sequence: BTreeMap<u32, u32>,
+}
+
+impl<W: Write> PageFixer<W> {
+    /// Constructs a `PageFixer` writing corrected pages to `output`
+    #[must_use]
+    pub fn new(output: W) -> PageFixer<W> {
+        PageFixer { output, buffer: Vec::new(), held: None, next_sequence: BTreeMap::new() }
+    }
+
+    /// Writes the last page and flushes the inner writer, which is returned.
+    /// Fails if a partial page was written.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if !self.buffer.is_empty() {
+            return Err(Error::WriteError(io::Error::new(ErrorKind::UnexpectedEof, "Incomplete Ogg page")));
+        }
+        if let Some(mut page) = self.held.take() {
+            page[5] |= FLAG_ENDS_STREAM;
+            self.write_page(page).map_err(Error::WriteError)?;
+        }
+        self.output.flush().map_err(Error::WriteError)?;
+        Ok(self.output)
+    }
+
+    /// Removes each complete page from the start of the buffer
+    fn process_buffer(&mut self) -> io::Result<()> {
+        while self.buffer.len() >= PAGE_HEADER_LENGTH {
+            if &self.buffer[..4] != CAPTURE_PATTERN || self.buffer[4] != 0 {
+                return Err(io::Error::new(ErrorKind::InvalidData, "Data written was not an Ogg page"));
+            }
+            let segments_end = PAGE_HEADER_LENGTH + usize::from(self.buffer[26]);
+            let Some(segments) = self.buffer.get(PAGE_HEADER_LENGTH..segments_end) else {
+                break;
+            };
+            let length = segments_end + segments.iter().copied().map(usize::from).sum::<usize>();
+            if self.buffer.len() < length {
+                break;
+            }
+            let page = self.buffer.drain(..length).collect();
+            self.submit_page(page)?;
+        }
+        Ok(())
+    }
+
+    fn submit_page(&mut self, mut page: Vec<u8>) -> io::Result<()> {
+        if let Some(mut held) = self.held.take() {
+            if held[5] & FLAG_ENDS_STREAM != 0 && page_serial(&held) == page_serial(&page) {
+                held[5] &= !FLAG_ENDS_STREAM;
+                page[5] |= FLAG_ENDS_STREAM;
+            }
+            self.write_page(held)?;
+        }
+        self.held = Some(page);
+        Ok(())
+    }
+
+    fn write_page(&mut self, mut page: Vec<u8>) -> io::Result<()> {
+        let sequence = self.next_sequence.entry(page_serial(&page)).or_insert(0);
+        if *sequence == 0 {
+            page[5] |= FLAG_BEGINS_STREAM;
+        } else {
+            page[5] &= !FLAG_BEGINS_STREAM;
+        }
+        page[18..22].copy_from_slice(&sequence.to_le_bytes());
+        *sequence = sequence.wrapping_add(1);
+        page[22..26].fill(0);
+        let checksum = update_checksum(0, &page);
+        page[22..26].copy_from_slice(&checksum.to_le_bytes());
+        self.output.write_all(&page)
+    }
+}
+
+impl<W: Write> Write for PageFixer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        self.process_buffer()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.output.flush() }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::header::{CommentHeader as _, CommentList as _};
+    use crate::ogg_seek::read_page_at;
+    use crate::opus::VolumeAnalyzer;
+    use crate::testing::{one_packet_per_page, opus_file, read_packets, StreamSpec, FRAME_SIZE, SAMPLE_RATE};
+
+    fn one_second() -> StreamSpec { StreamSpec { num_samples: SAMPLE_RATE, ..StreamSpec::default() } }
+
+    fn analyzed_duration(data: &[u8]) -> Duration {
+        let mut analyzer = VolumeAnalyzer::default();
+        analyzer.analyze_stream_with_interrupt(Cursor::new(data), &crate::interrupt::Never::default()).unwrap();
+        analyzer.last_track_duration().unwrap()
+    }
+
+    /// The granule position of the last packet of an Ogg file
+    fn end_granule(data: &[u8]) -> u64 { read_packets(data).last().unwrap().absgp_page() }
+
+    /// The duration of the audio of `data` up to `granule`, excluding pre-skip
+    fn audio_duration(data: &[u8], granule: u64) -> Duration {
+        let id_header = opus::IdHeader::try_parse(&read_packets(data)[0].data).unwrap().unwrap();
+        let samples = granule - u64::try_from(id_header.preskip_samples()).unwrap();
+        Duration::from_nanos(samples * 1_000_000_000 / 48000)
+    }
+
+    #[test]
+    fn page_range_contains() {
+        assert!(PageRange::starting_at(2).contains(2));
+        assert!(!PageRange::starting_at(2).contains(1));
+        assert!(PageRange::first(2).contains(1));
+        assert!(!PageRange::first(2).contains(2));
+        assert!(PageRange::default().contains(usize::MAX));
+    }
+
+    #[test]
+    fn copy_all_pages_preserves_packets() {
+        let data = one_packet_per_page(&opus_file(&[StreamSpec::default()]));
+        let mut output = Vec::new();
+        let num_copied = copy_pages(Cursor::new(&data), &mut output, PageRange::default()).unwrap();
+        assert_eq!(num_copied, read_packets(&data).len());
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn trimmed_pages_end_stream() {
+        let data = one_packet_per_page(&opus_file(&[StreamSpec::default()]));
+        let mut output = Vec::new();
+        // Both headers and 25 audio packets
+        let num_copied = copy_pages(Cursor::new(&data), &mut output, PageRange::first(27)).unwrap();
+        assert_eq!(num_copied, 27);
+        let packets = read_packets(&output);
+        assert_eq!(packets.len(), 27);
+        assert!(packets.last().unwrap().last_in_stream());
+        assert_eq!(packets.last().unwrap().absgp_page(), 25 * 960);
+        assert_eq!(analyzed_duration(&output), audio_duration(&output, 25 * 960));
+    }
+
+    #[test]
+    fn concatenated_streams_decode() {
+        let first = opus_file(&[StreamSpec { serial: 1, ..one_second() }]);
+        let second = opus_file(&[StreamSpec { serial: 2, num_samples: SAMPLE_RATE / 2, ..StreamSpec::default() }]);
+        let mut output = Vec::new();
+        concatenate([Cursor::new(&first), Cursor::new(&second)], &mut output).unwrap();
+
+        let packets = read_packets(&output);
+        let first_packets = read_packets(&first).len() - 2;
+        assert_eq!(packets.len(), first_packets + read_packets(&second).len());
+        assert!(packets.iter().all(|packet| packet.stream_serial() == 1));
+        let granules: Vec<_> = packets.iter().filter(|packet| packet.last_in_page()).map(Packet::absgp_page).collect();
+        assert!(granules.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(packets.last().unwrap().last_in_stream());
+        // The padding of the first input remains but the end trimming of the
+        // second is preserved
+        let end = u64::try_from(first_packets * FRAME_SIZE).unwrap() + end_granule(&second);
+        assert_eq!(packets.last().unwrap().absgp_page(), end);
+        assert_eq!(analyzed_duration(&output), audio_duration(&output, end));
+    }
+
+    #[test]
+    fn concatenate_rejects_differing_headers() {
+        let stereo = opus_file(&[StreamSpec::default()]);
+        let mono = opus_file(&[StreamSpec { channels: 1, ..StreamSpec::default() }]);
+        let result = concatenate([Cursor::new(&stereo), Cursor::new(&mono)], std::io::sink());
+        assert!(matches!(result, Err(Error::IncompatibleStreams)));
+    }
+
+    #[test]
+    fn concatenate_rejects_chained_input() {
+        let chained = opus_file(&[StreamSpec::default(), StreamSpec { serial: 2, ..StreamSpec::default() }]);
+        let result = concatenate([Cursor::new(&chained)], std::io::sink());
+        assert!(matches!(result, Err(Error::UnexpectedLogicalStream(2))));
+    }
+
+    /// Splits an Ogg file into its pages, checking their checksums
+    fn split_pages(data: &[u8]) -> Vec<&[u8]> {
+        let mut pages = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let page = read_page_at(&mut Cursor::new(data), offset as u64).unwrap().expect("Invalid page");
+            let end = usize::try_from(page.end()).unwrap();
+            pages.push(&data[offset..end]);
+            offset = end;
+        }
+        pages
+    }
+
+    /// Returns the sequence number and beginning and end of stream flags of
+    /// each page
+    fn page_fields(data: &[u8]) -> Vec<(u32, bool, bool)> {
+        let fields = |page: &[u8]| {
+            let sequence = u32::from_le_bytes(page[18..22].try_into().unwrap());
+            (sequence, page[5] & FLAG_BEGINS_STREAM != 0, page[5] & FLAG_ENDS_STREAM != 0)
+        };
+        split_pages(data).into_iter().map(fields).collect()
+    }
+
+    fn assert_well_formed(data: &[u8]) {
+        let fields = page_fields(data);
+        let last = fields.len() - 1;
+        for (idx, (sequence, begins, ends)) in fields.into_iter().enumerate() {
+            let expected = (u32::try_from(idx).unwrap(), idx == 0, idx == last);
+            assert_eq!((sequence, begins, ends), expected, "Page {}", idx);
+        }
+        assert!(read_packets(data).last().unwrap().last_in_stream());
+    }
+
+    /// Serializes an Opus comment header containing a comment of `length`
+    /// bytes
+    fn comment_header(length: usize) -> Vec<u8> {
+        let mut header = crate::opus::CommentHeader::default();
+        header.push("PADDING", &"x".repeat(length)).unwrap();
+        let mut data = Vec::new();
+        header.serialize_into(&mut data).unwrap();
+        data
+    }
+
+    /// Replaces the comment header of `data`, which occupies `comment_pages`
+    /// pages, by writing the new headers as packets and copying the following
+    /// pages unchanged
+    fn replace_comment_header(data: &[u8], comment_pages: usize, comment: Vec<u8>) -> Vec<u8> {
+        let pages = split_pages(data);
+        let id_header = read_packets(data).swap_remove(0);
+        let serial = id_header.stream_serial();
+        let mut fixer = PageFixer::new(Vec::new());
+        {
+            let mut writer = PacketWriter::new(&mut fixer);
+            writer.write_packet(id_header.data, serial, PacketWriteEndInfo::EndPage, 0).unwrap();
+            writer.write_packet(comment, serial, PacketWriteEndInfo::EndPage, 0).unwrap();
+        }
+        for page in &pages[1 + comment_pages..] {
+            fixer.write_all(page).unwrap();
+        }
+        fixer.finish().unwrap()
+    }
+
+    #[test]
+    fn header_grows_by_a_page() {
+        let data = one_packet_per_page(&opus_file(&[one_second()]));
+        let num_packets = read_packets(&data).len();
+        let output = replace_comment_header(&data, 1, comment_header(70000));
+        assert_eq!(split_pages(&output).len(), num_packets + 1);
+        assert_well_formed(&output);
+        assert_eq!(read_packets(&output).len(), num_packets);
+        assert_eq!(analyzed_duration(&output), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn header_shrinks_by_a_page() {
+        let data = one_packet_per_page(&opus_file(&[one_second()]));
+        let grown = replace_comment_header(&data, 1, comment_header(70000));
+        let output = replace_comment_header(&grown, 2, comment_header(10));
+        assert_eq!(split_pages(&output).len(), read_packets(&data).len());
+        assert_well_formed(&output);
+        assert_eq!(analyzed_duration(&output), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn comment_header_ending_stream_remains_last() {
+        let data = opus_file(&[StreamSpec { num_samples: 0, ..StreamSpec::default() }]);
+        assert_eq!(page_fields(&data), [(0, true, false), (1, false, true)]);
+        for length in [10, 70000] {
+            let output = replace_comment_header(&data, 1, comment_header(length));
+            assert_well_formed(&output);
+            assert_eq!(read_packets(&output).len(), 2);
+        }
+    }
+
+    #[test]
+    fn end_of_stream_moves_to_following_page() {
+        let data = opus_file(&[StreamSpec { num_samples: 0, ..StreamSpec::default() }]);
+        let pages = split_pages(&data);
+        let mut fixer = PageFixer::new(Vec::new());
+        // Pages may be split across writes
+        for page in [pages[0], pages[1], pages[1]] {
+            let (first, second) = page.split_at(page.len() / 2);
+            fixer.write_all(first).unwrap();
+            fixer.write_all(second).unwrap();
+        }
+        let output = fixer.finish().unwrap();
+        assert_eq!(page_fields(&output), [(0, true, false), (1, false, false), (2, false, true)]);
+    }
+
+    #[test]
+    fn page_fixer_rejects_partial_pages() {
+        let data = opus_file(&[StreamSpec::default()]);
+        let mut fixer = PageFixer::new(Vec::new());
+        fixer.write_all(&data[..data.len() - 1]).unwrap();
+        assert!(matches!(fixer.finish(), Err(Error::WriteError(_))));
+        assert!(PageFixer::new(Vec::new()).write_all(b"This is plain text rather than an Ogg page").is_err());
+    }
+}
@@ -34,6 +34,14 @@ impl header::CommentHeaderSpecifics for Specifics {
 /// Manipulates an Ogg Opus comment header
 pub type CommentHeader = CommentHeaderGeneric<Specifics>;
 
+impl CommentHeader {
+    /// The experimental data following the comments, which is preserved when
+    /// the header is rewritten. This is empty if the header had none, or only
+    /// padding which was discarded.
+    #[must_use]
+    pub fn suffix_data(&self) -> &[u8] { &self.specifics().suffix_data }
+}
+
 #[cfg(test)]
 mod tests {
     use rand::distributions::{Distribution, Uniform};
@@ -58,6 +66,7 @@ mod tests {
             comment_header_as_vec(&header)?
         };
         assert_eq!(original_data, processed_data);
+        assert!(CommentHeader::try_parse(&padded_data)?.suffix_data().is_empty());
         Ok(())
     }
 
@@ -80,6 +89,8 @@ mod tests {
             comment_header_as_vec(&header)?
         };
         assert_eq!(padded_data, processed_data);
+        let header = CommentHeader::try_parse(&padded_data)?;
+        assert_eq!(header.suffix_data(), &padded_data[original_data.len()..]);
         Ok(())
     }
 }
@@ -1,6 +1,7 @@
 use std::io::{Cursor, Write};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use derivative::Derivative;
 
 use crate::header::{self, FixedPointGain};
 use crate::{Codec, Error};
@@ -12,9 +13,13 @@ const OPUS_MAGIC: &[u8] = b"OpusHead";
 const OPUS_DECODE_SAMPLE_RATE: usize = 48000;
 
 /// Allows querying and modification of an Opus identification header
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Derivative)]
+#[derivative(Clone, Debug, PartialEq)]
 pub struct IdHeader {
     data: Vec<u8>,
+
+    #[derivative(PartialEq = "ignore")]
+    dirty: bool,
 }
 
 impl header::IdHeader for IdHeader {
@@ -26,7 +31,7 @@ impl header::IdHeader for IdHeader {
         if !identical {
             return Ok(None);
         }
-        let result = IdHeader { data: data.to_vec() };
+        let result = IdHeader { data: data.to_vec(), dirty: false };
         if result.version() != 1 {
             return Err(Error::UnsupportedCodecVersion(Codec::Opus, u64::from(result.version())));
         }
@@ -66,6 +71,8 @@ impl header::IdHeader for IdHeader {
         let value = reader.read_u16::<LittleEndian>().expect("Error reading pre-skip sample count");
         value.into()
     }
+
+    fn is_dirty(&self) -> bool { self.dirty }
 }
 
 impl IdHeader {
@@ -83,6 +90,20 @@ impl IdHeader {
     pub fn set_output_gain(&mut self, gain: FixedPointGain) {
         let mut writer = Cursor::new(&mut self.data[16..18]);
         writer.write_i16::<LittleEndian>(gain.as_fixed_point()).expect("Error writing gain");
+        self.dirty = true;
+    }
+
+    /// Sets the sample rate of the original input, which is purely
+    /// informational. `None` or zero marks the rate as unknown. Fails with
+    /// `Error::InvalidSampleRate` if the rate does not fit in the header.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn set_input_sample_rate(&mut self, rate: Option<usize>) -> Result<(), Error> {
+        let rate = rate.unwrap_or(0);
+        let value = u32::try_from(rate).map_err(|_| Error::InvalidSampleRate(rate))?;
+        let mut writer = Cursor::new(&mut self.data[12..16]);
+        writer.write_u32::<LittleEndian>(value).expect("Error writing sample rate");
+        self.dirty = true;
+        Ok(())
     }
 
     /// Applies a delta to the header's output gain. This may return an error if
@@ -101,4 +122,56 @@ impl IdHeader {
         let mut reader = Cursor::new(&self.data[8..9]);
         reader.read_u8().expect("Error reading output channel count")
     }
+
+    /// Gets the channel mapping family
+    #[must_use]
+    pub fn channel_mapping_family(&self) -> u8 { self.data[18] }
+
+    /// Gets the channel mapping table, which is empty for mapping family 0.
+    /// For other families this is whatever follows the stream and coupled
+    /// stream counts, so its length may not match the number of output
+    /// channels in a malformed header.
+    #[must_use]
+    pub fn channel_mapping_table(&self) -> &[u8] {
+        if self.channel_mapping_family() == 0 {
+            &[]
+        } else {
+            self.data.get(OPUS_MIN_HEADER_SIZE + 2..).unwrap_or_default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::IdHeader as _;
+    use crate::testing::opus_id_header;
+
+    fn parse(data: &[u8]) -> IdHeader { IdHeader::try_parse(data).unwrap().unwrap() }
+
+    #[test]
+    fn input_sample_rate_round_trip() -> Result<(), Error> {
+        let mut header = parse(&opus_id_header(2, 1, 312, -256));
+        assert_eq!(header.input_sample_rate(), Some(1));
+        for rate in [Some(44100), Some(768_000), None, Some(u32::MAX as usize)] {
+            header.set_input_sample_rate(rate)?;
+            assert!(header.is_dirty());
+            let reparsed = parse(&header.clone().into_vec());
+            assert_eq!(reparsed.input_sample_rate(), rate);
+            assert_eq!(reparsed.preskip_samples(), 312);
+            assert_eq!(reparsed.get_output_gain(), header.get_output_gain());
+        }
+        header.set_input_sample_rate(Some(0))?;
+        assert_eq!(header.input_sample_rate(), None);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn oversized_input_sample_rate_is_rejected() {
+        let mut header = parse(&opus_id_header(2, 48000, 0, 0));
+        let rate = u32::MAX as usize + 1;
+        assert!(matches!(header.set_input_sample_rate(Some(rate)), Err(Error::InvalidSampleRate(r)) if r == rate));
+        assert_eq!(header.input_sample_rate(), Some(48000));
+    }
 }
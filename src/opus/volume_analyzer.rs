@@ -1,54 +1,67 @@
-use bs1770::{ChannelLoudnessMeter, Power, Windows100ms};
-use derivative::Derivative;
-use ogg::Packet;
+use std::io::{Read, Seek, SeekFrom};
+use std::time::Duration;
+
+use ogg::{Packet, PacketReader};
 use opus::{Channels, Decoder};
 
+use crate::analysis::AudioParameters;
 use crate::header::{CommentHeader as _, IdHeader as _};
+use crate::interrupt::{Interrupt, Never};
+use crate::loudness::{combine_measurements, Meter, TrackMeasurement};
+use crate::ogg_seek::{find_page_before_granule, last_granule};
 use crate::opus::{CommentHeader as OpusCommentHeader, IdHeader as OpusIdHeader};
 use crate::{Codec, Decibels, Error};
 
 // Specified in RFC6716
 const OPUS_MAX_PACKET_DURATION_MS: usize = 120;
 
+// The number of samples RFC7845 recommends decoding before the audio following
+// a seek is considered accurate
+const OPUS_SEEK_PREROLL_SAMPLES: u64 = 3840;
+
 #[derive(Clone, Copy, Debug)]
 enum State {
     AwaitingHeader,
     AwaitingComments { serial: u32 },
+    // The first packet after the comment header is checked for a duplicate of it
+    AwaitingFirstPacket { serial: u32 },
     Analyzing { serial: u32 },
     Done,
 }
 
-#[derive(Derivative)]
-#[derivative(Debug)]
+#[derive(Debug)]
 struct DecodeState {
-    sample_rate: usize,
     decoder: Decoder,
-    #[derivative(Debug = "ignore")]
-    meters: Vec<ChannelLoudnessMeter>,
+    meter: Meter,
     sample_buffer: Vec<f32>,
     preskip_remaining: usize,
+    // The granule position of the end of the audio decoded so far
+    position: u64,
+    // The pre-skip of the current stream
+    preskip: u64,
+    // The granule positions of the start of the current stream and of its
+    // last page read, from which its duration is found
+    stream_start: Option<u64>,
+    stream_end: Option<u64>,
+    // The number of samples per channel implied by the granule positions of
+    // earlier streams of a chained file, excluding pre-skip
+    previous_streams_frames: u64,
+    // Whether decoding resumed part way through the file, so its duration
+    // cannot be compared with its granule positions
+    partial: bool,
 }
 
 impl DecodeState {
-    pub fn new(channel_count: usize, sample_rate: usize, preskip: usize) -> Result<DecodeState, Error> {
-        let sample_rate_u32: u32 = sample_rate.try_into().expect("Unable to truncate sample rate");
-        let decoder = Self::build_decoder(channel_count, sample_rate_u32)?;
-        let mut meters = Vec::with_capacity(channel_count);
-        for _ in 0..channel_count {
-            meters.push(ChannelLoudnessMeter::new(sample_rate_u32));
-        }
+    /// The number of samples needed to hold the longest possible packet
+    fn sample_buffer_len(parameters: AudioParameters) -> usize {
+        let AudioParameters { channel_count, sample_rate } = parameters;
         let ms_per_second: usize = 1000;
-        let state = DecodeState {
-            sample_rate,
-            decoder,
-            meters,
-            sample_buffer: vec![0.0f32; channel_count * sample_rate * OPUS_MAX_PACKET_DURATION_MS / ms_per_second],
-            preskip_remaining: preskip,
-        };
-        Ok(state)
+        channel_count * sample_rate * OPUS_MAX_PACKET_DURATION_MS / ms_per_second
     }
 
-    fn build_decoder(channel_count: usize, sample_rate: u32) -> Result<Decoder, Error> {
+    fn build_decoder(parameters: AudioParameters) -> Result<Decoder, Error> {
+        let AudioParameters { channel_count, sample_rate } = parameters;
+        let sample_rate: u32 = sample_rate.try_into().expect("Unable to truncate sample rate");
         let channel_count_typed = match channel_count {
             1 => Channels::Mono,
             2 => Channels::Stereo,
@@ -57,116 +70,223 @@ impl DecodeState {
         Decoder::new(sample_rate, channel_count_typed).map_err(Error::OpusError)
     }
 
-    pub fn reset_decoder(&mut self, channel_count: usize, sample_rate: usize, preskip: usize) -> Result<(), Error> {
-        if sample_rate != self.sample_rate || channel_count != self.num_channels() {
-            return Err(Error::UnexpectedAudioParametersChange);
+    pub fn new(parameters: AudioParameters, preskip: usize) -> Result<DecodeState, Error> {
+        let meter = Meter::new(parameters)?;
+        let decoder = Self::build_decoder(parameters)?;
+        let state = DecodeState {
+            decoder,
+            meter,
+            sample_buffer: vec![0.0f32; Self::sample_buffer_len(parameters)],
+            preskip_remaining: preskip,
+            position: 0,
+            preskip: u64::try_from(preskip).expect("Pre-skip out of range"),
+            stream_start: None,
+            stream_end: None,
+            previous_streams_frames: 0,
+            partial: false,
+        };
+        Ok(state)
+    }
+
+    /// Prepares to decode a chained stream. The loudness meter is retained so
+    /// the parameters of the new stream must match those of the previous one.
+    pub fn reset_decoder(&mut self, parameters: AudioParameters, preskip: usize) -> Result<(), Error> {
+        self.meter.parameters().check_unchanged(&parameters)?;
+        self.decoder.reset_state().map_err(Error::OpusError)?;
+        self.previous_streams_frames += self.stream_frames();
+        self.begin_stream(preskip);
+        Ok(())
+    }
+
+    /// Prepares to decode a new file, which may have different parameters.
+    /// The meter is replaced but the decoder and sample buffer are reused
+    /// where possible.
+    pub fn reset_for_file(&mut self, parameters: AudioParameters, preskip: usize) -> Result<(), Error> {
+        if self.meter.parameters() == parameters {
+            self.decoder.reset_state().map_err(Error::OpusError)?;
+        } else {
+            self.decoder = Self::build_decoder(parameters)?;
+            self.sample_buffer.resize(Self::sample_buffer_len(parameters), 0.0);
         }
-        let sample_rate_u32: u32 = sample_rate.try_into().expect("Unable to truncate sample rate");
-        let decoder = Self::build_decoder(channel_count, sample_rate_u32)?;
-        self.decoder = decoder;
-        self.preskip_remaining = preskip;
+        self.meter = Meter::new(parameters)?;
+        self.previous_streams_frames = 0;
+        self.partial = false;
+        self.begin_stream(preskip);
         Ok(())
     }
 
-    pub fn num_channels(&self) -> usize { self.meters.len() }
+    fn begin_stream(&mut self, preskip: usize) {
+        self.preskip_remaining = preskip;
+        self.preskip = u64::try_from(preskip).expect("Pre-skip out of range");
+        self.position = 0;
+        self.stream_start = None;
+        self.stream_end = None;
+    }
 
-    pub fn push_packet(&mut self, packet: &[u8]) -> Result<(), Error> {
+    /// Prepares to decode from the end of a page with the specified granule
+    /// position, discarding the next `count` decoded samples per channel rather
+    /// than measuring them
+    pub fn resume_at(&mut self, granule: u64, count: u64) {
+        self.partial = true;
+        self.position = granule;
+        self.preskip_remaining = usize::try_from(count).expect("Sample count out of range");
+    }
+
+    /// Decodes and measures `packet`. If it is the last packet of the stream,
+    /// `end_granule` is the granule position of its page and any samples
+    /// beyond it are padding which is discarded.
+    pub fn push_packet(&mut self, packet: &[u8], end_granule: Option<u64>) -> Result<(), Error> {
         // Decode to interleaved PCM
         let decode_fec = false;
-        let channel_count = self.num_channels();
+        let channel_count = self.meter.parameters().channel_count;
         let num_decoded_samples =
             self.decoder.decode_float(packet, &mut self.sample_buffer, decode_fec).map_err(Error::OpusError)?;
-        let decoded_samples = &self.sample_buffer[..(channel_count * num_decoded_samples)];
+        let num_decoded_samples = match end_granule {
+            Some(end_granule) => {
+                let remaining = end_granule.saturating_sub(self.position);
+                num_decoded_samples.min(usize::try_from(remaining).unwrap_or(usize::MAX))
+            }
+            None => num_decoded_samples,
+        };
+        self.position += u64::try_from(num_decoded_samples).expect("Sample count out of range");
         let to_skip = std::cmp::min(self.preskip_remaining, num_decoded_samples);
         self.preskip_remaining -= to_skip;
-        for (channel_idx, meter) in self.meters.iter_mut().enumerate() {
-            let samples = decoded_samples.iter().copied().skip(channel_idx).step_by(channel_count).skip(to_skip);
-            meter.push(samples);
-        }
+        let decoded_samples = &self.sample_buffer[(channel_count * to_skip)..(channel_count * num_decoded_samples)];
+        self.meter.push_interleaved(decoded_samples);
         Ok(())
     }
 
-    pub fn get_windows(&self) -> Windows100ms<Vec<Power>> {
-        let windows: Vec<_> = self.meters.iter().map(ChannelLoudnessMeter::as_100ms_windows).collect();
-        // See notes on `reduce_stero` in `bs1770` crate.
-        let power_scale_factor = match self.num_channels() {
-            1 => 2.0, // Since mono is still output to two devices
-            2 => 1.0,
-            n => panic!("Calculating power for number of channels {} not yet supported", n),
-        };
-        let num_windows = windows[0].len();
-        for channel_windows in &windows {
-            assert_eq!(num_windows, channel_windows.len(), "Channels had different amounts of audio");
+    /// Records the granule position of a page once all packets ending on it
+    /// have been decoded. The start of the stream is found from the first
+    /// page, since a stream need not start at granule position zero.
+    pub fn end_page(&mut self, granule: u64) {
+        // A granule position of -1 marks a page on which no packet ends
+        if granule != u64::MAX {
+            self.stream_start.get_or_insert(granule.saturating_sub(self.position));
+            self.stream_end = Some(granule);
         }
-        let mut result_windows = Vec::with_capacity(num_windows);
-        for i in 0..num_windows {
-            let mut power = 0.0;
-            for channel_windows in &windows {
-                let channel_windows = &channel_windows.inner;
-                // It would be nice if `Power` implemented addition since this is a
-                // semantically-valid operation
-                power += channel_windows[i].0;
-            }
-            power *= power_scale_factor;
-            result_windows.push(Power(power));
+    }
+
+    /// The number of samples per channel implied by the granule positions of
+    /// the current stream, excluding pre-skip
+    fn stream_frames(&self) -> u64 {
+        match (self.stream_start, self.stream_end) {
+            (Some(start), Some(end)) => end.saturating_sub(start).saturating_sub(self.preskip),
+            _ => 0,
         }
-        Windows100ms { inner: result_windows }
+    }
+
+    /// The duration implied by the granule positions of all streams decoded,
+    /// excluding pre-skip, or `None` if only part of the file was decoded
+    pub fn granule_duration(&self) -> Option<Duration> {
+        let frames = self.previous_streams_frames + self.stream_frames();
+        (!self.partial).then(|| self.meter.duration_of(frames))
     }
 }
 
 /// Determines the BS.1770 loudness in LUFS of one or more Ogg Opus files
-#[derive(Derivative)]
-#[derivative(Debug)]
+///
+/// Each analyzed file is measured as a separate track:
+///
+/// ```
+/// # #[cfg(feature = "testing")] {
+/// use std::io::Cursor;
+/// use std::time::Duration;
+///
+/// use zoog::opus::VolumeAnalyzer;
+/// use zoog::testing::{opus_file, Signal, StreamSpec};
+///
+/// let sine = |amplitude| StreamSpec { signal: Signal::Sine { frequency: 997.0, amplitude }, ..StreamSpec::default() };
+/// let quiet = opus_file(&[sine(0.25)]);
+/// let loud = opus_file(&[sine(0.5)]);
+/// let mut analyzer = VolumeAnalyzer::default();
+/// analyzer.analyze_stream(Cursor::new(quiet))?;
+/// analyzer.analyze_stream(Cursor::new(loud))?;
+/// let lufs = analyzer.track_lufs();
+/// assert_eq!(lufs.len(), 2);
+/// // Doubling the amplitude makes the track about 6 dB louder
+/// assert!(((lufs[1] - lufs[0]).as_f64() - 6.0).abs() < 0.5);
+/// assert_eq!(analyzer.last_track_duration(), Some(Duration::from_secs(2)));
+/// # }
+/// # Ok::<(), zoog::Error>(())
+/// ```
+#[derive(Debug)]
 pub struct VolumeAnalyzer {
     decode_state: Option<DecodeState>,
+    // Retained from the previous file so that its allocations can be reused
+    spare_decode_state: Option<DecodeState>,
     state: State,
-    #[derivative(Debug = "ignore")]
-    windows: Windows100ms<Vec<Power>>,
-    track_loudness: Vec<Decibels>,
+    tracks: Vec<TrackMeasurement>,
+    duplicate_headers: u64,
 }
 
 impl Default for VolumeAnalyzer {
     fn default() -> VolumeAnalyzer {
         VolumeAnalyzer {
             decode_state: None,
+            spare_decode_state: None,
             state: State::AwaitingHeader,
-            windows: Windows100ms::new(),
-            track_loudness: Vec::new(),
+            tracks: Vec::new(),
+            duplicate_headers: 0,
         }
     }
 }
 
 impl VolumeAnalyzer {
+    fn begin_stream(&mut self, packet: &Packet) -> Result<(), Error> {
+        let header = OpusIdHeader::try_parse(&packet.data)?.ok_or(Error::MissingStream(Codec::Opus))?;
+        let parameters = AudioParameters::from_id_header(&header);
+        let preskip = header.preskip_samples();
+        if let Some(ref mut decode_state) = self.decode_state {
+            decode_state.reset_decoder(parameters, preskip)?;
+        } else {
+            self.duplicate_headers = 0;
+            if let Some(mut decode_state) = self.spare_decode_state.take() {
+                decode_state.reset_for_file(parameters, preskip)?;
+                self.decode_state = Some(decode_state);
+            } else {
+                self.decode_state = Some(DecodeState::new(parameters, preskip)?);
+            }
+        }
+        self.state = State::AwaitingComments { serial: packet.stream_serial() };
+        Ok(())
+    }
+
     /// Submits a new Ogg packet to the analyzer
     #[allow(clippy::needless_pass_by_value, clippy::missing_panics_doc)]
     pub fn submit(&mut self, packet: Packet) -> Result<(), Error> {
         let packet_serial = packet.stream_serial();
         match self.state {
-            State::AwaitingHeader => {
-                let header = OpusIdHeader::try_parse(&packet.data)?.ok_or(Error::MissingStream(Codec::Opus))?;
-                let channel_count = header.num_output_channels();
-                let sample_rate = header.output_sample_rate();
-                let preskip = header.preskip_samples();
-                if let Some(ref mut decode_state) = self.decode_state {
-                    decode_state.reset_decoder(channel_count, sample_rate, preskip)?;
-                } else {
-                    self.decode_state = Some(DecodeState::new(channel_count, sample_rate, preskip)?);
-                }
-                self.state = State::AwaitingComments { serial: packet_serial };
-            }
+            State::AwaitingHeader => self.begin_stream(&packet)?,
             State::AwaitingComments { serial } => {
                 if serial == packet_serial {
                     // Check comment header is valid
                     OpusCommentHeader::try_parse(&packet.data)?;
-                    self.state = if packet.last_in_stream() { State::Done } else { State::Analyzing { serial } };
+                    self.state =
+                        if packet.last_in_stream() { State::Done } else { State::AwaitingFirstPacket { serial } };
                 } else {
                     return Err(Error::UnexpectedLogicalStream(packet_serial));
                 }
             }
-            State::Analyzing { serial } => {
+            State::AwaitingFirstPacket { serial } if serial == packet_serial => {
+                // A duplicate comment header written by a buggy tool is not audio
+                if OpusCommentHeader::try_parse(&packet.data).is_ok() {
+                    self.duplicate_headers += 1;
+                    self.state = if packet.last_in_stream() { State::Done } else { State::Analyzing { serial } };
+                } else {
+                    self.state = State::Analyzing { serial };
+                    return self.submit(packet);
+                }
+            }
+            State::AwaitingFirstPacket { serial } | State::Analyzing { serial } => {
                 if serial == packet_serial {
                     let decode_state = self.decode_state.as_mut().expect("Decode state unexpectedly missing");
-                    decode_state.push_packet(&packet.data)?;
+                    // Opus granule positions count samples at the decoding rate
+                    let end_granule = packet.last_in_stream().then(|| packet.absgp_page());
+                    decode_state.push_packet(&packet.data, end_granule)?;
+                    if packet.last_in_page() {
+                        decode_state.end_page(packet.absgp_page());
+                    }
                     if packet.last_in_stream() {
                         self.state = State::Done;
                     }
@@ -175,66 +295,434 @@ impl VolumeAnalyzer {
                 }
             }
             State::Done => {
-                // How does volume normalization for chained streams work, especially when
-                // they may have different values for the output gain header? For now we error
-                // if we see an additional stream.
-                return Err(Error::UnexpectedLogicalStream(packet_serial));
+                // A chained stream is measured as a continuation of the previous one. This
+                // is only meaningful if the channel count and sample rate are unchanged,
+                // which `begin_stream` checks. Anything else following the end of a stream
+                // is an error.
+                if packet.first_in_stream() {
+                    self.begin_stream(&packet)?;
+                } else {
+                    return Err(Error::UnexpectedLogicalStream(packet_serial));
+                }
             }
         }
         Ok(())
     }
 
-    fn gated_mean_to_lufs(windows: Windows100ms<&[Power]>) -> Decibels {
-        let power = bs1770::gated_mean(windows.as_ref());
-        let lufs = if power.0.is_nan() {
-            // Near silence can result in a NaN result (https://github.com/ruuda/bs1770/issues/1).
-            // Returning a large negative value might result in the application of a massive
-            // gain and is therefore not a good idea. Instead we return zero,
-            // which indicates the audio is at peak volume.
-            0.0
-        } else {
-            power.loudness_lkfs().into()
-        };
-        Decibels::from(lufs)
-    }
-
     /// This should be called after all packets from an Ogg Opus file have been
     /// submitted. It is then possible to start calculating the volume of a
     /// new file.
-    #[allow(clippy::missing_panics_doc)]
-    pub fn file_complete(&mut self) {
+    ///
+    /// Returns the measurement of the file, which is also recorded by the
+    /// analyzer. If no audio was decoded from the file, such as when it
+    /// contains only headers, `None` is returned and nothing is recorded.
+    pub fn file_complete(&mut self) -> Option<TrackMeasurement> {
+        let decode_state = self.decode_state.take()?;
+        self.state = State::AwaitingHeader;
+        let meter = &decode_state.meter;
+        let measurement = (meter.frames_measured() > 0).then(|| {
+            TrackMeasurement::new(meter.windows(), meter.duration())
+                .with_sample_peak(meter.sample_peak())
+                .with_granule_duration(decode_state.granule_duration())
+        });
+        self.spare_decode_state = Some(decode_state);
+        self.tracks.extend(measurement.clone());
+        measurement
+    }
+
+    /// The number of duplicate comment headers found immediately after the
+    /// comment header of a stream in the current or most recently completed
+    /// file. These are skipped rather than decoded as audio.
+    #[must_use]
+    pub fn duplicate_headers(&self) -> u64 { self.duplicate_headers }
+
+    /// Discards the results of all files submitted so far, together with any
+    /// partially submitted file, so that the analyzer is equivalent to a newly
+    /// constructed one. The decoder and buffers are retained, which avoids
+    /// reallocating them when many files are analyzed one at a time.
+    pub fn reset_for_new_file(&mut self) {
         if let Some(decode_state) = self.decode_state.take() {
-            let windows = decode_state.get_windows();
-            let track_power = Self::gated_mean_to_lufs(windows.as_ref());
-            self.track_loudness.push(track_power);
-            self.windows.inner.extend(windows.inner);
+            self.spare_decode_state = Some(decode_state);
         }
-        assert!(self.decode_state.is_none());
         self.state = State::AwaitingHeader;
+        self.tracks.clear();
+        self.duplicate_headers = 0;
+    }
+
+    /// Convenience function for analyzing an entire file.
+    ///
+    /// Submits all packets from `input` then calls `file_complete`, returning
+    /// its result. `Error::Interrupted` is returned if the interrupt condition
+    /// is set before the stream is exhausted.
+    pub fn analyze_stream_with_interrupt<R, I>(
+        &mut self, input: R, interrupt: &I,
+    ) -> Result<Option<TrackMeasurement>, Error>
+    where
+        R: Read + Seek,
+        I: Interrupt,
+    {
+        let mut ogg_reader = PacketReader::new(input);
+        loop {
+            if interrupt.is_set() {
+                return Err(Error::Interrupted);
+            }
+            match ogg_reader.read_packet() {
+                Err(e) => return Err(Error::OggDecode(e)),
+                Ok(None) => break,
+                Ok(Some(packet)) => self.submit(packet)?,
+            }
+        }
+        Ok(self.file_complete())
+    }
+
+    /// Identical to `analyze_stream_with_interrupt` except analysis cannot be
+    /// interrupted.
+    pub fn analyze_stream<R: Read + Seek>(&mut self, input: R) -> Result<Option<TrackMeasurement>, Error> {
+        self.analyze_stream_with_interrupt(input, &Never::default())
+    }
+
+    /// Analyzes only a window of the specified length from the middle of
+    /// `input`, giving an approximation of the loudness of a long file without
+    /// decoding all of it.
+    ///
+    /// The start of the window is found by seeking using granule positions
+    /// and is accurate to within the duration of a packet. Only the first
+    /// logical stream of a chained file is sampled. If the stream is no longer
+    /// than `length`, all of it is analyzed. As with
+    /// `analyze_stream_with_interrupt`, `file_complete` is called and the
+    /// measurement of the window is returned.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn analyze_centered_window_with_interrupt<R, I>(
+        &mut self, input: R, length: Duration, interrupt: &I,
+    ) -> Result<Option<TrackMeasurement>, Error>
+    where
+        R: Read + Seek,
+        I: Interrupt,
+    {
+        let mut ogg_reader = PacketReader::new(input);
+        let read_packet = |ogg_reader: &mut PacketReader<R>| ogg_reader.read_packet().map_err(Error::OggDecode);
+        let id_packet = read_packet(&mut ogg_reader)?.ok_or(Error::MissingStream(Codec::Opus))?;
+        let serial = id_packet.stream_serial();
+        let preskip = OpusIdHeader::try_parse(&id_packet.data)?
+            .map_or(0, |header| u64::try_from(header.preskip_samples()).expect("Pre-skip out of range"));
+        self.submit(id_packet)?;
+        let comment_packet = read_packet(&mut ogg_reader)?.ok_or(Error::MissingHeaders)?;
+        self.submit(comment_packet)?;
+
+        if matches!(self.state, State::AwaitingFirstPacket { .. }) {
+            // The comment header must end its page so the audio starts on the next one
+            let audio_start = ogg_reader.get_mut().stream_position().map_err(Error::ReadError)?;
+            let end_granule = last_granule(ogg_reader.get_mut(), serial, audio_start)?.unwrap_or(0);
+            let total_samples = end_granule.saturating_sub(preskip);
+            let decode_state = self.decode_state.as_mut().expect("Decode state unexpectedly missing");
+            let window_samples = decode_state.meter.frames_in(length);
+            // Searching for the end of the stream moved the underlying reader so we
+            // always seek, either to the start of the audio or shortly before the window
+            let (offset, granule, to_skip) = if window_samples < total_samples {
+                let window_start = preskip + (total_samples - window_samples) / 2;
+                let seek_target = window_start.saturating_sub(OPUS_SEEK_PREROLL_SAMPLES);
+                let page = find_page_before_granule(ogg_reader.get_mut(), serial, seek_target, audio_start)?;
+                let (offset, granule) = page.map_or((audio_start, 0), |page| (page.end(), page.granule));
+                (offset, granule, window_start - granule)
+            } else {
+                (audio_start, 0, preskip)
+            };
+            ogg_reader.seek_bytes(SeekFrom::Start(offset)).map_err(Error::ReadError)?;
+            decode_state.resume_at(granule, to_skip);
+            loop {
+                if interrupt.is_set() {
+                    return Err(Error::Interrupted);
+                }
+                let decode_state = self.decode_state.as_ref().expect("Decode state unexpectedly missing");
+                let measured = decode_state.meter.frames_measured();
+                let analyzing = matches!(self.state, State::AwaitingFirstPacket { .. } | State::Analyzing { .. });
+                if measured >= window_samples || !analyzing {
+                    break;
+                }
+                match read_packet(&mut ogg_reader)? {
+                    Some(packet) if packet.stream_serial() == serial => self.submit(packet)?,
+                    _ => break,
+                }
+            }
+        }
+        Ok(self.file_complete())
+    }
+
+    /// Identical to `analyze_centered_window_with_interrupt` except analysis
+    /// cannot be interrupted.
+    pub fn analyze_centered_window<R: Read + Seek>(
+        &mut self, input: R, length: Duration,
+    ) -> Result<Option<TrackMeasurement>, Error> {
+        self.analyze_centered_window_with_interrupt(input, length, &Never::default())
     }
 
     /// Returns the mean LUFS of all completed files submitted to the volume
     /// analyzer so far
     #[must_use]
-    pub fn mean_lufs(&self) -> Decibels { Self::gated_mean_to_lufs(self.windows.as_ref()) }
+    pub fn mean_lufs(&self) -> Decibels { combine_measurements(&self.tracks) }
+
+    /// Returns the measurements of all tracks submitted to the volume analyzer
+    /// so far
+    #[must_use]
+    pub fn track_measurements(&self) -> &[TrackMeasurement] { &self.tracks }
+
+    /// Removes and returns the measurements of all tracks submitted to the
+    /// volume analyzer so far, so they can be combined with those from other
+    /// analyzers using `loudness::combine_measurements`
+    pub fn take_track_measurements(&mut self) -> Vec<TrackMeasurement> { std::mem::take(&mut self.tracks) }
 
     /// Returns the LUFS of all tracks submitted ot the volume analyzer so far
     #[must_use]
-    pub fn track_lufs(&self) -> Vec<Decibels> { self.track_loudness.clone() }
+    pub fn track_lufs(&self) -> Vec<Decibels> { self.tracks.iter().map(|track| track.lufs).collect() }
 
     /// Returns the volume of the most recent track submitted to the volume
-    /// analyzer
+    /// analyzer, or `None` if no track with audio has been submitted. The
+    /// measurement returned by `file_complete` is usually more convenient.
     #[must_use]
-    pub fn last_track_lufs(&self) -> Option<Decibels> { self.track_loudness.last().copied() }
+    pub fn last_track_lufs(&self) -> Option<Decibels> { self.tracks.last().map(|track| track.lufs) }
+
+    /// Returns the decoded duration of the most recent track submitted to the
+    /// volume analyzer, excluding pre-skip
+    #[must_use]
+    pub fn last_track_duration(&self) -> Option<Duration> { self.tracks.last().map(|track| track.duration) }
 
     /// Returns the mean LUFS of all completed files submitted to the supplied
     /// volume analyzers
     pub fn mean_lufs_across_multiple<'a, I: IntoIterator<Item = &'a VolumeAnalyzer>>(analyzers: I) -> Decibels {
-        let mut windows: Vec<Power> = Vec::new();
-        for analyzer in analyzers {
-            windows.extend(analyzer.windows.inner.iter());
+        combine_measurements(analyzers.into_iter().flat_map(|analyzer| &analyzer.tracks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+
+    use super::*;
+    use crate::analysis::{AnalysisIssue, DEFAULT_MAX_DURATION_MISMATCH};
+    use crate::testing::{opus_file, read_packets, Anomaly, Signal, StreamSpec, FRAME_SIZE, SAMPLE_RATE};
+
+    fn one_second() -> StreamSpec { StreamSpec { num_samples: SAMPLE_RATE, ..StreamSpec::default() } }
+
+    fn sine(amplitude: f32) -> Signal { Signal::Sine { frequency: 997.0, amplitude } }
+
+    fn analyze(data: &[u8]) -> Result<VolumeAnalyzer, Error> {
+        let mut analyzer = VolumeAnalyzer::default();
+        analyzer.analyze_stream(std::io::Cursor::new(data))?;
+        Ok(analyzer)
+    }
+
+    #[test]
+    fn single_stream_is_analyzed() -> Result<(), Error> {
+        let data = opus_file(&[one_second()]);
+        let analyzer = analyze(&data)?;
+        assert_eq!(analyzer.track_lufs().len(), 1);
+        assert!(analyzer.last_track_lufs().unwrap().as_f64() < 0.0);
+        assert_eq!(analyzer.last_track_duration(), Some(Duration::from_secs(1)));
+        // The sine is encoded with an amplitude of 0.25
+        let peak = analyzer.track_measurements()[0].sample_peak;
+        assert!((0.2..0.3).contains(&peak), "Sample peak was {}", peak);
+        Ok(())
+    }
+
+    #[test]
+    fn chained_streams_with_same_parameters() -> Result<(), Error> {
+        let first = one_second();
+        let second = StreamSpec { serial: 2, ..first.clone() };
+        let data = opus_file(&[first, second]);
+        let analyzer = analyze(&data)?;
+        assert_eq!(analyzer.track_lufs().len(), 1);
+        assert_eq!(analyzer.last_track_duration(), Some(Duration::from_secs(2)));
+        assert_eq!(analyzer.track_measurements()[0].granule_duration, Some(Duration::from_secs(2)));
+        Ok(())
+    }
+
+    #[test]
+    fn chained_stream_with_changed_parameters() {
+        let first = StreamSpec::default();
+        let second = StreamSpec { serial: 2, channels: 1, ..first.clone() };
+        let data = opus_file(&[first, second]);
+        assert!(matches!(analyze(&data), Err(Error::UnexpectedAudioParametersChange)));
+    }
+
+    #[test]
+    fn interleaved_stream_is_rejected() {
+        let data = opus_file(&[StreamSpec::default()]);
+        let mut packets = read_packets(&data);
+        let foreign = read_packets(&opus_file(&[StreamSpec { serial: 7, ..StreamSpec::default() }]));
+        packets.insert(3, foreign.into_iter().nth(3).unwrap());
+        let mut analyzer = VolumeAnalyzer::default();
+        let result = packets.into_iter().try_for_each(|p| analyzer.submit(p));
+        assert!(matches!(result, Err(Error::UnexpectedLogicalStream(7))));
+    }
+
+    #[test]
+    fn reset_analyzer_matches_new_analyzer() -> Result<(), Error> {
+        let stereo = opus_file(&[StreamSpec::default()]);
+        let mono = opus_file(&[
+            StreamSpec { channels: 1, ..StreamSpec::default() }.with_anomaly(Anomaly::InputSampleRate(16000))
+        ]);
+        let mut analyzer = VolumeAnalyzer::default();
+
+        // A partially submitted file is discarded
+        for packet in read_packets(&stereo).into_iter().take(5) {
+            analyzer.submit(packet)?;
         }
-        let windows = Windows100ms { inner: windows };
-        Self::gated_mean_to_lufs(windows.as_ref())
+        for data in [&stereo, &mono, &stereo] {
+            analyzer.reset_for_new_file();
+            analyzer.analyze_stream(std::io::Cursor::new(data))?;
+            let fresh = analyze(data)?;
+            assert_eq!(analyzer.track_lufs().len(), 1);
+            assert!((analyzer.mean_lufs().as_f64() - fresh.mean_lufs().as_f64()).abs() < f64::EPSILON);
+            assert_eq!(analyzer.last_track_duration(), fresh.last_track_duration());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn taken_measurements_combine_as_analyzers() -> Result<(), Error> {
+        let loud = analyze(&opus_file(&[StreamSpec::default()]))?;
+        let quiet = analyze(&opus_file(&[StreamSpec { signal: sine(0.05), ..StreamSpec::default() }]))?;
+        let mean = VolumeAnalyzer::mean_lufs_across_multiple([&loud, &quiet]);
+        let mut measurements = Vec::new();
+        for mut analyzer in [loud, quiet] {
+            let lufs = analyzer.last_track_lufs();
+            measurements.extend(analyzer.take_track_measurements());
+            assert_eq!(measurements.last().map(|track| track.lufs), lufs);
+            assert!(analyzer.track_measurements().is_empty());
+        }
+        assert!((combine_measurements(&measurements).as_f64() - mean.as_f64()).abs() < f64::EPSILON);
+        Ok(())
+    }
+
+    /// Rewrites an Ogg file so that its last packet is `last_packet` and its
+    /// final granule position is `end_granule`
+    fn with_last_packet(data: &[u8], mut last_packet: Vec<u8>, end_granule: u64) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut writer = PacketWriter::new(&mut output);
+        for packet in read_packets(data) {
+            let serial = packet.stream_serial();
+            if packet.last_in_stream() {
+                let last_packet = std::mem::take(&mut last_packet);
+                writer.write_packet(last_packet, serial, PacketWriteEndInfo::EndStream, end_granule).unwrap();
+            } else {
+                let info =
+                    if packet.last_in_page() { PacketWriteEndInfo::EndPage } else { PacketWriteEndInfo::NormalPacket };
+                let granule = packet.absgp_page();
+                writer.write_packet(packet.data, serial, info, granule).unwrap();
+            }
+        }
+        drop(writer);
+        output
+    }
+
+    #[test]
+    fn end_trimmed_samples_are_not_measured() -> Result<(), Error> {
+        // The audio of the final frame would complete another 100ms window if it
+        // were measured
+        let spec = StreamSpec { num_samples: 25 * FRAME_SIZE, signal: sine(0.05), ..StreamSpec::default() };
+        let quiet = opus_file(&[spec.clone()]);
+        let loud = opus_file(&[StreamSpec { signal: sine(0.9), ..spec }]);
+        let loud_last = read_packets(&loud).pop().unwrap().data;
+        let quiet_last = read_packets(&quiet).pop().unwrap().data;
+        let preskip = OpusIdHeader::try_parse(&read_packets(&quiet)[0].data)?.unwrap().preskip_samples();
+        let preskip = u64::try_from(preskip).unwrap();
+        let end_granule = 25 * 960 + 1;
+
+        // All but one sample of the final packet is padding, so its content
+        // should make no difference
+        let reference = analyze(&with_last_packet(&quiet, quiet_last, end_granule))?;
+        let padded = analyze(&with_last_packet(&quiet, loud_last, end_granule))?;
+        let expected_duration = Duration::from_nanos((end_granule - preskip) * 1_000_000_000 / 48000);
+        for analyzer in [&reference, &padded] {
+            assert_eq!(analyzer.last_track_duration(), Some(expected_duration));
+        }
+        let (reference, padded) = (reference.mean_lufs().as_f64(), padded.mean_lufs().as_f64());
+        assert!((reference - padded).abs() < f64::EPSILON, "Measured {} rather than {} LUFS", padded, reference);
+        Ok(())
+    }
+
+    /// Rewrites a single stream so that each audio packet is on its own page,
+    /// leaving out the packets with indices in `missing` as if their pages had
+    /// been lost
+    fn with_missing_pages(data: &[u8], missing: std::ops::Range<usize>) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut writer = PacketWriter::new(&mut output);
+        let mut granule = 0;
+        for (idx, packet) in read_packets(data).into_iter().enumerate() {
+            // The first two packets are the headers
+            if idx >= 2 {
+                granule += 960;
+            }
+            if !missing.contains(&idx) {
+                let info =
+                    if packet.last_in_stream() { PacketWriteEndInfo::EndStream } else { PacketWriteEndInfo::EndPage };
+                writer.write_packet(packet.data, packet.stream_serial(), info, granule).unwrap();
+            }
+        }
+        drop(writer);
+        output
+    }
+
+    #[test]
+    fn missing_pages_are_found_from_granule_positions() -> Result<(), Error> {
+        let data = opus_file(&[one_second()]);
+        let intact = analyze(&with_missing_pages(&data, 0..0))?;
+        let measurement = &intact.track_measurements()[0];
+        let granule = measurement.duration;
+        assert_eq!(measurement.granule_duration, Some(granule));
+        assert_eq!(measurement.duration_issue(Duration::ZERO), None);
+
+        // Ten 20ms packets are lost from the middle of the stream
+        let damaged = analyze(&with_missing_pages(&data, 20..30))?;
+        let measurement = &damaged.track_measurements()[0];
+        let decoded = granule - Duration::from_millis(200);
+        assert_eq!((measurement.granule_duration, measurement.duration), (Some(granule), decoded));
+        let issue = measurement.duration_issue(Duration::from_millis(100));
+        assert_eq!(issue, Some(AnalysisIssue::DurationMismatch { granule, decoded }));
+        assert_eq!(measurement.duration_issue(DEFAULT_MAX_DURATION_MISMATCH), None);
+        Ok(())
+    }
+
+    #[test]
+    fn centered_window_is_analyzed() -> Result<(), Error> {
+        let data = opus_file(&[StreamSpec { num_samples: 3000 * FRAME_SIZE, ..StreamSpec::default() }]);
+        let full = analyze(&data)?.last_track_lufs().unwrap();
+        let mut analyzer = VolumeAnalyzer::default();
+        let window = Duration::from_secs(10);
+        let approximate = analyzer.analyze_centered_window(std::io::Cursor::new(&data), window)?.unwrap().lufs;
+        assert!((approximate.as_f64() - full.as_f64()).abs() < 0.1);
+        // Decoding stops at the first packet to complete the window
+        let duration = analyzer.last_track_duration().unwrap();
+        assert!(duration >= window && duration < window + Duration::from_millis(20), "Measured {:?}", duration);
+        assert_eq!(analyzer.track_measurements()[0].granule_duration, None);
+        Ok(())
+    }
+
+    #[test]
+    fn short_stream_is_analyzed_completely() -> Result<(), Error> {
+        let data = opus_file(&[one_second(), StreamSpec { serial: 2, ..one_second() }]);
+        let mut analyzer = VolumeAnalyzer::default();
+        analyzer.analyze_centered_window(std::io::Cursor::new(&data), Duration::from_secs(10))?;
+        // Only the first of the chained streams is sampled
+        assert_eq!(analyzer.last_track_duration(), Some(Duration::from_secs(1)));
+        Ok(())
+    }
+
+    #[test]
+    fn stream_without_audio_is_not_recorded() -> Result<(), Error> {
+        let empty = opus_file(&[StreamSpec { num_samples: 0, ..StreamSpec::default() }]);
+        let mut analyzer = VolumeAnalyzer::default();
+        assert!(analyzer.analyze_stream(std::io::Cursor::new(&empty))?.is_none());
+        assert!(analyzer.analyze_centered_window(std::io::Cursor::new(&empty), Duration::from_secs(10))?.is_none());
+        assert!(analyzer.track_measurements().is_empty());
+        assert_eq!(analyzer.last_track_lufs(), None);
+
+        let data = opus_file(&[StreamSpec::default()]);
+        let measurement = analyzer.analyze_stream(std::io::Cursor::new(&data))?.unwrap();
+        assert_eq!(analyzer.last_track_lufs(), Some(measurement.lufs));
+        assert_eq!(analyzer.last_track_duration(), Some(measurement.duration));
+        assert!(analyzer.file_complete().is_none());
+        assert_eq!(analyzer.track_measurements().len(), 1);
+        Ok(())
     }
 }
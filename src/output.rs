@@ -0,0 +1,252 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use parking_lot::Mutex;
+
+use crate::Error;
+
+/// A file that records are appended to, such as a rolling log. Each record is
+/// written with a single write while an advisory lock is held on the file, so
+/// records from concurrent writers, whether threads or other processes using
+/// `AppendFile`, are never interleaved. The lock is only held for the duration
+/// of each write.
+#[derive(Debug)]
+pub struct AppendFile {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl AppendFile {
+    /// Opens the file at `path` for appending, creating it if it does not
+    /// exist
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<AppendFile, Error> {
+        let path = path.into();
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)
+            .map_err(|e| Error::FileOpenError(path.clone(), e))?;
+        Ok(AppendFile { path, file: Mutex::new(file) })
+    }
+
+    /// The path of the file
+    #[must_use]
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// Appends `record`, which is terminated with a newline if it is not
+    /// already. If the file ends with a partial line, for example because an
+    /// earlier writer was killed, a newline is written first so that the
+    /// record starts on a line of its own.
+    pub fn append(&self, record: &str) -> Result<(), Error> {
+        let file = self.file.lock();
+        let _lock = FileLock::exclusive(&file).map_err(|e| Error::FileWriteError(self.path.clone(), e))?;
+        let mut data = Vec::with_capacity(record.len() + 2);
+        if ends_with_partial_line(&file).map_err(|e| Error::FileReadError(self.path.clone(), e))? {
+            data.push(b'\n');
+        }
+        data.extend_from_slice(record.as_bytes());
+        if !record.ends_with('\n') {
+            data.push(b'\n');
+        }
+        (&*file)
+            .write_all(&data)
+            .and_then(|()| (&*file).flush())
+            .map_err(|e| Error::FileWriteError(self.path.clone(), e))
+    }
+}
+
+/// Whether the data of `file` is non-empty and does not end with a newline
+fn ends_with_partial_line(mut file: &File) -> io::Result<bool> {
+    if file.metadata()?.len() == 0 {
+        return Ok(false);
+    }
+    file.seek(SeekFrom::End(-1))?;
+    let mut last = [0u8];
+    file.read_exact(&mut last)?;
+    Ok(last[0] != b'\n')
+}
+
+/// Reads the lines of a file written by `AppendFile`. A final line without a
+/// terminating newline is the remains of an interrupted write, so is skipped.
+pub fn read_complete_lines<R: Read>(reader: R) -> io::Result<Vec<String>> {
+    let mut reader = BufReader::new(reader);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    while reader.read_line(&mut line)? != 0 {
+        if let Some(complete) = line.strip_suffix('\n') {
+            lines.push(complete.strip_suffix('\r').unwrap_or(complete).to_string());
+        }
+        line.clear();
+    }
+    Ok(lines)
+}
+
+/// An advisory lock on a whole file, released when dropped
+struct FileLock<'a> {
+    file: &'a File,
+}
+
+impl<'a> FileLock<'a> {
+    /// Blocks until an exclusive lock on `file` is acquired
+    fn exclusive(file: &'a File) -> io::Result<FileLock<'a>> {
+        sys::lock(file)?;
+        Ok(FileLock { file })
+    }
+}
+
+impl Drop for FileLock<'_> {
+    fn drop(&mut self) {
+        // The lock is released when the file is closed anyway
+        let _ = sys::unlock(self.file);
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    fn flock(file: &File, operation: libc::c_int) -> io::Result<()> {
+        loop {
+            // SAFETY: The descriptor is valid for as long as `file` is borrowed
+            if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+                return Ok(());
+            }
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+    }
+
+    pub fn lock(file: &File) -> io::Result<()> { flock(file, libc::LOCK_EX) }
+
+    pub fn unlock(file: &File) -> io::Result<()> { flock(file, libc::LOCK_UN) }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::fs::File;
+    use std::io;
+    use std::os::windows::io::AsRawHandle;
+
+    use windows_sys::Win32::Storage::FileSystem::{LockFileEx, UnlockFileEx, LOCKFILE_EXCLUSIVE_LOCK};
+    use windows_sys::Win32::System::IO::OVERLAPPED;
+
+    pub fn lock(file: &File) -> io::Result<()> {
+        // SAFETY: The handle is valid for as long as `file` is borrowed and
+        // OVERLAPPED is valid when zeroed
+        let result = unsafe {
+            let mut overlapped: OVERLAPPED = std::mem::zeroed();
+            LockFileEx(file.as_raw_handle(), LOCKFILE_EXCLUSIVE_LOCK, 0, u32::MAX, u32::MAX, &mut overlapped)
+        };
+        if result == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn unlock(file: &File) -> io::Result<()> {
+        // SAFETY: As for `lock`
+        let result = unsafe {
+            let mut overlapped: OVERLAPPED = std::mem::zeroed();
+            UnlockFileEx(file.as_raw_handle(), 0, u32::MAX, u32::MAX, &mut overlapped)
+        };
+        if result == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod sys {
+    use std::fs::File;
+    use std::io;
+
+    // Other platforms rely on the mutex in `AppendFile`, which only excludes
+    // writers within the same process
+    #[allow(clippy::unnecessary_wraps)]
+    pub fn lock(_file: &File) -> io::Result<()> { Ok(()) }
+
+    #[allow(clippy::unnecessary_wraps)]
+    pub fn unlock(_file: &File) -> io::Result<()> { Ok(()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn records_are_appended() -> Result<(), Error> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.txt");
+        AppendFile::open(&path)?.append("first")?;
+        let file = AppendFile::open(&path)?;
+        file.append("second\n")?;
+        file.append("third\nfourth")?;
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first\nsecond\nthird\nfourth\n");
+        Ok(())
+    }
+
+    #[test]
+    fn partial_lines_are_recovered() -> Result<(), Error> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.txt");
+        std::fs::write(&path, "complete\r\ninterrup").unwrap();
+        let lines = read_complete_lines(File::open(&path).unwrap()).unwrap();
+        assert_eq!(lines, ["complete"]);
+
+        // The next record starts on a new line, leaving the partial one intact
+        AppendFile::open(&path)?.append("next")?;
+        let lines = read_complete_lines(File::open(&path).unwrap()).unwrap();
+        assert_eq!(lines, ["complete", "interrup", "next"]);
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_writers_do_not_interleave() -> Result<(), Error> {
+        const WRITERS: usize = 8;
+        const RECORDS: usize = 20;
+        // Long enough that an unlocked write could be split
+        const RECORD_LEN: usize = 64 * 1024;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.txt");
+        let shared = Arc::new(AppendFile::open(&path)?);
+        let handles: Vec<_> = (0..WRITERS)
+            .map(|idx| -> Result<_, Error> {
+                // Half of the writers open the file themselves, as a separate
+                // process would
+                let file = if idx % 2 == 0 { Arc::clone(&shared) } else { Arc::new(AppendFile::open(&path)?) };
+                Ok(std::thread::spawn(move || -> Result<(), Error> {
+                    let marker = char::from(b'a' + u8::try_from(idx).unwrap());
+                    let record = marker.to_string().repeat(RECORD_LEN);
+                    for _ in 0..RECORDS {
+                        file.append(&record)?;
+                    }
+                    Ok(())
+                }))
+            })
+            .collect::<Result<_, Error>>()?;
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+
+        let lines = read_complete_lines(File::open(&path).unwrap()).unwrap();
+        assert_eq!(lines.len(), WRITERS * RECORDS);
+        for line in &lines {
+            assert_eq!(line.len(), RECORD_LEN);
+            let first = line.chars().next().unwrap();
+            assert!(line.chars().all(|c| c == first), "Records were interleaved");
+        }
+        Ok(())
+    }
+}
@@ -1,8 +1,13 @@
 use std::ffi::{OsStr, OsString};
-use std::io::{self, Write};
+use std::fs::{self, Metadata, Permissions};
+use std::io::{self, ErrorKind, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Once;
 
-use tempfile::NamedTempFile;
+use clap::ValueEnum;
+use tempfile::{NamedTempFile, PersistError};
+#[cfg(windows)]
+use windows_sys::Win32::Storage::FileSystem::{SetFileAttributesW, FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_SYSTEM};
 use zoog::Error;
 
 #[derive(Debug)]
@@ -14,10 +19,82 @@ enum FileEnum {
 #[derive(Debug)]
 pub struct OutputFile {
     file_enum: FileEnum,
+    make_writable: bool,
+    permissions: Option<Permissions>,
+    overwrite: bool,
+    temp_naming: TempNaming,
 }
 
-fn make_sibling_temporary_file(path: &Path, distinguisher: &OsStr) -> Result<NamedTempFile, Error> {
-    let parent_dir = path.parent().ok_or_else(|| Error::NoParentError(path.to_path_buf()))?;
+/// How the temporary files written before being moved into place are named
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum TempNaming {
+    /// the name of the output followed by random characters
+    #[default]
+    Random,
+
+    /// `<stem>-zoogtmp<ext>` for an output named `<stem><ext>`, failing if it
+    /// already exists
+    Deterministic,
+}
+
+/// Where and how an `OutputFile` creates its temporary
+#[derive(Copy, Clone, Debug, Default)]
+pub struct OutputFileOptions<'a> {
+    /// The directory the temporary is created in, rather than next to the
+    /// output
+    pub temp_dir: Option<&'a Path>,
+    pub temp_naming: TempNaming,
+}
+
+/// The text inserted between the stem and extension of the output to name a
+/// deterministically named temporary. External tools may match on this, so it
+/// must not change.
+const DETERMINISTIC_TEMP_MARKER: &str = "zoogtmp";
+
+/// Parses an octal Unix file mode such as `644` for `--file-mode`
+#[allow(dead_code)]
+pub fn parse_file_mode(value: &str) -> Result<u32, String> {
+    let is_octal = !value.is_empty() && value.chars().all(|c| matches!(c, '0'..='7'));
+    match u32::from_str_radix(value, 8) {
+        Ok(mode) if is_octal && mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("`{}` is not an octal file mode such as 644", value)),
+    }
+}
+
+/// The permissions requested for temporary files, which on Unix are the
+/// standard permissions for new files so that the umask applies as usual
+#[cfg(unix)]
+fn new_file_permissions() -> Option<Permissions> {
+    use std::os::unix::fs::PermissionsExt as _;
+    Some(Permissions::from_mode(0o666))
+}
+
+#[cfg(not(unix))]
+fn new_file_permissions() -> Option<Permissions> { None }
+
+#[cfg(unix)]
+fn mode_permissions(mode: u32) -> Option<Permissions> {
+    use std::os::unix::fs::PermissionsExt as _;
+    Some(Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn mode_permissions(_: u32) -> Option<Permissions> { None }
+
+/// The directory containing `path`, in which temporaries are created by
+/// default so that they can be renamed over it
+fn parent_dir(path: &Path) -> Result<&Path, Error> {
+    path.parent().ok_or_else(|| Error::NoParentError(path.to_path_buf()))
+}
+
+/// Creates a temporary file in `dir` with a name derived from that of `path`.
+/// A randomly named temporary also includes `distinguisher`. A
+/// deterministically named temporary is created exclusively, so that an
+/// existing file of the same name, such as one left behind by an earlier run,
+/// is an error rather than being reused.
+fn make_temporary_file(
+    path: &Path, dir: &Path, distinguisher: &OsStr, naming: TempNaming,
+) -> Result<NamedTempFile, Error> {
     let file_stem = path.file_stem().ok_or_else(|| Error::NotAFilePath(path.to_path_buf()))?;
     let file_ext = path.extension().map(|e| {
         let mut ext = OsString::from(".");
@@ -27,7 +104,10 @@ fn make_sibling_temporary_file(path: &Path, distinguisher: &OsStr) -> Result<Nam
     let file_stem = {
         let mut stem = file_stem.to_os_string();
         stem.push("-");
-        stem.push(distinguisher);
+        match naming {
+            TempNaming::Random => stem.push(distinguisher),
+            TempNaming::Deterministic => stem.push(DETERMINISTIC_TEMP_MARKER),
+        }
         stem
     };
     let mut builder = tempfile::Builder::new();
@@ -35,28 +115,399 @@ fn make_sibling_temporary_file(path: &Path, distinguisher: &OsStr) -> Result<Nam
     if let Some(file_ext) = file_ext.as_ref() {
         builder.suffix(file_ext);
     }
-    let temp = builder.tempfile_in(parent_dir).map_err(|e| Error::TempFileOpenError(parent_dir.to_path_buf(), e))?;
-    Ok(temp)
+    if naming == TempNaming::Deterministic {
+        // Without random characters, creation is attempted exactly once
+        builder.rand_bytes(0);
+    }
+    if let Some(permissions) = new_file_permissions() {
+        builder.permissions(permissions);
+    }
+    builder.tempfile_in(dir).map_err(|e| {
+        if naming == TempNaming::Deterministic && e.kind() == ErrorKind::AlreadyExists {
+            let mut name = file_stem.clone();
+            if let Some(file_ext) = &file_ext {
+                name.push(file_ext);
+            }
+            Error::TempFileExists(dir.join(name))
+        } else {
+            Error::TempFileOpenError(dir.to_path_buf(), e)
+        }
+    })
+}
+
+/// The OS error code for renaming a file onto a different filesystem, which is
+/// `EXDEV` on Unix and `ERROR_NOT_SAME_DEVICE` on Windows
+#[cfg(unix)]
+const CROSS_DEVICE_ERROR: Option<i32> = Some(18);
+#[cfg(windows)]
+const CROSS_DEVICE_ERROR: Option<i32> = Some(17);
+#[cfg(not(any(unix, windows)))]
+const CROSS_DEVICE_ERROR: Option<i32> = None;
+
+/// Whether `error` was caused by trying to rename a file onto a different
+/// filesystem
+fn is_cross_device(error: &io::Error) -> bool {
+    CROSS_DEVICE_ERROR.is_some() && error.raw_os_error() == CROSS_DEVICE_ERROR
+}
+
+/// Moves a temporary file over its destination. This exists so that tests can
+/// simulate renames failing.
+trait Persist {
+    fn persist(&self, temp: NamedTempFile, path: &Path) -> Result<fs::File, PersistError>;
+}
+
+/// Persists temporary files by renaming them
+struct Rename;
+
+impl Persist for Rename {
+    fn persist(&self, temp: NamedTempFile, path: &Path) -> Result<fs::File, PersistError> { temp.persist(path) }
+}
+
+/// Persists temporary files by renaming them, failing if the destination
+/// exists
+struct NoClobber;
+
+impl Persist for NoClobber {
+    fn persist(&self, temp: NamedTempFile, path: &Path) -> Result<fs::File, PersistError> {
+        temp.persist_noclobber(path)
+    }
+}
+
+/// Copies `temp` to a new temporary next to `path` with the specified
+/// permissions and attributes, so that it can be renamed over `path`
+fn copy_to_sibling(
+    temp: &NamedTempFile, path: &Path, permissions: Option<&Permissions>, attributes: u32, naming: TempNaming,
+) -> Result<NamedTempFile, Error> {
+    let sibling = make_temporary_file(path, parent_dir(path)?, OsStr::new("new"), naming)?;
+    fs::copy(temp.path(), sibling.path())
+        .map_err(|e| Error::FileCopy(temp.path().to_path_buf(), path.to_path_buf(), e))?;
+    if let Some(permissions) = permissions {
+        fs::set_permissions(sibling.path(), permissions.clone())
+            .map_err(|e| Error::FileMetadataWriteError(sibling.path().to_path_buf(), e))?;
+    }
+    if attributes != 0 {
+        add_attributes(sibling.path(), attributes)?;
+    }
+    sibling.as_file().sync_all().map_err(Error::WriteError)?;
+    Ok(sibling)
+}
+
+/// Replaces the contents of `path` with those of `temp` by copying, for when
+/// `temp` cannot be renamed over `path` and there is no room for a copy of it
+/// next to `path`. Unlike a rename this is not atomic, so `path` may be left
+/// truncated if the copy fails. A warning saying so is printed the first time
+/// this happens.
+fn copy_over(temp: &NamedTempFile, path: &Path, reason: &Error) -> Result<(), Error> {
+    static WARNING: Once = Once::new();
+    WARNING.call_once(|| {
+        eprintln!(
+            "Warning: Unable to copy the temporary file next to {} ({}), so files are being replaced by copying. This \
+             is not atomic and a file may be left incomplete if replacing it fails.",
+            path.display(),
+            reason
+        );
+    });
+    let copy_error = |e| Error::FileCopy(temp.path().to_path_buf(), path.to_path_buf(), e);
+    let mut source = temp.reopen().map_err(|e| Error::FileOpenError(temp.path().to_path_buf(), e))?;
+    let mut destination = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+    io::copy(&mut source, &mut destination).map_err(copy_error)?;
+    destination.sync_all().map_err(Error::WriteError)
+}
+
+/// Replaces `path` with `temp`, which should already have the specified
+/// permissions and attributes, and syncs the result.
+///
+/// A temporary on a different filesystem to `path` cannot be renamed over it.
+/// It is then copied to a second temporary next to `path` which is renamed
+/// instead, so the replacement is still atomic. If even that copy cannot be
+/// made, `temp` is copied directly over `path`. Both temporaries are deleted
+/// whether or not this succeeds.
+fn persist_temporary<P: Persist>(
+    persister: &P, temp: NamedTempFile, path: &Path, permissions: Option<&Permissions>, attributes: u32,
+    naming: TempNaming,
+) -> Result<(), Error> {
+    let temp = match persister.persist(temp, path) {
+        Ok(file) => return file.sync_all().map_err(Error::WriteError),
+        Err(e) if is_cross_device(&e.error) => e.file,
+        Err(e) => return Err(Error::PersistError(e)),
+    };
+    match copy_to_sibling(&temp, path, permissions, attributes, naming) {
+        Ok(sibling) => persister
+            .persist(sibling, path)
+            .map_err(Error::PersistError)
+            .and_then(|f| f.sync_all().map_err(Error::WriteError)),
+        Err(e) => {
+            copy_over(&temp, path, &e)?;
+            permissions.map_or(Ok(()), |permissions| {
+                fs::set_permissions(path, permissions.clone())
+                    .map_err(|e| Error::FileMetadataWriteError(path.to_path_buf(), e))
+            })
+        }
+    }
+}
+
+/// Reads the permissions of `path`, returning `None` if it does not exist
+fn existing_permissions(path: &Path) -> Result<Option<(Metadata, Permissions)>, Error> {
+    match fs::metadata(path) {
+        Ok(metadata) => {
+            let permissions = metadata.permissions();
+            Ok(Some((metadata, permissions)))
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::FileMetadataReadError(path.to_path_buf(), e)),
+    }
+}
+
+#[cfg(unix)]
+fn same_owner(a: &Metadata, b: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt as _;
+    a.uid() == b.uid()
+}
+
+#[cfg(not(unix))]
+fn same_owner(_: &Metadata, _: &Metadata) -> bool { true }
+
+#[cfg(unix)]
+fn owner_writable(permissions: &Permissions) -> Permissions {
+    use std::os::unix::fs::PermissionsExt as _;
+    Permissions::from_mode(permissions.mode() | 0o200)
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::permissions_set_readonly_false)]
+fn owner_writable(permissions: &Permissions) -> Permissions {
+    let mut permissions = permissions.clone();
+    permissions.set_readonly(false);
+    permissions
+}
+
+/// File attributes on Windows which are not part of a file's permissions but
+/// which a replacement for it should keep. The archive attribute is not among
+/// them since a replaced file has changed and should be backed up again.
+#[cfg(windows)]
+const PRESERVED_ATTRIBUTES: u32 = FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM;
+
+/// Reads the attributes of `path` which should be kept when it is replaced,
+/// returning zero if it does not exist
+#[cfg(windows)]
+fn preserved_attributes(path: &Path) -> Result<u32, Error> {
+    use std::os::windows::fs::MetadataExt as _;
+    Ok(existing_permissions(path)?.map_or(0, |(metadata, _)| metadata.file_attributes() & PRESERVED_ATTRIBUTES))
+}
+
+#[cfg(not(windows))]
+#[allow(clippy::unnecessary_wraps)]
+fn preserved_attributes(_: &Path) -> Result<u32, Error> { Ok(0) }
+
+/// Sets `attributes` on `path` in addition to those it already has
+#[cfg(windows)]
+fn add_attributes(path: &Path, attributes: u32) -> Result<(), Error> {
+    use std::os::windows::ffi::OsStrExt as _;
+    use std::os::windows::fs::MetadataExt as _;
+
+    let metadata = fs::metadata(path).map_err(|e| Error::FileMetadataReadError(path.to_path_buf(), e))?;
+    let wide_path: Vec<u16> = path.as_os_str().encode_wide().chain(std::iter::once(0)).collect();
+    // SAFETY: `wide_path` is a NUL-terminated UTF-16 string which outlives the call
+    let succeeded = unsafe { SetFileAttributesW(wide_path.as_ptr(), metadata.file_attributes() | attributes) };
+    if succeeded == 0 {
+        return Err(Error::FileMetadataWriteError(path.to_path_buf(), io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[allow(clippy::unnecessary_wraps)]
+fn add_attributes(_: &Path, _: u32) -> Result<(), Error> { Ok(()) }
+
+/// Checks that `path` can be replaced by an `OutputFile` before any work is
+/// done which would be wasted if it could not. The directory containing
+/// `path` must be writable. If `path` is read-only it can only be replaced
+/// when `make_writable` is set and it is owned by the current user.
+#[allow(dead_code)]
+pub fn check_replaceable(path: &Path, make_writable: bool, naming: TempNaming) -> Result<(), Error> {
+    // Creating a file is the only reliable way to check the directory is writable
+    let probe = make_temporary_file(path, parent_dir(path)?, OsStr::new("probe"), naming).map_err(|e| match e {
+        Error::TempFileOpenError(dir, e) => Error::DirectoryNotWritable(dir, e),
+        e => e,
+    })?;
+    let Some((metadata, permissions)) = existing_permissions(path)? else {
+        return Ok(());
+    };
+    if !permissions.readonly() {
+        return Ok(());
+    }
+    if !make_writable {
+        return Err(Error::ReadOnlyFile(path.to_path_buf()));
+    }
+    // The probe is owned by whoever we are running as
+    let probe_path = probe.path().to_path_buf();
+    let probe_metadata = probe.as_file().metadata().map_err(|e| Error::FileMetadataReadError(probe_path, e))?;
+    if same_owner(&metadata, &probe_metadata) {
+        Ok(())
+    } else {
+        Err(Error::ReadOnlyFileNotOwned(path.to_path_buf()))
+    }
+}
+
+/// The maximum number of symbolic links followed when resolving an output path
+const MAX_SYMLINK_DEPTH: usize = 40;
+
+/// Resolves the path an output file should be written to. If `path` is a
+/// symbolic link and `dereference` is set, the file it ultimately refers to is
+/// returned so that replacing the output replaces that file and leaves the link
+/// in place. Otherwise `path` is returned, so a link would itself be replaced.
+/// The link target need not exist.
+#[allow(dead_code)]
+pub fn resolve_output_path(path: &Path, dereference: bool) -> Result<PathBuf, Error> {
+    let mut path = path.to_path_buf();
+    if !dereference {
+        return Ok(path);
+    }
+    for _ in 0..MAX_SYMLINK_DEPTH {
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let target = fs::read_link(&path).map_err(|e| Error::FileMetadataReadError(path.clone(), e))?;
+                // A relative target is relative to the directory containing the link
+                path = match path.parent() {
+                    Some(parent) => parent.join(target),
+                    None => target,
+                };
+            }
+            _ => return Ok(path),
+        }
+    }
+    Err(Error::FileMetadataReadError(path, io::Error::other("too many levels of symbolic links")))
+}
+
+/// Whether `a` and `b` refer to the same existing file, for example because
+/// one is a link to the other or because they differ only by case on a
+/// case-insensitive filesystem. Paths to files which do not exist never refer
+/// to the same file.
+#[cfg(unix)]
+#[allow(dead_code)]
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt as _;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+#[allow(dead_code)]
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    let cache = zoog::case_sensitivity::CaseSensitivityCache::default();
+    a.exists() && b.exists() && cache.file_key(a) == cache.file_key(b)
 }
 
 impl OutputFile {
     /// Creates a new output that discards all data written
-    pub fn new_sink() -> OutputFile { OutputFile { file_enum: FileEnum::Sink } }
+    pub fn new_sink() -> OutputFile {
+        OutputFile {
+            file_enum: FileEnum::Sink,
+            make_writable: false,
+            permissions: None,
+            overwrite: true,
+            temp_naming: TempNaming::default(),
+        }
+    }
 
     /// Writes to a temporary that replaces the specified path on `commit()`.
-    pub fn new_target(path: &Path) -> Result<OutputFile, Error> {
-        let temp = make_sibling_temporary_file(path, OsStr::new("new"))?;
-        Ok(OutputFile { file_enum: FileEnum::Temp(temp, path.to_path_buf()) })
+    /// The new file keeps the permissions of any file it replaces. Otherwise,
+    /// it has the standard permissions for new files (0666 less the umask on
+    /// Unix).
+    ///
+    /// The temporary is created in the temporary directory of `options` if
+    /// specified, and otherwise next to `path`. If that directory is on a
+    /// different filesystem to `path`, the temporary is copied next to `path`
+    /// on `commit()` so that it can still be renamed over it.
+    pub fn new_target(path: &Path, options: OutputFileOptions) -> Result<OutputFile, Error> {
+        let permissions = existing_permissions(path)?.map(|(_, permissions)| permissions);
+        let dir = match options.temp_dir {
+            Some(dir) => dir,
+            None => parent_dir(path)?,
+        };
+        let temp = make_temporary_file(path, dir, OsStr::new("new"), options.temp_naming)?;
+        let file_enum = FileEnum::Temp(temp, path.to_path_buf());
+        Ok(OutputFile {
+            file_enum,
+            make_writable: false,
+            permissions,
+            overwrite: true,
+            temp_naming: options.temp_naming,
+        })
+    }
+
+    /// Writes to a temporary that is moved to the specified path on
+    /// `commit()`, for output which is derived from another file rather than
+    /// replacing it. Unless `overwrite` is set, an existing file at `path` is
+    /// an error, both now and on `commit()`.
+    #[allow(dead_code)]
+    pub fn new_copy(path: &Path, options: OutputFileOptions, overwrite: bool) -> Result<OutputFile, Error> {
+        if !overwrite && fs::symlink_metadata(path).is_ok() {
+            return Err(Error::OutputFileExists(path.to_path_buf()));
+        }
+        Ok(OutputFile { overwrite, ..Self::new_target(path, options)? })
     }
 
     /// Writes to a temporary that replaces the specified path on `commit()` if
     /// `discard` is `false`. Otherwise discards all data written.
-    pub fn new_target_or_discard(path: &Path, discard: bool) -> Result<OutputFile, Error> {
+    pub fn new_target_or_discard(path: &Path, options: OutputFileOptions, discard: bool) -> Result<OutputFile, Error> {
         if discard {
             Ok(Self::new_sink())
         } else {
-            Self::new_target(path)
+            Self::new_target(path, options)
+        }
+    }
+
+    /// If `make_writable` is set, a read-only file at the target path is made
+    /// writable so that it can be replaced on `commit()`, and the new file is
+    /// given the original permissions.
+    #[allow(dead_code)]
+    #[must_use]
+    pub fn with_make_writable(self, make_writable: bool) -> OutputFile { OutputFile { make_writable, ..self } }
+
+    /// Gives the new file the permissions of `source`, such as the file it is
+    /// a modified copy of, rather than those of any file it replaces.
+    #[allow(dead_code)]
+    pub fn with_permissions_from(self, source: &Path) -> Result<OutputFile, Error> {
+        if matches!(self.file_enum, FileEnum::Sink) {
+            return Ok(self);
         }
+        let permissions = existing_permissions(source)?.map(|(_, permissions)| permissions);
+        Ok(OutputFile { permissions: permissions.or(self.permissions), ..self })
+    }
+
+    /// Gives the new file exactly the Unix mode `mode`, regardless of the
+    /// umask, if one is specified. This has no effect on other platforms.
+    #[allow(dead_code)]
+    #[must_use]
+    pub fn with_file_mode(self, mode: Option<u32>) -> OutputFile {
+        match mode.and_then(mode_permissions) {
+            Some(permissions) => OutputFile { permissions: Some(permissions), ..self },
+            None => self,
+        }
+    }
+
+    /// Replaces anything written so far with a copy of `source`. This uses a
+    /// filesystem copy rather than streaming the data through this process,
+    /// which on some filesystems allows the copy to share storage with
+    /// `source`.
+    #[allow(dead_code)]
+    pub fn copy_from(&mut self, source: &Path) -> Result<(), Error> {
+        match &self.file_enum {
+            FileEnum::Sink => {}
+            FileEnum::Temp(temp, final_path) => {
+                fs::copy(source, temp.path())
+                    .map_err(|e| Error::FileCopy(source.to_path_buf(), final_path.clone(), e))?;
+            }
+        }
+        Ok(())
     }
 
     /// Deletes the underlying file.
@@ -74,19 +525,69 @@ impl OutputFile {
 
     /// Persists the file to the intended path.
     pub fn commit(self) -> Result<(), Error> {
+        if self.overwrite {
+            return self.commit_with(&Rename);
+        }
+        let path = match &self.file_enum {
+            FileEnum::Temp(_, path) => path.clone(),
+            FileEnum::Sink => return Ok(()),
+        };
+        self.commit_with(&NoClobber).map_err(|e| match e {
+            Error::PersistError(e) if e.error.kind() == ErrorKind::AlreadyExists => Error::OutputFileExists(path),
+            e => e,
+        })
+    }
+
+    /// Persists the file to the intended path using `persister`
+    fn commit_with<P: Persist>(self, persister: &P) -> Result<(), Error> {
         match self.file_enum {
             FileEnum::Sink => {}
             FileEnum::Temp(temp, final_path) => {
                 // How to write this code so that it minimizes the chance of
                 // data loss is an open question.
 
+                if let Some(permissions) = &self.permissions {
+                    fs::set_permissions(temp.path(), permissions.clone())
+                        .map_err(|e| Error::FileMetadataWriteError(temp.path().to_path_buf(), e))?;
+                }
+
+                // Attributes such as hidden on Windows are given to the new file before
+                // it replaces the old one. Read-only is handled with the permissions.
+                let attributes = preserved_attributes(&final_path)?;
+                if attributes != 0 {
+                    add_attributes(temp.path(), attributes)?;
+                }
+
                 // Sync all data of the new file to disk
                 temp.as_file().sync_all().map_err(Error::WriteError)?;
 
+                let restore = if self.make_writable {
+                    existing_permissions(&final_path)?.map(|(_, permissions)| permissions).filter(Permissions::readonly)
+                } else {
+                    None
+                };
+                if let Some(permissions) = &restore {
+                    fs::set_permissions(&final_path, owner_writable(permissions))
+                        .map_err(|e| Error::FileMetadataWriteError(final_path.clone(), e))?;
+                }
+
                 // Persist the temporary to the final path
-                temp.persist(final_path)
-                    .map_err(Error::PersistError)
-                    .and_then(|f| f.sync_all().map_err(Error::WriteError))?;
+                let persisted = persist_temporary(
+                    persister,
+                    temp,
+                    &final_path,
+                    self.permissions.as_ref(),
+                    attributes,
+                    self.temp_naming,
+                );
+
+                // Whether or not the file was replaced, whatever is now at the final
+                // path gets the original permissions back
+                let restored = restore.map_or(Ok(()), |permissions| {
+                    fs::set_permissions(&final_path, permissions)
+                        .map_err(|e| Error::FileMetadataWriteError(final_path.clone(), e))
+                });
+                persisted.and(restored)?;
             }
         }
         Ok(())
@@ -108,3 +609,569 @@ impl Write for OutputFile {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::fs::File;
+    use std::io::{BufReader, BufWriter};
+
+    use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+    use zoog::comment_rewrite::{
+        CommentHeaderRewrite, CommentHeaderSummary, CommentRewriterAction, CommentRewriterConfig,
+    };
+    use zoog::header::{CommentHeader as _, CommentList as _, DiscreteCommentList};
+    use zoog::header_rewriter::{rewrite_stream_with_stats, RewriteStats};
+    use zoog::interrupt::Interrupt;
+    use zoog::opus::CommentHeader;
+
+    use super::*;
+
+    const NUM_AUDIO_PACKETS: usize = 20;
+
+    fn in_dir(temp_dir: &Path) -> OutputFileOptions<'_> {
+        OutputFileOptions { temp_dir: Some(temp_dir), ..OutputFileOptions::default() }
+    }
+
+    const DETERMINISTIC: OutputFileOptions =
+        OutputFileOptions { temp_dir: None, temp_naming: TempNaming::Deterministic };
+
+    /// An interrupt which triggers after it has been polled a fixed number of
+    /// times
+    struct AfterPolls {
+        remaining: Cell<usize>,
+    }
+
+    impl Interrupt for AfterPolls {
+        fn is_set(&self) -> bool {
+            let remaining = self.remaining.get();
+            self.remaining.set(remaining.saturating_sub(1));
+            remaining == 0
+        }
+    }
+
+    fn opus_file() -> Vec<u8> {
+        let mut id_header = b"OpusHead".to_vec();
+        id_header.extend_from_slice(&[1, 2, 0, 0, 0x80, 0xbb, 0, 0, 0, 0, 0]);
+        let mut comment_header = Vec::new();
+        let mut comments = CommentHeader::default();
+        comments.set_vendor("zoog-test");
+        comments.push("TITLE", "Original").unwrap();
+        comments.serialize_into(&mut comment_header).unwrap();
+
+        let mut data = Vec::new();
+        let mut writer = PacketWriter::new(&mut data);
+        writer.write_packet(id_header, 1, PacketWriteEndInfo::EndPage, 0).unwrap();
+        writer.write_packet(comment_header, 1, PacketWriteEndInfo::EndPage, 0).unwrap();
+        for idx in 0..NUM_AUDIO_PACKETS {
+            let info = if idx + 1 == NUM_AUDIO_PACKETS {
+                PacketWriteEndInfo::EndStream
+            } else if idx % 4 == 3 {
+                PacketWriteEndInfo::EndPage
+            } else {
+                PacketWriteEndInfo::NormalPacket
+            };
+            // Audio packets are forwarded untouched so need not be valid Opus
+            writer.write_packet(vec![0xfc; 100 + idx], 1, info, (960 * (idx + 1)) as u64).unwrap();
+        }
+        drop(writer);
+        data
+    }
+
+    #[test]
+    fn interrupted_rewrite_leaves_target_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("track.opus");
+        let original = opus_file();
+        std::fs::write(&path, &original).unwrap();
+
+        // Two header packets and the audio packets are read, then end-of-stream
+        let num_polls = NUM_AUDIO_PACKETS + 3;
+        for interrupt_after in 0..num_polls {
+            let input = BufReader::new(File::open(&path).unwrap());
+            let mut output_file = OutputFile::new_target(&path, OutputFileOptions::default()).unwrap();
+            let mut stats = RewriteStats::default();
+            let result = {
+                let rewrite = CommentHeaderRewrite::new(CommentRewriterConfig {
+                    action: CommentRewriterAction::Replace(DiscreteCommentList::default()),
+                });
+                let summarize = CommentHeaderSummary::default();
+                let interrupt = AfterPolls { remaining: Cell::new(interrupt_after) };
+                let output = BufWriter::new(&mut output_file);
+                rewrite_stream_with_stats(rewrite, summarize, input, output, false, &interrupt, &mut stats)
+            };
+            assert!(matches!(result, Err(Error::Interrupted)));
+            assert_eq!(stats.packets_read, interrupt_after as u64);
+            output_file.abort().unwrap();
+
+            assert_eq!(std::fs::read(&path).unwrap(), original);
+            assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_output_is_resolved() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target.opus");
+        std::fs::write(&target, opus_file()).unwrap();
+        let link = dir.path().join("link.opus");
+        let chained = dir.path().join("chained.opus");
+        std::os::unix::fs::symlink("target.opus", &link).unwrap();
+        std::os::unix::fs::symlink(&link, &chained).unwrap();
+
+        assert_eq!(resolve_output_path(&chained, true).unwrap(), target);
+        assert_eq!(resolve_output_path(&chained, false).unwrap(), chained);
+        assert_eq!(resolve_output_path(&target, true).unwrap(), target);
+        assert!(same_file(&chained, &target));
+
+        // Replacing the resolved path leaves the links in place
+        let mut output_file =
+            OutputFile::new_target(&resolve_output_path(&chained, true).unwrap(), OutputFileOptions::default())
+                .unwrap();
+        output_file.write_all(b"replaced").unwrap();
+        output_file.commit().unwrap();
+        assert!(std::fs::symlink_metadata(&chained).unwrap().file_type().is_symlink());
+        assert_eq!(std::fs::read(&chained).unwrap(), b"replaced");
+
+        // A dangling link resolves to the file it would create
+        let dangling = dir.path().join("dangling.opus");
+        std::os::unix::fs::symlink("missing.opus", &dangling).unwrap();
+        assert_eq!(resolve_output_path(&dangling, true).unwrap(), dir.path().join("missing.opus"));
+        assert!(!same_file(&dangling, &dir.path().join("missing.opus")));
+
+        let looped = dir.path().join("loop.opus");
+        std::os::unix::fs::symlink("loop.opus", &looped).unwrap();
+        assert!(matches!(resolve_output_path(&looped, true), Err(Error::FileMetadataReadError(..))));
+    }
+
+    #[test]
+    fn distinct_files_are_not_the_same() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.opus"), dir.path().join("b.opus"));
+        std::fs::write(&a, opus_file()).unwrap();
+        assert!(same_file(&a, &a));
+        assert!(!same_file(&a, &b));
+        std::fs::write(&b, opus_file()).unwrap();
+        assert!(!same_file(&a, &b));
+    }
+
+    #[test]
+    fn missing_file_is_replaceable() {
+        let dir = tempfile::tempdir().unwrap();
+        check_replaceable(&dir.path().join("new.opus"), false, TempNaming::Random).unwrap();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_only_file_needs_make_writable() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("track.opus");
+        std::fs::write(&path, opus_file()).unwrap();
+        std::fs::set_permissions(&path, Permissions::from_mode(0o444)).unwrap();
+
+        assert!(
+            matches!(check_replaceable(&path, false, TempNaming::Random), Err(Error::ReadOnlyFile(p)) if p == path)
+        );
+        check_replaceable(&path, true, TempNaming::Random).unwrap();
+        // The probe used to check the directory is removed
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn make_writable_restores_mode() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("track.opus");
+        std::fs::write(&path, opus_file()).unwrap();
+        std::fs::set_permissions(&path, Permissions::from_mode(0o444)).unwrap();
+
+        let mut output_file =
+            OutputFile::new_target(&path, OutputFileOptions::default()).unwrap().with_make_writable(true);
+        output_file.write_all(b"replaced").unwrap();
+        output_file.commit().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"replaced");
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o444);
+    }
+
+    /// The mode a file created with the standard permissions ends up with
+    /// under the current umask
+    #[cfg(unix)]
+    fn standard_new_file_mode(dir: &Path) -> u32 {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let path = dir.join("standard");
+        let mode = File::create(&path).unwrap().metadata().unwrap().permissions().mode() & 0o7777;
+        std::fs::remove_file(path).unwrap();
+        mode
+    }
+
+    #[cfg(unix)]
+    fn mode(path: &Path) -> u32 {
+        use std::os::unix::fs::PermissionsExt as _;
+        std::fs::metadata(path).unwrap().permissions().mode() & 0o7777
+    }
+
+    #[cfg(unix)]
+    fn write_output(mut output_file: OutputFile) {
+        output_file.write_all(b"output").unwrap();
+        output_file.commit().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn new_file_has_standard_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("new.opus");
+        write_output(OutputFile::new_target(&path, OutputFileOptions::default()).unwrap());
+        assert_eq!(mode(&path), standard_new_file_mode(dir.path()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replaced_file_keeps_mode() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("track.opus");
+        std::fs::write(&path, opus_file()).unwrap();
+        for existing in [0o640, 0o604, 0o755] {
+            std::fs::set_permissions(&path, Permissions::from_mode(existing)).unwrap();
+            write_output(OutputFile::new_target(&path, OutputFileOptions::default()).unwrap());
+            assert_eq!(mode(&path), existing);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copied_file_takes_source_mode() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source.opus");
+        let path = dir.path().join("copy.opus");
+        std::fs::write(&source, opus_file()).unwrap();
+        std::fs::set_permissions(&source, Permissions::from_mode(0o640)).unwrap();
+        write_output(
+            OutputFile::new_target(&path, OutputFileOptions::default())
+                .unwrap()
+                .with_permissions_from(&source)
+                .unwrap(),
+        );
+        assert_eq!(mode(&path), 0o640);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_mode_overrides_umask() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("track.opus");
+        write_output(OutputFile::new_target(&path, OutputFileOptions::default()).unwrap().with_file_mode(Some(0o664)));
+        assert_eq!(mode(&path), 0o664);
+
+        // An explicit mode also replaces that of an existing file
+        std::fs::set_permissions(&path, Permissions::from_mode(0o600)).unwrap();
+        write_output(OutputFile::new_target(&path, OutputFileOptions::default()).unwrap().with_file_mode(Some(0o644)));
+        assert_eq!(mode(&path), 0o644);
+    }
+
+    #[test]
+    fn temporary_is_created_in_temp_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("track.opus");
+        std::fs::write(&path, b"original").unwrap();
+        let mut output_file = OutputFile::new_target(&path, in_dir(temp_dir.path())).unwrap();
+        let FileEnum::Temp(temp, _) = &output_file.file_enum else { panic!("Expected a temporary file") };
+        assert_eq!(temp.path().parent(), Some(temp_dir.path()));
+        output_file.write_all(b"replacement").unwrap();
+        output_file.commit().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"replacement");
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    /// Fails the first `failures` renames with the specified OS error
+    struct FailingRename {
+        error: i32,
+        failures: Cell<usize>,
+        calls: Cell<usize>,
+    }
+
+    impl FailingRename {
+        fn new(error: i32, failures: usize) -> FailingRename {
+            FailingRename { error, failures: Cell::new(failures), calls: Cell::new(0) }
+        }
+
+        fn cross_device(failures: usize) -> FailingRename {
+            Self::new(CROSS_DEVICE_ERROR.expect("Cross-device errors should be detectable"), failures)
+        }
+    }
+
+    impl Persist for FailingRename {
+        fn persist(&self, temp: NamedTempFile, path: &Path) -> Result<fs::File, PersistError> {
+            self.calls.set(self.calls.get() + 1);
+            match self.failures.get() {
+                0 => Rename.persist(temp, path),
+                failures => {
+                    self.failures.set(failures - 1);
+                    Err(PersistError { error: io::Error::from_raw_os_error(self.error), file: temp })
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn copy_does_not_overwrite_unless_requested() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("copy.opus");
+        let mut output_file = OutputFile::new_copy(&path, OutputFileOptions::default(), false).unwrap();
+        output_file.write_all(b"first").unwrap();
+        output_file.commit().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"first");
+
+        let result = OutputFile::new_copy(&path, OutputFileOptions::default(), false);
+        assert!(matches!(result, Err(Error::OutputFileExists(p)) if p == path));
+        let mut output_file = OutputFile::new_copy(&path, OutputFileOptions::default(), true).unwrap();
+        output_file.write_all(b"second").unwrap();
+        output_file.commit().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+
+        // A file created after the output was started is not replaced either
+        std::fs::remove_file(&path).unwrap();
+        let mut output_file = OutputFile::new_copy(&path, OutputFileOptions::default(), false).unwrap();
+        output_file.write_all(b"third").unwrap();
+        std::fs::write(&path, b"other").unwrap();
+        assert!(matches!(output_file.commit(), Err(Error::OutputFileExists(p)) if p == path));
+        assert_eq!(std::fs::read(&path).unwrap(), b"other");
+        assert_eq!(dir_entries(dir.path()), [OsString::from("copy.opus")]);
+    }
+
+    fn dir_entries(dir: &Path) -> Vec<OsString> {
+        let mut entries: Vec<_> = std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn deterministic_temporary_is_named_after_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("track.opus");
+        let mut output_file = OutputFile::new_target(&path, DETERMINISTIC).unwrap();
+        assert_eq!(dir_entries(dir.path()), ["track-zoogtmp.opus"]);
+        output_file.write_all(b"replacement").unwrap();
+        output_file.commit().unwrap();
+        assert_eq!(dir_entries(dir.path()), ["track.opus"]);
+        assert_eq!(std::fs::read(&path).unwrap(), b"replacement");
+    }
+
+    #[test]
+    fn existing_deterministic_temporary_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("track.opus");
+        let stale = dir.path().join("track-zoogtmp.opus");
+        std::fs::write(&stale, b"stale").unwrap();
+        let result = OutputFile::new_target(&path, DETERMINISTIC);
+        assert!(matches!(result, Err(Error::TempFileExists(ref p)) if *p == stale), "{:?}", result);
+        let result = check_replaceable(&path, false, TempNaming::Deterministic);
+        assert!(matches!(result, Err(Error::TempFileExists(ref p)) if *p == stale), "{:?}", result);
+        // The existing file is left untouched
+        assert_eq!(std::fs::read(&stale).unwrap(), b"stale");
+
+        // Random names do not collide with it
+        OutputFile::new_target(&path, OutputFileOptions::default()).unwrap().abort().unwrap();
+        assert_eq!(dir_entries(dir.path()), ["track-zoogtmp.opus"]);
+    }
+
+    #[test]
+    fn abort_removes_deterministic_temporary() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("track.opus");
+        std::fs::write(&path, b"original").unwrap();
+        let mut output_file = OutputFile::new_target(&path, DETERMINISTIC).unwrap();
+        output_file.write_all(b"replacement").unwrap();
+        output_file.abort().unwrap();
+        assert_eq!(dir_entries(dir.path()), ["track.opus"]);
+        assert_eq!(std::fs::read(&path).unwrap(), b"original");
+
+        // The name can be used again once the temporary has gone
+        OutputFile::new_target(&path, DETERMINISTIC).unwrap().commit().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"");
+    }
+
+    /// Creates a target in `dir` whose temporary is in `temp_dir`, and commits
+    /// it using `persister`
+    fn commit_replacement<P: Persist>(dir: &Path, temp_dir: &Path, persister: &P) -> Result<(), Error> {
+        let path = dir.join("track.opus");
+        let mut output_file = OutputFile::new_target(&path, in_dir(temp_dir)).unwrap();
+        output_file.write_all(b"replacement").unwrap();
+        output_file.commit_with(persister)
+    }
+
+    #[test]
+    fn cross_device_errors_are_detected() {
+        if let Some(code) = CROSS_DEVICE_ERROR {
+            assert!(is_cross_device(&io::Error::from_raw_os_error(code)));
+        }
+        assert!(!is_cross_device(&io::Error::from_raw_os_error(2)));
+        assert!(!is_cross_device(&io::Error::other("not an OS error")));
+    }
+
+    #[test]
+    fn cross_device_rename_falls_back_to_sibling_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("track.opus");
+        std::fs::write(&path, b"original").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            std::fs::set_permissions(&path, Permissions::from_mode(0o640)).unwrap();
+        }
+
+        let persister = FailingRename::cross_device(1);
+        commit_replacement(dir.path(), temp_dir.path(), &persister).unwrap();
+        assert_eq!(persister.calls.get(), 2);
+        assert_eq!(std::fs::read(&path).unwrap(), b"replacement");
+        #[cfg(unix)]
+        assert_eq!(mode(&path), 0o640);
+        assert_eq!(dir_entries(dir.path()), [OsString::from("track.opus")]);
+        assert!(dir_entries(temp_dir.path()).is_empty());
+    }
+
+    #[test]
+    fn failed_fallback_removes_both_temporaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("track.opus");
+        std::fs::write(&path, b"original").unwrap();
+
+        let persister = FailingRename::cross_device(2);
+        let result = commit_replacement(dir.path(), temp_dir.path(), &persister);
+        assert!(matches!(result, Err(Error::PersistError(_))), "{:?}", result);
+        assert_eq!(persister.calls.get(), 2);
+        assert_eq!(std::fs::read(&path).unwrap(), b"original");
+        assert_eq!(dir_entries(dir.path()), [OsString::from("track.opus")]);
+        assert!(dir_entries(temp_dir.path()).is_empty());
+    }
+
+    #[test]
+    fn other_persist_errors_are_not_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        // `ENOENT` on Unix and `ERROR_FILE_NOT_FOUND` on Windows
+        let persister = FailingRename::new(2, 1);
+        let result = commit_replacement(dir.path(), temp_dir.path(), &persister);
+        assert!(matches!(result, Err(Error::PersistError(_))), "{:?}", result);
+        assert_eq!(persister.calls.get(), 1);
+        assert!(dir_entries(dir.path()).is_empty());
+        assert!(dir_entries(temp_dir.path()).is_empty());
+    }
+
+    #[test]
+    fn temporary_is_copied_over_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("track.opus");
+        std::fs::write(&path, b"a longer original").unwrap();
+        let mut temp = make_temporary_file(&path, dir.path(), OsStr::new("new"), TempNaming::Random).unwrap();
+        temp.write_all(b"replacement").unwrap();
+        let reason = Error::NoParentError(path.clone());
+        copy_over(&temp, &path, &reason).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"replacement");
+
+        // A file which does not yet exist is created
+        let new_path = dir.path().join("new.opus");
+        copy_over(&temp, &new_path, &reason).unwrap();
+        assert_eq!(std::fs::read(&new_path).unwrap(), b"replacement");
+    }
+
+    /// Replaces a file using a temporary directory on another filesystem, if
+    /// one is available, so that the temporary cannot be renamed
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn temporary_on_other_filesystem_is_copied() {
+        use std::os::unix::fs::{MetadataExt as _, PermissionsExt as _};
+
+        let dir = tempfile::tempdir_in(env!("CARGO_MANIFEST_DIR")).unwrap();
+        let Ok(temp_dir) = tempfile::tempdir_in("/dev/shm") else { return };
+        let device = |path: &Path| std::fs::metadata(path).unwrap().dev();
+        if device(dir.path()) == device(temp_dir.path()) {
+            return;
+        }
+        let path = dir.path().join("track.opus");
+        std::fs::write(&path, b"a longer original").unwrap();
+        std::fs::set_permissions(&path, Permissions::from_mode(0o640)).unwrap();
+        let mut output_file = OutputFile::new_target(&path, in_dir(temp_dir.path())).unwrap();
+        output_file.write_all(b"replacement").unwrap();
+        output_file.commit().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"replacement");
+        assert_eq!(mode(&path), 0o640);
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn attributes_are_not_preserved_off_windows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("track.opus");
+        std::fs::write(&path, b"original").unwrap();
+        assert_eq!(preserved_attributes(&path).unwrap(), 0);
+        assert!(add_attributes(&path, u32::MAX).is_ok());
+        let mut output_file = OutputFile::new_target(&path, OutputFileOptions::default()).unwrap();
+        output_file.write_all(b"replacement").unwrap();
+        output_file.commit().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"replacement");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn replaced_file_keeps_hidden_and_system_attributes() {
+        use std::os::windows::fs::MetadataExt as _;
+
+        use windows_sys::Win32::Storage::FileSystem::{FILE_ATTRIBUTE_ARCHIVE, FILE_ATTRIBUTE_READONLY};
+
+        let attributes = |path: &Path| std::fs::metadata(path).unwrap().file_attributes();
+        let dir = tempfile::tempdir().unwrap();
+        for (name, extra, make_writable) in [
+            ("hidden.opus", FILE_ATTRIBUTE_HIDDEN, false),
+            ("system.opus", FILE_ATTRIBUTE_SYSTEM, false),
+            ("read-only.opus", FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_READONLY, true),
+        ] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, b"original").unwrap();
+            add_attributes(&path, extra).unwrap();
+            assert_eq!(preserved_attributes(&path).unwrap(), extra & PRESERVED_ATTRIBUTES);
+
+            let mut output_file =
+                OutputFile::new_target(&path, OutputFileOptions::default()).unwrap().with_make_writable(make_writable);
+            output_file.write_all(b"replacement").unwrap();
+            output_file.commit().unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), b"replacement");
+            assert_eq!(attributes(&path) & extra, extra, "Attributes of {} not preserved", name);
+            // The content has changed so the file needs backing up again
+            assert_ne!(attributes(&path) & FILE_ATTRIBUTE_ARCHIVE, 0);
+
+            let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+            #[allow(clippy::permissions_set_readonly_false)]
+            permissions.set_readonly(false);
+            std::fs::set_permissions(&path, permissions).unwrap();
+        }
+    }
+
+    #[test]
+    fn parse_file_modes() {
+        assert_eq!(parse_file_mode("644"), Ok(0o644));
+        assert_eq!(parse_file_mode("0664"), Ok(0o664));
+        assert_eq!(parse_file_mode("2775"), Ok(0o2775));
+        for invalid in ["", "888", "0o644", "+644", "-644", "17777", "rw-r--r--"] {
+            assert!(parse_file_mode(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,472 @@
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::analysis::AnalysisIssue;
+use crate::header::{CommentDiff, CommentHeaderSizes};
+use crate::vendor_stats::VendorStats;
+use crate::volume_rewrite::OpusGains;
+use crate::Decibels;
+
+/// The version of the schema of the JSON documents written by this module.
+/// This is incremented whenever a field is renamed, removed or changes type.
+/// Adding a field does not change the version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The name of the field holding the schema version, which is the first field
+/// of every JSON document
+pub const SCHEMA_VERSION_FIELD: &str = "schema_version";
+
+/// Encodes a string as a JSON string literal
+#[must_use]
+pub fn json_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if c.is_control() => result.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// A value of a field of a report
+#[derive(Clone, Debug)]
+enum Value<'a> {
+    Text(Option<Cow<'a, str>>),
+    Decibels(Option<Decibels>),
+    Seconds(Option<Duration>),
+    Count(usize),
+    Flag(bool),
+}
+
+impl Value<'_> {
+    /// Encodes the value as JSON. Decibel values are rounded to hundredths and
+    /// durations to milliseconds.
+    fn to_json(&self) -> String {
+        match self {
+            Value::Text(Some(text)) => json_string(text),
+            Value::Decibels(Some(value)) if value.as_f64().is_finite() => format!("{:.2}", value.as_f64()),
+            Value::Seconds(Some(duration)) => format!("{:.3}", duration.as_secs_f64()),
+            Value::Count(count) => count.to_string(),
+            Value::Flag(flag) => flag.to_string(),
+            Value::Text(None) | Value::Decibels(_) | Value::Seconds(None) => "null".to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Value<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Text(Some(text)) => serializer.serialize_str(text),
+            Value::Decibels(Some(value)) if value.as_f64().is_finite() => serializer.serialize_f64(value.as_f64()),
+            Value::Seconds(Some(duration)) => serializer.serialize_f64(duration.as_secs_f64()),
+            Value::Count(count) => serde::Serialize::serialize(count, serializer),
+            Value::Flag(flag) => serializer.serialize_bool(*flag),
+            Value::Text(None) | Value::Decibels(_) | Value::Seconds(None) => serializer.serialize_none(),
+        }
+    }
+}
+
+/// The named fields of an object in a report, in output order
+type Fields<'a> = [(&'static str, Value<'a>)];
+
+/// Encodes fields as a single-line JSON object
+fn json_object(fields: &Fields<'_>) -> String {
+    let fields: Vec<_> =
+        fields.iter().map(|(name, value)| format!("{}: {}", json_string(name), value.to_json())).collect();
+    format!("{{{}}}", fields.join(", "))
+}
+
+/// Writes a JSON document consisting of the schema version, `fields` and then
+/// arrays of objects, one object per line
+fn write_document<W: Write>(output: &mut W, fields: &Fields<'_>, arrays: &[(&str, Vec<String>)]) -> io::Result<()> {
+    write!(output, "{{\n  {}: {}", json_string(SCHEMA_VERSION_FIELD), SCHEMA_VERSION)?;
+    for (name, value) in fields {
+        write!(output, ",\n  {}: {}", json_string(name), value.to_json())?;
+    }
+    for (name, objects) in arrays {
+        write!(output, ",\n  {}: [", json_string(name))?;
+        for (idx, object) in objects.iter().enumerate() {
+            let separator = if idx == 0 { "" } else { "," };
+            write!(output, "{}\n    {}", separator, object)?;
+        }
+        if !objects.is_empty() {
+            write!(output, "\n  ")?;
+        }
+        write!(output, "]")?;
+    }
+    writeln!(output, "\n}}")
+}
+
+/// Serializes fields as a map
+#[cfg(feature = "serde")]
+fn serialize_fields<S: serde::Serializer>(fields: &Fields<'_>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(fields.iter().map(|(name, value)| (*name, value)))
+}
+
+/// The outcome of processing a single file
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum FileStatus {
+    Changed,
+    WouldChange,
+    Unchanged,
+    Failed,
+    Interrupted,
+    TimedOut,
+    /// The file was left in place because it was still being written
+    Skipped,
+}
+
+impl FileStatus {
+    /// The name of the status as it appears in reports
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FileStatus::Changed => "changed",
+            FileStatus::WouldChange => "would-change",
+            FileStatus::Unchanged => "unchanged",
+            FileStatus::Failed => "failed",
+            FileStatus::Interrupted => "interrupted",
+            FileStatus::TimedOut => "timed-out",
+            FileStatus::Skipped => "skipped",
+        }
+    }
+}
+
+/// Hexadecimal SHA-256 digests of a file before and after processing. These
+/// are equal if the file was not changed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileDigests {
+    pub input: String,
+    pub output: String,
+}
+
+/// The analysis and gains of a single file processed by `opusgain`. Fields
+/// are `None` if they do not apply or processing failed before they were
+/// determined.
+///
+/// When the `serde` feature is enabled, this serializes with the same fields
+/// as each entry of the `files` array written by `write_file_results_json`.
+#[derive(Clone, Debug)]
+pub struct FileResult {
+    pub path: PathBuf,
+    pub status: FileStatus,
+    pub duration: Option<Duration>,
+    pub track_lufs: Option<Decibels>,
+    pub album_lufs: Option<Decibels>,
+    pub old_gains: Option<OpusGains>,
+    pub new_gains: Option<OpusGains>,
+    /// SHA-256 digests of the file before and after processing, if requested
+    pub digests: Option<FileDigests>,
+    /// A mismatch between the decoded duration and that implied by granule
+    /// positions, which suggests the file is damaged
+    pub duration_issue: Option<AnalysisIssue>,
+}
+
+impl FileResult {
+    /// Constructs a result for a file which failed before anything was
+    /// determined
+    #[must_use]
+    pub fn new(path: PathBuf) -> FileResult {
+        FileResult {
+            path,
+            status: FileStatus::Failed,
+            duration: None,
+            track_lufs: None,
+            album_lufs: None,
+            old_gains: None,
+            new_gains: None,
+            digests: None,
+            duration_issue: None,
+        }
+    }
+
+    /// The digest fields of the result, in column order
+    #[must_use]
+    pub fn digest_fields(&self) -> [(&'static str, Option<&str>); 2] {
+        let digests = self.digests.as_ref();
+        [
+            ("input_sha256", digests.map(|digests| digests.input.as_str())),
+            ("output_sha256", digests.map(|digests| digests.output.as_str())),
+        ]
+    }
+
+    /// The numeric fields of the result, in column order
+    #[must_use]
+    pub fn decibel_fields(&self) -> [(&'static str, Option<Decibels>); 8] {
+        let old = |f: fn(&OpusGains) -> Option<Decibels>| self.old_gains.as_ref().and_then(f);
+        let new = |f: fn(&OpusGains) -> Option<Decibels>| self.new_gains.as_ref().and_then(f);
+        [
+            ("track_lufs", self.track_lufs),
+            ("album_lufs", self.album_lufs),
+            ("old_output_gain_db", old(|g| Some(g.output))),
+            ("new_output_gain_db", new(|g| Some(g.output))),
+            ("old_track_r128_db", old(|g| g.track_r128)),
+            ("new_track_r128_db", new(|g| g.track_r128)),
+            ("old_album_r128_db", old(|g| g.album_r128)),
+            ("new_album_r128_db", new(|g| g.album_r128)),
+        ]
+    }
+
+    /// All fields of the result. Digests are omitted if the file was not
+    /// hashed and the duration implied by granule positions unless it differs
+    /// from the decoded duration.
+    fn fields(&self) -> Vec<(&'static str, Value<'_>)> {
+        let mut fields = vec![
+            ("path", Value::Text(Some(self.path.to_string_lossy()))),
+            ("status", Value::Text(Some(Cow::Borrowed(self.status.as_str())))),
+            ("duration_s", Value::Seconds(self.duration)),
+        ];
+        fields.extend(granule_duration_field("granule_duration_s", self.duration_issue.as_ref()));
+        fields.extend(self.decibel_fields().map(|(name, value)| (name, Value::Decibels(value))));
+        if self.digests.is_some() {
+            fields.extend(self.digest_fields().map(|(name, value)| (name, Value::Text(value.map(Cow::Borrowed)))));
+        }
+        fields
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for FileResult {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_fields(&self.fields(), serializer)
+    }
+}
+
+/// Writes a JSON document containing one object per file
+pub fn write_file_results_json<W: Write>(results: &[FileResult], output: &mut W) -> io::Result<()> {
+    let files: Vec<_> = results.iter().map(|result| json_object(&result.fields())).collect();
+    write_document(output, &[], &[("files", files)])
+}
+
+/// The field holding the duration implied by granule positions, which is only
+/// reported if it differs from the decoded duration
+fn granule_duration_field(name: &'static str, issue: Option<&AnalysisIssue>) -> Option<(&'static str, Value<'static>)> {
+    let AnalysisIssue::DurationMismatch { granule, .. } = issue?;
+    Some((name, Value::Seconds(Some(*granule))))
+}
+
+/// The loudness analysis of a single track
+#[derive(Clone, Debug)]
+pub struct TrackResult {
+    pub path: PathBuf,
+    pub lufs: Decibels,
+    pub duration: Duration,
+    /// A mismatch between the decoded duration and that implied by granule
+    /// positions, which suggests the file is damaged
+    pub duration_issue: Option<AnalysisIssue>,
+}
+
+impl TrackResult {
+    fn fields(&self) -> Vec<(&'static str, Value<'_>)> {
+        let mut fields = vec![
+            ("path", Value::Text(Some(self.path.to_string_lossy()))),
+            ("track_lufs", Value::Decibels(Some(self.lufs))),
+            ("duration_seconds", Value::Seconds(Some(self.duration))),
+        ];
+        fields.extend(granule_duration_field("granule_duration_seconds", self.duration_issue.as_ref()));
+        fields
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for TrackResult {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_fields(&self.fields(), serializer)
+    }
+}
+
+/// The loudness analysis of a set of tracks, optionally analyzed as an album
+///
+/// When the `serde` feature is enabled, this serializes with the same fields
+/// as the document written by `write_json`, other than the schema version.
+#[derive(Clone, Debug)]
+pub struct AnalysisResult {
+    pub tracks: Vec<TrackResult>,
+    pub album_lufs: Option<Decibels>,
+    /// Whether only part of each track was analyzed, so the loudness is an
+    /// approximation
+    pub approximate: bool,
+}
+
+impl AnalysisResult {
+    /// The fields of the result other than the tracks. `approximate` is only
+    /// present if set.
+    fn fields(&self) -> Vec<(&'static str, Value<'_>)> {
+        let mut fields = Vec::new();
+        if self.approximate {
+            fields.push(("approximate", Value::Flag(true)));
+        }
+        fields.push(("album_lufs", Value::Decibels(self.album_lufs)));
+        fields
+    }
+
+    /// Writes the analysis as a JSON document
+    pub fn write_json<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let tracks: Vec<_> = self.tracks.iter().map(|track| json_object(&track.fields())).collect();
+        write_document(output, &self.fields(), &[("tracks", tracks)])
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for AnalysisResult {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap as _;
+
+        let fields = self.fields();
+        let mut map = serializer.serialize_map(Some(fields.len() + 1))?;
+        for (name, value) in &fields {
+            map.serialize_entry(name, value)?;
+        }
+        map.serialize_entry("tracks", &self.tracks)?;
+        map.end()
+    }
+}
+
+/// Writes the encoders which produced a collection of files as a JSON
+/// document, most common first, with the number of files and an example of
+/// each
+pub fn write_vendor_stats_json<W: Write>(stats: &VendorStats, output: &mut W) -> io::Result<()> {
+    let encoders: Vec<_> = stats
+        .sorted()
+        .into_iter()
+        .map(|(encoder, count)| {
+            json_object(&[
+                ("vendor", Value::Text(Some(Cow::Borrowed(&encoder.vendor)))),
+                ("encoder", Value::Text(encoder.encoder.as_deref().map(Cow::Borrowed))),
+                ("count", Value::Count(count.count)),
+                ("example", Value::Text(Some(count.example.to_string_lossy()))),
+            ])
+        })
+        .collect();
+    write_document(output, &[("unreadable", Value::Count(stats.unreadable()))], &[("encoders", encoders)])
+}
+
+/// Writes the comments removed and added by a rewrite as a JSON document
+pub fn write_comment_diff_json<W: Write>(diff: &CommentDiff, output: &mut W) -> io::Result<()> {
+    let list = |comments: &[(String, String)]| -> Vec<String> {
+        comments
+            .iter()
+            .map(|(key, value)| {
+                json_object(&[
+                    ("name", Value::Text(Some(Cow::Borrowed(key)))),
+                    ("value", Value::Text(Some(Cow::Borrowed(value)))),
+                ])
+            })
+            .collect()
+    };
+    let fields = [("unchanged", Value::Count(diff.unchanged))];
+    write_document(output, &fields, &[("added", list(&diff.added)), ("removed", list(&diff.removed))])
+}
+
+/// Writes the sizes of the parts of a comment header as a JSON document.
+/// Comments are listed in order of decreasing size.
+pub fn write_comment_sizes_json<W: Write>(sizes: &CommentHeaderSizes, output: &mut W) -> io::Result<()> {
+    let fields = [
+        ("total", Value::Count(sizes.total)),
+        ("vendor", Value::Count(sizes.vendor)),
+        ("suffix", Value::Count(sizes.suffix)),
+        ("comments_total", Value::Count(sizes.comments_total())),
+    ];
+    let comments: Vec<_> = sizes
+        .comments_by_size()
+        .into_iter()
+        .map(|(key, size)| {
+            json_object(&[("name", Value::Text(Some(Cow::Borrowed(key)))), ("bytes", Value::Count(size))])
+        })
+        .collect();
+    write_document(output, &fields, &[("comments", comments)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_string_escapes() {
+        assert_eq!(json_string("a\\b\u{1}"), "\"a\\\\b\\u0001\"");
+        assert_eq!(json_string("\"quoted\"\n"), "\"\\\"quoted\\\"\\n\"");
+    }
+
+    fn render(result: &AnalysisResult) -> String {
+        let mut output = Vec::new();
+        result.write_json(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn schema_version_is_first() {
+        let result = AnalysisResult { tracks: Vec::new(), album_lufs: None, approximate: true };
+        assert_eq!(
+            render(&result),
+            "{\n  \"schema_version\": 1,\n  \"approximate\": true,\n  \"album_lufs\": null,\n  \"tracks\": []\n}\n"
+        );
+    }
+
+    #[test]
+    fn non_finite_decibels_are_null() {
+        let result = AnalysisResult {
+            tracks: Vec::new(),
+            album_lufs: Some(Decibels::from(f64::NEG_INFINITY)),
+            approximate: false,
+        };
+        assert!(render(&result).contains("\"album_lufs\": null"));
+    }
+
+    #[test]
+    fn granule_duration_is_only_reported_for_mismatches() {
+        let track = TrackResult {
+            path: PathBuf::from("a.opus"),
+            lufs: Decibels::from(-18.5),
+            duration: Duration::from_millis(1500),
+            duration_issue: None,
+        };
+        let damaged = TrackResult {
+            duration_issue: Some(AnalysisIssue::DurationMismatch {
+                granule: Duration::from_millis(4000),
+                decoded: track.duration,
+            }),
+            ..track.clone()
+        };
+        let result = AnalysisResult { tracks: vec![track], album_lufs: None, approximate: false };
+        assert!(!render(&result).contains("granule_duration_seconds"));
+        let result = AnalysisResult { tracks: vec![damaged], album_lufs: None, approximate: false };
+        assert!(render(&result).contains("\"duration_seconds\": 1.500, \"granule_duration_seconds\": 4.000}"));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_matches_json_output() -> Result<(), serde_json::Error> {
+        let result = AnalysisResult {
+            tracks: vec![TrackResult {
+                path: PathBuf::from("a.opus"),
+                lufs: Decibels::from(-18.5),
+                duration: Duration::from_millis(1500),
+                duration_issue: Some(AnalysisIssue::DurationMismatch {
+                    granule: Duration::from_millis(4000),
+                    decoded: Duration::from_millis(1500),
+                }),
+            }],
+            album_lufs: Some(Decibels::from(-19.25)),
+            approximate: false,
+        };
+        let mut written: serde_json::Value = serde_json::from_str(&render(&result))?;
+        if let Some(object) = written.as_object_mut() {
+            object.remove(SCHEMA_VERSION_FIELD);
+        }
+        assert_eq!(serde_json::to_value(&result)?, written);
+        Ok(())
+    }
+}
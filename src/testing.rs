@@ -0,0 +1,412 @@
+//! Generation of Ogg Opus streams for tests and examples. This module is only
+//! available with the `testing` feature.
+//!
+//! Audio is encoded with the `opus` crate and framed using the `ogg` writer,
+//! and the comment header is serialized independently of `zoog` so that
+//! round-trip tests compare against bytes `zoog` did not produce. Streams can
+//! optionally be generated with anomalies such as corrupt pages to test error
+//! handling:
+//!
+//! ```
+//! use zoog::testing::{opus_file, read_packets, Anomaly, StreamSpec};
+//!
+//! let first = StreamSpec::default().with_tag("TITLE", "First");
+//! let second = StreamSpec { serial: 2, channels: 1, ..StreamSpec::default() };
+//! let data = opus_file(&[first, second.with_anomaly(Anomaly::DuplicateTags)]);
+//! let packets = read_packets(&data);
+//! assert!(packets[1].data.starts_with(b"OpusTags"));
+//! assert_eq!(packets.last().unwrap().stream_serial(), 2);
+//! ```
+//!
+//! Since these are intended for tests, all functions panic rather than
+//! returning errors.
+#![allow(clippy::missing_panics_doc)]
+
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use ogg::{Packet, PacketReader};
+use opus::{Application, Channels, Encoder};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+use crate::header_rewriter::{read_raw_headers, CodecHeaders};
+
+/// The sample rate used for encoding and for granule positions
+pub const SAMPLE_RATE: usize = 48000;
+
+/// The number of samples per channel in each Opus packet (20ms)
+pub const FRAME_SIZE: usize = 960;
+
+/// The number of packets placed on each Ogg page following the headers
+const PACKETS_PER_PAGE: usize = 10;
+
+const MAX_PACKET_SIZE: usize = 4000;
+
+/// Offset of the CRC within an Ogg page header
+const PAGE_CHECKSUM_OFFSET: usize = 22;
+
+/// Length of an Ogg page header excluding its segment table
+const PAGE_HEADER_LENGTH: usize = 27;
+
+/// The audio encoded into a generated stream
+#[derive(Clone, Copy, Debug)]
+pub enum Signal {
+    /// A sine wave of the specified frequency in Hz and peak amplitude, with
+    /// identical samples in every channel
+    Sine { frequency: f32, amplitude: f32 },
+
+    /// Uniformly distributed white noise with the specified peak amplitude,
+    /// generated independently for each channel from a seeded generator
+    Noise { amplitude: f32, seed: u64 },
+
+    /// Single-sample impulses of the specified amplitude every `period`
+    /// samples in every channel, which have a far higher sample peak than
+    /// their loudness suggests
+    Impulses { amplitude: f32, period: usize },
+
+    /// Digital silence
+    Silence,
+}
+
+impl Signal {
+    /// Generates `num_samples` interleaved samples for `channels` channels
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    fn generate(&self, channels: usize, num_samples: usize) -> Vec<f32> {
+        match *self {
+            Signal::Sine { frequency, amplitude } => (0..num_samples)
+                .flat_map(|idx| {
+                    let t = idx as f64 / SAMPLE_RATE as f64;
+                    let value = f64::from(amplitude) * (2.0 * std::f64::consts::PI * f64::from(frequency) * t).sin();
+                    std::iter::repeat(value as f32).take(channels)
+                })
+                .collect(),
+            Signal::Noise { amplitude, seed } => {
+                let mut rng = SmallRng::seed_from_u64(seed);
+                (0..num_samples * channels).map(|_| rng.gen_range(-amplitude..=amplitude)).collect()
+            }
+            Signal::Impulses { amplitude, period } => (0..num_samples)
+                .flat_map(|idx| std::iter::repeat(if idx % period == 0 { amplitude } else { 0.0 }).take(channels))
+                .collect(),
+            Signal::Silence => vec![0.0; num_samples * channels],
+        }
+    }
+}
+
+/// A defect deliberately introduced into a generated stream
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Anomaly {
+    /// The checksum of the page with the specified index within the stream is
+    /// corrupted. Page 0 contains the identification header and page 1 the
+    /// comment header.
+    BadChecksum { page: usize },
+
+    /// The comment header packet is truncated to the specified length
+    TruncatedCommentHeader { length: usize },
+
+    /// Every comment is written twice
+    DuplicateTags,
+
+    /// The comment header packet is written twice, each on its own page
+    DuplicateCommentHeader,
+
+    /// The specified data follows the comments in the comment header. RFC 7845
+    /// requires it to be preserved if the first byte has its LSB set.
+    ExperimentalSuffix(Vec<u8>),
+
+    /// The identification header records the specified input sample rate
+    /// rather than 48 kHz
+    InputSampleRate(u32),
+
+    /// The specified number of pages starting at the page with index `first`
+    /// within the stream are left out, as in a damaged download. Page 2 is the
+    /// first page of audio.
+    MissingPages { first: usize, count: usize },
+}
+
+/// Describes an Ogg Opus logical stream to generate. The default is two
+/// seconds of a stereo sine wave.
+#[derive(Clone, Debug)]
+pub struct StreamSpec {
+    /// The serial number of the logical stream
+    pub serial: u32,
+
+    /// The channel count, which must be 1 or 2
+    pub channels: u8,
+
+    /// The number of samples per channel of audio, excluding pre-skip. A
+    /// stream without samples consists of only its headers.
+    pub num_samples: usize,
+
+    /// The audio to encode
+    pub signal: Signal,
+
+    /// The output gain in Q7.8 format
+    pub output_gain: i16,
+
+    /// The vendor string of the comment header
+    pub vendor: String,
+
+    /// The comments of the comment header as field name and value pairs
+    pub tags: Vec<(String, String)>,
+
+    /// Defects to introduce into the stream
+    pub anomalies: Vec<Anomaly>,
+}
+
+impl Default for StreamSpec {
+    fn default() -> StreamSpec {
+        StreamSpec {
+            serial: 1,
+            channels: 2,
+            num_samples: 2 * SAMPLE_RATE,
+            signal: Signal::Sine { frequency: 997.0, amplitude: 0.25 },
+            output_gain: 0,
+            vendor: String::from("zoog-fixture"),
+            tags: Vec::new(),
+            anomalies: Vec::new(),
+        }
+    }
+}
+
+impl StreamSpec {
+    /// Returns the specification with the specified tag appended
+    #[must_use]
+    pub fn with_tag(mut self, field: &str, value: &str) -> StreamSpec {
+        self.tags.push((field.into(), value.into()));
+        self
+    }
+
+    /// Returns the specification with the specified anomaly introduced
+    #[must_use]
+    pub fn with_anomaly(mut self, anomaly: Anomaly) -> StreamSpec {
+        self.anomalies.push(anomaly);
+        self
+    }
+
+    fn has_anomaly(&self, anomaly: &Anomaly) -> bool { self.anomalies.contains(anomaly) }
+}
+
+/// Builds an Opus identification header with the specified fields
+#[must_use]
+pub fn opus_id_header(channels: u8, input_sample_rate: u32, preskip: u16, output_gain: i16) -> Vec<u8> {
+    let mut header = b"OpusHead".to_vec();
+    header.push(1);
+    header.push(channels);
+    header.write_u16::<LittleEndian>(preskip).unwrap();
+    header.write_u32::<LittleEndian>(input_sample_rate).unwrap();
+    header.write_i16::<LittleEndian>(output_gain).unwrap();
+    // Mapping family 0 has no channel mapping table
+    header.push(0);
+    header
+}
+
+/// Builds an Opus comment header as specified by RFC 7845 without using
+/// `zoog`'s own serialization
+#[must_use]
+pub fn comment_header(vendor: &str, tags: &[(String, String)], suffix: &[u8]) -> Vec<u8> {
+    fn write_string(output: &mut Vec<u8>, value: &[u8]) {
+        output.write_u32::<LittleEndian>(u32::try_from(value.len()).unwrap()).unwrap();
+        output.extend_from_slice(value);
+    }
+
+    let mut header = b"OpusTags".to_vec();
+    write_string(&mut header, vendor.as_bytes());
+    header.write_u32::<LittleEndian>(u32::try_from(tags.len()).unwrap()).unwrap();
+    for (field, value) in tags {
+        write_string(&mut header, format!("{}={}", field, value).as_bytes());
+    }
+    header.extend_from_slice(suffix);
+    header
+}
+
+/// Encodes the stream described by `spec` as a complete Ogg physical bitstream
+#[must_use]
+pub fn opus_stream(spec: &StreamSpec) -> Vec<u8> {
+    let channels = match spec.channels {
+        1 => Channels::Mono,
+        2 => Channels::Stereo,
+        n => panic!("Unsupported channel count for test stream: {}", n),
+    };
+    let channel_count = usize::from(spec.channels);
+    let mut encoder = Encoder::new(48000, channels, Application::Audio).unwrap();
+    let preskip = usize::try_from(encoder.get_lookahead().unwrap()).unwrap();
+
+    // The decoder output is delayed by the pre-skip so enough frames must be
+    // encoded to cover it in addition to the audio itself
+    let total_samples = preskip + spec.num_samples;
+    let num_frames = if spec.num_samples == 0 { 0 } else { total_samples.div_ceil(FRAME_SIZE) };
+    let mut pcm = spec.signal.generate(channel_count, spec.num_samples);
+    pcm.resize(num_frames * FRAME_SIZE * channel_count, 0.0);
+
+    let mut data = Vec::new();
+    let mut writer = PacketWriter::new(&mut data);
+    let mut write = |packet: Vec<u8>, info, granule| writer.write_packet(packet, spec.serial, info, granule).unwrap();
+
+    let input_sample_rate = spec
+        .anomalies
+        .iter()
+        .find_map(|anomaly| match anomaly {
+            Anomaly::InputSampleRate(rate) => Some(*rate),
+            _ => None,
+        })
+        .unwrap_or(48000);
+    let preskip_field = u16::try_from(preskip).unwrap();
+    write(
+        opus_id_header(spec.channels, input_sample_rate, preskip_field, spec.output_gain),
+        PacketWriteEndInfo::EndPage,
+        0,
+    );
+    let tags: Vec<_> = if spec.has_anomaly(&Anomaly::DuplicateTags) {
+        spec.tags.iter().flat_map(|tag| [tag.clone(), tag.clone()]).collect()
+    } else {
+        spec.tags.clone()
+    };
+    let suffix = spec
+        .anomalies
+        .iter()
+        .find_map(|anomaly| match anomaly {
+            Anomaly::ExperimentalSuffix(suffix) => Some(suffix.as_slice()),
+            _ => None,
+        })
+        .unwrap_or_default();
+    let mut comments = comment_header(&spec.vendor, &tags, suffix);
+    for anomaly in &spec.anomalies {
+        if let Anomaly::TruncatedCommentHeader { length } = anomaly {
+            comments.truncate(*length);
+        }
+    }
+    let comment_info = if num_frames == 0 { PacketWriteEndInfo::EndStream } else { PacketWriteEndInfo::EndPage };
+    if spec.has_anomaly(&Anomaly::DuplicateCommentHeader) {
+        write(comments.clone(), PacketWriteEndInfo::EndPage, 0);
+    }
+    write(comments, comment_info, 0);
+
+    let mut output = vec![0u8; MAX_PACKET_SIZE];
+    for (frame_idx, frame) in pcm.chunks(FRAME_SIZE * channel_count).enumerate() {
+        let len = encoder.encode_float(frame, &mut output).unwrap();
+        let is_last = frame_idx + 1 == num_frames;
+        let info = if is_last {
+            PacketWriteEndInfo::EndStream
+        } else if (frame_idx + 1) % PACKETS_PER_PAGE == 0 {
+            PacketWriteEndInfo::EndPage
+        } else {
+            PacketWriteEndInfo::NormalPacket
+        };
+        // The granule position of the final page marks the end of the audio so
+        // that the padding of the last frame is trimmed
+        let granule = std::cmp::min((frame_idx + 1) * FRAME_SIZE, total_samples);
+        write(output[..len].to_vec(), info, u64::try_from(granule).unwrap());
+    }
+    drop(writer);
+
+    let offsets = page_offsets(&data);
+    for anomaly in &spec.anomalies {
+        if let Anomaly::BadChecksum { page } = anomaly {
+            let offset = offsets.get(*page).expect("Page index of corrupted checksum out of range");
+            data[offset + PAGE_CHECKSUM_OFFSET] ^= 0xFF;
+        }
+    }
+    for anomaly in &spec.anomalies {
+        if let Anomaly::MissingPages { first, count } = *anomaly {
+            let start = *offsets.get(first).expect("Index of missing page out of range");
+            let end = offsets.get(first + count).copied().unwrap_or(data.len());
+            data.drain(start..end);
+        }
+    }
+    data
+}
+
+/// Generates an Ogg file containing the specified streams chained one after
+/// another
+#[must_use]
+pub fn opus_file(specs: &[StreamSpec]) -> Vec<u8> { specs.iter().flat_map(opus_stream).collect() }
+
+/// Generates an Ogg file containing the specified streams grouped rather than
+/// chained, so that all beginning-of-stream pages come first and the packets
+/// of the streams are interleaved
+#[must_use]
+pub fn grouped_opus_file(specs: &[StreamSpec]) -> Vec<u8> {
+    let streams: Vec<_> = specs.iter().map(|spec| read_packets(&opus_stream(spec))).collect();
+    let longest = streams.iter().map(Vec::len).max().unwrap_or(0);
+    let streams = &streams;
+    write_packets((0..longest).flat_map(|idx| streams.iter().filter_map(move |packets| packets.get(idx))))
+}
+
+/// Writes an Ogg file containing the specified streams to `name` within `dir`
+#[must_use]
+pub fn write_opus_file(dir: &Path, name: &str, specs: &[StreamSpec]) -> PathBuf {
+    let path = dir.join(name);
+    let mut file = std::fs::File::create(&path).unwrap();
+    file.write_all(&opus_file(specs)).unwrap();
+    path
+}
+
+/// Finds the byte offset of each page in an Ogg file
+#[must_use]
+pub fn page_offsets(data: &[u8]) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        assert_eq!(&data[offset..offset + 4], b"OggS", "Missing capture pattern at offset {}", offset);
+        let num_segments = usize::from(data[offset + PAGE_HEADER_LENGTH - 1]);
+        let segment_table = &data[offset + PAGE_HEADER_LENGTH..offset + PAGE_HEADER_LENGTH + num_segments];
+        let body_length: usize = segment_table.iter().copied().map(usize::from).sum();
+        offsets.push(offset);
+        offset += PAGE_HEADER_LENGTH + num_segments + body_length;
+    }
+    offsets
+}
+
+/// Reads all packets from an Ogg file
+#[must_use]
+pub fn read_packets(data: &[u8]) -> Vec<Packet> {
+    let mut reader = PacketReader::new(Cursor::new(data));
+    let mut packets = Vec::new();
+    while let Some(packet) = reader.read_packet().unwrap() {
+        packets.push(packet);
+    }
+    packets
+}
+
+/// Writes `packets` as an Ogg file, keeping the page boundaries, granule
+/// positions and serial numbers with which they were read
+#[must_use]
+pub fn write_packets<'a, I: IntoIterator<Item = &'a Packet>>(packets: I) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut writer = PacketWriter::new(&mut data);
+    for packet in packets {
+        let info = if packet.last_in_stream() {
+            PacketWriteEndInfo::EndStream
+        } else if packet.last_in_page() {
+            PacketWriteEndInfo::EndPage
+        } else {
+            PacketWriteEndInfo::NormalPacket
+        };
+        let (serial, granule) = (packet.stream_serial(), packet.absgp_page());
+        writer.write_packet(packet.data.clone(), serial, info, granule).unwrap();
+    }
+    drop(writer);
+    data
+}
+
+/// Rewrites an Ogg file so that every packet ends a page
+#[must_use]
+pub fn one_packet_per_page(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut writer = PacketWriter::new(&mut output);
+    for packet in read_packets(data) {
+        let info = if packet.last_in_stream() { PacketWriteEndInfo::EndStream } else { PacketWriteEndInfo::EndPage };
+        let granule = packet.absgp_page();
+        let serial = packet.stream_serial();
+        writer.write_packet(packet.data, serial, info, granule).unwrap();
+    }
+    drop(writer);
+    output
+}
+
+/// Parses the headers of the first stream in an Ogg file
+#[must_use]
+pub fn read_headers(data: &[u8]) -> CodecHeaders { read_raw_headers(Cursor::new(data)).unwrap().parse().unwrap() }
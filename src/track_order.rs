@@ -0,0 +1,127 @@
+use std::cmp::Ordering;
+use std::path::Path;
+
+use crate::header::CommentList;
+
+/// The tag holding the position of a track on its disc
+pub const TAG_TRACK_NUMBER: &str = "TRACKNUMBER";
+
+/// The tag holding the number of the disc a track belongs to
+pub const TAG_DISC_NUMBER: &str = "DISCNUMBER";
+
+/// Parses a track or disc number tag value. Leading zeros and a trailing total
+/// such as the `/12` of `3/12` are accepted, as is surrounding whitespace.
+/// `None` is returned for anything else.
+#[must_use]
+pub fn parse_position_number(value: &str) -> Option<u32> {
+    let number = value.split_once('/').map_or(value, |(number, _)| number).trim();
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    number.parse().ok()
+}
+
+/// The position of a track within a release, ordered by disc then track
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TrackPosition {
+    /// The disc number, which is 1 if not tagged
+    pub disc: u32,
+
+    /// The track number
+    pub track: u32,
+}
+
+impl TrackPosition {
+    /// Reads the position from the `TRACKNUMBER` and `DISCNUMBER` tags.
+    /// Returns `None` if the track number is missing or cannot be parsed.
+    #[must_use]
+    pub fn from_comments<L: CommentList>(comments: &L) -> Option<TrackPosition> {
+        let track = comments.get_first(TAG_TRACK_NUMBER).and_then(parse_position_number)?;
+        let disc = comments.get_first(TAG_DISC_NUMBER).and_then(parse_position_number).unwrap_or(1);
+        Some(TrackPosition { disc, track })
+    }
+}
+
+/// Compares two files for musical order. Files with a position come first,
+/// ordered by disc then track, followed by files without one in path order.
+#[must_use]
+pub fn compare_track_order(a: (Option<TrackPosition>, &Path), b: (Option<TrackPosition>, &Path)) -> Ordering {
+    match (a, b) {
+        ((Some(a), _), (Some(b), _)) => a.cmp(&b),
+        ((Some(_), _), (None, _)) => Ordering::Less,
+        ((None, _), (Some(_), _)) => Ordering::Greater,
+        ((None, a), (None, b)) => a.cmp(b),
+    }
+}
+
+/// Sorts `files` into musical order as defined by `compare_track_order`,
+/// where `position` returns the position of each file. The sort is stable, so
+/// files with the same position keep their relative order.
+#[must_use]
+pub fn sort_by_track<P, F>(files: Vec<P>, mut position: F) -> Vec<P>
+where
+    P: AsRef<Path>,
+    F: FnMut(&P) -> Option<TrackPosition>,
+{
+    let mut keyed: Vec<_> = files.into_iter().map(|file| (position(&file), file)).collect();
+    keyed.sort_by(|(a, a_file), (b, b_file)| compare_track_order((*a, a_file.as_ref()), (*b, b_file.as_ref())));
+    keyed.into_iter().map(|(_, file)| file).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::header::DiscreteCommentList;
+    use crate::Error;
+
+    #[test]
+    fn position_numbers_are_parsed_tolerantly() {
+        assert_eq!(parse_position_number("3"), Some(3));
+        assert_eq!(parse_position_number("03"), Some(3));
+        assert_eq!(parse_position_number("3/12"), Some(3));
+        assert_eq!(parse_position_number("003/012"), Some(3));
+        assert_eq!(parse_position_number(" 7 / 9 "), Some(7));
+        assert_eq!(parse_position_number("0"), Some(0));
+    }
+
+    #[test]
+    fn garbage_position_numbers_are_rejected() {
+        for value in ["", " ", "/12", "A", "3a", "-3", "+3", "three", "3.0", "99999999999"] {
+            assert_eq!(parse_position_number(value), None, "{:?} should not parse", value);
+        }
+    }
+
+    #[test]
+    fn positions_are_read_from_comments() -> Result<(), Error> {
+        let mut comments = DiscreteCommentList::default();
+        assert_eq!(TrackPosition::from_comments(&comments), None);
+        comments.push("DISCNUMBER", "2/2")?;
+        assert_eq!(TrackPosition::from_comments(&comments), None);
+        comments.push("tracknumber", "05")?;
+        assert_eq!(TrackPosition::from_comments(&comments), Some(TrackPosition { disc: 2, track: 5 }));
+        comments.replace("DISCNUMBER", "side A")?;
+        assert_eq!(TrackPosition::from_comments(&comments), Some(TrackPosition { disc: 1, track: 5 }));
+        Ok(())
+    }
+
+    #[test]
+    fn files_are_sorted_by_disc_then_track() {
+        let position = |disc, track| Some(TrackPosition { disc, track });
+        let tags = [
+            ("z.opus", None),
+            ("d2t1.opus", position(2, 1)),
+            ("d1t10.opus", position(1, 10)),
+            ("a.opus", None),
+            ("d1t2.opus", position(1, 2)),
+            ("first.opus", position(1, 3)),
+            ("second.opus", position(1, 3)),
+        ];
+        let files: Vec<_> = tags.iter().map(|(name, _)| PathBuf::from(name)).collect();
+        let files =
+            sort_by_track(files, |path| tags.iter().find(|(name, _)| path == Path::new(name)).and_then(|(_, p)| *p));
+        let expected = ["d1t2.opus", "first.opus", "second.opus", "d1t10.opus", "d2t1.opus", "a.opus", "z.opus"];
+        assert_eq!(files, expected.map(PathBuf::from));
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::header;
+use crate::header_rewriter::{CodecHeaders, HeaderSummarize, HeaderSummarizeGeneric};
+use crate::Error;
+
+/// The comment key conventionally naming the software that encoded a file
+pub const TAG_ENCODER: &str = "ENCODER";
+
+/// The software recorded as having produced a file
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Encoder {
+    /// The vendor string of the comment header, with surrounding whitespace
+    /// removed
+    pub vendor: String,
+
+    /// The first `ENCODER` comment, with surrounding whitespace removed, if
+    /// present
+    pub encoder: Option<String>,
+}
+
+impl Encoder {
+    /// Constructs an `Encoder`, removing surrounding whitespace from each
+    /// field so that otherwise identical values are grouped together
+    #[must_use]
+    pub fn new(vendor: &str, encoder: Option<&str>) -> Encoder {
+        Encoder { vendor: vendor.trim().to_string(), encoder: encoder.map(|encoder| encoder.trim().to_string()) }
+    }
+}
+
+/// Summarizes headers as the software which encoded the stream
+#[derive(Debug, Default)]
+pub struct EncoderSummary {}
+
+impl HeaderSummarizeGeneric for EncoderSummary {
+    type Error = Error;
+    type Summary = Encoder;
+
+    fn summarize<I, C>(&self, _id_header: &I, comment_header: &C) -> Result<Encoder, Error>
+    where
+        I: header::IdHeader,
+        C: header::CommentHeader,
+    {
+        Ok(Encoder::new(comment_header.get_vendor(), comment_header.get_first(TAG_ENCODER)))
+    }
+}
+
+impl HeaderSummarize for EncoderSummary {
+    type Error = Error;
+    type Summary = Encoder;
+
+    fn summarize(&self, headers: &CodecHeaders) -> Result<Encoder, Error> { self.summarize_headers(headers) }
+}
+
+/// The files produced by a single encoder
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncoderCount {
+    /// The number of files
+    pub count: usize,
+
+    /// The earliest file recorded, as ordered by the index it was recorded
+    /// with
+    pub example: PathBuf,
+
+    example_index: usize,
+}
+
+/// Counts of the encoders which produced a collection of files.
+///
+/// Only one example path is kept for each distinct encoder so memory use is
+/// independent of the number of files. Statistics gathered in parallel can be
+/// combined with `merge`.
+#[derive(Clone, Debug, Default)]
+pub struct VendorStats {
+    counts: HashMap<Encoder, EncoderCount>,
+    unreadable: usize,
+}
+
+impl VendorStats {
+    /// Records the encoder of the file at `path`. `index` orders files when
+    /// choosing an example, so that the example does not depend on the order
+    /// in which statistics were merged.
+    pub fn record(&mut self, encoder: Encoder, index: usize, path: &Path) {
+        self.counts
+            .entry(encoder)
+            .and_modify(|entry| {
+                entry.count += 1;
+                if index < entry.example_index {
+                    entry.example = path.to_path_buf();
+                    entry.example_index = index;
+                }
+            })
+            .or_insert_with(|| EncoderCount { count: 1, example: path.to_path_buf(), example_index: index });
+    }
+
+    /// Records a file whose headers could not be read
+    pub fn record_unreadable(&mut self) { self.unreadable += 1; }
+
+    /// Combines the statistics of two sets of files
+    #[must_use]
+    pub fn merge(mut self, other: VendorStats) -> VendorStats {
+        for (encoder, other_count) in other.counts {
+            match self.counts.get_mut(&encoder) {
+                Some(count) => {
+                    count.count += other_count.count;
+                    if other_count.example_index < count.example_index {
+                        count.example = other_count.example;
+                        count.example_index = other_count.example_index;
+                    }
+                }
+                None => {
+                    self.counts.insert(encoder, other_count);
+                }
+            }
+        }
+        self.unreadable += other.unreadable;
+        self
+    }
+
+    /// The number of files whose headers could not be read
+    #[must_use]
+    pub fn unreadable(&self) -> usize { self.unreadable }
+
+    /// The number of files whose encoder was recorded
+    #[must_use]
+    pub fn num_files(&self) -> usize { self.counts.values().map(|count| count.count).sum() }
+
+    /// Returns each distinct encoder, most common first. Encoders with equal
+    /// counts are ordered by vendor string then `ENCODER` comment.
+    #[must_use]
+    pub fn sorted(&self) -> Vec<(&Encoder, &EncoderCount)> {
+        let mut result: Vec<_> = self.counts.iter().collect();
+        result.sort_by(|(lhs_encoder, lhs_count), (rhs_encoder, rhs_count)| {
+            rhs_count.count.cmp(&lhs_count.count).then_with(|| lhs_encoder.cmp(rhs_encoder))
+        });
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Entry = (String, Option<String>, usize, String);
+
+    fn summary(stats: &VendorStats) -> Vec<Entry> {
+        stats
+            .sorted()
+            .into_iter()
+            .map(|(encoder, count)| {
+                let example = count.example.to_string_lossy().into_owned();
+                (encoder.vendor.clone(), encoder.encoder.clone(), count.count, example)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn whitespace_is_trimmed() {
+        let encoder = Encoder::new("  libopus 1.3.1\n", Some("\topusenc "));
+        assert_eq!(encoder, Encoder { vendor: "libopus 1.3.1".into(), encoder: Some("opusenc".into()) });
+    }
+
+    #[test]
+    fn most_common_first() {
+        let mut stats = VendorStats::default();
+        let files = [("b", None), ("a", Some("x")), ("b", None), ("a", None), ("c", None), ("c", None)];
+        for (index, (vendor, encoder)) in files.iter().enumerate() {
+            stats.record(Encoder::new(vendor, *encoder), index, Path::new(&format!("{}.opus", index)));
+        }
+        stats.record_unreadable();
+        let expected: [Entry; 4] = [
+            ("b".into(), None, 2, "0.opus".into()),
+            ("c".into(), None, 2, "4.opus".into()),
+            ("a".into(), None, 1, "3.opus".into()),
+            ("a".into(), Some("x".into()), 1, "1.opus".into()),
+        ];
+        assert_eq!(summary(&stats), expected);
+        assert_eq!(stats.num_files(), 6);
+        assert_eq!(stats.unreadable(), 1);
+    }
+
+    #[test]
+    fn merge_keeps_earliest_example() {
+        let encoder = || Encoder::new("libopus", None);
+        let mut first = VendorStats::default();
+        first.record(encoder(), 3, Path::new("late.opus"));
+        let mut second = VendorStats::default();
+        second.record(encoder(), 1, Path::new("early.opus"));
+        second.record(Encoder::new("other", None), 2, Path::new("other.opus"));
+        second.record_unreadable();
+
+        let expected: [Entry; 2] =
+            [("libopus".into(), None, 2, "early.opus".into()), ("other".into(), None, 1, "other.opus".into())];
+        assert_eq!(summary(&first.clone().merge(second.clone())), expected);
+        assert_eq!(summary(&second.merge(first)), expected);
+    }
+}
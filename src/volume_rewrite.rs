@@ -1,10 +1,50 @@
 use std::convert::{Into, TryFrom};
+use std::fmt::{self, Display, Formatter};
 
+use crate::header::replaygain::{adjust_foreign_tags, FOREIGN_GAIN_TAGS};
 use crate::header::{CommentList, FixedPointGain};
-use crate::header_rewriter::{CodecHeaders, HeaderRewrite, HeaderSummarize};
-use crate::opus::{TAG_ALBUM_GAIN, TAG_TRACK_GAIN};
+use crate::header_issue::HeaderIssue;
+use crate::header_rewriter::{CodecHeaders, DuplicateHeaderAction, HeaderRewrite, HeaderSummarize};
+use crate::loudness_class::LoudnessClassifier;
+use crate::opus::{
+    CommentHeader as OpusCommentHeader, IdHeader as OpusIdHeader, TAG_ALBUM_GAIN, TAG_REFERENCE_LOUDNESS, TAG_TRACK_GAIN,
+};
 use crate::{Decibels, Error, R128_LUFS};
 
+/// The tolerance within which gains are treated as already correct when no
+/// other tolerance is specified, for example by `opusgain --check-tags`
+///
+/// To decide whether a proposed gain would change an existing one by more than
+/// the tolerance:
+///
+/// ```
+/// use zoog::volume_rewrite::{within_tolerance, DEFAULT_TOLERANCE};
+/// use zoog::Decibels;
+///
+/// let existing = Decibels::from(-5.0);
+/// assert!(within_tolerance(existing, Decibels::from(-5.05), DEFAULT_TOLERANCE));
+/// assert!(!within_tolerance(existing, Decibels::from(-5.5), DEFAULT_TOLERANCE));
+/// ```
+///
+/// `OpusGains::approx_eq` applies the same test to all the gains of a stream:
+///
+/// ```
+/// use zoog::volume_rewrite::{OpusGains, DEFAULT_TOLERANCE};
+/// use zoog::Decibels;
+///
+/// let existing = OpusGains { output: Decibels::from(2.0), track_r128: Some(Decibels::from(-5.0)), album_r128: None };
+/// let proposed = OpusGains { output: Decibels::from(2.04), ..existing };
+/// assert!(existing.approx_eq(&proposed, DEFAULT_TOLERANCE));
+/// ```
+pub const DEFAULT_TOLERANCE: Decibels = Decibels::new(0.1);
+
+/// Returns `true` if `a` and `b` differ by no more than `tolerance`. Any NaN
+/// value causes the comparison to fail.
+#[must_use]
+pub fn within_tolerance(a: Decibels, b: Decibels, tolerance: Decibels) -> bool {
+    (a - b).as_f64().abs() <= tolerance.as_f64()
+}
+
 /// Represents a target gain for an audio stream
 #[derive(Clone, Copy, Debug)]
 pub enum VolumeTarget {
@@ -26,7 +66,8 @@ pub enum OutputGainMode {
     Track,
 }
 
-/// Configuration type for `VolumeRewriter`
+/// Configuration type for `VolumeRewriter`. Prefer constructing it with
+/// `VolumeRewriterConfig::builder`, which rejects inconsistent settings.
 #[derive(Clone, Copy, Debug)]
 pub struct VolumeRewriterConfig {
     /// The target output gain
@@ -40,9 +81,33 @@ pub struct VolumeRewriterConfig {
 
     /// The pre-computed volume of the album the track belongs to (if available)
     pub album_volume: Option<Decibels>,
+
+    /// The reference volume the R128 tag gains normalize to. RFC 7845 requires
+    /// this to be `R128_LUFS` so other values will produce non-conforming
+    /// files.
+    pub r128_reference: Decibels,
+
+    /// Whether an existing R128 album gain is kept when `album_volume` is
+    /// `None`, such as when one track of an album is normalized on its own. It
+    /// is adjusted for any change to the output gain so that album playback is
+    /// unaffected. Otherwise the album gain is removed.
+    pub preserve_album_gain: bool,
+
+    /// The largest output gain that may be written, if limited, such as by
+    /// `limit_output_gain_to_peak`. An existing output gain above this is not
+    /// kept even when it is within half a fixed-point step of the computed
+    /// output gain.
+    pub max_output_gain: Option<Decibels>,
 }
 
 impl VolumeRewriterConfig {
+    /// Returns a builder for a configuration with a zero gain target, output
+    /// gain targeting track volume, no known volumes, an R128 reference of
+    /// `R128_LUFS`, no preservation of album gains and no limit on the output
+    /// gain
+    #[must_use]
+    pub fn builder() -> VolumeRewriterConfigBuilder { VolumeRewriterConfigBuilder::default() }
+
     /// Computes the source volume that will be used for the output gain
     /// calculation
     #[must_use]
@@ -52,6 +117,159 @@ impl VolumeRewriterConfig {
             OutputGainMode::Track => self.track_volume,
         }
     }
+
+    /// The volume the output gain is computed from, failing if it is unknown
+    fn required_volume(&self) -> Result<Decibels, ConfigError> {
+        self.volume_for_output_gain_calculation().ok_or(match self.output_gain_mode {
+            OutputGainMode::Album => ConfigError::MissingAlbumVolume,
+            OutputGainMode::Track => ConfigError::MissingTrackVolume,
+        })
+    }
+}
+
+/// An inconsistency in the settings of a `VolumeRewriterConfig`
+#[derive(Clone, Copy, Debug, PartialEq, thiserror::Error)]
+pub enum ConfigError {
+    /// A loudness target with output gain targeting track volume requires the
+    /// track volume
+    #[error("The output gain targets track volume but the track volume is unknown")]
+    MissingTrackVolume,
+
+    /// A loudness target with output gain targeting album volume requires the
+    /// album volume
+    #[error("The output gain targets album volume but the album volume is unknown")]
+    MissingAlbumVolume,
+
+    /// The target loudness is infinite or NaN
+    #[error("The target loudness `{0}` is not finite")]
+    NonFiniteTarget(Decibels),
+
+    /// The R128 reference volume is infinite or NaN
+    #[error("The R128 reference `{0}` is not finite")]
+    NonFiniteReference(Decibels),
+
+    /// Volumes were supplied although they are to be inferred from tags
+    #[error("Volumes cannot be supplied when they are inferred from existing tags")]
+    VolumesFromTagsConflict,
+}
+
+/// Builds a `VolumeRewriterConfig`, rejecting inconsistent settings when
+/// `build` is called rather than when a stream is rewritten.
+///
+/// ```
+/// use zoog::volume_rewrite::{ConfigError, OutputGainMode, VolumeRewriterConfig, VolumeTarget};
+/// use zoog::{Decibels, REPLAY_GAIN_LUFS};
+///
+/// let config = VolumeRewriterConfig::builder()
+///     .target(VolumeTarget::LUFS(REPLAY_GAIN_LUFS))
+///     .track_volume(Some(Decibels::from(-13.0)))
+///     .build()?;
+/// assert_eq!(config.volume_for_output_gain_calculation(), Some(Decibels::from(-13.0)));
+///
+/// // The album volume is needed for the output gain to target it
+/// let result = VolumeRewriterConfig::builder()
+///     .target(VolumeTarget::LUFS(REPLAY_GAIN_LUFS))
+///     .output_gain_mode(OutputGainMode::Album)
+///     .track_volume(Some(Decibels::from(-13.0)))
+///     .build();
+/// assert_eq!(result.unwrap_err(), ConfigError::MissingAlbumVolume);
+/// # Ok::<(), ConfigError>(())
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct VolumeRewriterConfigBuilder {
+    config: VolumeRewriterConfig,
+    volumes_from_tags: bool,
+}
+
+impl Default for VolumeRewriterConfigBuilder {
+    fn default() -> VolumeRewriterConfigBuilder {
+        let config = VolumeRewriterConfig {
+            output_gain: VolumeTarget::ZeroGain,
+            output_gain_mode: OutputGainMode::Track,
+            track_volume: None,
+            album_volume: None,
+            r128_reference: R128_LUFS,
+            preserve_album_gain: false,
+            max_output_gain: None,
+        };
+        VolumeRewriterConfigBuilder { config, volumes_from_tags: false }
+    }
+}
+
+impl VolumeRewriterConfigBuilder {
+    /// Sets the target of the output gain
+    #[must_use]
+    pub fn target(mut self, target: VolumeTarget) -> VolumeRewriterConfigBuilder {
+        self.config.output_gain = target;
+        self
+    }
+
+    /// Sets whether the output gain targets track or album volume
+    #[must_use]
+    pub fn output_gain_mode(mut self, mode: OutputGainMode) -> VolumeRewriterConfigBuilder {
+        self.config.output_gain_mode = mode;
+        self
+    }
+
+    /// Sets the pre-computed volume of the track
+    #[must_use]
+    pub fn track_volume(mut self, volume: Option<Decibels>) -> VolumeRewriterConfigBuilder {
+        self.config.track_volume = volume;
+        self
+    }
+
+    /// Sets the pre-computed volume of the album the track belongs to
+    #[must_use]
+    pub fn album_volume(mut self, volume: Option<Decibels>) -> VolumeRewriterConfigBuilder {
+        self.config.album_volume = volume;
+        self
+    }
+
+    /// Sets the reference volume the R128 tag gains normalize to
+    #[must_use]
+    pub fn r128_reference(mut self, reference: Decibels) -> VolumeRewriterConfigBuilder {
+        self.config.r128_reference = reference;
+        self
+    }
+
+    /// Sets whether an existing R128 album gain is kept when the album volume
+    /// is unknown
+    #[must_use]
+    pub fn preserve_album_gain(mut self, preserve: bool) -> VolumeRewriterConfigBuilder {
+        self.config.preserve_album_gain = preserve;
+        self
+    }
+
+    /// Declares that the volumes will be inferred from the R128 tags of each
+    /// stream, as by `VolumeHeaderRewrite::from_tags`, `with_volumes_from_tags`
+    /// or `check_tags`, so they need not be known when the configuration is
+    /// built
+    #[must_use]
+    pub fn volumes_from_tags(mut self) -> VolumeRewriterConfigBuilder {
+        self.volumes_from_tags = true;
+        self
+    }
+
+    /// Checks the settings are consistent and returns the configuration
+    pub fn build(self) -> Result<VolumeRewriterConfig, ConfigError> {
+        let config = self.config;
+        if !config.r128_reference.as_f64().is_finite() {
+            return Err(ConfigError::NonFiniteReference(config.r128_reference));
+        }
+        if let VolumeTarget::LUFS(target) = config.output_gain {
+            if !target.as_f64().is_finite() {
+                return Err(ConfigError::NonFiniteTarget(target));
+            }
+        }
+        if self.volumes_from_tags {
+            if config.track_volume.is_some() || config.album_volume.is_some() {
+                return Err(ConfigError::VolumesFromTagsConflict);
+            }
+        } else if matches!(config.output_gain, VolumeTarget::LUFS(_)) {
+            config.required_volume()?;
+        }
+        Ok(config)
+    }
 }
 
 impl VolumeTarget {
@@ -68,6 +286,7 @@ impl VolumeTarget {
 
 /// The gain values of an Opus file
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OpusGains {
     /// The output gain that is always applied to the decoded audio
     pub output: Decibels,
@@ -79,6 +298,456 @@ pub struct OpusGains {
     pub album_r128: Option<Decibels>,
 }
 
+impl OpusGains {
+    /// Returns `true` if all gains of `self` and `other` differ by no more than
+    /// `tolerance`. An R128 gain missing from only one of the two is
+    /// considered different. Any NaN value causes the comparison to fail.
+    #[must_use]
+    pub fn approx_eq(&self, other: &OpusGains, tolerance: Decibels) -> bool {
+        let within = |a: Decibels, b: Decibels| within_tolerance(a, b, tolerance);
+        let optional_within = |a: Option<Decibels>, b: Option<Decibels>| match (a, b) {
+            (Some(a), Some(b)) => within(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        within(self.output, other.output)
+            && optional_within(self.track_r128, other.track_r128)
+            && optional_within(self.album_r128, other.album_r128)
+    }
+
+    /// Returns `true` if the output gain already normalizes the track to the
+    /// specified target to within `tolerance`. For a LUFS target, the track
+    /// volume is inferred from the R128 track gain, which normalizes to
+    /// `r128_reference`, so `false` is returned if this is missing.
+    #[must_use]
+    pub fn is_normalized_to(&self, target: VolumeTarget, r128_reference: Decibels, tolerance: Decibels) -> bool {
+        match target {
+            VolumeTarget::ZeroGain => within_tolerance(self.output, Decibels::default(), tolerance),
+            VolumeTarget::LUFS(target_lufs) => self.track_r128.is_some_and(|track_r128| {
+                // The R128 track gain is relative to the volume after the output gain is
+                // applied
+                let volume_with_output_gain = r128_reference - track_r128;
+                within_tolerance(volume_with_output_gain, target_lufs, tolerance)
+            }),
+            VolumeTarget::NoChange => true,
+        }
+    }
+
+    fn from_headers(opus_header: &OpusIdHeader, comment_header: &OpusCommentHeader) -> OpusGains {
+        OpusGains {
+            output: opus_header.get_output_gain().into(),
+            track_r128: comment_header.get_gain_from_tag(TAG_TRACK_GAIN).unwrap_or(None).map(Into::into),
+            album_r128: comment_header.get_gain_from_tag(TAG_ALBUM_GAIN).unwrap_or(None).map(Into::into),
+        }
+    }
+}
+
+impl Display for OpusGains {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        let optional =
+            |gain: Option<Decibels>| gain.map_or_else(|| String::from("none"), |g| format!("{:.2} dB", g.as_f64()));
+        write!(
+            formatter,
+            "output: {:.2} dB, track: {}, album: {}",
+            self.output.as_f64(),
+            optional(self.track_r128),
+            optional(self.album_r128)
+        )
+    }
+}
+
+/// Allowance for floating-point error when checking whether a gain is within
+/// half a fixed-point step of another
+const ROUNDING_SLACK: f64 = 1e-9;
+
+/// Converts the unrounded gain `computed` to a fixed-point gain. If `existing`
+/// is within half a fixed-point step of `computed` it is returned instead, so
+/// that a value which lies on a rounding boundary is not flipped between
+/// adjacent fixed-point values by repeated rewrites. An existing gain above
+/// `max` is never returned.
+fn to_fixed_point(
+    computed: Decibels, existing: Option<Decibels>, max: Option<Decibels>,
+) -> Result<FixedPointGain, Error> {
+    let half_step = Decibels::from(FixedPointGain::STEP.as_f64() / 2.0 + ROUNDING_SLACK);
+    let within_max = |gain: Decibels| max.map_or(true, |max| gain.as_f64() <= max.as_f64() + ROUNDING_SLACK);
+    match existing {
+        Some(existing) if within_tolerance(computed, existing, half_step) && within_max(existing) => {
+            FixedPointGain::try_from(existing)
+        }
+        _ => FixedPointGain::try_from(computed),
+    }
+}
+
+/// Computes the gains that rewriting a stream with the existing gains
+/// `existing` would produce under the supplied configuration. No headers are
+/// modified. `VolumeHeaderRewrite` uses this function so the planned gains are
+/// exactly those that would be written.
+///
+/// Each gain is rounded exactly once, from a value computed directly from the
+/// configured volumes and the output gain that will actually be written, and
+/// an existing gain is kept if it is within half a fixed-point step of the
+/// computed value, unless it is an output gain above
+/// `VolumeRewriterConfig::max_output_gain`. Planning again from the resulting gains with the same
+/// configuration therefore leaves them unchanged.
+///
+/// Only an unrepresentable output gain is an error. An R128 gain which cannot
+/// be represented, for example because it was adjusted from an absurd existing
+/// value, is planned as its existing value, or left absent, and reported by
+/// `plan_with_issues`.
+pub fn plan(existing: &OpusGains, config: &VolumeRewriterConfig) -> Result<OpusGains, Error> {
+    plan_gains(existing, config).map(|(gains, _, _)| gains)
+}
+
+/// Equivalent to `plan` but also returns an issue for each R128 gain tag that
+/// could not be updated because the gain computed for it cannot be
+/// represented
+pub fn plan_with_issues(
+    existing: &OpusGains, config: &VolumeRewriterConfig,
+) -> Result<(OpusGains, Vec<HeaderIssue>), Error> {
+    plan_gains(existing, config).map(|(gains, _, issues)| (gains, issues))
+}
+
+/// The intermediate values from which `plan_with_explanation` computed a set
+/// of gains, so that users can see how they were arrived at.
+///
+/// For a loudness target the gains are computed as:
+///
+/// * `raw_output_gain = target - source_volume`
+/// * `raw_track_r128 = r128_reference - track_volume - output_gain`
+/// * `raw_album_r128 = r128_reference - album_volume - output_gain`
+///
+/// where `output_gain` is `raw_output_gain` rounded to a `FixedPointGain`, and
+/// each R128 gain in `gains` is the corresponding raw gain rounded in the same
+/// way. An existing gain within half a fixed-point step of a raw gain is kept
+/// rather than rounding it.
+///
+/// ```
+/// use zoog::header::FixedPointGain;
+/// use zoog::volume_rewrite::{plan_with_explanation, OpusGains, VolumeRewriterConfig, VolumeTarget};
+/// use zoog::{Decibels, R128_LUFS, REPLAY_GAIN_LUFS};
+///
+/// let track_volume = Decibels::from(-13.39);
+/// let config = VolumeRewriterConfig::builder()
+///     .target(VolumeTarget::LUFS(REPLAY_GAIN_LUFS))
+///     .track_volume(Some(track_volume))
+///     .build()?;
+/// let existing = OpusGains { output: Decibels::default(), track_r128: None, album_r128: None };
+/// let (gains, explanation) = plan_with_explanation(&existing, &config)?;
+///
+/// assert_eq!(explanation.source_volume, Some(track_volume));
+/// let raw_output_gain = REPLAY_GAIN_LUFS - track_volume;
+/// assert_eq!(explanation.raw_output_gain, Some(raw_output_gain));
+/// assert_eq!(explanation.output_gain, FixedPointGain::try_from(raw_output_gain)?);
+///
+/// let output_gain = Decibels::from(explanation.output_gain);
+/// let raw_track_r128 = R128_LUFS - track_volume - output_gain;
+/// assert_eq!(explanation.raw_track_r128, Some(raw_track_r128));
+/// assert_eq!(gains.output, output_gain);
+/// assert_eq!(gains.track_r128, Some(FixedPointGain::try_from(raw_track_r128)?.into()));
+/// assert_eq!(explanation.gains.track_r128, gains.track_r128);
+/// # Ok::<(), zoog::Error>(())
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Explanation {
+    /// The target of the output gain
+    pub target: VolumeTarget,
+
+    /// Whether the output gain targets track or album volume
+    pub output_gain_mode: OutputGainMode,
+
+    /// The volume of the track, if known
+    pub track_volume: Option<Decibels>,
+
+    /// The volume of the album, if known. When an existing album gain is
+    /// preserved this is the volume implied by that gain.
+    pub album_volume: Option<Decibels>,
+
+    /// The volume the output gain was computed from, as selected by
+    /// `VolumeRewriterConfig::volume_for_output_gain_calculation`. This is
+    /// `None` unless the target is a loudness.
+    pub source_volume: Option<Decibels>,
+
+    /// The output gain before rounding. This is `None` unless the target is a
+    /// loudness.
+    pub raw_output_gain: Option<Decibels>,
+
+    /// The output gain to be written
+    pub output_gain: FixedPointGain,
+
+    /// The reference volume the R128 gains normalize to
+    pub r128_reference: Decibels,
+
+    /// The R128 track gain before rounding, if the track volume is known
+    pub raw_track_r128: Option<Decibels>,
+
+    /// The R128 album gain before rounding, if the album volume is known
+    pub raw_album_r128: Option<Decibels>,
+
+    /// The planned gains, as returned by `plan`
+    pub gains: OpusGains,
+}
+
+/// Equivalent to `plan` but also returns the intermediate values from which
+/// the gains were computed.
+pub fn plan_with_explanation(
+    existing: &OpusGains, config: &VolumeRewriterConfig,
+) -> Result<(OpusGains, Explanation), Error> {
+    plan_gains(existing, config).map(|(gains, explanation, _)| (gains, explanation))
+}
+
+fn plan_gains(
+    existing: &OpusGains, config: &VolumeRewriterConfig,
+) -> Result<(OpusGains, Explanation, Vec<HeaderIssue>), Error> {
+    let (source_volume, raw_output_gain, new_header_gain) = match config.output_gain {
+        VolumeTarget::ZeroGain => (None, None, FixedPointGain::default()),
+        VolumeTarget::LUFS(target_lufs) => {
+            let volume_for_output_gain = config.required_volume()?;
+            let raw_output_gain = target_lufs - volume_for_output_gain;
+            let new_header_gain = to_fixed_point(raw_output_gain, Some(existing.output), config.max_output_gain)?;
+            (Some(volume_for_output_gain), Some(raw_output_gain), new_header_gain)
+        }
+        VolumeTarget::NoChange => (None, None, FixedPointGain::try_from(existing.output)?),
+    };
+    // R128 gains are relative to the volume after the output gain is applied
+    let raw_gain =
+        |volume: Option<Decibels>| volume.map(|volume| config.r128_reference - volume - new_header_gain.into());
+    // An R128 gain which cannot be represented is left as it was rather than
+    // failing the whole stream
+    let mut issues = Vec::new();
+    let mut rounded_gain = |tag: &str, raw: Option<Decibels>, existing: Option<Decibels>| -> Result<_, Error> {
+        let Some(raw) = raw else { return Ok(None) };
+        match to_fixed_point(raw, existing, None) {
+            Ok(gain) => Ok(Some(Decibels::from(gain))),
+            Err(Error::GainOutOfBounds) => {
+                let format = |gain: Decibels| format!("{:.2} dB", gain.as_f64());
+                let (tag, gain, existing_gain) = (tag.into(), format(raw), existing.map(format));
+                issues.push(HeaderIssue::UnrepresentableR128Gain { tag, gain, existing: existing_gain });
+                Ok(existing)
+            }
+            Err(e) => Err(e),
+        }
+    };
+    let album_volume = match config.album_volume {
+        // The existing album gain is relative to the existing output gain so is
+        // recomputed from the album volume it implies
+        None if config.preserve_album_gain => {
+            existing.album_r128.map(|gain| config.r128_reference - gain - existing.output)
+        }
+        album_volume => album_volume,
+    };
+    let raw_track_r128 = raw_gain(config.track_volume);
+    let raw_album_r128 = raw_gain(album_volume);
+    let gains = OpusGains {
+        output: new_header_gain.into(),
+        track_r128: rounded_gain(TAG_TRACK_GAIN, raw_track_r128, existing.track_r128)?,
+        album_r128: rounded_gain(TAG_ALBUM_GAIN, raw_album_r128, existing.album_r128)?,
+    };
+    let explanation = Explanation {
+        target: config.output_gain,
+        output_gain_mode: config.output_gain_mode,
+        track_volume: config.track_volume,
+        album_volume,
+        source_volume,
+        raw_output_gain,
+        output_gain: new_header_gain,
+        r128_reference: config.r128_reference,
+        raw_track_r128,
+        raw_album_r128,
+        gains,
+    };
+    Ok((gains, explanation, issues))
+}
+
+/// Computes the loudest target volume that can be reached without the output
+/// gain of any of the supplied tracks exceeding its headroom. Each track is
+/// given as its volume and, if known, its peak in dBFS. The headroom of a track
+/// is the distance from its peak to full scale, or zero if the peak is unknown,
+/// so the target is the minimum over tracks of the volume plus the headroom.
+/// Non-finite volumes, such as those of silent tracks, are ignored, as are
+/// non-finite peaks. Returns `None` if there are no finite volumes.
+#[must_use]
+pub fn max_headroom_target<I: IntoIterator<Item = (Decibels, Option<Decibels>)>>(tracks: I) -> Option<Decibels> {
+    tracks
+        .into_iter()
+        .map(|(volume, peak)| {
+            let headroom = peak.map(|peak| -peak.as_f64()).filter(|headroom| headroom.is_finite()).unwrap_or(0.0);
+            volume.as_f64() + headroom
+        })
+        .filter(|target| target.is_finite())
+        .reduce(f64::min)
+        .map(Decibels::from)
+}
+
+/// Computes the difference between the largest and smallest output gains in
+/// `gains`, such as those planned for the tracks of an album. Returns `None`
+/// if there are no gains.
+#[must_use]
+pub fn output_gain_spread<'a, I: IntoIterator<Item = &'a OpusGains>>(gains: I) -> Option<Decibels> {
+    let outputs: Vec<f64> = gains.into_iter().map(|gains| gains.output.as_f64()).collect();
+    let max = outputs.iter().copied().reduce(f64::max)?;
+    let min = outputs.iter().copied().reduce(f64::min)?;
+    Some(Decibels::from(max - min))
+}
+
+/// Returns `config` with the track and album volumes replaced by those implied
+/// by the R128 tags and output gain of `existing`. A volume is `None` if the
+/// corresponding tag is missing.
+#[must_use]
+pub fn with_volumes_from_tags(existing: &OpusGains, config: &VolumeRewriterConfig) -> VolumeRewriterConfig {
+    // R128 gains are relative to the volume after the output gain is applied
+    let inferred_volume = |gain: Option<Decibels>| gain.map(|gain| config.r128_reference - gain - existing.output);
+    VolumeRewriterConfig {
+        track_volume: inferred_volume(existing.track_r128),
+        album_volume: inferred_volume(existing.album_r128),
+        ..*config
+    }
+}
+
+/// The sample peak of a stream compared with the output gain planned for it.
+///
+/// A decoder applies the output gain before anything else, so if it raises the
+/// peak above full scale the audio may clip even though a player applying an
+/// R128 gain afterwards would bring the loudness back down.
+#[derive(Clone, Copy, Debug)]
+pub struct PeakCheck {
+    /// The sample peak of the decoded audio before the output gain is applied,
+    /// relative to full scale
+    pub peak: Decibels,
+
+    /// The output gain
+    pub output_gain: Decibels,
+}
+
+impl PeakCheck {
+    /// The largest output gain that does not raise the peak above full scale
+    #[must_use]
+    pub fn headroom(&self) -> Decibels { Decibels::default() - self.peak }
+
+    /// The sample peak once the output gain is applied
+    #[must_use]
+    pub fn peak_after_output_gain(&self) -> Decibels { self.peak + self.output_gain }
+
+    /// Whether the output gain raises the peak above full scale
+    #[must_use]
+    pub fn exceeds_full_scale(&self) -> bool { self.peak_after_output_gain().as_f64() > ROUNDING_SLACK }
+}
+
+/// Returns `config` with its loudness target lowered if necessary so that the
+/// output gain planned from it does not raise a sample peak of `peak` above
+/// full scale. The output gain is limited to a whole number of fixed-point
+/// steps within the headroom, which also becomes the `max_output_gain` so that
+/// an existing output gain above it is not kept. R128 gains are relative to
+/// the output gain written, so playback at their reference loudness is
+/// unaffected.
+///
+/// The configuration is returned unchanged if it does not have a loudness
+/// target.
+#[must_use]
+pub fn limit_output_gain_to_peak(config: &VolumeRewriterConfig, peak: Decibels) -> VolumeRewriterConfig {
+    let (VolumeTarget::LUFS(target), Some(volume)) = (config.output_gain, config.volume_for_output_gain_calculation())
+    else {
+        return *config;
+    };
+    let step = FixedPointGain::STEP.as_f64();
+    // Silence has unlimited headroom, which cannot be represented
+    let Ok(max_gain) = FixedPointGain::try_from(Decibels::from((-peak.as_f64() / step).floor() * step)) else {
+        return *config;
+    };
+    let max_gain = Decibels::from(max_gain);
+    let limit = volume + max_gain;
+    let output_gain = if target.as_f64() > limit.as_f64() { VolumeTarget::LUFS(limit) } else { config.output_gain };
+    VolumeRewriterConfig { output_gain, max_output_gain: Some(max_gain), ..*config }
+}
+
+/// Track and album R128 gains which differ by more than this are considered
+/// implausible by `check_tags`
+pub const MAX_PLAUSIBLE_TRACK_ALBUM_DIFFERENCE: Decibels = Decibels::new(20.0);
+
+/// A problem with the existing gains of a stream found by `check_tags`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TagProblem {
+    /// There is no R128 track gain tag
+    MissingTrackGain,
+
+    /// There is no R128 album gain tag but album normalization was requested
+    MissingAlbumGain,
+
+    /// The output gain does not normalize to the target. The output gain
+    /// that would is supplied.
+    OffTarget { expected_output: Decibels },
+
+    /// The R128 track and album gains differ by an implausible amount
+    ImplausibleTrackAlbumDifference { difference: Decibels },
+}
+
+impl Display for TagProblem {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            TagProblem::MissingTrackGain => write!(formatter, "{} is missing", TAG_TRACK_GAIN),
+            TagProblem::MissingAlbumGain => write!(formatter, "{} is missing", TAG_ALBUM_GAIN),
+            TagProblem::OffTarget { expected_output } => {
+                write!(formatter, "output gain should be {:.2} dB to reach the target", expected_output.as_f64())
+            }
+            TagProblem::ImplausibleTrackAlbumDifference { difference } => write!(
+                formatter,
+                "{} and {} differ by an implausible {:.2} dB",
+                TAG_TRACK_GAIN,
+                TAG_ALBUM_GAIN,
+                difference.as_f64()
+            ),
+        }
+    }
+}
+
+/// The result of checking the existing gains of a stream with `check_tags`
+#[derive(Clone, Debug, PartialEq)]
+pub struct TagCheck {
+    /// The playback loudness implied by the output gain and R128 tags, if the
+    /// required tag is present
+    pub implied_lufs: Option<Decibels>,
+
+    /// Problems found, if any
+    pub problems: Vec<TagProblem>,
+}
+
+impl TagCheck {
+    /// Returns `true` if no problems were found
+    #[must_use]
+    pub fn is_consistent(&self) -> bool { self.problems.is_empty() }
+}
+
+/// Checks whether the existing gains of a stream are consistent with the
+/// supplied configuration without decoding any audio. The track and album
+/// volumes are inferred from the R128 tags, so any volumes in `config` are
+/// ignored. The output gain is compared against the one `plan` would produce
+/// to within `tolerance`.
+pub fn check_tags(existing: &OpusGains, config: &VolumeRewriterConfig, tolerance: Decibels) -> Result<TagCheck, Error> {
+    let config = with_volumes_from_tags(existing, config);
+    let mut problems = Vec::new();
+    if existing.track_r128.is_none() {
+        problems.push(TagProblem::MissingTrackGain);
+    }
+    if matches!(config.output_gain_mode, OutputGainMode::Album) && existing.album_r128.is_none() {
+        problems.push(TagProblem::MissingAlbumGain);
+    }
+    if let (Some(track), Some(album)) = (existing.track_r128, existing.album_r128) {
+        let difference = Decibels::from((track - album).as_f64().abs());
+        if difference.as_f64() > MAX_PLAUSIBLE_TRACK_ALBUM_DIFFERENCE.as_f64() {
+            problems.push(TagProblem::ImplausibleTrackAlbumDifference { difference });
+        }
+    }
+    let volume = config.volume_for_output_gain_calculation();
+    let implied_lufs = volume.map(|volume| volume + existing.output);
+    let can_plan = volume.is_some() || !matches!(config.output_gain, VolumeTarget::LUFS(_));
+    if can_plan {
+        let planned = plan(existing, &config)?;
+        if !within_tolerance(planned.output, existing.output, tolerance) {
+            problems.push(TagProblem::OffTarget { expected_output: planned.output });
+        }
+    }
+    Ok(TagCheck { implied_lufs, problems })
+}
+
 /// Returns the gains from the codec headers
 #[derive(Debug, Default)]
 pub struct GainsSummary {}
@@ -89,29 +758,99 @@ impl HeaderSummarize for GainsSummary {
 
     fn summarize(&self, headers: &CodecHeaders) -> Result<OpusGains, Error> {
         match headers {
-            CodecHeaders::Opus(opus_header, comment_header) => {
-                let gains = OpusGains {
-                    output: opus_header.get_output_gain().into(),
-                    track_r128: comment_header.get_gain_from_tag(TAG_TRACK_GAIN).unwrap_or(None).map(Into::into),
-                    album_r128: comment_header.get_gain_from_tag(TAG_ALBUM_GAIN).unwrap_or(None).map(Into::into),
-                };
-                Ok(gains)
-            }
+            CodecHeaders::Opus(opus_header, comment_header) => Ok(OpusGains::from_headers(opus_header, comment_header)),
             CodecHeaders::Vorbis(_, _) => Err(Error::UnsupportedCodec(headers.codec())),
         }
     }
 }
 
+/// What a `VolumeHeaderRewrite` does with the ReplayGain tags written by other
+/// tools, as listed in `FOREIGN_GAIN_TAGS`, when it changes the output gain.
+/// Their values are computed from the decoded audio so become stale once the
+/// output gain changes. Nothing is done if the output gain is unchanged.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ForeignTagPolicy {
+    /// The tags are left unchanged, so callers should warn that they are stale
+    #[default]
+    Keep,
+
+    /// The tags are adjusted by the change to the output gain
+    Fix,
+
+    /// The tags are removed
+    Drop,
+}
+
 /// Parameterization struct for `HeaderRewriter` to rewrite ouput gain and R128
 /// tags.
 #[derive(Debug)]
 pub struct VolumeHeaderRewrite {
     config: VolumeRewriterConfig,
+    volumes_from_tags: bool,
+    reference_tag: bool,
+    duplicate_header_action: DuplicateHeaderAction,
+    classifier: Option<LoudnessClassifier>,
+    foreign_tag_policy: ForeignTagPolicy,
+    strict_headers: bool,
 }
 
 impl VolumeHeaderRewrite {
     #[must_use]
-    pub fn new(config: VolumeRewriterConfig) -> VolumeHeaderRewrite { VolumeHeaderRewrite { config } }
+    pub fn new(config: VolumeRewriterConfig) -> VolumeHeaderRewrite {
+        VolumeHeaderRewrite {
+            config,
+            volumes_from_tags: false,
+            reference_tag: false,
+            duplicate_header_action: DuplicateHeaderAction::default(),
+            classifier: None,
+            foreign_tag_policy: ForeignTagPolicy::default(),
+            strict_headers: false,
+        }
+    }
+
+    /// Rewrites using the track and album volumes implied by the existing R128
+    /// tags of each stream rather than those in `config`, so no audio needs to
+    /// be decoded. Rewriting fails with `Error::MissingR128Tag` if the tag
+    /// needed to compute the output gain is missing.
+    #[must_use]
+    pub fn from_tags(config: VolumeRewriterConfig) -> VolumeHeaderRewrite {
+        VolumeHeaderRewrite { volumes_from_tags: true, ..Self::new(config) }
+    }
+
+    /// Also sets the `REPLAYGAIN_REFERENCE_LOUDNESS` tag to the loudness
+    /// targeted by the output gain, e.g. `-18.00 LUFS`. When the target is not
+    /// a loudness (zero gain or no change) the tag is removed instead, since
+    /// the output gain then no longer reflects any particular loudness.
+    #[must_use]
+    pub fn with_reference_tag(self) -> VolumeHeaderRewrite { VolumeHeaderRewrite { reference_tag: true, ..self } }
+
+    /// Also sets the comment named by `classifier` to the class of the track
+    /// volume, replacing any existing values. The comment is left unchanged if
+    /// the track volume is unknown.
+    #[must_use]
+    pub fn with_classifier(self, classifier: LoudnessClassifier) -> VolumeHeaderRewrite {
+        VolumeHeaderRewrite { classifier: Some(classifier), ..self }
+    }
+
+    /// Sets what is done with a duplicate comment header following the
+    /// original
+    #[must_use]
+    pub fn with_duplicate_header_action(self, duplicate_header_action: DuplicateHeaderAction) -> VolumeHeaderRewrite {
+        VolumeHeaderRewrite { duplicate_header_action, ..self }
+    }
+
+    /// Sets what is done with ReplayGain tags written by other tools when the
+    /// output gain changes
+    #[must_use]
+    pub fn with_foreign_tag_policy(self, foreign_tag_policy: ForeignTagPolicy) -> VolumeHeaderRewrite {
+        VolumeHeaderRewrite { foreign_tag_policy, ..self }
+    }
+
+    /// Fails with `Error::HeaderIssues` if an R128 gain tag cannot be updated
+    /// because the gain computed for it cannot be represented. By default
+    /// such a tag is left unchanged, as reported by `plan_with_issues`.
+    #[must_use]
+    pub fn with_strict_headers(self) -> VolumeHeaderRewrite { VolumeHeaderRewrite { strict_headers: true, ..self } }
 }
 
 impl HeaderRewrite for VolumeHeaderRewrite {
@@ -120,37 +859,823 @@ impl HeaderRewrite for VolumeHeaderRewrite {
     fn rewrite(&self, headers: &mut CodecHeaders) -> Result<(), Error> {
         match headers {
             CodecHeaders::Opus(opus_header, comment_header) => {
-                let new_header_gain = match self.config.output_gain {
-                    VolumeTarget::ZeroGain => FixedPointGain::default(),
-                    VolumeTarget::LUFS(target_lufs) => {
-                        let volume_for_output_gain = self
-                            .config
-                            .volume_for_output_gain_calculation()
-                            .expect("Precomputed volume unexpectedly missing");
-                        FixedPointGain::try_from(target_lufs - volume_for_output_gain)?
+                let existing = OpusGains::from_headers(opus_header, comment_header);
+                let config = if self.volumes_from_tags {
+                    let config = with_volumes_from_tags(&existing, &self.config);
+                    if matches!(config.output_gain, VolumeTarget::LUFS(_))
+                        && config.volume_for_output_gain_calculation().is_none()
+                    {
+                        let tag = match config.output_gain_mode {
+                            OutputGainMode::Album => TAG_ALBUM_GAIN,
+                            OutputGainMode::Track => TAG_TRACK_GAIN,
+                        };
+                        return Err(Error::MissingR128Tag(tag.into()));
                     }
-                    VolumeTarget::NoChange => opus_header.get_output_gain(),
+                    config
+                } else {
+                    self.config
                 };
-                opus_header.set_output_gain(new_header_gain);
-                let compute_gain = |volume| -> Result<Option<FixedPointGain>, Error> {
-                    if let Some(volume) = volume {
-                        FixedPointGain::try_from(R128_LUFS - volume - new_header_gain.into()).map(Some)
-                    } else {
-                        Ok(None)
+                let (planned, issues) = plan_with_issues(&existing, &config)?;
+                if self.strict_headers && !issues.is_empty() {
+                    return Err(Error::HeaderIssues(issues));
+                }
+                let output_gain = FixedPointGain::try_from(planned.output)?;
+                if output_gain != opus_header.get_output_gain() {
+                    let output_gain_change = Decibels::from(output_gain) - existing.output;
+                    match self.foreign_tag_policy {
+                        ForeignTagPolicy::Keep => {}
+                        ForeignTagPolicy::Fix => adjust_foreign_tags(comment_header, output_gain_change)?,
+                        ForeignTagPolicy::Drop => {
+                            for tag in FOREIGN_GAIN_TAGS {
+                                comment_header.remove_all(tag);
+                            }
+                        }
                     }
-                };
-                let track_gain_r128 = compute_gain(self.config.track_volume)?;
-                let album_gain_r128 = compute_gain(self.config.album_volume)?;
-                for (tag, gain) in [(TAG_TRACK_GAIN, track_gain_r128), (TAG_ALBUM_GAIN, album_gain_r128)] {
+                }
+                opus_header.set_output_gain(output_gain);
+                for (tag, gain) in [(TAG_TRACK_GAIN, planned.track_r128), (TAG_ALBUM_GAIN, planned.album_r128)] {
                     if let Some(gain) = gain {
-                        comment_header.set_tag_to_gain(tag, gain)?;
+                        comment_header.set_tag_to_gain(tag, FixedPointGain::try_from(gain)?)?;
                     } else {
                         comment_header.remove_all(tag);
                     }
                 }
+                if self.reference_tag {
+                    match config.output_gain {
+                        VolumeTarget::LUFS(target) => {
+                            let value = format!("{:.2} LUFS", target.as_f64());
+                            comment_header.replace(TAG_REFERENCE_LOUDNESS, &value)?;
+                        }
+                        VolumeTarget::ZeroGain | VolumeTarget::NoChange => {
+                            comment_header.remove_all(TAG_REFERENCE_LOUDNESS);
+                        }
+                    }
+                }
+                if let (Some(classifier), Some(track_volume)) = (&self.classifier, config.track_volume) {
+                    comment_header.replace(classifier.tag(), classifier.classify(track_volume))?;
+                }
                 Ok(())
             }
             CodecHeaders::Vorbis(_, _) => Err(Error::UnsupportedCodec(headers.codec())),
         }
     }
+
+    fn duplicate_header_action(&self) -> DuplicateHeaderAction { self.duplicate_header_action }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::REPLAY_GAIN_LUFS;
+
+    /// Returns a builder for a configuration with output gain targeting
+    /// `target` from the track volume `track_volume`
+    fn track_config(target: VolumeTarget, track_volume: f64) -> VolumeRewriterConfigBuilder {
+        VolumeRewriterConfig::builder().target(target).track_volume(Some(Decibels::from(track_volume)))
+    }
+
+    /// Opus headers whose comment header holds the supplied tags
+    fn opus_headers(tags: &[(&str, &str)]) -> CodecHeaders {
+        use crate::header::IdHeader as _;
+        use crate::testing::opus_id_header;
+
+        let id_header = OpusIdHeader::try_parse(&opus_id_header(2, 48000, 312, 0)).unwrap().unwrap();
+        let mut comment_header = OpusCommentHeader::default();
+        for (key, value) in tags {
+            comment_header.push(key, value).unwrap();
+        }
+        CodecHeaders::Opus(id_header, comment_header)
+    }
+
+    #[test]
+    fn plan_computes_expected_gains() -> Result<(), Error> {
+        use OutputGainMode::{Album, Track};
+        use VolumeTarget::{NoChange, ZeroGain, LUFS};
+
+        let existing = OpusGains { output: Decibels::from(1.5), track_r128: None, album_r128: None };
+        let track_volume = Some(Decibels::from(-20.0));
+        let album = Some(Decibels::from(-16.0));
+        // (target, mode, album volume, expected (output, track, album))
+        let cases = [
+            (LUFS(REPLAY_GAIN_LUFS), Track, album, (2.0, Some(-5.0), Some(-9.0))),
+            (LUFS(REPLAY_GAIN_LUFS), Album, album, (-2.0, Some(-1.0), Some(-5.0))),
+            (LUFS(R128_LUFS), Track, album, (-3.0, Some(0.0), Some(-4.0))),
+            (LUFS(R128_LUFS), Album, album, (-7.0, Some(4.0), Some(0.0))),
+            (LUFS(R128_LUFS), Track, None, (-3.0, Some(0.0), None)),
+            (ZeroGain, Track, album, (0.0, Some(-3.0), Some(-7.0))),
+            (ZeroGain, Album, None, (0.0, Some(-3.0), None)),
+            (NoChange, Track, album, (1.5, Some(-4.5), Some(-8.5))),
+            (NoChange, Album, None, (1.5, Some(-4.5), None)),
+        ];
+        for (output_gain, output_gain_mode, album_volume, expected) in cases {
+            let config = VolumeRewriterConfig::builder()
+                .target(output_gain)
+                .output_gain_mode(output_gain_mode)
+                .track_volume(track_volume)
+                .album_volume(album_volume)
+                .build()?;
+            let planned = plan(&existing, &config)?;
+            let actual = (
+                planned.output.as_f64(),
+                planned.track_r128.map(|g| g.as_f64()),
+                planned.album_r128.map(|g| g.as_f64()),
+            );
+            assert_eq!(actual, expected, "Unexpected gains for {:?}", config);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn plan_preserves_album_gain_without_album_volume() -> Result<(), Error> {
+        let existing = OpusGains {
+            output: Decibels::from(2.0),
+            track_r128: Some(Decibels::from(-3.0)),
+            album_r128: Some(Decibels::from(-4.0)),
+        };
+        let config = track_config(VolumeTarget::LUFS(R128_LUFS), -20.0).preserve_album_gain(true).build()?;
+        // The output gain falls by 5 dB so the album gain must rise by 5 dB for album
+        // playback to be unchanged
+        let planned = plan(&existing, &config)?;
+        assert_eq!(planned.output.as_f64(), -3.0);
+        assert_eq!(planned.album_r128.map(|g| g.as_f64()), Some(1.0));
+        let album_playback_gain = |gains: &OpusGains| gains.album_r128.map(|g| (gains.output + g).as_f64());
+        assert_eq!(album_playback_gain(&planned), album_playback_gain(&existing));
+        assert!(plan(&planned, &config)?.approx_eq(&planned, Decibels::default()));
+
+        // A computed album volume takes precedence, and without one the album gain can
+        // be dropped
+        let computed = VolumeRewriterConfig { album_volume: Some(Decibels::from(-18.0)), ..config };
+        assert_eq!(plan(&existing, &computed)?.album_r128.map(|g| g.as_f64()), Some(-2.0));
+        let dropped = VolumeRewriterConfig { preserve_album_gain: false, ..config };
+        assert_eq!(plan(&existing, &dropped)?.album_r128, None);
+        let no_album = OpusGains { album_r128: None, ..existing };
+        assert_eq!(plan(&no_album, &config)?.album_r128, None);
+        Ok(())
+    }
+
+    #[test]
+    fn explanation_records_intermediate_values() -> Result<(), Error> {
+        let existing = OpusGains {
+            output: Decibels::from(2.0),
+            track_r128: Some(Decibels::from(-3.0)),
+            album_r128: Some(Decibels::from(-4.0)),
+        };
+        let config = track_config(VolumeTarget::LUFS(REPLAY_GAIN_LUFS), -20.0)
+            .output_gain_mode(OutputGainMode::Album)
+            .album_volume(Some(Decibels::from(-21.0)))
+            .build()?;
+        let (gains, explanation) = plan_with_explanation(&existing, &config)?;
+        assert!(gains.approx_eq(&plan(&existing, &config)?, Decibels::default()));
+        assert!(explanation.gains.approx_eq(&gains, Decibels::default()));
+        assert_eq!(explanation.source_volume, config.album_volume);
+        assert_eq!(explanation.raw_output_gain.map(|g| g.as_f64()), Some(3.0));
+        assert_eq!(Decibels::from(explanation.output_gain), gains.output);
+        assert_eq!(explanation.raw_track_r128.map(|g| g.as_f64()), Some(0.0));
+        assert_eq!(explanation.raw_album_r128.map(|g| g.as_f64()), Some(-5.0));
+
+        // Without a loudness target there is no source volume, and a preserved album
+        // gain is explained by the album volume it implies
+        let config = VolumeRewriterConfig {
+            output_gain: VolumeTarget::NoChange,
+            album_volume: None,
+            preserve_album_gain: true,
+            ..config
+        };
+        let (gains, explanation) = plan_with_explanation(&existing, &config)?;
+        assert_eq!(gains.output, existing.output);
+        assert_eq!(gains.album_r128, existing.album_r128);
+        assert_eq!(explanation.source_volume, None);
+        assert_eq!(explanation.raw_output_gain, None);
+        assert_eq!(explanation.album_volume.map(|v| v.as_f64()), Some(-21.0));
+        Ok(())
+    }
+
+    #[test]
+    fn plan_keeps_existing_gains_on_rounding_boundary() -> Result<(), Error> {
+        // The output gain lies exactly halfway between 512 and 513 fixed-point steps
+        let track_volume = R128_LUFS.as_f64() - 512.5 * FixedPointGain::STEP.as_f64();
+        let config = track_config(VolumeTarget::LUFS(R128_LUFS), track_volume).build()?;
+        for existing_output in [512, 513] {
+            let output = FixedPointGain::from_fixed_point(existing_output).into();
+            let existing = OpusGains { output, track_r128: None, album_r128: None };
+            let planned = plan(&existing, &config)?;
+            assert_eq!(FixedPointGain::try_from(planned.output)?.as_fixed_point(), existing_output);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn plan_replaces_existing_gains_beyond_half_step() -> Result<(), Error> {
+        let step = FixedPointGain::STEP.as_f64();
+        let existing = OpusGains { output: Decibels::from(512.0 * step), track_r128: None, album_r128: None };
+        // (offset of computed output gain from existing in steps, expected output gain in steps)
+        for (offset, expected) in [(0.5, 512), (-0.5, 512), (0.55, 513), (-0.55, 511), (3.0, 515)] {
+            let config =
+                track_config(VolumeTarget::LUFS(R128_LUFS), R128_LUFS.as_f64() - (512.0 + offset) * step).build()?;
+            let planned = plan(&existing, &config)?;
+            assert_eq!(FixedPointGain::try_from(planned.output)?.as_fixed_point(), expected, "Offset {}", offset);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tolerance_is_applied_consistently() -> Result<(), Error> {
+        let tolerance = DEFAULT_TOLERANCE.as_f64();
+        let config =
+            VolumeRewriterConfig::builder().target(VolumeTarget::LUFS(REPLAY_GAIN_LUFS)).volumes_from_tags().build()?;
+        // A track at -20 LUFS with 2 dB of output gain is at -18 LUFS
+        let correct =
+            OpusGains { output: Decibels::from(2.0), track_r128: Some(Decibels::from(-5.0)), album_r128: None };
+        for (factor, within) in [(0.9, true), (1.1, false)] {
+            let offset = Decibels::from(factor * tolerance);
+            assert_eq!(within_tolerance(correct.output, correct.output + offset, DEFAULT_TOLERANCE), within);
+            let off_output = OpusGains { output: correct.output + offset, ..correct };
+            assert_eq!(correct.approx_eq(&off_output, DEFAULT_TOLERANCE), within);
+            // Changing the track gain changes the volume inferred from it and so the
+            // expected output gain
+            let off_track = OpusGains { track_r128: correct.track_r128.map(|gain| gain - offset), ..correct };
+            assert_eq!(
+                off_track.is_normalized_to(VolumeTarget::LUFS(REPLAY_GAIN_LUFS), R128_LUFS, DEFAULT_TOLERANCE),
+                within
+            );
+            assert_eq!(check_tags(&off_track, &config, DEFAULT_TOLERANCE)?.is_consistent(), within);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn replanning_is_idempotent() -> Result<(), Error> {
+        use OutputGainMode::{Album, Track};
+
+        let no_gains = OpusGains { output: Decibels::default(), track_r128: None, album_r128: None };
+        for target in [REPLAY_GAIN_LUFS, R128_LUFS, Decibels::from(-14.3)] {
+            for mode in [Track, Album] {
+                // Steps of a seventh of a fixed-point step cover values on and
+                // around rounding boundaries
+                for step in 0..2000 {
+                    let volume = -30.0 + f64::from(step) * FixedPointGain::STEP.as_f64() / 7.0;
+                    let config = track_config(VolumeTarget::LUFS(target), volume)
+                        .output_gain_mode(mode)
+                        .album_volume(Some(Decibels::from(volume - FixedPointGain::STEP.as_f64() / 2.0)))
+                        .build()?;
+                    let first = plan(&no_gains, &config)?;
+                    let second = plan(&first, &config)?;
+                    assert!(first.approx_eq(&second, Decibels::default()), "Gains changed for {:?}", config);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn max_headroom_target_selects_quietest() {
+        let db = |values: &[f64]| values.iter().map(|&value| (Decibels::from(value), None)).collect::<Vec<_>>();
+        // (volumes, expected target)
+        let cases = [
+            (db(&[-14.0, -20.5, -9.25]), Some(-20.5)),
+            (db(&[-23.0]), Some(-23.0)),
+            (db(&[-12.0, f64::NEG_INFINITY, f64::NAN, -16.0]), Some(-16.0)),
+            (db(&[f64::NAN, f64::NEG_INFINITY]), None),
+            (db(&[]), None),
+        ];
+        for (tracks, expected) in cases {
+            let actual = max_headroom_target(tracks.iter().copied()).map(|v| v.as_f64());
+            assert_eq!(actual, expected, "Unexpected target for {:?}", tracks);
+        }
+    }
+
+    #[test]
+    fn max_headroom_target_adds_peak_headroom() {
+        let track = |volume: f64, peak: Option<f64>| (Decibels::from(volume), peak.map(Decibels::from));
+        // (tracks, expected target)
+        let cases = [
+            // The quieter track has 8 dB of headroom so the louder one limits the target
+            (vec![track(-20.0, Some(-8.0)), track(-16.0, Some(-1.0))], -15.0),
+            // Without a known peak a track has no headroom
+            (vec![track(-20.0, None), track(-16.0, Some(-1.0))], -20.0),
+            // A silent peak is treated as unknown
+            (vec![track(-20.0, Some(f64::NEG_INFINITY)), track(-16.0, Some(-6.0))], -20.0),
+            // A peak above full scale lowers the target
+            (vec![track(-16.0, Some(1.5))], -17.5),
+        ];
+        for (tracks, expected) in cases {
+            let actual = max_headroom_target(tracks.iter().copied()).map(|v| v.as_f64());
+            assert_eq!(actual, Some(expected), "Unexpected target for {:?}", tracks);
+        }
+    }
+
+    #[test]
+    fn output_gain_spread_is_range() {
+        let gains = |output: f64| OpusGains { output: Decibels::from(output), track_r128: None, album_r128: None };
+        let all = [gains(-3.5), gains(2.0), gains(0.25)];
+        assert_eq!(output_gain_spread(&all).map(|spread| spread.as_f64()), Some(5.5));
+        assert_eq!(output_gain_spread(&all[..1]).map(|spread| spread.as_f64()), Some(0.0));
+        assert!(output_gain_spread(std::iter::empty()).is_none());
+    }
+
+    #[test]
+    fn max_headroom_target_gives_no_positive_output_gain() -> Result<(), Error> {
+        let existing = OpusGains { output: Decibels::default(), track_r128: None, album_r128: None };
+        let volumes = [-14.3, -20.7, -9.1, -17.0].map(Decibels::from);
+        let target = max_headroom_target(volumes.map(|volume| (volume, None))).unwrap();
+        let mut max_output_gain = f64::NEG_INFINITY;
+        for track_volume in volumes {
+            let config = track_config(VolumeTarget::LUFS(target), track_volume.as_f64()).build()?;
+            max_output_gain = max_output_gain.max(plan(&existing, &config)?.output.as_f64());
+        }
+        assert_eq!(Decibels::from(max_output_gain), Decibels::default());
+        Ok(())
+    }
+
+    #[test]
+    fn output_gain_is_limited_to_peak_headroom() -> Result<(), Error> {
+        let existing = OpusGains { output: Decibels::default(), track_r128: None, album_r128: None };
+        let config = track_config(VolumeTarget::LUFS(REPLAY_GAIN_LUFS), -24.0).build()?;
+        let peak = Decibels::from(-0.5);
+        let unlimited = plan(&existing, &config)?;
+        assert_eq!(unlimited.output, Decibels::from(6.0));
+        assert!(PeakCheck { peak, output_gain: unlimited.output }.exceeds_full_scale());
+
+        let limited = plan(&existing, &limit_output_gain_to_peak(&config, peak))?;
+        let check = PeakCheck { peak, output_gain: limited.output };
+        assert!(!check.exceeds_full_scale());
+        assert!(check.headroom().as_f64() - limited.output.as_f64() < FixedPointGain::STEP.as_f64());
+        // Playback at the R128 reference is unaffected
+        let playback = |gains: OpusGains| (gains.output + gains.track_r128.unwrap()).as_f64();
+        assert!((playback(limited) - playback(unlimited)).abs() < FixedPointGain::STEP.as_f64());
+
+        // Gains within the headroom and targets other than a loudness are unchanged
+        for (config, peak) in [
+            (config, Decibels::from(-10.0)),
+            (config, Decibels::from(f64::NEG_INFINITY)),
+            (VolumeRewriterConfig { output_gain: VolumeTarget::ZeroGain, ..config }, peak),
+        ] {
+            let gains = plan(&existing, &limit_output_gain_to_peak(&config, peak))?;
+            assert!(gains.approx_eq(&plan(&existing, &config)?, Decibels::default()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn limited_output_gain_replaces_existing_gain_above_headroom() -> Result<(), Error> {
+        let config = track_config(VolumeTarget::LUFS(REPLAY_GAIN_LUFS), -24.0).build()?;
+        let peak = Decibels::from(-0.5);
+        let limited = limit_output_gain_to_peak(&config, peak);
+        assert_eq!(limited.max_output_gain, Some(Decibels::from(0.5)));
+
+        // An existing output gain half a step above the limit is within half a step
+        // of the computed output gain but would raise the peak above full scale
+        let half_step = Decibels::from(FixedPointGain::STEP.as_f64() / 2.0);
+        let above = OpusGains { output: Decibels::from(0.5) + half_step, track_r128: None, album_r128: None };
+        let gains = plan(&above, &limited)?;
+        assert_eq!(gains.output, Decibels::from(0.5));
+        assert!(!PeakCheck { peak, output_gain: gains.output }.exceeds_full_scale());
+        Ok(())
+    }
+
+    #[test]
+    fn approx_eq_within_tolerance() {
+        let tolerance = Decibels::from(0.01);
+        let a = OpusGains { output: Decibels::from(-2.0), track_r128: Some(Decibels::from(1.0)), album_r128: None };
+        let b = OpusGains { output: Decibels::from(-2.005), track_r128: Some(Decibels::from(1.005)), album_r128: None };
+        let c = OpusGains { output: Decibels::from(-2.1), ..a };
+        assert!(a.approx_eq(&b, tolerance));
+        assert!(b.approx_eq(&a, tolerance));
+        assert!(!a.approx_eq(&c, tolerance));
+    }
+
+    #[test]
+    fn approx_eq_missing_album_gain() {
+        let tolerance = Decibels::from(0.01);
+        let a = OpusGains { output: Decibels::default(), track_r128: None, album_r128: None };
+        let b = OpusGains { album_r128: Some(Decibels::default()), ..a };
+        assert!(a.approx_eq(&a, tolerance));
+        assert!(!a.approx_eq(&b, tolerance));
+        assert!(!b.approx_eq(&a, tolerance));
+    }
+
+    #[test]
+    fn approx_eq_nan() {
+        let tolerance = Decibels::from(0.01);
+        let a = OpusGains { output: Decibels::from(f64::NAN), track_r128: None, album_r128: None };
+        assert!(!a.approx_eq(&a, tolerance));
+    }
+
+    #[test]
+    fn is_normalized_to_targets() {
+        let tolerance = Decibels::from(0.01);
+        // A track at -20 LUFS with 2 dB of output gain is at -18 LUFS
+        let gains = OpusGains { output: Decibels::from(2.0), track_r128: Some(Decibels::from(-5.0)), album_r128: None };
+        let normalized = |gains: &OpusGains, target| gains.is_normalized_to(target, R128_LUFS, tolerance);
+        assert!(normalized(&gains, VolumeTarget::LUFS(REPLAY_GAIN_LUFS)));
+        assert!(!normalized(&gains, VolumeTarget::LUFS(R128_LUFS)));
+        assert!(!normalized(&gains, VolumeTarget::ZeroGain));
+        assert!(normalized(&gains, VolumeTarget::NoChange));
+
+        let untagged = OpusGains { track_r128: None, ..gains };
+        assert!(!normalized(&untagged, VolumeTarget::LUFS(REPLAY_GAIN_LUFS)));
+        let nan = OpusGains { track_r128: Some(Decibels::from(f64::NAN)), ..gains };
+        assert!(!normalized(&nan, VolumeTarget::LUFS(REPLAY_GAIN_LUFS)));
+
+        // If the track gain normalizes to -18 LUFS the same gains imply a volume of -13
+        // LUFS once the output gain is applied
+        let shifted = |target| gains.is_normalized_to(VolumeTarget::LUFS(target), REPLAY_GAIN_LUFS, tolerance);
+        assert!(shifted(Decibels::from(-13.0)));
+        assert!(!shifted(REPLAY_GAIN_LUFS));
+    }
+
+    #[test]
+    fn display_is_compact() {
+        let gains = OpusGains { output: Decibels::from(-4.3), track_r128: Some(Decibels::from(1.2)), album_r128: None };
+        assert_eq!(gains.to_string(), "output: -4.30 dB, track: 1.20 dB, album: none");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() -> Result<(), serde_json::Error> {
+        let tolerance = Decibels::default();
+        for gains in [
+            OpusGains { output: Decibels::from(-4.3), track_r128: Some(Decibels::from(1.2)), album_r128: None },
+            OpusGains {
+                output: Decibels::default(),
+                track_r128: Some(Decibels::from(-3.0)),
+                album_r128: Some(Decibels::from(2.5)),
+            },
+        ] {
+            let json = serde_json::to_string(&gains)?;
+            assert!(gains.approx_eq(&serde_json::from_str(&json)?, tolerance));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn plan_reports_unrepresentable_gain() -> Result<(), Error> {
+        let existing = OpusGains { output: Decibels::default(), track_r128: None, album_r128: None };
+        let config = track_config(VolumeTarget::LUFS(R128_LUFS), -500.0).build()?;
+        assert!(matches!(plan(&existing, &config), Err(Error::GainOutOfBounds)));
+        Ok(())
+    }
+
+    #[test]
+    fn unrepresentable_r128_gains_are_left_unchanged() -> Result<(), Error> {
+        use OutputGainMode::{Album, Track};
+        use VolumeTarget::{ZeroGain, LUFS};
+
+        type Case = (VolumeTarget, OutputGainMode, Option<f64>, f64, Option<f64>, (f64, Option<f64>, Option<f64>));
+        let existing_track = Some(Decibels::from(-3.0));
+        // ((target, mode, existing album gain, track volume, album volume, expected
+        // (output, track, album)), tags left unchanged)
+        let cases: [(Case, &[&str]); 7] = [
+            // Preserved album gains adjusted by the change to the output gain
+            ((LUFS(R128_LUFS), Track, Some(127.0), -22.5, None, (-0.5, Some(0.0), Some(127.5))), &[]),
+            ((LUFS(R128_LUFS), Track, Some(127.0), -21.0, None, (-2.0, Some(0.0), Some(127.0))), &[TAG_ALBUM_GAIN]),
+            ((LUFS(R128_LUFS), Track, Some(-127.5), -25.0, None, (2.0, Some(0.0), Some(-127.5))), &[TAG_ALBUM_GAIN]),
+            // Gains computed from absurd volumes
+            ((LUFS(R128_LUFS), Album, None, -150.5, Some(-23.0), (0.0, Some(127.5), Some(0.0))), &[]),
+            ((LUFS(R128_LUFS), Album, None, -151.5, Some(-23.0), (0.0, Some(-3.0), Some(0.0))), &[TAG_TRACK_GAIN]),
+            ((LUFS(R128_LUFS), Album, None, 110.0, Some(-23.0), (0.0, Some(-3.0), Some(0.0))), &[TAG_TRACK_GAIN]),
+            (
+                (ZeroGain, Track, Some(1.0), 110.0, Some(-160.0), (0.0, Some(-3.0), Some(1.0))),
+                &[TAG_TRACK_GAIN, TAG_ALBUM_GAIN],
+            ),
+        ];
+        for ((target, mode, album_r128, track_volume, album_volume, expected), unchanged) in cases {
+            let existing = OpusGains {
+                output: Decibels::default(),
+                track_r128: existing_track,
+                album_r128: album_r128.map(Decibels::from),
+            };
+            let config = track_config(target, track_volume)
+                .output_gain_mode(mode)
+                .album_volume(album_volume.map(Decibels::from))
+                .preserve_album_gain(true)
+                .build()?;
+            let (gains, issues) = plan_with_issues(&existing, &config)?;
+            let expected = OpusGains {
+                output: Decibels::from(expected.0),
+                track_r128: expected.1.map(Decibels::from),
+                album_r128: expected.2.map(Decibels::from),
+            };
+            assert!(gains.approx_eq(&expected, Decibels::from(0.001)), "Planned {} rather than {}", gains, expected);
+            let tags: Vec<_> = issues
+                .iter()
+                .map(|issue| match issue {
+                    HeaderIssue::UnrepresentableR128Gain { tag, .. } => tag.as_str(),
+                    other => panic!("Unexpected issue {:?}", other),
+                })
+                .collect();
+            assert_eq!(tags, unchanged, "Unexpected issues for {}", expected);
+            assert!(plan(&existing, &config)?.approx_eq(&gains, Decibels::default()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rewrite_keeps_unrepresentable_album_gain() -> Result<(), Error> {
+        let headers = || opus_headers(&[(TAG_ALBUM_GAIN, "32512")]);
+        let config = track_config(VolumeTarget::LUFS(R128_LUFS), -21.0).preserve_album_gain(true).build()?;
+        let mut rewritten = headers();
+        VolumeHeaderRewrite::new(config).rewrite(&mut rewritten)?;
+        let CodecHeaders::Opus(id_header, comment_header) = &rewritten else { unreachable!() };
+        assert_eq!(id_header.get_output_gain().as_fixed_point(), -512);
+        assert_eq!(comment_header.get_first(TAG_TRACK_GAIN), Some("0"));
+        assert_eq!(comment_header.get_first(TAG_ALBUM_GAIN), Some("32512"));
+
+        let result = VolumeHeaderRewrite::new(config).with_strict_headers().rewrite(&mut headers());
+        let expected = HeaderIssue::UnrepresentableR128Gain {
+            tag: TAG_ALBUM_GAIN.into(),
+            gain: "129.00 dB".into(),
+            existing: Some("127.00 dB".into()),
+        };
+        assert!(matches!(result, Err(Error::HeaderIssues(issues)) if issues == [expected]));
+        Ok(())
+    }
+
+    #[test]
+    fn r128_reference_shifts_tag_gains() -> Result<(), Error> {
+        let existing = OpusGains { output: Decibels::from(1.5), track_r128: None, album_r128: None };
+        let config = track_config(VolumeTarget::LUFS(REPLAY_GAIN_LUFS), -20.0)
+            .album_volume(Some(Decibels::from(-16.0)))
+            .build()?;
+        let standard = plan(&existing, &config)?;
+        for delta in [-7.0, -0.5, 2.25, 5.0] {
+            let shifted_config = VolumeRewriterConfig { r128_reference: R128_LUFS + Decibels::from(delta), ..config };
+            let shifted = plan(&existing, &shifted_config)?;
+            let expected = OpusGains {
+                output: standard.output,
+                track_r128: standard.track_r128.map(|g| g + Decibels::from(delta)),
+                album_r128: standard.album_r128.map(|g| g + Decibels::from(delta)),
+            };
+            assert!(
+                shifted.approx_eq(&expected, Decibels::default()),
+                "Unexpected gains {} for delta {}",
+                shifted,
+                delta
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn check_tags_detects_problems() -> Result<(), Error> {
+        use OutputGainMode::{Album, Track};
+        use TagProblem::{ImplausibleTrackAlbumDifference, MissingAlbumGain, MissingTrackGain, OffTarget};
+        use VolumeTarget::{NoChange, ZeroGain, LUFS};
+
+        let db = Decibels::from;
+        let gains = |output, track: Option<f64>, album: Option<f64>| OpusGains {
+            output: db(output),
+            track_r128: track.map(db),
+            album_r128: album.map(db),
+        };
+        let tolerance = db(0.01);
+        // (gains, target, mode, expected implied loudness, expected problems)
+        let cases = [
+            (gains(2.0, Some(-5.0), Some(-9.0)), LUFS(REPLAY_GAIN_LUFS), Track, Some(-18.0), vec![]),
+            (gains(-2.0, Some(-1.0), Some(-5.0)), LUFS(REPLAY_GAIN_LUFS), Album, Some(-18.0), vec![]),
+            (gains(-3.0, Some(0.0), None), LUFS(R128_LUFS), Track, Some(-23.0), vec![]),
+            (
+                gains(2.0, Some(-5.0), None),
+                LUFS(R128_LUFS),
+                Track,
+                Some(-18.0),
+                vec![OffTarget { expected_output: db(-3.0) }],
+            ),
+            (gains(2.0, None, None), LUFS(REPLAY_GAIN_LUFS), Track, None, vec![MissingTrackGain]),
+            (gains(2.0, Some(-5.0), None), LUFS(REPLAY_GAIN_LUFS), Album, None, vec![MissingAlbumGain]),
+            (gains(0.0, Some(-3.0), None), ZeroGain, Track, Some(-20.0), vec![]),
+            (gains(1.0, Some(-3.0), None), ZeroGain, Track, Some(-20.0), vec![OffTarget { expected_output: db(0.0) }]),
+            (gains(7.5, Some(-1.0), None), NoChange, Track, Some(-22.0), vec![]),
+            (
+                gains(0.0, Some(-5.0), Some(20.0)),
+                LUFS(REPLAY_GAIN_LUFS),
+                Track,
+                Some(-18.0),
+                vec![ImplausibleTrackAlbumDifference { difference: db(25.0) }],
+            ),
+        ];
+        for (existing, output_gain, output_gain_mode, expected_lufs, expected_problems) in cases {
+            let config = VolumeRewriterConfig::builder()
+                .target(output_gain)
+                .output_gain_mode(output_gain_mode)
+                .volumes_from_tags()
+                .build()?;
+            let check = check_tags(&existing, &config, tolerance)?;
+            assert_eq!(check.implied_lufs.map(|v| v.as_f64()), expected_lufs, "Unexpected loudness for {}", existing);
+            assert_eq!(check.problems, expected_problems, "Unexpected problems for {}", existing);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rewrite_from_tags_preserves_playback_loudness() -> Result<(), Error> {
+        let headers = |track_gain: &str| opus_headers(&[(TAG_TRACK_GAIN, track_gain)]);
+        let summarize = |headers: &CodecHeaders| GainsSummary::default().summarize(headers);
+        let config =
+            VolumeRewriterConfig::builder().target(VolumeTarget::LUFS(R128_LUFS)).volumes_from_tags().build()?;
+
+        // A track gain of -5 dB with no output gain implies a volume of -18 LUFS
+        let mut track_tagged = headers("-1280");
+        VolumeHeaderRewrite::from_tags(config).rewrite(&mut track_tagged)?;
+        let gains = summarize(&track_tagged)?;
+        let expected =
+            OpusGains { output: Decibels::from(-5.0), track_r128: Some(Decibels::default()), album_r128: None };
+        assert!(gains.approx_eq(&expected, Decibels::default()), "Unexpected gains {}", gains);
+
+        // Rewriting again changes nothing
+        let before = track_tagged.clone();
+        VolumeHeaderRewrite::from_tags(config).rewrite(&mut track_tagged)?;
+        assert_eq!(track_tagged, before);
+
+        let album_config = VolumeRewriterConfig { output_gain_mode: OutputGainMode::Album, ..config };
+        let result = VolumeHeaderRewrite::from_tags(album_config).rewrite(&mut headers("-1280"));
+        assert!(matches!(result, Err(Error::MissingR128Tag(tag)) if tag == TAG_ALBUM_GAIN));
+        Ok(())
+    }
+
+    #[test]
+    fn rewrite_preserves_album_gain() -> Result<(), Error> {
+        let mut headers = opus_headers(&[(TAG_ALBUM_GAIN, "-1024")]);
+        let config = track_config(VolumeTarget::LUFS(REPLAY_GAIN_LUFS), -21.0).preserve_album_gain(true).build()?;
+        VolumeHeaderRewrite::new(config).rewrite(&mut headers)?;
+        let gains = GainsSummary::default().summarize(&headers)?;
+        // An album gain of -4 dB with no output gain implies an album volume of -19 LUFS
+        let expected = OpusGains {
+            output: Decibels::from(3.0),
+            track_r128: Some(Decibels::from(-5.0)),
+            album_r128: Some(Decibels::from(-7.0)),
+        };
+        assert!(gains.approx_eq(&expected, Decibels::default()), "Unexpected gains {}", gains);
+        Ok(())
+    }
+
+    #[test]
+    fn reference_tag_reflects_target() -> Result<(), Error> {
+        use VolumeTarget::{NoChange, ZeroGain, LUFS};
+
+        let headers = || opus_headers(&[(TAG_REFERENCE_LOUDNESS, "-14.00 LUFS")]);
+        let reference = |headers: &CodecHeaders| match headers {
+            CodecHeaders::Opus(_, comment_header) => comment_header.get_first(TAG_REFERENCE_LOUDNESS).map(String::from),
+            CodecHeaders::Vorbis(_, _) => unreachable!(),
+        };
+        let max_headroom = max_headroom_target([-20.5, -9.25].map(|volume| (Decibels::from(volume), None))).unwrap();
+        // (target, expected tag)
+        let cases = [
+            (LUFS(REPLAY_GAIN_LUFS), Some("-18.00 LUFS")),
+            (LUFS(R128_LUFS), Some("-23.00 LUFS")),
+            (LUFS(max_headroom), Some("-20.50 LUFS")),
+            (ZeroGain, None),
+            (NoChange, None),
+        ];
+        for (output_gain, expected) in cases {
+            let config = track_config(output_gain, -20.5).build()?;
+            let mut rewritten = headers();
+            VolumeHeaderRewrite::new(config).with_reference_tag().rewrite(&mut rewritten)?;
+            assert_eq!(reference(&rewritten).as_deref(), expected, "Unexpected tag for {:?}", output_gain);
+
+            // The tag is left alone unless requested
+            let mut untouched = headers();
+            VolumeHeaderRewrite::new(config).rewrite(&mut untouched)?;
+            assert_eq!(reference(&untouched).as_deref(), Some("-14.00 LUFS"));
+        }
+
+        // Clearing tags removes the reference tag along with the R128 tags
+        let clear = VolumeRewriterConfig::builder().target(NoChange).build()?;
+        let mut cleared = headers();
+        VolumeHeaderRewrite::new(clear).with_reference_tag().rewrite(&mut cleared)?;
+        assert_eq!(reference(&cleared), None);
+        Ok(())
+    }
+
+    #[test]
+    fn foreign_tags_follow_policy() -> Result<(), Error> {
+        use crate::header::replaygain::{TAG_RG_TRACK_GAIN, TAG_RG_TRACK_PEAK};
+
+        let headers = || opus_headers(&[(TAG_RG_TRACK_GAIN, "-2.00 dB"), (TAG_RG_TRACK_PEAK, "0.500000")]);
+        let foreign = |headers: &CodecHeaders| match headers {
+            CodecHeaders::Opus(_, comment_header) => {
+                [TAG_RG_TRACK_GAIN, TAG_RG_TRACK_PEAK].map(|tag| comment_header.get_first(tag).map(String::from))
+            }
+            CodecHeaders::Vorbis(_, _) => unreachable!(),
+        };
+        // The output gain rises from 0 dB to 2 dB
+        let config = track_config(VolumeTarget::LUFS(REPLAY_GAIN_LUFS), -20.0).build()?;
+        let original = [Some(String::from("-2.00 dB")), Some(String::from("0.500000"))];
+        let cases = [
+            (ForeignTagPolicy::Keep, original.clone()),
+            (ForeignTagPolicy::Fix, [Some(String::from("-4.00 dB")), Some(String::from("0.629463"))]),
+            (ForeignTagPolicy::Drop, [None, None]),
+        ];
+        for (policy, expected) in cases {
+            let mut rewritten = headers();
+            VolumeHeaderRewrite::new(config).with_foreign_tag_policy(policy).rewrite(&mut rewritten)?;
+            assert_eq!(foreign(&rewritten), expected, "Unexpected tags for {:?}", policy);
+        }
+
+        // Nothing is done when the output gain does not change
+        let unchanged = VolumeRewriterConfig { output_gain: VolumeTarget::NoChange, ..config };
+        for policy in [ForeignTagPolicy::Fix, ForeignTagPolicy::Drop] {
+            let mut rewritten = headers();
+            VolumeHeaderRewrite::new(unchanged).with_foreign_tag_policy(policy).rewrite(&mut rewritten)?;
+            assert_eq!(foreign(&rewritten), original);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn classifier_replaces_class_tag() -> Result<(), Error> {
+        let classifier: LoudnessClassifier = "LOUDNESS_CLASS:quiet<-30,normal<-18,loud".parse()?;
+        let headers = || opus_headers(&[("LOUDNESS_CLASS", "old"), ("LOUDNESS_CLASS", "older")]);
+        let classes = |headers: &CodecHeaders| -> Vec<String> {
+            match headers {
+                CodecHeaders::Opus(_, comment_header) => comment_header
+                    .iter()
+                    .filter(|(key, _)| *key == "LOUDNESS_CLASS")
+                    .map(|(_, v)| v.to_string())
+                    .collect(),
+                CodecHeaders::Vorbis(_, _) => unreachable!(),
+            }
+        };
+        for (track_volume, expected) in [(-35.0, "quiet"), (-20.0, "normal"), (-10.0, "loud")] {
+            let mut rewritten = headers();
+            let config = track_config(VolumeTarget::LUFS(REPLAY_GAIN_LUFS), track_volume).build()?;
+            let rewrite = VolumeHeaderRewrite::new(config).with_classifier(classifier.clone());
+            rewrite.rewrite(&mut rewritten)?;
+            assert_eq!(classes(&rewritten), vec![expected.to_string()]);
+        }
+
+        // Without a track volume there is nothing to classify
+        let mut untouched = headers();
+        let config = VolumeRewriterConfig::builder().target(VolumeTarget::NoChange).build()?;
+        VolumeHeaderRewrite::new(config).with_classifier(classifier).rewrite(&mut untouched)?;
+        assert_eq!(classes(&untouched), vec!["old".to_string(), "older".to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn builder_rejects_inconsistent_configs() {
+        use OutputGainMode::{Album, Track};
+        use VolumeTarget::{NoChange, ZeroGain, LUFS};
+
+        let volume = Some(Decibels::from(-14.0));
+        let target = LUFS(REPLAY_GAIN_LUFS);
+        let infinite = Decibels::from(f64::INFINITY);
+        let builder = |target, mode, track_volume, album_volume| {
+            VolumeRewriterConfig::builder()
+                .target(target)
+                .output_gain_mode(mode)
+                .track_volume(track_volume)
+                .album_volume(album_volume)
+        };
+        // (target, mode, track volume, album volume, expected error)
+        let invalid = [
+            (target, Track, None, None, ConfigError::MissingTrackVolume),
+            (target, Track, None, volume, ConfigError::MissingTrackVolume),
+            (target, Album, None, None, ConfigError::MissingAlbumVolume),
+            (target, Album, volume, None, ConfigError::MissingAlbumVolume),
+            (LUFS(infinite), Track, volume, None, ConfigError::NonFiniteTarget(infinite)),
+            (LUFS(infinite), Album, None, None, ConfigError::NonFiniteTarget(infinite)),
+        ];
+        for (target, mode, track_volume, album_volume, expected) in invalid {
+            let result = builder(target, mode, track_volume, album_volume).build();
+            assert_eq!(result.err(), Some(expected));
+        }
+
+        // NaN is unequal to itself so cannot be compared with `assert_eq`
+        let nan = builder(LUFS(Decibels::from(f64::NAN)), Track, volume, None).build();
+        assert!(matches!(nan, Err(ConfigError::NonFiniteTarget(target)) if target.as_f64().is_nan()));
+        let reference = VolumeRewriterConfig::builder().r128_reference(Decibels::from(f64::NEG_INFINITY)).build();
+        assert!(matches!(reference, Err(ConfigError::NonFiniteReference(_))));
+        let from_tags = builder(target, Track, volume, None).volumes_from_tags().build();
+        assert_eq!(from_tags.err(), Some(ConfigError::VolumesFromTagsConflict));
+
+        // Volumes are only needed to compute an output gain reaching a loudness
+        let valid = [
+            builder(target, Track, volume, None),
+            builder(target, Album, None, volume),
+            builder(ZeroGain, Album, None, None),
+            builder(NoChange, Track, None, None),
+            builder(target, Album, None, None).volumes_from_tags(),
+        ];
+        for builder in valid {
+            assert!(builder.build().is_ok(), "{:?} was rejected", builder);
+        }
+    }
+
+    #[test]
+    fn invalid_config_is_an_error_when_planning() {
+        let config = VolumeRewriterConfig {
+            output_gain: VolumeTarget::LUFS(REPLAY_GAIN_LUFS),
+            output_gain_mode: OutputGainMode::Album,
+            track_volume: Some(Decibels::from(-14.0)),
+            album_volume: None,
+            r128_reference: R128_LUFS,
+            preserve_album_gain: false,
+            max_output_gain: None,
+        };
+        let existing = OpusGains { output: Decibels::default(), track_r128: None, album_r128: None };
+        let result = plan(&existing, &config);
+        assert!(matches!(result, Err(Error::InvalidVolumeRewriterConfig(ConfigError::MissingAlbumVolume))));
+        assert_eq!(result.unwrap_err().severity(), crate::Severity::Internal);
+    }
 }
@@ -9,7 +9,9 @@ const FRAMING_BYTE: u8 = 1;
 
 /// Vorbis-specific comment header logic
 #[derive(Clone, Debug, Default, PartialEq)]
-pub struct Specifics {}
+pub struct Specifics {
+    trailing_data: Vec<u8>,
+}
 
 impl header::CommentHeaderSpecifics for Specifics {
     fn get_magic() -> Cow<'static, [u8]> { COMMENT_MAGIC.into() }
@@ -17,21 +19,31 @@ impl header::CommentHeaderSpecifics for Specifics {
     fn read_suffix<R: Read>(&mut self, reader: &mut R) -> Result<(), Error> {
         let mut buffer = [0u8];
         if reader.read(&mut buffer).map_err(Error::ReadError)? != 1 || (buffer[0] & 1) == 0 {
-            Err(Error::MalformedCommentHeader)
-        } else {
-            Ok(())
+            return Err(Error::MalformedCommentHeader);
         }
+        // Some encoders emit data after the framing byte. This has no defined
+        // meaning, but is preserved so that rewrites are minimal.
+        reader.read_to_end(&mut self.trailing_data).map_err(Error::ReadError)?;
+        Ok(())
     }
 
     fn write_suffix<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
         let buffer = [FRAMING_BYTE];
-        writer.write_all(&buffer).map_err(Error::WriteError)
+        writer.write_all(&buffer).map_err(Error::WriteError)?;
+        writer.write_all(&self.trailing_data).map_err(Error::WriteError)
     }
 }
 
 /// Manipulates an Ogg Vorbis comment header
 pub type CommentHeader = CommentHeaderGeneric<Specifics>;
 
+impl CommentHeader {
+    /// Any data following the framing byte, which is preserved when the header
+    /// is rewritten. This is empty for well-formed headers.
+    #[must_use]
+    pub fn trailing_data(&self) -> &[u8] { &self.specifics().trailing_data }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
@@ -68,5 +80,18 @@ mod tests {
         let mut specifics = Specifics::default();
         let mut reader = Cursor::new(&[0x1]);
         assert!(specifics.read_suffix(&mut reader).is_ok());
+        assert!(specifics.trailing_data.is_empty());
+    }
+
+    #[test]
+    fn trailing_data_is_preserved() -> Result<(), Error> {
+        let mut specifics = Specifics::default();
+        let data = [0x1, 0x0, 0xAB, 0xCD];
+        specifics.read_suffix(&mut Cursor::new(&data))?;
+        assert_eq!(specifics.trailing_data, &data[1..]);
+        let mut suffix = Vec::new();
+        specifics.write_suffix(&mut suffix)?;
+        assert_eq!(suffix, data);
+        Ok(())
     }
 }
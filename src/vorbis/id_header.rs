@@ -58,6 +58,8 @@ impl header::IdHeader for IdHeader {
     }
 
     fn preskip_samples(&self) -> usize { 0 }
+
+    fn is_dirty(&self) -> bool { false }
 }
 
 impl IdHeader {
@@ -0,0 +1,62 @@
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Cursor;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use common::{opus_file, StreamSpec, FRAME_SIZE};
+use zoog::opus::VolumeAnalyzer;
+
+/// Counts allocations so the cost of analysis can be measured. This file
+/// contains a single test so that no other test allocates concurrently.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) { System.dealloc(ptr, layout) }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const NUM_FILES: usize = 1000;
+
+/// Runs `body`, returning the number of allocations and bytes allocated
+fn count_allocations<F: FnOnce()>(body: F) -> (usize, usize) {
+    let (allocations, bytes) = (ALLOCATIONS.load(Ordering::Relaxed), ALLOCATED_BYTES.load(Ordering::Relaxed));
+    body();
+    (ALLOCATIONS.load(Ordering::Relaxed) - allocations, ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes)
+}
+
+#[test]
+fn reused_analyzer_allocates_less() {
+    let data = opus_file(&[StreamSpec { num_samples: 5 * FRAME_SIZE, ..StreamSpec::default() }]);
+    let mut fresh_lufs = Vec::with_capacity(NUM_FILES);
+    let fresh = count_allocations(|| {
+        for _ in 0..NUM_FILES {
+            let mut analyzer = VolumeAnalyzer::default();
+            fresh_lufs.push(analyzer.analyze_stream(Cursor::new(&data)).unwrap().unwrap().lufs);
+        }
+    });
+
+    let mut reused_lufs = Vec::with_capacity(NUM_FILES);
+    let mut analyzer = VolumeAnalyzer::default();
+    let reused = count_allocations(|| {
+        for _ in 0..NUM_FILES {
+            analyzer.reset_for_new_file();
+            reused_lufs.push(analyzer.analyze_stream(Cursor::new(&data)).unwrap().unwrap().lufs);
+        }
+    });
+
+    assert_eq!(fresh_lufs, reused_lufs);
+    assert!(reused.0 < fresh.0, "{} allocations when reused, {} when fresh", reused.0, fresh.0);
+    assert!(reused.1 * 2 < fresh.1, "{} bytes allocated when reused, {} when fresh", reused.1, fresh.1);
+}
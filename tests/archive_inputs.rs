@@ -0,0 +1,79 @@
+mod common;
+
+use std::path::Path;
+
+use common::run;
+
+/// Writes an archive holding a tagged Opus file both with and without
+/// compression, returning the archive path
+#[cfg(feature = "archive")]
+fn write_archive(dir: &Path) -> String {
+    use std::io::Write;
+
+    use common::{opus_file, StreamSpec};
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    let data = opus_file(&[StreamSpec::default().with_tag("TITLE", "Zipped")]);
+    let path = dir.join("album.zip");
+    let mut writer = ZipWriter::new(std::fs::File::create(&path).unwrap());
+    for (name, method) in [("stored.opus", CompressionMethod::Stored), ("deflated.opus", CompressionMethod::Deflated)] {
+        writer.start_file(format!("disc 1/{}", name), FileOptions::default().compression_method(method)).unwrap();
+        writer.write_all(&data).unwrap();
+    }
+    writer.finish().unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+/// The name of a file inside an archive which does not need to exist
+fn member_path(dir: &Path) -> String { format!("{}!track.opus", dir.join("album.zip").display()) }
+
+#[test]
+fn archive_members_are_rejected_when_modifying_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let member = member_path(dir.path());
+    let output = run(env!("CARGO_BIN_EXE_opusgain"), &[], &[&member]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("inside an archive"), "{}", stderr);
+
+    for mode in ["-m", "-r"] {
+        let output = run(env!("CARGO_BIN_EXE_zoogcomment"), &[mode, "-t", "TITLE=Changed"], &[&member]);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("inside an archive"), "{}", stderr);
+    }
+}
+
+#[test]
+#[cfg(not(feature = "archive"))]
+fn archive_members_require_archive_feature() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = run(env!("CARGO_BIN_EXE_opusgain"), &["--analyze-only"], &[member_path(dir.path())]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("`archive` feature"), "{}", stderr);
+}
+
+#[test]
+#[cfg(feature = "archive")]
+fn archive_members_can_be_listed_and_analyzed() {
+    use common::{opusgain, zoogcomment};
+
+    let dir = tempfile::tempdir().unwrap();
+    let archive = write_archive(dir.path());
+    let before = std::fs::read(&archive).unwrap();
+    for name in ["stored.opus", "deflated.opus"] {
+        let member = format!("{}!disc 1/{}", archive, name);
+        let output = zoogcomment(&["-l"], &[&member]);
+        assert!(String::from_utf8(output.stdout).unwrap().contains("TITLE=Zipped"));
+
+        let output = opusgain(&["--analyze-only", "--format", "json"], &[&member]);
+        let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(report["tracks"][0]["path"], member.as_str());
+    }
+    assert_eq!(std::fs::read(&archive).unwrap(), before, "The archive was modified");
+
+    let output = run(env!("CARGO_BIN_EXE_opusgain"), &["--analyze-only"], &[format!("{}!missing.opus", archive)]);
+    assert!(!output.status.success());
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use std::path::Path;
+
+use common::{opusgain, read_headers, write_opus_file, Signal, StreamSpec};
+use zoog::header::CommentList as _;
+use zoog::header_rewriter::CodecHeaders;
+
+const SPEC: &str = "LOUDNESS_CLASS:quiet<-30,normal<-5,loud";
+
+fn classes(path: &Path) -> Vec<String> {
+    match read_headers(&std::fs::read(path).unwrap()) {
+        CodecHeaders::Opus(_, comments) => {
+            comments.iter().filter(|(key, _)| *key == "LOUDNESS_CLASS").map(|(_, value)| value.to_string()).collect()
+        }
+        CodecHeaders::Vorbis(_, _) => panic!("Unexpected Vorbis stream"),
+    }
+}
+
+#[test]
+fn opusgain_sets_loudness_class() {
+    let dir = tempfile::tempdir().unwrap();
+    let spec = StreamSpec { signal: Signal::Sine { frequency: 997.0, amplitude: 0.25 }, ..StreamSpec::default() };
+    let path = write_opus_file(dir.path(), "track.opus", &[spec.with_tag("LOUDNESS_CLASS", "stale")]);
+    let original = std::fs::read(&path).unwrap();
+
+    // A dry run shows the class without writing it
+    let output = opusgain(&["--dry-run", "--classify", SPEC], &[&path]);
+    assert!(String::from_utf8(output.stdout).unwrap().contains("Loudness class: LOUDNESS_CLASS=normal\n"));
+    assert_eq!(std::fs::read(&path).unwrap(), original);
+
+    opusgain(&["--classify", SPEC], &[&path]);
+    assert_eq!(classes(&path), ["normal"]);
+
+    // The class alone is enough for a file to be rewritten
+    opusgain(&["--classify", "LOUDNESS_CLASS:any"], &[&path]);
+    assert_eq!(classes(&path), ["any"]);
+}
@@ -0,0 +1,54 @@
+//! Helpers shared by the integration tests.
+//!
+//! Files are synthesized when the tests run rather than committed as binary
+//! fixtures, using the generator in `zoog::testing`.
+
+// Each integration test uses a different subset of this module
+#![allow(dead_code)]
+
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use zoog::header::CommentList as _;
+use zoog::header_rewriter::CodecHeaders;
+pub use zoog::testing::*;
+
+/// Runs `program` with `args` followed by `paths`, without checking whether it
+/// succeeded
+pub fn run<P: AsRef<OsStr>>(program: &str, args: &[&str], paths: &[P]) -> Output {
+    Command::new(program).args(args).args(paths).output().unwrap()
+}
+
+/// Fails the test with the standard error of `output` if the command did not
+/// succeed
+pub fn assert_success(output: &Output) {
+    assert!(output.status.success(), "Command failed: {}", String::from_utf8_lossy(&output.stderr));
+}
+
+/// Runs `opusgain` with `args` followed by `paths`, failing the test if it
+/// does not succeed
+pub fn opusgain<P: AsRef<OsStr>>(args: &[&str], paths: &[P]) -> Output {
+    let output = run(env!("CARGO_BIN_EXE_opusgain"), args, paths);
+    assert_success(&output);
+    output
+}
+
+/// Runs `zoogcomment` with `args` followed by `paths`, failing the test if it
+/// does not succeed
+pub fn zoogcomment<P: AsRef<OsStr>>(args: &[&str], paths: &[P]) -> Output {
+    let output = run(env!("CARGO_BIN_EXE_zoogcomment"), args, paths);
+    assert_success(&output);
+    output
+}
+
+/// Returns the first value of the comment `key` in the Opus file at `path`
+pub fn comment(path: &Path, key: &str) -> Option<String> {
+    match read_headers(&std::fs::read(path).unwrap()) {
+        CodecHeaders::Opus(_, comments) => comments.get_first(key).map(String::from),
+        CodecHeaders::Vorbis(_, _) => panic!("Unexpected Vorbis stream"),
+    }
+}
+
+/// Returns the artist of the Opus file at `path`
+pub fn artist(path: &Path) -> Option<String> { comment(path, "ARTIST") }
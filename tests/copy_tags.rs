@@ -0,0 +1,101 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use common::{read_headers, write_opus_file, zoogcomment, StreamSpec};
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use zoog::header::CommentList as _;
+use zoog::header_rewriter::CodecHeaders;
+
+/// Writes an Ogg Vorbis file holding only identification and comment headers,
+/// which is all that is read when copying tags
+fn write_vorbis_file(dir: &Path, tags: &[(&str, &str)]) -> PathBuf {
+    let mut id_header = b"\x01vorbis".to_vec();
+    id_header.extend([0, 0, 0, 0, 2, 0x44, 0xac, 0, 0]);
+    id_header.extend([0; 12]);
+    id_header.extend([0xb8, 1]);
+
+    let vendor = b"vorbis-fixture";
+    let mut comment_header = b"\x03vorbis".to_vec();
+    comment_header.write_u32::<LittleEndian>(vendor.len().try_into().unwrap()).unwrap();
+    comment_header.extend(vendor);
+    comment_header.write_u32::<LittleEndian>(tags.len().try_into().unwrap()).unwrap();
+    for (key, value) in tags {
+        let comment = format!("{}={}", key, value);
+        comment_header.write_u32::<LittleEndian>(comment.len().try_into().unwrap()).unwrap();
+        comment_header.extend(comment.as_bytes());
+    }
+    comment_header.push(1);
+
+    let mut data = Vec::new();
+    let mut writer = PacketWriter::new(&mut data);
+    writer.write_packet(id_header, 7, PacketWriteEndInfo::EndPage, 0).unwrap();
+    writer.write_packet(comment_header, 7, PacketWriteEndInfo::EndStream, 0).unwrap();
+    drop(writer);
+    let path = dir.join("master.ogg");
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+/// Returns the comments of an Opus file as field name and value pairs
+fn opus_comments(path: &Path) -> Vec<(String, String)> {
+    match read_headers(&std::fs::read(path).unwrap()) {
+        CodecHeaders::Opus(_, comments) => comments.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        CodecHeaders::Vorbis(_, _) => panic!("Unexpected Vorbis stream"),
+    }
+}
+
+fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+    expected.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+const MASTER_TAGS: [(&str, &str); 4] = [
+    ("TITLE", "Song"),
+    ("REPLAYGAIN_TRACK_GAIN", "-6.54 dB"),
+    ("REPLAYGAIN_TRACK_PEAK", "0.988553"),
+    ("ARTIST", "Band"),
+];
+
+#[test]
+fn gain_tags_are_skipped_when_copying_between_codecs() {
+    let dir = tempfile::tempdir().unwrap();
+    let master = write_vorbis_file(dir.path(), &MASTER_TAGS);
+    let transcode = write_opus_file(dir.path(), "transcode.opus", &[StreamSpec::default()]);
+    let output = zoogcomment(&["-r", "--copy-from", master.to_str().unwrap()], &[&transcode]);
+    assert_eq!(opus_comments(&transcode), pairs(&[("TITLE", "Song"), ("ARTIST", "Band")]));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    for tag in ["REPLAYGAIN_TRACK_GAIN", "REPLAYGAIN_TRACK_PEAK"] {
+        assert!(stderr.contains(&format!("not copying {}", tag)), "{}", stderr);
+    }
+    assert!(stderr.contains("--translate-gain-tags"), "{}", stderr);
+}
+
+#[test]
+fn gain_tags_are_translated_when_requested() {
+    let dir = tempfile::tempdir().unwrap();
+    let master = write_vorbis_file(dir.path(), &MASTER_TAGS);
+    // An output gain of +1 dB leaves 1 dB less for the R128 gain to apply
+    let spec = StreamSpec { output_gain: 256, ..StreamSpec::default() }.with_tag("TITLE", "Old");
+    let transcode = write_opus_file(dir.path(), "transcode.opus", &[spec]);
+    let output = zoogcomment(
+        &["-m", "-d", "TITLE", "--copy-from", master.to_str().unwrap(), "--translate-gain-tags"],
+        &[&transcode],
+    );
+    let expected = [("TITLE", "Song"), ("R128_TRACK_GAIN", "-3210"), ("ARTIST", "Band")];
+    assert_eq!(opus_comments(&transcode), pairs(&expected));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("not copying REPLAYGAIN_TRACK_PEAK"), "{}", stderr);
+}
+
+#[test]
+fn tags_are_copied_verbatim_between_opus_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let tags = [("TITLE", "Song"), ("R128_TRACK_GAIN", "-1280")];
+    let spec = tags.iter().fold(StreamSpec::default(), |spec, (field, value)| spec.with_tag(field, value));
+    let source = write_opus_file(dir.path(), "source.opus", &[spec]);
+    let target = write_opus_file(dir.path(), "target.opus", &[StreamSpec::default()]);
+    let output = zoogcomment(&["-r", "--copy-from", source.to_str().unwrap()], &[&target]);
+    assert_eq!(opus_comments(&target), pairs(&tags));
+    assert!(!String::from_utf8(output.stderr).unwrap().contains("not copying"));
+}
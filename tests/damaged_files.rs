@@ -0,0 +1,44 @@
+mod common;
+
+use std::path::Path;
+
+use common::{opusgain, write_opus_file, Anomaly, StreamSpec, SAMPLE_RATE};
+
+/// Analyzes the file at `path`, returning its entry in the JSON report and the
+/// warnings printed
+fn analyze(args: &[&str], path: &Path) -> (serde_json::Value, String) {
+    let output = opusgain(&[&["--analyze-only", "--format", "json"], args].concat(), &[path]);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    (report["tracks"][0].clone(), String::from_utf8(output.stderr).unwrap())
+}
+
+#[test]
+fn missing_pages_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let spec = StreamSpec { num_samples: 10 * SAMPLE_RATE, ..StreamSpec::default() };
+    let intact = write_opus_file(dir.path(), "intact.opus", &[spec.clone()]);
+    // Each page holds 200ms of audio so 4 seconds are lost from the middle
+    let damaged = spec.with_anomaly(Anomaly::MissingPages { first: 10, count: 20 });
+    let damaged = write_opus_file(dir.path(), "damaged.opus", &[damaged]);
+
+    let (track, stderr) = analyze(&[], &intact);
+    assert!(track.get("granule_duration_seconds").is_none(), "{}", track);
+    assert!(!stderr.contains("Warning"), "{}", stderr);
+
+    let (track, stderr) = analyze(&[], &damaged);
+    let seconds = |field: &str| track[field].as_f64().unwrap();
+    assert!((seconds("duration_seconds") - 6.0).abs() < 0.05, "{}", track);
+    assert!((seconds("granule_duration_seconds") - 10.0).abs() < 0.01, "{}", track);
+    let warning = format!("Warning: {}: Granule positions imply a duration of 10.000s", damaged.display());
+    assert!(stderr.contains(&warning), "{}", stderr);
+
+    // A mismatch within the threshold is not reported
+    let (track, stderr) = analyze(&["--max-duration-mismatch", "5"], &damaged);
+    assert!(track.get("granule_duration_seconds").is_none(), "{}", track);
+    assert!(!stderr.contains("Warning"), "{}", stderr);
+
+    // The mismatch is also reported when gains are written
+    let output = opusgain(&["--format", "json"], &[&damaged]);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(report["files"][0]["granule_duration_s"].is_number(), "{}", report);
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use std::path::Path;
+
+use common::{comment, opusgain, run, write_opus_file, Signal, StreamSpec};
+use zoog::opus::TAG_ALBUM_GAIN;
+
+fn album_gain(path: &Path) -> Option<String> { comment(path, TAG_ALBUM_GAIN) }
+
+#[test]
+fn opusgain_expands_directories_one_level() {
+    let dir = tempfile::tempdir().unwrap();
+    let album = dir.path().join("album");
+    let nested = album.join("extras");
+    std::fs::create_dir_all(&nested).unwrap();
+    let spec = |amplitude| StreamSpec { signal: Signal::Sine { frequency: 997.0, amplitude }, ..StreamSpec::default() };
+    let first = write_opus_file(&album, "01.opus", &[spec(0.25)]);
+    let second = write_opus_file(&album, "02.opus", &[spec(0.1)]);
+    let ignored = write_opus_file(&nested, "03.opus", &[spec(0.5)]);
+    std::fs::write(album.join("notes.txt"), b"Not an Ogg file").unwrap();
+
+    let output = run(env!("CARGO_BIN_EXE_opusgain"), &["--no-expand-dirs"], &[&album]);
+    assert!(!output.status.success());
+    assert_eq!(album_gain(&first), None);
+
+    opusgain(&["-a"], &[&album]);
+    // The directory is treated as a single album
+    assert!(album_gain(&first).is_some());
+    assert_eq!(album_gain(&first), album_gain(&second));
+    assert_eq!(album_gain(&ignored), None);
+
+    // An empty directory is only a warning
+    let empty = dir.path().join("empty");
+    std::fs::create_dir(&empty).unwrap();
+    let output = opusgain(&[], &[&empty]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("contains no Ogg files"));
+}
+
+#[test]
+fn zoogcomment_rejects_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = run(env!("CARGO_BIN_EXE_zoogcomment"), &["-l"], &[dir.path()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is a directory"));
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use std::path::Path;
+use std::time::SystemTime;
+
+use common::{comment, opusgain, run, write_opus_file, zoogcomment, Signal, StreamSpec};
+use zoog::opus::TAG_TRACK_GAIN;
+
+fn modified(path: &Path) -> SystemTime { std::fs::metadata(path).unwrap().modified().unwrap() }
+
+#[test]
+fn opusgain_writes_output_elsewhere() {
+    let dir = tempfile::tempdir().unwrap();
+    let spec = StreamSpec { signal: Signal::Sine { frequency: 997.0, amplitude: 0.25 }, ..StreamSpec::default() };
+    let input = write_opus_file(dir.path(), "input.opus", &[spec]);
+    let output = dir.path().join("output.opus");
+    let (original, original_modified) = (std::fs::read(&input).unwrap(), modified(&input));
+    let binary = env!("CARGO_BIN_EXE_opusgain");
+
+    opusgain(&["--dry-run-output", output.to_str().unwrap()], &[&input]);
+    assert!(comment(&output, TAG_TRACK_GAIN).is_some());
+    assert_eq!(std::fs::read(&input).unwrap(), original);
+    assert_eq!(modified(&input), original_modified);
+
+    // An existing output is only replaced with --overwrite
+    let args = ["--dry-run-output", output.to_str().unwrap()];
+    assert!(!run(binary, &args, &[&input]).status.success());
+    opusgain(&[&args[..], &["--overwrite"][..]].concat(), &[&input]);
+
+    // Only a single input is permitted
+    let second = write_opus_file(dir.path(), "second.opus", &[StreamSpec::default()]);
+    assert!(!run(binary, &[&args[..], &["--overwrite"][..]].concat(), &[&input, &second]).status.success());
+    assert_eq!(std::fs::read(&input).unwrap(), original);
+}
+
+#[test]
+fn zoogcomment_writes_output_elsewhere() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_opus_file(dir.path(), "input.opus", &[StreamSpec::default().with_tag("ARTIST", "Someone")]);
+    let output = dir.path().join("output.opus");
+    let (original, original_modified) = (std::fs::read(&input).unwrap(), modified(&input));
+    let binary = env!("CARGO_BIN_EXE_zoogcomment");
+
+    let args = ["-m", "-t", "TITLE=Something", "--dry-run-output", output.to_str().unwrap()];
+    zoogcomment(&args, &[&input]);
+    assert_eq!(comment(&output, "TITLE").as_deref(), Some("Something"));
+    assert_eq!(comment(&output, "ARTIST").as_deref(), Some("Someone"));
+    assert_eq!(std::fs::read(&input).unwrap(), original);
+    assert_eq!(modified(&input), original_modified);
+
+    // Without changes the output is a copy of the input
+    let args = ["-m", "--dry-run-output", output.to_str().unwrap()];
+    assert!(!run(binary, &args, &[&input]).status.success());
+    zoogcomment(&[&args[..], &["--overwrite"][..]].concat(), &[&input]);
+    assert_eq!(std::fs::read(&output).unwrap(), original);
+
+    // The input itself is never replaced
+    let args = ["-m", "-t", "TITLE=Something", "--overwrite", "--dry-run-output", input.to_str().unwrap()];
+    assert!(!run(binary, &args, &[&input]).status.success());
+    assert_eq!(std::fs::read(&input).unwrap(), original);
+}
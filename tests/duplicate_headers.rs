@@ -0,0 +1,87 @@
+mod common;
+
+use std::io::Cursor;
+
+use common::{opus_file, opusgain, read_packets, write_opus_file, Anomaly, StreamSpec};
+use zoog::comment_rewrite::{CommentHeaderRewrite, CommentHeaderSummary, CommentRewriterAction, CommentRewriterConfig};
+use zoog::header_rewriter::{rewrite_stream_with_stats, DuplicateHeaderAction, RewriteStats, SubmitResult};
+use zoog::interrupt::Never;
+use zoog::opus::VolumeAnalyzer;
+
+fn spec() -> StreamSpec { StreamSpec::default().with_tag("TITLE", "Fixture") }
+
+fn duplicated() -> Vec<u8> { opus_file(&[spec().with_anomaly(Anomaly::DuplicateCommentHeader)]) }
+
+/// Rewrites `data` without changing any comments, returning the output,
+/// whether the headers changed and the statistics of the rewrite
+fn rewrite(data: &[u8], action: DuplicateHeaderAction) -> (Vec<u8>, bool, RewriteStats) {
+    let config = CommentRewriterConfig { action: CommentRewriterAction::NoChange };
+    let rewrite = CommentHeaderRewrite::new(config).with_duplicate_header_action(action);
+    let mut output = Vec::new();
+    let mut stats = RewriteStats::default();
+    let result = rewrite_stream_with_stats(
+        rewrite,
+        CommentHeaderSummary::default(),
+        Cursor::new(data),
+        &mut output,
+        false,
+        &Never::default(),
+        &mut stats,
+    );
+    let changed = match result {
+        Ok(SubmitResult::HeadersChanged { .. }) => true,
+        Ok(SubmitResult::HeadersUnchanged(_)) => false,
+        r => panic!("Unexpected rewrite result: {:?}", r),
+    };
+    (output, changed, stats)
+}
+
+#[test]
+fn duplicate_header_is_preserved_by_default() {
+    let data = duplicated();
+    let (output, changed, stats) = rewrite(&data, DuplicateHeaderAction::Preserve);
+    assert!(!changed);
+    assert_eq!(stats.duplicate_headers, 1);
+    assert_eq!(read_packets(&output).len(), read_packets(&data).len());
+
+    // Nothing is reported for a well-formed file
+    let (_, _, stats) = rewrite(&opus_file(&[spec()]), DuplicateHeaderAction::Preserve);
+    assert_eq!(stats.duplicate_headers, 0);
+}
+
+#[test]
+fn duplicate_header_can_be_dropped() {
+    let (output, changed, stats) = rewrite(&duplicated(), DuplicateHeaderAction::Drop);
+    assert!(changed);
+    assert_eq!(stats.duplicate_headers, 1);
+    let expected: Vec<_> = read_packets(&opus_file(&[spec()])).into_iter().map(|p| p.data).collect();
+    let packets: Vec<_> = read_packets(&output).into_iter().map(|p| p.data).collect();
+    assert_eq!(packets, expected);
+}
+
+#[test]
+fn duplicate_header_is_not_analyzed() {
+    let mut analyzer = VolumeAnalyzer::default();
+    let expected = analyzer.analyze_stream(Cursor::new(opus_file(&[spec()]))).unwrap().unwrap();
+    assert_eq!(analyzer.duplicate_headers(), 0);
+    let measurement = analyzer.analyze_stream(Cursor::new(duplicated())).unwrap().unwrap();
+    assert_eq!(analyzer.duplicate_headers(), 1);
+    assert_eq!(measurement.lufs, expected.lufs);
+    assert_eq!(measurement.duration, expected.duration);
+}
+
+#[test]
+fn opusgain_warns_about_or_drops_duplicate_header() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_opus_file(dir.path(), "duplicated.opus", &[spec().with_anomaly(Anomaly::DuplicateCommentHeader)]);
+    let num_packets = read_packets(&std::fs::read(&path).unwrap()).len();
+    let run = |args: &[&str]| String::from_utf8(opusgain(args, &[&path]).stderr).unwrap();
+
+    let stderr = run(&[]);
+    assert!(stderr.contains("--drop-duplicate-headers"), "No warning in: {}", stderr);
+    assert_eq!(read_packets(&std::fs::read(&path).unwrap()).len(), num_packets);
+
+    run(&["--drop-duplicate-headers"]);
+    assert_eq!(read_packets(&std::fs::read(&path).unwrap()).len(), num_packets - 1);
+    assert!(!run(&[]).contains("duplicate"));
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use common::{opusgain, write_opus_file, Signal, StreamSpec};
+
+/// Writes a quiet and a loud track to `dir` so that the album loudness
+/// depends on how much each contributes
+fn write_album(dir: &Path) -> [PathBuf; 2] {
+    let spec = |amplitude| StreamSpec { signal: Signal::Sine { frequency: 997.0, amplitude }, ..StreamSpec::default() };
+    [write_opus_file(dir, "quiet.opus", &[spec(0.05)]), write_opus_file(dir, "loud.opus", &[spec(0.5)])]
+}
+
+/// Rewrites `paths` as an album, returning the files in the report, the album
+/// loudness and the warnings printed
+fn rewrite_album(paths: &[PathBuf]) -> (Vec<serde_json::Value>, serde_json::Value, String) {
+    let output = opusgain(&["--album", "--format", "json"], paths);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let files = report["files"].as_array().unwrap().clone();
+    let album_lufs = files[0]["album_lufs"].clone();
+    (files, album_lufs, String::from_utf8(output.stderr).unwrap())
+}
+
+#[test]
+fn each_file_is_processed_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let (single, duplicated) = (dir.path().join("single"), dir.path().join("duplicated"));
+    for path in [&single, &duplicated] {
+        std::fs::create_dir(path).unwrap();
+    }
+    let (_, expected_lufs, _) = rewrite_album(&write_album(&single));
+
+    let [quiet, loud] = write_album(&duplicated);
+    let mut inputs = vec![quiet.clone(), loud.clone(), quiet.clone(), duplicated.join(".").join("quiet.opus")];
+    #[cfg(unix)]
+    {
+        let link = duplicated.join("link.opus");
+        std::fs::hard_link(&loud, &link).unwrap();
+        inputs.push(link);
+    }
+    let (files, album_lufs, stderr) = rewrite_album(&inputs);
+    assert_eq!(album_lufs, expected_lufs);
+    assert!(album_lufs.is_number());
+
+    // Each file is reported, and so rewritten, once in the order first given
+    let paths: Vec<_> = files.iter().map(|file| PathBuf::from(file["path"].as_str().unwrap())).collect();
+    assert_eq!(paths, [quiet, loud]);
+    assert!(files.iter().all(|file| file["status"] == "changed"));
+    let warnings = stderr.lines().filter(|line| line.contains("refers to a file already specified")).count();
+    assert_eq!(warnings, inputs.len() - 2, "{}", stderr);
+}
@@ -0,0 +1,233 @@
+mod common;
+
+use std::io::Cursor;
+use std::time::Duration;
+
+use common::{opus_file, read_headers, read_packets, Anomaly, Signal, StreamSpec, SAMPLE_RATE};
+use zoog::comment_rewrite::{CommentHeaderRewrite, CommentHeaderSummary, CommentRewriterAction, CommentRewriterConfig};
+use zoog::header::{CommentList as _, DiscreteCommentList};
+use zoog::header_issue::{find_issues, HeaderIssue};
+use zoog::header_rewriter::{read_raw_headers, rewrite_stream, CodecHeaders, SubmitResult};
+use zoog::opus::{VolumeAnalyzer, TAG_TRACK_GAIN};
+use zoog::volume_rewrite::{GainsSummary, OpusGains, VolumeHeaderRewrite, VolumeRewriterConfig, VolumeTarget};
+use zoog::{Decibels, Error, R128_LUFS};
+
+/// The loudness of a full-scale 997 Hz sine in both channels is 0 LUFS so
+/// halving the amplitude twice gives about -12.04 LUFS
+const QUARTER_SCALE_SINE_LUFS: f64 = -12.04;
+
+/// Allowed deviation from the loudness of the unencoded signal
+const LOUDNESS_TOLERANCE: f64 = 0.5;
+
+fn analyze(data: &[u8]) -> Result<VolumeAnalyzer, Error> {
+    let mut analyzer = VolumeAnalyzer::default();
+    analyzer.analyze_stream(Cursor::new(data))?;
+    Ok(analyzer)
+}
+
+fn loudness(spec: StreamSpec) -> f64 { analyze(&opus_file(&[spec])).unwrap().last_track_lufs().unwrap().as_f64() }
+
+fn no_change_rewrite() -> CommentHeaderRewrite<'static> {
+    CommentHeaderRewrite::new(CommentRewriterConfig { action: CommentRewriterAction::NoChange })
+}
+
+fn track_gain_rewrite(track_volume: Decibels) -> VolumeHeaderRewrite {
+    let config = VolumeRewriterConfig::builder()
+        .target(VolumeTarget::LUFS(R128_LUFS))
+        .track_volume(Some(track_volume))
+        .build()
+        .unwrap();
+    VolumeHeaderRewrite::new(config)
+}
+
+/// Rewrites `data` with `rewrite`, returning the result and the rewritten file
+fn rewrite_gains(data: &[u8], rewrite: VolumeHeaderRewrite) -> Result<(SubmitResult<OpusGains>, Vec<u8>), Error> {
+    let mut output = Vec::new();
+    let result = rewrite_stream(rewrite, GainsSummary::default(), Cursor::new(data), &mut output, false)?;
+    Ok((result, output))
+}
+
+fn audio_packets(data: &[u8]) -> Vec<(Vec<u8>, u64)> {
+    read_packets(data).into_iter().skip(2).map(|packet| (packet.data.clone(), packet.absgp_page())).collect()
+}
+
+#[test]
+fn sine_loudness_matches_bs1770() {
+    for channels in [1, 2] {
+        // Mono audio is measured as though played on two channels
+        let measured = loudness(StreamSpec { channels, ..StreamSpec::default() });
+        assert!(
+            (measured - QUARTER_SCALE_SINE_LUFS).abs() < LOUDNESS_TOLERANCE,
+            "{} channel(s) measured at {} LUFS",
+            channels,
+            measured
+        );
+    }
+}
+
+#[test]
+fn loudness_scales_with_amplitude() {
+    let signals: [fn(f32) -> Signal; 2] =
+        [|amplitude| Signal::Sine { frequency: 997.0, amplitude }, |amplitude| Signal::Noise { amplitude, seed: 8127 }];
+    for signal in signals {
+        let loud = loudness(StreamSpec { signal: signal(0.5), ..StreamSpec::default() });
+        let quiet = loudness(StreamSpec { signal: signal(0.25), ..StreamSpec::default() });
+        assert!((loud - quiet - 6.02).abs() < 0.2, "Loudness changed from {} to {} LUFS", loud, quiet);
+    }
+}
+
+#[test]
+fn silence_is_not_amplified() {
+    // Gating leaves no audio to measure, which is reported as full scale so that
+    // no gain is applied
+    let measured = loudness(StreamSpec { signal: Signal::Silence, ..StreamSpec::default() });
+    assert!(measured.abs() < f64::EPSILON);
+}
+
+#[test]
+fn chained_streams_measured_as_one_track() {
+    let quiet = Signal::Sine { frequency: 997.0, amplitude: 0.125 };
+    let data = opus_file(&[StreamSpec::default(), StreamSpec { serial: 2, signal: quiet, ..StreamSpec::default() }]);
+    let analyzer = analyze(&data).unwrap();
+    let tracks = analyzer.track_lufs();
+    assert_eq!(tracks.len(), 1);
+    // The second stream has a quarter of the power of the first
+    let expected = QUARTER_SCALE_SINE_LUFS + 10.0 * (0.625f64).log10();
+    assert!((tracks[0].as_f64() - expected).abs() < LOUDNESS_TOLERANCE);
+    let duration = analyzer.last_track_duration().unwrap();
+    assert!(duration >= Duration::from_secs(4) && duration < Duration::from_millis(4040));
+}
+
+#[test]
+fn granules_trim_padding() {
+    let spec = StreamSpec { num_samples: SAMPLE_RATE + 100, ..StreamSpec::default() };
+    let data = opus_file(&[spec.clone()]);
+    let preskip = match read_headers(&data) {
+        CodecHeaders::Opus(id_header, _) => {
+            use zoog::header::IdHeader as _;
+            id_header.preskip_samples()
+        }
+        CodecHeaders::Vorbis(_, _) => panic!("Unexpected Vorbis stream"),
+    };
+    assert!(preskip > 0);
+    let packets = read_packets(&data);
+    let granules: Vec<_> =
+        packets.iter().filter(|packet| packet.last_in_page()).map(|packet| packet.absgp_page()).collect();
+    assert!(granules.windows(2).all(|pair| pair[0] <= pair[1]));
+    let last = packets.last().unwrap();
+    assert!(last.last_in_stream());
+    assert_eq!(last.absgp_page(), u64::try_from(preskip + spec.num_samples).unwrap());
+
+    // Padding in the final packet beyond the final granule position is not
+    // measured
+    let duration = analyze(&data).unwrap().last_track_duration().unwrap();
+    assert_eq!(duration, Duration::from_secs(1) + Duration::from_nanos(100 * 1_000_000_000 / 48000));
+}
+
+#[test]
+fn headers_round_trip() {
+    let spec = StreamSpec::default().with_tag("TITLE", "Fixture").with_tag("ARTIST", "zoog").with_tag("TITLE", "Again");
+    let data = opus_file(&[spec]);
+    let raw = read_raw_headers(Cursor::new(&data)).unwrap();
+    let headers = raw.parse().unwrap();
+    let mut identification = Vec::new();
+    headers.serialize_id_header(&mut identification).unwrap();
+    let mut comment = Vec::new();
+    headers.serialize_comment_header(&mut comment).unwrap();
+    assert_eq!(identification, raw.identification);
+    assert_eq!(comment, raw.comment);
+}
+
+#[test]
+fn unchanged_rewrite_preserves_packets() {
+    let data = opus_file(&[StreamSpec::default().with_tag("TITLE", "Fixture")]);
+    let mut output = Vec::new();
+    let result =
+        rewrite_stream(no_change_rewrite(), CommentHeaderSummary::default(), Cursor::new(&data), &mut output, false)
+            .unwrap();
+    match result {
+        SubmitResult::HeadersUnchanged(tags) => assert_eq!(tags.get_first("TITLE"), Some("Fixture")),
+        other => panic!("Unexpected rewrite result: {:?}", other),
+    }
+    let packets = |data: &[u8]| read_packets(data).into_iter().map(|packet| packet.data).collect::<Vec<_>>();
+    assert_eq!(packets(&output), packets(&data));
+    assert_eq!(audio_packets(&output), audio_packets(&data));
+}
+
+#[test]
+fn gain_rewrite_is_idempotent() {
+    let data = opus_file(&[StreamSpec::default()]);
+    let volume = analyze(&data).unwrap().last_track_lufs().unwrap();
+    let (result, first) = rewrite_gains(&data, track_gain_rewrite(volume)).unwrap();
+    match result {
+        SubmitResult::HeadersChanged { to, .. } => {
+            assert!(to.is_normalized_to(VolumeTarget::LUFS(R128_LUFS), R128_LUFS, Decibels::from(0.01)));
+        }
+        other => panic!("Unexpected rewrite result: {:?}", other),
+    }
+    assert_eq!(audio_packets(&first), audio_packets(&data));
+
+    // The output gain is not applied when measuring so the volume is unchanged
+    let remeasured = analyze(&first).unwrap().last_track_lufs().unwrap();
+    assert!((remeasured.as_f64() - volume.as_f64()).abs() < f64::EPSILON);
+    let (result, second) = rewrite_gains(&first, track_gain_rewrite(remeasured)).unwrap();
+    assert!(matches!(result, SubmitResult::HeadersUnchanged(_)));
+    assert_eq!(second, first);
+}
+
+#[test]
+fn bad_checksum_is_an_error() {
+    for page in [0, 1, 2] {
+        let data = opus_file(&[StreamSpec::default().with_anomaly(Anomaly::BadChecksum { page })]);
+        assert!(matches!(analyze(&data), Err(Error::OggDecode(_))), "Corrupt page {} was accepted", page);
+        let result = rewrite_gains(&data, track_gain_rewrite(Decibels::from(-20.0)));
+        assert!(matches!(result, Err(Error::OggDecode(_))), "Corrupt page {} was rewritten", page);
+    }
+}
+
+#[test]
+fn truncated_comment_header_is_an_error() {
+    let full_length = common::comment_header("zoog-fixture", &[], &[]).len();
+    for length in [4, 12, full_length - 1] {
+        let data = opus_file(&[StreamSpec::default().with_anomaly(Anomaly::TruncatedCommentHeader { length })]);
+        assert!(read_raw_headers(Cursor::new(&data)).unwrap().parse().is_err(), "Length {} was accepted", length);
+        assert!(analyze(&data).is_err());
+        assert!(rewrite_gains(&data, track_gain_rewrite(Decibels::from(-20.0))).is_err());
+    }
+}
+
+#[test]
+fn duplicate_gain_tags_are_fixed() {
+    let spec = StreamSpec::default().with_tag(TAG_TRACK_GAIN, "-256").with_anomaly(Anomaly::DuplicateTags);
+    let data = opus_file(&[spec]);
+    let issues = find_issues(&read_headers(&data));
+    assert_eq!(issues, [HeaderIssue::DuplicateR128Tag { tag: TAG_TRACK_GAIN.into(), count: 2 }]);
+
+    let volume = analyze(&data).unwrap().last_track_lufs().unwrap();
+    let (_, output) = rewrite_gains(&data, track_gain_rewrite(volume)).unwrap();
+    assert!(find_issues(&read_headers(&output)).is_empty());
+}
+
+#[test]
+fn experimental_suffix_is_preserved() {
+    let suffix = b"\x01experimental".to_vec();
+    let spec =
+        StreamSpec::default().with_tag("TITLE", "Fixture").with_anomaly(Anomaly::ExperimentalSuffix(suffix.clone()));
+    let data = opus_file(&[spec]);
+
+    let mut append = DiscreteCommentList::default();
+    append.push("ARTIST", "zoog").unwrap();
+    let retain = Box::new(|_: &str, _: &str| true);
+    let rewrite =
+        CommentHeaderRewrite::new(CommentRewriterConfig { action: CommentRewriterAction::Modify { retain, append } });
+    let mut output = Vec::new();
+    rewrite_stream(rewrite, CommentHeaderSummary::default(), Cursor::new(&data), &mut output, false).unwrap();
+    match read_headers(&output) {
+        CodecHeaders::Opus(_, comments) => {
+            assert_eq!(comments.suffix_data(), suffix);
+            assert_eq!(comments.get_first("ARTIST"), Some("zoog"));
+        }
+        CodecHeaders::Vorbis(_, _) => panic!("Unexpected Vorbis stream"),
+    }
+    assert_eq!(audio_packets(&output), audio_packets(&data));
+}
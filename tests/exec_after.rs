@@ -0,0 +1,87 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::os::unix::fs::PermissionsExt as _;
+use std::path::{Path, PathBuf};
+
+use common::{opusgain, run, write_opus_file, zoogcomment, StreamSpec};
+
+/// Writes a script which records each argument it is called with as a line of
+/// `log`, then exits with `status`
+fn recording_script(dir: &Path, log: &Path, status: i32) -> PathBuf {
+    let path = dir.join(format!("record-{}.sh", status));
+    let script = format!("#!/bin/sh\nprintf '%s\\n' \"$1\" >> '{}'\nexit {}\n", log.display(), status);
+    std::fs::write(&path, script).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+fn logged_paths(log: &Path) -> Vec<PathBuf> {
+    match std::fs::read_to_string(log) {
+        Ok(text) => text.lines().map(PathBuf::from).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[test]
+fn command_runs_once_per_changed_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("log.txt");
+    let script = recording_script(dir.path(), &log, 0);
+    let normalized = write_opus_file(dir.path(), "normalized.opus", &[StreamSpec::default()]);
+    opusgain(&[], &[&normalized]);
+    let changed: Vec<_> = ["a track.opus", "b.opus"]
+        .into_iter()
+        .map(|name| write_opus_file(dir.path(), name, &[StreamSpec::default()]))
+        .collect();
+
+    let command = format!("'{}' {{}}", script.display());
+    let inputs = [changed[0].as_path(), normalized.as_path(), changed[1].as_path()];
+    let output = opusgain(&["--exec-after", &command, "-j", "4"], &inputs);
+    let mut logged = logged_paths(&log);
+    logged.sort();
+    assert_eq!(logged, changed);
+    assert!(!String::from_utf8(output.stdout).unwrap().contains("Commands run after modifying files that failed"));
+
+    // Running again changes nothing so runs nothing
+    opusgain(&["--exec-after", &command], &inputs);
+    assert_eq!(logged_paths(&log).len(), changed.len());
+}
+
+#[test]
+fn failing_commands_are_warnings() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("log.txt");
+    let script = recording_script(dir.path(), &log, 3);
+    let inputs: Vec<_> = ["a.opus", "b.opus"]
+        .into_iter()
+        .map(|name| write_opus_file(dir.path(), name, &[StreamSpec::default()]))
+        .collect();
+    let inputs: Vec<_> = inputs.iter().map(PathBuf::as_path).collect();
+    let output = opusgain(&["--exec-after", script.to_str().unwrap()], &inputs);
+    assert_eq!(logged_paths(&log).len(), 2);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Warning: the command for"), "{}", stderr);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Commands run after modifying files that failed: 2"), "{}", stdout);
+}
+
+#[test]
+fn zoogcomment_runs_command_for_written_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("log.txt");
+    let path = write_opus_file(dir.path(), "it's a track.opus", &[StreamSpec::default()]);
+
+    // The path is passed to the shell as a parameter, so needs no quoting
+    let command = format!("printf '%s\\n' {} >> '{}'", "{}", log.display());
+    zoogcomment(&["-m", "-t", "TITLE=Song", "--exec-shell", "--exec-after", &command], &[&path]);
+    assert_eq!(logged_paths(&log), [path.clone()]);
+
+    // A dry run writes nothing, so a command to run afterwards is an error
+    let script = recording_script(dir.path(), &log, 0);
+    let args = ["-m", "-n", "-t", "TITLE=Other", "--exec-after", script.to_str().unwrap()];
+    let result = run(env!("CARGO_BIN_EXE_zoogcomment"), &args, &[&path]);
+    assert!(!result.status.success(), "--exec-after should conflict with --dry-run");
+    assert_eq!(logged_paths(&log).len(), 1);
+}
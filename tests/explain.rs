@@ -0,0 +1,25 @@
+mod common;
+
+use common::{opusgain, write_opus_file, Signal, StreamSpec};
+
+#[test]
+fn opusgain_explains_gains() {
+    let dir = tempfile::tempdir().unwrap();
+    let spec = StreamSpec { signal: Signal::Sine { frequency: 997.0, amplitude: 0.25 }, ..StreamSpec::default() };
+    let path = write_opus_file(dir.path(), "track.opus", &[spec]);
+    let original = std::fs::read(&path).unwrap();
+
+    let output = opusgain(&["--explain", "--dry-run", "--preset", "rg"], &[&path]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let explanation: Vec<_> = stdout.lines().skip_while(|line| *line != "Gain calculation:").collect();
+    assert!(explanation[1].starts_with("\tTrack loudness: -"));
+    assert_eq!(explanation[2], "\tAlbum loudness: none");
+    assert_eq!(explanation[3], "\tTarget: -18.00 LUFS");
+    assert!(explanation[4].ends_with(" LUFS (track loudness)"));
+    assert!(explanation[5].starts_with("\tUnrounded output gain = target - source = -18.000 - "));
+    assert!(explanation[6].starts_with("\tOutput gain (in steps of 1/256 dB) = "));
+    assert!(explanation[7].starts_with("\tR128_TRACK_GAIN = reference - track loudness - output gain = -23.000 - "));
+    assert!(explanation[7].ends_with(" dB, rounded to -5 dB"));
+    assert_eq!(explanation[8], "\tR128_ALBUM_GAIN: none");
+    assert_eq!(std::fs::read(&path).unwrap(), original);
+}
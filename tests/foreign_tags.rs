@@ -0,0 +1,77 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use common::{opusgain, read_headers, write_opus_file, StreamSpec};
+use zoog::header::replaygain::{parse_rg_peak, parse_rg_value, TAG_RG_TRACK_GAIN, TAG_RG_TRACK_PEAK};
+use zoog::header::CommentList as _;
+use zoog::header_rewriter::CodecHeaders;
+
+/// Returns the output gain of a file in decibels together with its ReplayGain
+/// track gain and peak tags
+fn foreign_tags(path: &Path) -> (f64, Option<String>, Option<String>) {
+    match read_headers(&std::fs::read(path).unwrap()) {
+        CodecHeaders::Opus(id, comments) => {
+            let tag = |name| comments.get_first(name).map(String::from);
+            (id.get_output_gain().as_decibels().as_f64(), tag(TAG_RG_TRACK_GAIN), tag(TAG_RG_TRACK_PEAK))
+        }
+        CodecHeaders::Vorbis(_, _) => panic!("Unexpected Vorbis stream"),
+    }
+}
+
+fn tagged_file(dir: &Path) -> PathBuf {
+    let spec = StreamSpec::default().with_tag(TAG_RG_TRACK_GAIN, "-3.00 dB").with_tag(TAG_RG_TRACK_PEAK, "0.250000");
+    write_opus_file(dir, "track.opus", &[spec])
+}
+
+#[test]
+fn stale_foreign_tags_are_reported_by_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = tagged_file(dir.path());
+    let output = opusgain(&["--preset", "rg"], &[&path]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("REPLAYGAIN_TRACK_GAIN, REPLAYGAIN_TRACK_PEAK written by other tools are now stale"));
+    let (output_gain, gain, peak) = foreign_tags(&path);
+    assert!(output_gain != 0.0);
+    assert_eq!((gain.as_deref(), peak.as_deref()), (Some("-3.00 dB"), Some("0.250000")));
+
+    // Nothing is reported once the output gain no longer changes
+    let output = opusgain(&["--preset", "rg"], &[&path]);
+    assert!(!String::from_utf8(output.stderr).unwrap().contains("stale"));
+}
+
+#[test]
+fn foreign_tags_are_fixed() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = tagged_file(dir.path());
+    let output = opusgain(&["--preset", "rg", "--fix-foreign-tags"], &[&path]);
+    assert!(!String::from_utf8(output.stderr).unwrap().contains("stale"));
+    let (output_gain, gain, peak) = foreign_tags(&path);
+    assert!(output_gain != 0.0);
+
+    // A louder decoded signal needs less ReplayGain gain and has a higher peak
+    let gain = parse_rg_value(&gain.unwrap()).unwrap().as_f64();
+    assert!((gain - (-3.0 - output_gain)).abs() <= 0.005, "Unexpected gain {} for output gain {}", gain, output_gain);
+    let peak = parse_rg_peak(&peak.unwrap()).unwrap();
+    let expected_peak = 0.25 * 10.0_f64.powf(output_gain / 20.0);
+    assert!((peak - expected_peak).abs() <= 5e-7, "Unexpected peak {} for output gain {}", peak, output_gain);
+
+    // Returning to zero gain restores the original values, to within the
+    // precision of the tags
+    opusgain(&["--preset", "original", "--fix-foreign-tags"], &[&path]);
+    let (output_gain, gain, peak) = foreign_tags(&path);
+    assert_eq!(output_gain, 0.0);
+    assert!((parse_rg_value(&gain.unwrap()).unwrap().as_f64() + 3.0).abs() <= 0.01);
+    assert!((parse_rg_peak(&peak.unwrap()).unwrap() - 0.25).abs() <= 2e-6);
+}
+
+#[test]
+fn foreign_tags_are_dropped() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = tagged_file(dir.path());
+    let output = opusgain(&["--preset", "rg", "--drop-foreign-tags"], &[&path]);
+    assert!(!String::from_utf8(output.stderr).unwrap().contains("stale"));
+    let (output_gain, gain, peak) = foreign_tags(&path);
+    assert!(output_gain != 0.0);
+    assert_eq!((gain, peak), (None, None));
+}
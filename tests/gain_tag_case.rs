@@ -0,0 +1,37 @@
+mod common;
+
+use std::path::Path;
+
+use common::{opusgain, read_headers, write_opus_file, Signal, StreamSpec};
+use zoog::header::CommentList as _;
+use zoog::header_rewriter::CodecHeaders;
+use zoog::opus::{TAG_ALBUM_GAIN, TAG_TRACK_GAIN};
+
+/// Returns the names of all comments which are case-insensitively equal to
+/// `tag`
+fn spellings(path: &Path, tag: &str) -> Vec<String> {
+    match read_headers(&std::fs::read(path).unwrap()) {
+        CodecHeaders::Opus(_, comments) => {
+            comments.iter().filter(|(k, _)| k.eq_ignore_ascii_case(tag)).map(|(k, _)| k.to_string()).collect()
+        }
+        CodecHeaders::Vorbis(_, _) => panic!("Unexpected Vorbis stream"),
+    }
+}
+
+#[test]
+fn gain_tags_are_rewritten_with_canonical_spelling() {
+    let dir = tempfile::tempdir().unwrap();
+    let spec = StreamSpec { signal: Signal::Sine { frequency: 997.0, amplitude: 0.25 }, ..StreamSpec::default() };
+    let fixtures = [
+        // An existing album gain is preserved outside album mode
+        ("lowercase.opus", spec.clone().with_tag("r128_track_gain", "0").with_tag("r128_album_gain", "0"), 1),
+        ("mixed.opus", spec.with_tag("R128_Track_Gain", "0").with_tag(TAG_TRACK_GAIN, "256"), 0),
+    ];
+    for (name, spec, album_tags) in fixtures {
+        let path = write_opus_file(dir.path(), name, &[spec]);
+        let stderr = String::from_utf8(opusgain(&[], &[&path]).stderr).unwrap();
+        assert!(stderr.contains(&format!("should be written as {}", TAG_TRACK_GAIN)), "{}: {}", name, stderr);
+        assert_eq!(spellings(&path, TAG_TRACK_GAIN), [TAG_TRACK_GAIN], "{}", name);
+        assert_eq!(spellings(&path, TAG_ALBUM_GAIN), vec![TAG_ALBUM_GAIN; album_tags], "{}", name);
+    }
+}
@@ -0,0 +1,24 @@
+mod common;
+
+use std::time::{Duration, SystemTime};
+
+use common::{opusgain, write_opus_file, StreamSpec};
+
+#[test]
+fn recently_modified_files_are_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_opus_file(dir.path(), "recording.opus", &[StreamSpec::default()]);
+    let before = std::fs::read(&path).unwrap();
+    let output = opusgain(&["--stable-seconds", "600"], &[&path]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("modified less than 600 second(s) ago"), "{}", stdout);
+    assert!(stdout.contains("Files skipped since they were not yet stable: 1"), "{}", stdout);
+    assert_eq!(std::fs::read(&path).unwrap(), before);
+
+    // Once the recording has been left alone for long enough it is processed
+    let mtime = SystemTime::now() - Duration::from_secs(3600);
+    std::fs::File::options().write(true).open(&path).unwrap().set_modified(mtime).unwrap();
+    let output = opusgain(&["--stable-seconds", "600"], &[&path]);
+    assert!(!String::from_utf8(output.stdout).unwrap().contains("Files skipped"));
+    assert_ne!(std::fs::read(&path).unwrap(), before);
+}
@@ -0,0 +1,43 @@
+#![cfg(feature = "sha2")]
+
+mod common;
+
+use std::path::Path;
+
+use common::{opusgain, write_opus_file, Signal, StreamSpec};
+use sha2::{Digest, Sha256};
+
+fn sha256(path: &Path) -> String {
+    Sha256::digest(std::fs::read(path).unwrap()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Runs `opusgain` with CSV output, returning the input and output digests
+/// reported for a single file
+fn reported_digests(path: &Path) -> (String, String) {
+    let output = opusgain(&["--hash", "sha256", "--format", "csv"], &[path]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].ends_with(",input_sha256,output_sha256"));
+    let mut fields = lines[1].rsplit(',');
+    let output = fields.next().unwrap().to_string();
+    let input = fields.next().unwrap().to_string();
+    (input, output)
+}
+
+#[test]
+fn opusgain_reports_digests_of_input_and_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let spec = StreamSpec { signal: Signal::Sine { frequency: 997.0, amplitude: 0.25 }, ..StreamSpec::default() };
+    let path = write_opus_file(dir.path(), "track.opus", &[spec]);
+    let original = sha256(&path);
+
+    let (input, output) = reported_digests(&path);
+    assert_eq!(input, original);
+    assert_ne!(output, original, "The file was not rewritten");
+    assert_eq!(output, sha256(&path));
+
+    // An unchanged file is reported with identical digests
+    let rewritten = sha256(&path);
+    assert_eq!(reported_digests(&path), (rewritten.clone(), rewritten));
+}
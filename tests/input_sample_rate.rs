@@ -0,0 +1,49 @@
+mod common;
+
+use std::path::Path;
+
+use common::{read_headers, read_packets, write_opus_file, zoogcomment, Anomaly, StreamSpec};
+use zoog::header::{CommentList as _, IdHeader as _};
+use zoog::header_rewriter::CodecHeaders;
+
+fn input_sample_rate(path: &Path) -> Option<usize> {
+    match read_headers(&std::fs::read(path).unwrap()) {
+        CodecHeaders::Opus(id_header, _) => id_header.input_sample_rate(),
+        CodecHeaders::Vorbis(_, _) => panic!("Expected an Opus stream"),
+    }
+}
+
+#[test]
+fn implausible_rate_is_reported_and_fixed() {
+    let dir = tempfile::tempdir().unwrap();
+    let spec = StreamSpec::default().with_tag("TITLE", "Broken").with_anomaly(Anomaly::InputSampleRate(1));
+    let path = write_opus_file(dir.path(), "track.opus", &[spec]);
+    let original = std::fs::read(&path).unwrap();
+    assert_eq!(input_sample_rate(&path), Some(1));
+
+    let output = zoogcomment(&["-l"], &[&path]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Input sample rate of 1 Hz is implausible"), "{}", stderr);
+
+    let output = zoogcomment(&["--fix-input-rate", "44100"], &[&path]);
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(input_sample_rate(&path), Some(44100));
+    let fixed = std::fs::read(&path).unwrap();
+    let CodecHeaders::Opus(_, comments) = read_headers(&fixed) else { unreachable!() };
+    assert_eq!(comments.get_first("TITLE"), Some("Broken"));
+    let packet_data = |data: &[u8]| -> Vec<_> { read_packets(data).into_iter().skip(2).map(|p| p.data).collect() };
+    assert_eq!(packet_data(&fixed), packet_data(&original));
+
+    let output = zoogcomment(&["--fix-input-rate", "unset"], &[&path]);
+    assert_eq!(input_sample_rate(&path), None);
+}
+
+#[test]
+fn unchanged_rate_leaves_file_untouched() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_opus_file(dir.path(), "track.opus", &[StreamSpec::default()]);
+    let original = std::fs::read(&path).unwrap();
+    let output = zoogcomment(&["--fix-input-rate", "48000"], &[&path]);
+    assert!(String::from_utf8(output.stderr).unwrap().contains("No changes"));
+    assert_eq!(std::fs::read(&path).unwrap(), original);
+}
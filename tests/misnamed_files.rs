@@ -0,0 +1,38 @@
+mod common;
+
+use common::{artist, opusgain, run, write_opus_file, zoogcomment, StreamSpec};
+
+#[test]
+fn misnamed_opus_files_are_processed() {
+    let dir = tempfile::tempdir().unwrap();
+    let spec = StreamSpec::default();
+    let named = write_opus_file(dir.path(), "named.opus", &[spec.clone()]);
+    let misnamed = write_opus_file(dir.path(), "misnamed.bin", &[spec]);
+
+    opusgain(&[], &[&named, &misnamed]);
+    assert_eq!(std::fs::read(&named).unwrap(), std::fs::read(&misnamed).unwrap());
+
+    zoogcomment(&["-m", "-t", "ARTIST=Someone"], &[&misnamed]);
+    assert_eq!(artist(&misnamed).as_deref(), Some("Someone"));
+}
+
+#[test]
+fn tags_files_are_judged_by_content() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_opus_file(dir.path(), "input.opus", &[StreamSpec::default()]);
+
+    // A text file with a media extension is accepted
+    let tags = dir.path().join("tags.opus");
+    std::fs::write(&tags, "ARTIST=Someone\n").unwrap();
+    zoogcomment(&["-m", "-I"], &[&tags, &input]);
+    assert_eq!(artist(&input).as_deref(), Some("Someone"));
+
+    // An Ogg file with a text extension is refused unless forced
+    let media = write_opus_file(dir.path(), "notes.txt", &[StreamSpec::default()]);
+    let original = std::fs::read(&media).unwrap();
+    assert!(!run(env!("CARGO_BIN_EXE_zoogcomment"), &["-O"], &[&media, &input]).status.success());
+    assert_eq!(std::fs::read(&media).unwrap(), original);
+
+    zoogcomment(&["--force-tags-file", "-O"], &[&media, &input]);
+    assert_eq!(std::fs::read_to_string(&media).unwrap(), "ARTIST=Someone\n");
+}
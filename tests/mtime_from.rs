@@ -0,0 +1,64 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use common::{opusgain, run, write_opus_file, zoogcomment, Signal, StreamSpec};
+
+/// The coarsest modification time granularity of common filesystems (FAT32)
+const GRANULARITY: Duration = Duration::from_secs(2);
+
+fn modified(path: &Path) -> SystemTime { std::fs::metadata(path).unwrap().modified().unwrap() }
+
+fn assert_close(actual: SystemTime, expected: SystemTime) {
+    let difference = actual.duration_since(expected).or_else(|_| expected.duration_since(actual)).unwrap();
+    assert!(difference <= GRANULARITY, "{:?} differs from {:?}", actual, expected);
+}
+
+/// Creates a reference file with a modification time well in the past
+fn reference(dir: &Path) -> (PathBuf, SystemTime) {
+    let path = dir.join("master.wav");
+    std::fs::write(&path, b"master").unwrap();
+    let mtime = SystemTime::now() - Duration::from_secs(30 * 24 * 60 * 60);
+    std::fs::File::options().write(true).open(&path).unwrap().set_modified(mtime).unwrap();
+    (path, modified(&path))
+}
+
+#[test]
+fn rewritten_files_take_reference_mtime() {
+    let dir = tempfile::tempdir().unwrap();
+    let (reference, reference_mtime) = reference(dir.path());
+    let spec = StreamSpec { signal: Signal::Sine { frequency: 997.0, amplitude: 0.25 }, ..StreamSpec::default() };
+    let track = write_opus_file(dir.path(), "track.opus", &[spec.clone()]);
+    let tagged = write_opus_file(dir.path(), "tagged.opus", &[spec]);
+
+    opusgain(&["--mtime-from"], &[&reference, &track]);
+    assert_close(modified(&track), reference_mtime);
+
+    zoogcomment(&["-m", "-t", "TITLE=Title", "--mtime-from"], &[&reference, &tagged]);
+    assert_close(modified(&tagged), reference_mtime);
+}
+
+#[test]
+fn dry_run_and_unreadable_reference_leave_files_untouched() {
+    let dir = tempfile::tempdir().unwrap();
+    let (reference, _) = reference(dir.path());
+    let path = write_opus_file(dir.path(), "track.opus", &[StreamSpec::default()]);
+    let (original, original_mtime) = (std::fs::read(&path).unwrap(), modified(&path));
+
+    zoogcomment(&["-n", "-m", "-t", "TITLE=Title", "--mtime-from"], &[&reference, &path]);
+    assert_eq!(modified(&path), original_mtime);
+
+    let missing = dir.path().join("missing.wav");
+    let invocations = [
+        (env!("CARGO_BIN_EXE_opusgain"), &["--mtime-from"][..]),
+        (env!("CARGO_BIN_EXE_zoogcomment"), &["-m", "-t", "TITLE=Title", "--mtime-from"][..]),
+    ];
+    for (program, args) in invocations {
+        let output = run(program, args, &[&missing, &path]);
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("missing.wav"));
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+        assert_eq!(modified(&path), original_mtime);
+    }
+}
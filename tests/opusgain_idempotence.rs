@@ -0,0 +1,69 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use common::{opusgain, write_opus_file, Signal, StreamSpec};
+
+/// Generates files of differing loudness, including values which are unlikely
+/// to be an exact number of fixed-point gain steps from any target
+fn write_fixtures(dir: &Path) -> Vec<PathBuf> {
+    let signals = [
+        (1, Signal::Sine { frequency: 997.0, amplitude: 0.25 }),
+        (2, Signal::Sine { frequency: 440.0, amplitude: 0.0731 }),
+        (2, Signal::Sine { frequency: 3000.0, amplitude: 0.61 }),
+        (2, Signal::Noise { amplitude: 0.3, seed: 1 }),
+        (1, Signal::Noise { amplitude: 0.047, seed: 2 }),
+    ];
+    signals
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (channels, signal))| {
+            let spec = StreamSpec { channels, signal, ..StreamSpec::default() };
+            write_opus_file(dir, &format!("track{}.opus", idx), &[spec])
+        })
+        .collect()
+}
+
+/// Runs `opusgain` with `--changed-only`, returning its standard output
+fn changed_only(args: &[&str], files: &[PathBuf]) -> String {
+    let output = opusgain(&[&["--changed-only"], args].concat(), files);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn contents(files: &[PathBuf]) -> Vec<Vec<u8>> { files.iter().map(|path| std::fs::read(path).unwrap()).collect() }
+
+#[test]
+fn second_run_changes_nothing() {
+    for preset in ["rg", "r128", "original", "max-headroom"] {
+        for album in [false, true] {
+            let dir = tempfile::tempdir().unwrap();
+            let files = write_fixtures(dir.path());
+            let mut args = vec!["--preset", preset];
+            if album {
+                args.push("--album");
+            }
+            let first = changed_only(&args, &files);
+            assert!(!first.is_empty(), "No files changed by first run with {:?}", args);
+            let after_first = contents(&files);
+
+            let second = changed_only(&args, &files);
+            assert!(second.is_empty(), "Second run with {:?} reported changes:\n{}", args, second);
+            assert_eq!(contents(&files), after_first, "Second run with {:?} modified files", args);
+        }
+    }
+}
+
+#[test]
+fn files_without_audio_are_skipped() {
+    for album in [false, true] {
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = write_fixtures(dir.path());
+        let empty = StreamSpec { num_samples: 0, ..StreamSpec::default() };
+        files.push(write_opus_file(dir.path(), "empty.opus", &[empty]));
+        let before = contents(&files);
+        let args = if album { vec!["--album"] } else { Vec::new() };
+        let changed = changed_only(&args, &files);
+        assert!(!changed.contains("empty.opus"), "File without audio was changed:\n{}", changed);
+        assert_eq!(contents(&files).last(), before.last());
+    }
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use std::path::Path;
+
+use common::{opusgain, read_headers, write_opus_file, Signal, StreamSpec};
+use zoog::header::CommentList as _;
+use zoog::header_rewriter::CodecHeaders;
+use zoog::opus::TAG_TRACK_GAIN;
+
+/// Returns the output gain and R128 track gain of a file in Q7.8 format
+fn gains(path: &Path) -> (i16, i16) {
+    match read_headers(&std::fs::read(path).unwrap()) {
+        CodecHeaders::Opus(id, comments) => {
+            let track_gain = comments.get_first(TAG_TRACK_GAIN).unwrap().parse().unwrap();
+            (id.get_output_gain().as_fixed_point(), track_gain)
+        }
+        CodecHeaders::Vorbis(_, _) => panic!("Unexpected Vorbis stream"),
+    }
+}
+
+#[test]
+fn output_gain_is_checked_against_peak() {
+    let dir = tempfile::tempdir().unwrap();
+    // Peaks close to full scale, but quiet enough that the ReplayGain preset
+    // calls for around 6 dB of gain
+    let spec = StreamSpec { signal: Signal::Impulses { amplitude: 0.98, period: 960 }, ..StreamSpec::default() };
+    let path = write_opus_file(dir.path(), "track.opus", &[spec]);
+    let unlimited_path = dir.path().join("unlimited.opus");
+
+    let output =
+        opusgain(&["--preset", "rg", "--explain", "--dry-run-output", unlimited_path.to_str().unwrap()], &[&path]);
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Warning: The output gain of "));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let peak_line = stdout.lines().find(|line| line.starts_with("\tSample peak after output gain = ")).unwrap();
+    assert!(!peak_line.contains("= -"), "Peak was not raised above full scale: {}", peak_line);
+    let (unlimited_output, unlimited_track) = gains(&unlimited_path);
+    assert!(unlimited_output > 5 * 256, "Output gain was only {}/256 dB", unlimited_output);
+
+    let output = opusgain(&["--preset", "rg", "--limit-output-gain-to-peak"], &[&path]);
+    assert!(!String::from_utf8(output.stderr).unwrap().contains("Warning: The output gain of "));
+    let (limited_output, limited_track) = gains(&path);
+    assert!(limited_output < unlimited_output);
+    // The R128 gain compensates for the lower output gain
+    let playback = |output: i16, track: i16| i32::from(output) + i32::from(track);
+    assert!((playback(limited_output, limited_track) - playback(unlimited_output, unlimited_track)).abs() <= 1);
+
+    // The limited gain is within the headroom, so rewriting again is quiet
+    let output = opusgain(&["--preset", "rg", "--limit-output-gain-to-peak"], &[&path]);
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(gains(&path), (limited_output, limited_track));
+}
@@ -0,0 +1,25 @@
+mod common;
+
+use common::{read_headers, run, write_opus_file, zoogcomment, StreamSpec};
+use zoog::header::CommentList as _;
+use zoog::header_rewriter::CodecHeaders;
+
+#[test]
+fn replacing_with_nothing_requires_allow_empty() {
+    let dir = tempfile::tempdir().unwrap();
+    let spec = StreamSpec::default().with_tag("TITLE", "Title").with_tag("ARTIST", "Someone");
+    let path = write_opus_file(dir.path(), "track.opus", &[spec]);
+    let original = std::fs::read(&path).unwrap();
+
+    let output = run(env!("CARGO_BIN_EXE_zoogcomment"), &["--replace"], &[&path]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("would remove all 2 comment(s)"), "{}", stderr);
+    assert_eq!(std::fs::read(&path).unwrap(), original);
+
+    zoogcomment(&["--replace", "--allow-empty"], &[&path]);
+    match read_headers(&std::fs::read(&path).unwrap()) {
+        CodecHeaders::Opus(_, comments) => assert!(comments.is_empty()),
+        CodecHeaders::Vorbis(_, _) => panic!("Unexpected Vorbis stream"),
+    }
+}
@@ -0,0 +1,118 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use zoog::header::{CommentHeaderSizes, CommentList as _, DiscreteCommentList};
+use zoog::report::{
+    write_comment_diff_json, write_comment_sizes_json, write_file_results_json, write_vendor_stats_json,
+    AnalysisResult, FileDigests, FileResult, FileStatus, TrackResult, SCHEMA_VERSION, SCHEMA_VERSION_FIELD,
+};
+use zoog::vendor_stats::{Encoder, VendorStats};
+use zoog::volume_rewrite::OpusGains;
+use zoog::Decibels;
+
+fn golden_dir() -> PathBuf { Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden") }
+
+/// Checks a document against its golden file. If the change in shape is
+/// intended, update the golden file and, unless fields were only added,
+/// increment `SCHEMA_VERSION`.
+fn assert_golden<F: FnOnce(&mut Vec<u8>) -> std::io::Result<()>>(name: &str, write: F) {
+    let mut output = Vec::new();
+    write(&mut output).unwrap();
+    let path = golden_dir().join(name);
+    let expected = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), expected, "Output no longer matches {}", path.display());
+}
+
+fn file_results() -> Vec<FileResult> {
+    let gains = |output: f64, track: Option<f64>| OpusGains {
+        output: Decibels::from(output),
+        track_r128: track.map(Decibels::from),
+        album_r128: None,
+    };
+    let changed = FileResult {
+        status: FileStatus::Changed,
+        duration: Some(Duration::from_millis(1500)),
+        track_lufs: Some(Decibels::from(-26.75)),
+        old_gains: Some(gains(0.0, None)),
+        new_gains: Some(gains(8.5, Some(-5.0))),
+        digests: Some(FileDigests { input: "a1".repeat(32), output: "b2".repeat(32) }),
+        ..FileResult::new(PathBuf::from("a.opus"))
+    };
+    vec![changed, FileResult::new(PathBuf::from("missing.opus"))]
+}
+
+fn analysis() -> AnalysisResult {
+    let track = |path: &str, lufs: f64, millis: u64| TrackResult {
+        path: PathBuf::from(path),
+        lufs: Decibels::from(lufs),
+        duration: Duration::from_millis(millis),
+        duration_issue: None,
+    };
+    AnalysisResult {
+        tracks: vec![track("one.opus", -18.5, 61_250), track("two.opus", -20.0, 5_000)],
+        album_lufs: Some(Decibels::from(-19.25)),
+        approximate: true,
+    }
+}
+
+#[test]
+fn file_results_schema() {
+    assert_golden("file_results.json", |output| write_file_results_json(&file_results(), output));
+}
+
+#[test]
+fn analysis_schema() { assert_golden("analysis.json", |output| analysis().write_json(output)); }
+
+#[test]
+fn vendor_stats_schema() {
+    let mut stats = VendorStats::default();
+    stats.record(Encoder::new("libopus 1.3", Some("opusenc")), 0, Path::new("a.opus"));
+    stats.record(Encoder::new("libopus 1.3", Some("opusenc")), 1, Path::new("b.opus"));
+    stats.record(Encoder::new("Lavf", None), 2, Path::new("c.opus"));
+    stats.record_unreadable();
+    assert_golden("vendor_stats.json", |output| write_vendor_stats_json(&stats, output));
+}
+
+#[test]
+fn comment_diff_schema() {
+    let mut old = DiscreteCommentList::default();
+    old.extend([("TITLE", "Old"), ("ARTIST", "Someone")]).unwrap();
+    let mut new = DiscreteCommentList::default();
+    new.extend([("ARTIST", "Someone"), ("TITLE", "Two\nlines"), ("GENRE", "Folk")]).unwrap();
+    assert_golden("comment_diff.json", |output| write_comment_diff_json(&old.diff(&new), output));
+}
+
+#[test]
+fn comment_sizes_schema() {
+    let sizes = CommentHeaderSizes {
+        vendor: 7,
+        comments: vec![("TITLE".into(), 10), ("METADATA_BLOCK_PICTURE".into(), 900), ("ARTIST".into(), 10)],
+        suffix: 3,
+        total: 1000,
+    };
+    assert_golden("comment_sizes.json", |output| write_comment_sizes_json(&sizes, output));
+}
+
+#[test]
+fn golden_files_have_current_version() {
+    for entry in std::fs::read_dir(golden_dir()).unwrap() {
+        let path = entry.unwrap().path();
+        let value: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(value[SCHEMA_VERSION_FIELD], SCHEMA_VERSION, "Schema version of {} is stale", path.display());
+    }
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn serde_matches_golden_files() {
+    let golden = |name: &str| -> serde_json::Value {
+        let mut value: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(golden_dir().join(name)).unwrap()).unwrap();
+        if let Some(object) = value.as_object_mut() {
+            object.remove(SCHEMA_VERSION_FIELD);
+        }
+        value
+    };
+    assert_eq!(serde_json::to_value(file_results()).unwrap(), golden("file_results.json")["files"]);
+    assert_eq!(serde_json::to_value(analysis()).unwrap(), golden("analysis.json"));
+}
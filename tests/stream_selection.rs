@@ -0,0 +1,65 @@
+mod common;
+
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use common::{grouped_opus_file, run, zoogcomment, StreamSpec};
+use zoog::header::CommentList as _;
+use zoog::header_rewriter::{read_selected_raw_headers, CodecHeaders, RawHeaders, StreamSelection};
+
+fn raw_headers(data: &[u8], serial: u32) -> RawHeaders {
+    read_selected_raw_headers(Cursor::new(data), StreamSelection::Serial(serial)).unwrap()
+}
+
+fn two_stream_file(dir: &Path) -> PathBuf {
+    let english = StreamSpec { serial: 0x1000, ..StreamSpec::default() }.with_tag("LANGUAGE", "en");
+    let french = StreamSpec { serial: 0x2000, ..StreamSpec::default() }.with_tag("LANGUAGE", "fr");
+    let path = dir.join("tracks.opus");
+    std::fs::write(&path, grouped_opus_file(&[english, french])).unwrap();
+    path
+}
+
+#[test]
+fn streams_are_listed() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = two_stream_file(dir.path());
+    let output = zoogcomment(&["--list-streams"], &[&path]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, "0\t0x00001000\tOpus\n1\t0x00002000\tOpus\n");
+}
+
+#[test]
+fn only_selected_stream_is_modified() {
+    for selection in [["--stream-serial", "0x2000"], ["--stream-index", "1"]] {
+        let dir = tempfile::tempdir().unwrap();
+        let path = two_stream_file(dir.path());
+        let original = std::fs::read(&path).unwrap();
+
+        zoogcomment(&[&selection[..], &["-m", "-t", "TITLE=Second"][..]].concat(), &[&path]);
+
+        let rewritten = std::fs::read(&path).unwrap();
+        assert_eq!(raw_headers(&rewritten, 0x1000), raw_headers(&original, 0x1000));
+        let comments = match raw_headers(&rewritten, 0x2000).parse().unwrap() {
+            CodecHeaders::Opus(_, comments) => comments,
+            CodecHeaders::Vorbis(_, _) => panic!("Unexpected Vorbis stream"),
+        };
+        assert_eq!(comments.get_first("LANGUAGE"), Some("fr"));
+        assert_eq!(comments.get_first("TITLE"), Some("Second"));
+
+        // The selected stream's comments are listed
+        let output = zoogcomment(&[&selection[..], &["-l"][..]].concat(), &[&path]);
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "LANGUAGE=fr\nTITLE=Second\n");
+    }
+}
+
+#[test]
+fn missing_stream_is_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = two_stream_file(dir.path());
+    let original = std::fs::read(&path).unwrap();
+    let output = run(env!("CARGO_BIN_EXE_zoogcomment"), &["--stream-index", "2", "-m", "-t", "TITLE=None"], &[&path]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("No logical stream with index 2"), "{}", stderr);
+    assert_eq!(std::fs::read(&path).unwrap(), original);
+}
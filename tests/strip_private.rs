@@ -0,0 +1,37 @@
+mod common;
+
+use std::path::Path;
+
+use common::{read_headers, write_opus_file, zoogcomment, StreamSpec};
+use zoog::header::CommentList as _;
+use zoog::header_rewriter::CodecHeaders;
+
+fn comments(path: &Path) -> Vec<(String, String)> {
+    match read_headers(&std::fs::read(path).unwrap()) {
+        CodecHeaders::Opus(_, comments) => comments.iter().map(|(k, v)| (k.into(), v.into())).collect(),
+        CodecHeaders::Vorbis(_, _) => panic!("Unexpected Vorbis stream"),
+    }
+}
+
+#[test]
+fn private_tags_are_stripped_alongside_other_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let spec = StreamSpec::default()
+        .with_tag("ZOOG_LOUDNESS", "-18")
+        .with_tag("TITLE", "Title")
+        .with_tag("zoog_undo", "data")
+        .with_tag("NOT_ZOOG_TAG", "kept");
+    let path = write_opus_file(dir.path(), "track.opus", &[spec]);
+
+    let output = zoogcomment(&["-m", "--strip-private", "-t", "ARTIST=Someone"], &[&path]);
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Removed 2 private tag(s)."));
+    let expected = [("TITLE", "Title"), ("NOT_ZOOG_TAG", "kept"), ("ARTIST", "Someone")]
+        .map(|(k, v)| (String::from(k), String::from(v)));
+    assert_eq!(comments(&path), expected);
+
+    // Stripping again changes nothing
+    let original = std::fs::read(&path).unwrap();
+    let output = zoogcomment(&["-m", "--strip-private"], &[&path]);
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Removed 0 private tag(s)."));
+    assert_eq!(std::fs::read(&path).unwrap(), original);
+}
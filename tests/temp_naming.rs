@@ -0,0 +1,40 @@
+mod common;
+
+use std::path::Path;
+use std::process::Output;
+
+use common::{assert_success, run, write_opus_file, StreamSpec};
+
+fn set_artist(path: &Path) -> Output {
+    run(env!("CARGO_BIN_EXE_zoogcomment"), &["--temp-naming", "deterministic", "-m", "-t", "ARTIST=Someone"], &[path])
+}
+
+fn dir_entries(dir: &Path) -> Vec<String> {
+    let mut entries: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    entries.sort();
+    entries
+}
+
+#[test]
+fn stale_deterministic_temporary_is_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_opus_file(dir.path(), "track.opus", &[StreamSpec::default()]);
+    let original = std::fs::read(&path).unwrap();
+    let stale = dir.path().join("track-zoogtmp.opus");
+    std::fs::write(&stale, b"left behind").unwrap();
+
+    let output = set_artist(&path);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("track-zoogtmp.opus") && stderr.contains("already exists"), "{}", stderr);
+    assert_eq!(std::fs::read(&path).unwrap(), original);
+    assert_eq!(std::fs::read(&stale).unwrap(), b"left behind");
+
+    std::fs::remove_file(&stale).unwrap();
+    assert_success(&set_artist(&path));
+    assert_ne!(std::fs::read(&path).unwrap(), original);
+    assert_eq!(dir_entries(dir.path()), ["track.opus"]);
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use std::path::PathBuf;
+
+use common::{opusgain, write_opus_file, StreamSpec};
+
+/// Runs an album analysis and returns the paths of the tracks in the order
+/// they were reported
+fn reported_order(args: &[&str], paths: &[PathBuf]) -> Vec<PathBuf> {
+    let output = opusgain(&[&["-a", "--analyze-only", "--format", "json"][..], args].concat(), paths);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    report["tracks"].as_array().unwrap().iter().map(|track| PathBuf::from(track["path"].as_str().unwrap())).collect()
+}
+
+#[test]
+fn album_is_ordered_by_track_tags() {
+    let dir = tempfile::tempdir().unwrap();
+    let write = |name: &str, tags: &[(&str, &str)]| {
+        let spec = tags.iter().fold(StreamSpec::default(), |spec, (field, value)| spec.with_tag(field, value));
+        write_opus_file(dir.path(), name, &[spec])
+    };
+    // Filenames which do not sort naturally
+    let untagged_z = write("z.opus", &[]);
+    let last = write("1.opus", &[("DISCNUMBER", "2"), ("TRACKNUMBER", "1/3")]);
+    let third = write("10.opus", &[("TRACKNUMBER", "10")]);
+    let second = write("2.opus", &[("TRACKNUMBER", "02")]);
+    let untagged_a = write("a.opus", &[("TRACKNUMBER", "unknown")]);
+    let first = write("b.opus", &[("DISCNUMBER", "1/2"), ("TRACKNUMBER", "1")]);
+    let inputs = [untagged_z, last, third, second, untagged_a, first];
+
+    assert_eq!(reported_order(&[], &inputs), inputs);
+    let sorted = reported_order(&["--sort-by-track"], &inputs);
+    let names: Vec<_> = sorted.iter().map(|path| path.file_name().unwrap().to_str().unwrap()).collect();
+    assert_eq!(names, ["b.opus", "2.opus", "10.opus", "1.opus", "a.opus", "z.opus"]);
+}
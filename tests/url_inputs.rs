@@ -0,0 +1,63 @@
+mod common;
+
+use common::run;
+
+const URL: &str = "http://127.0.0.1:9/track.opus";
+
+#[test]
+fn urls_are_rejected_when_modifying_files() {
+    let output = run(env!("CARGO_BIN_EXE_opusgain"), &[], &[URL]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("is a URL"), "{}", stderr);
+}
+
+#[test]
+#[cfg(not(feature = "http"))]
+fn urls_require_http_feature() {
+    let output = run(env!("CARGO_BIN_EXE_opusgain"), &["--analyze-only"], &[URL]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("`http` feature"), "{}", stderr);
+}
+
+#[test]
+#[cfg(feature = "http")]
+fn urls_can_be_analyzed() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    use common::{opus_file, opusgain, StreamSpec};
+
+    let data = opus_file(&[StreamSpec::default()]);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/track.opus", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut start = 0;
+            for line in BufReader::new(&stream).lines() {
+                let line = line.unwrap();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(range) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                    start = range.trim_end_matches('-').parse().unwrap();
+                }
+            }
+            let status = if start > 0 { "206 Partial Content" } else { "200 OK" };
+            let body = &data[start..];
+            let header = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len());
+            let _ = stream.write_all(header.as_bytes()).and_then(|()| stream.write_all(body));
+        }
+    });
+
+    // A quick analysis seeks to the middle of the file with a range request
+    for quick in [None, Some("--quick=1")] {
+        let mut args = vec!["--analyze-only", "--format", "json"];
+        args.extend(quick);
+        let output = opusgain(&args, &[&url]);
+        let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(report["tracks"][0]["path"], url.as_str());
+    }
+}
@@ -0,0 +1,31 @@
+mod common;
+
+use common::{opusgain, run, write_opus_file, StreamSpec};
+
+#[test]
+fn opusgain_counts_encoders() {
+    let dir = tempfile::tempdir().unwrap();
+    let spec = |vendor: &str| StreamSpec { vendor: vendor.into(), ..StreamSpec::default() };
+    let first = write_opus_file(dir.path(), "first.opus", &[spec("libopus 1.3.1")]);
+    let second = write_opus_file(dir.path(), "second.opus", &[spec(" libopus 1.3.1\n")]);
+    let tagged = write_opus_file(dir.path(), "tagged.opus", &[spec("libopus 1.4").with_tag("ENCODER", "opusenc")]);
+    let unreadable = dir.path().join("unreadable.opus");
+    std::fs::write(&unreadable, b"Not an Ogg file").unwrap();
+    let original = std::fs::read(&first).unwrap();
+
+    let inputs = [&tagged, &second, &first, &unreadable];
+    let output = run(env!("CARGO_BIN_EXE_opusgain"), &["--vendor-stats", "--format", "csv"], &inputs);
+    assert!(!output.status.success(), "The unreadable file was not reported as a failure");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines = stdout.lines();
+    assert_eq!(lines.next(), Some("vendor,encoder,count,example"));
+    let expected = format!("libopus 1.3.1,,2,{}", second.display());
+    assert_eq!(lines.next(), Some(expected.as_str()));
+    let expected = format!("libopus 1.4,opusenc,1,{}", tagged.display());
+    assert_eq!(lines.next(), Some(expected.as_str()));
+    assert_eq!(lines.next(), None);
+    assert_eq!(std::fs::read(&first).unwrap(), original);
+
+    let output = opusgain(&["--vendor-stats"], &[&first]);
+    assert!(String::from_utf8(output.stdout).unwrap().ends_with("Files that could not be read: 0\n"));
+}
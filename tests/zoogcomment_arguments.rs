@@ -0,0 +1,21 @@
+mod common;
+
+use common::{run, write_opus_file, StreamSpec};
+
+/// On Windows, `zoogcomment` expands wildcards itself, so a pattern matching
+/// several files arrives as several paths, as if they had been passed directly
+#[test]
+fn surplus_paths_are_not_taken_as_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths: Vec<_> = ["01.opus", "02.opus", "03.opus"]
+        .iter()
+        .map(|name| write_opus_file(dir.path(), name, &[StreamSpec::default().with_tag("TITLE", name)]))
+        .collect();
+    let originals: Vec<_> = paths.iter().map(|path| std::fs::read(path).unwrap()).collect();
+
+    let output = run(env!("CARGO_BIN_EXE_zoogcomment"), &["-m", "-t", "ARTIST=Someone"], &paths);
+    assert!(!output.status.success());
+    for (path, original) in paths.iter().zip(&originals) {
+        assert_eq!(&std::fs::read(path).unwrap(), original, "{} was modified", path.display());
+    }
+}
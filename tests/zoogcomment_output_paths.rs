@@ -0,0 +1,95 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use common::{artist, write_opus_file, zoogcomment, StreamSpec};
+
+fn is_symlink(path: &Path) -> bool { std::fs::symlink_metadata(path).unwrap().file_type().is_symlink() }
+
+/// Creates an input file and a second file with a link to it
+fn fixtures(dir: &Path) -> (PathBuf, PathBuf, PathBuf) {
+    let input = write_opus_file(dir, "input.opus", &[StreamSpec::default().with_tag("TITLE", "Fixture")]);
+    let target = write_opus_file(dir, "target.opus", &[StreamSpec::default().with_tag("ARTIST", "Target")]);
+    let link = dir.join("link.opus");
+    symlink("target.opus", &link).unwrap();
+    (input, target, link)
+}
+
+#[test]
+fn symlinked_output_target_is_replaced_by_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let (input, target, link) = fixtures(dir.path());
+    zoogcomment(&["-m", "-t", "ARTIST=Someone"], &[&input, &link]);
+    assert!(is_symlink(&link));
+    assert_eq!(artist(&target).as_deref(), Some("Someone"));
+    assert_eq!(artist(&input), None);
+}
+
+#[test]
+fn symlinked_output_can_be_replaced() {
+    let dir = tempfile::tempdir().unwrap();
+    let (input, target, link) = fixtures(dir.path());
+    zoogcomment(&["-m", "--dereference-output", "false", "-t", "ARTIST=Someone"], &[&input, &link]);
+    assert!(!is_symlink(&link));
+    assert_eq!(artist(&link).as_deref(), Some("Someone"));
+    assert_eq!(artist(&target).as_deref(), Some("Target"));
+}
+
+#[test]
+fn unchanged_output_linked_to_input_is_not_copied() {
+    let dir = tempfile::tempdir().unwrap();
+    let (input, _, _) = fixtures(dir.path());
+    let original = std::fs::read(&input).unwrap();
+    let symlinked = dir.path().join("symlinked.opus");
+    symlink("input.opus", &symlinked).unwrap();
+    let hard_linked = dir.path().join("hard-linked.opus");
+    std::fs::hard_link(&input, &hard_linked).unwrap();
+
+    // Modifying without any changes would otherwise copy the input to the output
+    for (output, dereference) in [(&symlinked, "true"), (&symlinked, "false"), (&hard_linked, "true")] {
+        zoogcomment(&["-m", "--dereference-output", dereference], &[&input, output]);
+        assert_eq!(std::fs::read(&input).unwrap(), original);
+        assert_eq!(std::fs::read(output).unwrap(), original);
+    }
+    assert!(is_symlink(&symlinked));
+}
+
+#[test]
+fn changed_output_linked_to_input_is_rewritten_in_place() {
+    let dir = tempfile::tempdir().unwrap();
+    let (input, _, _) = fixtures(dir.path());
+    let symlinked = dir.path().join("symlinked.opus");
+    symlink("input.opus", &symlinked).unwrap();
+    zoogcomment(&["-m", "-t", "ARTIST=Someone"], &[&input, &symlinked]);
+    assert!(is_symlink(&symlinked));
+    assert_eq!(artist(&input).as_deref(), Some("Someone"));
+}
+
+#[test]
+fn unchanged_in_place_edit_leaves_file_untouched() {
+    use std::os::unix::fs::MetadataExt as _;
+
+    let dir = tempfile::tempdir().unwrap();
+    let (input, _, _) = fixtures(dir.path());
+    let before = std::fs::metadata(&input).unwrap();
+    let original = std::fs::read(&input).unwrap();
+    zoogcomment(&["-m"], &[&input]);
+    let after = std::fs::metadata(&input).unwrap();
+    assert_eq!(after.ino(), before.ino());
+    assert_eq!(after.modified().unwrap(), before.modified().unwrap());
+    assert_eq!(std::fs::read(&input).unwrap(), original);
+}
+
+#[test]
+fn unchanged_edit_to_new_path_is_identical() {
+    let dir = tempfile::tempdir().unwrap();
+    let (input, target, _) = fixtures(dir.path());
+    let output = dir.path().join("output.opus");
+    for output in [&output, &target] {
+        zoogcomment(&["-m"], &[&input, output]);
+        assert_eq!(std::fs::read(output).unwrap(), std::fs::read(&input).unwrap());
+    }
+}